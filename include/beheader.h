@@ -0,0 +1,217 @@
+/* The C API of beheader, generated by cbindgen from src/ffi/mod.rs. Do not edit. */
+
+#ifndef BEHEADER_H
+#define BEHEADER_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The kind of a [`BeheaderToken`], as described by [`TokenKind`].
+typedef enum BeheaderTokenKind {
+  BEHEADER_TOKEN_KIND_HEADER,
+  BEHEADER_TOKEN_KIND_IDENT,
+  BEHEADER_TOKEN_KIND_NUMBER,
+  BEHEADER_TOKEN_KIND_CHAR,
+  BEHEADER_TOKEN_KIND_STR,
+  BEHEADER_TOKEN_KIND_PUNCT,
+  BEHEADER_TOKEN_KIND_ANY,
+  BEHEADER_TOKEN_KIND_SPACE,
+  BEHEADER_TOKEN_KIND_NEWLINE,
+} BeheaderTokenKind;
+
+// The severity of a [`BeheaderDiagnostic`], as described by [`Severity`].
+typedef enum BeheaderSeverity {
+  BEHEADER_SEVERITY_NOTE,
+  BEHEADER_SEVERITY_WARNING,
+  BEHEADER_SEVERITY_ERROR,
+} BeheaderSeverity;
+
+// A preprocessor and its configuration.
+typedef struct BeheaderPreprocessor BeheaderPreprocessor;
+
+// The result of preprocessing some source code, with the tokens, text and diagnostics it
+// produced.
+typedef struct BeheaderResult BeheaderResult;
+
+// A token of the output of a [`BeheaderResult`].
+typedef struct BeheaderToken {
+  enum BeheaderTokenKind kind;
+  // The spelling of the token, which is not NUL-terminated.
+  const char *spelling;
+  // The length of the spelling in bytes.
+  size_t spelling_len;
+  // The file where the token was written, or `NULL` if it is not in a file.
+  const char *file;
+  // The line where the token starts, or 0 if it is not in a file.
+  size_t line;
+  // The column where the token starts, or 0 if it is not in a file.
+  size_t column;
+} BeheaderToken;
+
+// A diagnostic reported while preprocessing, including the one that stopped the preprocessing
+// if there is one.
+typedef struct BeheaderDiagnostic {
+  enum BeheaderSeverity severity;
+  // The name of the code of the diagnostic, like `include-not-found`.
+  const char *code;
+  const char *message;
+  // The file the diagnostic is about, or `NULL` if it is not about a file.
+  const char *file;
+  // The line the diagnostic is about, or 0 if it is not about a file.
+  size_t line;
+  // The column the diagnostic is about, or 0 if it is not about a file.
+  size_t column;
+} BeheaderDiagnostic;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a preprocessor with the default configuration, which must be freed using
+// [`beheader_preprocessor_free`]. Return `NULL` if it cannot be created.
+struct BeheaderPreprocessor *beheader_preprocessor_new(void);
+
+// Free a preprocessor. Nothing happens if it is `NULL`.
+//
+// # Safety
+//
+// `preprocessor` must be `NULL` or come from [`beheader_preprocessor_new`] and not be freed.
+void beheader_preprocessor_free(struct BeheaderPreprocessor *preprocessor);
+
+// Define a macro as the `-D` option of a compiler does, using `NAME` or `NAME=DEFINITION`.
+// Return `false` if an argument is `NULL` or not valid UTF-8.
+//
+// # Safety
+//
+// `preprocessor` must come from [`beheader_preprocessor_new`] and not be freed, and
+// `definition` must point to a NUL-terminated string.
+bool beheader_preprocessor_define(struct BeheaderPreprocessor *preprocessor,
+                                  const char *definition);
+
+// Undefine a macro as the `-U` option of a compiler does. Return `false` if an argument is
+// `NULL` or not valid UTF-8.
+//
+// # Safety
+//
+// The same as for [`beheader_preprocessor_define`].
+bool beheader_preprocessor_undefine(struct BeheaderPreprocessor *preprocessor, const char *name);
+
+// Add a directory where included files are searched, as the `-I` option of a compiler does.
+// Return `false` if an argument is `NULL` or not valid UTF-8.
+//
+// # Safety
+//
+// The same as for [`beheader_preprocessor_define`].
+bool beheader_preprocessor_include_dir(struct BeheaderPreprocessor *preprocessor, const char *dir);
+
+// Add a directory where included files are searched and considered system headers, as the
+// `-isystem` option of a compiler does. Return `false` if an argument is `NULL` or not valid
+// UTF-8.
+//
+// # Safety
+//
+// The same as for [`beheader_preprocessor_define`].
+bool beheader_preprocessor_system_include_dir(struct BeheaderPreprocessor *preprocessor,
+                                              const char *dir);
+
+// Include a file before the input, as the `-include` option of a compiler does. Return
+// `false` if an argument is `NULL` or not valid UTF-8.
+//
+// # Safety
+//
+// The same as for [`beheader_preprocessor_define`].
+bool beheader_preprocessor_include(struct BeheaderPreprocessor *preprocessor, const char *path);
+
+// Set the version of the C standard using its name for the `-std` option of GCC, like `c99`
+// or `gnu11`. Return `false` if an argument is `NULL` or the standard is not known.
+//
+// # Safety
+//
+// The same as for [`beheader_preprocessor_define`].
+bool beheader_preprocessor_standard(struct BeheaderPreprocessor *preprocessor, const char *name);
+
+// Enable or disable warnings using an option like `-Wall` or `-Werror=trigraphs`. Return
+// `false` if an argument is `NULL` or not valid UTF-8.
+//
+// # Safety
+//
+// The same as for [`beheader_preprocessor_define`].
+bool beheader_preprocessor_warning_option(struct BeheaderPreprocessor *preprocessor,
+                                          const char *option);
+
+// Preprocess the file at `path`, returning a result that must be freed using
+// [`beheader_result_free`]. Return `NULL` if an argument is `NULL` or not valid UTF-8, or if
+// the preprocessor panics.
+//
+// # Safety
+//
+// `preprocessor` must come from [`beheader_preprocessor_new`] and not be freed, and `path`
+// must point to a NUL-terminated string.
+struct BeheaderResult *beheader_preprocess_file(const struct BeheaderPreprocessor *preprocessor,
+                                                const char *path);
+
+// Preprocess the `len` bytes at `source`, returning a result that must be freed using
+// [`beheader_result_free`]. Return `NULL` if an argument is `NULL` or if the preprocessor
+// panics.
+//
+// # Safety
+//
+// `preprocessor` must come from [`beheader_preprocessor_new`] and not be freed, and `source`
+// must point to `len` bytes.
+struct BeheaderResult *beheader_preprocess_source(const struct BeheaderPreprocessor *preprocessor,
+                                                  const uint8_t *source,
+                                                  size_t len);
+
+// Get the message of the error that stopped the preprocessing, or `NULL` if it finished. The
+// message lives as long as the result.
+//
+// # Safety
+//
+// `result` must come from [`beheader_preprocess_file`] or [`beheader_preprocess_source`] and
+// not be freed.
+const char *beheader_result_error(const struct BeheaderResult *result);
+
+// Get the output as text, with line markers, storing its length in `len` if it is not `NULL`.
+// The text is NUL-terminated, lives as long as the result and is empty if the preprocessing
+// did not finish.
+//
+// # Safety
+//
+// The same as for [`beheader_result_error`], and `len` must be `NULL` or valid for writes.
+const char *beheader_result_text(const struct BeheaderResult *result, size_t *len);
+
+// Get the tokens of the output, storing how many there are in `len`. The tokens live as long
+// as the result, and there are none if the preprocessing did not finish.
+//
+// # Safety
+//
+// The same as for [`beheader_result_error`], and `len` must be valid for writes.
+const struct BeheaderToken *beheader_result_tokens(const struct BeheaderResult *result,
+                                                   size_t *len);
+
+// Get the diagnostics reported while preprocessing, storing how many there are in `len`. The
+// diagnostics live as long as the result, and the last one is the error that stopped the
+// preprocessing if it was caused by the source code.
+//
+// # Safety
+//
+// The same as for [`beheader_result_tokens`].
+const struct BeheaderDiagnostic *beheader_result_diagnostics(const struct BeheaderResult *result,
+                                                             size_t *len);
+
+// Free a result, including everything obtained from it. Nothing happens if it is `NULL`.
+//
+// # Safety
+//
+// `result` must be `NULL` or come from [`beheader_preprocess_file`] or
+// [`beheader_preprocess_source`] and not be freed.
+void beheader_result_free(struct BeheaderResult *result);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BEHEADER_H */
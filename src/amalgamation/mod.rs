@@ -0,0 +1,290 @@
+//! Amalgamations: single files combining the sources and the internal headers of a project, in
+//! the style of SQLite.
+#[cfg(test)]
+mod tests;
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    charset::InputCharset,
+    error::Error,
+    fs::{default_file_system, normalize, FileSystem, PrefixMap},
+    lexer::{Lines, Token, TokenKind},
+    macros::skip_space,
+    span::{ColumnUnit, SourceMap, Span},
+    standard::LexerFeatures,
+};
+
+/// A single file combining several source files and the internal headers of a project, like the
+/// `sqlite3.c` file of SQLite.
+///
+/// The sources are copied one after the other. Every `#include` directive naming one of the
+/// internal headers is replaced by the contents of the header the first time and commented out
+/// after that, so each header is inlined exactly once. The directives naming any other file are
+/// kept as they are. Comments mark where each file begins and ends. Nothing else is changed, in
+/// particular conditional inclusion directives are not evaluated.
+///
+/// Quoted includes are searched in the directory of the including file first, then both forms
+/// are searched in the [`include_dir`](Self::include_dir)s. The `#pragma once` directives of the
+/// internal headers are commented out too.
+///
+/// ```
+/// use beheader::{Amalgamation, MemoryFileSystem};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("src/a.c", "#include \"util.h\"\n#include <stdio.h>\nint a;\n");
+/// files.insert("src/b.c", "#include \"util.h\"\nint b;\n");
+/// files.insert("src/util.h", "#pragma once\nint util;\n");
+///
+/// let mut amalgamation = Amalgamation::new();
+/// amalgamation
+///     .file_system(files)
+///     .source("src/a.c")
+///     .source("src/b.c")
+///     .header("src/util.h");
+/// let text = String::from_utf8(amalgamation.build().unwrap()).unwrap();
+/// assert_eq!(
+///     text.lines().collect::<Vec<_>>(),
+///     [
+///         "/************** Begin file src/a.c **************/",
+///         "/************** Include src/util.h in the middle of src/a.c **************/",
+///         "/************** Begin file src/util.h **************/",
+///         "/* #pragma once */",
+///         "int util;",
+///         "/************** End of src/util.h **************/",
+///         "/************** Continuing where we left off in src/a.c **************/",
+///         "#include <stdio.h>",
+///         "int a;",
+///         "/************** End of src/a.c **************/",
+///         "/************** Begin file src/b.c **************/",
+///         "/* #include \"util.h\" */",
+///         "int b;",
+///         "/************** End of src/b.c **************/",
+///     ]
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Amalgamation {
+    file_system: Arc<dyn FileSystem>,
+    sources: Vec<PathBuf>,
+    headers: Vec<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Default for Amalgamation {
+    fn default() -> Self {
+        Self {
+            file_system: default_file_system(),
+            sources: Vec::new(),
+            headers: Vec::new(),
+            include_dirs: Vec::new(),
+        }
+    }
+}
+
+impl Amalgamation {
+    /// Create an amalgamation without files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a source file, which is copied after the ones added before.
+    pub fn source<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.sources.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Add an internal header, which is inlined where it is first included.
+    pub fn header<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.headers.push(normalize(path.as_ref()));
+        self
+    }
+
+    /// Add a directory where the files named by `#include` directives are searched.
+    pub fn include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.include_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set the [`FileSystem`] used to read the files. By default, the files are read from the
+    /// actual file system.
+    pub fn file_system<F: FileSystem + 'static>(&mut self, file_system: F) -> &mut Self {
+        self.file_system = Arc::new(file_system);
+        self
+    }
+
+    /// Produce the contents of the amalgamation.
+    ///
+    /// Return an error if any of the files cannot be read or contains invalid tokens.
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let mut builder = Builder {
+            amalgamation: self,
+            map: SourceMap::new(
+                self.file_system.clone(),
+                false,
+                None,
+                ColumnUnit::Bytes,
+                InputCharset::Utf8,
+                PrefixMap::default(),
+                // The files are copied as they are written.
+                LexerFeatures {
+                    trigraphs: false,
+                    ..LexerFeatures::default()
+                },
+            ),
+            output: Vec::new(),
+            inlined: Vec::new(),
+        };
+        for path in &self.sources {
+            // Sources that are internal headers too are only copied once.
+            let path = normalize(path);
+            if builder.inlined.contains(&path) {
+                continue;
+            }
+            if self.headers.contains(&path) {
+                builder.inlined.push(path.clone());
+            }
+            builder.copy(&path)?;
+        }
+        Ok(builder.output)
+    }
+
+    /// Write the contents of the amalgamation to the file at `path`.
+    pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        Ok(fs::write(path, self.build()?)?)
+    }
+
+    /// Find the internal header named `name` by an `#include` directive in the file `includer`.
+    /// `angled` is `true` if the name was delimited by `<` and `>`.
+    fn find_header(&self, name: &str, angled: bool, includer: &Path) -> Option<PathBuf> {
+        let current = (!angled).then(|| includer.parent().unwrap_or(Path::new("")));
+        current
+            .into_iter()
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| normalize(&dir.join(name)))
+            .find(|path| self.headers.contains(path))
+    }
+}
+
+/// The state of [`Amalgamation::build`].
+struct Builder<'a> {
+    amalgamation: &'a Amalgamation,
+    map: SourceMap,
+    output: Vec<u8>,
+    /// The internal headers inlined so far.
+    inlined: Vec<PathBuf>,
+}
+
+impl Builder<'_> {
+    /// Copy the file at `path`, inlining the internal headers it includes.
+    fn copy(&mut self, path: &Path) -> Result<(), Error> {
+        let span = self.map.read_file(&path)?;
+        let header = self.amalgamation.headers.contains(&normalize(path));
+        self.banner(format_args!("Begin file {}", path.display()));
+
+        let mut lines = Lines::new(span);
+        let mut line = Vec::new();
+        while lines.next_line(&self.map, &mut line)? {
+            match self.directive(&line, path, header) {
+                Some(Directive::Inline(included)) => {
+                    self.banner(format_args!(
+                        "Include {} in the middle of {}",
+                        included.display(),
+                        path.display()
+                    ));
+                    self.inlined.push(included.clone());
+                    self.copy(&included)?;
+                    self.banner(format_args!(
+                        "Continuing where we left off in {}",
+                        path.display()
+                    ));
+                }
+                Some(Directive::Remove(text)) => self.output.extend_from_slice(
+                    format!("/* {} */\n", text.trim_end_matches(['\n', '\r'])).as_bytes(),
+                ),
+                None => {
+                    let span = Span::new(
+                        line.first().map_or(0, |token| token.span.lo()),
+                        line.last().map_or(0, |token| token.span.hi()),
+                    );
+                    self.output.extend_from_slice(&self.map.get_bytes(span));
+                }
+            }
+        }
+
+        self.banner(format_args!("End of {}", path.display()));
+        Ok(())
+    }
+
+    /// Decide what to do with `line`, a line of the file at `path`, which is an internal header
+    /// if `header` is `true`.
+    fn directive(&self, line: &[Token], path: &Path, header: bool) -> Option<Directive> {
+        let (name, rest) = self.map.directive(line)?;
+        let name = self.map.spelling(name?);
+        let mut rest = rest.iter().copied();
+        let operand = skip_space(&mut rest);
+        let only = operand.is_some()
+            && skip_space(&mut rest).is_none_or(|token| token.kind == TokenKind::Newline);
+        let text = || {
+            let start = line.iter().position(|token| token.kind != TokenKind::Space);
+            let line = &line[start.unwrap_or(0)..];
+            let mut text = String::new();
+            for &token in line {
+                match token.kind {
+                    TokenKind::Space => text.push(' '),
+                    _ => text.push_str(&self.map.spelling(token)),
+                }
+            }
+            text
+        };
+
+        match (name.as_str(), operand) {
+            ("include", Some(operand)) if only => {
+                let spelling = self.map.spelling(operand);
+                // Names produced by macros and string literals with prefixes are not supported.
+                let angled = match operand.kind {
+                    TokenKind::Header | TokenKind::Str if spelling.starts_with('<') => true,
+                    TokenKind::Header | TokenKind::Str if spelling.starts_with('"') => false,
+                    _ => return None,
+                };
+                let included = self.amalgamation.find_header(
+                    &spelling[1..spelling.len() - 1],
+                    angled,
+                    path,
+                )?;
+                if self.inlined.contains(&included) {
+                    Some(Directive::Remove(text()))
+                } else {
+                    Some(Directive::Inline(included))
+                }
+            }
+            ("pragma", Some(operand)) if header && only && self.map.spelling(operand) == "once" => {
+                Some(Directive::Remove(text()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Write a comment saying `text`, on its own line.
+    fn banner(&mut self, text: std::fmt::Arguments<'_>) {
+        if !self.output.is_empty() && !self.output.ends_with(b"\n") {
+            self.output.push(b'\n');
+        }
+        let mut banner = String::new();
+        writeln!(banner, "/************** {} **************/", text).unwrap();
+        self.output.extend_from_slice(banner.as_bytes());
+    }
+}
+
+/// What is done with a directive of a file copied to an amalgamation.
+enum Directive {
+    /// Replace it with the contents of the internal header at this path.
+    Inline(PathBuf),
+    /// Replace it with a comment containing this text.
+    Remove(String),
+}
@@ -0,0 +1,58 @@
+use super::Amalgamation;
+use crate::MemoryFileSystem;
+
+#[test]
+fn nested_headers() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "src/main.c",
+            "#include \"api.h\"\n#include <internal/a.h>\n#include \"missing.h\"\n#include \"api.h\"\nint main;",
+        )
+        .insert(
+            "src/api.h",
+            "#ifndef API_H\n#define API_H\n#include <internal/a.h>\n#endif\n",
+        )
+        .insert("include/internal/a.h", "#include \"b.h\"\nint a;\n")
+        .insert("include/internal/b.h", "int b;\n");
+
+    let mut amalgamation = Amalgamation::new();
+    amalgamation
+        .file_system(files)
+        .include_dir("include")
+        .source("src/main.c")
+        .source("src/api.h")
+        .header("src/api.h")
+        .header("include/internal/a.h")
+        .header("include/internal/./b.h");
+    let text = String::from_utf8(amalgamation.build().unwrap()).unwrap();
+
+    assert_eq!(
+        text.lines().collect::<Vec<_>>(),
+        [
+            "/************** Begin file src/main.c **************/",
+            "/************** Include src/api.h in the middle of src/main.c **************/",
+            "/************** Begin file src/api.h **************/",
+            "#ifndef API_H",
+            "#define API_H",
+            "/************** Include include/internal/a.h in the middle of src/api.h **************/",
+            "/************** Begin file include/internal/a.h **************/",
+            "/************** Include include/internal/b.h in the middle of include/internal/a.h **************/",
+            "/************** Begin file include/internal/b.h **************/",
+            "int b;",
+            "/************** End of include/internal/b.h **************/",
+            "/************** Continuing where we left off in include/internal/a.h **************/",
+            "int a;",
+            "/************** End of include/internal/a.h **************/",
+            "/************** Continuing where we left off in src/api.h **************/",
+            "#endif",
+            "/************** End of src/api.h **************/",
+            "/************** Continuing where we left off in src/main.c **************/",
+            "/* #include <internal/a.h> */",
+            "#include \"missing.h\"",
+            "/* #include \"api.h\" */",
+            "int main;",
+            "/************** End of src/main.c **************/",
+        ]
+    );
+}
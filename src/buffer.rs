@@ -1,27 +1,108 @@
-use std::{borrow::Borrow, ops::Deref};
+use std::{
+    borrow::Borrow,
+    fmt,
+    ops::{Deref, Index, RangeBounds},
+    slice::{self, SliceIndex},
+    vec,
+};
 
-use crate::lexer::Token;
+use crate::{
+    lexer::{Token, TokenKind},
+    span::{SourceMap, SourceMapSnapshot, Span},
+};
 
-/// A buffer of [`Token`]s.
-#[derive(Default)]
-pub(crate) struct TokenBuffer {
+/// The maximum number of bytes of the spelling of a token shown by a [`TokenDump`].
+const MAX_EXCERPT: usize = 40;
+
+/// A buffer of [`Token`]s, which can be used as a [`TokenSlice`].
+///
+/// ```
+/// use beheader::{TokenBuffer, TokenKind};
+///
+/// let preprocessed = beheader::preprocess(b"#define N 2\nint a[N];\n").unwrap();
+/// let mut buffer: TokenBuffer = preprocessed.tokens().iter().copied().collect();
+/// buffer.push(buffer[1]);
+/// assert_eq!(buffer.len(), 10);
+/// assert_eq!(buffer[..3].len(), 3);
+/// let idents = buffer.iter().filter(|token| token.kind() == TokenKind::Ident);
+/// assert_eq!(idents.count(), 3);
+///
+/// // Remove `[N]` and repeat the declaration without copying the tokens twice.
+/// buffer.splice(4..7, &[]);
+/// let declaration = buffer.slice(1..6);
+/// let twice = TokenBuffer::concat(&[declaration, declaration]);
+/// assert_eq!(twice.len(), 10);
+/// assert_eq!(twice.slice(5..), declaration);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TokenBuffer {
     rest: Vec<Token>,
 }
 
 impl TokenBuffer {
+    /// Create an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
     /// Push a [`Token`] into the buffer.
-    pub(crate) fn push(&mut self, token: Token) {
+    pub fn push(&mut self, token: Token) {
         self.rest.push(token)
     }
+
+    /// Create a buffer with the [`Token`]s of every slice in `slices`, one after the other.
+    pub fn concat<T: AsRef<[Token]>>(slices: &[T]) -> Self {
+        let len = slices.iter().map(|slice| slice.as_ref().len()).sum();
+        let mut buffer = Self {
+            rest: Vec::with_capacity(len),
+        };
+        for slice in slices {
+            buffer.extend_from_slice(slice);
+        }
+        buffer
+    }
+
+    /// Copy the [`Token`]s of `tokens` to the end of the buffer.
+    pub fn extend_from_slice<T: AsRef<[Token]> + ?Sized>(&mut self, tokens: &T) {
+        self.rest.extend_from_slice(tokens.as_ref())
+    }
+
+    /// Replace the [`Token`]s in `range` with the ones of `tokens`, like the tokens of a macro
+    /// invocation with its replacement or an `#include` directive with the tokens of the file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn splice<R, T>(&mut self, range: R, tokens: &T)
+    where
+        R: RangeBounds<usize>,
+        T: AsRef<[Token]> + ?Sized,
+    {
+        self.rest.splice(range, tokens.as_ref().iter().copied());
+    }
+
+    /// Remove all the [`Token`]s from the buffer and return them.
+    pub(crate) fn take(&mut self) -> Vec<Token> {
+        std::mem::take(&mut self.rest)
+    }
+
+    /// Remove the first `len` [`Token`]s from the buffer and return them.
+    pub(crate) fn take_front(&mut self, len: usize) -> Vec<Token> {
+        self.rest.drain(..len).collect()
+    }
 }
 
 impl Deref for TokenBuffer {
     type Target = TokenSlice;
 
     fn deref(&self) -> &Self::Target {
-        let ptr = self.rest.as_slice() as *const [Token] as *const TokenSlice;
-        // SAFETY: This pointer is valid because `TokenSlice` and `Token` have the same layout.
-        unsafe { &*ptr }
+        TokenSlice::new(&self.rest)
+    }
+}
+
+impl AsRef<[Token]> for TokenBuffer {
+    fn as_ref(&self) -> &[Token] {
+        &self.rest
     }
 }
 
@@ -31,12 +112,433 @@ impl Borrow<TokenSlice> for TokenBuffer {
     }
 }
 
-/// A slice of [`Token`]s.
+impl Extend<Token> for TokenBuffer {
+    fn extend<I: IntoIterator<Item = Token>>(&mut self, iter: I) {
+        self.rest.extend(iter)
+    }
+}
+
+impl IntoIterator for TokenBuffer {
+    type Item = Token;
+    type IntoIter = vec::IntoIter<Token>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rest.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TokenBuffer {
+    type Item = &'a Token;
+    type IntoIter = slice::Iter<'a, Token>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rest.iter()
+    }
+}
+
+impl FromIterator<Token> for TokenBuffer {
+    fn from_iter<I: IntoIterator<Item = Token>>(iter: I) -> Self {
+        TokenBuffer {
+            rest: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// A slice of [`Token`]s, usually borrowed from a [`TokenBuffer`].
+#[derive(Debug, PartialEq, Eq, Hash)]
 #[repr(transparent)]
-pub(crate) struct TokenSlice {
+pub struct TokenSlice {
     rest: [Token],
 }
 
+impl TokenSlice {
+    /// Use `tokens` as a token slice.
+    pub fn new(tokens: &[Token]) -> &Self {
+        let ptr = tokens as *const [Token] as *const TokenSlice;
+        // SAFETY: This pointer is valid because `TokenSlice` and `Token` have the same layout.
+        unsafe { &*ptr }
+    }
+
+    /// Get the [`Token`]s inside this slice.
+    pub fn tokens(&self) -> &[Token] {
+        &self.rest
+    }
+
+    /// Get the part of this slice in `range` as a slice, without copying its [`Token`]s.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `range` is out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> &TokenSlice {
+        let range = (range.start_bound().cloned(), range.end_bound().cloned());
+        TokenSlice::new(&self.rest[range])
+    }
+
+    /// Divide this slice in two at `mid`, without copying its [`Token`]s. The first slice has the
+    /// tokens before `mid` and the second one the rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid` is larger than the length of this slice.
+    pub fn split_at(&self, mid: usize) -> (&TokenSlice, &TokenSlice) {
+        let (before, after) = self.rest.split_at(mid);
+        (TokenSlice::new(before), TokenSlice::new(after))
+    }
+
+    /// Get the number of [`Token`]s inside this slice.
+    pub fn len(&self) -> usize {
+        self.rest.len()
+    }
+
+    /// Check if this slice has no [`Token`]s.
+    pub fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    /// Iterate over the [`Token`]s inside this slice.
+    pub fn iter(&self) -> slice::Iter<'_, Token> {
+        self.rest.iter()
+    }
+
+    /// Get a [`Cursor`] at the first [`Token`] of this slice.
+    pub fn cursor(&self) -> Cursor<'_> {
+        Cursor::new(&self.rest)
+    }
+
+    /// Split this slice into logical lines, each one ending with its [`TokenKind::Newline`] token
+    /// except for the last line if there is no new-line character at the end. Lines continued
+    /// by a backslash are a single logical line because the spliced new-line characters are part
+    /// of the white-space tokens. The spellings of the tokens are found in `map` to check if the
+    /// lines are preprocessing directives.
+    ///
+    /// ```
+    /// use beheader::{MacroDump, Preprocessor, TokenSlice};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.dump_macros(MacroDump::Directives);
+    /// let preprocessed = preprocessor.preprocess(b"  #define A \\\n 1\nint a = A;").unwrap();
+    /// let map = preprocessed.source_map();
+    /// let lines: Vec<_> = TokenSlice::new(preprocessed.tokens()).lines(&map).collect();
+    /// assert_eq!(lines.len(), 2);
+    /// assert!(lines[0].is_directive());
+    /// assert_eq!(lines[0].tokens().len(), 8);
+    /// assert!(!lines[1].is_directive());
+    /// ```
+    pub fn lines<'a>(&'a self, map: &'a SourceMapSnapshot) -> LogicalLines<'a> {
+        LogicalLines {
+            rest: &self.rest,
+            map,
+        }
+    }
+
+    /// Check if this slice and `other` have the same tokens, comparing their kinds and the
+    /// spellings found in `map` but not their spans. White-space tokens are equal even if they
+    /// are spelled differently, as in the replacement lists of identical macro definitions
+    /// (section 6.10.3p1 of C17).
+    ///
+    /// ```
+    /// use beheader::TokenSlice;
+    ///
+    /// let preprocessed = beheader::preprocess(b"f(a, b);\nf(a,b);\nf(a,  b);\n").unwrap();
+    /// let map = preprocessed.source_map();
+    /// let lines: Vec<_> = TokenSlice::new(preprocessed.tokens()).lines(&map).collect();
+    /// assert!(!lines[0].tokens().same_spelling(lines[1].tokens(), &map));
+    /// assert!(lines[0].tokens().same_spelling(lines[2].tokens(), &map));
+    /// ```
+    pub fn same_spelling(&self, other: &TokenSlice, map: &SourceMapSnapshot) -> bool {
+        same_spelling(&self.rest, &other.rest, |span| {
+            map.spelling(span).unwrap_or_default()
+        })
+    }
+
+    /// Get the part of this slice that goes from the first to the last [`Token`] whose span is
+    /// inside `span`, or an empty slice if there are none. The tokens between them are included
+    /// even if they are outside `span`, like the tokens produced by expanding a macro.
+    ///
+    /// ```
+    /// use beheader::TokenSlice;
+    ///
+    /// let preprocessed = beheader::preprocess(b"#define N 1\nint a;\nint b = N;\n").unwrap();
+    /// let map = preprocessed.source_map();
+    /// let tokens = TokenSlice::new(preprocessed.tokens());
+    /// let span = tokens[6].span().join(tokens[13].span());
+    /// let spelling = |tokens: &TokenSlice| {
+    ///     let spellings = tokens.iter().map(|token| map.spelling(token.span()).unwrap());
+    ///     spellings.collect::<Vec<_>>().concat()
+    /// };
+    /// assert_eq!(spelling(tokens.tokens_in(span)), b"int b = 1;");
+    /// ```
+    pub fn tokens_in(&self, span: Span) -> &TokenSlice {
+        let inside = |token: &Token| span.contains(token.span);
+        let tokens = match self.rest.iter().position(inside) {
+            Some(start) => {
+                let end = self.rest.iter().rposition(inside).unwrap();
+                &self.rest[start..=end]
+            }
+            None => &self.rest[..0],
+        };
+        TokenSlice::new(tokens)
+    }
+
+    /// Describe the [`Token`]s inside this slice using the source code stored in `map`. The
+    /// tokens inside `input`, the region of the source code read from the standard input if any,
+    /// are located in `<stdin>`.
+    pub(crate) fn dump<'a>(&'a self, map: &'a SourceMap, input: Option<Span>) -> TokenDump<'a> {
+        TokenDump {
+            tokens: &self.rest,
+            map,
+            input,
+        }
+    }
+}
+
+/// A position inside a slice of [`Token`]s used to walk over them, like the tokens of a line
+/// when parsing a preprocessing directive or the tokens returned by
+/// [`Preprocessed::tokens`](crate::Preprocessed::tokens).
+///
+/// White-space is kept as [`TokenKind::Space`] tokens, which can be skipped using
+/// [`skip_space`](Self::skip_space). A cursor can go back to a previous position using
+/// [`checkpoint`](Self::checkpoint) and [`rollback`](Self::rollback).
+///
+/// ```
+/// use beheader::{Cursor, TokenKind};
+///
+/// let preprocessed = beheader::preprocess(b"int f ( void );\n").unwrap();
+/// let map = preprocessed.source_map();
+/// let mut cursor = Cursor::new(preprocessed.tokens());
+///
+/// let name = cursor.skip_space().eat(TokenKind::Ident).unwrap();
+/// assert_eq!(map.spelling(name.span()), Some(&b"int"[..]));
+/// let checkpoint = cursor.checkpoint();
+/// assert!(cursor.skip_space().eat(TokenKind::Punct).is_none());
+/// assert_eq!(cursor.next_non_space().map(|token| token.kind()), Some(TokenKind::Ident));
+/// cursor.rollback(checkpoint);
+/// assert_eq!(cursor.peek().map(|token| token.kind()), Some(TokenKind::Space));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cursor<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+/// A position of a [`Cursor`] that it can go back to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
+impl<'a> Cursor<'a> {
+    /// Create a cursor at the first token of `tokens`.
+    pub fn new(tokens: &'a [Token]) -> Self {
+        Self {
+            tokens,
+            position: 0,
+        }
+    }
+
+    /// Get the next token without moving the cursor, or `None` at the end.
+    pub fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.position).copied()
+    }
+
+    /// Move past the next token if it is of the given `kind` and return it.
+    pub fn eat(&mut self, kind: TokenKind) -> Option<Token> {
+        let token = self.peek().filter(|token| token.kind == kind)?;
+        self.position += 1;
+        Some(token)
+    }
+
+    /// Move past the white-space tokens at the cursor. New-line characters are not skipped.
+    pub fn skip_space(&mut self) -> &mut Self {
+        while self.eat(TokenKind::Space).is_some() {}
+        self
+    }
+
+    /// Move past the white-space tokens at the cursor and the token after them, and return it.
+    pub fn next_non_space(&mut self) -> Option<Token> {
+        self.skip_space().next()
+    }
+
+    /// Get the current position of the cursor, to go back to it using
+    /// [`rollback`](Self::rollback).
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.position)
+    }
+
+    /// Go back to a position obtained from [`checkpoint`](Self::checkpoint) on this cursor.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        debug_assert!(checkpoint.0 <= self.tokens.len());
+        self.position = checkpoint.0;
+    }
+
+    /// Get the tokens after the cursor.
+    pub fn rest(&self) -> &'a [Token] {
+        &self.tokens[self.position..]
+    }
+
+    /// Check if there are no tokens after the cursor.
+    pub fn is_at_end(&self) -> bool {
+        self.position == self.tokens.len()
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.peek()?;
+        self.position += 1;
+        Some(token)
+    }
+}
+
+/// Check if `a` and `b` have the same tokens, comparing their kinds and the spellings obtained
+/// using `spelling`. White-space tokens are equal whatever their spelling.
+pub(crate) fn same_spelling<S: Deref<Target = [u8]>>(
+    a: &[Token],
+    b: &[Token],
+    spelling: impl Fn(Span) -> S,
+) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.kind == b.kind
+                && (a.kind == TokenKind::Space || *spelling(a.span) == *spelling(b.span))
+        })
+}
+
+/// An iterator over the logical lines of a [`TokenSlice`], created using [`TokenSlice::lines`].
+#[derive(Clone, Debug)]
+pub struct LogicalLines<'a> {
+    rest: &'a [Token],
+    map: &'a SourceMapSnapshot,
+}
+
+impl<'a> Iterator for LogicalLines<'a> {
+    type Item = LogicalLine<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let end = self
+            .rest
+            .iter()
+            .position(|token| token.kind == TokenKind::Newline)
+            .map_or(self.rest.len(), |i| i + 1);
+        let (tokens, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        // A directive starts with a `#` that is the first token of the line, which can also be
+        // spelled as `%:` (section 6.4.6p3 of C17).
+        let directive = Cursor::new(tokens).next_non_space().is_some_and(|token| {
+            token.kind == TokenKind::Punct
+                && matches!(self.map.spelling(token.span), Some(b"#" | b"%:"))
+        });
+        Some(LogicalLine {
+            tokens: TokenSlice::new(tokens),
+            directive,
+        })
+    }
+}
+
+/// A logical line of a [`TokenSlice`], obtained from [`TokenSlice::lines`].
+#[derive(Clone, Copy, Debug)]
+pub struct LogicalLine<'a> {
+    tokens: &'a TokenSlice,
+    directive: bool,
+}
+
+impl<'a> LogicalLine<'a> {
+    /// The tokens of the line, including its final new-line character if it has one.
+    pub fn tokens(&self) -> &'a TokenSlice {
+        self.tokens
+    }
+
+    /// Check if the first token of the line that is not white-space is a `#`, which means that
+    /// the line is a preprocessing directive (section 6.10 of C17).
+    pub fn is_directive(&self) -> bool {
+        self.directive
+    }
+}
+
+/// A human-readable description of some [`Token`]s, with one line per token containing its index,
+/// kind, span, location and spelling:
+///
+/// ```text
+///    0 Ident 0..3 main.c:1:1 "int"
+///    1 Space 3..4 main.c:1:4 " "
+/// ```
+///
+/// Locations are written as `?` for the tokens that are not spelled in a file or in the standard
+/// input, and long spellings are shortened.
+pub(crate) struct TokenDump<'a> {
+    tokens: &'a [Token],
+    map: &'a SourceMap,
+    input: Option<Span>,
+}
+
+impl fmt::Display for TokenDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, token) in self.tokens.iter().enumerate() {
+            let span = token.span;
+            write!(
+                f,
+                "{:>4} {:?} {}..{} ",
+                index,
+                token.kind,
+                span.lo(),
+                span.hi()
+            )?;
+            match self.map.find_file(span) {
+                Some(path) => write!(
+                    f,
+                    "{}:{}:{}",
+                    path.display(),
+                    self.map.find_line(span).unwrap(),
+                    self.map.find_column(span).unwrap()
+                )?,
+                None => match self.input.filter(|input| input.contains(span)) {
+                    Some(input) => {
+                        let before = self.map.get_bytes(Span::new(input.lo(), span.lo()));
+                        let line = before.iter().filter(|&&byte| byte == b'\n').count() + 1;
+                        let start = before
+                            .iter()
+                            .rposition(|&byte| byte == b'\n')
+                            .map_or(0, |pos| pos + 1);
+                        write!(f, "<stdin>:{}:{}", line, before.len() - start + 1)?
+                    }
+                    None => write!(f, "?")?,
+                },
+            }
+            let bytes = self.map.get_bytes(span);
+            let excerpt = String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_EXCERPT)]);
+            let ellipsis = if bytes.len() > MAX_EXCERPT { "..." } else { "" };
+            writeln!(f, " {:?}{}", excerpt, ellipsis)?;
+        }
+        Ok(())
+    }
+}
+
+impl AsRef<[Token]> for TokenSlice {
+    fn as_ref(&self) -> &[Token] {
+        &self.rest
+    }
+}
+
+impl<I: SliceIndex<[Token]>> Index<I> for TokenSlice {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &Self::Output {
+        &self.rest[index]
+    }
+}
+
+impl<'a> IntoIterator for &'a TokenSlice {
+    type Item = &'a Token;
+    type IntoIter = slice::Iter<'a, Token>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rest.iter()
+    }
+}
+
 impl ToOwned for TokenSlice {
     type Owned = TokenBuffer;
 
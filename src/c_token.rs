@@ -0,0 +1,519 @@
+//! The conversion of preprocessing tokens into tokens (translation phase 7, section 5.1.1.2p1 of
+//! C17).
+use std::borrow::Cow;
+
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Severity, SeverityMap},
+    lexer::{Token, TokenKind},
+    span::{SourceMap, Span},
+    standard::Standard,
+};
+
+/// A token, as defined in section 6.4 of C17, produced from a preprocessing token of the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CToken {
+    kind: CTokenKind,
+    span: Span,
+}
+
+impl CToken {
+    /// The kind of the token.
+    pub fn kind(&self) -> CTokenKind {
+        self.kind
+    }
+
+    /// The region of the preprocessing token the token was produced from, whose contents are its
+    /// spelling.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+/// The different kinds of tokens. The description for each kind can be found at the section 6.4
+/// of C17 using the identifier shown in the documentation of each variant of this `enum`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CTokenKind {
+    /// A `keyword`.
+    Keyword(Keyword),
+    /// An `identifier` that is not a keyword.
+    Ident,
+    /// An `integer-constant`.
+    Integer(IntegerConstant),
+    /// A `floating-constant`.
+    Floating(FloatingConstant),
+    /// A `character-constant`.
+    Char,
+    /// A `string-literal`.
+    Str,
+    /// A `punctuator`.
+    Punct,
+}
+
+/// The keywords of section 6.4.1 of C17 and the ones added by C23. The keywords of C23 that are
+/// alternative spellings of the ones of C17, like `bool` for `_Bool`, are the same variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Keyword {
+    Auto,
+    Break,
+    Case,
+    Char,
+    Const,
+    Continue,
+    Default,
+    Do,
+    Double,
+    Else,
+    Enum,
+    Extern,
+    Float,
+    For,
+    Goto,
+    If,
+    Inline,
+    Int,
+    Long,
+    Register,
+    Restrict,
+    Return,
+    Short,
+    Signed,
+    Sizeof,
+    Static,
+    Struct,
+    Switch,
+    Typedef,
+    Union,
+    Unsigned,
+    Void,
+    Volatile,
+    While,
+    /// `_Alignas` or, since C23, `alignas`.
+    Alignas,
+    /// `_Alignof` or, since C23, `alignof`.
+    Alignof,
+    Atomic,
+    /// `_Bool` or, since C23, `bool`.
+    Bool,
+    Complex,
+    Generic,
+    Imaginary,
+    Noreturn,
+    /// `_Static_assert` or, since C23, `static_assert`.
+    StaticAssert,
+    /// `_Thread_local` or, since C23, `thread_local`.
+    ThreadLocal,
+    /// `constexpr`, since C23.
+    Constexpr,
+    /// `false`, since C23.
+    False,
+    /// `nullptr`, since C23.
+    Nullptr,
+    /// `true`, since C23.
+    True,
+    /// `typeof`, since C23.
+    Typeof,
+    /// `typeof_unqual`, since C23.
+    TypeofUnqual,
+    /// `_BitInt`, since C23.
+    BitInt,
+    /// `_Decimal32`, since C23.
+    Decimal32,
+    /// `_Decimal64`, since C23.
+    Decimal64,
+    /// `_Decimal128`, since C23.
+    Decimal128,
+}
+
+impl Keyword {
+    /// Find the keyword spelled `spelling` in the version `standard` of C.
+    pub fn from_spelling(spelling: &str, standard: Standard) -> Option<Self> {
+        let keyword = match spelling {
+            "auto" => Self::Auto,
+            "break" => Self::Break,
+            "case" => Self::Case,
+            "char" => Self::Char,
+            "const" => Self::Const,
+            "continue" => Self::Continue,
+            "default" => Self::Default,
+            "do" => Self::Do,
+            "double" => Self::Double,
+            "else" => Self::Else,
+            "enum" => Self::Enum,
+            "extern" => Self::Extern,
+            "float" => Self::Float,
+            "for" => Self::For,
+            "goto" => Self::Goto,
+            "if" => Self::If,
+            "inline" => Self::Inline,
+            "int" => Self::Int,
+            "long" => Self::Long,
+            "register" => Self::Register,
+            "restrict" => Self::Restrict,
+            "return" => Self::Return,
+            "short" => Self::Short,
+            "signed" => Self::Signed,
+            "sizeof" => Self::Sizeof,
+            "static" => Self::Static,
+            "struct" => Self::Struct,
+            "switch" => Self::Switch,
+            "typedef" => Self::Typedef,
+            "union" => Self::Union,
+            "unsigned" => Self::Unsigned,
+            "void" => Self::Void,
+            "volatile" => Self::Volatile,
+            "while" => Self::While,
+            "_Alignas" => Self::Alignas,
+            "_Alignof" => Self::Alignof,
+            "_Atomic" => Self::Atomic,
+            "_Bool" => Self::Bool,
+            "_Complex" => Self::Complex,
+            "_Generic" => Self::Generic,
+            "_Imaginary" => Self::Imaginary,
+            "_Noreturn" => Self::Noreturn,
+            "_Static_assert" => Self::StaticAssert,
+            "_Thread_local" => Self::ThreadLocal,
+            _ if standard < Standard::C23 => return None,
+            "alignas" => Self::Alignas,
+            "alignof" => Self::Alignof,
+            "bool" => Self::Bool,
+            "static_assert" => Self::StaticAssert,
+            "thread_local" => Self::ThreadLocal,
+            "constexpr" => Self::Constexpr,
+            "false" => Self::False,
+            "nullptr" => Self::Nullptr,
+            "true" => Self::True,
+            "typeof" => Self::Typeof,
+            "typeof_unqual" => Self::TypeofUnqual,
+            "_BitInt" => Self::BitInt,
+            "_Decimal32" => Self::Decimal32,
+            "_Decimal64" => Self::Decimal64,
+            "_Decimal128" => Self::Decimal128,
+            _ => return None,
+        };
+        Some(keyword)
+    }
+}
+
+/// The value and suffix of an `integer-constant` (section 6.4.4.1 of C17).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IntegerConstant {
+    value: u64,
+    radix: u32,
+    unsigned: bool,
+    size: IntegerSize,
+}
+
+impl IntegerConstant {
+    /// Parse the spelling of a `pp-number` as an integer constant. Binary constants, with the
+    /// `0b` prefix, and digit separators are accepted as in C23. Return `None` if it is not a
+    /// valid integer constant or if its value does not fit in 64 bits.
+    pub fn parse(spelling: &str) -> Option<Self> {
+        let suffix_start = spelling.trim_end_matches(['u', 'U', 'l', 'L']).len();
+        let (digits, suffix) = spelling.split_at(suffix_start);
+        let digits = match digits.starts_with("0x") || digits.starts_with("0X") {
+            true => remove_separators(digits, |byte| byte.is_ascii_hexdigit())?,
+            false => remove_separators(digits, |byte| byte.is_ascii_digit())?,
+        };
+        let digits = &*digits;
+
+        let (unsigned, size) = match suffix {
+            "" => (false, IntegerSize::Int),
+            "l" | "L" => (false, IntegerSize::Long),
+            "ll" | "LL" => (false, IntegerSize::LongLong),
+            "u" | "U" => (true, IntegerSize::Int),
+            "ul" | "uL" | "Ul" | "UL" | "lu" | "lU" | "Lu" | "LU" => (true, IntegerSize::Long),
+            "ull" | "uLL" | "Ull" | "ULL" | "llu" | "llU" | "LLu" | "LLU" => {
+                (true, IntegerSize::LongLong)
+            }
+            _ => return None,
+        };
+
+        let (digits, radix) = if let Some(digits) = digits
+            .strip_prefix("0x")
+            .or_else(|| digits.strip_prefix("0X"))
+        {
+            (digits, 16)
+        } else if let Some(digits) = digits
+            .strip_prefix("0b")
+            .or_else(|| digits.strip_prefix("0B"))
+        {
+            (digits, 2)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            (&digits[1..], 8)
+        } else {
+            (digits, 10)
+        };
+
+        // `from_str_radix` accepts a leading sign, which is not valid here.
+        if digits.starts_with(['+', '-']) {
+            return None;
+        }
+        let value = u64::from_str_radix(digits, radix).ok()?;
+
+        Some(Self {
+            value,
+            radix,
+            unsigned,
+            size,
+        })
+    }
+
+    /// The value of the constant.
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+
+    /// The base the constant is written in: 2, 8, 10 or 16.
+    pub fn radix(&self) -> u32 {
+        self.radix
+    }
+
+    /// Whether the constant has the `u` or `U` suffix.
+    pub fn is_unsigned(&self) -> bool {
+        self.unsigned
+    }
+
+    /// The smallest type the suffix of the constant allows.
+    pub fn size(&self) -> IntegerSize {
+        self.size
+    }
+}
+
+/// The smallest integer type allowed by the suffix of an integer constant. The actual type of the
+/// constant is the first one of the list in section 6.4.4.1p5 of C17 that can represent its
+/// value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntegerSize {
+    /// No `l`, `L`, `ll` or `LL` suffix.
+    Int,
+    /// The `l` or `L` suffix.
+    Long,
+    /// The `ll` or `LL` suffix.
+    LongLong,
+}
+
+/// The value and suffix of a `floating-constant` (section 6.4.4.2 of C17).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FloatingConstant {
+    value: f64,
+    hexadecimal: bool,
+    size: FloatingSize,
+}
+
+impl FloatingConstant {
+    /// Parse the spelling of a `pp-number` as a floating constant. Digit separators are accepted
+    /// as in C23. Return `None` if it is not a valid floating constant.
+    pub fn parse(spelling: &str) -> Option<Self> {
+        let (body, size) = match spelling.as_bytes().last()? {
+            b'f' | b'F' => (&spelling[..spelling.len() - 1], FloatingSize::Float),
+            b'l' | b'L' => (&spelling[..spelling.len() - 1], FloatingSize::LongDouble),
+            _ => (spelling, FloatingSize::Double),
+        };
+        let body = match body.starts_with("0x") || body.starts_with("0X") {
+            true => remove_separators(body, |byte| byte.is_ascii_hexdigit())?,
+            false => remove_separators(body, |byte| byte.is_ascii_digit())?,
+        };
+        let body = &*body;
+
+        if let Some(hex) = body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+            // The binary exponent is mandatory in hexadecimal constants.
+            let (mantissa, exponent) = hex.split_once(['p', 'P'])?;
+            let (integer, fraction) = split_mantissa(mantissa, |c| c.is_ascii_hexdigit())?;
+            let exponent = parse_exponent(exponent)?;
+
+            let mut value = 0.0;
+            for digit in integer.chars().chain(fraction.chars()) {
+                value = value * 16.0 + f64::from(digit.to_digit(16).unwrap());
+            }
+            let exponent = exponent.saturating_sub(4 * fraction.len() as i32);
+            return Some(Self {
+                value: value * 2f64.powi(exponent),
+                hexadecimal: true,
+                size,
+            });
+        }
+
+        let (mantissa, exponent) = match body.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+            None => (body, None),
+        };
+        // Without exponent, the period is what makes the constant floating.
+        if exponent.is_none() && !mantissa.contains('.') {
+            return None;
+        }
+        split_mantissa(mantissa, |c| c.is_ascii_digit())?;
+        if let Some(exponent) = exponent {
+            parse_exponent(exponent)?;
+        }
+
+        Some(Self {
+            value: body.parse().ok()?,
+            hexadecimal: false,
+            size,
+        })
+    }
+
+    /// The value of the constant, rounded to the nearest `double`.
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// Whether the constant is a `hexadecimal-floating-constant`.
+    pub fn is_hexadecimal(&self) -> bool {
+        self.hexadecimal
+    }
+
+    /// The type given by the suffix of the constant.
+    pub fn size(&self) -> FloatingSize {
+        self.size
+    }
+}
+
+/// The type of a floating constant, given by its suffix (section 6.4.4.2p4 of C17).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FloatingSize {
+    /// The `f` or `F` suffix.
+    Float,
+    /// No suffix.
+    Double,
+    /// The `l` or `L` suffix.
+    LongDouble,
+}
+
+/// Remove the digit separators of the spelling of a constant (section 6.4.4.1 of C23), which
+/// must be found between two characters that are digits according to `is_digit`. Return `None`
+/// if any of them is not.
+fn remove_separators(spelling: &str, is_digit: impl Fn(u8) -> bool) -> Option<Cow<'_, str>> {
+    if !spelling.contains('\'') {
+        return Some(Cow::Borrowed(spelling));
+    }
+    let bytes = spelling.as_bytes();
+    for (i, _) in spelling.match_indices('\'') {
+        let before = i.checked_sub(1).map(|before| bytes[before]);
+        let after = bytes.get(i + 1).copied();
+        if !before.is_some_and(&is_digit) || !after.is_some_and(&is_digit) {
+            return None;
+        }
+    }
+    Some(Cow::Owned(spelling.replace('\'', "")))
+}
+
+/// Split the mantissa of a floating constant into the digits before and after the period, if
+/// it has at least one digit and every character besides the period is a digit.
+fn split_mantissa(mantissa: &str, is_digit: impl Fn(char) -> bool) -> Option<(&str, &str)> {
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let valid = |digits: &str| digits.chars().all(&is_digit);
+    if integer.is_empty() && fraction.is_empty() || !valid(integer) || !valid(fraction) {
+        return None;
+    }
+    Some((integer, fraction))
+}
+
+/// Parse the `exponent-part` or `binary-exponent-part` of a floating constant after the `e` or
+/// `p`. Exponents too large to fit in an `i32` saturate.
+fn parse_exponent(exponent: &str) -> Option<i32> {
+    let (negative, digits) = match exponent.as_bytes().first()? {
+        b'+' => (false, &exponent[1..]),
+        b'-' => (true, &exponent[1..]),
+        _ => (false, exponent),
+    };
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let value = digits.parse().unwrap_or(i32::MAX);
+    Some(if negative { -value } else { value })
+}
+
+/// Whether the `pp-number` spelled `spelling` must be a floating constant instead of an integer
+/// constant.
+fn is_floating(spelling: &str) -> bool {
+    match spelling
+        .strip_prefix("0x")
+        .or_else(|| spelling.strip_prefix("0X"))
+    {
+        Some(hex) => hex.contains(['.', 'p', 'P']),
+        None => spelling.contains(['.', 'e', 'E']),
+    }
+}
+
+/// The tokens produced from the output of a preprocessing run by
+/// [`c_tokens`](crate::Preprocessed::c_tokens) and the errors found while producing them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CTokens {
+    tokens: Vec<CToken>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl CTokens {
+    /// The tokens, in the order of the output. Preprocessing tokens that cannot be converted are
+    /// not included.
+    pub fn tokens(&self) -> &[CToken] {
+        &self.tokens
+    }
+
+    /// The errors found, one per preprocessing token that cannot be converted, with the
+    /// severities chosen using [`diagnostic_level`](crate::Preprocessor::diagnostic_level).
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Change the severities of the diagnostics to the ones of `severities`.
+    pub(crate) fn with_severities(mut self, severities: &SeverityMap) -> Self {
+        let diagnostics = std::mem::take(&mut self.diagnostics);
+        self.diagnostics = diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| severities.apply(diagnostic))
+            .collect();
+        self
+    }
+
+    /// Convert `tokens` into tokens, skipping white-space, following the version `standard` of
+    /// C.
+    pub(crate) fn convert(tokens: &[Token], map: &SourceMap, standard: Standard) -> Self {
+        let mut c_tokens = Self::default();
+        for &token in tokens {
+            let spelling = map.spelling(token);
+            let kind = match token.kind {
+                TokenKind::Space | TokenKind::Newline => continue,
+                TokenKind::Ident => match Keyword::from_spelling(&spelling, standard) {
+                    Some(keyword) => Some(CTokenKind::Keyword(keyword)),
+                    None => Some(CTokenKind::Ident),
+                },
+                TokenKind::Number if is_floating(&spelling) => {
+                    FloatingConstant::parse(&spelling).map(CTokenKind::Floating)
+                }
+                TokenKind::Number => IntegerConstant::parse(&spelling).map(CTokenKind::Integer),
+                TokenKind::Char => Some(CTokenKind::Char),
+                TokenKind::Str => Some(CTokenKind::Str),
+                // The preprocessing operators are not punctuators after preprocessing (section
+                // 6.4.6p3 of C17).
+                TokenKind::Punct if map.is_punct(token, b"#") || map.is_punct(token, b"##") => None,
+                TokenKind::Punct => Some(CTokenKind::Punct),
+                TokenKind::Header | TokenKind::Any => None,
+            };
+
+            match kind {
+                Some(kind) => c_tokens.tokens.push(CToken {
+                    kind,
+                    span: token.span,
+                }),
+                None => {
+                    let (code, message) = match token.kind {
+                        TokenKind::Number => (
+                            DiagnosticCode::InvalidNumber,
+                            format!("Invalid numeric constant \"{}\"", spelling),
+                        ),
+                        _ => (
+                            DiagnosticCode::StrayToken,
+                            format!("Stray \"{}\" in program", spelling),
+                        ),
+                    };
+                    let diagnostic = map.diagnostic(Severity::Error, code, token.span, message);
+                    c_tokens.diagnostics.push(diagnostic);
+                }
+            }
+        }
+        c_tokens
+    }
+}
@@ -0,0 +1,95 @@
+//! Integration with build scripts that compile C code using the [`cc`] crate.
+#[cfg(test)]
+mod tests;
+
+use std::{env, path::Path};
+
+use crate::{CommandLine, Error, Preprocessed, Preprocessor, Target};
+
+/// A preprocessor configured like a [`cc::Build`], for use inside build scripts.
+///
+/// The include directories and macro definitions of the build are taken from the arguments of
+/// its compiler, which also contain the flags set using the `CFLAGS` environment variables. If
+/// the `TARGET` environment variable set by Cargo names a supported target, its predefined
+/// macros are used as well.
+///
+/// ```no_run
+/// let mut build = cc::Build::new();
+/// build.include("include").define("USE_THREADS", None);
+///
+/// // Emits `cargo:rerun-if-changed` for every file read and `cargo:warning` for every diagnostic.
+/// let preprocessed = beheader::CcBuild::new(&build)
+///     .preprocess_file(&"src/config.h")
+///     .unwrap();
+/// if preprocessed.get_macro("HAVE_ZLIB").is_some() {
+///     build.file("src/compress.c");
+/// }
+/// build.file("src/main.c").compile("main");
+/// ```
+#[derive(Clone)]
+pub struct CcBuild {
+    preprocessor: Preprocessor,
+    cargo_metadata: bool,
+}
+
+impl CcBuild {
+    /// Create a preprocessor with the include directories and macro definitions of `build`.
+    ///
+    /// Panic if the compiler of `build` cannot be determined, as
+    /// [`cc::Build::get_compiler`] does.
+    pub fn new(build: &cc::Build) -> Self {
+        let compiler = build.get_compiler();
+        let directory = env::current_dir().unwrap_or_default();
+
+        // Arguments that are not valid UTF-8 cannot affect the options understood here.
+        let arguments = compiler
+            .args()
+            .iter()
+            .filter_map(|argument| argument.to_str());
+        let mut preprocessor = CommandLine::parse(arguments, &directory).into_preprocessor();
+        if let Some(target) = env::var("TARGET")
+            .ok()
+            .and_then(|t| Target::from_triple(&t))
+        {
+            preprocessor.target(target);
+        }
+
+        Self {
+            preprocessor,
+            cargo_metadata: true,
+        }
+    }
+
+    /// Get the preprocessor, which can be configured further.
+    pub fn preprocessor(&mut self) -> &mut Preprocessor {
+        &mut self.preprocessor
+    }
+
+    /// Set whether the metadata used by Cargo is printed after preprocessing a file: a
+    /// `cargo:rerun-if-changed` line for each file read and a `cargo:warning` line for each
+    /// diagnostic.
+    ///
+    /// By default, the metadata is printed.
+    pub fn cargo_metadata(&mut self, enabled: bool) -> &mut Self {
+        self.cargo_metadata = enabled;
+        self
+    }
+
+    /// Preprocess a file.
+    pub fn preprocess_file<P: AsRef<Path>>(&self, path: &P) -> Result<Preprocessed, Error> {
+        let preprocessed = self.preprocessor.preprocess_file(path)?;
+
+        if self.cargo_metadata {
+            for dependency in preprocessed.dependencies() {
+                println!("cargo:rerun-if-changed={}", dependency.display());
+            }
+            for diagnostic in preprocessed.diagnostics() {
+                // Each `cargo:warning` line is a single warning.
+                let diagnostic = diagnostic.to_string().replace('\n', " ");
+                println!("cargo:warning={}", diagnostic);
+            }
+        }
+
+        Ok(preprocessed)
+    }
+}
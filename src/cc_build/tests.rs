@@ -0,0 +1,37 @@
+use super::CcBuild;
+
+#[test]
+fn configuration() {
+    let dir = std::env::temp_dir().join("beheader-tests").join("cc_build");
+    std::fs::create_dir_all(dir.join("include")).unwrap();
+    std::fs::write(
+        dir.join("main.c"),
+        "#include <config.h>\n#if defined(USE_THREADS) && LEVEL == 2\nthreads\n#endif\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("include/config.h"), "#define VALUE 1\n").unwrap();
+
+    let mut build = cc::Build::new();
+    build
+        .target("x86_64-unknown-linux-gnu")
+        .host("x86_64-unknown-linux-gnu")
+        .opt_level(0)
+        .compiler("cc")
+        .cargo_metadata(false)
+        .include(dir.join("include"))
+        .define("USE_THREADS", None)
+        .define("LEVEL", "2");
+
+    let preprocessed = CcBuild::new(&build)
+        .cargo_metadata(false)
+        .preprocess_file(&dir.join("main.c"))
+        .unwrap();
+
+    assert!(preprocessed.diagnostics().is_empty());
+    assert!(preprocessed.get_macro("VALUE").is_some());
+    assert_eq!(preprocessed.get_macro("LEVEL").unwrap().replacement(), "2");
+    assert_eq!(
+        preprocessed.dependencies(),
+        [dir.join("main.c"), dir.join("include/config.h")]
+    );
+}
@@ -0,0 +1,165 @@
+//! The encodings of the source code read by the preprocessor, which is converted to UTF-8.
+use std::{borrow::Cow, io};
+
+use crate::standard::LexerFeatures;
+
+/// The characters that form a trigraph sequence after `??` and the characters they stand for
+/// (section 5.2.1.1p1 of C17).
+pub(crate) const TRIGRAPHS: &[(u8, char)] = &[
+    (b'=', '#'),
+    (b'(', '['),
+    (b'/', '\\'),
+    (b')', ']'),
+    (b'\'', '^'),
+    (b'<', '{'),
+    (b'!', '|'),
+    (b'>', '}'),
+    (b'-', '~'),
+];
+
+/// The encoding of the source code read by the preprocessor, chosen using
+/// [`Preprocessor::input_charset`](crate::Preprocessor::input_charset) like the `-finput-charset`
+/// option of GCC. Source code in any other encoding than UTF-8 is converted to UTF-8 when it is
+/// read, so the [`Span`](crate::Span)s and columns of the result refer to the converted contents.
+///
+/// ```
+/// use beheader::{Emitter, InputCharset, MemoryFileSystem, Preprocessor};
+///
+/// let source: Vec<u8> = "\u{feff}#define A 1\nint a = A;\n"
+///     .encode_utf16()
+///     .flat_map(|unit| unit.to_le_bytes())
+///     .collect();
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", source);
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor
+///     .file_system(files)
+///     .input_charset(InputCharset::from_name("UTF-16").unwrap());
+/// let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+/// let text = Emitter::new().line_markers(false).render(&preprocessed);
+/// assert_eq!(text, b"int a = 1;\n");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum InputCharset {
+    /// UTF-8, whose contents are used as they are.
+    #[default]
+    Utf8,
+    /// UTF-16 whose byte order is given by its byte order mark, which is removed. Contents
+    /// without a byte order mark are big-endian, as `iconv` does.
+    Utf16,
+    /// Little-endian UTF-16. A byte order mark at the start is removed.
+    Utf16Le,
+    /// Big-endian UTF-16. A byte order mark at the start is removed.
+    Utf16Be,
+    /// ISO-8859-1, also known as Latin-1, where each byte is the code point with the same value.
+    Latin1,
+}
+
+impl InputCharset {
+    /// Get the encoding named `name`, as used by `-finput-charset`. Names are compared ignoring
+    /// case, `-` and `_`, so `UTF-16LE` and `utf_16le` are the same encoding. `ISO-8859-1` is
+    /// accepted for [`Latin1`](Self::Latin1).
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name: String = name
+            .chars()
+            .filter(|&ch| ch != '-' && ch != '_')
+            .map(|ch| ch.to_ascii_lowercase())
+            .collect();
+        match name.as_str() {
+            "utf8" => Some(Self::Utf8),
+            "utf16" => Some(Self::Utf16),
+            "utf16le" => Some(Self::Utf16Le),
+            "utf16be" => Some(Self::Utf16Be),
+            "latin1" | "iso88591" => Some(Self::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Convert the source code `bytes` from this encoding to UTF-8 and replace its trigraph
+    /// sequences if `features` requires it, as the first translation phase does (section
+    /// 5.1.1.2p1 of C17). Return an error if they are not valid in this encoding.
+    pub(crate) fn decode_source(
+        self,
+        bytes: Cow<'_, [u8]>,
+        features: LexerFeatures,
+    ) -> io::Result<Cow<'_, [u8]>> {
+        let bytes = self.decode(bytes)?;
+        match features.trigraphs {
+            true => Ok(replace_trigraphs(bytes)),
+            false => Ok(bytes),
+        }
+    }
+
+    /// Convert `bytes` from this encoding to UTF-8. Return an error if they are not valid in this
+    /// encoding.
+    fn decode(self, bytes: Cow<'_, [u8]>) -> io::Result<Cow<'_, [u8]>> {
+        let little_endian = match self {
+            Self::Utf8 => return Ok(bytes),
+            Self::Latin1 => {
+                let text: String = bytes.iter().map(|&byte| char::from(byte)).collect();
+                return Ok(Cow::Owned(text.into_bytes()));
+            }
+            Self::Utf16 => bytes.starts_with(&[0xFF, 0xFE]),
+            Self::Utf16Le => true,
+            Self::Utf16Be => false,
+        };
+        if !bytes.len().is_multiple_of(2) {
+            return Err(invalid_utf16());
+        }
+        let units = bytes.chunks_exact(2).map(|pair| {
+            let pair = [pair[0], pair[1]];
+            if little_endian {
+                u16::from_le_bytes(pair)
+            } else {
+                u16::from_be_bytes(pair)
+            }
+        });
+        let mut text = String::with_capacity(bytes.len() / 2);
+        for ch in char::decode_utf16(units) {
+            text.push(ch.map_err(|_| invalid_utf16())?);
+        }
+        if text.starts_with('\u{feff}') {
+            text.drain(..'\u{feff}'.len_utf8());
+        }
+        Ok(Cow::Owned(text.into_bytes()))
+    }
+}
+
+/// Replace the trigraph sequences of `bytes` by the characters they stand for.
+fn replace_trigraphs(bytes: Cow<'_, [u8]>) -> Cow<'_, [u8]> {
+    let trigraph = |bytes: &[u8]| match bytes {
+        [b'?', b'?', byte, ..] => TRIGRAPHS
+            .iter()
+            .find(|(trigraph, _)| trigraph == byte)
+            .map(|&(_, replacement)| replacement as u8),
+        _ => None,
+    };
+    if !bytes.windows(3).any(|window| trigraph(window).is_some()) {
+        return bytes;
+    }
+    let mut replaced = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match trigraph(&bytes[pos..]) {
+            Some(byte) => {
+                replaced.push(byte);
+                pos += 3;
+            }
+            None => {
+                replaced.push(bytes[pos]);
+                pos += 1;
+            }
+        }
+    }
+    Cow::Owned(replaced)
+}
+
+/// The error for contents that are not valid UTF-16.
+fn invalid_utf16() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the source code is not valid UTF-16",
+    )
+}
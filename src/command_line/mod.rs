@@ -0,0 +1,420 @@
+//! Configuration of the preprocessor from the command-line options of a compiler.
+#[cfg(test)]
+mod tests;
+
+use std::path::{Path, PathBuf};
+
+use crate::{
+    ColumnUnit, Comments, Depfile, Extension, InputCharset, Level, MacroDump, Preprocessor,
+    Standard, Target,
+};
+
+/// The options of GCC-compatible compilers that do not affect preprocessing and whose value can
+/// be the next argument, so it is not taken for an input file.
+const IGNORED_WITH_VALUE: &[&str] = &[
+    "-x",
+    "-arch",
+    "-L",
+    "-l",
+    "-T",
+    "-u",
+    "-z",
+    "-e",
+    "-Xlinker",
+    "-Xassembler",
+    "-Xclang",
+    "-MJ",
+    "-aux-info",
+    "--param",
+    "-include-pch",
+];
+
+/// The command-line arguments of a GCC-compatible compiler, parsed into the configuration of a
+/// [`Preprocessor`], the input and output files and the dependency file to write, so wrappers
+/// around compilers interpret them the same way.
+///
+/// The options understood are the ones that affect preprocessing:
+///
+/// - `-D`, `-U`, `-undef`, `-I`, `-iquote`, `-isystem`, `-idirafter`, `-F`, `-nostdinc`,
+///   `-include`, `-imacros`, `--sysroot` and `-isysroot`.
+/// - `-std`, `-ansi`, `-target`, `-fmax-errors`, `-fdiagnostics-column-unit`, `-ftabstop`,
+///   `-finput-charset`, `-ffile-prefix-map`, `-fmacro-prefix-map`, `-fdollars-in-identifiers`,
+///   `-fno-dollars-in-identifiers`, `-pedantic` and `-pedantic-errors`.
+/// - The `-W` options of the supported [`Warning`](crate::Warning)s, like `-Wall`, `-Wno-undef`
+///   or `-Werror`.
+/// - `-MD`, `-MMD`, `-MF`, `-MT`, `-MQ` and `-MP`, which describe the dependency file.
+/// - `-o`, `-P`, `-C`, `-CC`, `-dM`, `-dD`, `-dN` and `-dI`, which describe the output.
+/// - `-Wp,<options>` and `-Xpreprocessor <option>`, whose options are parsed as if they were
+///   given directly, except that `-MD` and `-MMD` are followed by the path of the dependency
+///   file, as they are for the preprocessor of GCC.
+///
+/// As in GCC, the standard is `gnu17` unless another one is given, and the extensions of GCC
+/// are enabled unless the standard is a strict ISO C one, like `-std=c11`.
+///
+/// Any other argument starting with `-` is ignored and the rest are input files. Options can be
+/// followed by their value in the same argument or in the next one, except for those that end
+/// with `=`. Relative paths are relative to the directory given when parsing.
+///
+/// ```
+/// use std::path::Path;
+///
+/// use beheader::CommandLine;
+///
+/// let arguments = "-c -O2 -DLEVEL=2 -Iinclude -Wp,-MMD,main.d -o main.o main.c".split(' ');
+/// let command_line = CommandLine::parse(arguments, Path::new("/project"));
+/// assert_eq!(command_line.inputs(), [Path::new("/project/main.c")]);
+/// assert_eq!(command_line.output(), Some(Path::new("/project/main.o")));
+/// assert_eq!(command_line.ignored(), ["-c", "-O2"]);
+///
+/// let (_, depfile) = command_line.depfile().unwrap();
+/// assert_eq!(depfile, Path::new("/project/main.d"));
+///
+/// let preprocessed = command_line.preprocessor().preprocess(b"LEVEL").unwrap();
+/// assert_eq!(preprocessed.get_macro("LEVEL").unwrap().replacement(), "2");
+/// ```
+#[derive(Clone)]
+pub struct CommandLine {
+    preprocessor: Preprocessor,
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    line_markers: bool,
+    depfile: Option<(Depfile, PathBuf)>,
+    ignored: Vec<String>,
+}
+
+impl CommandLine {
+    /// Parse the command-line `arguments` of a GCC-compatible compiler, without the name of the
+    /// compiler, resolving relative paths against `directory`.
+    pub fn parse<'a, I>(arguments: I, directory: &Path) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut parser = Parser {
+            command_line: Self {
+                preprocessor: Preprocessor::new(),
+                inputs: Vec::new(),
+                output: None,
+                line_markers: true,
+                depfile: None,
+                ignored: Vec::new(),
+            },
+            directory,
+            display_columns: None,
+            tab_width: 8,
+            depfile: Depfile::new(),
+            depfile_path: None,
+            dependencies: false,
+            targets: false,
+            output: None,
+            isysroot: None,
+            standard: None,
+            dollar_idents: None,
+        };
+        parser.parse(&mut arguments.into_iter(), false);
+        parser.finish()
+    }
+
+    /// The preprocessor configured with the options.
+    pub fn preprocessor(&self) -> &Preprocessor {
+        &self.preprocessor
+    }
+
+    /// Take the preprocessor configured with the preprocessor.
+    pub fn into_preprocessor(self) -> Preprocessor {
+        self.preprocessor
+    }
+
+    /// The input files, which are every argument that is not an option. The standard input is
+    /// given as `-`.
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    /// The file given to `-o`, if any.
+    pub fn output(&self) -> Option<&Path> {
+        self.output.as_deref()
+    }
+
+    /// Whether the output should have line markers, which is the case unless `-P` is given.
+    pub fn line_markers(&self) -> bool {
+        self.line_markers
+    }
+
+    /// The configuration of the dependency file and the path where it is written, if `-MD` or
+    /// `-MMD` is given.
+    ///
+    /// As GCC does, the path is the one given to `-MF`, or the output file or the name of the
+    /// first input file with its extension replaced by `.d`, and the target is the output file
+    /// if no targets are given.
+    pub fn depfile(&self) -> Option<(&Depfile, &Path)> {
+        let (depfile, path) = self.depfile.as_ref()?;
+        Some((depfile, path))
+    }
+
+    /// The arguments that were ignored because they do not affect preprocessing, or because
+    /// their values were not valid.
+    pub fn ignored(&self) -> &[String] {
+        &self.ignored
+    }
+}
+
+/// The state of parsing a [`CommandLine`].
+struct Parser<'a, 'd> {
+    command_line: CommandLine,
+    /// The directory relative paths are relative to.
+    directory: &'d Path,
+    /// Whether columns are display columns, if it was chosen. The tab width only matters for
+    /// display columns, which can be chosen after it.
+    display_columns: Option<bool>,
+    tab_width: usize,
+    depfile: Depfile,
+    /// The path given to `-MF`.
+    depfile_path: Option<PathBuf>,
+    /// Whether a dependency file should be written.
+    dependencies: bool,
+    /// Whether targets were given to the dependency file.
+    targets: bool,
+    /// The file given to `-o` as it was written.
+    output: Option<&'a str>,
+    /// The directory given to `-isysroot`, which takes precedence over `--sysroot`.
+    isysroot: Option<PathBuf>,
+    /// The standard given to `-std` or `-ansi`.
+    standard: Option<Standard>,
+    /// Whether `$` is accepted in identifiers, if it was chosen. The extensions of GCC, which
+    /// include it, are only enabled once the standard is known.
+    dollar_idents: Option<bool>,
+}
+
+impl<'a> Parser<'a, '_> {
+    /// Parse `arguments`, which are options given to the preprocessor itself if `direct` is
+    /// true.
+    fn parse<I: Iterator<Item = &'a str>>(&mut self, arguments: &mut I, direct: bool) {
+        let directory = self.directory;
+        let path = |path: &str| -> PathBuf {
+            // Paths inside the sysroot are resolved by the preprocessor.
+            if path.starts_with('=') || path.starts_with("$SYSROOT") {
+                PathBuf::from(path)
+            } else {
+                directory.join(path)
+            }
+        };
+
+        while let Some(argument) = arguments.next() {
+            // The names of these options can start with the name of one that is understood, like
+            // `-include-pch`, so they are checked first.
+            if IGNORED_WITH_VALUE.contains(&argument) {
+                let ignored = &mut self.command_line.ignored;
+                ignored.push(argument.to_owned());
+                ignored.extend(arguments.next().map(str::to_owned));
+                continue;
+            }
+
+            // Options can be followed by their value in the same argument or in the next one.
+            let mut value = |option: &str| -> Option<&'a str> {
+                let rest = argument.strip_prefix(option)?;
+                if rest.is_empty() {
+                    arguments.next()
+                } else {
+                    Some(
+                        rest.strip_prefix('=')
+                            .filter(|_| option.starts_with("--"))
+                            .unwrap_or(rest),
+                    )
+                }
+            };
+
+            let command_line = &mut self.command_line;
+            let preprocessor = &mut command_line.preprocessor;
+            if let Some(definition) = value("-D") {
+                preprocessor.define(definition);
+            } else if let Some(name) = value("-U") {
+                preprocessor.undefine(name);
+            } else if let Some(dir) = value("-iquote") {
+                preprocessor.quote_include_dir(path(dir));
+            } else if let Some(dir) = value("-isystem") {
+                preprocessor.system_include_dir(path(dir));
+            } else if let Some(dir) = value("-idirafter") {
+                preprocessor.include_dir_after(path(dir));
+            } else if let Some(file) = value("-include") {
+                preprocessor.include(path(file));
+            } else if let Some(file) = value("-imacros") {
+                preprocessor.include_macros(path(file));
+            } else if let Some(dir) = value("--sysroot") {
+                preprocessor.sysroot(path(dir));
+            } else if let Some(dir) = value("-isysroot") {
+                self.isysroot = Some(path(dir));
+            } else if let Some(dir) = value("-I") {
+                preprocessor.include_dir(path(dir));
+            } else if let Some(dir) = value("-F") {
+                preprocessor.framework_dir(path(dir));
+            } else if let Some(standard) =
+                argument.strip_prefix("-std=").and_then(Standard::from_name)
+            {
+                self.standard = Some(standard);
+            } else if let Some(triple) = value("--target").or_else(|| value("-target")) {
+                match Target::from_triple(triple) {
+                    Some(target) => {
+                        preprocessor.target(target);
+                    }
+                    None => {
+                        command_line.ignored.push(argument.to_owned());
+                        if !argument.ends_with(triple) {
+                            command_line.ignored.push(triple.to_owned());
+                        }
+                    }
+                }
+            } else if argument == "-undef" {
+                preprocessor.standard_macros_only(true);
+            } else if argument == "-nostdinc" {
+                // The standard include directories are never searched, only the ones given.
+            } else if argument == "-ansi" {
+                self.standard = Some(Standard::C89);
+            } else if argument == "-fdollars-in-identifiers" {
+                self.dollar_idents = Some(true);
+            } else if argument == "-fno-dollars-in-identifiers" {
+                self.dollar_idents = Some(false);
+            } else if argument == "-pedantic" {
+                preprocessor.pedantic(Level::Warn);
+            } else if argument == "-pedantic-errors" {
+                preprocessor.pedantic(Level::Error);
+            } else if let Some(arguments) = argument.strip_prefix("-Wp,") {
+                self.parse(&mut arguments.split(','), true);
+            } else if argument == "-Xpreprocessor" {
+                if let Some(argument) = arguments.next() {
+                    self.parse(&mut std::iter::once(argument), true);
+                }
+            } else if argument.starts_with("-W")
+                && !argument.starts_with("-Wl,")
+                && !argument.starts_with("-Wa,")
+            {
+                preprocessor.warning_option(argument);
+            } else if let Some(limit) = argument
+                .strip_prefix("-fmax-errors=")
+                .and_then(|limit| limit.parse().ok())
+            {
+                preprocessor.max_errors(limit);
+            } else if let Some(display) = argument
+                .strip_prefix("-fdiagnostics-column-unit=")
+                .and_then(|unit| match unit {
+                    "byte" => Some(false),
+                    "display" => Some(true),
+                    _ => None,
+                })
+            {
+                self.display_columns = Some(display);
+            } else if let Some((old, new)) = argument
+                .strip_prefix("-ffile-prefix-map=")
+                .and_then(|map| map.split_once('='))
+            {
+                preprocessor.file_prefix_map(old, new);
+            } else if let Some((old, new)) = argument
+                .strip_prefix("-fmacro-prefix-map=")
+                .and_then(|map| map.split_once('='))
+            {
+                preprocessor.macro_prefix_map(old, new);
+            } else if let Some(charset) = argument
+                .strip_prefix("-finput-charset=")
+                .and_then(InputCharset::from_name)
+            {
+                preprocessor.input_charset(charset);
+            } else if let Some(width) = argument
+                .strip_prefix("-ftabstop=")
+                .and_then(|width| width.parse().ok())
+            {
+                self.tab_width = width;
+            } else if argument == "-MD" || argument == "-MMD" {
+                self.dependencies = true;
+                self.depfile.skip_system_headers(argument == "-MMD");
+                // The preprocessor of GCC takes the path of the dependency file after them.
+                if direct {
+                    if let Some(file) = value(argument) {
+                        self.depfile_path = Some(path(file));
+                    }
+                }
+            } else if let Some(file) = value("-MF") {
+                self.depfile_path = Some(path(file));
+            } else if let Some(target) = value("-MT") {
+                self.depfile.target(target);
+                self.targets = true;
+            } else if let Some(target) = value("-MQ") {
+                self.depfile.quoted_target(target);
+                self.targets = true;
+            } else if argument == "-MP" {
+                self.depfile.phony_targets(true);
+            } else if let Some(file) = value("-o") {
+                command_line.output = Some(path(file));
+                self.output = Some(file);
+            } else if argument == "-P" {
+                command_line.line_markers = false;
+            } else if argument == "-C" {
+                preprocessor.keep_comments(Comments::Text);
+            } else if argument == "-CC" {
+                preprocessor.keep_comments(Comments::All);
+            } else if argument == "-dM" {
+                preprocessor.dump_macros(MacroDump::Final);
+            } else if argument == "-dD" {
+                preprocessor.dump_macros(MacroDump::Directives);
+            } else if argument == "-dN" {
+                preprocessor.dump_macros(MacroDump::Names);
+            } else if argument == "-dI" {
+                preprocessor.keep_include_directives(true);
+            } else if argument == "-" || !argument.starts_with('-') {
+                command_line.inputs.push(path(argument));
+            } else {
+                command_line.ignored.push(argument.to_owned());
+            }
+        }
+    }
+
+    /// Apply the options that depend on other options.
+    fn finish(mut self) -> CommandLine {
+        let command_line = &mut self.command_line;
+        if let Some(dir) = self.isysroot {
+            command_line.preprocessor.sysroot(dir);
+        }
+        let standard = self.standard.unwrap_or(Standard::Gnu17);
+        command_line.preprocessor.standard(standard);
+        if standard.is_gnu() {
+            for &extension in Extension::GNU {
+                command_line.preprocessor.extension(extension, true);
+            }
+        }
+        if let Some(enabled) = self.dollar_idents {
+            command_line
+                .preprocessor
+                .extension(Extension::DollarIdents, enabled);
+        }
+        match self.display_columns {
+            Some(true) => {
+                let tab_width = self.tab_width;
+                command_line
+                    .preprocessor
+                    .column_unit(ColumnUnit::Display { tab_width });
+            }
+            Some(false) => {
+                command_line.preprocessor.column_unit(ColumnUnit::Bytes);
+            }
+            None => {}
+        }
+
+        if self.dependencies {
+            if let (false, Some(output)) = (self.targets, self.output) {
+                self.depfile.quoted_target(output);
+            }
+            let path = self.depfile_path.unwrap_or_else(|| {
+                let mut path = match (&command_line.output, command_line.inputs.first()) {
+                    (Some(output), _) => output.clone(),
+                    (None, Some(input)) => {
+                        self.directory.join(input.file_name().unwrap_or_default())
+                    }
+                    (None, None) => self.directory.join("-"),
+                };
+                path.set_extension("d");
+                path
+            });
+            command_line.depfile = Some((self.depfile, path));
+        }
+
+        self.command_line
+    }
+}
@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use super::CommandLine;
+use crate::{Emitter, MemoryFileSystem, Standard, Target};
+
+fn parse(arguments: &str) -> CommandLine {
+    CommandLine::parse(arguments.split_whitespace(), Path::new("/project"))
+}
+
+#[test]
+fn preprocessor_options() {
+    let command_line = parse(
+        "-DA=1 -D B -UB -Iinclude -isystem sys -Xpreprocessor -DC=3 -Wp,-DD=4,-UA \
+         -include config.h -std=c99 -fmax-errors=1 -Wall main.c",
+    );
+    assert_eq!(command_line.inputs(), [Path::new("/project/main.c")]);
+    assert!(command_line.ignored().is_empty());
+
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("/project/config.h", "#include <sys.h>\n")
+        .insert("/project/sys/sys.h", "#define SYS 5\n");
+    let mut preprocessor = command_line.into_preprocessor();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor
+        .preprocess(b"A B C D SYS __STDC_VERSION__\n")
+        .unwrap();
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        format!("A B 3 4 5 {}\n", Standard::C99.version().unwrap())
+    );
+}
+
+#[test]
+fn options_with_similar_names() {
+    let command_line = parse("-isysroot /sdk -include-pch foo.pch -isystem =/inc main.c");
+    assert_eq!(command_line.inputs(), [Path::new("/project/main.c")]);
+    assert_eq!(command_line.ignored(), ["-include-pch", "foo.pch"]);
+
+    let mut files = MemoryFileSystem::new();
+    files.insert("/sdk/inc/sys.h", "#define SYS 1\n");
+    let mut preprocessor = command_line.into_preprocessor();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess(b"#include <sys.h>\nSYS\n").unwrap();
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(text, b"1\n");
+}
+
+#[test]
+fn output_options() {
+    let source = b"#include \"a.h\"\n#define A (/* a */ 1)\nA // b\n";
+    let render = |arguments| {
+        let command_line = parse(arguments);
+        assert!(command_line.ignored().is_empty());
+        let mut files = MemoryFileSystem::new();
+        files.insert("a.h", "");
+        let mut preprocessor = command_line.into_preprocessor();
+        preprocessor
+            .file_system(files)
+            .target(Target::x86_64_linux_gnu());
+        let preprocessed = preprocessor.preprocess(source).unwrap();
+        let text = Emitter::new().line_markers(false).render(&preprocessed);
+        String::from_utf8(text).unwrap()
+    };
+
+    assert_eq!(render("-nostdinc"), "( 1)\n");
+    assert_eq!(render("-C"), "( 1) // b\n");
+    assert_eq!(render("-CC"), "( /* a */ 1) // b\n");
+    assert_eq!(render("-dI"), "#include \"a.h\"\n\n( 1)\n");
+    assert_eq!(render("-dD"), "#define A ( 1)\n( 1)\n");
+    assert_eq!(render("-dN"), "#define A\n( 1)\n");
+    assert!(render("-dM").contains("#define __x86_64__ 1\n"));
+    assert!(!render("-undef -dM").contains("__x86_64__"));
+}
+
+#[test]
+fn prefix_maps() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("/project/src/main.c", "__FILE__\n#if 1 / 0\n#endif\n");
+    let render = |arguments| {
+        let mut preprocessor = parse(arguments).into_preprocessor();
+        preprocessor.file_system(files.clone());
+        let preprocessed = preprocessor
+            .preprocess_file(&"/project/src/main.c")
+            .unwrap();
+        let file = preprocessed.diagnostics()[0].file().unwrap().to_owned();
+        let text = Emitter::new().line_markers(false).render(&preprocessed);
+        (String::from_utf8(text).unwrap(), file)
+    };
+    assert_eq!(
+        render("-fmacro-prefix-map=/project=. main.c"),
+        (
+            "\"./src/main.c\"\n\n".to_owned(),
+            "/project/src/main.c".into()
+        )
+    );
+    assert_eq!(
+        render("-ffile-prefix-map=/project/src=. main.c"),
+        ("\"./main.c\"\n\n".to_owned(), "./main.c".into())
+    );
+}
+
+#[test]
+fn standard_and_target() {
+    let source = b"#define L(a...) a\nL(1) __COUNTER__ $x __x86_64__ __aarch64__\n";
+    let render = |arguments| {
+        let preprocessor = parse(arguments).into_preprocessor();
+        let preprocessed = preprocessor.preprocess(source).unwrap();
+        let text = Emitter::new().line_markers(false).render(&preprocessed);
+        (
+            String::from_utf8(text).unwrap(),
+            preprocessed.diagnostics().len(),
+        )
+    };
+
+    // The extensions of GCC are enabled unless the standard is a strict one.
+    let (text, errors) = render("-target x86_64-linux-gnu main.c");
+    assert_eq!((text.as_str(), errors), ("1 0 $x 1 __aarch64__\n", 0));
+    assert_eq!(
+        render("--target=aarch64-apple-darwin -std=gnu11 main.c").0,
+        "1 0 $x __x86_64__ 1\n"
+    );
+    let preprocessor = parse("-fno-dollars-in-identifiers main.c").into_preprocessor();
+    let preprocessed = preprocessor.preprocess(b"#define $x 1\n").unwrap();
+    assert_eq!(preprocessed.diagnostics().len(), 1);
+    let (_, errors) = render("-std=c11 main.c");
+    assert!(errors > 0);
+
+    let command_line = parse("-target sparc-sun-solaris main.c");
+    assert_eq!(command_line.ignored(), ["-target", "sparc-sun-solaris"]);
+}
+
+#[test]
+fn output() {
+    let command_line = parse("-E -P -o out/main.i - -x c -arch x86_64 -Wl,-O1 -std=c42");
+    assert_eq!(command_line.inputs(), [Path::new("/project/-")]);
+    assert_eq!(
+        command_line.output(),
+        Some(Path::new("/project/out/main.i"))
+    );
+    assert!(!command_line.line_markers());
+    assert!(command_line.depfile().is_none());
+    assert_eq!(
+        command_line.ignored(),
+        ["-E", "-x", "c", "-arch", "x86_64", "-Wl,-O1", "-std=c42"]
+    );
+
+    assert!(parse("main.c").line_markers());
+    assert!(parse("main.c").output().is_none());
+}
+
+#[test]
+fn depfile() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("/project/main.c", "#include <a.h>\n#include <b.h>\n")
+        .insert("/project/include/a.h", "")
+        .insert("/project/sys/b.h", "");
+    let render = |arguments: &str| {
+        let command_line = parse(arguments);
+        let (depfile, path) = command_line.depfile().unwrap();
+        let mut preprocessor = command_line.preprocessor().clone();
+        preprocessor.file_system(files.clone());
+        let preprocessed = preprocessor.preprocess_file(&"/project/main.c").unwrap();
+        (depfile.render(&preprocessed), path.to_owned())
+    };
+
+    // The path and the target come from the output file if there is one.
+    assert_eq!(
+        render("-MD -Iinclude -isystem sys -c -o obj/main.o main.c"),
+        (
+            "obj/main.o: /project/main.c /project/include/a.h /project/sys/b.h\n".to_owned(),
+            Path::new("/project/obj/main.d").to_owned()
+        )
+    );
+    assert_eq!(
+        render("-Iinclude -isystem sys -MMD -MP -MT main main.c"),
+        (
+            "main: /project/main.c /project/include/a.h\n/project/include/a.h:\n".to_owned(),
+            Path::new("/project/main.d").to_owned()
+        )
+    );
+    // The preprocessor of GCC takes the path after `-MD` and `-MMD`.
+    assert_eq!(
+        render("-Iinclude -isystem sys -Wp,-MMD,deps/main.dep -MQ $out -o main.o main.c"),
+        (
+            "$$out: /project/main.c /project/include/a.h\n".to_owned(),
+            Path::new("/project/deps/main.dep").to_owned()
+        )
+    );
+    assert_eq!(
+        render("-Iinclude -isystem sys -MD -MF main.deps main.c").1,
+        Path::new("/project/main.deps")
+    );
+}
@@ -0,0 +1,157 @@
+//! Compilation databases in the `compile_commands.json` format used by Clang tooling.
+#[cfg(test)]
+mod tests;
+
+use std::{
+    error, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde_json::Value;
+
+use crate::{CommandLine, Error, Preprocessed, Preprocessor};
+
+/// A compilation database, which lists the commands used to compile each translation unit of a
+/// project. Its format is described in the
+/// [Clang documentation](https://clang.llvm.org/docs/JSONCompilationDatabase.html).
+///
+/// ```
+/// use beheader::CompilationDatabase;
+///
+/// let database: CompilationDatabase = r#"[{
+///     "directory": "/project",
+///     "command": "cc -DNDEBUG -Iinclude -c src/main.c",
+///     "file": "src/main.c"
+/// }]"#
+/// .parse()
+/// .unwrap();
+///
+/// let command = &database.commands()[0];
+/// assert_eq!(command.file(), "/project/src/main.c");
+/// let preprocessor = command.preprocessor();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompilationDatabase {
+    commands: Vec<CompileCommand>,
+}
+
+impl CompilationDatabase {
+    /// Read a compilation database from the file at `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        contents
+            .parse()
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// The commands inside the database, in the order they were listed.
+    pub fn commands(&self) -> &[CompileCommand] {
+        &self.commands
+    }
+}
+
+impl std::str::FromStr for CompilationDatabase {
+    type Err = ParseCompilationDatabaseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |message: &str| ParseCompilationDatabaseError {
+            message: message.to_owned(),
+        };
+
+        let value: Value =
+            serde_json::from_str(s).map_err(|error| ParseCompilationDatabaseError {
+                message: error.to_string(),
+            })?;
+        let entries = value
+            .as_array()
+            .ok_or_else(|| error("the database must be an array"))?;
+
+        let mut commands = Vec::new();
+        for entry in entries {
+            let field = |name| entry.get(name).and_then(Value::as_str);
+            let directory = PathBuf::from(
+                field("directory").ok_or_else(|| error("missing \"directory\" in entry"))?,
+            );
+            let file =
+                directory.join(field("file").ok_or_else(|| error("missing \"file\" in entry"))?);
+
+            let arguments = match (entry.get("arguments"), field("command")) {
+                (Some(Value::Array(arguments)), _) => arguments
+                    .iter()
+                    .map(|argument| argument.as_str().map(str::to_owned))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error("\"arguments\" must only contain strings"))?,
+                (None, Some(command)) => shlex::split(command)
+                    .ok_or_else(|| error("\"command\" is not a valid shell command"))?,
+                _ => return Err(error("missing \"arguments\" or \"command\" in entry")),
+            };
+
+            commands.push(CompileCommand {
+                directory,
+                file,
+                arguments,
+            });
+        }
+
+        Ok(Self { commands })
+    }
+}
+
+/// The error returned when a [`CompilationDatabase`] cannot be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseCompilationDatabaseError {
+    message: String,
+}
+
+impl fmt::Display for ParseCompilationDatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid compilation database: {}", self.message)
+    }
+}
+
+impl error::Error for ParseCompilationDatabaseError {}
+
+/// The command used to compile a single translation unit inside a [`CompilationDatabase`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileCommand {
+    directory: PathBuf,
+    file: PathBuf,
+    arguments: Vec<String>,
+}
+
+impl CompileCommand {
+    /// The working directory of the command.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The path of the translation unit, relative to the [`directory`](Self::directory) if it
+    /// was relative in the database.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// The arguments of the command, starting with the name of the compiler.
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// Parse the arguments of the command after the name of the compiler. Relative paths are
+    /// relative to the [`directory`](Self::directory).
+    pub fn command_line(&self) -> CommandLine {
+        let arguments = self.arguments.iter().skip(1).map(String::as_str);
+        CommandLine::parse(arguments, &self.directory)
+    }
+
+    /// Create a [`Preprocessor`] configured with the options of the command that affect
+    /// preprocessing, as [`CommandLine`] parses them. Any other option is ignored.
+    pub fn preprocessor(&self) -> Preprocessor {
+        self.command_line().into_preprocessor()
+    }
+
+    /// Preprocess the translation unit using the [`preprocessor`](Self::preprocessor) of the
+    /// command.
+    pub fn preprocess(&self) -> Result<Preprocessed, Error> {
+        self.preprocessor().preprocess_file(&self.file)
+    }
+}
@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use super::CompilationDatabase;
+
+#[test]
+fn parse() {
+    let database: CompilationDatabase = r#"[
+        {
+            "directory": "/project",
+            "command": "cc -DNAME=\"a b\" -D FLAG -UFLAG -I include -isystem=/usr/include -c main.c",
+            "file": "main.c"
+        },
+        {
+            "directory": "/project/lib",
+            "arguments": ["cc", "-iquote", "quote", "--sysroot=/sdk", "/abs/lib.c"],
+            "file": "/abs/lib.c"
+        }
+    ]"#
+    .parse()
+    .unwrap();
+
+    let commands = database.commands();
+    assert_eq!(commands.len(), 2);
+    assert_eq!(commands[0].file(), Path::new("/project/main.c"));
+    assert_eq!(
+        commands[0].arguments(),
+        [
+            "cc",
+            "-DNAME=a b",
+            "-D",
+            "FLAG",
+            "-UFLAG",
+            "-I",
+            "include",
+            "-isystem=/usr/include",
+            "-c",
+            "main.c"
+        ]
+    );
+    assert_eq!(commands[1].directory(), Path::new("/project/lib"));
+    assert_eq!(commands[1].file(), Path::new("/abs/lib.c"));
+
+    assert!("{}".parse::<CompilationDatabase>().is_err());
+    assert!(r#"[{"directory": "/", "file": "a.c"}]"#.parse::<CompilationDatabase>().is_err());
+}
+
+#[test]
+fn preprocess() {
+    let dir = std::env::temp_dir()
+        .join("beheader-tests")
+        .join("compile_commands");
+    std::fs::create_dir_all(dir.join("include")).unwrap();
+    std::fs::write(dir.join("main.c"), "#include <config.h>\nVALUE NAME FLAG\n").unwrap();
+    std::fs::write(dir.join("include/config.h"), "#define VALUE 1\n").unwrap();
+
+    let json = format!(
+        r#"[{{"directory": {:?}, "command": "cc -DNAME=\"a b\" -D FLAG -UFLAG -Iinclude main.c", "file": "main.c"}}]"#,
+        dir.to_str().unwrap()
+    );
+    let database: CompilationDatabase = json.parse().unwrap();
+    let preprocessed = database.commands()[0].preprocess().unwrap();
+
+    assert_eq!(preprocessed.get_macro("NAME").unwrap().replacement(), "a b");
+    assert!(preprocessed.get_macro("FLAG").is_none());
+    assert!(preprocessed.get_macro("VALUE").is_some());
+}
@@ -0,0 +1,253 @@
+//! The place where the severities of the diagnostics reported are decided.
+use std::{collections::HashMap, ops::Deref};
+
+use super::{Diagnostic, DiagnosticCode, DiagnosticGroup, Level, Severity, Warning, WarningGroup};
+
+/// The severities chosen by the user for the diagnostics that do not stop the preprocessing,
+/// which override the ones they are reported with.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SeverityMap {
+    codes: HashMap<DiagnosticCode, Level>,
+    groups: HashMap<DiagnosticGroup, Level>,
+    /// The warnings that were configured by name, which are used for their codes without a
+    /// level.
+    warnings: HashMap<Warning, WarningState>,
+    /// Whether warnings are reported as errors, after applying the levels of the codes and
+    /// groups.
+    warnings_as_errors: bool,
+}
+
+/// How a [`Warning`] was configured.
+#[derive(Clone, Copy, Debug, Default)]
+struct WarningState {
+    /// Whether the warning is reported, if it was enabled or disabled.
+    enabled: Option<bool>,
+    /// Whether the warning is reported as an error, overriding
+    /// [`SeverityMap::warnings_as_errors`].
+    error: Option<bool>,
+}
+
+impl SeverityMap {
+    /// Set what to do with the diagnostics with the code `code`, which takes precedence over the
+    /// level of its group.
+    pub(crate) fn set_code(&mut self, code: DiagnosticCode, level: Level) {
+        self.codes.insert(code, level);
+    }
+
+    /// Set what to do with the diagnostics whose code is in `group`.
+    pub(crate) fn set_group(&mut self, group: DiagnosticGroup, level: Level) {
+        self.groups.insert(group, level);
+    }
+
+    /// Set whether warnings are reported as errors.
+    pub(crate) fn set_warnings_as_errors(&mut self, enabled: bool) {
+        self.warnings_as_errors = enabled;
+    }
+
+    /// Set whether `warning` is reported.
+    pub(crate) fn set_warning(&mut self, warning: Warning, enabled: bool) {
+        self.warnings.entry(warning).or_default().enabled = Some(enabled);
+    }
+
+    /// Set whether `warning` is reported as an error, regardless of
+    /// [`set_warnings_as_errors`](Self::set_warnings_as_errors).
+    fn set_warning_error(&mut self, warning: Warning, error: bool) {
+        self.warnings.entry(warning).or_default().error = Some(error);
+    }
+
+    /// Apply the warning option `option` of GCC and Clang, like `-Wall`, `-Wno-undef`,
+    /// `-Werror` or `-Werror=comment`. Return `false` if the option is not supported.
+    pub(crate) fn apply_option(&mut self, option: &str) -> bool {
+        let option = match option.strip_prefix("-W") {
+            Some(option) => option,
+            None => return false,
+        };
+        match option {
+            "error" => self.warnings_as_errors = true,
+            "no-error" => self.warnings_as_errors = false,
+            _ => {
+                let (name, enabled, error) = if let Some(name) = option.strip_prefix("error=") {
+                    (name, Some(true), Some(true))
+                } else if let Some(name) = option.strip_prefix("no-error=") {
+                    (name, None, Some(false))
+                } else if let Some(name) = option.strip_prefix("no-") {
+                    (name, Some(false), None)
+                } else {
+                    (option, Some(true), None)
+                };
+                let warnings = match named_warnings(name) {
+                    Some(warnings) => warnings,
+                    None => return false,
+                };
+                for warning in warnings {
+                    if let Some(enabled) = enabled {
+                        self.set_warning(warning, enabled);
+                    }
+                    if let Some(error) = error {
+                        self.set_warning_error(warning, error);
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Apply the `#pragma GCC diagnostic` directive whose kind is `kind` to the warning option
+    /// `option`: `warning` and `error` enable the warnings of the option as warnings or errors
+    /// respectively and `ignored` disables them. Return `false` if the directive or the option
+    /// are not supported.
+    pub(crate) fn apply_pragma(&mut self, kind: &str, option: &str) -> bool {
+        let name = match option.strip_prefix("-W") {
+            Some(name) => name,
+            None => return false,
+        };
+        let warnings = match named_warnings(name) {
+            Some(warnings) => warnings,
+            None => return false,
+        };
+        let (enabled, error) = match kind {
+            "warning" => (true, false),
+            "error" => (true, true),
+            "ignored" => (false, false),
+            _ => return false,
+        };
+        for warning in warnings {
+            self.set_warning(warning, enabled);
+            self.set_warning_error(warning, error);
+        }
+        true
+    }
+
+    /// The severity to report a diagnostic with the code `code` and the severity `severity`
+    /// with. Return `None` if it must not be reported.
+    fn severity(&self, code: DiagnosticCode, severity: Severity) -> Option<Severity> {
+        if code.is_fatal() {
+            return Some(Severity::Error);
+        }
+        let level = self
+            .codes
+            .get(&code)
+            .or_else(|| self.groups.get(&code.group()));
+        let mut warnings_as_errors = self.warnings_as_errors;
+        let severity = match level {
+            Some(Level::Allow) => return None,
+            Some(Level::Note) => Severity::Note,
+            Some(Level::Warn) => Severity::Warning,
+            Some(Level::Error) => Severity::Error,
+            None => match Warning::from_code(code) {
+                Some(warning) => {
+                    let state = self.warnings.get(&warning).copied().unwrap_or_default();
+                    if !state.enabled.unwrap_or(warning.is_enabled_by_default()) {
+                        return None;
+                    }
+                    warnings_as_errors = state.error.unwrap_or(warnings_as_errors);
+                    Severity::Warning
+                }
+                None => severity,
+            },
+        };
+        if warnings_as_errors && severity == Severity::Warning {
+            return Some(Severity::Error);
+        }
+        Some(severity)
+    }
+
+    /// Check if the diagnostics with the code `code` are reported, so they are not created if
+    /// they would be ignored.
+    pub(crate) fn is_reported(&self, code: DiagnosticCode) -> bool {
+        self.severity(code, Severity::Warning).is_some()
+    }
+
+    /// Change the severity of `diagnostic` to the one chosen by the user. Return `None` if it
+    /// must not be reported.
+    pub(crate) fn apply(&self, mut diagnostic: Diagnostic) -> Option<Diagnostic> {
+        diagnostic.severity = self.severity(diagnostic.code, diagnostic.severity)?;
+        Some(diagnostic)
+    }
+}
+
+/// Get the warnings of the group named `name` or the warning named `name`.
+fn named_warnings(name: &str) -> Option<Vec<Warning>> {
+    match WarningGroup::from_name(name) {
+        Some(group) => Some(group.warnings().to_vec()),
+        None => Warning::from_name(name).map(|warning| vec![warning]),
+    }
+}
+
+/// The diagnostics reported so far, with the severities chosen by the user.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Diagnostics {
+    severities: SeverityMap,
+    /// The severities saved by `#pragma GCC diagnostic push` directives.
+    saved: Vec<SeverityMap>,
+    reported: Vec<Diagnostic>,
+    /// The number of diagnostics reported as errors.
+    errors: usize,
+}
+
+impl Diagnostics {
+    /// Create an empty list of diagnostics whose severities are changed using `severities`.
+    pub(crate) fn new(severities: SeverityMap) -> Self {
+        Self {
+            severities,
+            saved: Vec::new(),
+            reported: Vec::new(),
+            errors: 0,
+        }
+    }
+
+    /// The severities chosen by the user.
+    pub(crate) fn severities(&self) -> &SeverityMap {
+        &self.severities
+    }
+
+    /// Change the severities chosen by the user from now on.
+    pub(crate) fn severities_mut(&mut self) -> &mut SeverityMap {
+        &mut self.severities
+    }
+
+    /// Save the current severities so they can be restored by [`restore`](Self::restore).
+    pub(crate) fn save(&mut self) {
+        self.saved.push(self.severities.clone());
+    }
+
+    /// Restore the severities saved last. Nothing happens if there are none, as GCC does.
+    pub(crate) fn restore(&mut self) {
+        if let Some(severities) = self.saved.pop() {
+            self.severities = severities;
+        }
+    }
+
+    /// Check if the diagnostics with the code `code` are reported.
+    pub(crate) fn is_reported(&self, code: DiagnosticCode) -> bool {
+        self.severities.is_reported(code)
+    }
+
+    /// Replace each diagnostic reported after the first `start` ones with the result of calling
+    /// `f` with it.
+    pub(crate) fn update_since(&mut self, start: usize, f: impl FnMut(Diagnostic) -> Diagnostic) {
+        let updated = self.reported.split_off(start);
+        self.reported.extend(updated.into_iter().map(f));
+    }
+
+    /// The number of errors reported so far.
+    pub(crate) fn error_count(&self) -> usize {
+        self.errors
+    }
+
+    /// Report `diagnostic`, unless the user chose to ignore it.
+    pub(crate) fn push(&mut self, diagnostic: Diagnostic) {
+        if let Some(diagnostic) = self.severities.apply(diagnostic) {
+            self.errors += usize::from(diagnostic.severity == Severity::Error);
+            self.reported.push(diagnostic);
+        }
+    }
+}
+
+impl Deref for Diagnostics {
+    type Target = [Diagnostic];
+
+    fn deref(&self) -> &Self::Target {
+        &self.reported
+    }
+}
@@ -0,0 +1,652 @@
+//! Diagnostics reported while preprocessing.
+mod engine;
+mod render;
+mod warning;
+
+use std::{fmt, path::PathBuf};
+
+use crate::span::{SourceMap, Span};
+
+pub(crate) use engine::{Diagnostics, SeverityMap};
+pub use render::DiagnosticRenderer;
+use render::Snippet;
+pub use warning::{Warning, WarningGroup};
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Information that does not point to a problem by itself.
+    Note,
+    /// Something suspicious that does not prevent preprocessing.
+    Warning,
+    /// A violation of the standard. Preprocessing continues but its result is not reliable.
+    Error,
+}
+
+impl Severity {
+    /// The name of the severity in the text of diagnostics.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Note => "note",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// What to do when finding a problem that can be configured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Level {
+    /// Ignore the problem.
+    Allow,
+    /// Report a note.
+    Note,
+    /// Report a warning.
+    Warn,
+    /// Report an error.
+    Error,
+}
+
+/// A stable identifier of the kind of problem a [`Diagnostic`] reports. Unlike the message, the
+/// code of a problem does not change between versions, so it can be used to filter diagnostics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum DiagnosticCode {
+    /// Source code that cannot be split into preprocessing tokens, like an unterminated comment.
+    InvalidToken,
+    /// A `/*` inside a block comment, which usually means the previous comment was not closed.
+    NestedComment,
+    /// A line comment continued in the next line by a line splice.
+    MultiLineComment,
+    /// A trigraph sequence, which is not replaced in the GNU dialects and since C23.
+    Trigraph,
+    /// A directive whose operand must be a macro name has something else.
+    InvalidMacroName,
+    /// The parameters of a function-like macro are not a list of identifiers separated by
+    /// commas.
+    InvalidParameterList,
+    /// A `##` operator at either end of a replacement list.
+    PasteAtEdge,
+    /// A `__VA_OPT__` in the replacement list of a variadic macro that is not followed by tokens
+    /// between parentheses, or that is inside another one.
+    InvalidVaOpt,
+    /// An invocation of a function-like macro without the closing parenthesis.
+    UnterminatedArguments,
+    /// An invocation of a function-like macro with the wrong number of arguments.
+    ArgumentCount,
+    /// A `##` operator whose result is not a valid preprocessing token.
+    InvalidPaste,
+    /// A macro defined in the main file that is never replaced nor checked.
+    UnusedMacro,
+    /// A `#define` or `#undef` directive for a macro predefined by the preprocessor.
+    BuiltinMacroRedefined,
+    /// A `#define` directive for a macro that was defined differently.
+    MacroRedefined,
+    /// A conditional inclusion directive without its `#endif` in the same file.
+    UnterminatedConditional,
+    /// An `#elif`, `#else` or `#endif` directive without its `#if`.
+    UnmatchedConditional,
+    /// An `#elif` or `#else` directive after the `#else` of the same conditional.
+    ElseAfterElse,
+    /// Tokens after an `#else` or `#endif` directive.
+    ExtraTokens,
+    /// A `defined` operator without a valid operand.
+    InvalidDefined,
+    /// A `defined` operator produced by macro replacement.
+    ExpansionToDefined,
+    /// A `__has_include` or `__has_include_next` operator without a valid operand.
+    InvalidHasInclude,
+    /// An `#include` directive whose file cannot be found.
+    IncludeNotFound,
+    /// An `#include` directive whose file cannot be read.
+    IncludeUnreadable,
+    /// An `#include` directive whose file was found with a different case.
+    IncludeCaseMismatch,
+    /// `#include` directives nested too deeply.
+    IncludeDepth,
+    /// An `#include` directive without a valid file name.
+    InvalidInclude,
+    /// An `#error` directive.
+    ErrorDirective,
+    /// A `#warning` directive.
+    WarningDirective,
+    /// A `#line` directive or a line marker without a valid line number or file name.
+    InvalidLineDirective,
+    /// A line starting with `#` whose name is not the name of a directive, like `#defien`. The
+    /// line is ignored.
+    InvalidDirective,
+    /// A `_Pragma` operator whose operand is not a string literal between parentheses. The
+    /// operator is kept as it is.
+    InvalidPragmaOperator,
+    /// A directive that is not available in the standard being followed, like `#elifdef` before
+    /// C23, or that is not supported, like `#embed`. The directive is ignored.
+    UnsupportedDirective,
+    /// A controlling expression that cannot be parsed.
+    InvalidExpression,
+    /// A constant that is not valid inside a controlling expression.
+    InvalidConstant,
+    /// An integer constant that does not fit in its type.
+    ConstantTooLarge,
+    /// `true` or `false` used in a controlling expression before C23.
+    BoolKeyword,
+    /// An identifier that is not a macro evaluated as `0` in a controlling expression.
+    UndefinedIdentifier,
+    /// A shift by a negative amount or by at least the width of its type.
+    ShiftOutOfRange,
+    /// A division or remainder by zero.
+    DivisionByZero,
+    /// A signed operation whose result does not fit in its type.
+    IntegerOverflow,
+    /// Adjacent string literals with different encoding prefixes.
+    StringPrefixMismatch,
+    /// A preprocessing number that is not a valid constant.
+    InvalidNumber,
+    /// A preprocessing token that is not a valid token.
+    StrayToken,
+    /// A closing delimiter without its opening one.
+    UnmatchedDelimiter,
+    /// An opening delimiter without its closing one.
+    UnclosedDelimiter,
+    /// The maximum number of errors was reached.
+    TooManyErrors,
+    /// A run used more resources than the budget set using
+    /// [`Preprocessor::max_duration`](crate::Preprocessor::max_duration),
+    /// [`Preprocessor::max_source_bytes`](crate::Preprocessor::max_source_bytes) or
+    /// [`Preprocessor::max_tokens`](crate::Preprocessor::max_tokens).
+    BudgetExceeded,
+    /// The source code, including the tokens produced while preprocessing, does not fit in the
+    /// 4 GiB that can be stored.
+    SourceTooLarge,
+    /// The preprocessing was cancelled using the flag set with
+    /// [`Preprocessor::cancellation`](crate::Preprocessor::cancellation).
+    Cancelled,
+    /// An extension to ISO C, or a feature of a later version of the standard.
+    Extension,
+}
+
+impl DiagnosticCode {
+    /// The name of the code, which is written between brackets after the severity of a
+    /// diagnostic.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidToken => "invalid-token",
+            Self::NestedComment => "nested-comment",
+            Self::MultiLineComment => "multi-line-comment",
+            Self::Trigraph => "trigraph",
+            Self::InvalidMacroName => "invalid-macro-name",
+            Self::InvalidParameterList => "invalid-parameter-list",
+            Self::PasteAtEdge => "paste-at-edge",
+            Self::InvalidVaOpt => "invalid-va-opt",
+            Self::UnterminatedArguments => "unterminated-arguments",
+            Self::ArgumentCount => "argument-count",
+            Self::InvalidPaste => "invalid-paste",
+            Self::UnusedMacro => "unused-macro",
+            Self::BuiltinMacroRedefined => "builtin-macro-redefined",
+            Self::MacroRedefined => "macro-redefined",
+            Self::UnterminatedConditional => "unterminated-conditional",
+            Self::UnmatchedConditional => "unmatched-conditional",
+            Self::ElseAfterElse => "else-after-else",
+            Self::ExtraTokens => "extra-tokens",
+            Self::InvalidDefined => "invalid-defined",
+            Self::ExpansionToDefined => "expansion-to-defined",
+            Self::InvalidHasInclude => "invalid-has-include",
+            Self::IncludeNotFound => "include-not-found",
+            Self::IncludeUnreadable => "include-unreadable",
+            Self::IncludeCaseMismatch => "include-case-mismatch",
+            Self::IncludeDepth => "include-depth",
+            Self::InvalidInclude => "invalid-include",
+            Self::ErrorDirective => "error-directive",
+            Self::WarningDirective => "warning-directive",
+            Self::InvalidLineDirective => "invalid-line-directive",
+            Self::InvalidDirective => "invalid-directive",
+            Self::InvalidPragmaOperator => "invalid-pragma-operator",
+            Self::UnsupportedDirective => "unsupported-directive",
+            Self::InvalidExpression => "invalid-expression",
+            Self::InvalidConstant => "invalid-constant",
+            Self::ConstantTooLarge => "constant-too-large",
+            Self::BoolKeyword => "bool-keyword",
+            Self::UndefinedIdentifier => "undefined-identifier",
+            Self::ShiftOutOfRange => "shift-out-of-range",
+            Self::DivisionByZero => "division-by-zero",
+            Self::IntegerOverflow => "integer-overflow",
+            Self::StringPrefixMismatch => "string-prefix-mismatch",
+            Self::InvalidNumber => "invalid-number",
+            Self::StrayToken => "stray-token",
+            Self::UnmatchedDelimiter => "unmatched-delimiter",
+            Self::UnclosedDelimiter => "unclosed-delimiter",
+            Self::TooManyErrors => "too-many-errors",
+            Self::BudgetExceeded => "budget-exceeded",
+            Self::SourceTooLarge => "source-too-large",
+            Self::Cancelled => "cancelled",
+            Self::Extension => "extension",
+        }
+    }
+
+    /// The group the code belongs to.
+    pub fn group(self) -> DiagnosticGroup {
+        match self {
+            Self::InvalidToken | Self::NestedComment | Self::MultiLineComment | Self::Trigraph => {
+                DiagnosticGroup::Lexer
+            }
+            Self::InvalidMacroName
+            | Self::InvalidParameterList
+            | Self::PasteAtEdge
+            | Self::InvalidVaOpt
+            | Self::UnterminatedArguments
+            | Self::ArgumentCount
+            | Self::InvalidPaste
+            | Self::UnusedMacro
+            | Self::BuiltinMacroRedefined
+            | Self::MacroRedefined => DiagnosticGroup::Macros,
+            Self::UnterminatedConditional
+            | Self::UnmatchedConditional
+            | Self::ElseAfterElse
+            | Self::ExtraTokens
+            | Self::InvalidDefined
+            | Self::ExpansionToDefined
+            | Self::InvalidHasInclude => DiagnosticGroup::Conditionals,
+            Self::IncludeNotFound
+            | Self::IncludeUnreadable
+            | Self::IncludeCaseMismatch
+            | Self::IncludeDepth
+            | Self::InvalidInclude => DiagnosticGroup::Includes,
+            Self::ErrorDirective
+            | Self::WarningDirective
+            | Self::InvalidLineDirective
+            | Self::InvalidDirective
+            | Self::InvalidPragmaOperator
+            | Self::UnsupportedDirective => DiagnosticGroup::Directives,
+            Self::InvalidExpression
+            | Self::InvalidConstant
+            | Self::ConstantTooLarge
+            | Self::BoolKeyword
+            | Self::UndefinedIdentifier
+            | Self::ShiftOutOfRange
+            | Self::DivisionByZero
+            | Self::IntegerOverflow => DiagnosticGroup::Expressions,
+            Self::StringPrefixMismatch
+            | Self::InvalidNumber
+            | Self::StrayToken
+            | Self::UnmatchedDelimiter
+            | Self::UnclosedDelimiter => DiagnosticGroup::Tokens,
+            Self::TooManyErrors | Self::BudgetExceeded | Self::SourceTooLarge | Self::Cancelled => {
+                DiagnosticGroup::Limits
+            }
+            Self::Extension => DiagnosticGroup::Extensions,
+        }
+    }
+
+    /// Check if the problem stops the preprocessing immediately, as the output would not be
+    /// useful after it. These problems are always errors, whatever level is chosen for them, and
+    /// are shown as fatal errors. A main input file that cannot be read is also fatal, but it is
+    /// reported as [`Error::Io`](crate::Error::Io) instead.
+    ///
+    /// Other problems are reported as diagnostics and the preprocessing continues unless the
+    /// source code cannot be processed, until the limit set by
+    /// [`Preprocessor::max_errors`](crate::Preprocessor::max_errors) is reached.
+    pub fn is_fatal(self) -> bool {
+        matches!(
+            self,
+            Self::IncludeNotFound
+                | Self::IncludeUnreadable
+                | Self::IncludeDepth
+                | Self::TooManyErrors
+                | Self::BudgetExceeded
+                | Self::SourceTooLarge
+        )
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The groups of related [`DiagnosticCode`]s, whose severities can be changed together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum DiagnosticGroup {
+    /// Problems splitting the source code into preprocessing tokens.
+    Lexer,
+    /// Problems defining and replacing macros.
+    Macros,
+    /// Problems with conditional inclusion directives.
+    Conditionals,
+    /// Problems with `#include` directives.
+    Includes,
+    /// The diagnostics of `#error` and `#warning` directives and problems with `#line`
+    /// directives.
+    Directives,
+    /// Problems evaluating the controlling expressions of `#if` and `#elif` directives.
+    Expressions,
+    /// Problems converting the output into C tokens.
+    Tokens,
+    /// Limits of the preprocessor being exceeded.
+    Limits,
+    /// Uses of extensions to ISO C, which make the source code less portable.
+    Extensions,
+}
+
+/// A note, warning or error found while preprocessing.
+///
+/// Besides its message, a diagnostic can point to other regions of code related to the problem
+/// using [`Label`]s, carry notes with more details about it and offer [`Suggestion`]s to fix it.
+///
+/// ```
+/// use beheader::DiagnosticCode;
+///
+/// let preprocessed = beheader::preprocess(b"#if A\n#else\n#else\n#endif\n").unwrap();
+/// let diagnostic = &preprocessed.diagnostics()[0];
+/// assert_eq!(diagnostic.code(), DiagnosticCode::ElseAfterElse);
+/// assert_eq!(diagnostic.labels()[0].message(), "Previous #else is here");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    severity: Severity,
+    code: DiagnosticCode,
+    message: String,
+    /// The region of code the diagnostic refers to.
+    span: Span,
+    // The location, labels and notes are boxed to keep diagnostics small, as they are returned
+    // inside errors.
+    location: Box<Location>,
+    labels: Box<[Label]>,
+    notes: Box<[String]>,
+    suggestions: Box<[Suggestion]>,
+}
+
+/// Where the region of a [`Diagnostic`], a [`Label`] or a [`Suggestion`] is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Location {
+    file: Option<PathBuf>,
+    line: Option<usize>,
+    /// The column of the start of the region, if it is in a file.
+    column: Option<usize>,
+    /// The line of source code containing the start of the region, if it is stored.
+    snippet: Option<Snippet>,
+}
+
+impl Diagnostic {
+    /// How serious the diagnostic is.
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    /// The kind of problem.
+    pub fn code(&self) -> DiagnosticCode {
+        self.code
+    }
+
+    /// The description of the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The region of code where the problem was found.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The file where the problem was found, if it was found in a file.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.location.file.as_ref()
+    }
+
+    /// The line where the problem was found, starting from 1, if it was found in a file.
+    pub fn line(&self) -> Option<usize> {
+        self.location.line
+    }
+
+    /// The column where the problem was found, starting from 1, if it was found in a file. It is
+    /// counted in the unit chosen with
+    /// [`Preprocessor::column_unit`](crate::Preprocessor::column_unit), which is bytes by default.
+    pub fn column(&self) -> Option<usize> {
+        self.location.column
+    }
+
+    /// Other regions of code related to the problem.
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    /// More details about the problem.
+    pub fn notes(&self) -> &[String] {
+        &self.notes
+    }
+
+    /// Changes to the source code that fix the problem.
+    pub fn suggestions(&self) -> &[Suggestion] {
+        &self.suggestions
+    }
+
+    /// The name of the severity of the diagnostic in its text, which is "fatal error" for
+    /// [fatal](DiagnosticCode::is_fatal) problems.
+    fn severity_name(&self) -> &'static str {
+        match self.severity {
+            Severity::Error if self.code.is_fatal() => "fatal error",
+            severity => severity.as_str(),
+        }
+    }
+
+    /// Add `label` to the diagnostic.
+    pub(crate) fn with_label(mut self, label: Label) -> Self {
+        let mut labels = Vec::from(self.labels);
+        labels.push(label);
+        self.labels = labels.into();
+        self
+    }
+
+    /// Add the note `note` to the diagnostic.
+    pub(crate) fn with_note(mut self, note: impl Into<String>) -> Self {
+        let mut notes = Vec::from(self.notes);
+        notes.push(note.into());
+        self.notes = notes.into();
+        self
+    }
+
+    /// Add `suggestion` to the diagnostic.
+    pub(crate) fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        let mut suggestions = Vec::from(self.suggestions);
+        suggestions.push(suggestion);
+        self.suggestions = suggestions.into();
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_location(f, &self.location)?;
+        let severity = self.severity_name();
+        write!(f, "{}[{}]: {}", severity, self.code, self.message)?;
+        for label in &self.labels {
+            writeln!(f)?;
+            write_location(f, &label.location)?;
+            write!(f, "note: {}", label.message)?;
+        }
+        for note in &self.notes {
+            write!(f, "\nnote: {}", note)?;
+        }
+        for suggestion in &self.suggestions {
+            writeln!(f)?;
+            write_location(f, &suggestion.location)?;
+            write!(f, "help: {}", suggestion.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the file and line of `location` followed by a space, if it is in a file.
+fn write_location(f: &mut fmt::Formatter<'_>, location: &Location) -> fmt::Result {
+    if let Some(file) = &location.file {
+        write!(f, "{}:", file.display())?;
+        if let Some(line) = location.line {
+            write!(f, "{}:", line)?;
+        }
+        write!(f, " ")?;
+    }
+    Ok(())
+}
+
+/// A region of code related to the problem reported by a [`Diagnostic`], other than the one
+/// where it was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    message: String,
+    span: Span,
+    location: Box<Location>,
+}
+
+impl Label {
+    /// How the region is related to the problem.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The region of code.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The file of the region, if it is in a file.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.location.file.as_ref()
+    }
+
+    /// The line of the region, starting from 1, if it is in a file.
+    pub fn line(&self) -> Option<usize> {
+        self.location.line
+    }
+
+    /// The column of the region, starting from 1, if it is in a file. It is counted like
+    /// [`Diagnostic::column`].
+    pub fn column(&self) -> Option<usize> {
+        self.location.column
+    }
+}
+
+/// A change to the source code that fixes the problem reported by a [`Diagnostic`], which
+/// replaces the contents of a region with a new text. Tools like editors can apply it without
+/// asking the user for anything else.
+///
+/// ```
+/// use beheader::{DiagnosticCode, MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#if 1\nint a;\n");
+/// let preprocessed = Preprocessor::new()
+///     .file_system(files)
+///     .preprocess_file(&"main.c")
+///     .unwrap();
+/// let diagnostic = &preprocessed.diagnostics()[0];
+/// assert_eq!(diagnostic.code(), DiagnosticCode::UnterminatedConditional);
+/// let suggestion = &diagnostic.suggestions()[0];
+/// assert_eq!((suggestion.line(), suggestion.column()), (Some(2), Some(7)));
+/// assert_eq!(suggestion.replacement(), "\n#endif\n");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    message: String,
+    span: Span,
+    replacement: String,
+    location: Box<Location>,
+}
+
+impl Suggestion {
+    /// The description of the change.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The region of code replaced, which can be empty to insert text.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The text that replaces the region.
+    pub fn replacement(&self) -> &str {
+        &self.replacement
+    }
+
+    /// The file of the region, if it is in a file.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.location.file.as_ref()
+    }
+
+    /// The line of the region, starting from 1, if it is in a file.
+    pub fn line(&self) -> Option<usize> {
+        self.location.line
+    }
+
+    /// The column of the region, starting from 1, if it is in a file. It is counted like
+    /// [`Diagnostic::column`].
+    pub fn column(&self) -> Option<usize> {
+        self.location.column
+    }
+}
+
+impl SourceMap {
+    /// Create a diagnostic with the code `code` about the region `span`.
+    pub(crate) fn diagnostic(
+        &self,
+        severity: Severity,
+        code: DiagnosticCode,
+        span: Span,
+        message: impl Into<String>,
+    ) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            span,
+            location: Box::new(self.location(span)),
+            labels: Box::default(),
+            notes: Box::default(),
+            suggestions: Box::default(),
+        }
+    }
+
+    /// Create a label about the region `span`.
+    pub(crate) fn label(&self, span: Span, message: impl Into<String>) -> Label {
+        Label {
+            message: message.into(),
+            span,
+            location: Box::new(self.location(span)),
+        }
+    }
+
+    /// Create a suggestion to replace the region `span` with `replacement`.
+    pub(crate) fn suggestion(
+        &self,
+        span: Span,
+        message: impl Into<String>,
+        replacement: impl Into<String>,
+    ) -> Suggestion {
+        Suggestion {
+            message: message.into(),
+            span,
+            replacement: replacement.into(),
+            location: Box::new(self.location(span)),
+        }
+    }
+
+    /// Find where the region `span` is.
+    fn location(&self, span: Span) -> Location {
+        // The file and line are the ones given by the `#line` directives before the region.
+        let (file, line) = match self.find_presumed(span) {
+            Some((file, line)) => (Some(self.display_path(&file).into_owned()), Some(line)),
+            None => (None, None),
+        };
+        let snippet = Snippet::find(self, span);
+        let column = snippet
+            .as_ref()
+            .filter(|_| file.is_some())
+            .map(|snippet| snippet.column(self.column_unit()));
+        Location {
+            file,
+            line,
+            column,
+            snippet,
+        }
+    }
+}
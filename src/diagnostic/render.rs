@@ -0,0 +1,271 @@
+//! Rendering of diagnostics as text with the source code they refer to.
+use std::fmt::Write;
+
+use super::{Diagnostic, Location, Severity};
+use crate::span::{ColumnUnit, SourceMap, Span};
+
+/// The number of columns a tab is shown as.
+const TAB_WIDTH: usize = 4;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BOLD_GREEN: &str = "\x1b[1;32m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+
+/// The line of source code containing the start of a region, stored when the diagnostic is
+/// created so it can be shown even if the preprocessing stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Snippet {
+    /// The column of the start of the region, starting from 1 and counted in bytes.
+    column: usize,
+    /// The length in bytes of the part of the region inside the line, which is at least 1.
+    len: usize,
+    /// The contents of the line, without its line terminator.
+    text: Box<[u8]>,
+}
+
+impl Snippet {
+    /// Find the line containing the start of the region `span` in `map`.
+    pub(super) fn find(map: &SourceMap, span: Span) -> Option<Self> {
+        let line = map.find_source_line(span)?;
+        let mut text = map.get_bytes(line).to_vec();
+        if text.last() == Some(&b'\r') {
+            text.pop();
+        }
+        let start = span.lo() - line.lo();
+        let len = span
+            .hi()
+            .min(line.lo() + text.len())
+            .saturating_sub(span.lo());
+        Some(Self {
+            column: start + 1,
+            len: len.max(1),
+            text: text.into(),
+        })
+    }
+
+    /// The column of the start of the region, counted in `unit`.
+    pub(super) fn column(&self, unit: ColumnUnit) -> usize {
+        unit.column(&self.text[..(self.column - 1).min(self.text.len())])
+    }
+
+    /// The text of the line as shown, followed by the number of columns before the region and the
+    /// number of columns of the region.
+    fn layout(&self) -> (String, usize, usize) {
+        let start = (self.column - 1).min(self.text.len());
+        let end = (start + self.len).min(self.text.len());
+        let text = expand_tabs(&String::from_utf8_lossy(&self.text));
+        let before = width(&String::from_utf8_lossy(&self.text[..start]));
+        let region = width(&String::from_utf8_lossy(&self.text[start..end]));
+        (text, before, region.max(1))
+    }
+}
+
+/// Replace the tabs of `text` by spaces.
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+/// The number of columns `text` is shown in.
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|ch| if ch == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Formats [`Diagnostic`]s for people to read, in a similar way to `rustc`: a header with the
+/// severity, code and message, the file, line and column of the problem, and the lines of source
+/// code involved with the regions of the diagnostic and its labels underlined.
+///
+/// ```
+/// use beheader::{DiagnosticRenderer, MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#if 1\n#else\n#else\n#endif\n");
+/// let preprocessed = Preprocessor::new()
+///     .file_system(files)
+///     .preprocess_file(&"main.c")
+///     .unwrap();
+/// let text = DiagnosticRenderer::new().render(&preprocessed.diagnostics()[0]);
+/// assert_eq!(
+///     text.lines().collect::<Vec<_>>(),
+///     [
+///         "error[else-after-else]: #else after #else",
+///         " --> main.c:3:1",
+///         "  |",
+///         "3 | #else",
+///         "  | ^^^^^",
+///         "2 | #else",
+///         "  |  ---- Previous #else is here",
+///     ]
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DiagnosticRenderer {
+    colors: bool,
+}
+
+impl DiagnosticRenderer {
+    /// Create a new configuration with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use ANSI escape codes to color the text, which is disabled by default.
+    pub fn colors(&mut self, enabled: bool) -> &mut Self {
+        self.colors = enabled;
+        self
+    }
+
+    /// Produce the text of `diagnostic`, ending with a newline.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let mut text = String::new();
+        let style = match diagnostic.severity {
+            Severity::Note => BOLD_GREEN,
+            Severity::Warning => BOLD_YELLOW,
+            Severity::Error => BOLD_RED,
+        };
+
+        let severity = format!("{}[{}]", diagnostic.severity_name(), diagnostic.code);
+        let message = format!(": {}", diagnostic.message);
+        writeln!(
+            text,
+            "{}{}",
+            self.paint(style, &severity),
+            self.paint(BOLD, &message)
+        )
+        .unwrap();
+
+        let locations = std::iter::once(&*diagnostic.location)
+            .chain(diagnostic.labels.iter().map(|label| &*label.location));
+        let digits = locations
+            .filter_map(|location| location.line)
+            .max()
+            .map_or(1, |line| line.to_string().len());
+        let pad = " ".repeat(digits);
+
+        let primary = &diagnostic.location;
+        if let Some(position) = position(primary) {
+            writeln!(text, "{}{} {}", pad, self.paint(BOLD_BLUE, "-->"), position).unwrap();
+        }
+        if let Some(snippet) = &primary.snippet {
+            writeln!(text, "{} {}", pad, self.paint(BOLD_BLUE, "|")).unwrap();
+            self.snippet(&mut text, &pad, primary, snippet, ('^', style), "");
+        }
+
+        for label in diagnostic.labels.iter() {
+            let location = &label.location;
+            match &location.snippet {
+                Some(snippet) => {
+                    if location.file != primary.file {
+                        if let Some(position) = position(location) {
+                            let arrow = self.paint(BOLD_BLUE, ":::");
+                            writeln!(text, "{}{} {}", pad, arrow, position).unwrap();
+                        }
+                    }
+                    let marker = ('-', BOLD_BLUE);
+                    self.snippet(&mut text, &pad, location, snippet, marker, &label.message);
+                }
+                None => {
+                    let note = match position(location) {
+                        Some(position) => format!("{}: {}", position, label.message),
+                        None => label.message.clone(),
+                    };
+                    self.note(&mut text, &pad, &note);
+                }
+            }
+        }
+
+        for note in diagnostic.notes.iter() {
+            self.note(&mut text, &pad, note);
+        }
+        for suggestion in diagnostic.suggestions.iter() {
+            self.help(&mut text, &pad, &suggestion.message);
+        }
+        text
+    }
+
+    /// Write the line of `snippet` with its region underlined by `marker`, in its style, followed
+    /// by `message`.
+    fn snippet(
+        &self,
+        text: &mut String,
+        pad: &str,
+        location: &Location,
+        snippet: &Snippet,
+        (marker, style): (char, &str),
+        message: &str,
+    ) {
+        let (line, before, region) = snippet.layout();
+        let number = location
+            .line
+            .map_or_else(String::new, |line| line.to_string());
+        let gutter = format!("{:>width$} |", number, width = pad.len());
+        writeln!(text, "{} {}", self.paint(BOLD_BLUE, &gutter), line).unwrap();
+
+        let mut underline = marker.to_string().repeat(region);
+        if !message.is_empty() {
+            underline.push(' ');
+            underline.push_str(message);
+        }
+        writeln!(
+            text,
+            "{} {}{}",
+            self.paint(BOLD_BLUE, &format!("{} |", pad)),
+            " ".repeat(before),
+            self.paint(style, &underline)
+        )
+        .unwrap();
+    }
+
+    /// Write a line with the note `note`.
+    fn note(&self, text: &mut String, pad: &str, note: &str) {
+        let equals = self.paint(BOLD_BLUE, "=");
+        writeln!(
+            text,
+            "{} {} {}: {}",
+            pad,
+            equals,
+            self.paint(BOLD, "note"),
+            note
+        )
+        .unwrap();
+    }
+
+    /// Write a line with the help message `help`.
+    fn help(&self, text: &mut String, pad: &str, help: &str) {
+        let equals = self.paint(BOLD_BLUE, "=");
+        writeln!(
+            text,
+            "{} {} {}: {}",
+            pad,
+            equals,
+            self.paint(BOLD, "help"),
+            help
+        )
+        .unwrap();
+    }
+
+    /// Wrap `text` in the ANSI escape codes of `style` if colors are enabled.
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.colors {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    }
+}
+
+/// The file, line and column of `location` joined by colons, if it is in a file.
+fn position(location: &Location) -> Option<String> {
+    let mut position = location.file.as_ref()?.display().to_string();
+    if let Some(line) = location.line {
+        write!(position, ":{}", line).unwrap();
+        if let Some(column) = location.column {
+            write!(position, ":{}", column).unwrap();
+        }
+    }
+    Some(position)
+}
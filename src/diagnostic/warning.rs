@@ -0,0 +1,185 @@
+//! The warnings that can be enabled and disabled by name, like the `-W` options of GCC and Clang.
+use super::DiagnosticCode;
+
+/// A warning that can be enabled or disabled by name using
+/// [`Preprocessor::warning`](crate::Preprocessor::warning), the `-W` command-line options or the
+/// `#pragma GCC diagnostic` directives. Its names are the ones used by GCC and Clang when they
+/// have an equivalent warning.
+///
+/// ```
+/// use beheader::{DiagnosticCode, Warning};
+///
+/// let warning = Warning::from_name("undef").unwrap();
+/// assert_eq!(warning, Warning::Undef);
+/// assert_eq!(warning.codes(), [DiagnosticCode::UndefinedIdentifier]);
+/// assert!(!warning.is_enabled_by_default());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Warning {
+    /// `comment`: a `/*` inside a block comment or a line comment continued by a line splice.
+    Comment,
+    /// `trigraphs`: a trigraph sequence outside comments, which is not replaced in the GNU
+    /// dialects and since C23.
+    Trigraphs,
+    /// `unused-macros`: a macro defined in the main file that is never replaced nor checked with
+    /// `defined`, `#ifdef` or `#ifndef`.
+    UnusedMacros,
+    /// `undef`: an identifier that is not a macro evaluated inside a `#if` or `#elif` directive.
+    Undef,
+    /// `builtin-macro-redefined`: a `#define` or `#undef` directive for a predefined macro,
+    /// which is not allowed by section 6.10.8p2 of C17.
+    BuiltinMacroRedefined,
+    /// `macro-redefined`: a `#define` directive for a macro that is already defined with a
+    /// different definition, which is not allowed by section 6.10.3p2 of C17.
+    MacroRedefined,
+    /// `endif-labels`: tokens after an `#else` or `#endif` directive.
+    EndifLabels,
+    /// `expansion-to-defined`: a macro replacement inside a `#if` or `#elif` directive that
+    /// produces the `defined` operator.
+    ExpansionToDefined,
+    /// `bool-keywords`: `true` or `false` inside a `#if` or `#elif` directive when they are not
+    /// keywords.
+    BoolKeywords,
+    /// `nonportable-include-path`: a file included using a name with a different case.
+    NonportableIncludePath,
+    /// `cpp`: a `#warning` directive.
+    Cpp,
+    /// `pedantic`: an extension to ISO C accepted by the preprocessor, like `#include_next`,
+    /// binary constants before C23 or an object-like macro without white-space after its name.
+    Pedantic,
+}
+
+impl Warning {
+    /// Every warning, in the order of their declaration.
+    pub const ALL: &'static [Self] = &[
+        Self::Comment,
+        Self::Trigraphs,
+        Self::UnusedMacros,
+        Self::Undef,
+        Self::BuiltinMacroRedefined,
+        Self::MacroRedefined,
+        Self::EndifLabels,
+        Self::ExpansionToDefined,
+        Self::BoolKeywords,
+        Self::NonportableIncludePath,
+        Self::Cpp,
+        Self::Pedantic,
+    ];
+
+    /// The name of the warning, as used in `-W` options.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Comment => "comment",
+            Self::Trigraphs => "trigraphs",
+            Self::UnusedMacros => "unused-macros",
+            Self::Undef => "undef",
+            Self::BuiltinMacroRedefined => "builtin-macro-redefined",
+            Self::MacroRedefined => "macro-redefined",
+            Self::EndifLabels => "endif-labels",
+            Self::ExpansionToDefined => "expansion-to-defined",
+            Self::BoolKeywords => "bool-keywords",
+            Self::NonportableIncludePath => "nonportable-include-path",
+            Self::Cpp => "cpp",
+            Self::Pedantic => "pedantic",
+        }
+    }
+
+    /// Get the warning named `name`. The name `comments` is accepted for
+    /// [`Comment`](Self::Comment), as GCC does.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "comments" => Some(Self::Comment),
+            _ => Self::ALL
+                .iter()
+                .copied()
+                .find(|warning| warning.name() == name),
+        }
+    }
+
+    /// The codes of the diagnostics reported by the warning.
+    pub fn codes(self) -> &'static [DiagnosticCode] {
+        match self {
+            Self::Comment => &[
+                DiagnosticCode::NestedComment,
+                DiagnosticCode::MultiLineComment,
+            ],
+            Self::Trigraphs => &[DiagnosticCode::Trigraph],
+            Self::UnusedMacros => &[DiagnosticCode::UnusedMacro],
+            Self::Undef => &[DiagnosticCode::UndefinedIdentifier],
+            Self::BuiltinMacroRedefined => &[DiagnosticCode::BuiltinMacroRedefined],
+            Self::MacroRedefined => &[DiagnosticCode::MacroRedefined],
+            Self::EndifLabels => &[DiagnosticCode::ExtraTokens],
+            Self::ExpansionToDefined => &[DiagnosticCode::ExpansionToDefined],
+            Self::BoolKeywords => &[DiagnosticCode::BoolKeyword],
+            Self::NonportableIncludePath => &[DiagnosticCode::IncludeCaseMismatch],
+            Self::Cpp => &[DiagnosticCode::WarningDirective],
+            Self::Pedantic => &[DiagnosticCode::Extension],
+        }
+    }
+
+    /// Get the warning that reports the diagnostics with the code `code`, if any.
+    pub fn from_code(code: DiagnosticCode) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|warning| warning.codes().contains(&code))
+    }
+
+    /// Whether the warning is reported unless it is disabled.
+    pub fn is_enabled_by_default(self) -> bool {
+        match self {
+            Self::Comment
+            | Self::Trigraphs
+            | Self::UnusedMacros
+            | Self::Undef
+            | Self::BoolKeywords
+            | Self::Pedantic => false,
+            Self::BuiltinMacroRedefined
+            | Self::MacroRedefined
+            | Self::EndifLabels
+            | Self::ExpansionToDefined
+            | Self::NonportableIncludePath
+            | Self::Cpp => true,
+        }
+    }
+}
+
+/// A set of [`Warning`]s that can be enabled or disabled together, like the `-Wall` and
+/// `-Wextra` options of GCC and Clang.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum WarningGroup {
+    /// `all`: [`Comment`](Warning::Comment) and [`Trigraphs`](Warning::Trigraphs).
+    All,
+    /// `extra`: [`ExpansionToDefined`](Warning::ExpansionToDefined) and
+    /// [`BoolKeywords`](Warning::BoolKeywords).
+    Extra,
+}
+
+impl WarningGroup {
+    /// The name of the group, as used in `-W` options.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::Extra => "extra",
+        }
+    }
+
+    /// Get the group named `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "all" => Some(Self::All),
+            "extra" => Some(Self::Extra),
+            _ => None,
+        }
+    }
+
+    /// The warnings of the group.
+    pub fn warnings(self) -> &'static [Warning] {
+        match self {
+            Self::All => &[Warning::Comment, Warning::Trigraphs],
+            Self::Extra => &[Warning::ExpansionToDefined, Warning::BoolKeywords],
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! The errors that stop the preprocessing.
+use std::{error, fmt, io};
+
+use crate::diagnostic::Diagnostic;
+
+/// An error that stopped the preprocessing, either because a file could not be read or because
+/// the source code could not be processed.
+///
+/// Errors in the source code that do not stop the preprocessing are reported as diagnostics of
+/// the result instead, only the [fatal](crate::DiagnosticCode::is_fatal) ones are returned as
+/// errors, together with the diagnostics reported before them.
+///
+/// ```
+/// let error = beheader::preprocess(b"#if 1/0\n#endif\n#include \"missing.h\"\n")
+///     .err()
+///     .unwrap();
+/// let diagnostic = error.diagnostic().unwrap();
+/// assert_eq!(diagnostic.message(), "Cannot find included file \"missing.h\"");
+/// assert_eq!(error.diagnostics()[0].message(), "Division by zero in preprocessor expression");
+/// ```
+#[derive(Debug)]
+pub enum Error {
+    /// A file given to the preprocessor could not be read.
+    Io(io::Error),
+    /// The source code is not valid and preprocessing cannot continue.
+    Source {
+        /// The diagnostic of the error, which always has [`Severity::Error`](crate::Severity::Error).
+        diagnostic: Diagnostic,
+        /// The diagnostics reported before the error, in the order they were reported.
+        reported: Vec<Diagnostic>,
+    },
+}
+
+impl Error {
+    /// The diagnostic describing the problem, if the source code is not valid.
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        match self {
+            Self::Io(_) => None,
+            Self::Source { diagnostic, .. } => Some(diagnostic),
+        }
+    }
+
+    /// The diagnostics reported before the error, which do not include the one returned by
+    /// [`diagnostic`](Self::diagnostic).
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            Self::Io(_) => &[],
+            Self::Source { reported, .. } => reported,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Source { diagnostic, .. } => write!(f, "{}", diagnostic),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            Self::Source { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<Diagnostic> for Error {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self::Source {
+            diagnostic,
+            reported: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,772 @@
+//! Evaluation of the controlling expressions of conditional inclusion directives as described in
+//! section 6.10.1 of C17.
+use crate::{
+    c_token::IntegerConstant,
+    diagnostic::{Diagnostic, DiagnosticCode, Diagnostics, Severity},
+    lexer::{Token, TokenKind},
+    span::{SourceMap, Span},
+};
+
+/// The value of an integer constant expression. All the signed integer types behave as if they
+/// had the same representation as `intmax_t` and all the unsigned integer types behave as if they
+/// had the same representation as `uintmax_t` (section 6.10.1p4 of C17).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    Signed(i64),
+    Unsigned(u64),
+}
+
+impl Value {
+    fn is_true(self) -> bool {
+        self != Value::Signed(0) && self != Value::Unsigned(0)
+    }
+
+    fn from_bool(value: bool) -> Self {
+        Value::Signed(value.into())
+    }
+
+    /// Truncate the value to its lowest `width` bits, extending the sign of signed values.
+    /// Return the truncated value and whether it is different from the original one.
+    fn truncate(self, width: u32) -> (Self, bool) {
+        let unused = 64 - width;
+        let truncated = match self {
+            Value::Signed(value) => Value::Signed((value << unused) >> unused),
+            Value::Unsigned(value) => Value::Unsigned((value << unused) >> unused),
+        };
+        (truncated, truncated != self)
+    }
+
+    /// Apply the usual arithmetic conversions (section 6.3.1.8 of C17) to a pair of values whose
+    /// types are `width` bits wide: if any of them is unsigned, both are converted to unsigned.
+    fn convert(lhs: Self, rhs: Self, width: u32) -> (Self, Self) {
+        let unsigned = |value| match value {
+            Value::Signed(value) => Value::Unsigned(value as u64).truncate(width).0,
+            value => value,
+        };
+        match (lhs, rhs) {
+            (Value::Signed(_), Value::Signed(_)) => (lhs, rhs),
+            _ => (unsigned(lhs), unsigned(rhs)),
+        }
+    }
+}
+
+/// The width of `intmax_t` and `uintmax_t`, the types used to evaluate the controlling
+/// expressions of conditional inclusion directives (section 6.10.1p4 of C17).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum IntmaxWidth {
+    /// 32 bits, like old compilers whose widest integer type was a 32-bit `long`.
+    Bits32,
+    /// 64 bits, like modern compilers.
+    #[default]
+    Bits64,
+}
+
+impl IntmaxWidth {
+    /// The number of bits of the width.
+    pub(crate) fn bits(self) -> u32 {
+        match self {
+            Self::Bits32 => 32,
+            Self::Bits64 => 64,
+        }
+    }
+}
+
+/// The configuration used to evaluate controlling expressions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Evaluation {
+    /// Whether `char` is a signed type. This changes the value of character constants whose
+    /// only character does not fit in 7 bits.
+    pub(crate) signed_char: bool,
+    /// Whether character constants with more than one character are accepted.
+    pub(crate) multi_char: bool,
+    /// Whether `true` and `false` are keywords that evaluate to `1` and `0`, as in C23.
+    pub(crate) bool_keywords: bool,
+    /// Whether binary integer constants are part of the standard, as in C23. They are accepted
+    /// anyway, but reported as extensions otherwise.
+    pub(crate) binary_constants: bool,
+    /// The width in bits of `intmax_t` and `uintmax_t`.
+    pub(crate) width: u32,
+}
+
+impl Default for Evaluation {
+    fn default() -> Self {
+        Self {
+            signed_char: true,
+            multi_char: true,
+            bool_keywords: false,
+            binary_constants: false,
+            width: 64,
+        }
+    }
+}
+
+/// The binary operators, in increasing order of precedence.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BinOp {
+    Comma,
+    Conditional,
+    LogicalOr,
+    LogicalAnd,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl BinOp {
+    /// The precedence of the operator. Operators with higher precedence bind tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Comma => 1,
+            BinOp::Conditional => 2,
+            BinOp::LogicalOr => 3,
+            BinOp::LogicalAnd => 4,
+            BinOp::BitOr => 5,
+            BinOp::BitXor => 6,
+            BinOp::BitAnd => 7,
+            BinOp::Eq | BinOp::Ne => 8,
+            BinOp::Lt | BinOp::Gt | BinOp::Le | BinOp::Ge => 9,
+            BinOp::Shl | BinOp::Shr => 10,
+            BinOp::Add | BinOp::Sub => 11,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 12,
+        }
+    }
+}
+
+impl SourceMap {
+    /// Evaluate the controlling expression of a `#if` or `#elif` directive. The `defined`
+    /// operators must have been evaluated and the macros must have been replaced already.
+    ///
+    /// Overflows, divisions by zero and shifts out of range are reported in `diagnostics`.
+    /// Return an error if the expression is not valid, about `span`, the region of the directive,
+    /// if it is not about a specific token.
+    pub(crate) fn evaluate(
+        &self,
+        span: Span,
+        tokens: &[Token],
+        options: &Evaluation,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<bool, Diagnostic> {
+        let tokens: Vec<Token> = tokens
+            .iter()
+            .copied()
+            .filter(|token| !matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+            .collect();
+
+        let mut parser = Parser {
+            map: self,
+            options,
+            tokens: &tokens,
+            pos: 0,
+            span,
+            diagnostics,
+        };
+
+        let operand = parser.expr(BinOp::Comma.precedence(), true)?;
+
+        if let Some(&token) = parser.tokens.get(parser.pos) {
+            return Err(parser.error(
+                DiagnosticCode::InvalidExpression,
+                token.span,
+                format!(
+                    "Unexpected \"{}\" in preprocessor expression",
+                    self.spelling(token)
+                ),
+            ));
+        }
+
+        Ok(operand.value.is_true())
+    }
+}
+
+/// The value of a subexpression and the region of code where it was found.
+#[derive(Clone, Copy)]
+struct Operand {
+    value: Value,
+    span: Span,
+}
+
+/// The region of code from the start of `first` to the end of `last`.
+fn join(first: Span, last: Span) -> Span {
+    // Both regions could come from different macro definitions.
+    if first.lo() <= last.hi() {
+        Span::new(first.lo(), last.hi())
+    } else {
+        last
+    }
+}
+
+/// A precedence climbing parser that evaluates the expression while parsing it.
+struct Parser<'a> {
+    map: &'a SourceMap,
+    options: &'a Evaluation,
+    tokens: &'a [Token],
+    pos: usize,
+    /// The region of the whole directive.
+    span: Span,
+    diagnostics: &'a mut Diagnostics,
+}
+
+impl Parser<'_> {
+    /// Return the next token without consuming it.
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    /// Consume the next token if it is the punctuator `punct`.
+    fn eat(&mut self, punct: &[u8]) -> Option<Token> {
+        let token = self
+            .peek()
+            .filter(|&token| self.map.is_punct(token, punct))?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Consume the next token, which must be the punctuator `punct` matching the punctuator
+    /// `opening`.
+    fn expect(&mut self, punct: &[u8], opening: Token) -> Result<Token, Diagnostic> {
+        match self.eat(punct) {
+            Some(token) => Ok(token),
+            None => {
+                let diagnostic = self.error(
+                    DiagnosticCode::InvalidExpression,
+                    self.peek().map_or(self.span, |token| token.span),
+                    format!(
+                        "Expected \"{}\" in preprocessor expression",
+                        String::from_utf8_lossy(punct)
+                    ),
+                );
+                let message = format!("To match this \"{}\"", self.map.spelling(opening));
+                Err(diagnostic.with_label(self.map.label(opening.span, message)))
+            }
+        }
+    }
+
+    /// Create the error that stops the evaluation of an expression that is not valid.
+    fn error(&self, code: DiagnosticCode, span: Span, message: String) -> Diagnostic {
+        self.map.diagnostic(Severity::Error, code, span, message)
+    }
+
+    /// Report a problem found while evaluating the expression.
+    fn report(&mut self, severity: Severity, code: DiagnosticCode, span: Span, message: &str) {
+        if !self.diagnostics.is_reported(code) {
+            return;
+        }
+        let diagnostic = self.map.diagnostic(severity, code, span, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Return the binary operator that is the next token, if any.
+    fn peek_binop(&self) -> Option<BinOp> {
+        let token = self.peek()?;
+        const OPERATORS: &[(&[u8], BinOp)] = &[
+            (b",", BinOp::Comma),
+            (b"?", BinOp::Conditional),
+            (b"||", BinOp::LogicalOr),
+            (b"&&", BinOp::LogicalAnd),
+            (b"|", BinOp::BitOr),
+            (b"^", BinOp::BitXor),
+            (b"&", BinOp::BitAnd),
+            (b"==", BinOp::Eq),
+            (b"!=", BinOp::Ne),
+            (b"<", BinOp::Lt),
+            (b">", BinOp::Gt),
+            (b"<=", BinOp::Le),
+            (b">=", BinOp::Ge),
+            (b"<<", BinOp::Shl),
+            (b">>", BinOp::Shr),
+            (b"+", BinOp::Add),
+            (b"-", BinOp::Sub),
+            (b"*", BinOp::Mul),
+            (b"/", BinOp::Div),
+            (b"%", BinOp::Rem),
+        ];
+        OPERATORS
+            .iter()
+            .find(|(punct, _)| self.map.is_punct(token, punct))
+            .map(|&(_, op)| op)
+    }
+
+    /// Parse and evaluate an expression whose binary operators have a precedence of at least
+    /// `min_precedence`. If `eval` is `false`, this is an unevaluated operand and no problems
+    /// caused by the values of the operands are reported.
+    fn expr(&mut self, min_precedence: u8, eval: bool) -> Result<Operand, Diagnostic> {
+        let mut lhs = self.unary(eval)?;
+
+        while let Some(op) = self.peek_binop() {
+            let precedence = op.precedence();
+            if precedence < min_precedence {
+                break;
+            }
+            self.pos += 1;
+
+            let (value, rhs) = match op {
+                BinOp::LogicalOr => {
+                    let rhs = self.expr(precedence + 1, eval && !lhs.value.is_true())?;
+                    (
+                        Value::from_bool(lhs.value.is_true() || rhs.value.is_true()),
+                        rhs,
+                    )
+                }
+                BinOp::LogicalAnd => {
+                    let rhs = self.expr(precedence + 1, eval && lhs.value.is_true())?;
+                    (
+                        Value::from_bool(lhs.value.is_true() && rhs.value.is_true()),
+                        rhs,
+                    )
+                }
+                BinOp::Conditional => {
+                    let question = self.tokens[self.pos - 1];
+                    let condition = lhs.value.is_true();
+                    let then = self.expr(BinOp::Comma.precedence(), eval && condition)?;
+                    self.expect(b":", question)?;
+                    // The conditional operator is right associative.
+                    let otherwise = self.expr(precedence, eval && !condition)?;
+                    // The result has the type of both operands after the usual arithmetic
+                    // conversions.
+                    let (then_value, otherwise_value) =
+                        Value::convert(then.value, otherwise.value, self.options.width);
+                    if condition {
+                        (then_value, otherwise)
+                    } else {
+                        (otherwise_value, otherwise)
+                    }
+                }
+                BinOp::Comma => {
+                    let rhs = self.expr(precedence + 1, eval)?;
+                    (rhs.value, rhs)
+                }
+                op => {
+                    let rhs = self.expr(precedence + 1, eval)?;
+                    (self.apply(op, lhs, rhs, eval), rhs)
+                }
+            };
+
+            lhs = Operand {
+                value,
+                span: join(lhs.span, rhs.span),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    /// Parse and evaluate a unary expression.
+    fn unary(&mut self, eval: bool) -> Result<Operand, Diagnostic> {
+        let op = ["+", "-", "~", "!"]
+            .into_iter()
+            .find_map(|op| Some((op, self.eat(op.as_bytes())?)));
+        let (op, token) = match op {
+            Some(op) => op,
+            None => return self.primary(eval),
+        };
+
+        let operand = self.unary(eval)?;
+        let span = join(token.span, operand.span);
+        let (value, overflow) = match (op, operand.value) {
+            ("+", value) => (value, false),
+            ("-", Value::Signed(value)) => {
+                let (result, overflow) = value.overflowing_neg();
+                (Value::Signed(result), overflow)
+            }
+            ("-", Value::Unsigned(value)) => (Value::Unsigned(value.wrapping_neg()), false),
+            ("~", Value::Signed(value)) => (Value::Signed(!value), false),
+            ("~", Value::Unsigned(value)) => (Value::Unsigned(!value), false),
+            (_, value) => (Value::from_bool(!value.is_true()), false),
+        };
+
+        Ok(Operand {
+            value: self.wrap(value, overflow, span, eval),
+            span,
+        })
+    }
+
+    /// Parse and evaluate a primary expression.
+    fn primary(&mut self, eval: bool) -> Result<Operand, Diagnostic> {
+        if let Some(open) = self.eat(b"(") {
+            let operand = self.expr(BinOp::Comma.precedence(), eval)?;
+            let close = self.expect(b")", open)?;
+            return Ok(Operand {
+                value: operand.value,
+                span: join(open.span, close.span),
+            });
+        }
+
+        let token = match self.peek() {
+            Some(token) => token,
+            None => {
+                let span = self.tokens.last().map_or(self.span, |token| token.span);
+                let message = "Missing operand in preprocessor expression".to_owned();
+                return Err(self.error(DiagnosticCode::InvalidExpression, span, message));
+            }
+        };
+        self.pos += 1;
+
+        let value = match token.kind {
+            TokenKind::Number => {
+                let spelling = self.map.spelling(token);
+                let value = match parse_integer(&spelling) {
+                    // Constants that do not fit in `intmax_t` can only be represented as
+                    // `uintmax_t`.
+                    Some(Value::Signed(value)) if value >> (self.options.width - 1) != 0 => {
+                        Value::Unsigned(value as u64)
+                    }
+                    Some(value) => value,
+                    None => {
+                        return Err(self.error(
+                            DiagnosticCode::InvalidConstant,
+                            token.span,
+                            format!(
+                                "Invalid integer constant \"{}\" in preprocessor expression",
+                                spelling
+                            ),
+                        ))
+                    }
+                };
+                if !self.options.binary_constants
+                    && (spelling.starts_with("0b") || spelling.starts_with("0B"))
+                {
+                    self.report(
+                        Severity::Warning,
+                        DiagnosticCode::Extension,
+                        token.span,
+                        "Binary integer constants are a C23 extension",
+                    );
+                }
+                let (value, truncated) = value.truncate(self.options.width);
+                if truncated {
+                    self.report(
+                        Severity::Warning,
+                        DiagnosticCode::ConstantTooLarge,
+                        token.span,
+                        "Integer constant is too large for its type",
+                    );
+                }
+                value
+            }
+            TokenKind::Char => {
+                parse_char(&self.map.spelling(token), self.options).map_err(|message| {
+                    self.error(DiagnosticCode::InvalidConstant, token.span, message)
+                })?
+            }
+            TokenKind::Ident => {
+                let spelling = self.map.spelling(token);
+                let boolean = matches!(spelling.as_str(), "true" | "false");
+                if boolean && self.options.bool_keywords {
+                    Value::from_bool(spelling == "true")
+                } else {
+                    if boolean && eval {
+                        self.report(
+                            Severity::Warning,
+                            DiagnosticCode::BoolKeyword,
+                            token.span,
+                            &format!(
+                                "\"{}\" is not a keyword before C23 and evaluates to 0",
+                                spelling
+                            ),
+                        );
+                    } else if eval {
+                        self.report(
+                            Severity::Warning,
+                            DiagnosticCode::UndefinedIdentifier,
+                            token.span,
+                            &format!("\"{}\" is not defined, evaluates to 0", spelling),
+                        );
+                    }
+                    // All the identifiers remaining after macro replacement are replaced by `0`.
+                    Value::Signed(0)
+                }
+            }
+            _ => {
+                return Err(self.error(
+                    DiagnosticCode::InvalidExpression,
+                    token.span,
+                    format!(
+                        "Unexpected \"{}\" in preprocessor expression",
+                        self.map.spelling(token)
+                    ),
+                ))
+            }
+        };
+
+        Ok(Operand {
+            value,
+            span: token.span,
+        })
+    }
+
+    /// Apply a binary operator that is not a logical, conditional or comma operator.
+    ///
+    /// Overflows, divisions by zero and shifts out of range are reported if `eval` is `true`.
+    fn apply(&mut self, op: BinOp, lhs: Operand, rhs: Operand, eval: bool) -> Value {
+        let span = join(lhs.span, rhs.span);
+
+        // The type of the result of a shift is the type of the left operand.
+        if let BinOp::Shl | BinOp::Shr = op {
+            let amount = match rhs.value {
+                Value::Signed(amount) => u32::try_from(amount).ok(),
+                Value::Unsigned(amount) => u32::try_from(amount).ok(),
+            }
+            .filter(|&amount| amount < self.options.width);
+
+            let amount = match amount {
+                Some(amount) => amount,
+                None => {
+                    if eval {
+                        self.report(
+                            Severity::Warning,
+                            DiagnosticCode::ShiftOutOfRange,
+                            rhs.span,
+                            "Shift count out of range in preprocessor expression",
+                        );
+                    }
+                    // Every bit is shifted out of the value.
+                    return match (op, lhs.value) {
+                        (BinOp::Shr, Value::Signed(value)) if value < 0 => Value::Signed(-1),
+                        (_, Value::Signed(_)) => Value::Signed(0),
+                        (_, Value::Unsigned(_)) => Value::Unsigned(0),
+                    };
+                }
+            };
+
+            let (result, overflow) = match (op, lhs.value) {
+                (BinOp::Shl, Value::Signed(value)) => {
+                    let result = value << amount;
+                    (Value::Signed(result), result >> amount != value)
+                }
+                (BinOp::Shl, Value::Unsigned(value)) => (Value::Unsigned(value << amount), false),
+                (_, Value::Signed(value)) => (Value::Signed(value >> amount), false),
+                (_, Value::Unsigned(value)) => (Value::Unsigned(value >> amount), false),
+            };
+            return self.wrap(result, overflow, span, eval);
+        }
+
+        if matches!(op, BinOp::Div | BinOp::Rem) && !rhs.value.is_true() {
+            if eval {
+                self.report(
+                    Severity::Error,
+                    DiagnosticCode::DivisionByZero,
+                    rhs.span,
+                    "Division by zero in preprocessor expression",
+                );
+            }
+            return Value::Signed(0);
+        }
+
+        let (result, overflow) = match Value::convert(lhs.value, rhs.value, self.options.width) {
+            (Value::Signed(lhs), Value::Signed(rhs)) => {
+                let (result, overflow) = match op {
+                    BinOp::Eq => (i64::from(lhs == rhs), false),
+                    BinOp::Ne => (i64::from(lhs != rhs), false),
+                    BinOp::Lt => (i64::from(lhs < rhs), false),
+                    BinOp::Gt => (i64::from(lhs > rhs), false),
+                    BinOp::Le => (i64::from(lhs <= rhs), false),
+                    BinOp::Ge => (i64::from(lhs >= rhs), false),
+                    BinOp::BitOr => (lhs | rhs, false),
+                    BinOp::BitXor => (lhs ^ rhs, false),
+                    BinOp::BitAnd => (lhs & rhs, false),
+                    BinOp::Add => lhs.overflowing_add(rhs),
+                    BinOp::Sub => lhs.overflowing_sub(rhs),
+                    BinOp::Mul => lhs.overflowing_mul(rhs),
+                    BinOp::Div => lhs.overflowing_div(rhs),
+                    BinOp::Rem => lhs.overflowing_rem(rhs),
+                    _ => unreachable!(),
+                };
+                (Value::Signed(result), overflow)
+            }
+            (Value::Unsigned(lhs), Value::Unsigned(rhs)) => {
+                let result = match op {
+                    BinOp::Eq => return Value::from_bool(lhs == rhs),
+                    BinOp::Ne => return Value::from_bool(lhs != rhs),
+                    BinOp::Lt => return Value::from_bool(lhs < rhs),
+                    BinOp::Gt => return Value::from_bool(lhs > rhs),
+                    BinOp::Le => return Value::from_bool(lhs <= rhs),
+                    BinOp::Ge => return Value::from_bool(lhs >= rhs),
+                    BinOp::BitOr => lhs | rhs,
+                    BinOp::BitXor => lhs ^ rhs,
+                    BinOp::BitAnd => lhs & rhs,
+                    // Unsigned arithmetic cannot overflow, it wraps around.
+                    BinOp::Add => lhs.wrapping_add(rhs),
+                    BinOp::Sub => lhs.wrapping_sub(rhs),
+                    BinOp::Mul => lhs.wrapping_mul(rhs),
+                    BinOp::Div => lhs / rhs,
+                    BinOp::Rem => lhs % rhs,
+                    _ => unreachable!(),
+                };
+                (Value::Unsigned(result), false)
+            }
+            _ => unreachable!(),
+        };
+
+        self.wrap(result, overflow, span, eval)
+    }
+
+    /// Truncate the result of an operation found in `span` to the width of `intmax_t`. Signed
+    /// results that overflowed or do not fit are reported if `eval` is `true`, while unsigned
+    /// results wrap around.
+    fn wrap(&mut self, result: Value, overflow: bool, span: Span, eval: bool) -> Value {
+        let (result, truncated) = result.truncate(self.options.width);
+        if (overflow || truncated) && matches!(result, Value::Signed(_)) && eval {
+            self.report(
+                Severity::Warning,
+                DiagnosticCode::IntegerOverflow,
+                span,
+                "Integer overflow in preprocessor expression",
+            );
+        }
+        result
+    }
+}
+
+/// Parse an `integer-constant` as defined in section 6.4.4.1 of C17. Return `None` if
+/// `spelling` is not a valid `integer-constant`.
+fn parse_integer(spelling: &str) -> Option<Value> {
+    let constant = IntegerConstant::parse(spelling)?;
+    // Constants that do not fit in `i64` can only be represented as `u64`.
+    match i64::try_from(constant.value()) {
+        Ok(value) if !constant.is_unsigned() => Some(Value::Signed(value)),
+        _ => Some(Value::Unsigned(constant.value())),
+    }
+}
+
+/// Compute the value of a `character-constant` as defined in section 6.4.4.4 of C17.
+///
+/// Constants without prefix have type `int`. If they have a single character, their value is the
+/// one of that character as a `char`. Otherwise, each of their bytes is appended to the value
+/// from the most to the least significant one, keeping the lowest 32 bits. Source characters are
+/// encoded as UTF-8, so a non-ASCII character is a sequence of bytes.
+///
+/// Constants with the `L` prefix are 32-bit signed integers and the ones with the `u` and `U`
+/// prefixes are 16 and 32-bit unsigned integers respectively. Their value is the one of their
+/// only character as a Unicode code point.
+///
+/// Return the description of the problem if the constant is not valid.
+pub(crate) fn parse_char(spelling: &str, options: &Evaluation) -> Result<Value, String> {
+    // The lexer only produces constants enclosed in single quotes.
+    let (prefix, body) = spelling.split_once('\'').unwrap();
+    let body = &body[..body.len() - 1];
+
+    // The width of each character of the constant, in bits.
+    let width = match prefix {
+        "" => 8,
+        "u" => 16,
+        _ => 32,
+    };
+
+    let units = decode(body, width, spelling)?;
+    let value = match units[..] {
+        [] => return Err("Empty character constant".to_owned()),
+        [unit] if width == 8 && options.signed_char => Value::Signed((unit as u8 as i8).into()),
+        [unit] if prefix == "L" => Value::Signed((unit as i32).into()),
+        [unit] if width == 8 => Value::Signed(unit.into()),
+        [unit] => Value::Unsigned(unit.into()),
+        _ if width != 8 => {
+            return Err(format!(
+                "Character constant {} is too long for its type",
+                spelling
+            ))
+        }
+        _ if !options.multi_char => {
+            return Err(format!("Multi-character character constant {}", spelling))
+        }
+        _ => {
+            let value = units
+                .iter()
+                .fold(0u32, |value, &unit| value.wrapping_shl(8) | unit);
+            Value::Signed((value as i32).into())
+        }
+    };
+    Ok(value)
+}
+
+/// Replace the escape sequences of `body`, the characters of the constant or string literal
+/// spelled `spelling`, producing code units of `width` bits. Characters that are not escaped are
+/// encoded as UTF-8 if the width is 8 bits.
+///
+/// Return the description of the problem if an escape sequence is not valid.
+pub(crate) fn decode(body: &str, width: u32, spelling: &str) -> Result<Vec<u32>, String> {
+    let mut units: Vec<u32> = Vec::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            if width == 8 {
+                units.extend(c.to_string().bytes().map(u32::from));
+            } else {
+                units.push(c.into());
+            }
+            continue;
+        }
+
+        let escape = chars.next().unwrap();
+        let unit = match escape {
+            'n' => 0x0a,
+            't' => 0x09,
+            'v' => 0x0b,
+            'b' => 0x08,
+            'r' => 0x0d,
+            'f' => 0x0c,
+            'a' => 0x07,
+            '\\' | '\'' | '"' | '?' => escape.into(),
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => value = value * 8 + digit,
+                        None => break,
+                    }
+                    chars.next();
+                }
+                value
+            }
+            'x' => {
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = value.wrapping_mul(16).wrapping_add(digit);
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 {
+                    return Err("\\x used with no following hex digits".to_owned());
+                }
+                value
+            }
+            'u' | 'U' => {
+                let len = if escape == 'u' { 4 } else { 8 };
+                let digits: String = (0..len).filter_map(|_| chars.next()).collect();
+                let c = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| digits.len() == len)
+                    .and_then(char::from_u32);
+                match c {
+                    Some(c) if width == 8 => {
+                        units.extend(c.to_string().bytes().map(u32::from));
+                        continue;
+                    }
+                    Some(c) => c.into(),
+                    None => {
+                        return Err(format!("Invalid universal character name in {}", spelling))
+                    }
+                }
+            }
+            _ => return Err(format!("Unknown escape sequence \"\\{}\"", escape)),
+        };
+        // Escape sequences whose value does not fit are truncated.
+        units.push(unit & (u32::MAX >> (32 - width)));
+    }
+
+    Ok(units)
+}
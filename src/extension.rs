@@ -0,0 +1,51 @@
+//! The extensions to ISO C that can be enabled one by one.
+
+/// An extension to ISO C implemented by some compilers, enabled using
+/// [`Preprocessor::extension`](crate::Preprocessor::extension). Every extension is disabled by
+/// default and can be enabled independently of the others and of the
+/// [`Standard`](crate::Standard), so the exact set of features of a compiler can be emulated.
+///
+/// ```
+/// use beheader::{Emitter, Extension, Preprocessor};
+///
+/// let source = b"#define LOG(fmt, args...) log(fmt, ## args)\nLOG(\"a\") __COUNTER__\n";
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor
+///     .extension(Extension::GnuVariadic, true)
+///     .extension(Extension::Counter, true);
+/// let preprocessed = preprocessor.preprocess(source).unwrap();
+/// let text = Emitter::new().line_markers(false).render(&preprocessed);
+/// assert_eq!(text, b"log(\"a\") 0\n");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Extension {
+    /// The variadic macros of GNU C: the variable arguments can be named by writing a parameter
+    /// name before the `...`, as in `#define F(args...)`, and a `,` followed by `##` and the
+    /// variable arguments is removed if they are empty.
+    GnuVariadic,
+    /// The `#include_next` directive, which searches the included file in the directories that
+    /// come after the one where the file containing the directive was found.
+    IncludeNext,
+    /// The `__COUNTER__` macro, which is replaced by `0` the first time and by the next integer
+    /// every other time.
+    Counter,
+    /// `$` is accepted in identifiers like a letter.
+    DollarIdents,
+    /// The `#import` directive, which includes a file unless it was included before.
+    Import,
+    /// Pasting two tokens with `##` does not need to give a valid preprocessing token, as in
+    /// MSVC. The tokens are kept as they are if it does not.
+    MsvcPaste,
+}
+
+impl Extension {
+    /// The extensions implemented by GCC.
+    pub const GNU: &'static [Self] = &[
+        Self::GnuVariadic,
+        Self::IncludeNext,
+        Self::Counter,
+        Self::DollarIdents,
+        Self::Import,
+    ];
+}
@@ -0,0 +1,591 @@
+//! A C API to embed the preprocessor in programs written in other languages.
+//!
+//! The declarations of this API are in `include/beheader.h`, which is generated from this module
+//! by running `cbindgen --config cbindgen.toml --output include/beheader.h`. The libraries to
+//! link with are built by running `cargo rustc --lib --release --features ffi --crate-type
+//! cdylib`, or `staticlib` instead of `cdylib`. Every string given to these functions must be
+//! NUL-terminated and encoded in UTF-8. A panic inside any of them is caught and makes it fail,
+//! as if an argument was not valid.
+//!
+//! A preprocessor is created using [`beheader_preprocessor_new`], configured using the other
+//! `beheader_preprocessor_*` functions and run using [`beheader_preprocess_file`] or
+//! [`beheader_preprocess_source`], which give a result whose output and diagnostics can be
+//! inspected until it is freed:
+//!
+//! ```c
+//! BeheaderPreprocessor *preprocessor = beheader_preprocessor_new();
+//! beheader_preprocessor_include_dir(preprocessor, "include");
+//! beheader_preprocessor_define(preprocessor, "NDEBUG");
+//!
+//! BeheaderResult *result = beheader_preprocess_file(preprocessor, "main.c");
+//! size_t len;
+//! const BeheaderDiagnostic *diagnostics = beheader_result_diagnostics(result, &len);
+//! for (size_t i = 0; i < len; i++) {
+//!     fprintf(stderr, "%s\n", diagnostics[i].message);
+//! }
+//! if (!beheader_result_error(result)) {
+//!     fputs(beheader_result_text(result, &len), stdout);
+//! }
+//! beheader_result_free(result);
+//! beheader_preprocessor_free(preprocessor);
+//! ```
+#[cfg(test)]
+mod tests;
+
+use std::{
+    collections::HashMap,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use crate::{
+    Diagnostic, Emitter, Error, Preprocessed, Preprocessor, Severity, SourceMapSnapshot, Standard,
+    TokenKind,
+};
+
+/// A preprocessor and its configuration.
+pub struct BeheaderPreprocessor {
+    preprocessor: Preprocessor,
+}
+
+/// The result of preprocessing some source code, with the tokens, text and diagnostics it
+/// produced.
+pub struct BeheaderResult {
+    /// The message of the error that stopped the preprocessing, if any.
+    error: Option<CString>,
+    /// The output as text, followed by a NUL character that is not part of it.
+    text: Vec<u8>,
+    tokens: Vec<BeheaderToken>,
+    diagnostics: Vec<BeheaderDiagnostic>,
+    /// The strings the tokens and diagnostics point to.
+    _strings: Vec<CString>,
+    /// The source code the spellings of the tokens point to.
+    _snapshot: SourceMapSnapshot,
+}
+
+/// The kind of a [`BeheaderToken`], as described by [`TokenKind`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BeheaderTokenKind {
+    Header,
+    Ident,
+    Number,
+    Char,
+    Str,
+    Punct,
+    Any,
+    Space,
+    Newline,
+}
+
+impl From<TokenKind> for BeheaderTokenKind {
+    fn from(kind: TokenKind) -> Self {
+        match kind {
+            TokenKind::Header => Self::Header,
+            TokenKind::Ident => Self::Ident,
+            TokenKind::Number => Self::Number,
+            TokenKind::Char => Self::Char,
+            TokenKind::Str => Self::Str,
+            TokenKind::Punct => Self::Punct,
+            TokenKind::Any => Self::Any,
+            TokenKind::Space => Self::Space,
+            TokenKind::Newline => Self::Newline,
+        }
+    }
+}
+
+/// A token of the output of a [`BeheaderResult`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BeheaderToken {
+    pub kind: BeheaderTokenKind,
+    /// The spelling of the token, which is not NUL-terminated.
+    pub spelling: *const c_char,
+    /// The length of the spelling in bytes.
+    pub spelling_len: usize,
+    /// The file where the token was written, or `NULL` if it is not in a file.
+    pub file: *const c_char,
+    /// The line where the token starts, or 0 if it is not in a file.
+    pub line: usize,
+    /// The column where the token starts, or 0 if it is not in a file.
+    pub column: usize,
+}
+
+/// The severity of a [`BeheaderDiagnostic`], as described by [`Severity`].
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BeheaderSeverity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl From<Severity> for BeheaderSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Note => Self::Note,
+            Severity::Warning => Self::Warning,
+            Severity::Error => Self::Error,
+        }
+    }
+}
+
+/// A diagnostic reported while preprocessing, including the one that stopped the preprocessing
+/// if there is one.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BeheaderDiagnostic {
+    pub severity: BeheaderSeverity,
+    /// The name of the code of the diagnostic, like `include-not-found`.
+    pub code: *const c_char,
+    pub message: *const c_char,
+    /// The file the diagnostic is about, or `NULL` if it is not about a file.
+    pub file: *const c_char,
+    /// The line the diagnostic is about, or 0 if it is not about a file.
+    pub line: usize,
+    /// The column the diagnostic is about, or 0 if it is not about a file.
+    pub column: usize,
+}
+
+/// The strings of a [`BeheaderResult`], which are only stored once.
+#[derive(Default)]
+struct Strings {
+    strings: Vec<CString>,
+    indices: HashMap<String, usize>,
+}
+
+impl Strings {
+    /// Get a pointer to a NUL-terminated copy of `string`, which lives as long as the strings
+    /// are kept. Any NUL character of `string` ends it early.
+    fn get(&mut self, string: &str) -> *const c_char {
+        if let Some(&index) = self.indices.get(string) {
+            return self.strings[index].as_ptr();
+        }
+        let end = string.find('\0').unwrap_or(string.len());
+        let copy = CString::new(&string[..end]).unwrap();
+        self.indices.insert(string.to_owned(), self.strings.len());
+        self.strings.push(copy);
+        self.strings.last().unwrap().as_ptr()
+    }
+
+    /// Get a pointer to the path `file` like [`get`](Self::get) does, or `NULL` if there is none.
+    fn file(&mut self, file: Option<&PathBuf>) -> *const c_char {
+        match file {
+            Some(file) => self.get(&file.display().to_string()),
+            None => ptr::null(),
+        }
+    }
+
+    fn diagnostic(&mut self, diagnostic: &Diagnostic) -> BeheaderDiagnostic {
+        BeheaderDiagnostic {
+            severity: diagnostic.severity().into(),
+            code: self.get(diagnostic.code().as_str()),
+            message: self.get(diagnostic.message()),
+            file: self.file(diagnostic.file()),
+            line: diagnostic.line().unwrap_or_default(),
+            column: diagnostic.column().unwrap_or_default(),
+        }
+    }
+}
+
+impl BeheaderResult {
+    fn new(result: Result<Preprocessed, Error>) -> Self {
+        let mut strings = Strings::default();
+        let (preprocessed, error) = match result {
+            Ok(preprocessed) => (Some(preprocessed), None),
+            Err(error) => (None, Some(error)),
+        };
+        let mut diagnostics: Vec<_> = preprocessed
+            .iter()
+            .flat_map(Preprocessed::diagnostics)
+            .map(|diagnostic| strings.diagnostic(diagnostic))
+            .collect();
+        if let Some(error) = &error {
+            diagnostics.extend(
+                error
+                    .diagnostics()
+                    .iter()
+                    .chain(error.diagnostic())
+                    .map(|diagnostic| strings.diagnostic(diagnostic)),
+            );
+        }
+
+        let snapshot = preprocessed
+            .as_ref()
+            .map(Preprocessed::source_map)
+            .unwrap_or_default();
+        let mut tokens = Vec::new();
+        let mut text = Vec::new();
+        if let Some(preprocessed) = &preprocessed {
+            for &token in preprocessed.tokens() {
+                let spelling = snapshot.spelling(token.span()).unwrap_or_default();
+                let (file, line, column) = match preprocessed.resolve_span(token.span()) {
+                    Some(location) if location.file().is_some() => (
+                        strings.file(location.file()),
+                        location.start_line(),
+                        location.start_column(),
+                    ),
+                    _ => (ptr::null(), 0, 0),
+                };
+                tokens.push(BeheaderToken {
+                    kind: token.kind().into(),
+                    spelling: spelling.as_ptr().cast(),
+                    spelling_len: spelling.len(),
+                    file,
+                    line,
+                    column,
+                });
+            }
+            text = Emitter::new().render(preprocessed);
+        }
+        text.push(0);
+
+        Self {
+            error: error.map(|error| CString::new(error.to_string().replace('\0', "")).unwrap()),
+            text,
+            tokens,
+            diagnostics,
+            _strings: strings.strings,
+            _snapshot: snapshot,
+        }
+    }
+}
+
+/// Run `f` and return its result, or `failed` if it panics, as a panic cannot unwind into the
+/// caller of a C function.
+fn catch<T>(failed: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(failed)
+}
+
+/// Get the string `string` points to, or `None` if it is `NULL` or not valid UTF-8.
+///
+/// # Safety
+///
+/// `string` must be `NULL` or point to a NUL-terminated string that lives as long as `'a`.
+unsafe fn to_str<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(string) }.to_str().ok()
+}
+
+/// Configure `preprocessor` using `configure` with the string `argument`. Return `false` if
+/// either of them is `NULL` or if the string is not valid UTF-8.
+///
+/// # Safety
+///
+/// `preprocessor` must be `NULL` or come from [`beheader_preprocessor_new`] and not be freed,
+/// and `argument` must be `NULL` or point to a NUL-terminated string.
+unsafe fn configure<F>(
+    preprocessor: *mut BeheaderPreprocessor,
+    argument: *const c_char,
+    f: F,
+) -> bool
+where
+    F: FnOnce(&mut Preprocessor, &str) -> bool,
+{
+    let preprocessor = match unsafe { preprocessor.as_mut() } {
+        Some(preprocessor) => &mut preprocessor.preprocessor,
+        None => return false,
+    };
+    match unsafe { to_str(argument) } {
+        Some(argument) => catch(false, || f(preprocessor, argument)),
+        None => false,
+    }
+}
+
+/// Create a preprocessor with the default configuration, which must be freed using
+/// [`beheader_preprocessor_free`]. Return `NULL` if it cannot be created.
+#[no_mangle]
+pub extern "C" fn beheader_preprocessor_new() -> *mut BeheaderPreprocessor {
+    catch(ptr::null_mut(), || {
+        Box::into_raw(Box::new(BeheaderPreprocessor {
+            preprocessor: Preprocessor::new(),
+        }))
+    })
+}
+
+/// Free a preprocessor. Nothing happens if it is `NULL`.
+///
+/// # Safety
+///
+/// `preprocessor` must be `NULL` or come from [`beheader_preprocessor_new`] and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_free(preprocessor: *mut BeheaderPreprocessor) {
+    if !preprocessor.is_null() {
+        catch((), || drop(unsafe { Box::from_raw(preprocessor) }));
+    }
+}
+
+/// Define a macro as the `-D` option of a compiler does, using `NAME` or `NAME=DEFINITION`.
+/// Return `false` if an argument is `NULL` or not valid UTF-8.
+///
+/// # Safety
+///
+/// `preprocessor` must come from [`beheader_preprocessor_new`] and not be freed, and
+/// `definition` must point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_define(
+    preprocessor: *mut BeheaderPreprocessor,
+    definition: *const c_char,
+) -> bool {
+    unsafe {
+        configure(preprocessor, definition, |preprocessor, definition| {
+            preprocessor.define(definition);
+            true
+        })
+    }
+}
+
+/// Undefine a macro as the `-U` option of a compiler does. Return `false` if an argument is
+/// `NULL` or not valid UTF-8.
+///
+/// # Safety
+///
+/// The same as for [`beheader_preprocessor_define`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_undefine(
+    preprocessor: *mut BeheaderPreprocessor,
+    name: *const c_char,
+) -> bool {
+    unsafe {
+        configure(preprocessor, name, |preprocessor, name| {
+            preprocessor.undefine(name);
+            true
+        })
+    }
+}
+
+/// Add a directory where included files are searched, as the `-I` option of a compiler does.
+/// Return `false` if an argument is `NULL` or not valid UTF-8.
+///
+/// # Safety
+///
+/// The same as for [`beheader_preprocessor_define`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_include_dir(
+    preprocessor: *mut BeheaderPreprocessor,
+    dir: *const c_char,
+) -> bool {
+    unsafe {
+        configure(preprocessor, dir, |preprocessor, dir| {
+            preprocessor.include_dir(dir);
+            true
+        })
+    }
+}
+
+/// Add a directory where included files are searched and considered system headers, as the
+/// `-isystem` option of a compiler does. Return `false` if an argument is `NULL` or not valid
+/// UTF-8.
+///
+/// # Safety
+///
+/// The same as for [`beheader_preprocessor_define`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_system_include_dir(
+    preprocessor: *mut BeheaderPreprocessor,
+    dir: *const c_char,
+) -> bool {
+    unsafe {
+        configure(preprocessor, dir, |preprocessor, dir| {
+            preprocessor.system_include_dir(dir);
+            true
+        })
+    }
+}
+
+/// Include a file before the input, as the `-include` option of a compiler does. Return
+/// `false` if an argument is `NULL` or not valid UTF-8.
+///
+/// # Safety
+///
+/// The same as for [`beheader_preprocessor_define`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_include(
+    preprocessor: *mut BeheaderPreprocessor,
+    path: *const c_char,
+) -> bool {
+    unsafe {
+        configure(preprocessor, path, |preprocessor, path| {
+            preprocessor.include(path);
+            true
+        })
+    }
+}
+
+/// Set the version of the C standard using its name for the `-std` option of GCC, like `c99`
+/// or `gnu11`. Return `false` if an argument is `NULL` or the standard is not known.
+///
+/// # Safety
+///
+/// The same as for [`beheader_preprocessor_define`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_standard(
+    preprocessor: *mut BeheaderPreprocessor,
+    name: *const c_char,
+) -> bool {
+    unsafe {
+        configure(
+            preprocessor,
+            name,
+            |preprocessor, name| match Standard::from_name(name) {
+                Some(standard) => {
+                    preprocessor.standard(standard);
+                    true
+                }
+                None => false,
+            },
+        )
+    }
+}
+
+/// Enable or disable warnings using an option like `-Wall` or `-Werror=trigraphs`. Return
+/// `false` if an argument is `NULL` or not valid UTF-8.
+///
+/// # Safety
+///
+/// The same as for [`beheader_preprocessor_define`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocessor_warning_option(
+    preprocessor: *mut BeheaderPreprocessor,
+    option: *const c_char,
+) -> bool {
+    unsafe {
+        configure(preprocessor, option, |preprocessor, option| {
+            preprocessor.warning_option(option);
+            true
+        })
+    }
+}
+
+/// Preprocess the file at `path`, returning a result that must be freed using
+/// [`beheader_result_free`]. Return `NULL` if an argument is `NULL` or not valid UTF-8, or if
+/// the preprocessor panics.
+///
+/// # Safety
+///
+/// `preprocessor` must come from [`beheader_preprocessor_new`] and not be freed, and `path`
+/// must point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocess_file(
+    preprocessor: *const BeheaderPreprocessor,
+    path: *const c_char,
+) -> *mut BeheaderResult {
+    let (preprocessor, path) = match unsafe { (preprocessor.as_ref(), to_str(path)) } {
+        (Some(preprocessor), Some(path)) => (&preprocessor.preprocessor, Path::new(path)),
+        _ => return ptr::null_mut(),
+    };
+    catch(ptr::null_mut(), || {
+        let result = BeheaderResult::new(preprocessor.preprocess_file(&path));
+        Box::into_raw(Box::new(result))
+    })
+}
+
+/// Preprocess the `len` bytes at `source`, returning a result that must be freed using
+/// [`beheader_result_free`]. Return `NULL` if an argument is `NULL` or if the preprocessor
+/// panics.
+///
+/// # Safety
+///
+/// `preprocessor` must come from [`beheader_preprocessor_new`] and not be freed, and `source`
+/// must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_preprocess_source(
+    preprocessor: *const BeheaderPreprocessor,
+    source: *const u8,
+    len: usize,
+) -> *mut BeheaderResult {
+    let preprocessor = match unsafe { preprocessor.as_ref() } {
+        Some(preprocessor) if !source.is_null() => &preprocessor.preprocessor,
+        _ => return ptr::null_mut(),
+    };
+    let source = unsafe { std::slice::from_raw_parts(source, len) };
+    catch(ptr::null_mut(), || {
+        let result = BeheaderResult::new(preprocessor.preprocess(source));
+        Box::into_raw(Box::new(result))
+    })
+}
+
+/// Get the message of the error that stopped the preprocessing, or `NULL` if it finished. The
+/// message lives as long as the result.
+///
+/// # Safety
+///
+/// `result` must come from [`beheader_preprocess_file`] or [`beheader_preprocess_source`] and
+/// not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_result_error(result: *const BeheaderResult) -> *const c_char {
+    let result = unsafe { &*result };
+    result
+        .error
+        .as_ref()
+        .map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// Get the output as text, with line markers, storing its length in `len` if it is not `NULL`.
+/// The text is NUL-terminated, lives as long as the result and is empty if the preprocessing
+/// did not finish.
+///
+/// # Safety
+///
+/// The same as for [`beheader_result_error`], and `len` must be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_result_text(
+    result: *const BeheaderResult,
+    len: *mut usize,
+) -> *const c_char {
+    let result = unsafe { &*result };
+    if let Some(len) = unsafe { len.as_mut() } {
+        *len = result.text.len() - 1;
+    }
+    result.text.as_ptr().cast()
+}
+
+/// Get the tokens of the output, storing how many there are in `len`. The tokens live as long
+/// as the result, and there are none if the preprocessing did not finish.
+///
+/// # Safety
+///
+/// The same as for [`beheader_result_error`], and `len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_result_tokens(
+    result: *const BeheaderResult,
+    len: *mut usize,
+) -> *const BeheaderToken {
+    let result = unsafe { &*result };
+    unsafe { *len = result.tokens.len() };
+    result.tokens.as_ptr()
+}
+
+/// Get the diagnostics reported while preprocessing, storing how many there are in `len`. The
+/// diagnostics live as long as the result, and the last one is the error that stopped the
+/// preprocessing if it was caused by the source code.
+///
+/// # Safety
+///
+/// The same as for [`beheader_result_tokens`].
+#[no_mangle]
+pub unsafe extern "C" fn beheader_result_diagnostics(
+    result: *const BeheaderResult,
+    len: *mut usize,
+) -> *const BeheaderDiagnostic {
+    let result = unsafe { &*result };
+    unsafe { *len = result.diagnostics.len() };
+    result.diagnostics.as_ptr()
+}
+
+/// Free a result, including everything obtained from it. Nothing happens if it is `NULL`.
+///
+/// # Safety
+///
+/// `result` must be `NULL` or come from [`beheader_preprocess_file`] or
+/// [`beheader_preprocess_source`] and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn beheader_result_free(result: *mut BeheaderResult) {
+    if !result.is_null() {
+        catch((), || drop(unsafe { Box::from_raw(result) }));
+    }
+}
@@ -0,0 +1,87 @@
+use std::{ffi::CStr, ptr};
+
+use super::*;
+
+/// Get the string `string` points to.
+unsafe fn string<'a>(string: *const c_char) -> &'a str {
+    unsafe { CStr::from_ptr(string) }.to_str().unwrap()
+}
+
+#[test]
+fn preprocess_source() {
+    let source = b"#ifdef DEBUG\nint debug;\n#endif\n#define A 1\n#define A 2\nint a;\n";
+    unsafe {
+        let preprocessor = beheader_preprocessor_new();
+        assert!(beheader_preprocessor_define(
+            preprocessor,
+            c"DEBUG=1".as_ptr()
+        ));
+        assert!(beheader_preprocessor_standard(
+            preprocessor,
+            c"gnu11".as_ptr()
+        ));
+        assert!(!beheader_preprocessor_standard(
+            preprocessor,
+            c"c42".as_ptr()
+        ));
+        assert!(!beheader_preprocessor_define(preprocessor, ptr::null()));
+
+        let result = beheader_preprocess_source(preprocessor, source.as_ptr(), source.len());
+        beheader_preprocessor_free(preprocessor);
+        assert!(beheader_result_error(result).is_null());
+
+        let mut len = 0;
+        let text = beheader_result_text(result, &mut len);
+        assert_eq!(
+            string(text),
+            "# 1 \"<stdin>\"\n\nint debug;\n\n\n\nint a;\n"
+        );
+        assert_eq!(len, string(text).len());
+
+        let tokens = beheader_result_tokens(result, &mut len);
+        let tokens = std::slice::from_raw_parts(tokens, len);
+        let token = tokens[1];
+        assert_eq!(token.kind, BeheaderTokenKind::Ident);
+        let spelling = std::slice::from_raw_parts(token.spelling.cast::<u8>(), token.spelling_len);
+        assert_eq!(spelling, b"int");
+        assert!(token.file.is_null());
+
+        let diagnostics = beheader_result_diagnostics(result, &mut len);
+        let diagnostics = std::slice::from_raw_parts(diagnostics, len);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, BeheaderSeverity::Warning);
+        assert_eq!(string(diagnostics[0].code), "macro-redefined");
+        assert_eq!(string(diagnostics[0].message), "Macro \"A\" redefined");
+        beheader_result_free(result);
+    }
+}
+
+#[test]
+fn preprocess_file() {
+    let dir = std::env::temp_dir().join("beheader-tests").join("ffi");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("main.c"), "#include \"missing.h\"\n").unwrap();
+    let path = CString::new(dir.join("main.c").to_str().unwrap()).unwrap();
+
+    unsafe {
+        let preprocessor = beheader_preprocessor_new();
+        let result = beheader_preprocess_file(preprocessor, path.as_ptr());
+        beheader_preprocessor_free(preprocessor);
+
+        let error = string(beheader_result_error(result));
+        assert!(error.contains("Cannot find included file \"missing.h\""));
+        let mut len = 0;
+        assert_eq!(string(beheader_result_text(result, &mut len)), "");
+        beheader_result_tokens(result, &mut len);
+        assert_eq!(len, 0);
+
+        // The error is the last diagnostic.
+        let diagnostics = beheader_result_diagnostics(result, &mut len);
+        let diagnostic = std::slice::from_raw_parts(diagnostics, len)[len - 1];
+        assert_eq!(diagnostic.severity, BeheaderSeverity::Error);
+        assert_eq!(string(diagnostic.code), "include-not-found");
+        assert_eq!(string(diagnostic.file), path.to_str().unwrap());
+        assert_eq!((diagnostic.line, diagnostic.column), (1, 1));
+        beheader_result_free(result);
+    }
+}
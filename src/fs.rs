@@ -0,0 +1,300 @@
+//! Access to the files read while preprocessing.
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+/// The replacements of path prefixes used to show paths, like the `-ffile-prefix-map` option of
+/// GCC.
+#[derive(Clone, Debug, Default, Hash)]
+pub(crate) struct PrefixMap {
+    /// The prefixes and their replacements, in the order they were given.
+    prefixes: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PrefixMap {
+    /// Replace the prefix `old` of paths by `new`. Prefixes given later take precedence.
+    pub(crate) fn insert(&mut self, old: PathBuf, new: PathBuf) {
+        self.prefixes.push((old, new));
+    }
+
+    /// Replace the prefix of `path` by the last replacement given for it, if any. Prefixes are
+    /// made of whole components, so `/src` is not a prefix of `/srcs/a.c`.
+    pub(crate) fn apply<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let replaced = self.prefixes.iter().rev().find_map(|(old, new)| {
+            let rest = path.strip_prefix(old).ok()?;
+            Some(if rest.as_os_str().is_empty() {
+                new.clone()
+            } else {
+                new.join(rest)
+            })
+        });
+        match replaced {
+            Some(path) => Cow::Owned(path),
+            None => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// The source of every file read while preprocessing, including the input file, the files from
+/// the command line and the files included by `#include` directives.
+///
+/// ```
+/// use beheader::{MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("include/config.h", "#define VERSION 2\n");
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files).include_dir("include");
+/// preprocessor.preprocess(b"#include <config.h>\nVERSION").unwrap();
+/// ```
+pub trait FileSystem: Send + Sync {
+    /// Read the whole contents of the file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Check if there is a file at `path` that can be read. Directories are not files.
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// Get the canonical form of `path`, which is the same for every path referring to the same
+    /// file. This is only used if
+    /// [`Preprocessor::resolve_symlinks`](crate::Preprocessor::resolve_symlinks) is enabled.
+    ///
+    /// By default, the `.` components and the `..` components after a directory are removed.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        Ok(normalize(path))
+    }
+
+    /// Check if there is a file or a directory at `path`.
+    ///
+    /// By default, only files are considered to exist.
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path)
+    }
+
+    /// List the paths of the entries of the directory at `path`. Each path is `path` joined with
+    /// the name of the entry. An empty `path` is the current directory.
+    ///
+    /// This is only used to find included files whose name has the wrong case, see
+    /// [`Preprocessor::case_insensitive_includes`](crate::Preprocessor::case_insensitive_includes).
+    /// By default, directories cannot be listed.
+    fn read_dir(&self, _path: &Path) -> io::Result<Vec<PathBuf>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// Get the size and the last modification time of the file at `path`, which are used to
+    /// check if a file changed without reading it, see [`Preprocessed::stale_files`].
+    ///
+    /// By default, they cannot be obtained, so files are read again to check them.
+    ///
+    /// [`Preprocessed::stale_files`]: crate::Preprocessed::stale_files
+    fn metadata(&self, _path: &Path) -> io::Result<FileMetadata> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// The size and last modification time of a file, obtained using [`FileSystem::metadata`]. A
+/// file whose metadata did not change since it was read is assumed to have the same contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FileMetadata {
+    size: u64,
+    modified: SystemTime,
+}
+
+impl FileMetadata {
+    /// Create the metadata of a file whose size is `size` bytes and that was modified last at
+    /// `modified`.
+    pub fn new(size: u64, modified: SystemTime) -> Self {
+        Self { size, modified }
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The last time the file was modified.
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+}
+
+/// The file system of the operating system. This is the [`FileSystem`] used by default, except
+/// on `wasm32-unknown-unknown`, which has no file system, where an empty [`MemoryFileSystem`] is
+/// used instead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let dir = if path.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            path
+        };
+        fs::read_dir(dir)?
+            .map(|entry| Ok(path.join(entry?.file_name())))
+            .collect()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileMetadata::new(metadata.len(), metadata.modified()?))
+    }
+}
+
+/// Get the [`FileSystem`] used by default, which is described by [`RealFileSystem`].
+pub(crate) fn default_file_system() -> Arc<dyn FileSystem> {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        Arc::new(MemoryFileSystem::new())
+    } else {
+        Arc::new(RealFileSystem)
+    }
+}
+
+/// Remove the `.` components and the `..` components after a directory of `path` without
+/// accessing the file system. `..` components at the start of a relative path are kept.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // The parent of the root is the root itself.
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(component),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// A [`FileSystem`] whose files are stored in memory.
+///
+/// It can be used on its own to keep the preprocessor from reading any actual file or on top of
+/// another [`FileSystem`] using [`overlay`](Self::overlay), in which case its files hide the ones
+/// with the same path in the other one.
+///
+/// Paths are compared after removing their `.` components and the `..` components after a
+/// directory, so `a/b.h` and `./a/c/../b.h` are the same file.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    files: HashMap<PathBuf, Arc<[u8]>>,
+    base: Option<Arc<dyn FileSystem>>,
+}
+
+impl MemoryFileSystem {
+    /// Create a new file system without files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new file system without files on top of `base`. Any file that is not stored in
+    /// memory is accessed using `base`.
+    pub fn overlay<F: FileSystem + 'static>(base: F) -> Self {
+        Self {
+            files: HashMap::new(),
+            base: Some(Arc::new(base)),
+        }
+    }
+
+    /// Store a file at `path` whose contents are `contents`, replacing any previous file with
+    /// the same path.
+    pub fn insert<P: AsRef<Path>, C: AsRef<[u8]>>(&mut self, path: P, contents: C) -> &mut Self {
+        self.files
+            .insert(normalize(path.as_ref()), contents.as_ref().into());
+        self
+    }
+
+    /// Remove the file stored at `path`, if any. Files accessed using the base file system are
+    /// not affected.
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.files.remove(&normalize(path.as_ref()));
+        self
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match (self.files.get(&normalize(path)), &self.base) {
+            (Some(contents), _) => Ok(contents.to_vec()),
+            (None, Some(base)) => base.read(path),
+            (None, None) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not stored in memory", path.display()),
+            )),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+            || self.base.as_ref().is_some_and(|base| base.is_file(path))
+    }
+
+    /// Directories exist if any file stored in memory is inside them.
+    fn exists(&self, path: &Path) -> bool {
+        self.files.keys().any(|file| file.starts_with(path))
+            || self.base.as_ref().is_some_and(|base| base.exists(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries: Vec<PathBuf> = match &self.base {
+            Some(base) => base.read_dir(path).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        for file in self.files.keys() {
+            let relative = match file.strip_prefix(path) {
+                Ok(relative) => relative,
+                Err(_) => continue,
+            };
+            if let Some(name) = relative.iter().next() {
+                let entry = path.join(name);
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Files stored in memory have no metadata, they are read again to check them.
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        match (self.files.contains_key(&normalize(path)), &self.base) {
+            (false, Some(base)) => base.metadata(path),
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+impl fmt::Debug for MemoryFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryFileSystem")
+            .field("files", &self.files.keys().collect::<Vec<_>>())
+            .field("overlay", &self.base.is_some())
+            .finish()
+    }
+}
@@ -8,67 +8,378 @@ mod token;
 #[cfg(test)]
 mod tests;
 
-use std::path::Path;
+pub use token::{Token, TokenKind};
 
-pub(crate) use token::{Token, TokenKind};
+use std::sync::Arc;
 
 use crate::{
     buffer::TokenBuffer,
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
     span::{SourceMap, Span},
+    standard::LexerFeatures,
 };
 
 impl SourceMap {
-    /// Read a file and tokenize it.
-    pub(crate) fn tokenize_file<P: AsRef<Path>>(&self, path: &P) -> std::io::Result<TokenBuffer> {
-        let span = self.read_file(path)?;
-        Ok(self.tokenize_region(span))
+    /// Read a sequence of bytes produced while processing the region `at` and tokenize it.
+    /// Return an error if it contains invalid tokens or if it cannot be stored.
+    pub(crate) fn tokenize_bytes(
+        &self,
+        source: &[u8],
+        at: Span,
+    ) -> std::result::Result<TokenBuffer, Diagnostic> {
+        let span = self.store_at(source, at)?;
+        let mut lines = Lines::new(span);
+        let mut buffer = TokenBuffer::default();
+        let mut line = Vec::new();
+        while lines.next_line(self, &mut line)? {
+            buffer.extend(line.drain(..));
+        }
+        Ok(buffer)
     }
+}
 
-    /// Read a sequence of bytes and tokenize it.
-    pub(crate) fn tokenize_bytes(&self, source: &[u8]) -> TokenBuffer {
-        let span = self.store_bytes(source);
-        self.tokenize_region(span)
+/// Check if writing `spellings` one after the other produces one token for each of them when
+/// tokenized using `features`, so no white-space is needed between them. Header names are not
+/// considered.
+pub(crate) fn tokenized_apart(spellings: &[&[u8]], features: LexerFeatures) -> bool {
+    let text = spellings.concat();
+    let mut lexer = Lexer {
+        rest: &text,
+        offset: 0,
+        features,
+    };
+    for spelling in spellings {
+        match lexer.next_token(false) {
+            Ok((next, token)) if token.span.hi() - token.span.lo() == spelling.len() => {
+                lexer = next
+            }
+            _ => return false,
+        }
     }
+    true
+}
 
-    /// Tokenize a region.
-    ///
-    /// Panic if the region contains invalid tokens.
-    fn tokenize_region(&self, span: Span) -> TokenBuffer {
-        let rest = &*self.get_bytes(span);
+/// The names of the directives that must be found when skipping groups, including the ones that
+/// are only directives in some standards.
+const CONDITIONAL_DIRECTIVES: &[&[u8]] = &[
+    b"if",
+    b"ifdef",
+    b"ifndef",
+    b"elif",
+    b"elifdef",
+    b"elifndef",
+    b"else",
+    b"endif",
+];
+
+/// A lexer that tokenizes a region one line at a time, so the lines inside groups that are
+/// skipped do not need to be tokenized.
+pub(crate) struct Lines {
+    /// The start of the next line.
+    pos: usize,
+    /// The start of the region.
+    start: usize,
+    /// The end of the region.
+    end: usize,
+    /// The tokens of the whole region relative to its start, if it was tokenized already.
+    tokens: Option<Arc<[Token]>>,
+    /// The number of bytes tokenized so far.
+    lexed: usize,
+}
+
+impl Lines {
+    /// Create a lexer for the lines of the region `span`.
+    pub(crate) fn new(span: Span) -> Self {
+        Self {
+            pos: span.lo(),
+            start: span.lo(),
+            end: span.hi(),
+            tokens: None,
+            lexed: 0,
+        }
+    }
+
+    /// Create a lexer for the lines of the region `span` that takes its tokens from `tokens`, the
+    /// result of [`tokenize`](Self::tokenize) for the same contents, instead of tokenizing the
+    /// region again.
+    pub(crate) fn with_tokens(span: Span, tokens: Arc<[Token]>) -> Self {
+        Self {
+            pos: span.lo(),
+            start: span.lo(),
+            end: span.hi(),
+            tokens: Some(tokens),
+            lexed: 0,
+        }
+    }
+
+    /// The number of bytes tokenized so far, which does not include the skipped groups and the
+    /// lines whose tokens were given when creating the lexer.
+    pub(crate) fn lexed(&self) -> usize {
+        self.lexed
+    }
+
+    /// Tokenize the whole region `span`, producing tokens relative to its start so they can be
+    /// reused for any region with the same contents. Return `None` if the region contains
+    /// invalid tokens, even if they would be inside groups that are skipped.
+    pub(crate) fn tokenize(map: &SourceMap, span: Span) -> Option<Vec<Token>> {
+        let mut lines = Self::new(span);
+        let mut tokens = Vec::new();
+        let mut line = Vec::new();
+        while lines.lex_line(map, &mut line).ok()? {
+            tokens.extend(line.drain(..).map(|token| {
+                Token::new(
+                    token.kind,
+                    Span::new(token.span.lo() - span.lo(), token.span.hi() - span.lo()),
+                )
+            }));
+        }
+        Some(tokens)
+    }
+
+    /// Tokenize the next line, including its new-line character, into `line`, replacing its
+    /// contents. Return `false` if there are no lines left or an error if the line contains
+    /// invalid tokens.
+    pub(crate) fn next_line(
+        &mut self,
+        map: &SourceMap,
+        line: &mut Vec<Token>,
+    ) -> std::result::Result<bool, Diagnostic> {
+        if let Some(tokens) = &self.tokens {
+            line.clear();
+            // Skipping a group could stop in the middle of a token if the skipped lines were
+            // tokenized differently, the rest of the region is tokenized again in that case.
+            let pos = self.pos - self.start;
+            let first = tokens.partition_point(|token| token.span.lo() < pos);
+            match tokens.get(first) {
+                Some(token) if token.span.lo() == pos => {
+                    let last = tokens[first..]
+                        .iter()
+                        .position(|token| token.kind == TokenKind::Newline)
+                        .map_or(tokens.len(), |len| first + len + 1);
+                    // The tokens can be shared with other maps, so their identifiers are interned
+                    // again.
+                    line.extend(tokens[first..last].iter().map(|token| {
+                        let mut token = Token::new(
+                            token.kind,
+                            Span::new(self.start + token.span.lo(), self.start + token.span.hi()),
+                        );
+                        map.intern_token(&mut token);
+                        token
+                    }));
+                    self.pos = line.last().map_or(self.end, |token| token.span.hi());
+                    return Ok(true);
+                }
+                _ if self.pos == self.end => return Ok(false),
+                _ => self.tokens = None,
+            }
+        }
+
+        self.lex_line(map, line).map_err(|offset| {
+            let span = Span::new(offset, self.end);
+            let rest = &*map.get_bytes(span);
+            let rest_short = String::from_utf8_lossy(rest.get(..80).unwrap_or(rest));
+            map.diagnostic(
+                Severity::Error,
+                DiagnosticCode::InvalidToken,
+                span,
+                format!("Invalid token \"{}\"", rest_short),
+            )
+        })
+    }
+
+    /// Tokenize the next line into `line` like [`next_line`](Self::next_line), returning the
+    /// offset of the first invalid token if there is one.
+    fn lex_line(
+        &mut self,
+        map: &SourceMap,
+        line: &mut Vec<Token>,
+    ) -> std::result::Result<bool, usize> {
+        line.clear();
+        if self.pos == self.end {
+            return Ok(false);
+        }
+
+        let span = Span::new(self.pos, self.end);
+        let rest = &*map.get_bytes(span);
 
         let mut lexer = Lexer {
             rest,
-            offset: span.lo,
+            offset: span.lo(),
+            features: map.lexer_features(),
         };
 
-        let mut buffer = TokenBuffer::default();
+        // Header names are only recognized inside `#include` directives (section 6.4p4 of C17). So
+        // we need to keep track of where we are inside the current line.
+        let mut state = LineState::Start;
 
         while !lexer.is_empty() {
-            match lexer.next_token() {
-                Ok((rest, token)) => {
-                    buffer.push(token);
-                    lexer = rest;
+            match lexer.next_token(state == LineState::Include) {
+                Ok((next, mut token)) => {
+                    let bytes = &rest[token.span.lo() - span.lo()..token.span.hi() - span.lo()];
+                    state = state.next(token.kind, bytes);
+                    if token.kind == TokenKind::Ident {
+                        token.symbol = Some(map.intern(bytes));
+                    }
+                    line.push(token);
+                    lexer = next;
+                    if token.kind == TokenKind::Newline {
+                        break;
+                    }
                 }
-                Err(Reject) => {
-                    let span = lexer.get_span(lexer.len());
-                    let rest = &*self.get_bytes(span);
-                    let rest_short = String::from_utf8_lossy(rest.get(..80).unwrap_or(rest));
-
-                    if let Some(path) = self.find_file(span) {
-                        panic!(
-                            "Invalid token at {}:{} \"{}\"",
-                            path.display(),
-                            lexer.offset,
-                            rest_short
-                        );
-                    } else {
-                        panic!("Invalid token in input \"{}\"", rest_short);
+                Err(Reject) => return Err(lexer.offset),
+            }
+        }
+
+        self.lexed += lexer.offset - self.pos;
+        self.pos = lexer.offset;
+        Ok(true)
+    }
+
+    /// Skip the lines of a group that is not being processed until the next line that could be
+    /// a conditional inclusion directive. Only comments, string literals, character constants
+    /// and line splices are recognized while skipping, the rest of the line is not tokenized.
+    ///
+    /// The new-line characters of the skipped lines are added to `newlines`.
+    pub(crate) fn skip_group(&mut self, map: &SourceMap, newlines: &mut TokenBuffer) {
+        // Positions are relative to the start of the region while skipping.
+        let start = self.start;
+        let bytes = &*map.get_bytes(Span::new(start, self.end));
+        let end = self.end - start;
+        let mut pos = self.pos - start;
+
+        'lines: while pos < end {
+            if is_conditional_directive(bytes, pos) {
+                break;
+            }
+
+            let mut i = pos;
+            loop {
+                match bytes.get(i..end).unwrap_or_default() {
+                    [] => {
+                        pos = end;
+                        break 'lines;
+                    }
+                    [b'\\', b'\n', ..] => i += 2,
+                    [b'\\', b'\r', b'\n', ..] => i += 3,
+                    [b'/', b'*', ..] => match find_comment_end(bytes, i + 2, end) {
+                        Some(end) => i = end,
+                        // Leave unterminated comments to the lexer, which rejects them.
+                        None => break 'lines,
+                    },
+                    [b'/', b'/', ..] => {
+                        // Line comments end at the new-line character, honoring line splices.
+                        i += 2;
+                        while i < end && bytes[i] != b'\n' {
+                            i += if bytes[i] == b'\\' { 2 } else { 1 };
+                        }
+                        i = i.min(end);
+                    }
+                    [quote @ (b'"' | b'\''), ..] => {
+                        // Unterminated literals are allowed in skipped groups, they end at the
+                        // new-line character.
+                        let quote = *quote;
+                        i += 1;
+                        while i < end && bytes[i] != quote && bytes[i] != b'\n' {
+                            i += if bytes[i] == b'\\' { 2 } else { 1 };
+                        }
+                        i = i.min(end);
+                        if i < end && bytes[i] == quote {
+                            i += 1;
+                        }
+                    }
+                    [b'\r', b'\n', ..] | [b'\n', ..] => {
+                        let len = if bytes[i] == b'\r' { 2 } else { 1 };
+                        newlines.push(Token::new(
+                            TokenKind::Newline,
+                            Span::new(start + i, start + i + len),
+                        ));
+                        pos = i + len;
+                        break;
                     }
+                    _ => i += 1,
                 }
             }
         }
 
-        buffer
+        self.pos = start + pos;
+    }
+}
+
+/// Check if the line starting at `pos` could be a conditional inclusion directive.
+fn is_conditional_directive(bytes: &[u8], pos: usize) -> bool {
+    let mut i = skip_blank(bytes, pos);
+    match bytes.get(i..).unwrap_or_default() {
+        [b'#', ..] => i += 1,
+        [b'%', b':', ..] => i += 2,
+        _ => return false,
+    }
+    i = skip_blank(bytes, i);
+
+    let start = i;
+    while bytes
+        .get(i)
+        .is_some_and(|byte| byte.is_ascii_alphanumeric() || *byte == b'_')
+    {
+        i += 1;
+    }
+    // A line splice or a universal character name could be part of the name, let the lexer
+    // decide in that case.
+    bytes.get(i) == Some(&b'\\') || CONDITIONAL_DIRECTIVES.contains(&&bytes[start..i])
+}
+
+/// Skip the horizontal white-space, comments and line splices starting at `pos`.
+fn skip_blank(bytes: &[u8], mut pos: usize) -> usize {
+    loop {
+        match bytes.get(pos..).unwrap_or_default() {
+            [b' ' | b'\t' | b'\x0b' | b'\x0c', ..] => pos += 1,
+            [b'\\', b'\n', ..] => pos += 2,
+            [b'\\', b'\r', b'\n', ..] => pos += 3,
+            [b'/', b'*', ..] => match find_comment_end(bytes, pos + 2, bytes.len()) {
+                Some(end) => pos = end,
+                None => return pos,
+            },
+            _ => return pos,
+        }
+    }
+}
+
+/// Find the end of a block comment whose contents start at `pos`, returning the position after
+/// the `*/`.
+fn find_comment_end(bytes: &[u8], pos: usize, end: usize) -> Option<usize> {
+    bytes[pos.min(end)..end]
+        .windows(2)
+        .position(|window| window == b"*/")
+        .map(|offset| pos + offset + 2)
+}
+
+/// The position of the lexer inside the current line, used to decide if a `header-name` can be
+/// produced.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LineState {
+    /// Only white-space has been found since the last new-line character.
+    Start,
+    /// A `#` has been found at the start of the line.
+    Hash,
+    /// The line starts with `#include` or one of its variants.
+    Include,
+    /// Anything else.
+    Other,
+}
+
+impl LineState {
+    /// Compute the state after producing a token of kind `kind` whose contents are `bytes`.
+    fn next(self, kind: TokenKind, bytes: &[u8]) -> Self {
+        match (self, kind) {
+            (_, TokenKind::Newline) => Self::Start,
+            (state, TokenKind::Space) => state,
+            (Self::Start, TokenKind::Punct) if matches!(bytes, b"#" | b"%:") => Self::Hash,
+            (Self::Hash, TokenKind::Ident)
+                if matches!(bytes, b"include" | b"include_next" | b"import") =>
+            {
+                Self::Include
+            }
+            _ => Self::Other,
+        }
     }
 }
 
@@ -90,16 +401,31 @@ struct Lexer<'a> {
     rest: &'a [u8],
     /// The start of `rest`, relative to the start of the region being tokenized.
     offset: usize,
+    /// The parts of the grammar that depend on the standard.
+    features: LexerFeatures,
 }
 
 impl<'a> Lexer<'a> {
-    fn next_token(self) -> Result<'a, Token> {
-        let (rest, token) = if let Ok((rest, header)) = header(self) {
+    /// Produce the next token. `header-name`s are only produced if `allow_header` is `true`.
+    fn next_token(self, allow_header: bool) -> Result<'a, Token> {
+        let (rest, token) = if let Some(Ok((rest, header))) = allow_header.then(|| header(self)) {
             (rest, header)
+        } else if let Ok((rest, space)) = space(self) {
+            (rest, space)
+        } else if let Ok((rest, newline)) = newline(self) {
+            (rest, newline)
+        } else if let Ok((rest, char)) = char_constant(self) {
+            (rest, char)
+        } else if let Ok((rest, str)) = string_literal(self) {
+            (rest, str)
         } else if let Ok((rest, ident)) = ident(self) {
             (rest, ident)
         } else if let Ok((rest, number)) = number(self) {
             (rest, number)
+        } else if let Ok((rest, punct)) = punct(self) {
+            (rest, punct)
+        } else if let Ok((rest, any)) = any(self) {
+            (rest, any)
         } else {
             return Err(Reject);
         };
@@ -115,15 +441,13 @@ impl<'a> Lexer<'a> {
         Self {
             offset: self.offset + head.len(),
             rest,
+            features: self.features,
         }
     }
 
     /// Return a new span that starts at the current offset and has `len` length.
     fn get_span(&self, len: usize) -> Span {
-        Span {
-            lo: self.offset,
-            hi: self.offset + len,
-        }
+        Span::new(self.offset, self.offset + len)
     }
 
     /// Get the length of the remaining text region.
@@ -216,10 +540,7 @@ fn h_header(input: Lexer<'_>) -> Result<'_, Token> {
                 let len = i + 2;
                 return Ok((
                     input.advance(len),
-                    Token {
-                        kind: TokenKind::Header,
-                        span: input.get_span(len),
-                    },
+                    Token::new(TokenKind::Header, input.get_span(len)),
                 ));
             }
             // any other character is a valid `h-char`
@@ -253,10 +574,7 @@ fn q_header(input: Lexer<'_>) -> Result<'_, Token> {
                 let len = i + 2;
                 return Ok((
                     input.advance(len),
-                    Token {
-                        kind: TokenKind::Header,
-                        span: input.get_span(len),
-                    },
+                    Token::new(TokenKind::Header, input.get_span(len)),
                 ));
             }
             // any other character is a valid `q-char`
@@ -270,28 +588,23 @@ fn q_header(input: Lexer<'_>) -> Result<'_, Token> {
 
 /// Produce an `identifier` as defined in section 6.4.2 of C17.
 fn ident(input: Lexer<'_>) -> Result<'_, Token> {
-    let mut chars = input.byte_indices();
     // The first char of an `identifier` must be an `identifier-nondigit`.
-    must_match!(chars.next(), Some((_, c)) if is_ident_nondigit(c));
-
-    // This is the length of the `identifier`.
-    let mut len = input.len();
-    for (i, ch) in chars {
-        // A valid `identifier` can be followed by either an `identifier-nondigit` or a `digit`.
-        // Otherwise, this character does not belong to the `identifier` and its position is the
-        // same as the length of the `identifier`.
-        if !(is_ident_nondigit(ch) || ch.is_ascii_digit()) {
-            len = i;
-            break;
+    let mut len = ident_nondigit_len(input, 0).ok_or(Reject)?;
+
+    // A valid `identifier` can be followed by either an `identifier-nondigit` or a `digit`.
+    loop {
+        match input.rest.get(len) {
+            Some(byte) if byte.is_ascii_digit() => len += 1,
+            _ => match ident_nondigit_len(input, len) {
+                Some(nondigit) => len += nondigit,
+                None => break,
+            },
         }
     }
 
     Ok((
         input.advance(len),
-        Token {
-            kind: TokenKind::Ident,
-            span: input.get_span(len),
-        },
+        Token::new(TokenKind::Ident, input.get_span(len)),
     ))
 }
 
@@ -300,46 +613,232 @@ fn is_ident_nondigit(byte: u8) -> bool {
     byte == b'_' || byte.is_ascii_alphabetic()
 }
 
-/// Produce a `pp-number` as defined in section 6.4.8 of C17.
+/// Get the length of the `identifier-nondigit` at position `pos` of the remaining text of
+/// `input`, if there is one. Universal character names (section 6.4.3 of C17) and `$` are only
+/// recognized if the lexer supports them.
+fn ident_nondigit_len(input: Lexer<'_>, pos: usize) -> Option<usize> {
+    let rest = input.rest.get(pos..)?;
+    let digits = match rest {
+        [byte, ..] if is_ident_nondigit(*byte) => return Some(1),
+        [b'$', ..] if input.features.dollar_idents => return Some(1),
+        [b'\\', b'u', ..] if input.features.ucns => 4,
+        [b'\\', b'U', ..] if input.features.ucns => 8,
+        _ => return None,
+    };
+    let hex = rest.get(2..2 + digits)?;
+    hex.iter().all(u8::is_ascii_hexdigit).then_some(2 + digits)
+}
+
+/// Produce a `pp-number` as defined in section 6.4.8 of C17, including the digit separators
+/// added by C23 if the lexer supports them.
 fn number(input: Lexer<'_>) -> Result<'_, Token> {
+    let rest = input.rest;
     // A `pp-number` optionally starts with `.`
-    let (rest, prefix_len) = input
-        .parse_byte(b'.')
-        .map(|rest| (rest, 1))
-        .unwrap_or((input, 0));
+    let mut len = usize::from(rest.first() == Some(&b'.'));
 
-    let mut bytes = rest.byte_indices().peekable();
     // The next character must be a `digit`.
-    must_match!(bytes.next(), Some((_, c)) if c.is_ascii_digit());
+    must_match!(rest.get(len), Some(byte) if byte.is_ascii_digit());
+    len += 1;
+
+    // A valid `pp-number` can be followed by a `.`, a `digit`, an `identifier-nondigit`, or it
+    // can also be followed by `e`, `E`, `p` or `P` immediately followed by a `sign`.
+    loop {
+        match rest[len..] {
+            // We do exponents first because the exponents are `identifier-nondigit`s.
+            [b'e' | b'E' | b'p' | b'P', b'+' | b'-', ..] => len += 2,
+            [byte, ..] if byte == b'.' || byte.is_ascii_digit() => len += 1,
+            [b'\'', byte, ..]
+                if input.features.digit_separators
+                    && (byte.is_ascii_digit() || ident_nondigit_len(input, len + 1).is_some()) =>
+            {
+                len += 1
+            }
+            _ => match ident_nondigit_len(input, len) {
+                Some(nondigit) => len += nondigit,
+                // Otherwise, this character does not belong to the `number`.
+                None => break,
+            },
+        }
+    }
+
+    Ok((
+        input.advance(len),
+        Token::new(TokenKind::Number, input.get_span(len)),
+    ))
+}
+
+/// Produce a sequence of white-space characters and comments. Comments are replaced by one space
+/// character in translation phase 3 (section 5.1.1.2 of C17) so we consider them white-space.
+///
+/// Line splices (a `\` immediately followed by a new-line character) are also consumed here as they
+/// are deleted in translation phase 2.
+fn space(input: Lexer<'_>) -> Result<'_, Token> {
+    let mut rest = input;
+
+    loop {
+        if let Ok(next) = rest.parse_byte(is_space) {
+            rest = next;
+        } else if let Ok(next) = rest.parse_bytes(b"/*") {
+            // A block comment must be terminated, otherwise the input is invalid.
+            let end = next
+                .rest
+                .windows(2)
+                .position(|window| window == b"*/")
+                .ok_or(Reject)?;
+            rest = next.advance(end + 2);
+        } else if let Ok(next) = rest.parse_bytes(b"//") {
+            // A line comment goes until the next new-line character that is not part of a line
+            // splice.
+            let mut bytes = next.byte_indices().peekable();
+            let mut end = next.len();
+            while let Some((i, byte)) = bytes.next() {
+                match byte {
+                    b'\\' if matches!(bytes.peek(), Some((_, b'\n'))) => {
+                        bytes.next();
+                    }
+                    b'\n' => {
+                        end = i;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            // Do not include the `\r` of a `\r\n` sequence.
+            if end > 0 && next.rest[end - 1] == b'\r' {
+                end -= 1;
+            }
+            rest = next.advance(end);
+        } else if let Ok(next) = splice(rest) {
+            rest = next;
+        } else {
+            break;
+        }
+    }
+
+    let len = input.len() - rest.len();
+    must_match!(len, 1..);
 
-    // This is the length of the `pp-number`.
-    let mut len = input.len();
+    Ok((rest, Token::new(TokenKind::Space, input.get_span(len))))
+}
+
+/// Check if `byte` is a white-space character other than a new-line character.
+fn is_space(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\x0b' | b'\x0c')
+}
+
+/// Consume a line splice: a `\` immediately followed by a new-line character.
+fn splice(input: Lexer<'_>) -> std::result::Result<Lexer<'_>, Reject> {
+    let rest = input.parse_byte(b'\\')?;
+    rest.parse_bytes(b"\n")
+        .or_else(|_| rest.parse_bytes(b"\r\n"))
+}
+
+/// Produce a single new-line character. A `\r\n` sequence is considered a single new-line
+/// character.
+fn newline(input: Lexer<'_>) -> Result<'_, Token> {
+    let rest = input
+        .parse_bytes(b"\n")
+        .or_else(|_| input.parse_bytes(b"\r\n"))?;
+    let len = input.len() - rest.len();
+
+    Ok((rest, Token::new(TokenKind::Newline, input.get_span(len))))
+}
+
+/// Produce a `character-constant` as defined in section 6.4.4.4 of C17.
+fn char_constant(input: Lexer<'_>) -> Result<'_, Token> {
+    // The encoding prefix is optional.
+    let rest = input
+        .parse_byte(|byte| matches!(byte, b'L' | b'u' | b'U'))
+        .unwrap_or(input);
+
+    quoted(input, rest, b'\'', TokenKind::Char)
+}
+
+/// Produce a `string-literal` as defined in section 6.4.5 of C17.
+fn string_literal(input: Lexer<'_>) -> Result<'_, Token> {
+    // The encoding prefix is optional.
+    let rest = input
+        .parse_bytes(b"u8")
+        .or_else(|_| input.parse_byte(|byte| matches!(byte, b'L' | b'u' | b'U')))
+        .unwrap_or(input);
+
+    quoted(input, rest, b'"', TokenKind::Str)
+}
+
+/// Produce a token of kind `kind` starting at `input` whose contents after the encoding prefix,
+/// which begin at `rest`, are a sequence of characters or escape sequences surrounded by
+/// `delimiter`.
+fn quoted<'a>(
+    input: Lexer<'a>,
+    rest: Lexer<'a>,
+    delimiter: u8,
+    kind: TokenKind,
+) -> Result<'a, Token> {
+    let rest = rest.parse_byte(delimiter)?;
+
+    let mut bytes = rest.byte_indices();
 
     while let Some((i, byte)) = bytes.next() {
-        // A valid `pp-number` can be followed by a `.`, a `digit`, an `identifier-nondigit`, or it
-        // can also be followed by `e`, `E`, `p` or `P` immediately followed by a `sign`.
         match byte {
-            // We do exponents first because the exponents are `identifier-nondigit`s.
-            b'e' | b'E' | b'p' | b'P' if matches!(bytes.peek(), Some((_, b'+' | b'-'))) => {
-                bytes.next().unwrap();
-                continue;
+            // new-line characters cannot appear inside the sequence.
+            b'\n' => break,
+            // the character after a `\` is part of an escape sequence, even if it is the
+            // delimiter.
+            b'\\' => {
+                bytes.next();
             }
-            byte if byte == b'.' || byte.is_ascii_digit() || is_ident_nondigit(byte) => {
-                continue;
+            // if we find the delimiter then we are done
+            byte if byte == delimiter => {
+                let len = input.len() - rest.len() + i + 1;
+                return Ok((input.advance(len), Token::new(kind, input.get_span(len))));
             }
+            // any other character is valid
             _ => {}
         }
-        // Otherwise, this character does not belong to the `number` and its position is the same
-        // as the length of the `number`.
-        len = i + prefix_len;
-        break;
     }
 
+    Err(Reject)
+}
+
+/// All the `punctuator`s defined in section 6.4.6 of C17, sorted so that longer punctuators are
+/// tried first.
+const PUNCTUATORS: &[&[u8]] = &[
+    b"%:%:", b"...", b"<<=", b">>=", b"->", b"++", b"--", b"<<", b">>", b"<=", b">=", b"==", b"!=",
+    b"&&", b"||", b"*=", b"/=", b"%=", b"+=", b"-=", b"&=", b"^=", b"|=", b"##", b"<:", b":>",
+    b"<%", b"%>", b"%:", b"[", b"]", b"(", b")", b"{", b"}", b".", b"&", b"*", b"+", b"-", b"~",
+    b"!", b"/", b"%", b"<", b">", b"^", b"|", b"?", b":", b";", b"=", b",", b"#",
+];
+
+/// Produce a `punctuator` as defined in section 6.4.6 of C17.
+fn punct(input: Lexer<'_>) -> Result<'_, Token> {
+    for punct in PUNCTUATORS {
+        if let Ok(rest) = input.parse_bytes(punct) {
+            return Ok((
+                rest,
+                Token::new(TokenKind::Punct, input.get_span(punct.len())),
+            ));
+        }
+    }
+
+    Err(Reject)
+}
+
+/// Produce a single non-white-space character that cannot be part of any other preprocessing
+/// token as described in section 6.4 of C17.
+fn any(input: Lexer<'_>) -> Result<'_, Token> {
+    let mut bytes = input.bytes();
+    let len = match bytes.next() {
+        Some(byte) if is_space(byte) || byte == b'\n' => return Err(Reject),
+        // Consume the whole UTF-8 encoded character so we do not split it.
+        Some(byte) => match byte.leading_ones() {
+            2..=4 => (byte.leading_ones() as usize).min(input.len()),
+            _ => 1,
+        },
+        None => return Err(Reject),
+    };
+
     Ok((
         input.advance(len),
-        Token {
-            kind: TokenKind::Number,
-            span: input.get_span(len),
-        },
+        Token::new(TokenKind::Any, input.get_span(len)),
     ))
 }
@@ -1,6 +1,6 @@
-use crate::{buffer::TokenBuffer, lexer::TokenKind, span::Span};
+use crate::{lexer::TokenKind, span::Span, standard::LexerFeatures};
 
-use super::{Lexer, Reject, Token};
+use super::{Lexer, Token};
 
 fn single_token(
     bytes: &[u8],
@@ -9,19 +9,14 @@ fn single_token(
     f(Lexer {
         rest: bytes,
         offset: 0,
+        features: LexerFeatures::default(),
     })
 }
 
 #[track_caller]
 fn tokenize_one(bytes: &[u8], kind: TokenKind, f: impl Fn(Lexer<'_>) -> super::Result<'_, Token>) {
     let (rest, token) = single_token(bytes, f).unwrap();
-    let expected_token = Token {
-        kind,
-        span: Span {
-            lo: 0,
-            hi: bytes.len(),
-        },
-    };
+    let expected_token = Token::new(kind, Span::new(0, bytes.len()));
     println!("Parsed token was: {:?}", token);
     assert!(
         rest.is_empty(),
@@ -117,6 +112,17 @@ fn ident_mixed_case() {
     tokenize_one(b"sneaky_Camel", TokenKind::Ident, super::ident);
 }
 
+#[test]
+fn ident_universal_character_names() {
+    tokenize_one(b"caf\\u00e9_\\U0001F600", TokenKind::Ident, super::ident);
+}
+
+#[test]
+#[should_panic]
+fn ident_short_universal_character_name() {
+    tokenize_one(b"a\\u00e", TokenKind::Ident, super::ident);
+}
+
 #[test]
 fn number_digits() {
     tokenize_one(b"42", TokenKind::Number, super::number);
@@ -164,8 +170,88 @@ fn number_empty() {
     tokenize_one(b"", TokenKind::Number, super::number);
 }
 
+#[test]
+#[should_panic]
+fn number_digit_separators_before_c23() {
+    tokenize_one(b"1'000", TokenKind::Number, super::number);
+}
+
+#[test]
+fn number_digit_separators() {
+    let features = LexerFeatures {
+        digit_separators: true,
+        ucns: true,
+        dollar_idents: false,
+        trigraphs: false,
+    };
+    let (rest, token) = super::number(Lexer {
+        rest: b"0x1'FF'e+1'",
+        offset: 0,
+        features,
+    })
+    .unwrap();
+    assert_eq!(rest.rest, b"'");
+    assert_eq!(Token::new(TokenKind::Number, Span::new(0, 10)), token);
+}
+
 #[test]
 #[should_panic]
 fn number_ident_nondigit() {
     tokenize_one(b"e", TokenKind::Number, super::number);
 }
+
+#[test]
+fn space_with_comments() {
+    tokenize_one(b" \t/* hello */ // world", TokenKind::Space, super::space);
+}
+
+#[test]
+fn space_with_splice() {
+    tokenize_one(b" \\\n ", TokenKind::Space, super::space);
+}
+
+#[test]
+#[should_panic]
+fn space_unterminated_comment() {
+    tokenize_one(b"/* hello", TokenKind::Space, super::space);
+}
+
+#[test]
+fn newline_crlf() {
+    tokenize_one(b"\r\n", TokenKind::Newline, super::newline);
+}
+
+#[test]
+fn char_escaped_quote() {
+    tokenize_one(b"'\\''", TokenKind::Char, super::char_constant);
+}
+
+#[test]
+fn char_with_prefix() {
+    tokenize_one(b"L'a'", TokenKind::Char, super::char_constant);
+}
+
+#[test]
+fn str_with_prefix() {
+    tokenize_one(
+        b"u8\"hello \\\"world\\\"\"",
+        TokenKind::Str,
+        super::string_literal,
+    );
+}
+
+#[test]
+#[should_panic]
+fn str_unterminated() {
+    tokenize_one(b"\"hello\n\"", TokenKind::Str, super::string_literal);
+}
+
+#[test]
+fn punct_longest() {
+    tokenize_one(b"%:%:", TokenKind::Punct, super::punct);
+}
+
+#[test]
+fn any_utf8() {
+    tokenize_one("λ".as_bytes(), TokenKind::Any, super::any);
+}
@@ -1,10 +1,65 @@
-use crate::span::Span;
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use crate::{span::Span, symbol::Symbol};
 
 /// A preprocessing token, as defined in the section 6.4 of C17.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub(crate) struct Token {
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Token {
     pub(crate) kind: TokenKind,
     pub(crate) span: Span,
+    /// The symbol of the spelling of an identifier, given when it is tokenized. Tokens are
+    /// compared without it as it only depends on the spelling.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) symbol: Option<Symbol>,
+}
+
+impl Token {
+    /// Create a token without a symbol.
+    pub(crate) fn new(kind: TokenKind, span: Span) -> Self {
+        Self {
+            kind,
+            span,
+            symbol: None,
+        }
+    }
+
+    /// The kind of the token.
+    pub fn kind(&self) -> TokenKind {
+        self.kind
+    }
+
+    /// The region of the token, whose contents are its spelling.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.span == other.span
+    }
+}
+
+impl Eq for Token {}
+
+impl Hash for Token {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind.hash(state);
+        self.span.hash(state);
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("kind", &self.kind)
+            .field("span", &self.span)
+            .finish()
+    }
 }
 
 /// The differen kinds of preprocessing tokens. The description for each kind can be found at the
@@ -17,24 +72,25 @@ pub(crate) struct Token {
 /// be found in the syntax definition in 6.10) and the presence of white-space characters changes
 /// the semantics of some preprocessing directives (This can be infered from section 6.10.3, as an
 /// example, `#define FOO()` is a function-like macro and `#define FOO ()` is an object-like macro).
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub(crate) enum TokenKind {
-    // A `header-name`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenKind {
+    /// A `header-name`.
     Header,
-    // An `identifier`.
+    /// An `identifier`.
     Ident,
-    // A `pp-number`.
+    /// A `pp-number`.
     Number,
-    // A `character-constant`.
+    /// A `character-constant`.
     Char,
-    // A `string-literal`.
+    /// A `string-literal`.
     Str,
-    // A `punctuator`.
+    /// A `punctuator`.
     Punct,
-    // Any non-white-space character that cannot be one of the above.
+    /// Any non-white-space character that cannot be one of the above.
     Any,
-    // A sequence of white-space characters possibly including comments.
+    /// A sequence of white-space characters possibly including comments.
     Space,
-    // A single new-line character.
+    /// A single new-line character.
     Newline,
 }
@@ -5,21 +5,86 @@
 //! whose most recent free draft can be found
 //! [here](https://web.archive.org/web/20181230041359if_/http://www.open-std.org/jtc1/sc22/wg14/www/abq/c17_updated_proposed_fdis.pdf).
 
+mod amalgamation;
 mod buffer;
+mod c_token;
+#[cfg(feature = "cc")]
+mod cc_build;
+mod charset;
+mod command_line;
+#[cfg(feature = "compile-commands")]
+mod compile_commands;
+mod diagnostic;
+mod error;
+mod expr;
+mod extension;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod fs;
 mod lexer;
+mod macros;
+mod predefined;
+mod preprocessor;
+mod presence;
+#[cfg(feature = "python")]
+mod python;
 mod span;
+mod standard;
+mod symbol;
+mod target;
+mod time;
+mod token_stream;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-use std::{io, path::Path};
+use std::path::Path;
 
-use span::SourceMap;
+pub use amalgamation::Amalgamation;
+pub use buffer::{Checkpoint, Cursor, LogicalLine, LogicalLines, TokenBuffer, TokenSlice};
+pub use c_token::{
+    CToken, CTokenKind, CTokens, FloatingConstant, FloatingSize, IntegerConstant, IntegerSize,
+    Keyword,
+};
+#[cfg(feature = "cc")]
+pub use cc_build::CcBuild;
+pub use charset::InputCharset;
+pub use command_line::CommandLine;
+#[cfg(feature = "compile-commands")]
+pub use compile_commands::{CompilationDatabase, CompileCommand, ParseCompilationDatabaseError};
+pub use diagnostic::{
+    Diagnostic, DiagnosticCode, DiagnosticGroup, DiagnosticRenderer, Label, Level, Severity,
+    Suggestion, Warning, WarningGroup,
+};
+pub use error::Error;
+pub use expr::IntmaxWidth;
+pub use extension::Extension;
+pub use fs::{FileMetadata, FileSystem, MemoryFileSystem, RealFileSystem};
+pub use lexer::{Token, TokenKind};
+pub use macros::MacroDefinition;
+pub use predefined::FeatureMacro;
+pub use preprocessor::{
+    Batch, BatchResults, Branch, CacheKey, Comments, ConditionalSection, ContentHash, Depfile,
+    Emitter, Event, Events, HeaderCache, IncludeEdge, IncludeGraph, IncludeKind, IncludeRequest,
+    IncludeResolution, MacroConstant, MacroDump, MacroExpansion, MappedToken, OutputProfile,
+    ParseSnapshotError, PositionMap, Preprocessed, Preprocessor, Session, Snapshot, Statistics,
+    TimeTrace, TokenInfo, TokenTable, TraceEvent,
+};
+pub use presence::{presence_conditions, Condition, Region};
+pub use span::{ColumnUnit, SourceMapSnapshot, SourceSnippet, Span, SpanLocation, StoredRegion};
+pub use standard::Standard;
+pub use target::Target;
+pub use token_stream::{Delimiter, Group, Ident, Literal, Punct, Spacing, TokenStream, TokenTree};
 
-pub fn preprocess(source: &[u8]) {
-    let map = SourceMap::default();
-    map.tokenize_bytes(source);
+/// Preprocess `source` with the default configuration, which is the same as
+/// `Preprocessor::new().preprocess(source)`. Use a [`Preprocessor`] to choose the standard, the
+/// include directories, the predefined macros, the warnings, the limits, the hooks and the
+/// output.
+pub fn preprocess(source: &[u8]) -> Result<Preprocessed, Error> {
+    Preprocessor::new().preprocess(source)
 }
 
-pub fn preprocess_file<P: AsRef<Path>>(path: &P) -> io::Result<()> {
-    let map = SourceMap::default();
-    map.tokenize_file(path)?;
-    Ok(())
+/// Preprocess the file at `path` with the default configuration, which is the same as
+/// `Preprocessor::new().preprocess_file(path)`.
+pub fn preprocess_file<P: AsRef<Path>>(path: &P) -> Result<Preprocessed, Error> {
+    Preprocessor::new().preprocess_file(path)
 }
@@ -0,0 +1,726 @@
+//! Macro replacement. This follows the algorithm written by Dave Prosser for the ANSI C committee,
+//! which uses "hide sets" to decide which macro names cannot be replaced again during rescanning
+//! (section 6.10.3.4 of C17).
+use std::{collections::BTreeSet, fmt::Write, path::PathBuf, rc::Rc};
+
+use super::{va_opt_group, Macro, MacroKind, MacroTable};
+use crate::{
+    buffer::TokenSlice,
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    lexer::{Token, TokenKind},
+    span::{SourceMap, Span},
+    symbol::Symbol,
+};
+
+/// The symbols of the names of the macros that cannot be replaced when found in a token.
+#[derive(Clone, Default)]
+struct HideSet(Rc<BTreeSet<Symbol>>);
+
+impl HideSet {
+    fn contains(&self, name: Symbol) -> bool {
+        self.0.contains(&name)
+    }
+
+    /// Return a new set with `name` added to it.
+    fn with(&self, name: Symbol) -> Self {
+        let mut set = (*self.0).clone();
+        set.insert(name);
+        Self(Rc::new(set))
+    }
+
+    fn union(&self, other: &Self) -> Self {
+        Self(Rc::new(self.0.union(&other.0).cloned().collect()))
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        Self(Rc::new(self.0.intersection(&other.0).cloned().collect()))
+    }
+}
+
+/// A token produced by macro replacement together with the invocation whose replacement list
+/// produced it, if any.
+pub(crate) type ExpandedToken = (Token, Option<Rc<Expansion>>);
+
+/// A macro invocation whose replacement list produced some tokens.
+pub(crate) struct Expansion {
+    /// The name of the macro.
+    pub(crate) name: String,
+    /// The region of the name of the macro in the invocation.
+    pub(crate) span: Span,
+    /// The region of the name of the macro inside its definition.
+    pub(crate) definition: Span,
+    /// The invocation whose replacement list produced the name of the macro, if any.
+    pub(crate) parent: Option<Rc<Expansion>>,
+}
+
+impl Expansion {
+    /// Add a note to `diagnostic` for each invocation in the chain of invocations that produced
+    /// this one, starting with the innermost one, with the position of the invocation if it is
+    /// in a file.
+    pub(crate) fn backtrace(&self, map: &SourceMap, mut diagnostic: Diagnostic) -> Diagnostic {
+        let chain = std::iter::successors(Some(self), |expansion| expansion.parent.as_deref());
+        for expansion in chain {
+            let mut note = format!("In expansion of macro \"{}\"", expansion.name);
+            if let Some((file, line)) = map.find_presumed(expansion.span) {
+                write!(note, " at {}:{}", file.display(), line).unwrap();
+            }
+            diagnostic = diagnostic.with_note(note);
+        }
+        diagnostic
+    }
+}
+
+/// Add the notes of [`Expansion::backtrace`] to `diagnostic` if it was found inside the
+/// replacement list of `expansion`.
+fn backtrace(map: &SourceMap, expansion: Option<&Expansion>, diagnostic: Diagnostic) -> Diagnostic {
+    match expansion {
+        Some(expansion) => expansion.backtrace(map, diagnostic),
+        None => diagnostic,
+    }
+}
+
+/// A token together with its hide set.
+#[derive(Clone)]
+struct PpToken {
+    token: Token,
+    hide_set: HideSet,
+    /// The invocation whose replacement list produced this token, if any.
+    expansion: Option<Rc<Expansion>>,
+}
+
+impl PpToken {
+    fn new(token: Token) -> Self {
+        Self {
+            token,
+            hide_set: HideSet::default(),
+            expansion: None,
+        }
+    }
+}
+
+impl MacroTable {
+    /// Replace all the macro invocations inside `tokens`. Return an error if the replacement
+    /// cannot continue. The invocations that are not valid are reported using
+    /// [`take_errors`](Self::take_errors) and kept as they are, as GCC does.
+    pub(crate) fn expand(
+        &self,
+        map: &SourceMap,
+        tokens: &[Token],
+    ) -> Result<Vec<Token>, Diagnostic> {
+        let tokens = tokens.iter().copied().map(PpToken::new).collect();
+        let tokens = self.expand_tokens(map, tokens, false)?;
+        Ok(tokens.into_iter().map(|token| token.token).collect())
+    }
+
+    /// Replace all the macro invocations inside `tokens` like [`expand`](Self::expand), returning
+    /// each token together with the invocation whose replacement list produced it, if any.
+    pub(crate) fn expand_traced(
+        &self,
+        map: &SourceMap,
+        tokens: &[Token],
+    ) -> Result<Vec<ExpandedToken>, Diagnostic> {
+        let tokens = tokens.iter().copied().map(PpToken::new).collect();
+        let tokens = self.expand_tokens(map, tokens, false)?;
+        Ok(tokens
+            .into_iter()
+            .map(|token| (token.token, token.expansion))
+            .collect())
+    }
+
+    /// Replace all the macro invocations inside the controlling expression of a `#if` or `#elif`
+    /// directive. The operands of the `defined` operators produced by the replacement are not
+    /// replaced.
+    ///
+    /// Return each token together with the invocation whose replacement list produced it, if
+    /// any, or an error if the replacement cannot continue.
+    pub(crate) fn expand_condition(
+        &self,
+        map: &SourceMap,
+        tokens: &[Token],
+    ) -> Result<Vec<ExpandedToken>, Diagnostic> {
+        let tokens = tokens.iter().copied().map(PpToken::new).collect();
+        let tokens = self.expand_tokens(map, tokens, true)?;
+        Ok(tokens
+            .into_iter()
+            .map(|token| (token.token, token.expansion))
+            .collect())
+    }
+
+    /// Replace all the macro invocations inside `tokens`. If `condition` is `true`, the operands
+    /// of the `defined` operators are not replaced.
+    fn expand_tokens(
+        &self,
+        map: &SourceMap,
+        tokens: Vec<PpToken>,
+        condition: bool,
+    ) -> Result<Vec<PpToken>, Diagnostic> {
+        // The remaining input is stored in reverse order, this way the result of a replacement can
+        // be pushed back cheaply to be rescanned.
+        let mut input: Vec<PpToken> = tokens.into_iter().rev().collect();
+        let mut output = Vec::new();
+
+        while let Some(current) = input.pop() {
+            if current.token.kind != TokenKind::Ident {
+                output.push(current);
+                continue;
+            }
+
+            let symbol = map.symbol(current.token);
+
+            if condition && symbol == Symbol::DEFINED {
+                // Keep the operand, which is either an identifier or an identifier enclosed in
+                // parentheses.
+                output.push(current);
+                let mut parenthesized = false;
+                while let Some(next) = input.pop() {
+                    let is_space = matches!(next.token.kind, TokenKind::Space | TokenKind::Newline);
+                    let is_lparen = !parenthesized && map.is_punct(next.token, b"(");
+                    let is_ident = next.token.kind == TokenKind::Ident;
+                    parenthesized |= is_lparen;
+                    if !(is_space || is_lparen || is_ident) {
+                        input.push(next);
+                        break;
+                    }
+                    output.push(next);
+                    if is_ident {
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            // `__COUNTER__` is replaced by its next value unless it was defined as a macro.
+            if let Some(counter) = self
+                .counter
+                .as_ref()
+                .filter(|_| symbol == Symbol::COUNTER && self.get(symbol).is_none())
+            {
+                let value = counter.get();
+                counter.set(value + 1);
+                self.expansions.set(self.expansions.get() + 1);
+                let span = map.store_at(value.to_string().as_bytes(), current.token.span)?;
+                output.push(PpToken {
+                    token: Token::new(TokenKind::Number, span),
+                    ..current
+                });
+                continue;
+            }
+
+            // `__FILE__` and `__LINE__` are replaced by the presumed name of the file and number of
+            // the line of the invocation they come from (section 6.10.8.1 of C17), unless they were
+            // defined as macros.
+            if matches!(symbol, Symbol::FILE | Symbol::LINE) && self.get(symbol).is_none() {
+                let (kind, replacement) = self.position(map, symbol, &current);
+                self.expansions.set(self.expansions.get() + 1);
+                let span = map.store_at(replacement.as_bytes(), current.token.span)?;
+                output.push(PpToken {
+                    token: Token::new(kind, span),
+                    ..current
+                });
+                continue;
+            }
+
+            let definition = match self.get(symbol) {
+                Some(definition) if !current.hide_set.contains(symbol) => definition,
+                _ => {
+                    output.push(current);
+                    continue;
+                }
+            };
+
+            let expansion = Rc::new(Expansion {
+                name: map.spelling(current.token),
+                span: current.token.span,
+                definition: definition.span,
+                parent: current.expansion.clone(),
+            });
+            self.expansions.set(self.expansions.get() + 1);
+
+            let replaced = match &definition.kind {
+                MacroKind::Object => {
+                    definition.used.set(true);
+                    let hide_set = current.hide_set.with(symbol);
+                    self.substitute(map, definition, &[], &hide_set, &expansion, condition)?
+                }
+                MacroKind::Function { params, variadic } => {
+                    // The name of a function-like macro is only replaced if it is followed by a
+                    // `(` (section 6.10.3p10 of C17).
+                    let next = input.iter().rev().find(|token| {
+                        !matches!(token.token.kind, TokenKind::Space | TokenKind::Newline)
+                    });
+                    if !next.is_some_and(|token| map.is_punct(token.token, b"(")) {
+                        output.push(current);
+                        continue;
+                    }
+
+                    definition.used.set(true);
+                    let collected = collect_args(
+                        map,
+                        &mut input,
+                        params.len(),
+                        *variadic,
+                        current.token,
+                        definition,
+                    );
+                    // The name of an invocation that is not valid is kept and its arguments are
+                    // discarded, as GCC does.
+                    let (args, rparen, omitted) = match collected {
+                        Ok(collected) => collected,
+                        Err(diagnostic) => {
+                            let diagnostic =
+                                backtrace(map, current.expansion.as_deref(), diagnostic);
+                            self.errors.borrow_mut().push(diagnostic);
+                            output.push(current);
+                            continue;
+                        }
+                    };
+                    if omitted {
+                        self.omitted_variadic.borrow_mut().push(current.token.span);
+                    }
+                    if current.expansion.is_none() && rparen.expansion.is_none() {
+                        self.record_invocation(map, current.token.span, rparen.token.span);
+                    }
+                    let hide_set = current.hide_set.intersection(&rparen.hide_set).with(symbol);
+                    self.substitute(map, definition, &args, &hide_set, &expansion, condition)?
+                }
+            };
+
+            input.extend(replaced.into_iter().rev());
+        }
+
+        Ok(output)
+    }
+
+    /// Record the invocation written in the source code from the name in the region `name` to
+    /// the `)` in the region `rparen` if its arguments span several lines, unless it is inside
+    /// the arguments of the last one recorded.
+    fn record_invocation(&self, map: &SourceMap, name: Span, rparen: Span) {
+        if rparen.lo() < name.hi() {
+            return;
+        }
+        let span = Span::new(name.lo(), rparen.hi());
+        let mut invocations = self.invocations.borrow_mut();
+        if !invocations.last().is_some_and(|last| last.contains(span))
+            && map.get_bytes(span).contains(&b'\n')
+        {
+            invocations.push(span);
+        }
+    }
+
+    /// Check if replacing the macro invocations inside `tokens` gives no tokens other than
+    /// white-space, without counting the invocations nor reporting anything about them, as they
+    /// are replaced again where they are used.
+    fn is_replaced_by_nothing(
+        &self,
+        map: &SourceMap,
+        tokens: &[PpToken],
+        condition: bool,
+    ) -> Result<bool, Diagnostic> {
+        if tokens.is_empty() {
+            return Ok(true);
+        }
+        let counter = self.counter();
+        let expansions = self.expansions.get();
+        let omitted = self.omitted_variadic.borrow().len();
+        let invocations = self.invocations.borrow().len();
+        let errors = self.errors.borrow().len();
+        let replaced = self.expand_tokens(map, tokens.to_vec(), condition)?;
+        if let Some(counter) = counter {
+            self.set_counter(counter);
+        }
+        self.expansions.set(expansions);
+        self.omitted_variadic.borrow_mut().truncate(omitted);
+        self.invocations.borrow_mut().truncate(invocations);
+        self.errors.borrow_mut().truncate(errors);
+        Ok(replaced
+            .iter()
+            .all(|token| token.token.kind == TokenKind::Space))
+    }
+
+    /// Get the kind and spelling of the token replacing `current`, which is `__FILE__` or
+    /// `__LINE__` as given by `symbol`. Its position is the one of the outermost invocation it
+    /// comes from, as the lines of the replacement lists of macros do not matter.
+    fn position(&self, map: &SourceMap, symbol: Symbol, current: &PpToken) -> (TokenKind, String) {
+        let outermost = std::iter::successors(current.expansion.as_deref(), |expansion| {
+            expansion.parent.as_deref()
+        })
+        .last();
+        let span = outermost.map_or(current.token.span, |expansion| expansion.span);
+        let (path, line) = map.find_presumed(span).unwrap_or_else(|| {
+            // The source code that is not in a file is the standard input.
+            let line = match self.input.get().filter(|input| input.contains(span)) {
+                Some(input) => {
+                    let bytes = map.get_bytes(Span::new(input.lo(), span.lo()));
+                    bytes.iter().filter(|&&byte| byte == b'\n').count() + 1
+                }
+                None => 1,
+            };
+            (PathBuf::from("<stdin>"), line)
+        });
+        if symbol == Symbol::LINE {
+            return (TokenKind::Number, line.to_string());
+        }
+        let mut literal = String::from('"');
+        for c in self.prefix_map.apply(&path).to_string_lossy().chars() {
+            if matches!(c, '\\' | '"') {
+                literal.push('\\');
+            }
+            literal.push(c);
+        }
+        literal.push('"');
+        (TokenKind::Str, literal)
+    }
+
+    /// Produce the replacement list of `definition` after argument substitution, stringizing and
+    /// token pasting. Every token in the result has `hide_set` added to its hide set and the
+    /// tokens coming from the replacement list are produced by `expansion`.
+    fn substitute(
+        &self,
+        map: &SourceMap,
+        definition: &Macro,
+        args: &[Vec<PpToken>],
+        hide_set: &HideSet,
+        expansion: &Rc<Expansion>,
+        condition: bool,
+    ) -> Result<Vec<PpToken>, Diagnostic> {
+        let body = definition.replacement.tokens();
+        let mut output =
+            self.substitute_tokens(map, definition, body, args, expansion, condition)?;
+        for token in &mut output {
+            token.hide_set = token.hide_set.union(hide_set);
+        }
+        Ok(output)
+    }
+
+    /// Produce the tokens `body` of the replacement list of `definition` after argument
+    /// substitution, stringizing and token pasting, like [`substitute`](Self::substitute) does
+    /// without changing their hide sets.
+    fn substitute_tokens(
+        &self,
+        map: &SourceMap,
+        definition: &Macro,
+        body: &[Token],
+        args: &[Vec<PpToken>],
+        expansion: &Rc<Expansion>,
+        condition: bool,
+    ) -> Result<Vec<PpToken>, Diagnostic> {
+        let is_function = matches!(definition.kind, MacroKind::Function { .. });
+        // The position of the parameter of the variable arguments, if the macro is variadic.
+        let variadic = match &definition.kind {
+            MacroKind::Function {
+                params,
+                variadic: true,
+            } => Some(params.len() - 1),
+            _ => None,
+        };
+        let body_token = |token: Token| PpToken {
+            expansion: Some(expansion.clone()),
+            ..PpToken::new(token)
+        };
+        let param = |token: Token| {
+            if token.kind == TokenKind::Ident {
+                definition.kind.param(&map.get_bytes(token.span))
+            } else {
+                None
+            }
+        };
+        // The group of the `__VA_OPT__` at `body[i]` and the position of its `)`, if there is one.
+        let group = |i: usize| variadic.and_then(|_| va_opt_group(map, body, i));
+        // A `__VA_OPT__` is replaced by its group after substitution if the variable arguments
+        // are not empty once replaced, and by nothing otherwise (section 6.10.5.1 of C23).
+        let va_opt = |group: &[Token]| {
+            let index = variadic.unwrap();
+            if self.is_replaced_by_nothing(map, &args[index], condition)? {
+                return Ok(Vec::new());
+            }
+            self.substitute_tokens(map, definition, group, args, expansion, condition)
+        };
+
+        let mut output: Vec<PpToken> = Vec::new();
+        // Whether the last operand added to the output was an empty argument. Such arguments are
+        // represented by placemarker preprocessing tokens (section 6.10.3.3p2 of C17).
+        let mut placemarker = false;
+
+        let mut i = 0;
+        while i < body.len() {
+            let token = body[i];
+            let next = (i + 1..body.len()).find(|&j| body[j].kind != TokenKind::Space);
+
+            // The `#` operator (section 6.10.3.2 of C17).
+            if is_function && map.is_punct(token, b"#") {
+                if let Some((j, index)) = next.and_then(|j| Some((j, param(body[j])?))) {
+                    output.push(body_token(stringize(map, &args[index], expansion.span)?));
+                    placemarker = false;
+                    i = j + 1;
+                    continue;
+                }
+                if let Some((end, group)) = next.and_then(group) {
+                    output.push(body_token(stringize(map, &va_opt(group)?, expansion.span)?));
+                    placemarker = false;
+                    i = end + 1;
+                    continue;
+                }
+            }
+
+            // The `##` operator (section 6.10.3.3 of C17). The definition of the macro guarantees
+            // that this operator is never at the end of the replacement list.
+            if map.is_punct(token, b"##") {
+                let j = next.unwrap();
+                let (rhs, end) = match (param(body[j]), group(j)) {
+                    (Some(index), _) => (args[index].clone(), j),
+                    (None, Some((end, group))) => (va_opt(group)?, end),
+                    (None, None) => (vec![body_token(body[j])], j),
+                };
+
+                while output.last().map(|token| token.token.kind) == Some(TokenKind::Space) {
+                    output.pop();
+                }
+
+                // In GNU C, a `,` followed by `##` and the variable arguments is removed if they
+                // are empty and is not pasted with them otherwise.
+                if self.gnu_variadic
+                    && variadic.is_some()
+                    && param(body[j]) == variadic
+                    && output
+                        .last()
+                        .is_some_and(|lhs| map.is_punct(lhs.token, b","))
+                {
+                    if rhs.is_empty() {
+                        output.pop();
+                    }
+                    output.extend(rhs);
+                    placemarker = false;
+                    i = end + 1;
+                    continue;
+                }
+
+                if let Some((first, rest)) = rhs.split_first() {
+                    match output.pop() {
+                        Some(lhs) if !placemarker => match paste(map, lhs.clone(), first) {
+                            Ok(pasted) => output.push(pasted),
+                            Err(diagnostic) if diagnostic.code().is_fatal() => {
+                                return Err(diagnostic)
+                            }
+                            // MSVC keeps the tokens that cannot be pasted as they are silently,
+                            // and the others keep them after reporting an error.
+                            Err(diagnostic) => {
+                                if !self.msvc_paste {
+                                    let diagnostic = expansion.backtrace(map, diagnostic);
+                                    self.errors.borrow_mut().push(diagnostic);
+                                }
+                                output.extend([lhs, first.clone()]);
+                            }
+                        },
+                        lhs => output.extend(lhs.into_iter().chain(Some(first.clone()))),
+                    }
+                    output.extend(rest.iter().cloned());
+                    placemarker = false;
+                }
+
+                i = end + 1;
+                continue;
+            }
+
+            if let Some((end, group)) = group(i) {
+                let replaced = va_opt(group)?;
+                placemarker = replaced.is_empty();
+                output.extend(replaced);
+                i = end + 1;
+                continue;
+            }
+
+            if let Some(index) = param(token) {
+                // Arguments are only macro-expanded if they are not operands of `##`.
+                if next.is_some_and(|j| map.is_punct(body[j], b"##")) {
+                    output.extend(args[index].iter().cloned());
+                    placemarker = args[index].is_empty();
+                } else {
+                    output.extend(self.expand_tokens(map, args[index].clone(), condition)?);
+                    placemarker = false;
+                }
+            } else {
+                output.push(body_token(token));
+                placemarker = false;
+            }
+
+            i += 1;
+        }
+
+        Ok(output)
+    }
+}
+
+/// Collect the arguments of the invocation of the function-like macro named by the token `name`,
+/// which has `param_count` parameters and is defined by `definition`, from `input`. `input` must
+/// start with white-space followed by a `(`.
+///
+/// Return the arguments without leading or trailing white-space, the `)` that closes the
+/// invocation and whether the variable arguments were omitted, or an error if the invocation is
+/// not terminated or has the wrong number of arguments.
+fn collect_args(
+    map: &SourceMap,
+    input: &mut Vec<PpToken>,
+    param_count: usize,
+    variadic: bool,
+    name: Token,
+    definition: &Macro,
+) -> Result<(Vec<Vec<PpToken>>, PpToken, bool), Diagnostic> {
+    let spelling = map.spelling(name);
+    // Discard everything up to and including the `(`.
+    while let Some(token) = input.pop() {
+        if map.is_punct(token.token, b"(") {
+            break;
+        }
+    }
+
+    let mut args = vec![Vec::new()];
+    let mut depth = 0usize;
+
+    let rparen = loop {
+        let mut token = match input.pop() {
+            Some(token) => token,
+            None => {
+                return Err(map.diagnostic(
+                    Severity::Error,
+                    DiagnosticCode::UnterminatedArguments,
+                    name.span,
+                    format!("Unterminated argument list invoking macro \"{}\"", spelling),
+                ))
+            }
+        };
+
+        if map.is_punct(token.token, b"(") {
+            depth += 1;
+        } else if map.is_punct(token.token, b")") {
+            match depth.checked_sub(1) {
+                Some(next) => depth = next,
+                None => break token,
+            }
+        } else if depth == 0
+            && map.is_punct(token.token, b",")
+            // The variable arguments of a variadic macro are a single argument including the
+            // commas that separate them.
+            && !(variadic && args.len() == param_count)
+        {
+            args.push(Vec::new());
+            continue;
+        }
+
+        // New-line characters inside arguments are considered normal white-space characters
+        // (section 6.10.3p10 of C17).
+        if token.token.kind == TokenKind::Newline {
+            token.token.kind = TokenKind::Space;
+        }
+        args.last_mut().unwrap().push(token);
+    };
+
+    for arg in &mut args {
+        while arg.last().map(|token| token.token.kind) == Some(TokenKind::Space) {
+            arg.pop();
+        }
+        let start = arg
+            .iter()
+            .position(|token| token.token.kind != TokenKind::Space)
+            .unwrap_or(arg.len());
+        arg.drain(..start);
+    }
+
+    // An invocation with an empty argument list has no arguments if the macro does not have
+    // parameters.
+    if param_count == 0 && args.len() == 1 && args[0].is_empty() {
+        args.clear();
+    }
+    // The variable arguments can be omitted entirely.
+    let omitted = variadic && args.len() + 1 == param_count;
+    if omitted {
+        args.push(Vec::new());
+    }
+
+    if args.len() != param_count {
+        let diagnostic = map.diagnostic(
+            Severity::Error,
+            DiagnosticCode::ArgumentCount,
+            name.span,
+            format!(
+                "Macro \"{}\" requires {} arguments, but {} were given",
+                spelling,
+                param_count,
+                args.len()
+            ),
+        );
+        let label = map.label(
+            definition.span,
+            format!("Macro \"{}\" is defined here", spelling),
+        );
+        return Err(diagnostic.with_label(label));
+    }
+
+    Ok((args, rparen, omitted))
+}
+
+/// Produce a string literal from the spelling of the tokens in `arg` as described in section
+/// 6.10.3.2p2 of C17, for the `#` operator in the region `at`.
+///
+/// Return an error if the string literal cannot be stored.
+fn stringize(map: &SourceMap, arg: &[PpToken], at: Span) -> Result<Token, Diagnostic> {
+    let mut bytes = vec![b'"'];
+
+    for token in arg {
+        let spelling = map.get_bytes(token.token.span);
+        match token.token.kind {
+            // Each sequence of white-space becomes a single space.
+            TokenKind::Space => {
+                if !bytes.ends_with(b" ") {
+                    bytes.push(b' ');
+                }
+            }
+            // `"` and `\` must be escaped inside character constants and string literals.
+            TokenKind::Char | TokenKind::Str => {
+                for &byte in spelling.iter() {
+                    if matches!(byte, b'"' | b'\\') {
+                        bytes.push(b'\\');
+                    }
+                    bytes.push(byte);
+                }
+            }
+            _ => bytes.extend_from_slice(&spelling),
+        }
+    }
+
+    bytes.push(b'"');
+
+    Ok(Token::new(TokenKind::Str, map.store_at(&bytes, at)?))
+}
+
+/// Concatenate two tokens as described in section 6.10.3.3p3 of C17.
+///
+/// Return an error if the result is not a valid preprocessing token.
+fn paste(map: &SourceMap, lhs: PpToken, rhs: &PpToken) -> Result<PpToken, Diagnostic> {
+    let mut bytes = map.get_bytes(lhs.token.span).to_vec();
+    bytes.extend_from_slice(&map.get_bytes(rhs.token.span));
+
+    match map
+        .tokenize_bytes(&bytes, lhs.token.span)
+        .as_deref()
+        .map(TokenSlice::tokens)
+    {
+        Err(diagnostic) if diagnostic.code().is_fatal() => Err(diagnostic.clone()),
+        Ok([token]) if !matches!(token.kind, TokenKind::Space | TokenKind::Newline) => {
+            Ok(PpToken {
+                token: *token,
+                hide_set: lhs.hide_set.intersection(&rhs.hide_set),
+                expansion: lhs.expansion,
+            })
+        }
+        _ => Err(map.diagnostic(
+            Severity::Error,
+            DiagnosticCode::InvalidPaste,
+            lhs.token.span,
+            format!(
+                "Pasting \"{}\" and \"{}\" does not give a valid preprocessing token",
+                map.spelling(lhs.token),
+                map.spelling(rhs.token)
+            ),
+        )),
+    }
+}
@@ -0,0 +1,544 @@
+//! Macro definitions and macro replacement as described in section 6.10.3 of C17.
+mod expand;
+
+pub(crate) use expand::{ExpandedToken, Expansion};
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::PathBuf,
+};
+
+use crate::{
+    buffer::{same_spelling, Cursor, TokenBuffer},
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    extension::Extension,
+    fs::PrefixMap,
+    lexer::{Token, TokenKind},
+    span::{SourceMap, Span},
+    symbol::Symbol,
+};
+
+/// A macro definition.
+pub(crate) struct Macro {
+    /// The span of the name of the macro inside its definition.
+    pub(crate) span: Span,
+    /// The kind of the macro.
+    pub(crate) kind: MacroKind,
+    /// The replacement list of the macro without leading or trailing white-space.
+    pub(crate) replacement: TokenBuffer,
+    /// Whether the macro was replaced or checked with `defined`, `#ifdef` or `#ifndef`.
+    pub(crate) used: Cell<bool>,
+}
+
+/// The two kinds of macros defined in section 6.10.3 of C17.
+#[derive(PartialEq, Eq)]
+pub(crate) enum MacroKind {
+    /// An object-like macro.
+    Object,
+    /// A function-like macro.
+    Function {
+        /// The names of the parameters of the macro. If the macro is variadic, the last parameter
+        /// is `__VA_ARGS__`, or the name given to the variable arguments in GNU C.
+        params: Vec<String>,
+        /// Whether the parameter list of the macro ends with `...`.
+        variadic: bool,
+    },
+}
+
+impl Macro {
+    /// Check if this definition and `other` are identical, which is required to redefine a macro
+    /// (section 6.10.3p2 of C17): they must be of the same kind, have the same parameters and
+    /// their replacement lists must have the same spelling. The spellings are found in `map`.
+    pub(crate) fn is_identical(&self, other: &Macro, map: &SourceMap) -> bool {
+        self.kind == other.kind
+            && same_spelling(
+                self.replacement.tokens(),
+                other.replacement.tokens(),
+                |span| map.get_bytes(span),
+            )
+    }
+}
+
+impl MacroKind {
+    /// Return the position of the parameter named `name`, if any.
+    fn param(&self, name: &[u8]) -> Option<usize> {
+        match self {
+            MacroKind::Object => None,
+            MacroKind::Function { params, .. } => {
+                params.iter().position(|param| param.as_bytes() == name)
+            }
+        }
+    }
+}
+
+/// The set of macros defined at some point of the preprocessing.
+#[derive(Default)]
+pub(crate) struct MacroTable {
+    /// The names and definitions of the macros, by the symbols of their names.
+    macros: HashMap<Symbol, (String, Macro)>,
+    /// The number of macro invocations replaced so far.
+    expansions: Cell<usize>,
+    /// The regions of the names of the invocations of variadic macros without their variable
+    /// arguments, which is only allowed since C23, replaced since the last call to
+    /// [`take_omitted_variadic`](Self::take_omitted_variadic).
+    omitted_variadic: RefCell<Vec<Span>>,
+    /// The regions of the invocations of function-like macros written in the source code whose
+    /// arguments span several lines, from their names to their `)`, replaced since the last call
+    /// to [`take_invocations`](Self::take_invocations).
+    invocations: RefCell<Vec<Span>>,
+    /// The errors found replacing macros that did not stop the replacement since the last call
+    /// to [`take_errors`](Self::take_errors), like invocations with the wrong number of
+    /// arguments.
+    errors: RefCell<Vec<Diagnostic>>,
+    /// Whether a `,` followed by `##` and empty variable arguments is removed, as in GNU C.
+    gnu_variadic: bool,
+    /// Whether pasting tokens that do not give a valid preprocessing token keeps them, as in
+    /// MSVC.
+    msvc_paste: bool,
+    /// The next value of `__COUNTER__`, if it is replaced.
+    counter: Option<Cell<usize>>,
+    /// The replacements of path prefixes in the names of files given by `__FILE__`.
+    prefix_map: PrefixMap,
+    /// The region of the source code processed last, which gives the line numbers of
+    /// `__LINE__` when it is not in a file.
+    input: Cell<Option<Span>>,
+}
+
+impl MacroTable {
+    /// Enable the extensions to macro replacement that are in `extensions`.
+    pub(crate) fn set_extensions(&mut self, extensions: &BTreeSet<Extension>) {
+        self.gnu_variadic = extensions.contains(&Extension::GnuVariadic);
+        self.msvc_paste = extensions.contains(&Extension::MsvcPaste);
+        self.counter = extensions
+            .contains(&Extension::Counter)
+            .then(|| Cell::new(0));
+    }
+
+    /// Replace path prefixes in the names of files given by `__FILE__` using `prefix_map`.
+    pub(crate) fn set_prefix_map(&mut self, prefix_map: PrefixMap) {
+        self.prefix_map = prefix_map;
+    }
+
+    /// Set the region of the source code that is processed, whose lines are counted from its
+    /// start by `__LINE__` if it is not in a file.
+    pub(crate) fn set_input(&self, input: Span) {
+        self.input.set(Some(input));
+    }
+
+    /// Define the macro named `name`, whose symbol is `symbol`, replacing any previous
+    /// definition with the same name. Return the previous definition, if any.
+    pub(crate) fn define(
+        &mut self,
+        symbol: Symbol,
+        name: String,
+        definition: Macro,
+    ) -> Option<Macro> {
+        let (_, previous) = self.macros.insert(symbol, (name, definition))?;
+        Some(previous)
+    }
+
+    /// Remove the definition of the macro whose name has the symbol `symbol` and return it.
+    /// Nothing happens if the macro is not defined.
+    pub(crate) fn undefine(&mut self, symbol: Symbol) -> Option<Macro> {
+        let (_, definition) = self.macros.remove(&symbol)?;
+        Some(definition)
+    }
+
+    /// Get the definition of the macro whose name has the symbol `symbol`.
+    pub(crate) fn get(&self, symbol: Symbol) -> Option<&Macro> {
+        let (_, definition) = self.macros.get(&symbol)?;
+        Some(definition)
+    }
+
+    /// Check if the macro whose name has the symbol `symbol` is defined. `__FILE__` and
+    /// `__LINE__` are always defined, and so is `__COUNTER__` if it is replaced.
+    pub(crate) fn is_defined(&self, symbol: Symbol) -> bool {
+        let definition = self.get(symbol);
+        if let Some(definition) = definition {
+            definition.used.set(true);
+        }
+        definition.is_some()
+            || matches!(symbol, Symbol::FILE | Symbol::LINE)
+            || (symbol == Symbol::COUNTER && self.counter.is_some())
+    }
+
+    /// Get the name and definition of the macro whose name has the symbol `symbol`.
+    pub(crate) fn get_key_value(&self, symbol: Symbol) -> Option<(&String, &Macro)> {
+        let (name, definition) = self.macros.get(&symbol)?;
+        Some((name, definition))
+    }
+
+    /// The next value of `__COUNTER__`, if it is replaced.
+    pub(crate) fn counter(&self) -> Option<usize> {
+        self.counter.as_ref().map(Cell::get)
+    }
+
+    /// Set the next value of `__COUNTER__`, if it is replaced.
+    pub(crate) fn set_counter(&self, value: usize) {
+        if let Some(counter) = &self.counter {
+            counter.set(value);
+        }
+    }
+
+    /// The number of macro invocations replaced so far.
+    pub(crate) fn expansions(&self) -> usize {
+        self.expansions.get()
+    }
+
+    /// Take the regions of the names of the invocations of variadic macros without their
+    /// variable arguments replaced so far.
+    pub(crate) fn take_omitted_variadic(&self) -> Vec<Span> {
+        self.omitted_variadic.take()
+    }
+
+    /// Take the regions of the invocations written in the source code whose arguments span
+    /// several lines replaced so far, in the order they were found.
+    pub(crate) fn take_invocations(&self) -> Vec<Span> {
+        self.invocations.take()
+    }
+
+    /// Take the errors found replacing macros so far that did not stop the replacement.
+    pub(crate) fn take_errors(&self) -> Vec<Diagnostic> {
+        self.errors.take()
+    }
+
+    /// Return an iterator over the names and definitions of all the macros, in arbitrary order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &Macro)> {
+        self.macros
+            .values()
+            .map(|(name, definition)| (name, definition))
+    }
+}
+
+/// The definition of a macro.
+#[derive(Clone, Copy)]
+pub struct MacroDefinition<'a> {
+    name: &'a str,
+    definition: &'a Macro,
+    map: &'a SourceMap,
+}
+
+impl<'a> MacroDefinition<'a> {
+    pub(crate) fn new(name: &'a str, definition: &'a Macro, map: &'a SourceMap) -> Self {
+        Self {
+            name,
+            definition,
+            map,
+        }
+    }
+
+    /// The name of the macro.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Whether this is a function-like macro.
+    pub fn is_function_like(&self) -> bool {
+        matches!(self.definition.kind, MacroKind::Function { .. })
+    }
+
+    /// Whether this is a function-like macro whose parameter list ends with `...`.
+    pub fn is_variadic(&self) -> bool {
+        matches!(
+            self.definition.kind,
+            MacroKind::Function { variadic: true, .. }
+        )
+    }
+
+    /// Whether this macro was predefined by the preprocessor.
+    pub fn is_builtin(&self) -> bool {
+        self.file()
+            .is_some_and(|path| path.as_os_str() == "<built-in>")
+    }
+
+    /// The names of the parameters of the macro, without the parameter of the variable arguments
+    /// of variadic macros. Return `None` if this is an object-like macro.
+    pub fn params(&self) -> Option<&'a [String]> {
+        match &self.definition.kind {
+            MacroKind::Object => None,
+            MacroKind::Function { params, variadic } => {
+                Some(&params[..params.len() - usize::from(*variadic)])
+            }
+        }
+    }
+
+    /// The spelling of each token in the replacement list of the macro. Each sequence of
+    /// white-space is spelled as a single space.
+    pub fn replacement_tokens(&self) -> Vec<String> {
+        self.definition
+            .replacement
+            .tokens()
+            .iter()
+            .map(|token| match token.kind {
+                TokenKind::Space => " ".to_owned(),
+                _ => self.map.spelling(*token),
+            })
+            .collect()
+    }
+
+    /// The spelling of the replacement list of the macro. Each sequence of white-space is spelled
+    /// as a single space.
+    pub fn replacement(&self) -> String {
+        self.replacement_tokens().concat()
+    }
+
+    /// The file where the macro was defined. Macros that were predefined or defined from the
+    /// command line belong to the `<built-in>` and `<command-line>` files respectively.
+    pub fn file(&self) -> Option<PathBuf> {
+        self.map.find_file(self.definition.span)
+    }
+
+    /// The line where the macro was defined, starting from 1.
+    pub fn line(&self) -> Option<usize> {
+        self.map.find_line(self.definition.span)
+    }
+}
+
+impl SourceMap {
+    /// Parse the contents of a `#define` directive in the region `span`, that is, the tokens
+    /// after `define` without the final new-line character. If `gnu_variadic` is `true`, the
+    /// variable arguments can be named as in GNU C.
+    ///
+    /// Return an error about the directive if the definition is not valid.
+    pub(crate) fn parse_define(
+        &self,
+        span: Span,
+        tokens: &[Token],
+        gnu_variadic: bool,
+    ) -> Result<(Symbol, String, Macro), Diagnostic> {
+        let error = |code, message| Err(self.diagnostic(Severity::Error, code, span, message));
+        let mut cursor = Cursor::new(tokens);
+
+        let name = match cursor.next_non_space() {
+            Some(token) if token.kind == TokenKind::Ident => token,
+            _ => {
+                return error(
+                    DiagnosticCode::InvalidMacroName,
+                    "Macro names must be identifiers",
+                )
+            }
+        };
+
+        // If the name is immediately followed by a `(`, this is a function-like macro.
+        let kind = match cursor.peek() {
+            Some(token) if self.is_punct(token, b"(") => {
+                cursor.next();
+                let mut params = Vec::new();
+                let mut variadic = false;
+                loop {
+                    match cursor.next_non_space() {
+                        Some(token) if params.is_empty() && self.is_punct(token, b")") => break,
+                        Some(token) if token.kind == TokenKind::Ident => {
+                            params.push(self.spelling(token));
+                            // GNU C names the variable arguments with the parameter before the
+                            // `...`.
+                            let checkpoint = cursor.checkpoint();
+                            match cursor.next_non_space() {
+                                Some(token) if gnu_variadic && self.is_punct(token, b"...") => {
+                                    variadic = true;
+                                }
+                                _ => cursor.rollback(checkpoint),
+                            }
+                        }
+                        Some(token) if self.is_punct(token, b"...") => {
+                            params.push("__VA_ARGS__".to_owned());
+                            variadic = true;
+                        }
+                        _ => {
+                            return error(
+                                DiagnosticCode::InvalidParameterList,
+                                "Invalid parameter list in the definition of a macro",
+                            )
+                        }
+                    }
+                    match cursor.next_non_space() {
+                        Some(token) if self.is_punct(token, b")") => break,
+                        Some(token) if !variadic && self.is_punct(token, b",") => continue,
+                        _ => {
+                            return error(
+                                DiagnosticCode::InvalidParameterList,
+                                "Invalid parameter list in the definition of a macro",
+                            )
+                        }
+                    }
+                }
+                MacroKind::Function { params, variadic }
+            }
+            _ => MacroKind::Object,
+        };
+
+        let mut replacement = cursor.skip_space().rest();
+        while let Some((last, rest)) = replacement.split_last() {
+            if last.kind != TokenKind::Space {
+                break;
+            }
+            replacement = rest;
+        }
+
+        // A `##` cannot appear at either end of a replacement list (section 6.10.3.3p1 of C17).
+        if replacement
+            .first()
+            .into_iter()
+            .chain(replacement.last())
+            .any(|token| self.is_punct(*token, b"##"))
+        {
+            return error(
+                DiagnosticCode::PasteAtEdge,
+                "'##' cannot appear at either end of a macro replacement list",
+            );
+        }
+
+        // `__VA_OPT__` must be followed by a group of tokens between parentheses that does not
+        // start or end with `##` and does not contain another `__VA_OPT__` (section 6.10.5.1 of
+        // C23).
+        if matches!(kind, MacroKind::Function { variadic: true, .. }) {
+            for (i, &token) in replacement.iter().enumerate() {
+                if !self.is_va_opt(token) {
+                    continue;
+                }
+                let group = match va_opt_group(self, replacement, i) {
+                    Some((_, group)) => group,
+                    None => {
+                        return error(
+                            DiagnosticCode::InvalidVaOpt,
+                            "__VA_OPT__ must be followed by tokens between parentheses",
+                        )
+                    }
+                };
+                if group.iter().any(|&token| self.is_va_opt(token)) {
+                    return error(
+                        DiagnosticCode::InvalidVaOpt,
+                        "__VA_OPT__ cannot appear inside another __VA_OPT__",
+                    );
+                }
+                if group
+                    .first()
+                    .into_iter()
+                    .chain(group.last())
+                    .any(|token| self.is_punct(*token, b"##"))
+                {
+                    return error(
+                        DiagnosticCode::PasteAtEdge,
+                        "'##' cannot appear at either end of __VA_OPT__",
+                    );
+                }
+            }
+        }
+
+        Ok((
+            self.symbol(name),
+            self.spelling(name),
+            Macro {
+                span: name.span,
+                kind,
+                replacement: replacement.iter().copied().collect(),
+                used: Cell::new(false),
+            },
+        ))
+    }
+
+    /// Get the spelling of a token as a string.
+    pub(crate) fn spelling(&self, token: Token) -> String {
+        String::from_utf8_lossy(&self.get_bytes(token.span)).into_owned()
+    }
+
+    /// Check if `token` is the identifier `__VA_OPT__`.
+    fn is_va_opt(&self, token: Token) -> bool {
+        token.kind == TokenKind::Ident && *self.get_bytes(token.span) == *b"__VA_OPT__"
+    }
+
+    /// Check if `token` is a punctuator spelled as `punct`. Digraphs are considered to be spelled
+    /// as the punctuator they represent (section 6.4.6p3 of C17).
+    pub(crate) fn is_punct(&self, token: Token, punct: &[u8]) -> bool {
+        if token.kind != TokenKind::Punct {
+            return false;
+        }
+
+        let bytes = self.get_bytes(token.span);
+        let bytes: &[u8] = match &*bytes {
+            b"<:" => b"[",
+            b":>" => b"]",
+            b"<%" => b"{",
+            b"%>" => b"}",
+            b"%:" => b"#",
+            b"%:%:" => b"##",
+            bytes => bytes,
+        };
+        bytes == punct
+    }
+}
+
+/// Skip the white-space tokens at the start of `tokens` and return the first token that is not
+/// white-space.
+pub(crate) fn skip_space(tokens: &mut impl Iterator<Item = Token>) -> Option<Token> {
+    tokens.find(|token| token.kind != TokenKind::Space)
+}
+
+/// Find the group of the `__VA_OPT__` at `tokens[start]`, which are the tokens of the replacement
+/// list of a variadic macro (section 6.10.5.1 of C23). Return the position of the `)` that ends
+/// the group and the tokens between its parentheses without leading or trailing white-space, or
+/// `None` if `tokens[start]` is not a `__VA_OPT__` followed by a group.
+fn va_opt_group<'a>(
+    map: &SourceMap,
+    tokens: &'a [Token],
+    start: usize,
+) -> Option<(usize, &'a [Token])> {
+    if !map.is_va_opt(tokens[start]) {
+        return None;
+    }
+    let open = (start + 1..tokens.len()).find(|&i| tokens[i].kind != TokenKind::Space)?;
+    if !map.is_punct(tokens[open], b"(") {
+        return None;
+    }
+    let mut depth = 0usize;
+    for (i, &token) in tokens.iter().enumerate().skip(open + 1) {
+        if map.is_punct(token, b"(") {
+            depth += 1;
+        } else if map.is_punct(token, b")") {
+            match depth.checked_sub(1) {
+                Some(next) => depth = next,
+                None => {
+                    let group = &tokens[open + 1..i];
+                    let is_space = |token: &Token| token.kind == TokenKind::Space;
+                    let lo = group.iter().position(|token| !is_space(token));
+                    let hi = group.iter().rposition(|token| !is_space(token));
+                    let group = match (lo, hi) {
+                        (Some(lo), Some(hi)) => &group[lo..=hi],
+                        _ => &[],
+                    };
+                    return Some((i, group));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Format the definition as a `#define` directive.
+impl fmt::Display for MacroDefinition<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#define {}", self.name)?;
+
+        if let MacroKind::Function { params, variadic } = &self.definition.kind {
+            let mut params = params.clone();
+            if *variadic {
+                // The variable arguments can be named in GNU C.
+                let last = params.pop().unwrap();
+                params.push(match last.as_str() {
+                    "__VA_ARGS__" => "...".to_owned(),
+                    _ => format!("{}...", last),
+                });
+            }
+            write!(f, "({})", params.join(", "))?;
+        }
+
+        let replacement = self.replacement();
+        if !replacement.is_empty() {
+            write!(f, " {}", replacement)?;
+        }
+
+        Ok(())
+    }
+}
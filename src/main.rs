@@ -1,4 +1,234 @@
+use std::{
+    io::{self, BufWriter, IsTerminal, Write},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use beheader::{CommandLine, DiagnosticRenderer, Emitter, Error, Severity};
+
+const USAGE: &str = "\
+usage: beheader [options] <file>
+
+Preprocess <file>, or the standard input if it is `-`, and write the result.
+
+options:
+  -o <file>           Write the result to <file> instead of the standard output
+  -D <name>[=<value>] Define the macro <name>
+  -U <name>           Undefine the macro <name>
+  -I <dir>            Search included files in <dir>
+  -include <file>     Include <file> before the input
+  -std=<standard>     Use the version <standard> of C, like c99 or gnu11
+  -P                  Do not write line markers
+  -MD, -MMD           Write the dependencies of the input to a file
+  -E                  Only preprocess the input, which is always the case
+
+The other options of GCC that affect preprocessing are understood too, and the rest are
+ignored.
+
+The target and the system include directories are the ones of the C compiler of the system,
+which is the one in the CC environment variable or cc.";
+
 fn main() {
     let args: Vec<_> = std::env::args_os().collect();
-    beheader::preprocess_file(&args[1]).unwrap();
+
+    #[cfg(feature = "compile-commands")]
+    if args.get(1).is_some_and(|arg| arg == "compile-commands") && args.len() == 3 {
+        compile_commands(&args[2]);
+        return;
+    }
+
+    let args: Vec<_> = match args
+        .into_iter()
+        .skip(1)
+        .map(|arg| arg.into_string())
+        .collect()
+    {
+        Ok(args) => args,
+        Err(arg) => usage_error(&format!("invalid argument {:?}", arg)),
+    };
+    // Paths are kept relative to the working directory, as C compilers do.
+    let command_line = Host::probe().command_line(&args, Path::new(""));
+    let input = match command_line.inputs() {
+        [input] => input,
+        [] => usage_error("no input file"),
+        _ => usage_error("more than one input file"),
+    };
+
+    // The input is read from the standard input when its path is `-`, as C compilers do.
+    let preprocessor = command_line.preprocessor();
+    let result = if input.as_os_str() == "-" {
+        preprocessor.preprocess_reader(io::stdin().lock(), &"<stdin>")
+    } else {
+        // Errors reading the input do not say which file it is otherwise.
+        if let Err(error) = std::fs::metadata(input) {
+            eprintln!("beheader: error: {}: {}", input.display(), error);
+            std::process::exit(1);
+        }
+        preprocessor.preprocess_file(input)
+    };
+    let preprocessed = match result {
+        Ok(preprocessed) => preprocessed,
+        Err(error) => {
+            report(&error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut failed = false;
+    for diagnostic in preprocessed.diagnostics() {
+        eprint!("{}", renderer().render(diagnostic));
+        failed |= diagnostic.severity() == Severity::Error;
+    }
+    if let Some((depfile, path)) = command_line.depfile() {
+        if let Err(error) = depfile.write_to(&preprocessed, path) {
+            eprintln!("beheader: error: {}: {}", path.display(), error);
+            failed = true;
+        }
+    }
+    // The output is written even if there are errors, as C compilers do.
+    let mut emitter = Emitter::new();
+    emitter.line_markers(command_line.line_markers());
+    let written = match command_line.output() {
+        Some(path) => emitter.write_to(&preprocessed, path).map_err(|error| {
+            eprintln!("beheader: error: {}: {}", path.display(), error);
+        }),
+        None => {
+            let mut stdout = BufWriter::new(io::stdout().lock());
+            let written = emitter.render_to(&preprocessed, &mut stdout);
+            written.and_then(|()| stdout.flush()).map_err(|error| {
+                eprintln!("beheader: error: {}", error);
+            })
+        }
+    };
+    if failed || written.is_err() {
+        std::process::exit(1);
+    }
+}
+
+/// The arguments that make the preprocessor work like the C compiler of the system, which is
+/// the one in the `CC` environment variable or `cc`.
+struct Host {
+    /// The arguments setting the target of the compiler.
+    target: Vec<String>,
+    /// The arguments adding the system include directories of the compiler.
+    include_dirs: Vec<String>,
+}
+
+impl Host {
+    /// Find the arguments by running the compiler. They are empty if it cannot be run.
+    fn probe() -> Self {
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+        let run = |args: &[&str]| {
+            Command::new(&compiler)
+                .args(args)
+                .stdin(Stdio::null())
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+        };
+
+        let target = run(&["-dumpmachine"])
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+            .map(|triple| vec!["-target".to_owned(), triple.trim().to_owned()])
+            .unwrap_or_default();
+
+        // The directories are listed by `cc -v` between these lines, and the directories of
+        // Apple frameworks are followed by a note.
+        let mut include_dirs = Vec::new();
+        if let Some(output) = run(&["-E", "-v", "-x", "c", "-"]) {
+            let output = String::from_utf8_lossy(&output.stderr);
+            let dirs = output
+                .lines()
+                .skip_while(|line| !line.starts_with("#include <...> search starts here:"))
+                .skip(1)
+                .take_while(|line| !line.starts_with("End of search list."));
+            for dir in dirs {
+                let dir = dir.trim();
+                match dir.strip_suffix(" (framework directory)") {
+                    Some(dir) => include_dirs.extend(["-F".to_owned(), dir.to_owned()]),
+                    None => include_dirs.extend(["-isystem".to_owned(), dir.to_owned()]),
+                }
+            }
+        }
+
+        Self {
+            target,
+            include_dirs,
+        }
+    }
+
+    /// Parse the command-line arguments `args` of the compiler, resolving relative paths against
+    /// `directory`. The target of the compiler comes first so `-target` can change it, and its
+    /// include directories come last so they are searched after the ones given, unless
+    /// `-nostdinc` is given.
+    fn command_line(&self, args: &[String], directory: &Path) -> CommandLine {
+        let include_dirs = match args.iter().any(|arg| arg == "-nostdinc") {
+            true => &[][..],
+            false => &self.include_dirs,
+        };
+        let args = self.target.iter().chain(args).chain(include_dirs);
+        CommandLine::parse(args.map(String::as_str), directory)
+    }
+}
+
+/// Print a problem with the command-line arguments and the usage, and exit.
+fn usage_error(message: &str) -> ! {
+    eprintln!("beheader: error: {}\n\n{}", message, USAGE);
+    std::process::exit(1);
+}
+
+/// Create the renderer of diagnostics, using colors if they are written to a terminal.
+fn renderer() -> DiagnosticRenderer {
+    let mut renderer = DiagnosticRenderer::new();
+    renderer.colors(io::stderr().is_terminal());
+    renderer
+}
+
+/// Print the error that stopped the preprocessing, after the diagnostics reported before it.
+fn report(error: &Error) {
+    for diagnostic in error.diagnostics() {
+        eprint!("{}", renderer().render(diagnostic));
+    }
+    match error.diagnostic() {
+        Some(diagnostic) => eprint!("{}", renderer().render(diagnostic)),
+        None => eprintln!("beheader: error: {}", error),
+    }
+}
+
+/// Preprocess every translation unit of a compilation database and print its diagnostics.
+#[cfg(feature = "compile-commands")]
+fn compile_commands(path: &std::ffi::OsStr) {
+    let database = match beheader::CompilationDatabase::read(path) {
+        Ok(database) => database,
+        Err(error) => {
+            eprintln!("beheader: error: {}: {}", Path::new(path).display(), error);
+            std::process::exit(1);
+        }
+    };
+    let host = Host::probe();
+    let mut failed = false;
+    for command in database.commands() {
+        let command_line = host.command_line(
+            command.arguments().get(1..).unwrap_or_default(),
+            command.directory(),
+        );
+        match command_line.preprocessor().preprocess_file(&command.file()) {
+            Ok(preprocessed) => {
+                for diagnostic in preprocessed.diagnostics() {
+                    eprint!("{}", renderer().render(diagnostic));
+                    failed |= diagnostic.severity() == Severity::Error;
+                }
+            }
+            Err(error) => {
+                if error.diagnostic().is_none() {
+                    eprint!("{}: ", command.file().display());
+                }
+                report(&error);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
 }
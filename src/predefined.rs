@@ -0,0 +1,73 @@
+//! Predefined macro names as described in section 6.10.8 of C17.
+
+/// The macros that must be predefined by every implementation (section 6.10.8.1 of C17), except
+/// for `__STDC_VERSION__`, whose value depends on the [`Standard`](crate::Standard), and
+/// `__FILE__` and `__LINE__`, which are replaced while replacing macros as their value depends on
+/// the position they are found in.
+pub(crate) const MANDATORY_MACROS: &[(&str, &str)] = &[("__STDC__", "1"), ("__STDC_HOSTED__", "1")];
+
+/// The environment and conditional feature macros defined in sections 6.10.8.2 and 6.10.8.3 of
+/// C17. Which of these macros are predefined can be configured using
+/// [`Preprocessor::feature_macro`](crate::Preprocessor::feature_macro).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum FeatureMacro {
+    /// `__STDC_ISO_10646__`.
+    Iso10646,
+    /// `__STDC_MB_MIGHT_NEQ_WC__`.
+    MbMightNeqWc,
+    /// `__STDC_UTF_16__`.
+    Utf16,
+    /// `__STDC_UTF_32__`.
+    Utf32,
+    /// `__STDC_ANALYZABLE__`.
+    Analyzable,
+    /// `__STDC_IEC_559__`.
+    Iec559,
+    /// `__STDC_IEC_559_COMPLEX__`.
+    Iec559Complex,
+    /// `__STDC_LIB_EXT1__`.
+    LibExt1,
+    /// `__STDC_NO_ATOMICS__`.
+    NoAtomics,
+    /// `__STDC_NO_COMPLEX__`.
+    NoComplex,
+    /// `__STDC_NO_THREADS__`.
+    NoThreads,
+    /// `__STDC_NO_VLA__`.
+    NoVla,
+}
+
+impl FeatureMacro {
+    /// The feature macros predefined by default, which are the ones predefined by GCC and Clang
+    /// without the help of any C library.
+    pub(crate) const DEFAULT: &'static [Self] = &[Self::Utf16, Self::Utf32];
+
+    /// The name of the macro.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Iso10646 => "__STDC_ISO_10646__",
+            Self::MbMightNeqWc => "__STDC_MB_MIGHT_NEQ_WC__",
+            Self::Utf16 => "__STDC_UTF_16__",
+            Self::Utf32 => "__STDC_UTF_32__",
+            Self::Analyzable => "__STDC_ANALYZABLE__",
+            Self::Iec559 => "__STDC_IEC_559__",
+            Self::Iec559Complex => "__STDC_IEC_559_COMPLEX__",
+            Self::LibExt1 => "__STDC_LIB_EXT1__",
+            Self::NoAtomics => "__STDC_NO_ATOMICS__",
+            Self::NoComplex => "__STDC_NO_COMPLEX__",
+            Self::NoThreads => "__STDC_NO_THREADS__",
+            Self::NoVla => "__STDC_NO_VLA__",
+        }
+    }
+
+    /// The replacement list of the macro when it is predefined.
+    pub fn value(self) -> &'static str {
+        match self {
+            // The latest version of ISO/IEC 10646 when C17 was published.
+            Self::Iso10646 => "201706L",
+            Self::LibExt1 => "201112L",
+            _ => "1",
+        }
+    }
+}
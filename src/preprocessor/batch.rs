@@ -0,0 +1,229 @@
+//! Preprocessing of many translation units in parallel.
+use std::{
+    collections::HashMap,
+    io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use super::{HeaderCache, Preprocessed, Preprocessor};
+use crate::{
+    diagnostic::Diagnostic,
+    error::Error,
+    fs::{FileMetadata, FileSystem},
+};
+
+/// A set of translation units preprocessed in parallel using the same configuration. They are
+/// preprocessed one after the other in the calling thread if only one thread can be used, which is
+/// always the case in WebAssembly.
+///
+/// The files read by any translation unit are kept in memory and shared with the others, so
+/// headers included by many of them are only read once. They are also tokenized only once, using
+/// the [`HeaderCache`] of the preprocessor or a new one if it does not have any.
+///
+/// ```no_run
+/// use std::num::NonZeroUsize;
+///
+/// use beheader::{Batch, Preprocessor};
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.include_dir("include");
+///
+/// let threads = NonZeroUsize::new(4).unwrap();
+/// let results = Batch::new(preprocessor).threads(threads).preprocess_files(&["a.c", "b.c"]);
+/// for diagnostic in results.diagnostics() {
+///     eprintln!("{}", diagnostic);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Batch {
+    preprocessor: Preprocessor,
+    threads: NonZeroUsize,
+}
+
+impl Batch {
+    /// Create a new batch that uses `preprocessor` for every translation unit.
+    pub fn new(preprocessor: Preprocessor) -> Self {
+        Self {
+            preprocessor,
+            threads: thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+        }
+    }
+
+    /// Set the maximum number of threads used to preprocess translation units.
+    ///
+    /// By default, the number of threads is the amount of parallelism available in the system.
+    pub fn threads(&mut self, threads: NonZeroUsize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Preprocess every file in `paths`.
+    pub fn preprocess_files<P: AsRef<Path> + Sync>(&self, paths: &[P]) -> BatchResults {
+        self.run(paths, |preprocessor, path| {
+            preprocessor.preprocess_file(path)
+        })
+    }
+
+    /// Preprocess the contents of every pair in `sources`, each like the contents of a file with
+    /// the name in the pair that is not a dependency of its result, as done by
+    /// [`Preprocessor::preprocess_source`].
+    ///
+    /// ```
+    /// use beheader::{Batch, Emitter, Preprocessor};
+    ///
+    /// let sources = [("a.c", "int a;\n"), ("b.c", "#define B 2\nint b = B;\n")];
+    /// let results = Batch::new(Preprocessor::new()).preprocess_sources(&sources);
+    /// let mut emitter = Emitter::new();
+    /// emitter.line_markers(false);
+    /// let texts: Vec<_> = results
+    ///     .results()
+    ///     .iter()
+    ///     .map(|result| emitter.render(result.as_ref().unwrap()))
+    ///     .collect();
+    /// assert_eq!(texts, [&b"int a;\n"[..], b"int b = 2;\n"]);
+    /// ```
+    pub fn preprocess_sources<P, B>(&self, sources: &[(P, B)]) -> BatchResults
+    where
+        P: AsRef<Path> + Sync,
+        B: AsRef<[u8]> + Sync,
+    {
+        self.run(sources, |preprocessor, (name, source)| {
+            preprocessor.preprocess_source(name, source.as_ref())
+        })
+    }
+
+    /// Preprocess every translation unit in `inputs` using `preprocess`.
+    fn run<T: Sync>(
+        &self,
+        inputs: &[T],
+        preprocess: impl Fn(&Preprocessor, &T) -> Result<Preprocessed, Error> + Sync,
+    ) -> BatchResults {
+        let mut preprocessor = self.preprocessor.clone();
+        preprocessor.file_system = Arc::new(SharedFiles {
+            file_system: self.preprocessor.file_system.clone(),
+            files: Mutex::default(),
+        });
+        preprocessor
+            .header_cache
+            .get_or_insert_with(HeaderCache::default);
+
+        // There are no threads in WebAssembly.
+        let threads = self.threads.get().min(inputs.len());
+        if threads <= 1 || cfg!(target_family = "wasm") {
+            let results = inputs
+                .iter()
+                .map(|input| preprocess(&preprocessor, input))
+                .collect();
+            return BatchResults { results };
+        }
+
+        // Each thread takes the next translation unit that has not been taken yet.
+        let next = AtomicUsize::new(0);
+        let mut results: Vec<_> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            match inputs.get(index) {
+                                Some(input) => {
+                                    results.push((index, preprocess(&preprocessor, input)))
+                                }
+                                None => break results,
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .flat_map(|thread| match thread.join() {
+                    Ok(results) => results,
+                    Err(panic) => std::panic::resume_unwind(panic),
+                })
+                .collect()
+        });
+
+        results.sort_by_key(|&(index, _)| index);
+        BatchResults {
+            results: results.into_iter().map(|(_, result)| result).collect(),
+        }
+    }
+}
+
+/// The results of preprocessing the translation units of a [`Batch`].
+pub struct BatchResults {
+    results: Vec<Result<Preprocessed, Error>>,
+}
+
+impl BatchResults {
+    /// The result of each translation unit, in the same order as the inputs given to
+    /// [`Batch::preprocess_files`] or [`Batch::preprocess_sources`].
+    pub fn results(&self) -> &[Result<Preprocessed, Error>] {
+        &self.results
+    }
+
+    /// Take the result of each translation unit.
+    pub fn into_results(self) -> Vec<Result<Preprocessed, Error>> {
+        self.results
+    }
+
+    /// The diagnostics of every translation unit that could be preprocessed, in the same order
+    /// as the inputs given to [`Batch::preprocess_files`] or [`Batch::preprocess_sources`].
+    pub fn diagnostics(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.results
+            .iter()
+            .flatten()
+            .flat_map(Preprocessed::diagnostics)
+    }
+}
+
+/// A [`FileSystem`] that keeps the files read from another one in memory, so they can be shared
+/// between threads.
+struct SharedFiles {
+    file_system: Arc<dyn FileSystem>,
+    files: Mutex<HashMap<PathBuf, Arc<[u8]>>>,
+}
+
+impl FileSystem for SharedFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        if let Some(contents) = self.files.lock().unwrap().get(path) {
+            return Ok(contents.to_vec());
+        }
+        // The lock is not held while reading so other threads are not blocked.
+        let contents = self.file_system.read(path)?;
+        self.files
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), contents.as_slice().into());
+        Ok(contents)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(path) || self.file_system.is_file(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.file_system.canonicalize(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.file_system.exists(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.file_system.read_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        self.file_system.metadata(path)
+    }
+}
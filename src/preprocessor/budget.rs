@@ -0,0 +1,65 @@
+//! The limits on the resources a preprocessing run can use.
+use std::time::Duration;
+
+use super::Context;
+use crate::{
+    buffer::TokenBuffer,
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    span::Span,
+};
+
+/// The resources a preprocessing run can use, where `0` means there is no limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Budget {
+    /// The time the run can take.
+    pub(super) duration: Duration,
+    /// The bytes of source code that can be loaded.
+    pub(super) source_bytes: usize,
+    /// The tokens that can be produced.
+    pub(super) tokens: usize,
+}
+
+impl Context {
+    /// Start limiting the resources used from now on to `budget`, except for the time, which
+    /// is counted from the start of the run.
+    pub(super) fn start_budget(&mut self, budget: Budget) {
+        self.budget = budget;
+        self.stored_before = self.map.stored_len();
+    }
+
+    /// Check that the run did not use more resources than its budget, where `output` is the
+    /// output of the run. Return a [`BudgetExceeded`](DiagnosticCode::BudgetExceeded) error
+    /// located at `span` and listing what was used otherwise.
+    pub(super) fn check_budget(&self, span: Span, output: &TokenBuffer) -> Result<(), Diagnostic> {
+        let budget = self.budget;
+        let elapsed = self
+            .started
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        let source_bytes = self.map.stored_len() - self.stored_before;
+        let tokens = self.streamed + output.tokens().len();
+
+        let exceeded = if !budget.duration.is_zero() && elapsed > budget.duration {
+            format!("{:?}", budget.duration)
+        } else if budget.source_bytes != 0 && source_bytes > budget.source_bytes {
+            format!("{} bytes of source code", budget.source_bytes)
+        } else if budget.tokens != 0 && tokens > budget.tokens {
+            format!("{} tokens", budget.tokens)
+        } else {
+            return Ok(());
+        };
+        let message = format!("Stopping after exceeding the budget of {}", exceeded);
+        let diagnostic = self
+            .map
+            .diagnostic(
+                Severity::Error,
+                DiagnosticCode::BudgetExceeded,
+                span,
+                message,
+            )
+            .with_note(format!("{:?} elapsed", elapsed))
+            .with_note(format!("{} bytes of source code loaded", source_bytes))
+            .with_note(format!("{} tokens produced", tokens));
+        Err(diagnostic)
+    }
+}
@@ -0,0 +1,169 @@
+//! Keys identifying the result of preprocessing for caching tools.
+use std::{fmt, io, path::Path};
+
+use super::{
+    content_hash::{ContentHash, Sha256},
+    Preprocessed, Preprocessor,
+};
+
+/// A SHA-256 hash of everything that can change the result of preprocessing a translation unit: the
+/// configuration of the [`Preprocessor`], the input and the paths and contents of every file
+/// read. It can be used as the key of a cache of compilation results, like the direct mode of
+/// `ccache`.
+///
+/// The hash is stable across runs and platforms but not across versions of this crate. The
+/// [`FileSystem`](crate::FileSystem), the
+/// [`include_hook`](Preprocessor::include_hook) and the contents it provides are not part of it.
+///
+/// ```
+/// use beheader::{MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#include \"config.h\"\n");
+/// files.insert("config.h", "#define VERSION 1\n");
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files);
+/// let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+/// let key = preprocessor.cache_key(&preprocessed);
+///
+/// // On the next run, the key can be computed again without preprocessing.
+/// let dependencies = preprocessed.dependencies();
+/// assert_eq!(preprocessor.file_cache_key(&dependencies).unwrap(), key);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CacheKey(ContentHash);
+
+impl CacheKey {
+    /// The bytes of the hash.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        self.0.as_bytes()
+    }
+}
+
+/// The key is formatted as 64 hexadecimal digits.
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The SHA-256 hash function used for keys, so different inputs cannot be made to have the same
+/// key in practice.
+pub(super) struct KeyHasher {
+    hasher: Sha256,
+}
+
+impl KeyHasher {
+    pub(super) fn new() -> Self {
+        Self {
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Hash a sequence of bytes, preceded by its length so consecutive sequences cannot be
+    /// confused with each other.
+    pub(super) fn write(&mut self, bytes: &[u8]) {
+        self.hasher.update_framed(bytes);
+    }
+
+    fn write_path(&mut self, path: &Path) {
+        self.write(path.to_string_lossy().as_bytes());
+    }
+
+    pub(super) fn finish(self) -> CacheKey {
+        CacheKey(self.hasher.finish())
+    }
+}
+
+impl Preprocessor {
+    /// Compute the [`CacheKey`] of the result of preprocessing some source code with this
+    /// preprocessor.
+    ///
+    /// `preprocessed` must be the result of using this preprocessor, otherwise the key does not
+    /// describe it.
+    pub fn cache_key(&self, preprocessed: &Preprocessed) -> CacheKey {
+        let context = &preprocessed.context;
+        let mut hasher = self.options_hasher();
+
+        hasher.write_path(context.input.as_deref().unwrap_or(Path::new("")));
+        if let Some(source) = context.source {
+            hasher.write(&context.map.get_bytes(source));
+        }
+
+        let mut dependencies = preprocessed.dependencies();
+        if context.input.is_some() {
+            dependencies.remove(0);
+        }
+        for path in dependencies {
+            hasher.write_path(&path);
+            if let Some(span) = context.map.file_span(&path) {
+                hasher.write(&context.map.get_bytes(span));
+            }
+        }
+
+        hasher.finish()
+    }
+
+    /// Compute the [`CacheKey`] of preprocessing a file with this preprocessor from the files it
+    /// reads, without preprocessing it. `dependencies` must be the
+    /// [`dependencies`](Preprocessed::dependencies) of an earlier result of preprocessing the file,
+    /// starting with the file itself.
+    ///
+    /// If the contents of the dependencies did not change, the key is the same as the
+    /// [`cache_key`](Self::cache_key) of that result. This is not the case if any of the
+    /// [`missing_files`](Preprocessed::missing_files) has appeared since, so those must be
+    /// checked separately. Return an error if any of the files cannot be read.
+    pub fn file_cache_key<P: AsRef<Path>>(&self, dependencies: &[P]) -> io::Result<CacheKey> {
+        let mut hasher = self.options_hasher();
+        for path in dependencies {
+            let path = path.as_ref();
+            hasher.write_path(path);
+            let contents = self.file_system.read(path)?;
+            let contents = self
+                .input_charset
+                .decode_source(contents.into(), self.lexer_features())?;
+            hasher.write(&contents);
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Create a hasher that has already hashed the configuration of this preprocessor.
+    fn options_hasher(&self) -> KeyHasher {
+        let mut hasher = KeyHasher::new();
+        hasher.write(env!("CARGO_PKG_NAME").as_bytes());
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+
+        // The debug representations of the options only depend on the version of this crate.
+        let options = format!(
+            "{:?}",
+            (
+                (
+                    self.standard,
+                    &self.target,
+                    &self.feature_macros,
+                    &self.extensions,
+                    self.standard_macros_only,
+                    &self.command_line,
+                    &self.macro_files,
+                    &self.included_files,
+                ),
+                (
+                    &self.search_path,
+                    self.resolve_symlinks,
+                    self.keep_includes,
+                    self.concatenate_strings,
+                    self.max_include_depth,
+                    self.macro_dump,
+                    self.comments,
+                    &self.snapshot,
+                    self.evaluation,
+                    &self.prefix_map,
+                    &self.macro_prefix_map,
+                ),
+            )
+        );
+        hasher.write(options.as_bytes());
+        hasher
+    }
+}
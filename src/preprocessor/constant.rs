@@ -0,0 +1,359 @@
+//! The evaluation of the replacement lists of object-like macros as constants, the way binding
+//! generators turn macros into constants of other languages.
+use super::Preprocessed;
+use crate::{
+    c_token::{CToken, CTokenKind, CTokens, Keyword},
+    expr::{self, Evaluation, Value},
+    lexer::{Token, TokenKind},
+    macros::{MacroDefinition, MacroKind},
+    span::SourceMap,
+};
+
+/// The value of an object-like macro whose replacement list, after replacing the macros inside
+/// it, is a constant expression or a sequence of string literals.
+///
+/// Integer expressions are evaluated as if every signed type was `int64_t` and every unsigned
+/// type was `uint64_t`, and floating ones as if every floating type was `double`.
+///
+/// ```
+/// use beheader::MacroConstant;
+///
+/// let source = b"#define BASE 0x10\n#define SIZE (BASE * 2 + 1u)\n#define NAME \"a\" \"b\\n\"\n";
+/// let preprocessed = beheader::preprocess(source).unwrap();
+/// assert_eq!(preprocessed.macro_constant("SIZE"), Some(MacroConstant::Unsigned(33)));
+/// assert_eq!(preprocessed.macro_constant("NAME"), Some(MacroConstant::Str(b"ab\n".to_vec())));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroConstant {
+    /// An integer constant expression of a signed type, including character constants.
+    Signed(i64),
+    /// An integer constant expression of an unsigned type.
+    Unsigned(u64),
+    /// An arithmetic constant expression of a floating type.
+    Float(f64),
+    /// A sequence of string literals without encoding prefix or with the `u8` prefix, with the
+    /// escape sequences replaced and without the terminating null character.
+    Str(Vec<u8>),
+}
+
+impl Preprocessed {
+    /// Evaluate the replacement list of the object-like macro named `name` as a constant. Return
+    /// `None` if there is no such macro at the end of the input or if its replacement list is not
+    /// a constant that can be evaluated, like the ones with identifiers, casts or overflows, or
+    /// if replacing the macros inside it fails.
+    pub fn macro_constant(&self, name: &str) -> Option<MacroConstant> {
+        let symbol = self.context.map.find_symbol(name.as_bytes())?;
+        let (_, definition) = self.context.macros.get_key_value(symbol)?;
+        if !matches!(definition.kind, MacroKind::Object) {
+            return None;
+        }
+        let map = &self.context.map;
+        let name = Token::new(TokenKind::Ident, definition.span);
+        let expanded = self.context.macros.expand(map, &[name]).ok()?;
+        if !self.context.macros.take_errors().is_empty() {
+            return None;
+        }
+        let c_tokens = CTokens::convert(&expanded, map, self.context.standard);
+        let tokens = c_tokens.tokens();
+        if !c_tokens.diagnostics().is_empty() || tokens.is_empty() {
+            return None;
+        }
+
+        if tokens.iter().all(|token| token.kind() == CTokenKind::Str) {
+            let mut bytes = Vec::new();
+            for token in tokens {
+                let spelling = String::from_utf8_lossy(&map.get_bytes(token.span())).into_owned();
+                let (prefix, body) = spelling.split_once('"').unwrap();
+                if !matches!(prefix, "" | "u8") {
+                    return None;
+                }
+                let units = expr::decode(&body[..body.len() - 1], 8, &spelling).ok()?;
+                bytes.extend(units.into_iter().map(|unit| unit as u8));
+            }
+            return Some(MacroConstant::Str(bytes));
+        }
+
+        let mut evaluator = Evaluator {
+            map,
+            options: &self.context.evaluation,
+            tokens,
+            pos: 0,
+        };
+        let number = evaluator.conditional()?;
+        if evaluator.pos != tokens.len() {
+            return None;
+        }
+        Some(match number {
+            Number::Signed(value) => MacroConstant::Signed(value),
+            Number::Unsigned(value) => MacroConstant::Unsigned(value),
+            Number::Float(value) => MacroConstant::Float(value),
+        })
+    }
+
+    /// Return an iterator over the object-like macros defined at the end of the input whose
+    /// replacement list can be evaluated as a constant, sorted by name, together with their
+    /// values. This includes the predefined macros, which can be told apart using
+    /// [`MacroDefinition::is_builtin`].
+    pub fn macro_constants(&self) -> impl Iterator<Item = (MacroDefinition<'_>, MacroConstant)> {
+        self.macros().filter_map(|definition| {
+            let constant = self.macro_constant(definition.name())?;
+            Some((definition, constant))
+        })
+    }
+}
+
+/// The value of an arithmetic constant expression.
+#[derive(Clone, Copy)]
+enum Number {
+    Signed(i64),
+    Unsigned(u64),
+    Float(f64),
+}
+
+impl Number {
+    fn from_bool(value: bool) -> Self {
+        Self::Signed(value.into())
+    }
+
+    fn is_true(self) -> bool {
+        match self {
+            Self::Signed(value) => value != 0,
+            Self::Unsigned(value) => value != 0,
+            Self::Float(value) => value != 0.0,
+        }
+    }
+
+    /// Apply the usual arithmetic conversions to `lhs` and `rhs` (section 6.3.1.8 of C17).
+    fn convert(lhs: Self, rhs: Self) -> (Self, Self) {
+        match (lhs, rhs) {
+            (Self::Float(_), _) | (_, Self::Float(_)) => {
+                (Self::Float(lhs.as_f64()), Self::Float(rhs.as_f64()))
+            }
+            (Self::Unsigned(_), _) | (_, Self::Unsigned(_)) => {
+                (Self::Unsigned(lhs.as_u64()), Self::Unsigned(rhs.as_u64()))
+            }
+            _ => (lhs, rhs),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Self::Signed(value) => value as f64,
+            Self::Unsigned(value) => value as f64,
+            Self::Float(value) => value,
+        }
+    }
+
+    fn as_u64(self) -> u64 {
+        match self {
+            Self::Signed(value) => value as u64,
+            Self::Unsigned(value) => value,
+            Self::Float(value) => value as u64,
+        }
+    }
+}
+
+/// The binary operators and their precedence. Operators with higher precedence bind tighter.
+const OPERATORS: &[(&str, u8)] = &[
+    ("||", 1),
+    ("&&", 2),
+    ("|", 3),
+    ("^", 4),
+    ("&", 5),
+    ("==", 6),
+    ("!=", 6),
+    ("<", 7),
+    (">", 7),
+    ("<=", 7),
+    (">=", 7),
+    ("<<", 8),
+    (">>", 8),
+    ("+", 9),
+    ("-", 9),
+    ("*", 10),
+    ("/", 10),
+    ("%", 10),
+];
+
+/// A precedence climbing parser that evaluates a constant expression while parsing it. Every
+/// method returns `None` if the expression is not valid or cannot be evaluated.
+struct Evaluator<'a> {
+    map: &'a SourceMap,
+    options: &'a Evaluation,
+    tokens: &'a [CToken],
+    pos: usize,
+}
+
+impl Evaluator<'_> {
+    /// The spelling of the next token if it is a punctuator.
+    fn peek_punct(&self) -> Option<String> {
+        let token = self.tokens.get(self.pos)?;
+        let spelling = self.map.get_bytes(token.span());
+        (token.kind() == CTokenKind::Punct).then(|| String::from_utf8_lossy(&spelling).into())
+    }
+
+    /// Consume the next token if it is the punctuator `punct`.
+    fn eat(&mut self, punct: &str) -> bool {
+        let found = self.peek_punct().is_some_and(|spelling| spelling == punct);
+        self.pos += usize::from(found);
+        found
+    }
+
+    /// Parse and evaluate a conditional expression.
+    fn conditional(&mut self) -> Option<Number> {
+        let condition = self.binary(1)?;
+        if !self.eat("?") {
+            return Some(condition);
+        }
+        let then = self.conditional()?;
+        if !self.eat(":") {
+            return None;
+        }
+        let otherwise = self.conditional()?;
+        let (then, otherwise) = Number::convert(then, otherwise);
+        Some(if condition.is_true() { then } else { otherwise })
+    }
+
+    /// Parse and evaluate an expression whose binary operators have a precedence of at least
+    /// `min_precedence`.
+    fn binary(&mut self, min_precedence: u8) -> Option<Number> {
+        let mut lhs = self.unary()?;
+        while let Some((op, precedence)) = self.peek_punct().and_then(|spelling| {
+            OPERATORS
+                .iter()
+                .find(|(op, precedence)| *op == spelling && *precedence >= min_precedence)
+        }) {
+            self.pos += 1;
+            let rhs = self.binary(precedence + 1)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+        Some(lhs)
+    }
+
+    /// Parse and evaluate a unary expression.
+    fn unary(&mut self) -> Option<Number> {
+        let op = match ["+", "-", "~", "!"].into_iter().find(|op| self.eat(op)) {
+            Some(op) => op,
+            None => return self.primary(),
+        };
+        let operand = self.unary()?;
+        match (op, operand) {
+            ("+", operand) => Some(operand),
+            ("-", Number::Signed(value)) => value.checked_neg().map(Number::Signed),
+            ("-", Number::Unsigned(value)) => Some(Number::Unsigned(value.wrapping_neg())),
+            ("-", Number::Float(value)) => Some(Number::Float(-value)),
+            ("~", Number::Signed(value)) => Some(Number::Signed(!value)),
+            ("~", Number::Unsigned(value)) => Some(Number::Unsigned(!value)),
+            ("~", Number::Float(_)) => None,
+            (_, operand) => Some(Number::from_bool(!operand.is_true())),
+        }
+    }
+
+    /// Parse and evaluate a primary expression.
+    fn primary(&mut self) -> Option<Number> {
+        if self.eat("(") {
+            let number = self.conditional()?;
+            return self.eat(")").then_some(number);
+        }
+
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        match token.kind() {
+            CTokenKind::Integer(constant) => match i64::try_from(constant.value()) {
+                Ok(value) if !constant.is_unsigned() => Some(Number::Signed(value)),
+                _ => Some(Number::Unsigned(constant.value())),
+            },
+            CTokenKind::Floating(constant) => Some(Number::Float(constant.value())),
+            CTokenKind::Char => {
+                let spelling =
+                    String::from_utf8_lossy(&self.map.get_bytes(token.span())).into_owned();
+                match expr::parse_char(&spelling, self.options).ok()? {
+                    Value::Signed(value) => Some(Number::Signed(value)),
+                    Value::Unsigned(value) => Some(Number::Unsigned(value)),
+                }
+            }
+            CTokenKind::Keyword(Keyword::True) => Some(Number::from_bool(true)),
+            CTokenKind::Keyword(Keyword::False) => Some(Number::from_bool(false)),
+            _ => None,
+        }
+    }
+}
+
+/// Apply the binary operator `op`. Return `None` if the operation overflows, divides by zero or
+/// cannot be applied to floating operands.
+fn apply(op: &str, lhs: Number, rhs: Number) -> Option<Number> {
+    match op {
+        "&&" => return Some(Number::from_bool(lhs.is_true() && rhs.is_true())),
+        "||" => return Some(Number::from_bool(lhs.is_true() || rhs.is_true())),
+        // The type of the result of a shift is the type of the left operand.
+        "<<" | ">>" => {
+            let amount = match rhs {
+                Number::Signed(amount) => u32::try_from(amount).ok(),
+                Number::Unsigned(amount) => u32::try_from(amount).ok(),
+                Number::Float(_) => None,
+            }
+            .filter(|&amount| amount < 64)?;
+            return match (op, lhs) {
+                ("<<", Number::Signed(value)) => {
+                    let result = value << amount;
+                    (result >> amount == value).then_some(Number::Signed(result))
+                }
+                ("<<", Number::Unsigned(value)) => Some(Number::Unsigned(value << amount)),
+                (_, Number::Signed(value)) => Some(Number::Signed(value >> amount)),
+                (_, Number::Unsigned(value)) => Some(Number::Unsigned(value >> amount)),
+                (_, Number::Float(_)) => None,
+            };
+        }
+        _ => {}
+    }
+
+    match Number::convert(lhs, rhs) {
+        (Number::Signed(lhs), Number::Signed(rhs)) => match op {
+            "+" => lhs.checked_add(rhs),
+            "-" => lhs.checked_sub(rhs),
+            "*" => lhs.checked_mul(rhs),
+            "/" => lhs.checked_div(rhs),
+            "%" => lhs.checked_rem(rhs),
+            "&" => Some(lhs & rhs),
+            "^" => Some(lhs ^ rhs),
+            "|" => Some(lhs | rhs),
+            op => return Some(Number::from_bool(compare(op, lhs, rhs))),
+        }
+        .map(Number::Signed),
+        (Number::Unsigned(lhs), Number::Unsigned(rhs)) => match op {
+            // Unsigned arithmetic cannot overflow, it wraps around.
+            "+" => Some(lhs.wrapping_add(rhs)),
+            "-" => Some(lhs.wrapping_sub(rhs)),
+            "*" => Some(lhs.wrapping_mul(rhs)),
+            "/" => lhs.checked_div(rhs),
+            "%" => lhs.checked_rem(rhs),
+            "&" => Some(lhs & rhs),
+            "^" => Some(lhs ^ rhs),
+            "|" => Some(lhs | rhs),
+            op => return Some(Number::from_bool(compare(op, lhs, rhs))),
+        }
+        .map(Number::Unsigned),
+        (Number::Float(lhs), Number::Float(rhs)) => match op {
+            "+" => Some(lhs + rhs),
+            "-" => Some(lhs - rhs),
+            "*" => Some(lhs * rhs),
+            "/" => Some(lhs / rhs),
+            "%" | "&" | "^" | "|" => None,
+            op => return Some(Number::from_bool(compare(op, lhs, rhs))),
+        }
+        .map(Number::Float),
+        _ => unreachable!(),
+    }
+}
+
+/// Apply the relational or equality operator `op`.
+fn compare<T: PartialOrd>(op: &str, lhs: T, rhs: T) -> bool {
+    match op {
+        "==" => lhs == rhs,
+        "!=" => lhs != rhs,
+        "<" => lhs < rhs,
+        ">" => lhs > rhs,
+        "<=" => lhs <= rhs,
+        _ => lhs >= rhs,
+    }
+}
@@ -0,0 +1,215 @@
+//! SHA-256 hashes of the source code read while preprocessing, for freshness checks.
+use std::{fmt, path::PathBuf};
+
+use super::Preprocessed;
+
+/// The SHA-256 hash of some contents, like the contents of a file read while preprocessing,
+/// obtained using [`Preprocessed::file_hashes`] or [`Preprocessed::content_hash`].
+///
+/// Files are hashed after converting them to UTF-8 from the
+/// [`input_charset`](super::Preprocessor::input_charset), so a file can be checked for changes
+/// by comparing the hash of its converted contents with the hash of the result.
+///
+/// ```
+/// use beheader::{ContentHash, MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#include \"config.h\"\n");
+/// files.insert("config.h", "#define VERSION 1\n");
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files);
+/// let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+/// let hashes = preprocessed.file_hashes();
+/// assert_eq!(hashes[1], ("config.h".into(), ContentHash::of(b"#define VERSION 1\n")));
+/// assert_eq!(
+///     ContentHash::of(b"abc").to_string(),
+///     "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContentHash([u8; 32]);
+
+impl ContentHash {
+    /// Compute the hash of `contents`.
+    pub fn of(contents: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(contents);
+        hasher.finish()
+    }
+
+    /// The bytes of the hash.
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// The hash is formatted as 64 hexadecimal digits.
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The initial hash value of SHA-256, from section 5.3.3 of FIPS 180-4.
+const INITIAL: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The round constants of SHA-256, from section 4.2.2 of FIPS 180-4.
+const ROUNDS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The SHA-256 hash function of FIPS 180-4, which hashes its input in blocks of 64 bytes.
+pub(super) struct Sha256 {
+    state: [u32; 8],
+    /// The bytes of the current block that were not hashed yet.
+    block: Vec<u8>,
+    /// The number of bytes hashed so far.
+    len: u64,
+}
+
+impl Sha256 {
+    pub(super) fn new() -> Self {
+        Self {
+            state: INITIAL,
+            block: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+
+    /// Hash a sequence of bytes.
+    pub(super) fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if !self.block.is_empty() {
+            let taken = bytes.len().min(64 - self.block.len());
+            self.block.extend_from_slice(&bytes[..taken]);
+            bytes = &bytes[taken..];
+            if self.block.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.block);
+            self.compress(&block);
+            self.block = block;
+            self.block.clear();
+        }
+        let mut blocks = bytes.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        self.block.extend_from_slice(blocks.remainder());
+    }
+
+    /// Hash a sequence of bytes preceded by its length, so consecutive sequences cannot be
+    /// confused with each other.
+    pub(super) fn update_framed(&mut self, bytes: &[u8]) {
+        self.update(&(bytes.len() as u64).to_le_bytes());
+        self.update(bytes);
+    }
+
+    /// Pad the input as described in section 5.1.1 of FIPS 180-4 and produce the hash.
+    pub(super) fn finish(mut self) -> ContentHash {
+        let bits = self.len.wrapping_mul(8);
+        let mut padding = vec![0x80];
+        let zeros = (119 - self.len % 64) % 64;
+        padding.resize(1 + zeros as usize, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        self.update(&padding);
+        debug_assert!(self.block.is_empty());
+
+        let mut hash = [0; 32];
+        for (bytes, word) in hash.chunks_exact_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        ContentHash(hash)
+    }
+
+    /// Process a block of 64 bytes as described in section 6.2.2 of FIPS 180-4.
+    fn compress(&mut self, block: &[u8]) {
+        let mut schedule = [0u32; 64];
+        for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for t in 16..64 {
+            let (w15, w2) = (schedule[t - 15], schedule[t - 2]);
+            let s0 = w15.rotate_right(7) ^ w15.rotate_right(18) ^ (w15 >> 3);
+            let s1 = w2.rotate_right(17) ^ w2.rotate_right(19) ^ (w2 >> 10);
+            schedule[t] = schedule[t - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[t - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (&round, &word) in ROUNDS.iter().zip(&schedule) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(round)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+impl Preprocessed {
+    /// The hash of the contents of each file in [`dependencies`](Self::dependencies), in the
+    /// same order. The contents are hashed again every time this method is called.
+    pub fn file_hashes(&self) -> Vec<(PathBuf, ContentHash)> {
+        let map = &self.context.map;
+        self.dependencies()
+            .into_iter()
+            .map(|path| {
+                let hash = match map.file_span(&path) {
+                    Some(span) => ContentHash::of(&map.get_bytes(span)),
+                    None => ContentHash::of(&[]),
+                };
+                (path, hash)
+            })
+            .collect()
+    }
+
+    /// The hash of all the source code read while preprocessing: the input, even if it was not a
+    /// file, and the path and contents of each file in [`dependencies`](Self::dependencies). It
+    /// changes if any of them changes, unlike the [`CacheKey`](super::CacheKey) it does not
+    /// depend on the configuration of the [`Preprocessor`](super::Preprocessor).
+    pub fn content_hash(&self) -> ContentHash {
+        let context = &self.context;
+        let mut hasher = Sha256::new();
+        match context.source.filter(|_| context.input.is_none()) {
+            Some(source) => hasher.update_framed(&context.map.get_bytes(source)),
+            None => hasher.update_framed(&[]),
+        }
+        for (path, hash) in self.file_hashes() {
+            hasher.update_framed(path.to_string_lossy().as_bytes());
+            hasher.update(hash.as_bytes());
+        }
+        hasher.finish()
+    }
+}
@@ -0,0 +1,177 @@
+//! Reports of the conditional inclusion directives found while preprocessing.
+use std::path::PathBuf;
+
+use super::{Context, Event, Preprocessed};
+use crate::{
+    lexer::{Token, TokenKind},
+    span::SourceMap,
+};
+
+/// A conditional inclusion directive and all its groups, from the `#if`, `#ifdef` or `#ifndef`
+/// that starts it to the `#endif` that ends it (section 6.10.1 of C17).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConditionalSection {
+    branches: Vec<Branch>,
+}
+
+impl ConditionalSection {
+    /// The branches of the section, in the order they were found. Each branch corresponds to an
+    /// `#if`, `#ifdef`, `#ifndef`, `#elif` or `#else` directive.
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    /// The branch whose group was processed, if any.
+    pub fn taken(&self) -> Option<&Branch> {
+        self.branches.iter().find(|branch| branch.taken)
+    }
+}
+
+/// A directive that starts a group inside a [`ConditionalSection`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Branch {
+    directive: String,
+    condition: String,
+    file: Option<PathBuf>,
+    line: Option<usize>,
+    value: Option<bool>,
+    taken: bool,
+}
+
+impl Branch {
+    /// The name of the directive, such as `if` or `else`.
+    pub fn directive(&self) -> &str {
+        &self.directive
+    }
+
+    /// The controlling expression of an `#if` or `#elif` directive or the macro name of an
+    /// `#ifdef` or `#ifndef` directive, as written in the source before macro replacement. It is
+    /// empty for `#else` directives.
+    pub fn condition(&self) -> &str {
+        &self.condition
+    }
+
+    /// The file where the directive was found, if it was found in a file.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    /// The line where the directive was found, starting from 1, if it was found in a file.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// Whether the group of the branch is processed according to the condition, or `None` if the
+    /// condition was not evaluated. Conditions are not evaluated if the section is inside a group
+    /// that is skipped or a previous branch was taken already. The condition of an `#ifndef`
+    /// directive is `true` if the macro is not defined.
+    ///
+    /// It is always `None` for `#else` directives.
+    pub fn value(&self) -> Option<bool> {
+        self.value
+    }
+
+    /// Whether the group of the branch was processed.
+    pub fn is_taken(&self) -> bool {
+        self.taken
+    }
+}
+
+/// A [`Branch`] whose location has not been resolved yet.
+pub(super) struct BranchRecord {
+    directive: Token,
+    condition: String,
+    value: Option<bool>,
+    taken: bool,
+}
+
+impl BranchRecord {
+    /// The name of the directive of the branch.
+    pub(super) fn directive(&self) -> Token {
+        self.directive
+    }
+
+    pub(super) fn is_taken(&self) -> bool {
+        self.taken
+    }
+
+    /// The tokens of the directive after its name, separated by single spaces.
+    pub(super) fn condition(&self) -> &str {
+        &self.condition
+    }
+
+    /// Find the location of the directive of the branch in `map`.
+    fn resolve(&self, map: &SourceMap) -> Branch {
+        Branch {
+            directive: map.spelling(self.directive),
+            condition: self.condition.clone(),
+            file: map.find_file(self.directive.span),
+            line: map.find_line(self.directive.span),
+            value: self.value,
+            taken: self.taken,
+        }
+    }
+}
+
+impl Context {
+    /// Record a branch found in the directive named `directive` whose tokens after the name are
+    /// `body`. A new section is started if `section` is `None`.
+    ///
+    /// Return the index of the section of the branch.
+    pub(super) fn record_branch(
+        &mut self,
+        section: Option<usize>,
+        directive: Token,
+        body: &[Token],
+        value: Option<bool>,
+        taken: bool,
+    ) -> usize {
+        let mut condition = String::new();
+        for &token in body {
+            match token.kind {
+                TokenKind::Space => {
+                    if !condition.is_empty() && !condition.ends_with(' ') {
+                        condition.push(' ');
+                    }
+                }
+                _ => condition.push_str(&self.map.spelling(token)),
+            }
+        }
+        let condition = condition.trim_end().to_owned();
+
+        let record = BranchRecord {
+            directive,
+            condition,
+            value,
+            taken,
+        };
+        if !taken {
+            self.event(|context| Event::ConditionalSkipped(record.resolve(&context.map)));
+        }
+        match section {
+            Some(section) => {
+                self.sections[section].push(record);
+                section
+            }
+            None => {
+                self.sections.push(vec![record]);
+                self.sections.len() - 1
+            }
+        }
+    }
+}
+
+impl Preprocessed {
+    /// The conditional inclusion directives found while preprocessing, in the order they
+    /// started, including the ones inside groups that were skipped.
+    pub fn conditionals(&self) -> Vec<ConditionalSection> {
+        let map = &self.context.map;
+        self.context
+            .sections
+            .iter()
+            .map(|records| ConditionalSection {
+                branches: records.iter().map(|record| record.resolve(map)).collect(),
+            })
+            .collect()
+    }
+}
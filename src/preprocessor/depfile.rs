@@ -0,0 +1,224 @@
+//! Dependency files in the syntax of `make`.
+use std::{fs, io, path::Path, path::PathBuf};
+
+use super::Preprocessed;
+
+/// The configuration of a dependency file, which lists the files read while preprocessing as
+/// the prerequisites of a `make` rule. This is equivalent to the `-M` family of command-line
+/// options of most C compilers.
+///
+/// ```
+/// use beheader::{Depfile, MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#include \"my header.h\"\n").insert("my header.h", "");
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files);
+/// let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+///
+/// let depfile = Depfile::new().phony_targets(true).render(&preprocessed);
+/// assert_eq!(depfile, "main.o: main.c my\\ header.h\nmy\\ header.h:\n");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Depfile {
+    targets: Vec<String>,
+    phony: bool,
+    skip_system_headers: bool,
+}
+
+impl Depfile {
+    /// Create a new configuration with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a target to the rule, written exactly as given. This is equivalent to the `-MT`
+    /// command-line option of most C compilers.
+    ///
+    /// If no targets are given, the target is the name of the input file without its directory
+    /// and with its extension replaced by `.o`, or `-` if the input was not a file.
+    pub fn target(&mut self, target: &str) -> &mut Self {
+        self.targets.push(target.to_owned());
+        self
+    }
+
+    /// Add a target to the rule, escaping the characters that are special to `make`. This is
+    /// equivalent to the `-MQ` command-line option of most C compilers.
+    pub fn quoted_target(&mut self, target: &str) -> &mut Self {
+        self.targets.push(escape(target));
+        self
+    }
+
+    /// Set whether a rule without prerequisites is added for each dependency other than the
+    /// input file, so `make` does not fail when one of them is removed. This is equivalent to
+    /// the `-MP` command-line option of most C compilers.
+    ///
+    /// By default, these rules are not added.
+    pub fn phony_targets(&mut self, enabled: bool) -> &mut Self {
+        self.phony = enabled;
+        self
+    }
+
+    /// Set whether system headers are omitted from the dependencies. These are the files found
+    /// in the directories given to
+    /// [`system_include_dir`](super::Preprocessor::system_include_dir) and
+    /// [`include_dir_after`](super::Preprocessor::include_dir_after) and any file they include.
+    /// This is equivalent to the `-MM` command-line option of most C compilers.
+    ///
+    /// By default, system headers are listed.
+    pub fn skip_system_headers(&mut self, enabled: bool) -> &mut Self {
+        self.skip_system_headers = enabled;
+        self
+    }
+
+    /// Produce the contents of the dependency file for the result of a preprocessing run.
+    pub fn render(&self, preprocessed: &Preprocessed) -> String {
+        let targets = if self.targets.is_empty() {
+            let target = match &preprocessed.context.input {
+                Some(input) => {
+                    let mut object = PathBuf::from(input.file_name().unwrap_or_default());
+                    object.set_extension("o");
+                    escape(&object.to_string_lossy())
+                }
+                None => "-".to_owned(),
+            };
+            vec![target]
+        } else {
+            self.targets.clone()
+        };
+
+        let system_headers = &preprocessed.context.system_headers;
+        let dependencies: Vec<String> = preprocessed
+            .dependencies()
+            .iter()
+            .filter(|path| !self.skip_system_headers || !system_headers.contains(*path))
+            .map(|path| {
+                let path = preprocessed.context.map.display_path(path);
+                escape(&path.to_string_lossy())
+            })
+            .collect();
+
+        // Long lines are split after a backslash, as GCC does.
+        let mut output = format!("{}:", targets.join(" "));
+        let mut width = output.len();
+        for dependency in &dependencies {
+            if width + dependency.len() + 1 > 75 {
+                output.push_str(" \\\n");
+                width = 0;
+            }
+            output.push(' ');
+            output.push_str(dependency);
+            width += dependency.len() + 1;
+        }
+        output.push('\n');
+
+        if self.phony {
+            let skip = usize::from(preprocessed.context.input.is_some());
+            for dependency in dependencies.iter().skip(skip) {
+                output.push_str(&format!("{}:\n", dependency));
+            }
+        }
+
+        output
+    }
+
+    /// Write the dependency file for the result of a preprocessing run to the file at `path`.
+    /// This is equivalent to the `-MF` command-line option of most C compilers.
+    pub fn write_to<P: AsRef<Path>>(&self, preprocessed: &Preprocessed, path: P) -> io::Result<()> {
+        fs::write(path, self.render(preprocessed))
+    }
+}
+
+/// Escape the characters of `path` that are special to `make`, as GCC does.
+fn escape(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    let mut backslashes = 0;
+    for c in path.chars() {
+        match c {
+            // Backslashes are only special before spaces.
+            ' ' | '\t' => {
+                escaped.extend(std::iter::repeat_n('\\', backslashes + 1));
+            }
+            '$' => escaped.push('$'),
+            '#' => escaped.push('\\'),
+            _ => {}
+        }
+        backslashes = if c == '\\' { backslashes + 1 } else { 0 };
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Preprocessed {
+    /// The paths of every file read while preprocessing, starting with the input file if it was
+    /// a file. This includes the files from the command line and the ones included by `#include`
+    /// directives but not the contents provided by
+    /// [`include_hook`](super::Preprocessor::include_hook).
+    pub fn dependencies(&self) -> Vec<PathBuf> {
+        let context = &self.context;
+        let mut files = context.map.files_read();
+        if let Some(input) = &context.input {
+            files.retain(|file| file != input);
+            files.insert(0, input.clone());
+        }
+        files
+    }
+
+    /// The paths searched for the files included by `#include` directives where no file was
+    /// found, in the order they were searched. If a file appeared at any of these paths, the
+    /// result of preprocessing could change, so build systems should consider them dependencies
+    /// too.
+    pub fn missing_files(&self) -> &[PathBuf] {
+        &self.context.missing_files
+    }
+
+    /// The files whose changes make the result outdated: the [`dependencies`](Self::dependencies)
+    /// that changed or cannot be read anymore since they were read and the
+    /// [`missing_files`](Self::missing_files) that appeared since. Files whose
+    /// [`FileMetadata`](crate::FileMetadata) did not change are not read again.
+    ///
+    /// ```
+    /// use beheader::{MemoryFileSystem, Preprocessor};
+    ///
+    /// let mut files = MemoryFileSystem::new();
+    /// files.insert("main.c", "#include \"config.h\"\n");
+    /// files.insert("config.h", "#define VERSION 1\n");
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.file_system(files);
+    /// let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    /// assert!(preprocessed.stale_files().is_empty());
+    /// ```
+    ///
+    /// The files are checked using the [`FileSystem`](crate::FileSystem) used to read them, so
+    /// the result of preprocessing with a [`MemoryFileSystem`](crate::MemoryFileSystem) never
+    /// changes unless it is on top of another file system.
+    pub fn stale_files(&self) -> Vec<PathBuf> {
+        let map = &self.context.map;
+        let changed = self
+            .dependencies()
+            .into_iter()
+            .filter(|path| map.is_stale(path));
+        let appeared = self
+            .missing_files()
+            .iter()
+            .filter(|path| map.file_system().is_file(path))
+            .cloned();
+        changed.chain(appeared).collect()
+    }
+
+    /// Remove the contents of the [`stale_files`](Self::stale_files) that were read from `cache`,
+    /// which must be the [`HeaderCache`](super::HeaderCache) used to preprocess, so they do not
+    /// stay in memory after preprocessing again. The contents that are still used are stored
+    /// again when they are read. Return the stale files.
+    pub fn invalidate_stale(&self, cache: &super::HeaderCache) -> Vec<PathBuf> {
+        let map = &self.context.map;
+        let stale = self.stale_files();
+        for path in &stale {
+            if let Some(span) = map.file_span(path) {
+                cache.remove(&map.get_bytes(span));
+            }
+        }
+        stale
+    }
+}
@@ -0,0 +1,1246 @@
+//! The textual representation of the result of preprocessing.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+use super::{
+    mapping::PositionMap,
+    rewrite::{count_lines, line_marker},
+    Context, Event, MacroDump, Preprocessed, Preprocessor,
+};
+use crate::{
+    buffer::TokenBuffer,
+    diagnostic::Diagnostic,
+    error::Error,
+    lexer::{tokenized_apart, Token, TokenKind},
+    span::{PresumedLine, Span},
+    time::Instant,
+};
+
+/// The maximum number of tokens that can be written together and be tokenized as a single one,
+/// as in `%:%:`.
+const MAX_PASTED: usize = 4;
+
+/// The maximum number of blank lines written to keep the line numbers right. A line marker is
+/// written instead if more are needed.
+const MAX_BLANK_LINES: usize = 8;
+
+/// The maximum number of blank lines written by `gcc -E` to keep the line numbers right.
+const GCC_MAX_BLANK_LINES: usize = 7;
+
+/// The number of output tokens written at a time when streaming.
+const STREAM_CHUNK: usize = 4096;
+
+/// The configuration used to write the result of preprocessing as text, which can be given to
+/// a compiler.
+///
+/// White-space is written as a single space and a space is added between tokens that would
+/// otherwise be tokenized differently, so the text has exactly the same tokens as the result of
+/// preprocessing. The comments kept using
+/// [`keep_comments`](super::Preprocessor::keep_comments) are written as they are.
+///
+/// By default, the text has GNU-style line markers like `# 12 "foo.h" 1` so the compiler reports
+/// the locations of the original source code: the line number of the next line and the path of
+/// its file, followed by `1` when entering an included file, `2` when returning to the including
+/// file and `3` for system headers.
+///
+/// ```
+/// use beheader::{Emitter, Preprocessor};
+///
+/// let source = b"#define PLUS +\n#define EMPTY\nx = PLUS+y /* comment */ EMPTY;\n";
+/// let preprocessed = Preprocessor::new().preprocess(source).unwrap();
+/// let text = Emitter::new().render(&preprocessed);
+/// assert_eq!(text, b"# 1 \"<stdin>\"\n\n\nx = + +y ;\n");
+///
+/// let text = Emitter::new().line_markers(false).render(&preprocessed);
+/// assert_eq!(text, b"x = + +y ;\n");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    line_markers: bool,
+    profile: OutputProfile,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            line_markers: true,
+            profile: OutputProfile::default(),
+        }
+    }
+}
+
+/// The conventions followed by the text written by an [`Emitter`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OutputProfile {
+    /// The conventions described in the documentation of [`Emitter`], which keep the text as
+    /// short as possible.
+    #[default]
+    Beheader,
+    /// The conventions of `gcc -E`, so the text can be compared with the one produced by GCC
+    /// byte for byte.
+    ///
+    /// The text starts with the line markers of `<built-in>` and `<command-line>` with line
+    /// number 0, as done since GCC 11, and the files included using
+    /// [`include`](super::Preprocessor::include) are entered from `<command-line>`. The lines
+    /// keep the indentation of their first token. Runs of more than 7 blank lines are replaced by
+    /// line markers and spaces are added between tokens coming from different macro replacements
+    /// using the same rules as GCC, even if they would not be tokenized differently. System
+    /// headers are marked with the flags `3 4` and the lines where the tokens go from a system
+    /// header to a file that is not, or the other way around, are repeated after a line marker.
+    ///
+    /// Files whose whole contents are skipped by an include guard are only entered the first time
+    /// they are included. Tokens produced by a macro invocation are written in the line of the
+    /// invocation and the tokens that appear in a later line, like the ones after an invocation
+    /// whose arguments span several lines, start a new line.
+    ///
+    /// ```
+    /// use beheader::{Emitter, OutputProfile, Preprocessor};
+    ///
+    /// let source = b"#define ONE 1\nint f() {\n    return ONE+ONE;\n}\n";
+    /// let preprocessed = Preprocessor::new().preprocess(source).unwrap();
+    /// let text = Emitter::new().profile(OutputProfile::Gcc).render(&preprocessed);
+    /// assert_eq!(
+    ///     String::from_utf8(text).unwrap().lines().collect::<Vec<_>>(),
+    ///     [
+    ///         "# 0 \"<stdin>\"",
+    ///         "# 0 \"<built-in>\"",
+    ///         "# 0 \"<command-line>\"",
+    ///         "# 1 \"<stdin>\"",
+    ///         "",
+    ///         "int f() {",
+    ///         "    return 1 +1;",
+    ///         "}",
+    ///     ]
+    /// );
+    /// ```
+    Gcc,
+}
+
+impl Emitter {
+    /// Create a new configuration with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write line markers, which is enabled by default. If disabled, runs of blank lines are
+    /// written as a single one and the blank lines at the start are omitted. Disabling this is
+    /// equivalent to the `-P` command-line option.
+    pub fn line_markers(&mut self, enabled: bool) -> &mut Self {
+        self.line_markers = enabled;
+        self
+    }
+
+    /// Set the conventions followed by the text, which are the ones of
+    /// [`OutputProfile::Beheader`] by default.
+    pub fn profile(&mut self, profile: OutputProfile) -> &mut Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Produce the text of the result of a preprocessing run.
+    pub fn render(&self, preprocessed: &Preprocessed) -> Vec<u8> {
+        let mut text = Vec::new();
+        self.render_to(preprocessed, &mut text).unwrap();
+        text
+    }
+
+    /// Produce the text of the result of a preprocessing run and the locations in the source code
+    /// of its tokens.
+    pub fn render_with_map(&self, preprocessed: &Preprocessed) -> (Vec<u8>, PositionMap) {
+        let context = &preprocessed.context;
+        let mut renderer = Renderer::new(self);
+        renderer.map = Some(PositionMap::default());
+        renderer.write(context, context.output.tokens());
+        renderer.finish(context);
+        (renderer.text, renderer.map.unwrap_or_default())
+    }
+
+    /// Write the text of the result of a preprocessing run to `writer`, a few lines at a time.
+    pub fn render_to<W: Write>(
+        &self,
+        preprocessed: &Preprocessed,
+        mut writer: W,
+    ) -> io::Result<()> {
+        let context = &preprocessed.context;
+        let mut renderer = Renderer::new(self);
+        for tokens in context.output.tokens().chunks(STREAM_CHUNK) {
+            renderer.write(context, tokens);
+            renderer.flush(&mut writer)?;
+        }
+        renderer.finish(context);
+        renderer.flush(&mut writer)
+    }
+
+    /// Write the text of the result of a preprocessing run to the file at `path`.
+    pub fn write_to<P: AsRef<Path>>(&self, preprocessed: &Preprocessed, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.render_to(preprocessed, &mut writer)?;
+        writer.flush()
+    }
+
+    /// Preprocess a sequence of bytes using `preprocessor` and write the text of the result to
+    /// `writer` while it is being produced, so the output is never kept in memory as a whole.
+    ///
+    /// The text is the same one [`render`](Self::render) would produce. The output is not part
+    /// of the result, so rendering it produces no text, but everything else is. If the macros are
+    /// dumped using [`MacroDump::Final`], the text is only written once the input has been
+    /// processed.
+    ///
+    /// Return an error if any of the files included before processing cannot be read, if the
+    /// source code is not valid or if writing fails.
+    ///
+    /// ```
+    /// use beheader::{Emitter, Preprocessor};
+    ///
+    /// let mut text = Vec::new();
+    /// let source = b"#define A 1\nint a = A;\n";
+    /// Emitter::new()
+    ///     .line_markers(false)
+    ///     .stream(&Preprocessor::new(), source, &mut text)
+    ///     .unwrap();
+    /// assert_eq!(text, b"int a = 1;\n");
+    /// ```
+    ///
+    /// [`MacroDump::Final`]: super::MacroDump::Final
+    pub fn stream<W: Write>(
+        &self,
+        preprocessor: &Preprocessor,
+        source: &[u8],
+        mut writer: W,
+    ) -> Result<Preprocessed, Error> {
+        let mut context = preprocessor.context()?;
+        let span = context.map.store_bytes(source)?;
+        context.source = Some(span);
+        self.stream_context(preprocessor, context, span, &mut writer)
+    }
+
+    /// Preprocess a file using `preprocessor` and write the text of the result to `writer` while
+    /// it is being produced, like [`stream`](Self::stream) does.
+    pub fn stream_file<P: AsRef<Path>, W: Write>(
+        &self,
+        preprocessor: &Preprocessor,
+        path: &P,
+        mut writer: W,
+    ) -> Result<Preprocessed, Error> {
+        let mut context = preprocessor.context()?;
+        let span = context.map.read_file(path)?;
+        context.input = context.map.find_file(span);
+        context.source = Some(span);
+        self.stream_context(preprocessor, context, span, &mut writer)
+    }
+
+    /// Process the input in the region `span` using `context` and write the text of the result
+    /// to `writer`.
+    fn stream_context(
+        &self,
+        preprocessor: &Preprocessor,
+        mut context: Context,
+        span: Span,
+        writer: &mut dyn Write,
+    ) -> Result<Preprocessed, Error> {
+        // The final macro dump replaces the whole output.
+        if preprocessor.macro_dump == Some(MacroDump::Final) {
+            context.process(span)?;
+            let preprocessed = preprocessor.finish(context)?;
+            self.render_to(&preprocessed, writer)?;
+            return Ok(preprocessed);
+        }
+
+        context.stream = Some(Stream {
+            renderer: Renderer::new(self),
+            writer: BorrowedWriter::new(writer),
+            error: None,
+        });
+        let result = context.process(span);
+        // The writer is not used anymore once the stream is removed from the context.
+        let mut stream = context.stream.take().unwrap();
+        result?;
+
+        let output = std::mem::take(&mut context.output);
+        stream.renderer.write(&context, output.tokens());
+        stream.renderer.finish(&context);
+        if let Some(error) = stream.error {
+            return Err(error.into());
+        }
+        stream.renderer.flush(writer)?;
+        writer.flush()?;
+
+        // The files and the macro invocations refer to the tokens that are not kept.
+        context.output_files.clear();
+        context.expanded.clear();
+        context.streamed = 0;
+        preprocessor.finish(context)
+    }
+}
+
+/// A file processed into the output.
+pub(super) struct OutputFile {
+    /// The region of the file.
+    span: Span,
+    /// The number of files being processed when the file was entered.
+    depth: usize,
+    /// The output tokens produced while processing the file, including the ones of the files it
+    /// included. The end is `usize::MAX` until the file has been processed.
+    tokens: Range<usize>,
+    /// The index in [`Context::sections`] of the first conditional inclusion directive that can
+    /// be found in the file.
+    sections: usize,
+}
+
+/// The destination of the text of the output while it is being produced.
+pub(super) struct Stream {
+    renderer: Renderer,
+    writer: BorrowedWriter,
+    /// The first error returned by the writer, after which nothing else is written.
+    error: Option<io::Error>,
+}
+
+/// The writer given to [`Emitter::stream`] or [`Emitter::stream_file`], borrowed while the
+/// input is processed. Its lifetime is erased so it can be stored in the [`Context`] of the run,
+/// which is never moved to another thread while it is there.
+struct BorrowedWriter(NonNull<dyn Write>);
+
+// SAFETY: The writer is only used by the thread that called `Emitter::stream_context`, which
+// removes it from the context before returning.
+unsafe impl Send for BorrowedWriter {}
+
+impl BorrowedWriter {
+    fn new(writer: &mut dyn Write) -> Self {
+        // SAFETY: Only the lifetime of the reference changes, and the writer is not used after
+        // `Emitter::stream_context` returns, while it is still borrowed.
+        let writer: &mut (dyn Write + 'static) = unsafe { std::mem::transmute(writer) };
+        Self(NonNull::from(writer))
+    }
+
+    fn get(&mut self) -> &mut dyn Write {
+        // SAFETY: The writer is borrowed for as long as this is in the context of the run.
+        unsafe { self.0.as_mut() }
+    }
+}
+
+impl Context {
+    /// Process the file in the region `span` with `depth` other files being processed and add
+    /// the result to `output`, recording which tokens it produced.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(file = %self.trace_file(span), depth = depth)
+        )
+    )]
+    pub(super) fn process_file(
+        &mut self,
+        span: Span,
+        depth: usize,
+        output: &mut TokenBuffer,
+    ) -> Result<(), Diagnostic> {
+        let index = self.output_files.len();
+        let start = self.streamed + output.tokens().len();
+        self.output_files.push(OutputFile {
+            span,
+            depth,
+            tokens: start..usize::MAX,
+            sections: self.sections.len(),
+        });
+        self.event(|context| Event::EnterFile(context.map.find_file(span)));
+        let processing = Instant::now();
+        let result = self.process_into(span, output);
+        if result.is_ok() {
+            self.event(|context| Event::ExitFile(context.map.find_file(span)));
+        }
+        self.output_files[index].tokens.end = self.streamed + output.tokens().len();
+        self.trace_event("Source", processing, |context| context.trace_file(span));
+        result
+    }
+
+    /// Get the region of the innermost file whose processing produced the output token at
+    /// `index`, if it is known.
+    pub(super) fn output_file(&self, index: usize) -> Option<Span> {
+        // Files are entered after the files that include them, whose tokens contain theirs.
+        let file = self
+            .output_files
+            .iter()
+            .rev()
+            .find(|file| file.tokens.contains(&index))?;
+        Some(file.span)
+    }
+
+    /// Update the tokens produced by each file after moving each output token that was not
+    /// written to the stream yet to the index given by `indices`, relative to the first token
+    /// that was not written.
+    pub(super) fn move_output(&mut self, indices: &[usize]) {
+        for file in &mut self.output_files {
+            for index in [&mut file.tokens.start, &mut file.tokens.end] {
+                if *index != usize::MAX && *index >= self.streamed {
+                    *index = self.streamed + indices[*index - self.streamed];
+                }
+            }
+        }
+    }
+
+    /// Write the tokens of `output`, the output of the run, to the stream and remove them, if
+    /// there is a stream and enough of them. They are sent as events instead if the events of
+    /// the run are sent somewhere.
+    ///
+    /// Return an error if the string literals of the tokens cannot be concatenated.
+    pub(super) fn stream_output(&mut self, output: &mut TokenBuffer) -> Result<(), Diagnostic> {
+        if self.events.is_some() {
+            return self.send_events(output);
+        }
+        if output.tokens().len() < STREAM_CHUNK || self.stream.is_none() {
+            return Ok(());
+        }
+        // The string literals at the end could be concatenated with the ones that come next.
+        let len = match self.concatenate_strings {
+            true => {
+                self.concatenate_strings(output)?;
+                let tokens = output.tokens();
+                let last = tokens.iter().rposition(|token| {
+                    !matches!(token.kind, TokenKind::Space | TokenKind::Newline)
+                });
+                match last {
+                    Some(last) if tokens[last].kind == TokenKind::Str => last,
+                    _ => tokens.len(),
+                }
+            }
+            false => output.tokens().len(),
+        };
+        let mut stream = self.stream.take().unwrap();
+        let tokens = output.take_front(len);
+        self.streamed += tokens.len();
+        stream.renderer.write(self, &tokens);
+        if stream.error.is_none() {
+            stream.error = stream.renderer.flush(stream.writer.get()).err();
+        } else {
+            stream.renderer.text.clear();
+        }
+        self.stream = Some(stream);
+        Ok(())
+    }
+}
+
+/// A file whose tokens are being written.
+struct OpenFile {
+    /// The index of the file in [`Context::output_files`].
+    file: usize,
+    /// The region of the file.
+    span: Span,
+    path: PathBuf,
+    /// The flags added to every line marker of the file.
+    flags: &'static str,
+    /// The position in the file up to which the lines were counted.
+    pos: usize,
+    /// The number of the line starting at `pos`.
+    line: usize,
+    /// Whether the file was included before the input and is entered from `<command-line>`.
+    command_line: bool,
+}
+
+/// The state of writing the text of an output, which is given to it a few tokens at a time.
+struct Renderer {
+    emitter: Emitter,
+    /// The text that has not been given to the writer yet.
+    text: Vec<u8>,
+    /// Whether any text has been given to the writer.
+    flushed: bool,
+    /// The tokens written since the last white-space, at most `MAX_PASTED - 1` of them.
+    adjacent: Vec<Vec<u8>>,
+    /// Whether white-space must be written before the next token. Spaces are only written
+    /// before tokens, so lines never end with white-space.
+    space: bool,
+    /// Whether nothing has been written in the current line yet.
+    line_start: bool,
+    /// Whether the last line written was blank.
+    blank: bool,
+    /// The line number the compiler gives to the current line.
+    line: usize,
+    /// The index in [`Context::output_files`] of the next file to enter.
+    next_file: usize,
+    /// The files being written, from the outermost to the innermost.
+    stack: Vec<OpenFile>,
+    /// Whether the line marker of the return to the innermost file must be written.
+    returned: bool,
+    /// The line number the current line must have once something is written on it.
+    synced_line: Option<usize>,
+    /// The index in the output of the next token to write.
+    index: usize,
+    /// The last token given, which is only written once the next one is known.
+    pending: Option<Token>,
+    /// The locations of the tokens written so far, if they are recorded.
+    map: Option<PositionMap>,
+    /// The line of the text being written, starting from 1.
+    output_line: usize,
+    /// The number of bytes written in the current line of the text.
+    output_column: usize,
+    /// The last token written in the current line that is not white-space.
+    previous: Option<Token>,
+    /// The white-space before the first token of the current line.
+    indent: Option<Span>,
+    /// Whether the current line goes on with the line of the file after the `#pragma` directive
+    /// of a `_Pragma` operator.
+    after_pragma: bool,
+    /// Whether the last token that is not white-space was written while writing a system header.
+    system_token: bool,
+    /// The index in the output of the first token after the last file that is not entered,
+    /// whose tokens are not written.
+    skipped_until: usize,
+}
+
+impl Renderer {
+    fn new(emitter: &Emitter) -> Self {
+        Self {
+            emitter: emitter.clone(),
+            text: Vec::new(),
+            flushed: false,
+            adjacent: Vec::new(),
+            space: false,
+            line_start: true,
+            blank: false,
+            line: 1,
+            next_file: 0,
+            stack: Vec::new(),
+            returned: false,
+            synced_line: None,
+            index: 0,
+            pending: None,
+            map: None,
+            output_line: 1,
+            output_column: 0,
+            previous: None,
+            indent: None,
+            after_pragma: false,
+            system_token: false,
+            skipped_until: 0,
+        }
+    }
+
+    /// Write `tokens`, the next tokens of the output of `context`.
+    fn write(&mut self, context: &Context, tokens: &[Token]) {
+        for &token in tokens {
+            if let Some(pending) = self.pending.replace(token) {
+                self.write_token(context, pending, Some(&token));
+            }
+        }
+    }
+
+    /// Write the rest of the output of `context`, once all of its tokens were given.
+    fn finish(&mut self, context: &Context) {
+        if let Some(pending) = self.pending.take() {
+            self.write_token(context, pending, None);
+        }
+        self.enter_files(context, self.index);
+        while !self.stack.is_empty() {
+            self.leave_file();
+        }
+        self.write_return();
+    }
+
+    /// Give the text written so far to `writer`.
+    fn flush<W: Write + ?Sized>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.flushed |= !self.text.is_empty();
+        writer.write_all(&self.text)?;
+        self.text.clear();
+        Ok(())
+    }
+
+    /// Add `bytes` to the text.
+    fn push(&mut self, bytes: &[u8]) {
+        self.text.extend_from_slice(bytes);
+        match bytes.iter().rposition(|&byte| byte == b'\n') {
+            Some(pos) => {
+                self.output_line += count_lines(bytes);
+                self.output_column = bytes.len() - pos - 1;
+            }
+            None => self.output_column += bytes.len(),
+        }
+    }
+
+    /// Find the file, line and column where the token in the region `span` of the source map of
+    /// `context` is spelled.
+    fn location(
+        &self,
+        context: &Context,
+        span: Span,
+    ) -> (Option<PathBuf>, Option<usize>, Option<usize>) {
+        // Most tokens come from the innermost file, after the lines counted so far. Otherwise,
+        // the lines of the file are counted from its start.
+        let found = self.stack.iter().rev().find_map(|open| {
+            if span.lo() >= open.pos && span.hi() <= open.span.hi() {
+                Some((open, open.pos, open.line))
+            } else if span.lo() >= open.span.lo() && span.hi() <= open.span.hi() {
+                Some((open, open.span.lo(), 1))
+            } else {
+                None
+            }
+        });
+        if let Some((open, lo, line)) = found {
+            let bytes = context.map.get_bytes(Span::new(lo, span.lo()));
+            let start = bytes
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |pos| pos + 1);
+            let column = context.map.column_unit().column(&bytes[start..]);
+            let line = line + count_lines(&bytes);
+            return (Some(open.path.clone()), Some(line), Some(column));
+        }
+        (
+            context.map.find_file(span),
+            context.map.find_line(span),
+            context.map.find_column(span),
+        )
+    }
+
+    /// Write the next token of the output of `context`, which is followed by `next`.
+    fn write_token(&mut self, context: &Context, token: Token, next: Option<&Token>) {
+        self.enter_files(context, self.index);
+        if self.index >= self.skipped_until {
+            self.token(context, token, next);
+        }
+        self.index += 1;
+    }
+
+    /// Enter and leave the files whose tokens start or end before the token at `index`.
+    fn enter_files(&mut self, context: &Context, index: usize) {
+        loop {
+            let next = context
+                .output_files
+                .get(self.next_file)
+                .filter(|file| file.tokens.start == index);
+            // Files are entered in the order they were processed, so the next file is inside the
+            // innermost one unless it has a lower depth.
+            let leave = self
+                .stack
+                .last()
+                .is_some_and(|open| context.output_files[open.file].tokens.end == index)
+                && next.is_none_or(|next| next.depth < self.stack.len());
+            if leave {
+                self.leave_file();
+            } else if let Some(next) = next {
+                if self.gcc_skips(context, next) {
+                    self.skipped_until = next.tokens.end;
+                    self.next_file += 1;
+                } else {
+                    self.enter_file(context, next.span);
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Check if `gcc -E` does not enter `file`, the next file, at all. GCC remembers the files
+    /// whose whole contents are a `#ifndef` directive, or a `#if` directive whose condition is
+    /// `!defined` followed by a name, and its `#endif`, and skips them if they are included again
+    /// with the macro defined.
+    fn gcc_skips(&self, context: &Context, file: &OutputFile) -> bool {
+        if self.emitter.profile != OutputProfile::Gcc {
+            return false;
+        }
+        let entered = context.output_files[..self.next_file]
+            .iter()
+            .any(|entered| entered.span == file.span);
+        entered
+            && context
+                .skipped_conditional(file.span, file.sections)
+                .is_some_and(|branches| match branches {
+                    [branch] => match context.map.spelling(branch.directive()).as_str() {
+                        "ifndef" => true,
+                        "if" => is_guard_condition(branch.condition()),
+                        _ => false,
+                    },
+                    _ => false,
+                })
+    }
+
+    /// Enter the next file, which is in the region `span`.
+    fn enter_file(&mut self, context: &Context, span: Span) {
+        self.write_return();
+        let gcc = self.emitter.profile == OutputProfile::Gcc;
+        // GCC writes the blank lines before the `#include` directive.
+        if let Some(line) = self.synced_line.take_if(|_| gcc) {
+            self.sync(line);
+        }
+        let path = context
+            .map
+            .find_file(span)
+            .unwrap_or_else(|| PathBuf::from("<stdin>"));
+        let system = match context.system_headers.contains(&path) {
+            true if gcc => " 3 4",
+            true => " 3",
+            false => "",
+        };
+        let path = context.map.display_path(&path).into_owned();
+        if gcc && self.next_file == 0 {
+            let input = context
+                .source
+                .and_then(|source| context.map.find_file(source))
+                .map(|input| context.map.display_path(&input).into_owned())
+                .unwrap_or_else(|| PathBuf::from("<stdin>"));
+            for path in [&input, Path::new("<built-in>"), Path::new("<command-line>")] {
+                self.line_marker(0, path, "");
+            }
+        }
+        let command_line = gcc && self.stack.is_empty() && Some(span) != context.source;
+        // GCC looks for these files in the current directory first.
+        let path = if command_line && path.is_relative() {
+            Path::new(".").join(path)
+        } else {
+            path
+        };
+        let flags = if self.stack.is_empty() && !command_line {
+            ""
+        } else {
+            " 1"
+        };
+        self.line_marker(1, &path, &format!("{}{}", flags, system));
+        self.stack.push(OpenFile {
+            file: self.next_file,
+            span,
+            path,
+            flags: system,
+            pos: span.lo(),
+            line: 1,
+            command_line,
+        });
+        self.next_file += 1;
+    }
+
+    fn leave_file(&mut self) {
+        self.write_return();
+        let open = self.stack.pop();
+        self.returned = !self.stack.is_empty();
+        if open.is_some_and(|open| open.command_line) {
+            self.line_marker(0, Path::new("<command-line>"), " 2");
+        }
+    }
+
+    /// Write the line marker of the return to the innermost file, if it was not written yet.
+    /// It says the next line is the one of the `#include` directive, as the new-line character of
+    /// the directive was not found yet.
+    fn write_return(&mut self) {
+        if !std::mem::take(&mut self.returned) {
+            return;
+        }
+        let open = self.stack.last().unwrap();
+        let (line, path) = (open.line, open.path.clone());
+        let flags = format!(" 2{}", open.flags);
+        self.line_marker(line, &path, &flags);
+    }
+
+    /// Write `token`, which is followed by `next` in the output of `context`.
+    fn token(&mut self, context: &Context, token: Token, next: Option<&Token>) {
+        match token.kind {
+            TokenKind::Space if context.comments.is_some() => {
+                let bytes = context.map.get_bytes(token.span).to_vec();
+                let ends_line = next.is_none_or(|next| next.kind == TokenKind::Newline);
+                self.comments(&bytes, ends_line);
+            }
+            TokenKind::Space => {
+                if self.line_start {
+                    self.indent = Some(token.span);
+                }
+                // GCC does not take line splices for white-space.
+                self.space |= self.emitter.profile != OutputProfile::Gcc
+                    || !is_splices(&context.map.get_bytes(token.span));
+            }
+            TokenKind::Newline => {
+                if self.emitter.profile == OutputProfile::Gcc && self.emitter.line_markers {
+                    self.gcc_pragma_once(context, token);
+                }
+                self.newline(context, token);
+            }
+            _ => {
+                self.start_text();
+                let spelling = context.map.get_bytes(token.span).to_vec();
+                if !self.space && !self.adjacent.is_empty() {
+                    let mut spellings: Vec<&[u8]> =
+                        self.adjacent.iter().map(Vec::as_slice).collect();
+                    spellings.push(&spelling);
+                    self.space = !tokenized_apart(&spellings, context.map.lexer_features());
+                }
+                if self.emitter.profile == OutputProfile::Gcc {
+                    self.gcc_space(context, token, &spelling);
+                }
+                if self.space && !self.line_start {
+                    self.push(b" ");
+                    self.adjacent.clear();
+                }
+                self.space = false;
+                self.previous = Some(token);
+                self.indent = None;
+                self.line_start = false;
+                if let Some(mut map) = self.map.take() {
+                    let location = self.location(context, token.span);
+                    let position = (self.output_line, self.output_column + 1);
+                    map.push(position, spelling.len(), location);
+                    self.map = Some(map);
+                }
+                self.push(&spelling);
+                if self.adjacent.len() == MAX_PASTED - 1 {
+                    self.adjacent.remove(0);
+                }
+                self.adjacent.push(spelling);
+            }
+        }
+    }
+
+    /// Write the new-line character `newline`, which can be replaced by a line marker or by
+    /// nothing at all if the next line is synchronized later.
+    fn newline(&mut self, context: &Context, newline: Token) {
+        match self.count_lines(context, newline.span) {
+            // The line marker of the return to a file replaces the new-line character of the
+            // `#include` directive.
+            Some(line) if self.returned => {
+                self.returned = false;
+                let open = self.stack.last().unwrap();
+                let (path, flags) = (open.path.clone(), format!(" 2{}", open.flags));
+                self.line_marker(line, &path, &flags);
+            }
+            Some(line) => match context.map.presumed_line(newline.span.hi()) {
+                Some(presumed) => self.presumed_line(context, presumed),
+                None => {
+                    if !self.defers_blank_lines() {
+                        self.end_line();
+                    }
+                    self.synced_line = Some(line);
+                }
+            },
+            None => {
+                self.write_return();
+                if self.defers_blank_lines() {
+                    self.synced_line = Some(self.synced_line.unwrap_or(self.line) + 1);
+                } else {
+                    self.end_line();
+                    // The line of the file goes on after the new-line character, like after the
+                    // `#pragma` directive of a `_Pragma` operator.
+                    self.synced_line = self.stack.last().map(|open| open.line);
+                    self.after_pragma = context
+                        .pragma_operators
+                        .iter()
+                        .any(|line| line.hi() == newline.span.hi());
+                }
+            }
+        }
+    }
+
+    /// Give the next line of the innermost file the number and file name given by the `#line`
+    /// directive or line marker before it, writing a line marker if the file name or the flags
+    /// change, which replaces the new-line character of the directive. GCC writes a line marker
+    /// for every one of them.
+    fn presumed_line(&mut self, context: &Context, presumed: PresumedLine) {
+        let gcc = self.emitter.profile == OutputProfile::Gcc;
+        let path = context.map.display_path(&presumed.path).into_owned();
+        let open = self.stack.last_mut().unwrap();
+        open.line = presumed.line;
+        let mut changed = gcc || open.path != path;
+        open.path = path.clone();
+        let mut flags = "";
+        if let Some(marker) = presumed.flags {
+            let system = match marker.system {
+                true if gcc => " 3 4",
+                true => " 3",
+                false => "",
+            };
+            changed |= marker.enter || marker.leave || open.flags != system;
+            open.flags = system;
+            if marker.enter {
+                flags = " 1";
+            } else if marker.leave {
+                flags = " 2";
+            }
+        }
+        let flags = format!("{}{}", flags, open.flags);
+        if changed && self.emitter.line_markers {
+            self.line_marker(presumed.line, &path, &flags);
+        } else {
+            if !self.defers_blank_lines() {
+                self.end_line();
+            }
+            self.synced_line = Some(presumed.line);
+        }
+    }
+
+    /// Check if the current line is empty and its new-line character can be left for the next
+    /// synchronization, which is what GCC does with blank lines.
+    fn defers_blank_lines(&self) -> bool {
+        self.emitter.profile == OutputProfile::Gcc && self.emitter.line_markers && self.line_start
+    }
+
+    /// Write the indentation `gcc -E` writes instead of a `#pragma once` directive, which is the
+    /// one of its last token, if `newline` is the new-line character of such a directive.
+    fn gcc_pragma_once(&mut self, context: &Context, newline: Token) {
+        let line = match context
+            .once_directives
+            .iter()
+            .find(|line| line.hi() == newline.span.hi())
+        {
+            Some(line) => *line,
+            None => return,
+        };
+        let bytes = context
+            .map
+            .get_bytes(Span::new(line.lo(), newline.span.lo()));
+        let once = bytes.windows(4).rposition(|word| word == b"once");
+        let spaces = " ".repeat(once.unwrap_or(0).saturating_sub(1));
+        drop(bytes);
+        if !spaces.is_empty() {
+            self.start_text();
+            self.push(spaces.as_bytes());
+            self.line_start = false;
+        }
+    }
+
+    /// Add the white-space `gcc -E` writes before `token`, whose spelling is `spelling`: the
+    /// indentation of the first token of a line and the spaces between tokens coming from
+    /// different macro replacements.
+    fn gcc_space(&mut self, context: &Context, token: Token, spelling: &[u8]) {
+        if !self.line_start {
+            if self.gcc_line_change(context, token) {
+                return;
+            }
+            let previous = match self.previous {
+                // Tokens that are next to each other in the source code were already written
+                // together by GCC.
+                Some(previous) if !self.space && previous.span.hi() != token.span.lo() => previous,
+                _ => return,
+            };
+            let previous_spelling = context.map.get_bytes(previous.span).to_vec();
+            self.space =
+                gcc_avoid_paste((previous.kind, &previous_spelling), (token.kind, spelling));
+            return;
+        }
+
+        let column = match self.indent.take() {
+            // The white-space after the directive of a `_Pragma` operator is in the middle of a
+            // line, and it is written as a single space.
+            Some(_) if self.after_pragma => 2,
+            Some(indent) => {
+                let end = Span::new(indent.hi(), indent.hi());
+                self.location(context, end).2.unwrap_or(1)
+            }
+            None => 1,
+        };
+        // A `#` coming from a macro replacement cannot start a directive, unless it starts the
+        // directive of a `_Pragma` operator.
+        let in_file = self
+            .stack
+            .last()
+            .is_some_and(|open| token.span.lo() >= open.pos && token.span.hi() <= open.span.hi());
+        let hash = !in_file
+            && matches!(spelling, b"#" | b"%:")
+            && !context
+                .pragma_operators
+                .iter()
+                .any(|line| line.lo() == token.span.lo());
+        let mut spaces = " ".repeat((column - 1).max(usize::from(hash)));
+
+        // GCC starts a new line with a line marker when it goes from the tokens of a system
+        // header to the ones of a file that is not, or the other way around, and indents it
+        // with one space less.
+        let system = self.stack.last().is_some_and(|open| !open.flags.is_empty());
+        if system != self.system_token && self.emitter.line_markers {
+            let open = self.stack.last().unwrap();
+            let (line, path, flags) = (self.line, open.path.clone(), open.flags);
+            self.push(spaces.as_bytes());
+            self.end_line();
+            self.line_marker(line, &path, flags);
+            spaces = " ".repeat(column.saturating_sub(2));
+        }
+        self.system_token = system;
+        if !spaces.is_empty() {
+            self.push(spaces.as_bytes());
+            self.line_start = false;
+            self.adjacent.clear();
+        }
+        self.space = false;
+    }
+
+    /// Start a new line before `token` if GCC does, which it does when the token is separated
+    /// from the previous one and appears in a line after the current one. This happens after an
+    /// invocation of a function-like macro whose arguments span several lines, as GCC gives every
+    /// token produced by an invocation the line of its name, and after a line splice. The new
+    /// line is indented up to the column where the token appears.
+    ///
+    /// Return `true` if the line was started, in which case the token is written after the
+    /// indentation.
+    fn gcc_line_change(&mut self, context: &Context, token: Token) -> bool {
+        let separated = self.space
+            || match self.previous {
+                Some(previous) if previous.span.hi() <= token.span.lo() => {
+                    // Tokens separated only by line splices are next to each other.
+                    let gap = Span::new(previous.span.hi(), token.span.lo());
+                    !self
+                        .stack
+                        .last()
+                        .is_some_and(|open| open.span.contains(gap))
+                        || !is_splices(&context.map.get_bytes(gap))
+                }
+                _ => true,
+            };
+        if !separated || !self.emitter.line_markers {
+            return false;
+        }
+        let site = context.gcc_site(self.index, token);
+        let open = match self.stack.last_mut() {
+            Some(open) if site.lo() >= open.pos && site.hi() <= open.span.hi() => open,
+            _ => return false,
+        };
+        let bytes = context.map.get_bytes(Span::new(open.pos, site.lo()));
+        let start = match bytes.iter().rposition(|&byte| byte == b'\n') {
+            Some(pos) => pos + 1,
+            None => return false,
+        };
+        open.line += count_lines(&bytes);
+        open.pos += start;
+        let line = open.line;
+        drop(bytes);
+        if line == self.line {
+            return false;
+        }
+
+        let column = self.location(context, site).2.unwrap_or(1);
+        self.end_line();
+        self.sync(line);
+        self.push(" ".repeat(column.saturating_sub(1).max(1)).as_bytes());
+        self.line_start = false;
+        self.space = false;
+        true
+    }
+
+    /// Write the comments inside `space`, a white-space token. A comment starting with `//` is
+    /// written as a comment starting with `/*` unless `ends_line` is `true`, as it could have
+    /// been moved to the middle of a line by a macro replacement.
+    fn comments(&mut self, space: &[u8], ends_line: bool) {
+        self.space = true;
+        let start = match space.iter().position(|&byte| byte == b'/') {
+            Some(start) => start,
+            None => return,
+        };
+        let end = space
+            .iter()
+            .rposition(|&byte| {
+                !matches!(
+                    byte,
+                    b' ' | b'\t' | b'\x0b' | b'\x0c' | b'\r' | b'\n' | b'\\'
+                )
+            })
+            .map_or(space.len(), |end| end + 1);
+        let mut comments = space[start..end].to_vec();
+        if let Some(pos) = line_comment(&comments).filter(|_| !ends_line) {
+            let text = comments.split_off(pos + 2);
+            comments.truncate(pos);
+            comments.extend_from_slice(b"/*");
+            // The text cannot end the new comment early.
+            for (i, &byte) in text.iter().enumerate() {
+                comments.push(byte);
+                if byte == b'*' && text.get(i + 1) == Some(&b'/') {
+                    comments.push(b' ');
+                }
+            }
+            comments.extend_from_slice(b" */");
+        }
+
+        self.start_text();
+        if !self.line_start {
+            self.push(b" ");
+        }
+        self.push(&comments);
+        self.line += count_lines(&comments);
+        self.line_start = false;
+        self.adjacent.clear();
+    }
+
+    /// Count the lines of the innermost file up to the new-line character in the region
+    /// `newline` of the source map of `context` and return the number of the line after it.
+    /// Return `None` if the character is not part of the file.
+    fn count_lines(&mut self, context: &Context, newline: Span) -> Option<usize> {
+        let open = self.stack.last_mut()?;
+        if newline.lo() < open.pos || newline.hi() > open.span.hi() {
+            return None;
+        }
+        let span = Span::new(open.pos, newline.hi());
+        open.line += count_lines(&context.map.get_bytes(span));
+        open.pos = newline.hi();
+        Some(open.line)
+    }
+
+    /// Prepare the current line to write text on it, writing the pending line markers or blank
+    /// lines first.
+    fn start_text(&mut self) {
+        // The white-space already found belongs to the line that is being prepared.
+        let indent = self.indent;
+        self.write_return();
+        if let Some(line) = self.synced_line.take() {
+            self.sync(line);
+        }
+        self.indent = indent;
+    }
+
+    /// Make the compiler give the number `line` to the current line, which is empty.
+    fn sync(&mut self, line: usize) {
+        if !self.emitter.line_markers || line == self.line {
+            return;
+        }
+        let max_blank_lines = match self.emitter.profile {
+            OutputProfile::Beheader => MAX_BLANK_LINES,
+            OutputProfile::Gcc => GCC_MAX_BLANK_LINES,
+        };
+        if line > self.line && line - self.line <= max_blank_lines {
+            while self.line < line {
+                self.end_line();
+            }
+            return;
+        }
+        let open = self.stack.last().unwrap();
+        let (path, flags) = (open.path.clone(), open.flags);
+        self.line_marker(line, &path, flags);
+    }
+
+    /// Write a line marker saying the next line is the line `line` of the file at `path`,
+    /// followed by `flags`, if line markers are enabled.
+    fn line_marker(&mut self, line: usize, path: &Path, flags: &str) {
+        if !self.emitter.line_markers {
+            return;
+        }
+        if !self.line_start {
+            self.end_line();
+        }
+        self.push(line_marker(line, path, flags).as_bytes());
+        self.line = line;
+        self.synced_line = None;
+    }
+
+    /// Finish the current line. Without line markers, blank lines are only written after a line
+    /// that is not blank.
+    fn end_line(&mut self) {
+        if self.emitter.line_markers
+            || !self.line_start
+            || (!self.blank && (self.flushed || !self.text.is_empty()))
+        {
+            self.push(b"\n");
+            self.blank = self.line_start;
+        }
+        self.line_start = true;
+        self.line += 1;
+        self.adjacent.clear();
+        self.space = false;
+        self.previous = None;
+        self.indent = None;
+        self.after_pragma = false;
+    }
+}
+
+/// Check if `bytes` only contains line splices, a `\` followed by a new-line character.
+fn is_splices(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .all(|byte| matches!(byte, b'\\' | b'\r' | b'\n'))
+}
+
+/// Check if `condition`, the condition of a `#if` directive, is `!defined` followed by a name,
+/// which GCC takes as an include guard.
+fn is_guard_condition(condition: &str) -> bool {
+    let operand = condition
+        .strip_prefix('!')
+        .map(str::trim_start)
+        .and_then(|rest| rest.strip_prefix("defined"))
+        .filter(|operand| operand.starts_with([' ', '(']));
+    let name = operand
+        .map(str::trim)
+        .and_then(|operand| match operand.strip_prefix('(') {
+            Some(operand) => operand.strip_suffix(')').map(str::trim),
+            None => Some(operand),
+        });
+    name.is_some_and(|name| {
+        !name.is_empty()
+            && name
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+    })
+}
+
+/// Find the start of the comment starting with `//` at the end of `comments`, a sequence of
+/// comments and white-space, if there is one.
+fn line_comment(comments: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 1 < comments.len() {
+        match &comments[pos..pos + 2] {
+            b"//" => return Some(pos),
+            b"/*" => {
+                let end = comments[pos + 2..]
+                    .windows(2)
+                    .position(|pair| pair == b"*/")?;
+                pos += end + 4;
+            }
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+/// Check if `gcc -E` writes a space between two tokens coming from different macro replacements,
+/// given the kind and spelling of each of them. This follows the `cpp_avoid_paste` function of
+/// GCC.
+fn gcc_avoid_paste((kind, a): (TokenKind, &[u8]), (next_kind, b): (TokenKind, &[u8])) -> bool {
+    // The first character of the next token, if it is a punctuator.
+    let c = match next_kind {
+        TokenKind::Punct => b.first().copied(),
+        _ => None,
+    };
+    if kind == TokenKind::Punct
+        && matches!(
+            a,
+            b"=" | b"!"
+                | b">"
+                | b"<"
+                | b"+"
+                | b"-"
+                | b"*"
+                | b"/"
+                | b"%"
+                | b"&"
+                | b"|"
+                | b"^"
+                | b">>"
+                | b"<<"
+        )
+        && c == Some(b'=')
+    {
+        return true;
+    }
+    match (kind, a) {
+        (TokenKind::Punct, b">") => c == Some(b'>'),
+        (TokenKind::Punct, b"<") => matches!(c, Some(b'<' | b'%' | b':')),
+        (TokenKind::Punct, b"+") => c == Some(b'+'),
+        (TokenKind::Punct, b"-") => matches!(c, Some(b'-' | b'>')),
+        // Comments.
+        (TokenKind::Punct, b"/") => matches!(c, Some(b'/' | b'*')),
+        (TokenKind::Punct, b"%") => matches!(c, Some(b':' | b'%')),
+        (TokenKind::Punct, b"&") => c == Some(b'&'),
+        (TokenKind::Punct, b"|") => c == Some(b'|'),
+        (TokenKind::Punct, b":") => matches!(c, Some(b':' | b'>')),
+        (TokenKind::Punct, b"->") => c == Some(b'*'),
+        (TokenKind::Punct, b".") => {
+            matches!(c, Some(b'.' | b'%')) || next_kind == TokenKind::Number
+        }
+        (TokenKind::Punct, b"#" | b"%:") => matches!(c, Some(b'#' | b'%')),
+        (TokenKind::Ident, _) => matches!(
+            next_kind,
+            TokenKind::Ident | TokenKind::Char | TokenKind::Str
+        ),
+        (TokenKind::Number, _) => {
+            matches!(
+                next_kind,
+                TokenKind::Number | TokenKind::Ident | TokenKind::Char
+            ) || matches!(c, Some(b'.' | b'+' | b'-'))
+        }
+        (TokenKind::Any, b"\\") => next_kind == TokenKind::Ident,
+        _ => false,
+    }
+}
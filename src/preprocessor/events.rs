@@ -0,0 +1,350 @@
+//! A pull-based interface to the preprocessor that yields what happens while preprocessing.
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use super::{Branch, Context, Preprocessed, Preprocessor};
+use crate::{
+    buffer::TokenBuffer,
+    diagnostic::Diagnostic,
+    error::Error,
+    lexer::Token,
+    span::{SourceMap, Span},
+};
+
+/// The number of events that can be produced before they are received.
+const EVENT_BUFFER: usize = 1024;
+
+/// Something that happened while preprocessing, yielded by [`Events`].
+///
+/// Events are yielded in the order they happened: the tokens produced before a directive come
+/// before the events of the directive, and the events of an included file come between the
+/// [`EnterFile`](Self::EnterFile) and [`ExitFile`](Self::ExitFile) events of the file.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Event {
+    /// A token of the output. Its spelling can be found using [`Events::spelling`] and its span
+    /// can be resolved using the result of [`Events::finish`].
+    Token(Token),
+    /// A file started being processed. The path is `None` for input that is not a file.
+    EnterFile(Option<PathBuf>),
+    /// The file of the last [`EnterFile`](Self::EnterFile) event without its own `ExitFile`
+    /// event was processed completely.
+    ExitFile(Option<PathBuf>),
+    /// A macro was defined by a `#define` directive. The first string is the name of the macro and
+    /// the second one is its definition as a `#define` directive.
+    MacroDefined(String, String),
+    /// The group of a branch of a conditional inclusion directive is skipped, either because its
+    /// condition is false or because the whole directive is inside a group that is skipped.
+    ConditionalSkipped(Branch),
+    /// A diagnostic was reported. Errors that stop the preprocessing are not reported as events,
+    /// they are returned by [`Events::finish`] instead.
+    Diagnostic(Diagnostic),
+}
+
+/// An iterator over the [`Event`]s of a preprocessing run, obtained using
+/// [`Preprocessor::events`] or [`Preprocessor::events_file`].
+///
+/// The preprocessing runs in another thread while the events are consumed, and it waits when
+/// too many events were not received yet, so the output is never kept in memory as a whole.
+/// The iterator ends once the preprocessing finishes. Dropping the iterator before that cancels
+/// the preprocessing. In WebAssembly, where there are no threads, the whole run happens when the
+/// iterator is created and its events are kept until they are consumed.
+///
+/// Adjacent string literals are only concatenated if there are no directives between them.
+///
+/// ```
+/// use beheader::{Event, Preprocessor};
+///
+/// let source = b"#define A 1\nint a = A;\n#if 0\n#endif\n";
+/// let mut events = Preprocessor::new().events(source);
+/// let mut tokens = Vec::new();
+/// let mut macros = Vec::new();
+/// while let Some(event) = events.next() {
+///     match event {
+///         Event::Token(token) => tokens.extend_from_slice(events.spelling(token).unwrap()),
+///         Event::MacroDefined(name, _) => macros.push(name),
+///         _ => {}
+///     }
+/// }
+/// assert_eq!(tokens, b"\nint a = 1;\n\n\n");
+/// assert_eq!(macros, ["A"]);
+/// assert!(events.finish().unwrap().tokens().is_empty());
+/// ```
+pub struct Events {
+    /// The receiver of the messages of the run, or `None` once it finished.
+    receiver: Option<Receiver<Message>>,
+    /// The run, or `None` once it finished.
+    run: Option<Run>,
+    /// The flag that cancels the run when the iterator is dropped.
+    cancellation: Arc<AtomicBool>,
+    /// The regions of the source code where the tokens received so far are spelled and their
+    /// contents, sorted by their start.
+    regions: Vec<(Span, Arc<[u8]>)>,
+}
+
+/// A preprocessing run whose events are yielded by [`Events`].
+enum Run {
+    Thread(JoinHandle<Result<Preprocessed, Error>>),
+    /// A run that happened in the thread that created the [`Events`], with its result.
+    Finished(Box<Result<Preprocessed, Error>>),
+}
+
+/// What is sent by a run to its [`Events`].
+enum Message {
+    Event(Event),
+    /// The contents of a region of the source code where the tokens sent after it can be spelled,
+    /// which is sent before the first of them.
+    Region(Span, Arc<[u8]>),
+}
+
+impl Events {
+    /// Start the preprocessing run `run` in another thread, or run it in this one if there are
+    /// no threads.
+    fn spawn<F>(run: F) -> Self
+    where
+        F: FnOnce(EventSink) -> Result<Preprocessed, Error> + Send + 'static,
+    {
+        let cancellation = Arc::new(AtomicBool::new(false));
+        let sink = |sender| EventSink {
+            sender: Some(sender),
+            pending: Vec::new(),
+            diagnostics: 0,
+            cancellation: cancellation.clone(),
+            regions: HashSet::new(),
+            last_region: None,
+        };
+        let (receiver, run) = if cfg!(target_family = "wasm") {
+            let (sender, receiver) = mpsc::channel();
+            let result = run(sink(Sender::Unbounded(sender)));
+            (receiver, Run::Finished(Box::new(result)))
+        } else {
+            let (sender, receiver) = mpsc::sync_channel(EVENT_BUFFER);
+            let sink = sink(Sender::Bounded(sender));
+            (receiver, Run::Thread(thread::spawn(move || run(sink))))
+        };
+        Self {
+            receiver: Some(receiver),
+            run: Some(run),
+            cancellation,
+            regions: Vec::new(),
+        }
+    }
+
+    /// Get the spelling of `token`, the token of an [`Event::Token`] received from this iterator.
+    /// Return `None` for other tokens.
+    pub fn spelling(&self, token: Token) -> Option<&[u8]> {
+        let index = self
+            .regions
+            .partition_point(|(region, _)| region.lo() <= token.span.lo());
+        let (region, bytes) = self.regions[..index].last()?;
+        let start = token.span.lo() - region.lo();
+        bytes.get(start..start + token.span.hi() - token.span.lo())
+    }
+
+    /// Wait until the preprocessing finishes and return its result, which has no output tokens
+    /// as they were yielded as events instead. The events not received yet are discarded.
+    ///
+    /// Return an error if any of the files cannot be read or if the source code is not valid.
+    pub fn finish(mut self) -> Result<Preprocessed, Error> {
+        self.receiver = None;
+        match self.run.take().unwrap() {
+            Run::Thread(thread) => match thread.join() {
+                Ok(result) => result,
+                Err(panic) => std::panic::resume_unwind(panic),
+            },
+            Run::Finished(result) => *result,
+        }
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        if matches!(self.run, Some(Run::Thread(_))) {
+            self.cancellation.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Iterator for Events {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            match self.receiver.as_ref()?.recv().ok()? {
+                Message::Event(event) => return Some(event),
+                Message::Region(span, bytes) => {
+                    let index = self
+                        .regions
+                        .partition_point(|(region, _)| region.lo() <= span.lo());
+                    self.regions.insert(index, (span, bytes));
+                }
+            }
+        }
+    }
+}
+
+/// The sending side of the channel between a run and its [`Events`].
+enum Sender {
+    /// The sender of a run in another thread, which waits when too many events were not received.
+    Bounded(SyncSender<Message>),
+    /// The sender of a run in the thread of its [`Events`], which keeps all of its events.
+    Unbounded(mpsc::Sender<Message>),
+}
+
+/// Where the events of a run are sent.
+pub(super) struct EventSink {
+    /// The sender of the events, or `None` once the events are not received anymore.
+    sender: Option<Sender>,
+    /// The events that happened after the output tokens that were not sent yet.
+    pending: Vec<Event>,
+    /// The number of diagnostics already sent.
+    diagnostics: usize,
+    /// The flag that cancels the run once the events are not received anymore.
+    pub(super) cancellation: Arc<AtomicBool>,
+    /// The regions of the source code whose contents were already sent.
+    regions: HashSet<Span>,
+    /// The region of the source code where the last token sent is spelled.
+    last_region: Option<Span>,
+}
+
+impl EventSink {
+    fn send(&mut self, event: Event) {
+        self.send_message(Message::Event(event));
+    }
+
+    fn send_message(&mut self, message: Message) {
+        let sent = match &self.sender {
+            Some(Sender::Bounded(sender)) => sender.send(message).is_ok(),
+            Some(Sender::Unbounded(sender)) => sender.send(message).is_ok(),
+            None => return,
+        };
+        if !sent {
+            self.sender = None;
+        }
+    }
+
+    /// Send `token`, sending the contents of the region of `map` where it is spelled first if
+    /// they were not sent yet.
+    fn send_token(&mut self, map: &SourceMap, token: Token) {
+        if !self
+            .last_region
+            .is_some_and(|region| region.contains(token.span))
+        {
+            if let Some((region, bytes)) = map.get_region(token.span) {
+                if self.regions.insert(region) {
+                    self.send_message(Message::Region(region, bytes));
+                }
+                self.last_region = Some(region);
+            }
+        }
+        self.send(Event::Token(token));
+    }
+}
+
+impl Preprocessor {
+    /// Preprocess a sequence of bytes, yielding the [`Event`]s of the run as they happen instead
+    /// of producing the whole output at once.
+    pub fn events(&self, source: &[u8]) -> Events {
+        let preprocessor = self.clone();
+        let source = source.to_vec();
+        Events::spawn(move |sink| {
+            let mut context = preprocessor.context()?;
+            let source = preprocessor
+                .input_charset
+                .decode_source(source.into(), preprocessor.lexer_features())?;
+            let span = context.map.store_bytes(&source)?;
+            context.source = Some(span);
+            preprocessor.process_events(context, span, sink)
+        })
+    }
+
+    /// Preprocess a file, yielding the [`Event`]s of the run as they happen like
+    /// [`events`](Self::events) does.
+    pub fn events_file<P: AsRef<Path>>(&self, path: &P) -> Events {
+        let preprocessor = self.clone();
+        let path = path.as_ref().to_owned();
+        Events::spawn(move |sink| {
+            let mut context = preprocessor.context()?;
+            let span = context.read_file(&path)?;
+            context.input = context.map.find_file(span);
+            context.source = Some(span);
+            preprocessor.process_events(context, span, sink)
+        })
+    }
+
+    /// Process the input in the region `span` using `context`, sending its events to `sink`.
+    fn process_events(
+        &self,
+        mut context: Context,
+        span: Span,
+        sink: EventSink,
+    ) -> Result<Preprocessed, Error> {
+        context.events = Some(sink);
+        let result = context.process(span);
+        let mut output = std::mem::take(&mut context.output);
+        let sent = context.send_events(&mut output);
+        result?;
+        sent.map_err(|diagnostic| context.error(diagnostic))?;
+
+        // The files and the macro invocations refer to the tokens that are not kept.
+        context.output_files.clear();
+        context.expanded.clear();
+        context.streamed = 0;
+        let mut sink = context.events.take().unwrap();
+        let preprocessed = self.finish(context)?;
+        // Finishing can report more diagnostics.
+        for diagnostic in &preprocessed.diagnostics()[sink.diagnostics..] {
+            sink.send(Event::Diagnostic(diagnostic.clone()));
+        }
+        Ok(preprocessed)
+    }
+}
+
+impl Context {
+    /// Record the event produced by `event` if the events of the run are sent somewhere. It is
+    /// sent once the output tokens produced before it are sent.
+    pub(super) fn event<F: FnOnce(&Self) -> Event>(&mut self, event: F) {
+        if self.events.is_some() {
+            let event = event(self);
+            if let Some(sink) = &mut self.events {
+                sink.pending.push(event);
+            }
+        }
+    }
+
+    /// Send the tokens of `output`, the output of the run, and the events that happened after
+    /// them, removing the tokens from the output, if the events of the run are sent somewhere.
+    ///
+    /// Return an error if the string literals of the tokens cannot be concatenated.
+    pub(super) fn send_events(&mut self, output: &mut TokenBuffer) -> Result<(), Diagnostic> {
+        if self.events.is_none() {
+            return Ok(());
+        }
+        if self.concatenate_strings {
+            self.concatenate_strings(output)?;
+        }
+        let mut sink = self.events.take().unwrap();
+        let tokens = output.take_front(output.tokens().len());
+        self.streamed += tokens.len();
+        for token in tokens {
+            sink.send_token(&self.map, token);
+        }
+        for event in std::mem::take(&mut sink.pending) {
+            sink.send(event);
+        }
+        for diagnostic in &self.diagnostics[sink.diagnostics..] {
+            sink.send(Event::Diagnostic(self.included_from(diagnostic.clone())));
+        }
+        sink.diagnostics = self.diagnostics.len();
+        self.events = Some(sink);
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! The macro invocations that produced the tokens of the output, which give each of those tokens
+//! a second location: where the invocation is, besides where the token is spelled.
+use std::{collections::HashMap, rc::Rc};
+
+use super::{Context, Preprocessed};
+use crate::{
+    lexer::Token,
+    macros::{ExpandedToken, Expansion},
+    span::Span,
+};
+
+/// A macro invocation stored in [`Context::expansions`].
+pub(super) struct ExpansionRecord {
+    /// The name of the macro.
+    name: String,
+    /// The region of the name of the macro in the invocation.
+    call_site: Span,
+    /// The region of the name of the macro inside its definition.
+    definition: Span,
+    /// The index of the invocation whose replacement list produced the name of the macro, if any.
+    parent: Option<usize>,
+}
+
+/// A macro invocation whose replacement list produced some tokens of the output, obtained using
+/// [`Preprocessed::expansion`].
+///
+/// The tokens produced by the replacement list are spelled inside the definition of the macro,
+/// or in no file at all if they were produced by the `#` and `##` operators, while the
+/// invocation is where they appear in the source code. If the invocation was produced by the
+/// replacement list of another invocation, its [`parent`](Self::parent) is that invocation, so
+/// the chain of parents leads to the invocation written in the source code.
+///
+/// ```
+/// let source = b"#define ONE 1\n#define TWO (ONE + ONE)\nint a = TWO;\n";
+/// let preprocessed = beheader::preprocess(source).unwrap();
+/// let snapshot = preprocessed.source_map();
+/// let spelling = |span| std::str::from_utf8(snapshot.spelling(span).unwrap()).unwrap();
+///
+/// let one = preprocessed.tokens().iter().position(|token| spelling(token.span()) == "1");
+/// let expansion = preprocessed.expansion(one.unwrap()).unwrap();
+/// assert_eq!(expansion.name(), "ONE");
+/// assert_eq!(spelling(expansion.call_site()), "ONE");
+/// let parent = expansion.parent().unwrap();
+/// assert_eq!(parent.name(), "TWO");
+/// assert!(parent.parent().is_none());
+///
+/// let location = preprocessed.resolve_span(parent.call_site()).unwrap();
+/// assert_eq!((location.start_line(), location.start_column()), (3, 9));
+/// assert_eq!(expansion.outermost().call_site(), parent.call_site());
+/// ```
+#[derive(Clone, Copy)]
+pub struct MacroExpansion<'a> {
+    records: &'a [ExpansionRecord],
+    index: usize,
+}
+
+impl<'a> MacroExpansion<'a> {
+    fn record(&self) -> &'a ExpansionRecord {
+        &self.records[self.index]
+    }
+
+    /// The name of the macro.
+    pub fn name(&self) -> &'a str {
+        &self.record().name
+    }
+
+    /// The region of the name of the macro in the invocation, which is inside the replacement
+    /// list of the [`parent`](Self::parent) invocation if there is one.
+    pub fn call_site(&self) -> Span {
+        self.record().call_site
+    }
+
+    /// The region of the name of the macro inside its definition.
+    pub fn definition(&self) -> Span {
+        self.record().definition
+    }
+
+    /// The invocation whose replacement list produced the name of the macro of this invocation,
+    /// if any.
+    pub fn parent(&self) -> Option<MacroExpansion<'a>> {
+        self.record().parent.map(|index| MacroExpansion {
+            records: self.records,
+            index,
+        })
+    }
+
+    /// The last invocation of the chain of [`parent`](Self::parent)s, which is written in the
+    /// source code.
+    pub fn outermost(&self) -> MacroExpansion<'a> {
+        let mut expansion = *self;
+        while let Some(parent) = expansion.parent() {
+            expansion = parent;
+        }
+        expansion
+    }
+}
+
+impl Context {
+    /// Record the invocations that produced `tokens`, the tokens produced by macro replacement
+    /// that are added to the output after the first `start` ones.
+    pub(super) fn record_expansions(&mut self, start: usize, tokens: &[ExpandedToken]) {
+        // The same invocation produces many tokens, so it is only recorded once.
+        let mut recorded = HashMap::new();
+        for (offset, (_, expansion)) in tokens.iter().enumerate() {
+            if let Some(expansion) = expansion {
+                let index = self.record_expansion(expansion, &mut recorded);
+                self.expanded.insert(start + offset, index);
+            }
+        }
+    }
+
+    /// Record `expansion` and its parents unless they are in `recorded`, which has the index of
+    /// each invocation recorded already. Return the index of `expansion`.
+    fn record_expansion(
+        &mut self,
+        expansion: &Rc<Expansion>,
+        recorded: &mut HashMap<*const Expansion, usize>,
+    ) -> usize {
+        if let Some(&index) = recorded.get(&Rc::as_ptr(expansion)) {
+            return index;
+        }
+        let parent = expansion
+            .parent
+            .as_ref()
+            .map(|parent| self.record_expansion(parent, recorded));
+        let index = self.expansions.len();
+        self.expansions.push(ExpansionRecord {
+            name: expansion.name.clone(),
+            call_site: expansion.span,
+            definition: expansion.definition,
+            parent,
+        });
+        recorded.insert(Rc::as_ptr(expansion), index);
+        index
+    }
+
+    /// Get the region where the output token at `index`, which is `token`, appears in the source
+    /// code as GCC sees it: the name of the outermost macro invocation that produced it, or the
+    /// one whose arguments it comes from if they span several lines, or where it is spelled.
+    pub(super) fn gcc_site(&self, index: usize, token: Token) -> Span {
+        let site = match self.expanded.get(index) {
+            Some(&expansion) => {
+                let mut record = &self.expansions[expansion];
+                while let Some(parent) = record.parent {
+                    record = &self.expansions[parent];
+                }
+                record.call_site
+            }
+            None => token.span,
+        };
+        let next = self
+            .invocations
+            .partition_point(|span| span.lo() <= site.lo());
+        match next.checked_sub(1).map(|last| self.invocations[last]) {
+            Some(invocation) if invocation.contains(site) => invocation,
+            _ => site,
+        }
+    }
+
+    /// Update the indices of the output tokens produced by macro replacement after moving each
+    /// output token that was not written to the stream yet as done by
+    /// [`move_output`](Self::move_output). The tokens that were removed are forgotten.
+    pub(super) fn move_expanded(&mut self, indices: &[usize]) {
+        let streamed = self.streamed;
+        self.expanded.retain_mut(|index, _| {
+            if *index < streamed {
+                return true;
+            }
+            let moved = *index - streamed;
+            // Removed tokens are moved to the same index as the token after them.
+            if indices[moved] == indices[moved + 1] {
+                return false;
+            }
+            *index = streamed + indices[moved];
+            true
+        });
+    }
+}
+
+impl Preprocessed {
+    /// Get the macro invocation whose replacement list produced the token at `index` of
+    /// [`tokens`](Self::tokens), if any. Tokens coming from the arguments of an invocation are
+    /// produced by the same invocation as the tokens of the arguments were, if any.
+    pub fn expansion(&self, index: usize) -> Option<MacroExpansion<'_>> {
+        Some(MacroExpansion {
+            records: &self.context.expansions,
+            index: *self.context.expanded.get(index)?,
+        })
+    }
+
+    /// Get the region where the token at `index` of [`tokens`](Self::tokens) appears in the
+    /// source code: the name of the outermost macro invocation that produced it, or where it is
+    /// spelled if it was not produced by macro replacement.
+    pub fn expansion_site(&self, index: usize) -> Span {
+        match self.expansion(index) {
+            Some(expansion) => expansion.outermost().call_site(),
+            None => self.tokens()[index].span,
+        }
+    }
+}
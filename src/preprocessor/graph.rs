@@ -0,0 +1,153 @@
+//! The graph of the files included while preprocessing.
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{IncludeKind, Preprocessed};
+use crate::{lexer::Token, span::Span};
+
+/// The files included by `#include` directives while preprocessing and the files that included
+/// them.
+///
+/// ```
+/// use beheader::{MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#include \"a.h\"\n").insert("a.h", "");
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files);
+/// let graph = preprocessor.preprocess_file(&"main.c").unwrap().include_graph();
+/// assert_eq!(graph.edges()[0].included(), "a.h");
+/// println!("{}", graph.to_dot());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncludeGraph {
+    edges: Vec<IncludeEdge>,
+}
+
+impl IncludeGraph {
+    /// Every `#include` directive whose file was found, in the order they were processed. A file
+    /// included by several directives, even in the same file, has an edge for each one of them.
+    pub fn edges(&self) -> &[IncludeEdge] {
+        &self.edges
+    }
+
+    /// The files included by any directive and the files containing these directives, in the
+    /// order they were first found.
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files: Vec<&Path> = Vec::new();
+        for edge in &self.edges {
+            for file in edge.includer().into_iter().chain([edge.included()]) {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files
+    }
+
+    /// The edges of the directives found in the file `includer`.
+    pub fn includes<'a>(&'a self, includer: &'a Path) -> impl Iterator<Item = &'a IncludeEdge> {
+        self.edges
+            .iter()
+            .filter(move |edge| edge.includer() == Some(includer))
+    }
+
+    /// Produce a description of the graph in the DOT language of Graphviz. Each pair of files is
+    /// connected once even if there are several directives between them. Directives that are
+    /// not inside a file start at a node named `<input>`. Edges of `#include <file>` directives
+    /// are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph includes {\n");
+        let mut seen = Vec::new();
+        for edge in &self.edges {
+            let includer = edge.includer().unwrap_or(Path::new("<input>"));
+            if seen.contains(&(includer, edge.included())) {
+                continue;
+            }
+            seen.push((includer, edge.included()));
+
+            write!(dot, "    {} -> {}", quote(includer), quote(edge.included())).unwrap();
+            if edge.kind() == IncludeKind::Angled {
+                dot.push_str(" [style=dashed]");
+            }
+            dot.push_str(";\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Write `path` as a quoted DOT identifier.
+fn quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// An `#include` directive inside an [`IncludeGraph`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IncludeEdge {
+    includer: Option<PathBuf>,
+    line: Option<usize>,
+    included: PathBuf,
+    kind: IncludeKind,
+}
+
+impl IncludeEdge {
+    /// The file containing the directive, if the directive was found in a file.
+    pub fn includer(&self) -> Option<&Path> {
+        self.includer.as_deref()
+    }
+
+    /// The line of the directive, starting from 1, if the directive was found in a file.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The path of the included file.
+    pub fn included(&self) -> &Path {
+        &self.included
+    }
+
+    /// The form of the directive.
+    pub fn kind(&self) -> IncludeKind {
+        self.kind
+    }
+}
+
+/// An [`IncludeEdge`] whose paths have not been resolved yet.
+pub(super) struct IncludeRecord {
+    pub(super) directive: Token,
+    /// The region of the whole line of the directive.
+    pub(super) line: Span,
+    /// The number of `#include` directives being processed when the directive was found.
+    pub(super) depth: usize,
+    /// The path of the included file.
+    pub(super) included: PathBuf,
+    /// The region of the included file, if its contents were processed.
+    pub(super) contents: Option<Span>,
+    pub(super) kind: IncludeKind,
+    /// The number of conditional inclusion directives found before the included file.
+    pub(super) sections: usize,
+}
+
+impl Preprocessed {
+    /// The graph of the files included by `#include` directives.
+    pub fn include_graph(&self) -> IncludeGraph {
+        let map = &self.context.map;
+        let edges = self
+            .context
+            .include_edges
+            .iter()
+            .map(|record| IncludeEdge {
+                includer: map.find_file(record.directive.span),
+                line: map.find_line(record.directive.span),
+                included: record.included.clone(),
+                kind: record.kind,
+            })
+            .collect();
+        IncludeGraph { edges }
+    }
+}
@@ -0,0 +1,117 @@
+//! A cache of the files read and their tokens shared by several preprocessors.
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use crate::{lexer::Token, standard::LexerFeatures};
+
+/// The tokens of some contents for each combination of [`LexerFeatures`], computed the first time
+/// they are needed. They are `None` if the contents have invalid tokens.
+type Tokens = Arc<[OnceLock<Option<Arc<[Token]>>>; LexerFeatures::COUNT]>;
+
+/// A cache of the contents of the files read and their tokens that can be shared by several
+/// [`Preprocessor`](crate::Preprocessor)s, even if they are used in different threads.
+///
+/// Preprocessors using the same cache share a single copy of the contents of each file instead of
+/// keeping one copy each, which is useful for long-running processes that preprocess many
+/// translation units including the same headers. Files are still read every time they are needed
+/// but they are only tokenized once.
+///
+/// Files are identified by their contents, so the cache never returns outdated contents. It is
+/// cloned cheaply and every clone refers to the same cache. Contents are never removed unless
+/// [`clear`](Self::clear) is called.
+///
+/// ```
+/// use beheader::{HeaderCache, MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("a.c", "#include \"common.h\"\n");
+/// files.insert("b.c", "#include \"common.h\"\n");
+/// files.insert("common.h", "#define COMMON 1\n");
+///
+/// let cache = HeaderCache::new();
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files).header_cache(cache.clone());
+///
+/// let a = preprocessor.preprocess_file(&"a.c").unwrap();
+/// let b = preprocessor.preprocess_file(&"b.c").unwrap();
+/// // `a.c` and `b.c` have the same contents too.
+/// assert_eq!(cache.len(), 2);
+/// ```
+#[derive(Clone, Default)]
+pub struct HeaderCache {
+    files: Arc<RwLock<HashMap<Arc<[u8]>, Tokens>>>,
+}
+
+impl HeaderCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of different file contents stored.
+    pub fn len(&self) -> usize {
+        self.files.read().unwrap().len()
+    }
+
+    /// Check if there are no contents stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove every file from the cache. Preprocessors using the contents of any of them keep
+    /// their own copy.
+    pub fn clear(&self) {
+        self.files.write().unwrap().clear();
+    }
+
+    /// Remove `contents` and their tokens from the cache, if they are stored, so the memory is
+    /// released once no preprocessor uses them. Return whether they were stored.
+    pub fn remove(&self, contents: &[u8]) -> bool {
+        self.files.write().unwrap().remove(contents).is_some()
+    }
+
+    /// Get the shared copy of `contents`, storing it first if it is not stored yet.
+    pub(crate) fn intern(&self, contents: Vec<u8>) -> Arc<[u8]> {
+        if let Some((contents, _)) = self.files.read().unwrap().get_key_value(&*contents) {
+            return contents.clone();
+        }
+        let mut files = self.files.write().unwrap();
+        // Another thread could have stored the same contents while the lock was released.
+        match files.get_key_value(&*contents) {
+            Some((contents, _)) => contents.clone(),
+            None => {
+                let contents: Arc<[u8]> = contents.into();
+                files.insert(contents.clone(), Tokens::default());
+                contents
+            }
+        }
+    }
+
+    /// Get the tokens of `contents` produced using `features`, relative to their start,
+    /// computing them using `tokenize` if they were not computed yet. Return `None` if
+    /// `contents` are not stored in the cache or have invalid tokens.
+    pub(crate) fn tokens<F>(
+        &self,
+        contents: &[u8],
+        features: LexerFeatures,
+        tokenize: F,
+    ) -> Option<Arc<[Token]>>
+    where
+        F: FnOnce() -> Option<Arc<[Token]>>,
+    {
+        let tokens = self.files.read().unwrap().get(contents)?.clone();
+        // The lock is not held while tokenizing so other threads are not blocked.
+        tokens[features.index()].get_or_init(tokenize).clone()
+    }
+}
+
+impl fmt::Debug for HeaderCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderCache")
+            .field("files", &self.len())
+            .finish()
+    }
+}
@@ -0,0 +1,665 @@
+//! Source file inclusion (section 6.10.2 of C17).
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use super::{graph::IncludeRecord, Context};
+use crate::{
+    buffer::TokenBuffer,
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    fs::FileSystem,
+    lexer::{Token, TokenKind},
+    macros::skip_space,
+    span::Span,
+    time::Instant,
+};
+
+/// The two forms of `#include` directives (section 6.10.2 of C17).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IncludeKind {
+    /// `#include "file"`, which searches the directory of the including file first.
+    Quoted,
+    /// `#include <file>`.
+    Angled,
+}
+
+/// An `#include` directive that must be resolved, as seen by the hook set using
+/// [`Preprocessor::include_hook`](super::Preprocessor::include_hook).
+#[derive(Clone, Copy, Debug)]
+pub struct IncludeRequest<'a> {
+    name: &'a str,
+    kind: IncludeKind,
+    includer: Option<&'a Path>,
+}
+
+impl<'a> IncludeRequest<'a> {
+    /// The name of the included file as spelled in the directive without its delimiters, after
+    /// replacing macros if the directive used them.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// The form of the directive.
+    pub fn kind(&self) -> IncludeKind {
+        self.kind
+    }
+
+    /// The file containing the directive, if the directive was found in a file.
+    pub fn includer(&self) -> Option<&'a Path> {
+        self.includer
+    }
+}
+
+/// What to do with an `#include` directive, as decided by the hook set using
+/// [`Preprocessor::include_hook`](super::Preprocessor::include_hook).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IncludeResolution {
+    /// Include the file at this path, without searching the include directories.
+    Path(PathBuf),
+    /// Include `contents` as if they were the contents of a file at `path`, without reading any
+    /// file.
+    Contents { path: PathBuf, contents: Vec<u8> },
+    /// Ignore the directive.
+    Skip,
+    /// Search the file in the include directories as usual.
+    Default,
+}
+
+/// A hook consulted for every `#include` directive.
+pub(crate) type IncludeHook = Arc<dyn Fn(&IncludeRequest<'_>) -> IncludeResolution + Send + Sync>;
+
+/// The directories searched for the files named by `#include` directives.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SearchPath {
+    /// The directories searched only for `#include "file"` directives. These come from the
+    /// `-iquote` command-line option.
+    pub(crate) quote: Vec<PathBuf>,
+    /// The directories searched for both forms of `#include` directives. These come from the
+    /// `-I` command-line option.
+    pub(crate) angled: Vec<PathBuf>,
+    /// The directories of system headers searched for both forms of `#include` directives after
+    /// the `angled` directories. These come from the `-isystem` command-line option.
+    pub(crate) system: Vec<PathBuf>,
+    /// The directories of system headers searched for both forms of `#include` directives after
+    /// all the other ones. These come from the `-idirafter` command-line option.
+    pub(crate) after: Vec<PathBuf>,
+    /// The directory used as the root of the directories starting with `=` or `$SYSROOT`. These
+    /// come from the `--sysroot` command-line option.
+    pub(crate) sysroot: Option<PathBuf>,
+    /// Whether files that are not found are searched again ignoring the case of their names.
+    pub(crate) case_insensitive: bool,
+    /// The directories containing Apple frameworks, searched for both forms of `#include`
+    /// directives after the `system` directories. These come from the `-F` command-line option.
+    pub(crate) frameworks: Vec<PathBuf>,
+}
+
+impl SearchPath {
+    /// Find the file named `name` by an `#include` directive found in the file `includer` inside
+    /// `file_system`. `angled` is `true` if the name was delimited by `<` and `>`.
+    ///
+    /// The lookup order is the one used by GCC: files named between quotes are searched first in
+    /// the directory of `includer` and then in the `quote` directories. Then both forms search
+    /// the `angled` directories, the `system` directories, the `frameworks` directories and the
+    /// `after` directories. Finally, if `includer` is inside a framework, its subframeworks are
+    /// searched.
+    ///
+    /// Directories starting with `=` or `$SYSROOT` are relative to the `sysroot`.
+    ///
+    /// Return the path of the file and whether it was found in a directory of system headers.
+    /// The paths that were searched without finding the file are added to `missing`.
+    pub(crate) fn find(
+        &self,
+        file_system: &dyn FileSystem,
+        name: &str,
+        angled: bool,
+        includer: Option<&Path>,
+        missing: &mut Vec<PathBuf>,
+    ) -> Option<(PathBuf, bool)> {
+        search(
+            file_system,
+            self.candidates(name, angled, includer),
+            missing,
+        )
+    }
+
+    /// Find the file named `name` by an `#include_next` directive found in the file `includer`
+    /// like [`find`](Self::find) does, but only searching the directories that come after `dir`,
+    /// the directory where `includer` was found. If `includer` was not found in any directory,
+    /// every directory except the one of `includer` is searched.
+    pub(crate) fn find_next(
+        &self,
+        file_system: &dyn FileSystem,
+        name: &str,
+        angled: bool,
+        includer: Option<&Path>,
+        dir: Option<&Path>,
+        missing: &mut Vec<PathBuf>,
+    ) -> Option<(PathBuf, bool)> {
+        let mut candidates = self.candidates(name, angled, includer);
+        if !angled {
+            candidates.remove(0);
+        }
+        let depth = Path::new(name).components().count();
+        let start = dir
+            .and_then(|dir| {
+                candidates
+                    .iter()
+                    .position(|(path, _)| path.ancestors().nth(depth) == Some(dir))
+            })
+            .map_or(0, |position| position + 1);
+        candidates.drain(..start);
+        search(file_system, candidates, missing)
+    }
+
+    /// Find the file named `name` like [`find`](Self::find) does but ignoring the case of the
+    /// path of the file, if `case_insensitive` is `true`. Only ASCII letters are matched
+    /// ignoring their case and the directories where the search begins must match exactly.
+    pub(crate) fn find_case_insensitive(
+        &self,
+        file_system: &dyn FileSystem,
+        name: &str,
+        angled: bool,
+        includer: Option<&Path>,
+    ) -> Option<(PathBuf, bool)> {
+        if !self.case_insensitive {
+            return None;
+        }
+
+        let depth = Path::new(name).components().count();
+        self.candidates(name, angled, includer)
+            .into_iter()
+            .find_map(|(candidate, system)| {
+                let dir = candidate.ancestors().nth(depth)?;
+                let relative = candidate.strip_prefix(dir).ok()?;
+                let mut path = dir.to_owned();
+                for component in relative {
+                    let exact = path.join(component);
+                    path = if file_system.exists(&exact) {
+                        exact
+                    } else {
+                        let component = component.to_str()?;
+                        file_system
+                            .read_dir(&path)
+                            .ok()?
+                            .into_iter()
+                            .find(|entry| {
+                                entry
+                                    .file_name()
+                                    .and_then(|name| name.to_str())
+                                    .is_some_and(|name| name.eq_ignore_ascii_case(component))
+                            })?
+                    };
+                }
+                file_system.is_file(&path).then_some((path, system))
+            })
+    }
+
+    /// Get the paths where the file named `name` could be, in the order they must be searched,
+    /// and whether they are inside a directory of system headers.
+    fn candidates(
+        &self,
+        name: &str,
+        angled: bool,
+        includer: Option<&Path>,
+    ) -> Vec<(PathBuf, bool)> {
+        let name = Path::new(name);
+        if name.is_absolute() {
+            return vec![(name.to_owned(), false)];
+        }
+
+        let join = |dirs: &[PathBuf], system| {
+            dirs.iter()
+                .map(move |dir| (self.resolve(dir).join(name), system))
+                .collect::<Vec<_>>()
+        };
+
+        let mut candidates = Vec::new();
+        if !angled {
+            let current = includer.and_then(Path::parent).unwrap_or(Path::new(""));
+            candidates.push((current.join(name), false));
+            candidates.extend(join(&self.quote, false));
+        }
+        candidates.extend(join(&self.angled, false));
+        candidates.extend(join(&self.system, true));
+        for dir in &self.frameworks {
+            let headers = framework_header(&self.resolve(dir), name);
+            candidates.extend(headers.into_iter().map(|path| (path, false)));
+        }
+        candidates.extend(join(&self.after, true));
+
+        // A framework can contain other frameworks inside its `Frameworks` directory, which can
+        // only be included from the files of the framework.
+        let framework = includer.and_then(|includer| {
+            includer
+                .ancestors()
+                .find(|dir| dir.extension().is_some_and(|ext| ext == "framework"))
+        });
+        if let Some(framework) = framework {
+            let headers = framework_header(&framework.join("Frameworks"), name);
+            candidates.extend(headers.into_iter().map(|path| (path, false)));
+        }
+
+        candidates
+    }
+
+    /// Replace the `=` or `$SYSROOT` prefix of `dir` by the `sysroot`. If there is no `sysroot`,
+    /// the prefix is removed.
+    fn resolve(&self, dir: &Path) -> PathBuf {
+        let relative = dir.to_str().and_then(|dir| {
+            dir.strip_prefix('=')
+                .or_else(|| dir.strip_prefix("$SYSROOT"))
+        });
+        match (relative, &self.sysroot) {
+            (Some(relative), Some(sysroot)) => sysroot.join(relative.trim_start_matches('/')),
+            (Some(relative), None) => PathBuf::from(relative),
+            (None, _) => dir.to_owned(),
+        }
+    }
+}
+
+/// Return the first of `candidates` that is a file in `file_system`, together with whether it is
+/// inside a directory of system headers. The candidates that are not files are added to
+/// `missing`.
+fn search(
+    file_system: &dyn FileSystem,
+    candidates: Vec<(PathBuf, bool)>,
+    missing: &mut Vec<PathBuf>,
+) -> Option<(PathBuf, bool)> {
+    for (path, system) in candidates {
+        if file_system.is_file(&path) {
+            return Some((path, system));
+        }
+        if !missing.contains(&path) {
+            missing.push(path);
+        }
+    }
+    None
+}
+
+/// Get the paths where the header named `name` could be if it was inside a framework of `dir`.
+///
+/// The first component of `name` is the name of the framework, so `<Foo/Bar.h>` is searched in
+/// `Foo.framework/Headers/Bar.h` and then in `Foo.framework/PrivateHeaders/Bar.h`.
+fn framework_header(dir: &Path, name: &Path) -> Vec<PathBuf> {
+    let mut components = name.components();
+    let framework = match components.next() {
+        Some(Component::Normal(framework)) => framework,
+        _ => return Vec::new(),
+    };
+    let header = components.as_path();
+    if header.as_os_str().is_empty() {
+        return Vec::new();
+    }
+
+    let mut framework_dir = framework.to_owned();
+    framework_dir.push(".framework");
+    let framework_dir = dir.join(framework_dir);
+    ["Headers", "PrivateHeaders"]
+        .iter()
+        .map(|headers| framework_dir.join(headers).join(header))
+        .collect()
+}
+
+impl Context {
+    /// Process an `#include` directive named `directive` in the region `line` whose tokens after
+    /// the name are `body` and add the contents of the included file to `output`. The directive
+    /// can also be an `#include_next` or an `#import` directive.
+    ///
+    /// Return an error if the included file cannot be found or read or if the source code is not
+    /// valid.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                file = %self.trace_file(line),
+                line = self.map.find_line(line).unwrap_or_default()
+            )
+        )
+    )]
+    pub(super) fn include(
+        &mut self,
+        directive: Token,
+        line: Span,
+        body: &[Token],
+        output: &mut TokenBuffer,
+    ) -> Result<(), Diagnostic> {
+        let start = Instant::now();
+        let name = self.header_name(line, body);
+        self.report_expansion_errors();
+        let (name, angled) = name?;
+        let kind = if angled {
+            IncludeKind::Angled
+        } else {
+            IncludeKind::Quoted
+        };
+        let includer = self.map.find_file(directive.span);
+        let spelling = self.map.spelling(directive);
+        let (next, import) = (spelling == "include_next", spelling == "import");
+
+        let resolution = match &self.include_hook {
+            Some(hook) => hook(&IncludeRequest {
+                name: &name,
+                kind,
+                includer: includer.as_deref(),
+            }),
+            None => IncludeResolution::Default,
+        };
+
+        let searched = resolution == IncludeResolution::Default;
+        let (path, contents, system) = match resolution {
+            IncludeResolution::Skip => return Ok(()),
+            IncludeResolution::Contents { path, contents } => (path, Some(contents), false),
+            IncludeResolution::Path(path) => (path, None, false),
+            IncludeResolution::Default => match self.find_include(&name, angled, next, &includer) {
+                Some((path, system)) => (path, None, system),
+                None => match self.search_path.find_case_insensitive(
+                    self.map.file_system(),
+                    &name,
+                    angled,
+                    includer.as_deref(),
+                ) {
+                    Some((path, system)) => {
+                        let diagnostic = self.map.diagnostic(
+                            Severity::Warning,
+                            DiagnosticCode::IncludeCaseMismatch,
+                            directive.span,
+                            format!(
+                                "Included file \"{}\" was found with a different case at {}",
+                                name,
+                                path.display()
+                            ),
+                        );
+                        self.diagnostics.push(diagnostic);
+                        (path, None, system)
+                    }
+                    None => {
+                        let code = DiagnosticCode::IncludeNotFound;
+                        let message = format!("Cannot find included file \"{}\"", name);
+                        return Err(self.map.diagnostic(Severity::Error, code, line, message));
+                    }
+                },
+            },
+        };
+
+        let read = match contents {
+            Some(contents) => self
+                .map
+                .store_virtual_file(&path, &contents)
+                .map(|span| (span, path.clone())),
+            None => self.map.read_file(&path).and_then(|span| {
+                let path = self.map.file_path(&path)?;
+                Ok((span, path))
+            }),
+        };
+        // The directory where the file was found, which `#include_next` directives inside it
+        // start searching after.
+        let depth = Path::new(&name).components().count();
+        let dir = searched
+            .then(|| path.ancestors().nth(depth).map(Path::to_owned))
+            .flatten();
+        // The path that identifies the file, which can be different from the one used to find it.
+        let (span, path) = match read {
+            Ok(read) => read,
+            Err(error) => {
+                let code = DiagnosticCode::IncludeUnreadable;
+                let message = format!("Cannot read included file {}: {}", path.display(), error);
+                return Err(self.map.diagnostic(Severity::Error, code, line, message));
+            }
+        };
+        self.statistics.includes += start.elapsed();
+        self.trace_event("ReadFile", start, |_| path.display().to_string());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(name = %name, path = %path.display(), system, "resolved include");
+
+        // Everything included by a system header is a system header too.
+        let system = system || self.system_headers.contains(&includer.unwrap_or_default());
+        if system {
+            self.system_headers.insert(path.clone());
+        }
+
+        let record = self.include_edges.len();
+        self.include_edges.push(IncludeRecord {
+            directive,
+            line,
+            depth: self.inclusions.len(),
+            included: path.clone(),
+            contents: None,
+            kind,
+            sections: self.sections.len(),
+        });
+
+        if let Some(dir) = dir {
+            self.include_dirs.entry(path.clone()).or_insert(dir);
+        }
+
+        // Files containing `#pragma once` are only included once, and so are the files included
+        // by `#import` directives if they were not included before. The same file is always
+        // stored in the same region even if it is reached through different paths.
+        if self.once.contains(&span) || import && self.entered.contains_key(&span) {
+            return Ok(());
+        }
+        // The files whose tokens were taken from the token cache are not processed again if all
+        // of their contents would be skipped because their include guards are defined, like GCC
+        // does.
+        if let Some(info) = self.file_info.get(&span) {
+            let guarded = info.guard.as_deref().is_some_and(|guard| {
+                let symbol = self.map.intern(guard.as_bytes());
+                self.macros.is_defined(symbol)
+            });
+            if guarded || info.once && self.entered.contains_key(&span) {
+                return Ok(());
+            }
+        }
+        if import {
+            self.once.insert(span);
+        }
+
+        // Deeply nested inclusions are usually caused by files that include themselves without
+        // include guards, so the whole chain is reported as notes to find the cycle.
+        if self.inclusions.len() >= self.max_include_depth {
+            let mut diagnostic = self.map.diagnostic(
+                Severity::Error,
+                DiagnosticCode::IncludeDepth,
+                directive.span,
+                format!(
+                    "#include nested more than {} levels deep",
+                    self.max_include_depth
+                ),
+            );
+            for (directive, path) in self.inclusions.iter().chain([&(directive, path)]) {
+                let includer = self.map.find_file(directive.span).unwrap_or_default();
+                let line = self.map.find_line(directive.span).unwrap_or_default();
+                diagnostic = diagnostic.with_note(format!(
+                    "{}:{} includes {}",
+                    includer.display(),
+                    line,
+                    path.display()
+                ));
+            }
+            return Err(diagnostic);
+        }
+
+        self.include_edges[record].contents = Some(span);
+        self.inclusions.push((directive, path));
+        let directives = self.inclusions.iter().map(|&(directive, _)| directive);
+        self.entered
+            .entry(span)
+            .or_insert_with(|| directives.collect());
+        self.process_file(span, self.inclusions.len(), output)?;
+        self.inclusions.pop();
+        Ok(())
+    }
+
+    /// Find the file named `name` by an `#include` directive, or an `#include_next` directive if
+    /// `next` is `true`, found in the file `includer`.
+    fn find_include(
+        &mut self,
+        name: &str,
+        angled: bool,
+        next: bool,
+        includer: &Option<PathBuf>,
+    ) -> Option<(PathBuf, bool)> {
+        let file_system = self.map.file_system();
+        let includer = includer.as_deref();
+        match next {
+            true => {
+                let dir = includer.and_then(|includer| self.include_dirs.get(includer));
+                self.search_path.find_next(
+                    file_system,
+                    name,
+                    angled,
+                    includer,
+                    dir.map(PathBuf::as_path),
+                    &mut self.missing_files,
+                )
+            }
+            false => {
+                let missing = &mut self.missing_files;
+                self.search_path
+                    .find(file_system, name, angled, includer, missing)
+            }
+        }
+    }
+
+    /// Add a note to `diagnostic` with the position of each `#include` directive that was being
+    /// processed when the file containing it was entered for the first time, starting with the
+    /// innermost one, as GCC and Clang do with their "In file included from" lines.
+    pub(super) fn included_from(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        let span = diagnostic.span();
+        let directives = self
+            .entered
+            .iter()
+            .find(|(file, _)| file.contains(span))
+            .map(|(_, directives)| directives);
+        for directive in directives.into_iter().flatten().rev() {
+            if let Some((file, line)) = self.map.find_presumed(directive.span) {
+                let note = format!("Included from {}:{}", file.display(), line);
+                diagnostic = diagnostic.with_note(note);
+            }
+        }
+        diagnostic
+    }
+
+    /// Get the name of the file included by an `#include` directive in the region `line` whose
+    /// tokens after the name are `body` and whether it was delimited by `<` and `>`.
+    ///
+    /// If the directive does not have a header name, its tokens are replaced as in normal text
+    /// and the result must be a string literal or a sequence of tokens between `<` and `>`
+    /// (section 6.10.2p4 of C17).
+    fn header_name(&self, line: Span, body: &[Token]) -> Result<(String, bool), Diagnostic> {
+        let mut spelling = String::new();
+        match skip_space(&mut body.iter().copied()) {
+            Some(token) if token.kind == TokenKind::Header => {
+                spelling = self.map.spelling(token);
+            }
+            _ => {
+                for token in self.macros.expand(&self.map, body)? {
+                    match token.kind {
+                        TokenKind::Space => spelling.push(' '),
+                        _ => spelling.push_str(&self.map.spelling(token)),
+                    }
+                }
+            }
+        }
+
+        split_header_name(&spelling).ok_or_else(|| {
+            let message = "#include expects \"FILENAME\" or <FILENAME>";
+            let code = DiagnosticCode::InvalidInclude;
+            self.map.diagnostic(Severity::Error, code, line, message)
+        })
+    }
+
+    /// Evaluate the `__has_include` operator `operator`, or the `__has_include_next` operator
+    /// if `next` is `true`, whose operand is `operand`, the tokens between its parentheses
+    /// (section 6.10.1 of C23). Return whether the file it names would be found by an
+    /// `#include` directive in its place.
+    ///
+    /// The operand is a header name or the tokens of an `#include` directive that does not have
+    /// one, whose macros are replaced. Return an error if it is not valid.
+    pub(super) fn has_include(
+        &mut self,
+        operator: Token,
+        operand: &[Token],
+        next: bool,
+    ) -> Result<bool, Diagnostic> {
+        // Header names are not recognized outside `#include` directives, so a name between `<`
+        // and `>` is made of the tokens between them, whose macros are not replaced.
+        let angled = skip_space(&mut operand.iter().copied())
+            .is_some_and(|token| self.map.is_punct(token, b"<"));
+        let tokens = match angled {
+            true => operand.to_vec(),
+            false => self.macros.expand(&self.map, operand)?,
+        };
+        let mut spelling = String::new();
+        for token in tokens {
+            match token.kind {
+                TokenKind::Space => spelling.push(' '),
+                _ => spelling.push_str(&self.map.spelling(token)),
+            }
+        }
+        let (name, angled) = match split_header_name(&spelling) {
+            Some(name) => name,
+            None => {
+                let message = format!(
+                    "Operator \"{}\" requires a header name",
+                    self.map.spelling(operator)
+                );
+                let code = DiagnosticCode::InvalidHasInclude;
+                return Err(self
+                    .map
+                    .diagnostic(Severity::Error, code, operator.span, message));
+            }
+        };
+        let kind = if angled {
+            IncludeKind::Angled
+        } else {
+            IncludeKind::Quoted
+        };
+        let includer = self.map.find_file(operator.span);
+
+        let resolution = match &self.include_hook {
+            Some(hook) => hook(&IncludeRequest {
+                name: &name,
+                kind,
+                includer: includer.as_deref(),
+            }),
+            None => IncludeResolution::Default,
+        };
+        Ok(match resolution {
+            IncludeResolution::Skip => false,
+            IncludeResolution::Contents { .. } => true,
+            IncludeResolution::Path(path) => self.map.file_system().is_file(&path),
+            IncludeResolution::Default => {
+                self.find_include(&name, angled, next, &includer).is_some()
+                    || self
+                        .search_path
+                        .find_case_insensitive(
+                            self.map.file_system(),
+                            &name,
+                            angled,
+                            includer.as_deref(),
+                        )
+                        .is_some()
+            }
+        })
+    }
+}
+
+/// Split the spelling of the name of an included file into the name without its delimiters and
+/// whether it is delimited by `<` and `>` instead of `"`. White-space around it is ignored.
+/// Return `None` if it is not delimited by either of them.
+fn split_header_name(spelling: &str) -> Option<(String, bool)> {
+    let spelling = spelling.trim();
+    if let Some(name) = spelling
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+    {
+        Some((name.to_owned(), false))
+    } else {
+        let name = spelling.strip_prefix('<')?.strip_suffix('>')?;
+        Some((name.to_owned(), true))
+    }
+}
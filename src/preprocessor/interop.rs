@@ -0,0 +1,97 @@
+//! Conversions from the output of the preprocessor into the representations used by other crates
+//! that handle C code.
+#[cfg(feature = "lang-c")]
+use super::Emitter;
+use super::Preprocessed;
+#[cfg(feature = "cexpr")]
+use crate::{
+    c_token::{CTokenKind, CTokens},
+    lexer::Token,
+    macros::MacroKind,
+};
+
+#[cfg(feature = "cexpr")]
+impl Preprocessed {
+    /// Convert the tokens of the output into the tokens of the [`cexpr`] crate. The tokens that
+    /// are not valid C tokens are skipped, as they are by [`c_tokens`](Self::c_tokens).
+    ///
+    /// ```
+    /// use cexpr::expr::EvalResult;
+    ///
+    /// let preprocessed = beheader::preprocess(b"#define FOUR (2 << 1)\nFOUR * 3\n").unwrap();
+    /// let tokens = preprocessed.cexpr_tokens();
+    /// assert_eq!(cexpr::expr::expr(&tokens).unwrap().1, EvalResult::Int(std::num::Wrapping(12)));
+    /// ```
+    pub fn cexpr_tokens(&self) -> Vec<cexpr::token::Token> {
+        self.to_cexpr(self.context.output.tokens())
+    }
+
+    /// Convert the definition of the object-like macro named `name` into the tokens of the
+    /// [`cexpr`] crate: its name followed by its replacement list, as expected by
+    /// [`cexpr::expr::macro_definition`]. Return `None` if there is no object-like macro with
+    /// that name at the end of the input.
+    ///
+    /// ```
+    /// use cexpr::expr::EvalResult;
+    ///
+    /// let preprocessed = beheader::preprocess(b"#define MASK (1u << 4) - 1\n").unwrap();
+    /// let tokens = preprocessed.cexpr_macro("MASK").unwrap();
+    /// let (name, value) = cexpr::expr::macro_definition(&tokens).unwrap().1;
+    /// assert_eq!((name, value), (&b"MASK"[..], EvalResult::Int(std::num::Wrapping(15))));
+    /// ```
+    pub fn cexpr_macro(&self, name: &str) -> Option<Vec<cexpr::token::Token>> {
+        let symbol = self.context.map.find_symbol(name.as_bytes())?;
+        let (_, definition) = self.context.macros.get_key_value(symbol)?;
+        if !matches!(definition.kind, MacroKind::Object) {
+            return None;
+        }
+        let name = cexpr::token::Token::from((cexpr::token::Kind::Identifier, name.as_bytes()));
+        let mut tokens = vec![name];
+        tokens.extend(self.to_cexpr(definition.replacement.tokens()));
+        Some(tokens)
+    }
+
+    /// Convert `tokens` into the tokens of the [`cexpr`] crate.
+    fn to_cexpr(&self, tokens: &[Token]) -> Vec<cexpr::token::Token> {
+        let c_tokens = CTokens::convert(tokens, &self.context.map, self.context.standard);
+        c_tokens
+            .tokens()
+            .iter()
+            .map(|token| {
+                let kind = match token.kind() {
+                    CTokenKind::Keyword(_) => cexpr::token::Kind::Keyword,
+                    CTokenKind::Ident => cexpr::token::Kind::Identifier,
+                    CTokenKind::Integer(_)
+                    | CTokenKind::Floating(_)
+                    | CTokenKind::Char
+                    | CTokenKind::Str => cexpr::token::Kind::Literal,
+                    CTokenKind::Punct => cexpr::token::Kind::Punctuation,
+                };
+                let spelling = self.context.map.get_bytes(token.span());
+                cexpr::token::Token::from((kind, &*spelling))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "lang-c")]
+impl Preprocessed {
+    /// Parse the output using the [`lang_c`] crate, which works on preprocessed text instead of
+    /// tokens. The output is rendered with the line markers of the default [`Emitter`] so the
+    /// locations of the parser refer to the source files. Only the `flavor` of `config` is used,
+    /// as the preprocessing was already done.
+    ///
+    /// ```
+    /// let source = b"#define T int\nT main(void) { return 0; }\n";
+    /// let preprocessed = beheader::preprocess(source).unwrap();
+    /// let parse = preprocessed.parse_lang_c(&lang_c::driver::Config::default()).unwrap();
+    /// assert_eq!(parse.unit.0.len(), 1);
+    /// ```
+    pub fn parse_lang_c(
+        &self,
+        config: &lang_c::driver::Config,
+    ) -> Result<lang_c::driver::Parse, lang_c::driver::SyntaxError> {
+        let text = Emitter::new().render(self);
+        lang_c::driver::parse_preprocessed(config, String::from_utf8_lossy(&text).into_owned())
+    }
+}
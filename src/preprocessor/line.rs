@@ -0,0 +1,197 @@
+//! Line control (section 6.10.4 of C17), the line markers written by GCC and the diagnostic
+//! directives `#error` (section 6.10.5 of C17) and `#warning`.
+use std::path::PathBuf;
+
+use super::Context;
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    lexer::{Token, TokenKind},
+    span::{LineMarkerFlags, Span},
+};
+
+/// The largest line number a `#line` directive can give (section 6.10.4p3 of C17).
+const MAX_LINE: usize = 2147483647;
+
+impl Context {
+    /// Process the `#line` directive in the region `line`, whose tokens after its name are
+    /// `body`. They are replaced like the text of the file unless they start with a number, as
+    /// they must match the form of the directive after the replacement (section 6.10.4p5 of
+    /// C17).
+    ///
+    /// Return an error if the directive does not have a valid line number or file name.
+    pub(super) fn line_directive(&mut self, line: Span, body: &[Token]) -> Result<(), Diagnostic> {
+        let first = body.iter().find(|token| token.kind != TokenKind::Space);
+        let tokens = match first {
+            Some(token) if token.kind == TokenKind::Number => body.to_vec(),
+            _ => {
+                let expanded = self.macros.expand(&self.map, body);
+                self.report_expansion_errors();
+                expanded?
+            }
+        };
+        let mut rest = &tokens[..];
+        let number = self.line_number(line, "#line", next_token(&mut rest))?;
+        let path = match next_token(&mut rest) {
+            Some(token) => Some(self.line_file_name(token)?),
+            None => None,
+        };
+        self.check_extra_tokens("line", rest);
+        self.map.set_presumed_line(line, number, path, None);
+        Ok(())
+    }
+
+    /// Process the line marker in the region `line`, like the `# 1 "file.h" 1` lines written by
+    /// GCC, whose line number is `number` and whose tokens after it are `body`.
+    ///
+    /// Return an error if the line marker does not have a valid line number, file name or flags.
+    pub(super) fn line_marker(
+        &mut self,
+        line: Span,
+        number: Token,
+        body: &[Token],
+    ) -> Result<(), Diagnostic> {
+        let mut rest = body;
+        let number = self.line_number(line, "#", Some(number))?;
+        let path = match next_token(&mut rest) {
+            Some(token) => Some(self.line_file_name(token)?),
+            None => None,
+        };
+        // The flags must be given in increasing order, each one at most once.
+        let mut flags = LineMarkerFlags::default();
+        let mut previous = 0;
+        while let Some(token) = next_token(&mut rest) {
+            let flag = self.map.spelling(token);
+            let value = match flag.as_str() {
+                "1" if previous < 1 => 1,
+                "2" if previous < 1 => 2,
+                "3" if previous < 3 => 3,
+                "4" if previous < 4 => 4,
+                _ => {
+                    let message = format!("Invalid flag \"{}\" in line marker", flag);
+                    let code = DiagnosticCode::InvalidLineDirective;
+                    return Err(self
+                        .map
+                        .diagnostic(Severity::Error, code, token.span, message));
+                }
+            };
+            match value {
+                1 => flags.enter = true,
+                2 => flags.leave = true,
+                3 => flags.system = true,
+                // Flag 4 says the file is implicitly wrapped in `extern "C"`, which does not matter
+                // to the preprocessor.
+                _ => {}
+            }
+            previous = value;
+        }
+        self.map.set_presumed_line(line, number, path, Some(flags));
+        Ok(())
+    }
+
+    /// Get the line number given by `token` in the `#line` directive or line marker in the region
+    /// `line`, whose name is `directive`, or an error if it is not a valid one.
+    fn line_number(
+        &self,
+        line: Span,
+        directive: &str,
+        token: Option<Token>,
+    ) -> Result<usize, Diagnostic> {
+        let code = DiagnosticCode::InvalidLineDirective;
+        let token = match token {
+            Some(token) => token,
+            None => {
+                let message = format!("{} expects a line number", directive);
+                return Err(self.map.diagnostic(Severity::Error, code, line, message));
+            }
+        };
+        // The line number is a sequence of digits, which is decimal even if it starts with `0`.
+        let spelling = self.map.spelling(token);
+        if token.kind != TokenKind::Number || !spelling.bytes().all(|byte| byte.is_ascii_digit()) {
+            let message = format!(
+                "\"{}\" after {} is not a positive integer",
+                spelling, directive
+            );
+            return Err(self
+                .map
+                .diagnostic(Severity::Error, code, token.span, message));
+        }
+        match spelling.parse().ok().filter(|&number| number <= MAX_LINE) {
+            Some(number) => Ok(number),
+            None => {
+                let message = "Line number out of range";
+                Err(self
+                    .map
+                    .diagnostic(Severity::Error, code, token.span, message))
+            }
+        }
+    }
+
+    /// Get the file name given by `token` in a `#line` directive or a line marker, or an error if
+    /// it is not a string literal without encoding prefix.
+    fn line_file_name(&self, token: Token) -> Result<PathBuf, Diagnostic> {
+        let spelling = self.map.spelling(token);
+        match spelling
+            .strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+        {
+            Some(name) if token.kind == TokenKind::Str => Ok(PathBuf::from(unescape(name))),
+            _ => {
+                let message = format!("\"{}\" is not a valid file name", spelling);
+                let code = DiagnosticCode::InvalidLineDirective;
+                Err(self
+                    .map
+                    .diagnostic(Severity::Error, code, token.span, message))
+            }
+        }
+    }
+
+    /// Report the `#error` or `#warning` directive in the region `line`, whose name is `directive`
+    /// and whose tokens after it are `body`. An `#error` directive is reported as an error that
+    /// does not stop the preprocessing, as GCC does.
+    pub(super) fn diagnostic_directive(&mut self, line: Span, directive: &str, body: &[Token]) {
+        let mut message = format!("#{}", directive);
+        for token in body {
+            match token.kind {
+                TokenKind::Space => message.push(' '),
+                _ => message.push_str(&self.map.spelling(*token)),
+            }
+        }
+        let message = message.trim_end();
+        let (severity, code) = match directive {
+            "error" => (Severity::Error, DiagnosticCode::ErrorDirective),
+            _ => (Severity::Warning, DiagnosticCode::WarningDirective),
+        };
+        if self.diagnostics.is_reported(code) {
+            let diagnostic = self.map.diagnostic(severity, code, line, message);
+            self.diagnostics.push(diagnostic);
+        }
+    }
+}
+
+/// Take the next token of `rest` that is not white-space, if any.
+fn next_token(rest: &mut &[Token]) -> Option<Token> {
+    let index = rest
+        .iter()
+        .position(|token| token.kind != TokenKind::Space)?;
+    let token = rest[index];
+    *rest = &rest[index + 1..];
+    Some(token)
+}
+
+/// Replace the escape sequences `\\` and `\"` of the contents of a string literal, which are the
+/// only ones needed to write any file name and the only ones replaced by the `_Pragma` operator
+/// (section 6.10.9 of C17). The others are kept as they are.
+pub(super) fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('\\' | '"'))) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
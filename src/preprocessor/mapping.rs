@@ -0,0 +1,172 @@
+//! The correspondence between the text produced by an [`Emitter`](super::Emitter) and the
+//! source code it came from.
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// A token of the text produced by an [`Emitter`](super::Emitter) and the location of its
+/// spelling in the source code.
+///
+/// Lines and columns start from 1 and columns are counted in bytes. The tokens produced by
+/// macro replacement are located where they are spelled, usually inside the definition of the
+/// macro.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MappedToken {
+    output_line: usize,
+    output_column: usize,
+    len: usize,
+    file: Option<PathBuf>,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+impl MappedToken {
+    /// The line of the text where the token is.
+    pub fn output_line(&self) -> usize {
+        self.output_line
+    }
+
+    /// The column of the text where the token starts.
+    pub fn output_column(&self) -> usize {
+        self.output_column
+    }
+
+    /// The length of the token in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the token is empty, which never happens.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The file where the token is spelled, if it is spelled in a file. Tokens produced by the
+    /// preprocessor, like the ones produced by `#` and `##`, are not.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    /// The line of the file where the token is spelled, if it is spelled in a file.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// The column of the file where the token starts, if it is spelled in a file.
+    pub fn column(&self) -> Option<usize> {
+        self.column
+    }
+}
+
+/// The locations in the source code of every token of the text produced by an
+/// [`Emitter`](super::Emitter), which can be used to translate positions in the text back to
+/// the source code.
+///
+/// ```
+/// use beheader::{Emitter, Preprocessor};
+///
+/// let source = b"#define ONE 1\nint a =\n  ONE;\n";
+/// let preprocessed = Preprocessor::new().preprocess(source).unwrap();
+/// let (text, map) = Emitter::new().line_markers(false).render_with_map(&preprocessed);
+/// assert_eq!(text, b"int a =\n1;\n");
+///
+/// let one = map.find(2, 1).unwrap();
+/// assert_eq!((one.line(), one.column()), (Some(1), Some(13)));
+/// let semicolon = map.find(2, 2).unwrap();
+/// assert_eq!((semicolon.line(), semicolon.column()), (Some(3), Some(6)));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PositionMap {
+    tokens: Vec<MappedToken>,
+}
+
+impl PositionMap {
+    /// The tokens of the text, in the order they were written.
+    pub fn tokens(&self) -> &[MappedToken] {
+        &self.tokens
+    }
+
+    /// Find the token containing the position at `column` of `line` of the text, or the last
+    /// token before it in the same line if the position is white-space.
+    pub fn find(&self, line: usize, column: usize) -> Option<&MappedToken> {
+        let end = self
+            .tokens
+            .partition_point(|token| (token.output_line, token.output_column) <= (line, column));
+        self.tokens[..end]
+            .last()
+            .filter(|token| token.output_line == line)
+    }
+
+    /// Produce the JSON representation of the map, an object whose `tokens` member has an array
+    /// with one object per token. Each of them has the members `output_line`, `output_column`,
+    /// `length`, `file`, `line` and `column`, the last three of which can be `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"tokens\":[");
+        for (i, token) in self.tokens.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"output_line\":{},\"output_column\":{},\"length\":{},\"file\":",
+                token.output_line, token.output_column, token.len
+            )
+            .unwrap();
+            match &token.file {
+                Some(file) => json_string(&mut json, &file.to_string_lossy()),
+                None => json.push_str("null"),
+            }
+            for (name, value) in [("line", token.line), ("column", token.column)] {
+                match value {
+                    Some(value) => write!(json, ",\"{}\":{}", name, value).unwrap(),
+                    None => write!(json, ",\"{}\":null", name).unwrap(),
+                }
+            }
+            json.push('}');
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// Write the JSON representation of the map to the file at `path`.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+
+    /// Add a token of `len` bytes starting at `output_column` of `output_line`, spelled at the
+    /// given location.
+    pub(super) fn push(
+        &mut self,
+        (output_line, output_column): (usize, usize),
+        len: usize,
+        (file, line, column): (Option<PathBuf>, Option<usize>, Option<usize>),
+    ) {
+        self.tokens.push(MappedToken {
+            output_line,
+            output_column,
+            len,
+            file,
+            line,
+            column,
+        });
+    }
+}
+
+/// Append `text` to `json` as a JSON string.
+pub(super) fn json_string(json: &mut String, text: &str) {
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
@@ -0,0 +1,181 @@
+//! Side tables of data attached to the tokens of the output by their indices, so the tokens
+//! themselves stay small.
+use std::path::PathBuf;
+
+use super::{MacroExpansion, Preprocessed};
+use crate::lexer::Token;
+
+/// A table of values attached to some tokens of a sequence, like the tokens of
+/// [`Preprocessed::tokens`], keyed by the indices of the tokens. It can be used to annotate
+/// tokens while analyzing them.
+///
+/// ```
+/// use beheader::{TokenKind, TokenTable};
+///
+/// let preprocessed = beheader::preprocess(b"int a = 1, b = a;\n").unwrap();
+/// let map = preprocessed.source_map();
+/// let mut uses = TokenTable::new();
+/// for (index, token) in preprocessed.tokens().iter().enumerate() {
+///     if token.kind() == TokenKind::Ident && map.spelling(token.span()) == Some(b"a") {
+///         uses.insert(index, "a");
+///     }
+/// }
+/// assert_eq!(uses.len(), 2);
+/// assert_eq!(uses.iter().map(|(index, _)| index).collect::<Vec<_>>(), [2, 13]);
+/// assert_eq!(uses.get(13), Some(&"a"));
+/// assert_eq!(uses.get(14), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenTable<T> {
+    /// The indices of the tokens and their values, sorted by the former.
+    entries: Vec<(usize, T)>,
+}
+
+impl<T> Default for TokenTable<T> {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<T> TokenTable<T> {
+    /// Create an empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find the position of the entry of the token at `index` in the entries.
+    fn position(&self, index: usize) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by_key(&index, |&(token, _)| token)
+    }
+
+    /// Attach `value` to the token at `index`. Return the value that was attached to it before,
+    /// if any. Adding values in the order of the tokens is faster.
+    pub fn insert(&mut self, index: usize, value: T) -> Option<T> {
+        if self.entries.last().is_none_or(|&(last, _)| last < index) {
+            self.entries.push((index, value));
+            return None;
+        }
+        match self.position(index) {
+            Ok(position) => Some(std::mem::replace(&mut self.entries[position].1, value)),
+            Err(position) => {
+                self.entries.insert(position, (index, value));
+                None
+            }
+        }
+    }
+
+    /// Get the value attached to the token at `index`, if any.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let position = self.position(index).ok()?;
+        Some(&self.entries[position].1)
+    }
+
+    /// Get the value attached to the token at `index` to modify it, if any.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        let position = self.position(index).ok()?;
+        Some(&mut self.entries[position].1)
+    }
+
+    /// Remove the value attached to the token at `index` and return it, if any.
+    pub fn remove(&mut self, index: usize) -> Option<T> {
+        let position = self.position(index).ok()?;
+        Some(self.entries.remove(position).1)
+    }
+
+    /// Iterate over the indices of the tokens with a value and their values, in the order of
+    /// the tokens.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.entries.iter().map(|(index, value)| (*index, value))
+    }
+
+    /// The number of tokens with a value.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no token has a value.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove every value.
+    pub(super) fn clear(&mut self) {
+        self.entries.clear()
+    }
+
+    /// Keep only the values for which `f` returns `true`, which can change the index of their
+    /// tokens as long as their order does not change.
+    pub(super) fn retain_mut<F: FnMut(&mut usize, &mut T) -> bool>(&mut self, mut f: F) {
+        self.entries.retain_mut(|(index, value)| f(index, value));
+        debug_assert!(self.entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+}
+
+/// What is known about a token of the output, obtained using [`Preprocessed::token_info`].
+///
+/// ```
+/// use beheader::{MemoryFileSystem, Preprocessor};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#include \"config.h\"\nint a = VERSION;\n");
+/// files.insert("config.h", "#define VERSION 2\nint b;\n");
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files);
+/// let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+///
+/// let map = preprocessed.source_map();
+/// let spelling = |token: beheader::Token| map.spelling(token.span()).unwrap();
+/// let b = preprocessed.tokens().iter().position(|&token| spelling(token) == b"b").unwrap();
+/// let info = preprocessed.token_info(b).unwrap();
+/// assert_eq!(info.file(), Some("config.h".into()));
+/// assert!(info.expansion().is_none());
+///
+/// let two = preprocessed.tokens().iter().position(|&token| spelling(token) == b"2").unwrap();
+/// let info = preprocessed.token_info(two).unwrap();
+/// assert_eq!(info.file(), Some("main.c".into()));
+/// assert_eq!(info.expansion().unwrap().name(), "VERSION");
+/// ```
+#[derive(Clone)]
+pub struct TokenInfo<'a> {
+    token: Token,
+    expansion: Option<MacroExpansion<'a>>,
+    file: Option<PathBuf>,
+}
+
+impl<'a> TokenInfo<'a> {
+    /// The token.
+    pub fn token(&self) -> Token {
+        self.token
+    }
+
+    /// The macro invocation whose replacement list produced the token, if any, like
+    /// [`Preprocessed::expansion`].
+    pub fn expansion(&self) -> Option<MacroExpansion<'a>> {
+        self.expansion
+    }
+
+    /// The path of the file whose processing produced the token, which is the innermost file
+    /// being processed at that point. It is `None` for the tokens of an input that is not a file
+    /// and when the output was written to a stream.
+    pub fn file(&self) -> Option<PathBuf> {
+        self.file.clone()
+    }
+}
+
+impl Preprocessed {
+    /// Get what is known about the token at `index` of [`tokens`](Self::tokens), or `None` if
+    /// there is no such token.
+    pub fn token_info(&self, index: usize) -> Option<TokenInfo<'_>> {
+        let context = &self.context;
+        Some(TokenInfo {
+            token: *self.tokens().get(index)?,
+            expansion: self.expansion(index),
+            file: context
+                .output_file(index)
+                .and_then(|span| context.map.find_file(span)),
+        })
+    }
+}
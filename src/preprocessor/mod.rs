@@ -0,0 +1,2316 @@
+//! The preprocessor itself, which processes directives and replaces macros in tokenized source.
+mod batch;
+mod budget;
+mod cache_key;
+mod constant;
+mod content_hash;
+mod coverage;
+mod depfile;
+mod emitter;
+mod events;
+mod expansions;
+mod graph;
+mod header_cache;
+mod include;
+#[cfg(any(feature = "cexpr", feature = "lang-c"))]
+mod interop;
+mod line;
+mod mapping;
+mod metadata;
+mod pedantic;
+mod pragma;
+mod rewrite;
+mod session;
+mod snapshot;
+mod statistics;
+mod strings;
+mod suggestions;
+#[cfg(test)]
+mod tests;
+mod token_cache;
+mod trace;
+mod unifdef;
+mod warnings;
+
+use std::{
+    cell::Cell,
+    collections::{BTreeSet, HashMap, HashSet},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    buffer::{Cursor, TokenBuffer},
+    c_token::CTokens,
+    charset::InputCharset,
+    diagnostic::{
+        Diagnostic, DiagnosticCode, DiagnosticGroup, Diagnostics, Level, Severity, SeverityMap,
+        Warning, WarningGroup,
+    },
+    error::Error,
+    expr::{Evaluation, IntmaxWidth},
+    extension::Extension,
+    fs::{default_file_system, FileSystem, PrefixMap},
+    lexer::{Lines, Token, TokenKind},
+    macros::{skip_space, MacroDefinition, MacroTable},
+    predefined::{FeatureMacro, MANDATORY_MACROS},
+    span::{ColumnUnit, SourceMap, SourceMapSnapshot, Span, SpanLocation},
+    standard::{LexerFeatures, Standard},
+    target::Target,
+    time::Instant,
+    token_stream::TokenStream,
+};
+
+use budget::Budget;
+use include::{IncludeHook, SearchPath};
+use token_cache::{FileInfo, TokenCache};
+use trace::Tracer;
+
+pub use batch::{Batch, BatchResults};
+pub use cache_key::CacheKey;
+pub use constant::MacroConstant;
+pub use content_hash::ContentHash;
+pub use coverage::{Branch, ConditionalSection};
+pub use depfile::Depfile;
+pub use emitter::{Emitter, OutputProfile};
+pub use events::{Event, Events};
+pub use expansions::MacroExpansion;
+pub use graph::{IncludeEdge, IncludeGraph};
+pub use header_cache::HeaderCache;
+pub use include::{IncludeKind, IncludeRequest, IncludeResolution};
+pub use mapping::{MappedToken, PositionMap};
+pub use metadata::{TokenInfo, TokenTable};
+pub use session::Session;
+pub use snapshot::{ParseSnapshotError, Snapshot};
+pub use statistics::Statistics;
+pub use trace::{TimeTrace, TraceEvent};
+
+/// A configurable C preprocessor.
+///
+/// ```
+/// let mut preprocessor = beheader::Preprocessor::new();
+/// preprocessor.define("NDEBUG").define("VERSION=2").undefine("unix");
+/// preprocessor.preprocess(b"int version = VERSION;").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Preprocessor {
+    /// The version of the C standard being followed.
+    standard: Standard,
+    /// The target whose macros are predefined.
+    target: Target,
+    /// The environment and conditional feature macros that are predefined.
+    feature_macros: BTreeSet<FeatureMacro>,
+    /// The extensions to ISO C that are enabled.
+    extensions: BTreeSet<Extension>,
+    /// Whether only the macros required by the standard are predefined.
+    standard_macros_only: bool,
+    /// The macros defined and undefined before processing begins, in the order they were given.
+    command_line: Vec<CommandLineMacro>,
+    /// The files whose macros are defined before processing begins.
+    macro_files: Vec<PathBuf>,
+    /// The files included before processing begins, in the order they were given.
+    included_files: Vec<IncludedFile>,
+    /// The directories searched for included files.
+    search_path: SearchPath,
+    /// Where files are read from.
+    file_system: Arc<dyn FileSystem>,
+    /// Whether symbolic links are resolved when comparing paths.
+    resolve_symlinks: bool,
+    /// The hook consulted for every `#include` directive.
+    include_hook: Option<IncludeHook>,
+    /// Whether `#include` directives are kept in the output.
+    keep_includes: bool,
+    /// Whether adjacent string literals are concatenated.
+    concatenate_strings: bool,
+    /// The maximum number of nested `#include` directives.
+    max_include_depth: usize,
+    /// The number of errors that stops the preprocessing, or `0` if there is no limit.
+    max_errors: usize,
+    /// How the columns of locations are counted.
+    column_unit: ColumnUnit,
+    /// The encoding of the source code read.
+    input_charset: InputCharset,
+    /// The replacements of the prefixes of the paths shown to users.
+    prefix_map: PrefixMap,
+    /// The replacements of the prefixes of the paths given by `__FILE__`.
+    macro_prefix_map: PrefixMap,
+    /// The cache of the tokens of the files read.
+    token_cache: Option<TokenCache>,
+    /// The cache of the files read shared with other preprocessors.
+    header_cache: Option<HeaderCache>,
+    /// How macro definitions are dumped into the output.
+    macro_dump: Option<MacroDump>,
+    /// Which comments are kept in the output.
+    comments: Option<Comments>,
+    /// The snapshot used as the initial state.
+    snapshot: Option<Snapshot>,
+    /// How controlling expressions of conditional inclusion directives are evaluated.
+    evaluation: Evaluation,
+    /// The severities chosen for the diagnostics.
+    severities: SeverityMap,
+    /// The minimum duration of the events of the time trace, if there is one.
+    time_trace: Option<Duration>,
+    /// The resources a run can use.
+    budget: Budget,
+    /// The flag that cancels the preprocessing when it is set, if there is one.
+    cancellation: Option<Arc<AtomicBool>>,
+}
+
+/// The ways in which macro definitions can be dumped into the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MacroDump {
+    /// Replace the output with a `#define` directive for each macro defined at the end of the
+    /// input, including the predefined ones. This is equivalent to the `-dM` option of GCC.
+    Final,
+    /// Keep the `#define` and `#undef` directives in the output. This is equivalent to the `-dD`
+    /// option of GCC.
+    Directives,
+    /// Keep the `#define` and `#undef` directives in the output but remove the parameters and
+    /// replacement lists of the macros. This is equivalent to the `-dN` option of GCC.
+    Names,
+}
+
+/// The comments that can be kept in the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comments {
+    /// Keep the comments outside directives, including the ones inside the arguments of macro
+    /// invocations. This is equivalent to the `-C` option of GCC.
+    Text,
+    /// Keep the comments inside the replacement lists of macros too, which are kept wherever the
+    /// macros are replaced. This is equivalent to the `-CC` option of GCC.
+    All,
+}
+
+#[derive(Clone, Debug)]
+enum CommandLineMacro {
+    Define(String),
+    Undefine(String),
+}
+
+#[derive(Clone, Debug)]
+enum IncludedFile {
+    Path(PathBuf),
+    Source { name: PathBuf, contents: Arc<[u8]> },
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self {
+            standard: Standard::default(),
+            target: Target::default(),
+            feature_macros: FeatureMacro::DEFAULT.iter().copied().collect(),
+            extensions: BTreeSet::new(),
+            standard_macros_only: false,
+            command_line: Vec::new(),
+            macro_files: Vec::new(),
+            included_files: Vec::new(),
+            search_path: SearchPath::default(),
+            file_system: default_file_system(),
+            resolve_symlinks: false,
+            include_hook: None,
+            keep_includes: false,
+            concatenate_strings: false,
+            max_include_depth: 200,
+            max_errors: 0,
+            column_unit: ColumnUnit::Bytes,
+            input_charset: InputCharset::Utf8,
+            prefix_map: PrefixMap::default(),
+            macro_prefix_map: PrefixMap::default(),
+            token_cache: None,
+            header_cache: None,
+            macro_dump: None,
+            comments: None,
+            snapshot: None,
+            evaluation: Evaluation::default(),
+            severities: SeverityMap::default(),
+            time_trace: None,
+            budget: Budget::default(),
+            cancellation: None,
+        }
+    }
+}
+
+impl Preprocessor {
+    /// Create a new preprocessor with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define a macro before processing begins. This is equivalent to the `-D` command-line
+    /// option of most C compilers: `NAME` defines `NAME` as `1` and `NAME=value` defines `NAME`
+    /// as `value`. Function-like macros can be defined using `NAME(params)=value`.
+    ///
+    /// The definitions are attributed to the `<command-line>` file.
+    pub fn define(&mut self, definition: &str) -> &mut Self {
+        self.command_line
+            .push(CommandLineMacro::Define(definition.to_owned()));
+        self
+    }
+
+    /// Undefine a macro before processing begins. This is equivalent to the `-U` command-line
+    /// option of most C compilers.
+    ///
+    /// Definitions and undefinitions are processed in the order they were given.
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.command_line
+            .push(CommandLineMacro::Undefine(name.to_owned()));
+        self
+    }
+
+    /// Set the version of the C standard to follow.
+    ///
+    /// By default, C17 is followed.
+    pub fn standard(&mut self, standard: Standard) -> &mut Self {
+        self.standard = standard;
+        self
+    }
+
+    /// Set the compilation target, whose macros are predefined before processing begins.
+    ///
+    /// By default, no target macros are predefined.
+    pub fn target(&mut self, target: Target) -> &mut Self {
+        self.target = target;
+        self
+    }
+
+    /// Set whether the environment or conditional feature macro `feature` is predefined.
+    ///
+    /// By default, only `__STDC_UTF_16__` and `__STDC_UTF_32__` are predefined.
+    pub fn feature_macro(&mut self, feature: FeatureMacro, enabled: bool) -> &mut Self {
+        if enabled {
+            self.feature_macros.insert(feature);
+        } else {
+            self.feature_macros.remove(&feature);
+        }
+        self
+    }
+
+    /// Set whether the extension to ISO C `extension` is enabled.
+    ///
+    /// By default, every extension is disabled.
+    pub fn extension(&mut self, extension: Extension, enabled: bool) -> &mut Self {
+        if enabled {
+            self.extensions.insert(extension);
+        } else {
+            self.extensions.remove(&extension);
+        }
+        self
+    }
+
+    /// Set whether only the macros specified by the standard are predefined. If `true`, the
+    /// macros of the [`target`](Self::target) are not predefined. This is equivalent to the
+    /// `-undef` command-line option of most C compilers.
+    ///
+    /// The mandatory macros such as `__STDC__` and the macros enabled using
+    /// [`feature_macro`](Self::feature_macro) are always predefined.
+    pub fn standard_macros_only(&mut self, enabled: bool) -> &mut Self {
+        self.standard_macros_only = enabled;
+        self
+    }
+
+    /// Include a file before processing begins, as if `#include "path"` was the first line of
+    /// the source. This is equivalent to the `-include` command-line option of most C compilers.
+    ///
+    /// Files are included in the order they were given, after defining and undefining the
+    /// macros from [`define`](Self::define) and [`undefine`](Self::undefine).
+    pub fn include<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.included_files
+            .push(IncludedFile::Path(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Include `contents` before processing begins like [`include`](Self::include) does with
+    /// files, as if they were the contents of a file named `name` that is not read from the file
+    /// system. Names like `<prelude>` are used by convention, as the `<built-in>` and
+    /// `<command-line>` names of the predefined macros and the macros from
+    /// [`define`](Self::define), so the diagnostics and macros of `contents` have locations.
+    ///
+    /// ```
+    /// use beheader::Preprocessor;
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.include_source("<prelude>", "#define A 1\n#if 1 / 0\n#endif\n");
+    /// let preprocessed = preprocessor.preprocess(b"int a = A;\n").unwrap();
+    /// let diagnostic = &preprocessed.diagnostics()[0];
+    /// assert_eq!(diagnostic.file().unwrap().to_str(), Some("<prelude>"));
+    /// assert_eq!(diagnostic.line(), Some(2));
+    /// let definition = preprocessed.get_macro("A").unwrap();
+    /// assert_eq!(definition.file(), Some("<prelude>".into()));
+    /// ```
+    pub fn include_source<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &mut self,
+        name: P,
+        contents: C,
+    ) -> &mut Self {
+        self.included_files.push(IncludedFile::Source {
+            name: name.as_ref().to_owned(),
+            contents: contents.as_ref().into(),
+        });
+        self
+    }
+
+    /// Process a file before processing begins, keeping only the macros it defines and
+    /// discarding the rest of its output. This is equivalent to the `-imacros` command-line
+    /// option of most C compilers.
+    ///
+    /// These files are processed before the ones from [`include`](Self::include).
+    pub fn include_macros<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.macro_files.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for files included by `#include "file"` directives. This is
+    /// equivalent to the `-iquote` command-line option of most C compilers.
+    ///
+    /// These files are searched first in the directory of the file containing the directive,
+    /// then in the directories given to this method and then in the same directories as files
+    /// included by `#include <file>` directives. Directories are searched in the order they were
+    /// given.
+    pub fn quote_include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.search_path.quote.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for files included by `#include` directives of both forms.
+    /// This is equivalent to the `-I` command-line option of most C compilers.
+    ///
+    /// Files included by `#include <file>` directives are searched in the directories given to
+    /// this method, then in the ones given to [`system_include_dir`](Self::system_include_dir),
+    /// then in the ones given to [`framework_dir`](Self::framework_dir) and finally in the ones
+    /// given to [`include_dir_after`](Self::include_dir_after).
+    pub fn include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.search_path.angled.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory of system headers to search for files included by `#include` directives
+    /// of both forms, after the directories given to [`include_dir`](Self::include_dir). This is
+    /// equivalent to the `-isystem` command-line option of most C compilers.
+    ///
+    /// The files found in these directories and the files they include are omitted by
+    /// [`Depfile::skip_system_headers`].
+    pub fn system_include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.search_path.system.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for files included by `#include` directives of both forms after
+    /// all the other directories. This is equivalent to the `-idirafter` command-line option of
+    /// most C compilers.
+    ///
+    /// These directories contain system headers, like the ones given to
+    /// [`system_include_dir`](Self::system_include_dir).
+    pub fn include_dir_after<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.search_path.after.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for Apple frameworks. This is equivalent to the `-F`
+    /// command-line option of Clang and GCC on Apple platforms.
+    ///
+    /// A file included as `<Foo/Bar.h>` is searched in `Foo.framework/Headers/Bar.h` and
+    /// `Foo.framework/PrivateHeaders/Bar.h` inside these directories, after searching the
+    /// directories given to [`include_dir`](Self::include_dir). The files of a framework can also
+    /// include the headers of the frameworks inside its `Frameworks` directory.
+    pub fn framework_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.search_path.frameworks.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set whether included files that are not found are searched again ignoring the case of
+    /// their paths, reporting a warning with the path that was found. This allows preprocessing
+    /// code written for case-insensitive file systems, such as `#include <Windows.h>` when the
+    /// file is named `windows.h`.
+    ///
+    /// The [`FileSystem`] must support [`read_dir`](FileSystem::read_dir). By default, the case
+    /// of the paths must match exactly.
+    pub fn case_insensitive_includes(&mut self, enabled: bool) -> &mut Self {
+        self.search_path.case_insensitive = enabled;
+        self
+    }
+
+    /// Set the directory used as the logical root of the headers of the target, such as the
+    /// root of its cross-compilation toolchain. This is equivalent to the `--sysroot` command-line
+    /// option of most C compilers.
+    ///
+    /// Include directories starting with `=` or `$SYSROOT` are relative to this directory, so
+    /// `=/usr/include` becomes `/usr/include` inside the sysroot. If no sysroot is set, these
+    /// prefixes are removed.
+    pub fn sysroot<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.search_path.sysroot = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Set the [`FileSystem`] used to read every file, including the ones given to
+    /// [`preprocess_file`](Self::preprocess_file) and the ones included by `#include`
+    /// directives.
+    ///
+    /// By default, files are read from the file system of the operating system.
+    pub fn file_system<F: FileSystem + 'static>(&mut self, file_system: F) -> &mut Self {
+        self.file_system = Arc::new(file_system);
+        self
+    }
+
+    /// Set a hook consulted for every `#include` directive before searching the included file.
+    /// The hook receives the name and form of the directive and the file containing it and
+    /// decides whether to include a specific file, include some contents that are not in any
+    /// file, skip the directive or search the file as usual.
+    ///
+    /// This can be used to provide generated headers that are not written to disk yet:
+    ///
+    /// ```
+    /// use beheader::{IncludeResolution, Preprocessor};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.include_hook(|request| match request.name() {
+    ///     "version.h" => IncludeResolution::Contents {
+    ///         path: "generated/version.h".into(),
+    ///         contents: b"#define VERSION 2\n".to_vec(),
+    ///     },
+    ///     _ => IncludeResolution::Default,
+    /// });
+    /// preprocessor.preprocess(b"#include \"version.h\"\nVERSION").unwrap();
+    /// ```
+    pub fn include_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&IncludeRequest<'_>) -> IncludeResolution + Send + Sync + 'static,
+    {
+        self.include_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Set the maximum number of nested `#include` directives. A directive that would exceed this
+    /// depth stops the preprocessing with a fatal error, which has the chain of directives that
+    /// led to it as notes. This prevents files that include themselves from being processed
+    /// forever.
+    ///
+    /// By default, the maximum depth is 200, which is the limit used by GCC.
+    pub fn max_include_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_include_depth = depth;
+        self
+    }
+
+    /// Set the maximum time a run can take, after which it stops with a
+    /// [`BudgetExceeded`](crate::DiagnosticCode::BudgetExceeded) error whose notes list what the
+    /// run used. The time is checked before processing each line, so the run can take slightly
+    /// longer. Using [`Duration::ZERO`] removes the limit.
+    ///
+    /// By default, there is no limit.
+    pub fn max_duration(&mut self, limit: Duration) -> &mut Self {
+        self.budget.duration = limit;
+        self
+    }
+
+    /// Set the maximum number of bytes of source code a run can load, counting the input and
+    /// every file it includes but not the predefined macros, after which it stops with a
+    /// [`BudgetExceeded`](crate::DiagnosticCode::BudgetExceeded) error like
+    /// [`max_duration`](Self::max_duration) does. Using `0` removes the limit.
+    ///
+    /// By default, there is no limit.
+    pub fn max_source_bytes(&mut self, limit: usize) -> &mut Self {
+        self.budget.source_bytes = limit;
+        self
+    }
+
+    /// Set the maximum number of tokens of the output, including white-space, a run can
+    /// produce, after which it stops with a
+    /// [`BudgetExceeded`](crate::DiagnosticCode::BudgetExceeded) error like
+    /// [`max_duration`](Self::max_duration) does. Using `0` removes the limit.
+    ///
+    /// By default, there is no limit.
+    ///
+    /// ```
+    /// use beheader::{DiagnosticCode, Preprocessor};
+    ///
+    /// let source = b"#define A a a a a\n#define B A A A A\nB B B B\nB B B B\n";
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.max_tokens(100);
+    /// let error = preprocessor.preprocess(source).err().unwrap();
+    /// let diagnostic = error.diagnostic().unwrap();
+    /// assert_eq!(diagnostic.code(), DiagnosticCode::BudgetExceeded);
+    /// assert_eq!(diagnostic.notes()[2], "258 tokens produced");
+    /// ```
+    pub fn max_tokens(&mut self, limit: usize) -> &mut Self {
+        self.budget.tokens = limit;
+        self
+    }
+
+    /// Set a flag that cancels the preprocessing once it is set, which can be done from another
+    /// thread. The flag is checked before processing each line, so a cancelled run stops
+    /// quickly. Its result has the output produced so far, a
+    /// [`Cancelled`](crate::DiagnosticCode::Cancelled) note where it stopped and is
+    /// [cancelled](Preprocessed::is_cancelled).
+    ///
+    /// By default, the preprocessing cannot be cancelled.
+    ///
+    /// ```
+    /// use std::sync::{atomic::AtomicBool, Arc};
+    ///
+    /// use beheader::Preprocessor;
+    ///
+    /// let cancelled = Arc::new(AtomicBool::new(true));
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.cancellation(cancelled.clone());
+    /// let preprocessed = preprocessor.preprocess(b"int a;\n").unwrap();
+    /// assert!(preprocessed.is_cancelled());
+    /// assert!(preprocessed.tokens().is_empty());
+    /// ```
+    pub fn cancellation(&mut self, flag: Arc<AtomicBool>) -> &mut Self {
+        self.cancellation = Some(flag);
+        self
+    }
+
+    /// Set the number of errors reported as diagnostics after which the preprocessing stops
+    /// with a [`TooManyErrors`](crate::DiagnosticCode::TooManyErrors) error, which keeps the
+    /// errors that were counted in its [`diagnostics`](Error::diagnostics). Using `0` removes the
+    /// limit. This is equivalent to the `-fmax-errors` option of GCC.
+    ///
+    /// By default, there is no limit.
+    ///
+    /// ```
+    /// use beheader::{DiagnosticCode, Preprocessor};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.max_errors(2);
+    /// let error = preprocessor
+    ///     .preprocess(b"#if 1 / 0\n#endif\n#if 2 / 0\n#endif\n#if 3 / 0\n#endif\n")
+    ///     .err()
+    ///     .unwrap();
+    /// let diagnostic = error.diagnostic().unwrap();
+    /// assert_eq!(diagnostic.code(), DiagnosticCode::TooManyErrors);
+    /// assert_eq!(diagnostic.message(), "Stopping after 2 errors");
+    /// assert_eq!(error.diagnostics().len(), 2);
+    /// ```
+    pub fn max_errors(&mut self, limit: usize) -> &mut Self {
+        self.max_errors = limit;
+        self
+    }
+
+    /// Set how the columns of diagnostics and of the locations returned by
+    /// [`Preprocessed::resolve_span`] are counted. This is similar to the
+    /// `-fdiagnostics-column-unit` and `-ftabstop` options of GCC.
+    ///
+    /// By default, columns are counted in bytes.
+    pub fn column_unit(&mut self, unit: ColumnUnit) -> &mut Self {
+        self.column_unit = unit;
+        self
+    }
+
+    /// Set the encoding of the source code read, which is converted to UTF-8 before
+    /// preprocessing it. This is similar to the `-finput-charset` option of GCC. The contents
+    /// provided by the [`include_hook`](Self::include_hook) are not converted.
+    ///
+    /// By default, the source code is UTF-8 and used as it is.
+    pub fn input_charset(&mut self, charset: InputCharset) -> &mut Self {
+        self.input_charset = charset;
+        self
+    }
+
+    /// Replace the prefix `old` of the paths of files by `new` in the diagnostics, the line markers
+    /// written by [`Emitter`] and [`Preprocessed::rewrite_includes`], the dependency files
+    /// written by [`Depfile`] and the replacements of `__FILE__`, so they do not depend on where
+    /// the source code is. This is similar to the `-ffile-prefix-map` option of GCC. Prefixes are
+    /// made of whole components and the prefixes given later take precedence.
+    ///
+    /// ```
+    /// use beheader::{MemoryFileSystem, Preprocessor};
+    ///
+    /// let mut files = MemoryFileSystem::new();
+    /// files.insert("/home/user/project/main.c", "#if 1 / 0\n#endif\n");
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor
+    ///     .file_system(files)
+    ///     .file_prefix_map("/home/user/project", ".");
+    /// let preprocessed = preprocessor.preprocess_file(&"/home/user/project/main.c").unwrap();
+    /// let diagnostic = &preprocessed.diagnostics()[0];
+    /// assert_eq!(diagnostic.file().unwrap().to_str(), Some("./main.c"));
+    /// ```
+    pub fn file_prefix_map<P: AsRef<Path>, Q: AsRef<Path>>(&mut self, old: P, new: Q) -> &mut Self {
+        self.prefix_map
+            .insert(old.as_ref().to_owned(), new.as_ref().to_owned());
+        self.macro_prefix_map(old, new)
+    }
+
+    /// Replace the prefix `old` of the paths of files by `new` in the replacements of `__FILE__`
+    /// only. This is similar to the `-fmacro-prefix-map` option of GCC. Prefixes are made of
+    /// whole components and the prefixes given later take precedence, including the ones given
+    /// using [`file_prefix_map`](Self::file_prefix_map).
+    ///
+    /// ```
+    /// use beheader::{Emitter, MemoryFileSystem, Preprocessor};
+    ///
+    /// let mut files = MemoryFileSystem::new();
+    /// files.insert("/home/user/project/main.c", "__FILE__\n");
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor
+    ///     .file_system(files)
+    ///     .macro_prefix_map("/home/user/project", "src");
+    /// let preprocessed = preprocessor.preprocess_file(&"/home/user/project/main.c").unwrap();
+    /// let text = Emitter::new().line_markers(false).render(&preprocessed);
+    /// assert_eq!(text, b"\"src/main.c\"\n");
+    /// ```
+    pub fn macro_prefix_map<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        old: P,
+        new: Q,
+    ) -> &mut Self {
+        self.macro_prefix_map
+            .insert(old.as_ref().to_owned(), new.as_ref().to_owned());
+        self
+    }
+
+    /// Set whether symbolic links are resolved when deciding if two paths refer to the same
+    /// file. Paths are always normalized by removing `.` components and `..` components after a
+    /// directory, so `include/../config.h` and `config.h` refer to the same file. Files are only
+    /// read once for each path and a file containing `#pragma once` is not included again
+    /// through any path referring to it.
+    ///
+    /// If `true`, the paths reported in diagnostics are the canonical ones returned by
+    /// [`FileSystem::canonicalize`]. By default, symbolic links are not resolved.
+    pub fn resolve_symlinks(&mut self, enabled: bool) -> &mut Self {
+        self.resolve_symlinks = enabled;
+        self
+    }
+
+    /// Set whether adjacent string literals are concatenated into a single string literal, which
+    /// is done in translation phase 6 (section 5.1.1.2p1 of C17), for the users of the output
+    /// that expect the tokens of that phase. The white-space between the string literals is
+    /// removed and string literals with different encoding prefixes are diagnosed and kept as
+    /// they are.
+    ///
+    /// ```
+    /// use beheader::{Emitter, Preprocessor};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.concatenate_strings(true);
+    /// let preprocessed = preprocessor.preprocess(b"#define NAME \"b\"\nu8\"a\" NAME\n  \"c\";\n").unwrap();
+    /// let text = Emitter::new().line_markers(false).render(&preprocessed);
+    /// assert_eq!(text, b"u8\"abc\";\n");
+    /// ```
+    ///
+    /// By default, string literals are not concatenated.
+    pub fn concatenate_strings(&mut self, enabled: bool) -> &mut Self {
+        self.concatenate_strings = enabled;
+        self
+    }
+
+    /// Set whether `#include` directives are kept in the output, before the contents of the
+    /// files they include. This is equivalent to the `-dI` option of GCC.
+    ///
+    /// By default, `#include` directives are not part of the output.
+    pub fn keep_include_directives(&mut self, enabled: bool) -> &mut Self {
+        self.keep_includes = enabled;
+        self
+    }
+
+    /// Set a directory where the tokens of every file read are stored, so they do not need to be
+    /// tokenized again in later runs. The directory is created if it does not exist and it can
+    /// be shared by several processes.
+    ///
+    /// The tokens are found using the contents of the files, so the cache never needs to be
+    /// invalidated, but nothing is ever removed from it either. Errors while accessing the cache
+    /// are ignored.
+    ///
+    /// The include guard of each file and whether it contains `#pragma once` are stored with its
+    /// tokens. Like GCC does, a file whose whole contents are a `#ifndef` directive is not
+    /// processed again when it is included with the macro defined, so it is left out of the
+    /// result like the files that are not included again because of `#pragma once`.
+    ///
+    /// By default, there is no cache.
+    pub fn token_cache<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.token_cache = Some(TokenCache::new(dir.as_ref()));
+        self
+    }
+
+    /// Set the cache where the contents of the files read and their tokens are shared with the
+    /// other preprocessors using it.
+    ///
+    /// By default, there is no cache and each result of preprocessing keeps its own copy of the
+    /// files read.
+    pub fn header_cache(&mut self, cache: HeaderCache) -> &mut Self {
+        self.header_cache = Some(cache);
+        self
+    }
+
+    /// Set how macro definitions are dumped into the output.
+    ///
+    /// By default, macro definitions are not part of the output.
+    pub fn dump_macros(&mut self, dump: MacroDump) -> &mut Self {
+        self.macro_dump = Some(dump);
+        self
+    }
+
+    /// Record a [`TimeTrace`] of every run, which can be inspected using
+    /// [`Preprocessed::time_trace`]. The events that take less than `granularity` are not
+    /// recorded, Clang uses 500 microseconds by default.
+    ///
+    /// By default, there is no time trace.
+    pub fn time_trace(&mut self, granularity: Duration) -> &mut Self {
+        self.time_trace = Some(granularity);
+        self
+    }
+
+    /// Keep comments in the output, which are written by the [`Emitter`]. Comments are white-space
+    /// for the preprocessor, so they never change the result otherwise.
+    ///
+    /// By default, comments are not part of the output.
+    pub fn keep_comments(&mut self, comments: Comments) -> &mut Self {
+        self.comments = Some(comments);
+        self
+    }
+
+    /// Use a [`Snapshot`] as the initial state of the preprocessor. The conditional inclusion
+    /// directives of the snapshot whose `#endif` was not found yet are terminated in the input.
+    ///
+    /// The predefined macros, the macros from [`define`](Self::define) and
+    /// [`undefine`](Self::undefine) and the files from [`include`](Self::include) and
+    /// [`include_macros`](Self::include_macros) are ignored when a snapshot is used, as their
+    /// effects are expected to be part of the snapshot already.
+    pub fn restore(&mut self, snapshot: Snapshot) -> &mut Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    /// Set whether `char` is a signed type when evaluating the character constants inside `#if`
+    /// and `#elif` directives. If `true`, `'\xff'` evaluates to `-1` instead of `255`.
+    ///
+    /// Character constants without prefix evaluate to the value of their character as a `char`
+    /// converted to `int`. The ones with the `L` prefix evaluate to the code point of their
+    /// character as a 32-bit `wchar_t` and the ones with the `u` and `U` prefixes evaluate to the
+    /// code point of their character as an unsigned integer.
+    ///
+    /// By default, `char` is signed.
+    pub fn signed_char(&mut self, signed: bool) -> &mut Self {
+        self.evaluation.signed_char = signed;
+        self
+    }
+
+    /// Set whether character constants with more than one character, such as `'ab'`, are
+    /// accepted inside `#if` and `#elif` directives. Their value is computed like GCC and Clang
+    /// do: each byte is appended to the value from the most to the least significant one and the
+    /// result is truncated to 32 bits, so `'ab'` evaluates to `0x6162`.
+    ///
+    /// By default, these constants are accepted.
+    pub fn multi_char_constants(&mut self, enabled: bool) -> &mut Self {
+        self.evaluation.multi_char = enabled;
+        self
+    }
+
+    /// Set whether to warn about `true` and `false` inside `#if` and `#elif` directives when they
+    /// are not keywords. Before C23, they are identifiers and evaluate to `0` unless they are
+    /// macros, which is rarely intended. From C23 onwards, they evaluate to `1` and `0`.
+    ///
+    /// By default, no warnings are reported. This is equivalent to calling
+    /// [`warning`](Self::warning) with [`Warning::BoolKeywords`].
+    pub fn warn_bool_keywords(&mut self, enabled: bool) -> &mut Self {
+        self.warning(Warning::BoolKeywords, enabled)
+    }
+
+    /// Set what to do when a macro replacement inside a `#if` or `#elif` directive produces the
+    /// `defined` operator, which is undefined behavior. The operator is evaluated regardless of
+    /// this setting. This is equivalent to the `-Wexpansion-to-defined` option of GCC and Clang.
+    ///
+    /// By default, a warning is reported. This is equivalent to calling
+    /// [`diagnostic_level`](Self::diagnostic_level) with
+    /// [`DiagnosticCode::ExpansionToDefined`].
+    pub fn expansion_to_defined(&mut self, level: Level) -> &mut Self {
+        self.diagnostic_level(DiagnosticCode::ExpansionToDefined, level)
+    }
+
+    /// Set what to do when finding an extension to ISO C accepted by the preprocessor or a
+    /// feature of a later version of the standard, like `#include_next`, binary constants
+    /// before C23 or invocations of variadic macros without their variable arguments before
+    /// C23. Using [`Level::Warn`] or [`Level::Error`] is equivalent to the `-pedantic` and
+    /// `-pedantic-errors` options of GCC and Clang respectively.
+    ///
+    /// By default, nothing is reported. This is equivalent to calling
+    /// [`diagnostic_level`](Self::diagnostic_level) with [`DiagnosticCode::Extension`].
+    ///
+    /// ```
+    /// use beheader::{DiagnosticCode, Level, Preprocessor};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.pedantic(Level::Error);
+    /// let preprocessed = preprocessor.preprocess(b"#if 0b1\n#endif\n").unwrap();
+    /// let diagnostic = &preprocessed.diagnostics()[0];
+    /// assert_eq!(diagnostic.code(), DiagnosticCode::Extension);
+    /// assert_eq!(diagnostic.message(), "Binary integer constants are a C23 extension");
+    /// ```
+    pub fn pedantic(&mut self, level: Level) -> &mut Self {
+        self.diagnostic_level(DiagnosticCode::Extension, level)
+    }
+
+    /// Set what to do when finding a problem with the code `code`, overriding the level of its
+    /// group set using [`diagnostic_group_level`](Self::diagnostic_group_level). This can promote
+    /// warnings to errors, demote errors to notes or ignore the problem, like the `-Werror=`,
+    /// `-Wno-error=` and `-Wno-` options of GCC and Clang.
+    ///
+    /// Only the diagnostics that do not stop the preprocessing are affected, as the ones returned
+    /// inside an [`Error`] cannot be ignored.
+    ///
+    /// ```
+    /// use beheader::{DiagnosticCode, Level, Preprocessor, Severity};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.diagnostic_level(DiagnosticCode::DivisionByZero, Level::Warn);
+    /// let preprocessed = preprocessor.preprocess(b"#if 1 / 0\n#endif\n").unwrap();
+    /// assert_eq!(preprocessed.diagnostics()[0].severity(), Severity::Warning);
+    /// ```
+    pub fn diagnostic_level(&mut self, code: DiagnosticCode, level: Level) -> &mut Self {
+        self.severities.set_code(code, level);
+        self
+    }
+
+    /// Set what to do when finding a problem whose code is in `group`, unless a level was set
+    /// for its code using [`diagnostic_level`](Self::diagnostic_level).
+    pub fn diagnostic_group_level(&mut self, group: DiagnosticGroup, level: Level) -> &mut Self {
+        self.severities.set_group(group, level);
+        self
+    }
+
+    /// Set whether `warning` is reported, like the `-W` and `-Wno-` options of GCC and Clang.
+    /// The levels set using [`diagnostic_level`](Self::diagnostic_level) and
+    /// [`diagnostic_group_level`](Self::diagnostic_group_level) take precedence over this.
+    ///
+    /// By default, only the warnings whose
+    /// [`is_enabled_by_default`](Warning::is_enabled_by_default) returns `true` are reported.
+    /// The `#pragma GCC diagnostic` and `#pragma clang diagnostic` directives change this setting
+    /// for the source code after them.
+    ///
+    /// ```
+    /// use beheader::{Preprocessor, Warning};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.warning(Warning::Undef, true);
+    /// let preprocessed = preprocessor.preprocess(b"#if VERSION > 2\n#endif\n").unwrap();
+    /// assert_eq!(
+    ///     preprocessed.diagnostics()[0].message(),
+    ///     "\"VERSION\" is not defined, evaluates to 0"
+    /// );
+    /// ```
+    pub fn warning(&mut self, warning: Warning, enabled: bool) -> &mut Self {
+        self.severities.set_warning(warning, enabled);
+        self
+    }
+
+    /// Set whether the warnings of `group` are reported, like the `-Wall` and `-Wextra` options
+    /// of GCC and Clang.
+    pub fn warning_group(&mut self, group: WarningGroup, enabled: bool) -> &mut Self {
+        for &warning in group.warnings() {
+            self.warning(warning, enabled);
+        }
+        self
+    }
+
+    /// Apply a warning option of GCC and Clang: `-Wname` and `-Wno-name` enable and disable the
+    /// [`Warning`] or [`WarningGroup`] named `name`, `-Werror=name` enables it and reports it as
+    /// an error, `-Wno-error=name` reports it as a warning even if
+    /// [`warnings_as_errors`](Self::warnings_as_errors) is enabled and `-Werror` and
+    /// `-Wno-error` enable and disable `warnings_as_errors`. Other options are ignored, as they
+    /// are usually meant for the compiler.
+    ///
+    /// ```
+    /// use beheader::{Preprocessor, Severity, Standard};
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor
+    ///     .standard(Standard::Gnu17)
+    ///     .warning_option("-Wall")
+    ///     .warning_option("-Werror=trigraphs");
+    /// let preprocessed = preprocessor.preprocess(b"/* /* */ char *s = \"??=\";\n").unwrap();
+    /// let severities: Vec<_> = preprocessed
+    ///     .diagnostics()
+    ///     .iter()
+    ///     .map(|diagnostic| diagnostic.severity())
+    ///     .collect();
+    /// assert_eq!(severities, [Severity::Warning, Severity::Error]);
+    /// ```
+    pub fn warning_option(&mut self, option: &str) -> &mut Self {
+        self.severities.apply_option(option);
+        self
+    }
+
+    /// Set whether to report warnings as errors, after applying the levels set using
+    /// [`diagnostic_level`](Self::diagnostic_level) and
+    /// [`diagnostic_group_level`](Self::diagnostic_group_level). This is equivalent to the
+    /// `-Werror` option of GCC and Clang.
+    ///
+    /// By default, warnings are reported as warnings.
+    pub fn warnings_as_errors(&mut self, enabled: bool) -> &mut Self {
+        self.severities.set_warnings_as_errors(enabled);
+        self
+    }
+
+    /// Set the width of `intmax_t` and `uintmax_t`, which are the types used to evaluate `#if`
+    /// and `#elif` directives. Using [`IntmaxWidth::Bits32`] reproduces the behavior of old
+    /// compilers whose widest integer type was a 32-bit `long`.
+    ///
+    /// By default, the width is 64 bits.
+    pub fn intmax_width(&mut self, width: IntmaxWidth) -> &mut Self {
+        self.evaluation.width = width.bits();
+        self
+    }
+
+    /// Preprocess a sequence of bytes.
+    ///
+    /// Return an error if any of the files included before processing cannot be read or if the
+    /// source code is not valid.
+    pub fn preprocess(&self, source: &[u8]) -> Result<Preprocessed, Error> {
+        let mut context = self.context()?;
+        let source = self
+            .input_charset
+            .decode_source(source.into(), self.lexer_features())?;
+        let span = context.map.store_bytes(&source)?;
+        context.source = Some(span);
+        context.process(span)?;
+        self.finish(context)
+    }
+
+    /// Preprocess a sequence of bytes that is the start of some input, so a [`Snapshot`] can be
+    /// taken at its end to preprocess the rest of the input later. The conditional inclusion
+    /// directives whose `#endif` is not in `source` are not reported, as they are part of the
+    /// snapshot instead.
+    ///
+    /// Return an error if any of the files included before processing cannot be read or if the
+    /// source code is not valid.
+    pub fn preprocess_prefix(&self, source: &[u8]) -> Result<Preprocessed, Error> {
+        let mut context = self.context()?;
+        let source = self
+            .input_charset
+            .decode_source(source.into(), self.lexer_features())?;
+        let span = context.map.store_bytes(&source)?;
+        context.source = Some(span);
+        context.prefix = true;
+        context.process(span)?;
+        self.finish(context)
+    }
+
+    /// Preprocess the contents of `reader`, which is read until its end, like the contents of a
+    /// file named `name` that is not a dependency of the result. This allows preprocessing from
+    /// pipes, sockets or the standard input without collecting the contents first.
+    ///
+    /// Return an error if `reader` or any of the files cannot be read or if the source code is
+    /// not valid.
+    ///
+    /// ```
+    /// use beheader::{Emitter, Preprocessor};
+    ///
+    /// let source = &b"#define A 1\nint a = A;\n"[..];
+    /// let preprocessed = Preprocessor::new().preprocess_reader(source, &"<stdin>").unwrap();
+    /// let text = Emitter::new().line_markers(false).render(&preprocessed);
+    /// assert_eq!(text, b"int a = 1;\n");
+    /// assert!(preprocessed.dependencies().is_empty());
+    /// ```
+    pub fn preprocess_reader<R: Read, P: AsRef<Path>>(
+        &self,
+        reader: R,
+        name: &P,
+    ) -> Result<Preprocessed, Error> {
+        self.preprocess_reader_in(self.source_map(), reader, name.as_ref())
+    }
+
+    /// Preprocess the contents of `reader` like [`preprocess_reader`](Self::preprocess_reader),
+    /// storing the source code in `map`.
+    pub(super) fn preprocess_reader_in<R: Read>(
+        &self,
+        map: SourceMap,
+        reader: R,
+        name: &Path,
+    ) -> Result<Preprocessed, Error> {
+        let mut context = self.context_in(map)?;
+        let span = context.map.read_from(reader, name)?;
+        context.source = Some(span);
+        context.process(span)?;
+        self.finish(context)
+    }
+
+    /// Preprocess `source` like the contents of a file named `name` that is not a dependency of
+    /// the result, so diagnostics and line markers refer to `name` even if it does not exist.
+    ///
+    /// Return an error if any of the files cannot be read or if the source code is not valid.
+    ///
+    /// ```
+    /// use beheader::{Emitter, Preprocessor};
+    ///
+    /// let source = b"#define A 1\nint a = A;\n";
+    /// let preprocessed = Preprocessor::new().preprocess_source(&"a.c", source).unwrap();
+    /// let text = Emitter::new().render(&preprocessed);
+    /// assert!(text.starts_with(b"# 1 \"a.c\""));
+    /// assert!(preprocessed.dependencies().is_empty());
+    /// ```
+    pub fn preprocess_source<P: AsRef<Path>>(
+        &self,
+        name: &P,
+        source: &[u8],
+    ) -> Result<Preprocessed, Error> {
+        self.preprocess_reader(source, name)
+    }
+
+    /// Preprocess a file.
+    ///
+    /// Return an error if any of the files cannot be read or if the source code is not valid.
+    pub fn preprocess_file<P: AsRef<Path>>(&self, path: &P) -> Result<Preprocessed, Error> {
+        self.preprocess_file_in(self.source_map(), path.as_ref())
+    }
+
+    /// Preprocess a file like [`preprocess_file`](Self::preprocess_file), storing the source
+    /// code in `map`.
+    pub(super) fn preprocess_file_in(
+        &self,
+        map: SourceMap,
+        path: &Path,
+    ) -> Result<Preprocessed, Error> {
+        let mut context = self.context_in(map)?;
+        let span = context.read_file(path)?;
+        context.input = context.map.find_file(span);
+        context.source = Some(span);
+        context.process(span)?;
+        self.finish(context)
+    }
+
+    /// Produce the result of the preprocessing once all the input has been processed.
+    ///
+    /// Return an error if the macros are dumped and their definitions cannot be stored.
+    fn finish(&self, mut context: Context) -> Result<Preprocessed, Error> {
+        // The macros of a cancelled run could have been used after it stopped.
+        if !context.cancelled {
+            context.check_unused_macros();
+        }
+        let mut diagnostics = std::mem::take(&mut context.diagnostics);
+        diagnostics.update_since(0, |diagnostic| context.included_from(diagnostic));
+        context.diagnostics = diagnostics;
+        let statistics = &mut context.statistics;
+        statistics.files_read = context.map.files_read().len();
+        statistics.macros_expanded = context.macros.expansions();
+        statistics.branches_skipped = context
+            .sections
+            .iter()
+            .flatten()
+            .filter(|branch| !branch.is_taken())
+            .count();
+        statistics.total = context
+            .started
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        let mut preprocessed = Preprocessed { context };
+
+        if self.macro_dump == Some(MacroDump::Final) {
+            let mut dump = String::new();
+            for definition in preprocessed.macros() {
+                dump.push_str(&format!("{}\n", definition));
+            }
+            let context = &mut preprocessed.context;
+            // The definitions were valid when they were parsed, so their dump is valid too.
+            let end = context
+                .source
+                .map_or(Span::new(0, 0), |span| Span::new(span.hi(), span.hi()));
+            context.output = context.map.tokenize_bytes(dump.as_bytes(), end)?;
+            context.output_files.clear();
+            context.expanded.clear();
+        }
+
+        Ok(preprocessed)
+    }
+
+    /// The features of the lexer given by the standard and the extensions.
+    fn lexer_features(&self) -> LexerFeatures {
+        LexerFeatures {
+            dollar_idents: self.extensions.contains(&Extension::DollarIdents),
+            ..self.standard.lexer_features()
+        }
+    }
+
+    /// Create an empty [`SourceMap`] that reads files as configured.
+    pub(super) fn source_map(&self) -> SourceMap {
+        SourceMap::new(
+            self.file_system.clone(),
+            self.resolve_symlinks,
+            self.header_cache.clone(),
+            self.column_unit,
+            self.input_charset,
+            self.prefix_map.clone(),
+            self.lexer_features(),
+        )
+    }
+
+    /// Create a new [`Context`] with the predefined macros and the macros from the command line
+    /// already defined and the files that must be included before processing already processed.
+    pub(crate) fn context(&self) -> Result<Context, Error> {
+        self.context_in(self.source_map())
+    }
+
+    /// Create a new [`Context`] like [`context`](Self::context) that stores the source code in
+    /// `map`.
+    fn context_in(&self, map: SourceMap) -> Result<Context, Error> {
+        let mut context = Context {
+            map,
+            search_path: self.search_path.clone(),
+            include_hook: self.include_hook.clone(),
+            keep_includes: self.keep_includes,
+            concatenate_strings: self.concatenate_strings,
+            max_include_depth: self.max_include_depth,
+            max_errors: self.max_errors,
+            token_cache: self.token_cache.clone(),
+            macro_dump: self.macro_dump,
+            comments: self.comments,
+            evaluation: Evaluation {
+                bool_keywords: self.standard >= Standard::C23,
+                binary_constants: self.standard >= Standard::C23,
+                ..self.evaluation
+            },
+            diagnostics: Diagnostics::new(self.severities.clone()),
+            standard: self.standard,
+            extensions: self.extensions.clone(),
+            started: Some(Instant::now()),
+            tracer: self.time_trace.map(Tracer::new),
+            ..Context::default()
+        };
+        context.macros.set_extensions(&self.extensions);
+        context.macros.set_prefix_map(self.macro_prefix_map.clone());
+
+        if let Some(snapshot) = &self.snapshot {
+            snapshot.restore_into(&mut context)?;
+            context.cancellation = self.cancellation.clone();
+            context.start_budget(self.budget);
+            return Ok(context);
+        }
+
+        let mandatory = MANDATORY_MACROS.iter().copied().chain(
+            self.standard
+                .version()
+                .map(|version| ("__STDC_VERSION__", version)),
+        );
+        let features = self
+            .feature_macros
+            .iter()
+            .map(|feature| (feature.name(), feature.value()));
+        let target = self.target.macros().filter(|_| !self.standard_macros_only);
+
+        let mut built_in = String::new();
+        for (name, value) in mandatory.chain(features).chain(target) {
+            built_in.push_str(&format!("#define {} {}\n", name, value));
+        }
+
+        let span = context
+            .map
+            .store_virtual_file("<built-in>", built_in.as_bytes())?;
+        context
+            .process_into(span, &mut TokenBuffer::default())
+            .map_err(|diagnostic| context.error(diagnostic))?;
+
+        let mut command_line = String::new();
+        for item in &self.command_line {
+            match item {
+                CommandLineMacro::Define(definition) => match definition.split_once('=') {
+                    Some((name, value)) => {
+                        command_line.push_str(&format!("#define {} {}\n", name, value))
+                    }
+                    None => command_line.push_str(&format!("#define {} 1\n", definition)),
+                },
+                CommandLineMacro::Undefine(name) => {
+                    command_line.push_str(&format!("#undef {}\n", name))
+                }
+            }
+        }
+
+        let span = context
+            .map
+            .store_virtual_file("<command-line>", command_line.as_bytes())?;
+        context
+            .process_into(span, &mut TokenBuffer::default())
+            .map_err(|diagnostic| context.error(diagnostic))?;
+
+        for path in &self.macro_files {
+            let span = context.read_file(path)?;
+            context
+                .process_into(span, &mut TokenBuffer::default())
+                .map_err(|diagnostic| context.error(diagnostic))?;
+        }
+
+        // The files included by the files of macros and their macro invocations were not
+        // processed into the output.
+        context.output_files.clear();
+        context.expansions.clear();
+        context.expanded.clear();
+        for file in &self.included_files {
+            let span = match file {
+                IncludedFile::Path(path) => context.read_file(path)?,
+                IncludedFile::Source { name, contents } => {
+                    context.map.store_virtual_file(name, contents)?
+                }
+            };
+            context.process(span)?;
+        }
+
+        // Only the processing of the input can be cancelled or exceed the budget.
+        context.cancellation = self.cancellation.clone();
+        context.start_budget(self.budget);
+        Ok(context)
+    }
+}
+
+/// The result of preprocessing some source code.
+pub struct Preprocessed {
+    context: Context,
+}
+
+impl Preprocessed {
+    /// Return an iterator over the macros defined at the end of the input, sorted by name.
+    pub fn macros(&self) -> impl Iterator<Item = MacroDefinition<'_>> {
+        let mut macros: Vec<_> = self
+            .context
+            .macros
+            .iter()
+            .map(|(name, definition)| MacroDefinition::new(name, definition, &self.context.map))
+            .collect();
+        macros.sort_by_key(|definition| definition.name());
+        macros.into_iter()
+    }
+
+    /// The notes, warnings and errors found while preprocessing, in the order they were found,
+    /// with the severities chosen using [`Preprocessor::diagnostic_level`] and similar methods.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.context.diagnostics
+    }
+
+    /// Get the macro named `name` if it was defined at the end of the input.
+    pub fn get_macro(&self, name: &str) -> Option<MacroDefinition<'_>> {
+        let symbol = self.context.map.find_symbol(name.as_bytes())?;
+        let (name, definition) = self.context.macros.get_key_value(symbol)?;
+        Some(MacroDefinition::new(name, definition, &self.context.map))
+    }
+
+    /// The tokens of the output, whose spellings can be found using
+    /// [`source_map`](Self::source_map).
+    pub fn tokens(&self) -> &[Token] {
+        self.context.output.tokens()
+    }
+
+    /// Describe the tokens of the output for debugging, one per line, with their kind, their
+    /// span, the location where they are spelled and their spelling.
+    ///
+    /// ```
+    /// let preprocessed = beheader::preprocess(b"#define A 1\nint a = A;\n").unwrap();
+    /// let dump = preprocessed.dump_tokens();
+    /// assert_eq!(dump.lines().nth(7).unwrap(), "   7 Number 140..141 <stdin>:1:11 \"1\"");
+    /// ```
+    pub fn dump_tokens(&self) -> String {
+        let input = self.context.source.filter(|_| self.context.input.is_none());
+        self.context
+            .output
+            .dump(&self.context.map, input)
+            .to_string()
+    }
+
+    /// Convert the tokens of the output into tokens (translation phase 7), classifying keywords
+    /// and computing the value of numeric constants, so the output can be parsed. Adjacent
+    /// string literals are only concatenated if
+    /// [`concatenate_strings`](Preprocessor::concatenate_strings) was enabled.
+    ///
+    /// ```
+    /// use beheader::{CTokenKind, Keyword};
+    ///
+    /// let preprocessed = beheader::preprocess(b"#define SIZE 0x10u\nint a[SIZE];\n").unwrap();
+    /// let c_tokens = preprocessed.c_tokens();
+    /// let kinds: Vec<_> = c_tokens.tokens().iter().map(|token| token.kind()).collect();
+    /// assert_eq!(kinds[0], CTokenKind::Keyword(Keyword::Int));
+    /// assert_eq!(kinds[1], CTokenKind::Ident);
+    /// match kinds[3] {
+    ///     CTokenKind::Integer(constant) => assert_eq!(constant.value(), 16),
+    ///     kind => panic!("Unexpected {:?}", kind),
+    /// }
+    /// assert!(c_tokens.diagnostics().is_empty());
+    /// ```
+    pub fn c_tokens(&self) -> CTokens {
+        CTokens::convert(
+            self.context.output.tokens(),
+            &self.context.map,
+            self.context.standard,
+        )
+        .with_severities(self.context.diagnostics.severities())
+    }
+
+    /// Group the tokens of the output produced by [`c_tokens`](Self::c_tokens) into token trees.
+    /// The tokens that cannot be converted are skipped. Return an error if there is a
+    /// parenthesis, bracket or brace without a matching one.
+    pub fn token_stream(&self) -> Result<TokenStream, Diagnostic> {
+        TokenStream::build(self.c_tokens().tokens(), &self.context.map)
+    }
+
+    /// The time trace of the run, if it was enabled using
+    /// [`time_trace`](Preprocessor::time_trace).
+    pub fn time_trace(&self) -> Option<&TimeTrace> {
+        self.context.tracer.as_ref().map(Tracer::trace)
+    }
+
+    /// What was done while preprocessing and how long it took.
+    pub fn statistics(&self) -> &Statistics {
+        &self.context.statistics
+    }
+
+    /// Check if the preprocessing was cancelled using the flag set with
+    /// [`Preprocessor::cancellation`], so the output is only the part produced before it
+    /// stopped.
+    pub fn is_cancelled(&self) -> bool {
+        self.context.cancelled
+    }
+
+    /// Copy all the source code seen while preprocessing, including the contents of every file
+    /// read.
+    pub fn source_map(&self) -> SourceMapSnapshot {
+        self.context.map.snapshot()
+    }
+
+    /// Find the file, lines and columns of `span`, like the span of a token or a diagnostic, without
+    /// copying the source code as [`source_map`](Self::source_map) does. Return `None` if `span`
+    /// is not inside a single region of the source code seen while preprocessing.
+    pub fn resolve_span(&self, span: Span) -> Option<SpanLocation> {
+        self.context.map.resolve(span)
+    }
+}
+
+/// The state of a single preprocessing run.
+#[derive(Default)]
+pub(crate) struct Context {
+    pub(crate) map: SourceMap,
+    /// The path of the file being preprocessed, if the input is a file.
+    input: Option<PathBuf>,
+    /// The region of the source code being preprocessed.
+    source: Option<Span>,
+    macros: MacroTable,
+    /// The preprocessed tokens.
+    pub(crate) output: TokenBuffer,
+    /// The files processed into the output, in the order they were entered.
+    output_files: Vec<emitter::OutputFile>,
+    /// The macro invocations that produced tokens of the output.
+    expansions: Vec<expansions::ExpansionRecord>,
+    /// The index of the invocation in [`expansions`](Self::expansions) that produced each output
+    /// token produced by macro replacement.
+    expanded: TokenTable<usize>,
+    /// The regions of the macro invocations written in the source code whose arguments span
+    /// several lines, sorted by their start.
+    invocations: Vec<Span>,
+    /// Where the text of the output is written while it is being produced, if anywhere.
+    stream: Option<emitter::Stream>,
+    /// Where the events of the run are sent, if anywhere.
+    events: Option<events::EventSink>,
+    /// The number of output tokens already written to the stream or sent as events and removed
+    /// from the output.
+    streamed: usize,
+    /// The directories searched for included files.
+    search_path: SearchPath,
+    /// The hook consulted for every `#include` directive.
+    include_hook: Option<IncludeHook>,
+    /// Whether `#include` directives are kept in the output.
+    keep_includes: bool,
+    /// Whether adjacent string literals are concatenated.
+    concatenate_strings: bool,
+    /// The maximum number of nested `#include` directives.
+    max_include_depth: usize,
+    /// The number of errors that stops the preprocessing, or `0` if there is no limit.
+    max_errors: usize,
+    /// The cache of the tokens of the files read.
+    token_cache: Option<TokenCache>,
+    /// The tokens of the regions processed so far, if they could be taken from the cache.
+    cached_tokens: HashMap<Span, Option<Arc<[Token]>>>,
+    /// The include guards and `#pragma once` directives of the files whose tokens were taken
+    /// from the token cache, keyed by the regions of the files.
+    file_info: HashMap<Span, FileInfo>,
+    /// The `#include` directives being processed and the paths of their files, from the
+    /// outermost to the innermost.
+    inclusions: Vec<(Token, PathBuf)>,
+    /// The `#include` directives that were being processed when each file was entered for the
+    /// first time, from the outermost to the innermost, keyed by the region of the file.
+    entered: HashMap<Span, Vec<Token>>,
+    /// The regions of the files that contain a `#pragma once` directive or were included by an
+    /// `#import` directive.
+    once: HashSet<Span>,
+    /// The directories of the search path where the files included so far were found, keyed by
+    /// the paths of the files.
+    include_dirs: HashMap<PathBuf, PathBuf>,
+    /// The regions of the lines of the `#pragma once` directives processed so far.
+    once_directives: Vec<Span>,
+    /// The regions of the lines of the `#pragma` directives of the `_Pragma` operators processed
+    /// so far.
+    pragma_operators: Vec<Span>,
+    /// The paths of the files found in directories of system headers or included by them.
+    system_headers: HashSet<PathBuf>,
+    /// The paths searched for included files where no file was found.
+    missing_files: Vec<PathBuf>,
+    /// Every `#include` directive whose file was found so far.
+    include_edges: Vec<graph::IncludeRecord>,
+    /// How macro definitions are dumped into the output.
+    macro_dump: Option<MacroDump>,
+    /// Which comments are kept in the output.
+    comments: Option<Comments>,
+    /// How controlling expressions of conditional inclusion directives are evaluated.
+    evaluation: Evaluation,
+    /// The version of the C standard being followed.
+    standard: Standard,
+    /// The extensions to ISO C that are enabled.
+    extensions: BTreeSet<Extension>,
+    /// The conditional inclusion directives whose `#endif` has not been found yet, from the
+    /// outermost to the innermost.
+    conditionals: Vec<Conditional>,
+    /// The number of conditional inclusion directives restored from a [`Snapshot`] whose
+    /// `#endif` can be in the next region processed, which is the input.
+    resumed: usize,
+    /// Whether the input is the prefix of some input, whose conditional inclusion directives can
+    /// be terminated after it.
+    prefix: bool,
+    /// The notes, warnings and errors found so far.
+    diagnostics: Diagnostics,
+    /// The branches of every conditional inclusion directive found so far.
+    sections: Vec<Vec<coverage::BranchRecord>>,
+    /// When the run started.
+    started: Option<Instant>,
+    /// What was done so far.
+    statistics: Statistics,
+    /// The time trace of the run, if there is one.
+    tracer: Option<Tracer>,
+    /// The resources the run can use.
+    budget: Budget,
+    /// The number of bytes stored in the source map before the input started being processed.
+    stored_before: usize,
+    /// The flag that cancels the run when it is set, if there is one.
+    cancellation: Option<Arc<AtomicBool>>,
+    /// Whether the run was cancelled.
+    cancelled: bool,
+}
+
+/// The state of a conditional inclusion directive (section 6.10.1 of C17) whose `#endif` has not
+/// been found yet.
+struct Conditional {
+    /// Whether the group containing the directive is being processed.
+    enclosing: bool,
+    /// Whether the current group of the directive is being processed.
+    active: bool,
+    /// Whether any of the groups of the directive has been processed already.
+    taken: bool,
+    /// Whether the `#else` directive has been found already.
+    has_else: bool,
+    /// The index of the record of the directive in [`Context::sections`].
+    section: usize,
+}
+
+impl Context {
+    /// Process the source code in the region `span` and add the result to the output.
+    ///
+    /// Errors that do not stop the preprocessing are reported as diagnostics. Return an error if a
+    /// fatal one is found, the output produced before it is kept.
+    pub(crate) fn process(&mut self, span: Span) -> Result<(), Error> {
+        let mut output = std::mem::take(&mut self.output);
+        let start = self.streamed + output.tokens().len();
+        self.macros.set_input(span);
+        let result = match self.process_file(span, 0, &mut output) {
+            // A cancelled run is not an error, its result is what was produced so far.
+            Err(diagnostic) if diagnostic.code() == DiagnosticCode::Cancelled => {
+                self.cancelled = true;
+                let diagnostic = self.included_from(diagnostic);
+                self.diagnostics.push(diagnostic);
+                Ok(())
+            }
+            result => result,
+        };
+        // The output produced before an error is kept, so it is concatenated too.
+        let result = match self.concatenate_strings {
+            true => result.and(self.concatenate_strings(&mut output)),
+            false => result,
+        };
+        self.statistics.tokens += self.streamed + output.tokens().len() - start;
+        self.output = output;
+        result.map_err(|diagnostic| self.error(diagnostic))
+    }
+
+    /// Make the error returned for the fatal error `diagnostic`, with the diagnostics reported
+    /// before it.
+    pub(crate) fn error(&self, diagnostic: Diagnostic) -> Error {
+        Error::Source {
+            diagnostic: self.included_from(diagnostic),
+            reported: self
+                .diagnostics
+                .iter()
+                .map(|diagnostic| self.included_from(diagnostic.clone()))
+                .collect(),
+        }
+    }
+
+    /// Process the source code in the region `span` and add the result to `output`.
+    ///
+    /// Return an error if the source code is not valid.
+    fn process_into(&mut self, span: Span, output: &mut TokenBuffer) -> Result<(), Diagnostic> {
+        // The lines that are not directives, waiting to be macro-expanded.
+        let mut text = Vec::new();
+        // Conditional inclusion directives must be terminated in the same file they started,
+        // unless they were restored from a snapshot.
+        let depth = self.conditionals.len() - std::mem::take(&mut self.resumed);
+
+        let lexing = Instant::now();
+        let mut lines = match self.cached_tokens(span) {
+            Some(tokens) => Lines::with_tokens(span, tokens),
+            None => Lines::new(span),
+        };
+        self.statistics.lexing += lexing.elapsed();
+        let mut line = Vec::new();
+
+        loop {
+            self.check_expansion_extensions();
+            // The previous line, or the start of the region if there is none.
+            let previous = match line.is_empty() {
+                true => Span::new(span.lo(), span.lo()),
+                false => line_span(&line),
+            };
+            // The limit is checked once the previous line has been processed completely.
+            if self.max_errors != 0 && self.diagnostics.error_count() >= self.max_errors {
+                let code = DiagnosticCode::TooManyErrors;
+                let message = format!("Stopping after {} errors", self.max_errors);
+                return Err(self
+                    .map
+                    .diagnostic(Severity::Error, code, previous, message));
+            }
+            self.check_budget(previous, output)?;
+            // The run whose events are not received anymore is cancelled too.
+            let cancelled = self
+                .cancellation
+                .iter()
+                .chain(self.events.as_ref().map(|sink| &sink.cancellation))
+                .any(|cancellation| cancellation.load(Ordering::Relaxed));
+            if cancelled {
+                let code = DiagnosticCode::Cancelled;
+                let message = "The preprocessing was cancelled";
+                return Err(self.map.diagnostic(Severity::Note, code, previous, message));
+            }
+            self.stream_output(output)?;
+            let lexing = Instant::now();
+            // The lines inside skipped groups are only tokenized if they could be conditional
+            // inclusion directives.
+            if !self.is_active() {
+                lines.skip_group(&self.map, output);
+            }
+            // The rest of a region that cannot be tokenized is ignored, like the rest of the file
+            // after an unterminated comment.
+            let more = lines.next_line(&self.map, &mut line);
+            self.statistics.lexing += lexing.elapsed();
+            if self.recover(more)? != Some(true) {
+                break;
+            }
+            let line = &line[..];
+            self.check_line(line);
+
+            // Whether this line is inside a group that is being skipped.
+            let skipping = !self.is_active();
+            // Keep the new-line character so lines are not merged.
+            let newline = line
+                .last()
+                .copied()
+                .filter(|token| token.kind == TokenKind::Newline);
+
+            let (name, rest) = match self.map.directive(line) {
+                Some(directive) => directive,
+                None if skipping => {
+                    output.extend(newline);
+                    continue;
+                }
+                None => {
+                    text.extend_from_slice(line);
+                    continue;
+                }
+            };
+
+            // Any text before a directive must be replaced before processing the directive as
+            // the directive could change the definition of a macro.
+            self.flush(&mut text, output)?;
+
+            // The contents of the directive without the new-line character.
+            let body = match rest.split_last() {
+                Some((last, body)) if last.kind == TokenKind::Newline => body,
+                _ => rest,
+            };
+
+            match name.map(|name| self.map.spelling(name)).as_deref() {
+                Some(directive @ ("if" | "ifdef" | "ifndef")) => {
+                    // The conditions of nested directives inside skipped groups are not evaluated.
+                    let reported = self.diagnostics.len();
+                    // A condition that cannot be evaluated is false, as in GCC.
+                    let value = match skipping {
+                        true => None,
+                        false => {
+                            let value = self.condition(directive, line_span(line), body);
+                            Some(self.recover(value)?.unwrap_or(false))
+                        }
+                    };
+                    if directive == "if" {
+                        self.suggest_ifdef(name.unwrap(), body, reported);
+                    }
+                    let active = value == Some(true);
+                    let section = self.record_branch(None, name.unwrap(), body, value, active);
+                    self.conditionals.push(Conditional {
+                        enclosing: !skipping,
+                        active,
+                        taken: active,
+                        has_else: false,
+                        section,
+                    });
+                }
+                // `#elifdef` and `#elifndef` are only directives since C23 (section 6.10.1 of
+                // C23).
+                Some(directive @ ("elif" | "elifdef" | "elifndef"))
+                    if directive == "elif" || self.standard.has_elifdef() =>
+                {
+                    if directive != "elif" {
+                        self.check_directive_extension(name.unwrap());
+                    }
+                    let hash_directive = format!("#{}", directive);
+                    let conditional = match self.innermost_conditional(depth, line, &hash_directive)
+                    {
+                        Some(conditional) => conditional,
+                        None => {
+                            output.extend(newline);
+                            continue;
+                        }
+                    };
+                    // A group after the `#else` group is never processed, as a group was taken
+                    // already.
+                    if conditional.has_else {
+                        let section = conditional.section;
+                        let message = format!("{} after #else", hash_directive);
+                        let diagnostic = self.else_after_else(line, section, &message);
+                        self.diagnostics.push(diagnostic);
+                    }
+                    let conditional = &self.conditionals[self.conditionals.len() - 1];
+                    let value = match conditional.enclosing && !conditional.taken {
+                        // `#elifdef` is evaluated like `#ifdef`, and `#elifndef` like `#ifndef`.
+                        true => {
+                            let value = self.condition(&directive[2..], line_span(line), body);
+                            Some(self.recover(value)?.unwrap_or(false))
+                        }
+                        false => None,
+                    };
+                    let active = value == Some(true);
+                    let conditional = self.conditionals.last_mut().unwrap();
+                    conditional.active = active;
+                    conditional.taken |= active;
+                    let section = Some(conditional.section);
+                    self.record_branch(section, name.unwrap(), body, value, active);
+                }
+                Some("else") => {
+                    let conditional = match self.innermost_conditional(depth, line, "#else") {
+                        Some(conditional) => conditional,
+                        None => {
+                            output.extend(newline);
+                            continue;
+                        }
+                    };
+                    if conditional.has_else {
+                        let section = conditional.section;
+                        let diagnostic = self.else_after_else(line, section, "#else after #else");
+                        self.diagnostics.push(diagnostic);
+                    }
+                    let conditional = self.conditionals.last_mut().unwrap();
+                    conditional.active = conditional.enclosing && !conditional.taken;
+                    conditional.taken = true;
+                    conditional.has_else = true;
+                    let (section, active) = (Some(conditional.section), conditional.active);
+                    self.record_branch(section, name.unwrap(), &[], None, active);
+                    self.check_extra_tokens("else", body);
+                }
+                Some("endif") => {
+                    if self.innermost_conditional(depth, line, "#endif").is_some() {
+                        self.conditionals.pop();
+                    }
+                    self.check_extra_tokens("endif", body);
+                }
+                // Any other directive inside a skipped group is ignored.
+                _ if skipping => {}
+                // The null directive (section 6.10.7 of C17).
+                None => {}
+                Some("line") => {
+                    let result = self.line_directive(line_span(line), body);
+                    self.recover(result)?;
+                }
+                // The line markers written by GCC, which are `#line` directives without `line`.
+                Some(number) if number.starts_with(|c: char| c.is_ascii_digit()) => {
+                    self.check_directive_extension(name.unwrap());
+                    let number = name.unwrap();
+                    let start = line.iter().position(|token| token.span == number.span);
+                    let rest = &line[start.unwrap() + 1..];
+                    let body = match rest.split_last() {
+                        Some((last, body)) if last.kind == TokenKind::Newline => body,
+                        _ => rest,
+                    };
+                    let result = self.line_marker(line_span(line), number, body);
+                    self.recover(result)?;
+                }
+                Some(directive @ ("error" | "warning")) => {
+                    self.check_directive_extension(name.unwrap());
+                    self.diagnostic_directive(line_span(line), directive, body);
+                }
+                Some(directive @ ("define" | "undef")) => {
+                    if directive == "define" {
+                        self.statistics.macros_defined += 1;
+                        let gnu_variadic = self.extensions.contains(&Extension::GnuVariadic);
+                        let parsed = if self.comments == Some(Comments::Text) {
+                            let body = self.remove_comments(body)?;
+                            self.map.parse_define(line_span(line), &body, gnu_variadic)
+                        } else {
+                            self.map.parse_define(line_span(line), body, gnu_variadic)
+                        };
+                        // A definition that is not valid is ignored.
+                        let (symbol, name, definition) = match self.recover(parsed)? {
+                            Some(parsed) => parsed,
+                            None => {
+                                output.extend(newline);
+                                continue;
+                            }
+                        };
+                        self.check_define_extension(body, &definition);
+                        if let Some(previous) = self.macros.define(symbol, name.clone(), definition)
+                        {
+                            self.check_redefinition(symbol, &name, &previous);
+                            self.check_previous_definition(
+                                directive,
+                                line_span(line),
+                                &name,
+                                &previous,
+                            );
+                        }
+                        self.event(|context| {
+                            let definition = context.macros.get(symbol).unwrap();
+                            let definition = MacroDefinition::new(&name, definition, &context.map);
+                            Event::MacroDefined(name.clone(), definition.to_string())
+                        });
+                    } else {
+                        match skip_space(&mut body.iter().copied()) {
+                            Some(token) if token.kind == TokenKind::Ident => {
+                                let symbol = self.map.symbol(token);
+                                if let Some(previous) = self.macros.undefine(symbol) {
+                                    let name = self.map.spelling(token);
+                                    self.check_previous_definition(
+                                        directive,
+                                        line_span(line),
+                                        &name,
+                                        &previous,
+                                    );
+                                }
+                            }
+                            _ => {
+                                let code = DiagnosticCode::InvalidMacroName;
+                                let message = "Macro names must be identifiers";
+                                let diagnostic = self.directive_error(line, code, message);
+                                self.diagnostics.push(diagnostic);
+                                output.extend(newline);
+                                continue;
+                            }
+                        }
+                    }
+
+                    match self.macro_dump {
+                        Some(MacroDump::Directives) => {
+                            output.extend_from_slice(line);
+                            continue;
+                        }
+                        Some(MacroDump::Names) => {
+                            // Keep everything up to the name of the macro, which is the first
+                            // token after the name of the directive that is not white-space.
+                            let start = line.len() - body.len();
+                            let end = (start..line.len())
+                                .find(|&i| line[i].kind != TokenKind::Space)
+                                .map_or(line.len(), |i| i + 1);
+                            output.extend_from_slice(&line[..end]);
+                        }
+                        _ => {}
+                    }
+                }
+                // `#include_next` and `#import` are only directives if their extensions are
+                // enabled.
+                Some(directive @ ("include" | "include_next" | "import"))
+                    if match directive {
+                        "include_next" => self.extensions.contains(&Extension::IncludeNext),
+                        "import" => self.extensions.contains(&Extension::Import),
+                        _ => true,
+                    } =>
+                {
+                    if directive != "include" {
+                        self.check_directive_extension(name.unwrap());
+                    }
+                    if self.keep_includes {
+                        output.extend_from_slice(line);
+                    }
+                    let included = self.include(name.unwrap(), line_span(line), body, output);
+                    self.recover(included)?;
+                }
+                // `#elifdef` and `#elifndef` are not directives before C23 unless GNU extensions
+                // are enabled, and `#embed` is not supported yet.
+                Some(directive @ ("elifdef" | "elifndef" | "embed")) => {
+                    let message = match directive {
+                        "embed" => "#embed is not supported".to_owned(),
+                        _ => format!("#{} is not a directive before C23", directive),
+                    };
+                    let code = DiagnosticCode::UnsupportedDirective;
+                    let diagnostic = self.directive_error(line, code, message);
+                    self.diagnostics.push(diagnostic);
+                }
+                Some("pragma") if self.is_pragma_once(body) => {
+                    self.once.insert(span);
+                    self.once_directives.push(line_span(line));
+                }
+                // Pragmas are kept as they are, without replacing the macros inside them as GCC
+                // does, and so are the `#pragma GCC diagnostic` directives after applying them.
+                Some("pragma") => {
+                    self.pragma_diagnostic(body);
+                    output.extend_from_slice(line);
+                    continue;
+                }
+                // `#ident` and `#sccs` are GNU extensions that are also kept as they are.
+                Some("ident" | "sccs") => {
+                    self.check_directive_extension(name.unwrap());
+                    output.extend_from_slice(line);
+                    continue;
+                }
+                Some(directive) => {
+                    let code = DiagnosticCode::InvalidDirective;
+                    let message = format!("Invalid preprocessing directive #{}", directive);
+                    let diagnostic =
+                        self.map
+                            .diagnostic(Severity::Error, code, name.unwrap().span, message);
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+
+            output.extend(newline);
+        }
+
+        self.flush(&mut text, output)?;
+        self.check_expansion_extensions();
+        self.statistics.bytes_lexed += lines.lexed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes = lines.lexed(), "tokenized lines");
+        // The text after the last directive is only replaced here, so it was not checked yet.
+        self.check_budget(Span::new(span.hi(), span.hi()), output)?;
+
+        // The conditional inclusion directives of a prefix are terminated after it.
+        if self.conditionals.len() > depth && !(self.prefix && self.inclusions.is_empty()) {
+            // The error is reported on the innermost directive that was not terminated, and the
+            // directives are terminated at the end of the file.
+            let section = self.conditionals[self.conditionals.len() - 1].section;
+            let directive = self.sections[section][0].directive().span;
+            let code = DiagnosticCode::UnterminatedConditional;
+            let message = "Unterminated conditional directive";
+            let diagnostic = self
+                .map
+                .diagnostic(Severity::Error, code, directive, message)
+                .with_suggestion(self.endif_suggestion(span, self.conditionals.len() - depth));
+            self.diagnostics.push(diagnostic);
+            self.conditionals.truncate(depth);
+        }
+        Ok(())
+    }
+
+    /// Report the error of `result` if it does not stop the preprocessing, so the preprocessing
+    /// continues as GCC does. Return the value of `result`, `None` if it is an error that was
+    /// reported or the error if it is [fatal](DiagnosticCode::is_fatal).
+    fn recover<T>(&mut self, result: Result<T, Diagnostic>) -> Result<Option<T>, Diagnostic> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(diagnostic)
+                if diagnostic.code().is_fatal()
+                    || diagnostic.code() == DiagnosticCode::Cancelled =>
+            {
+                Err(diagnostic)
+            }
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Report the errors found replacing macros that did not stop the replacement.
+    fn report_expansion_errors(&mut self) {
+        for diagnostic in self.macros.take_errors() {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Create an error with the code `code` about the directive in `line`.
+    fn directive_error(
+        &self,
+        line: &[Token],
+        code: DiagnosticCode,
+        message: impl Into<String>,
+    ) -> Diagnostic {
+        self.map
+            .diagnostic(Severity::Error, code, line_span(line), message)
+    }
+
+    /// Create an error about the directive in `line`, which comes after the `#else` directive of
+    /// the conditional whose record is the `section`-th one.
+    fn else_after_else(&self, line: &[Token], section: usize, message: &str) -> Diagnostic {
+        let previous = self.sections[section].last().unwrap().directive();
+        let label = self.map.label(previous.span, "Previous #else is here");
+        self.directive_error(line, DiagnosticCode::ElseAfterElse, message)
+            .with_label(label)
+    }
+
+    /// Get the tokens of the region `span` relative to its start from the caches of tokens, if
+    /// there are any.
+    fn cached_tokens(&mut self, span: Span) -> Option<Arc<[Token]>> {
+        if self.token_cache.is_none() && self.map.header_cache().is_none() {
+            return None;
+        }
+        if let Some(tokens) = self.cached_tokens.get(&span) {
+            self.statistics.cache_hits += usize::from(tokens.is_some());
+            return tokens.clone();
+        }
+
+        // Whether the tokens were taken from a cache instead of tokenizing the region.
+        let hit = Cell::new(true);
+        let info = Cell::new(None);
+        let start = Instant::now();
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("tokenize", file = %self.trace_file(span)).entered();
+        let tokenize = || match &self.token_cache {
+            Some(cache) => cache.tokens(&self.map, span).map(|(tokens, file, stored)| {
+                hit.set(stored);
+                info.set(Some(file));
+                tokens
+            }),
+            None => {
+                hit.set(false);
+                Lines::tokenize(&self.map, span).map(Arc::from)
+            }
+        };
+        let tokens = match (self.map.header_cache(), self.map.get_contents(span)) {
+            (Some(cache), Some(contents)) => {
+                cache.tokens(&contents, self.map.lexer_features(), tokenize)
+            }
+            _ if self.token_cache.is_some() => tokenize(),
+            _ => None,
+        };
+        if tokens.is_some() && hit.get() {
+            self.statistics.cache_hits += 1;
+        } else if tokens.is_some() {
+            self.statistics.bytes_lexed += span.hi() - span.lo();
+            self.trace_event("Tokenize", start, |context| context.trace_file(span));
+        }
+        if let (Some(tokens), Some(_)) = (&tokens, &self.token_cache) {
+            // The tokens of the header cache do not come with the information about the file.
+            let info = info
+                .into_inner()
+                .unwrap_or_else(|| FileInfo::new(&self.map, span, tokens));
+            self.file_info.insert(span, info);
+        }
+        self.cached_tokens.insert(span, tokens.clone());
+        tokens
+    }
+
+    /// Replace the white-space containing comments inside `tokens` with a single space.
+    ///
+    /// Return an error if the spaces cannot be stored.
+    fn remove_comments(&self, tokens: &[Token]) -> Result<Vec<Token>, Diagnostic> {
+        tokens
+            .iter()
+            .map(|&token| match token.kind {
+                TokenKind::Space if self.map.get_bytes(token.span).contains(&b'/') => {
+                    let span = self.map.store_at(b" ", token.span)?;
+                    Ok(Token::new(TokenKind::Space, span))
+                }
+                _ => Ok(token),
+            })
+            .collect()
+    }
+
+    /// Check if `body`, the tokens after the name of a `#pragma` directive, is `once`.
+    fn is_pragma_once(&self, body: &[Token]) -> bool {
+        let mut cursor = Cursor::new(body);
+        matches!(
+            (cursor.next_non_space(), cursor.next_non_space()),
+            (Some(token), None) if &*self.map.get_bytes(token.span) == b"once"
+        )
+    }
+
+    /// Check if the current group is being processed.
+    fn is_active(&self) -> bool {
+        self.conditionals
+            .last()
+            .is_none_or(|conditional| conditional.active)
+    }
+
+    /// Get the innermost conditional inclusion directive started after the first `depth` ones.
+    ///
+    /// Report an error about the directive in `line` and return `None` if there is no such
+    /// directive, `directive` is used in the message.
+    fn innermost_conditional(
+        &mut self,
+        depth: usize,
+        line: &[Token],
+        directive: &str,
+    ) -> Option<&mut Conditional> {
+        if self.conditionals.len() == depth {
+            let code = DiagnosticCode::UnmatchedConditional;
+            let message = format!("{} without #if", directive);
+            let diagnostic = self.directive_error(line, code, message);
+            self.diagnostics.push(diagnostic);
+            return None;
+        }
+        self.conditionals.last_mut()
+    }
+
+    /// Evaluate the condition of an `#if`, `#ifdef` or `#ifndef` directive in the region `span`
+    /// whose tokens after the name are `body`.
+    fn condition(
+        &mut self,
+        directive: &str,
+        span: Span,
+        body: &[Token],
+    ) -> Result<bool, Diagnostic> {
+        if directive != "if" {
+            let name = match skip_space(&mut body.iter().copied()) {
+                Some(token) if token.kind == TokenKind::Ident => token,
+                _ => {
+                    let code = DiagnosticCode::InvalidMacroName;
+                    let message = "Macro names must be identifiers";
+                    return Err(self.map.diagnostic(Severity::Error, code, span, message));
+                }
+            };
+            return Ok(self.is_defined(name) == (directive == "ifdef"));
+        }
+
+        // The `defined` operators are evaluated before replacing macros so their operands are
+        // not replaced.
+        let expansion = Instant::now();
+        let tokens = self.replace_defined(span, body.iter().copied())?;
+        let expanded = self.macros.expand_condition(&self.map, &tokens);
+        self.report_expansion_errors();
+        let expanded = expanded?;
+
+        // Any `defined` left was produced by a macro, which is undefined behavior (section
+        // 6.10.1p4 of C17). It is evaluated anyway, as most compilers do.
+        for (token, expansion) in &expanded {
+            let expansion = match expansion {
+                Some(expansion) if &*self.map.get_bytes(token.span) == b"defined" => expansion,
+                _ => continue,
+            };
+            let diagnostic = self.map.diagnostic(
+                Severity::Warning,
+                DiagnosticCode::ExpansionToDefined,
+                token.span,
+                "Macro expansion producing \"defined\" has undefined behavior",
+            );
+            self.diagnostics
+                .push(expansion.backtrace(&self.map, diagnostic));
+        }
+
+        // The problems found evaluating tokens produced by macros get the invocations that
+        // produced them.
+        let backtrace = |map: &SourceMap, diagnostic: Diagnostic| {
+            let found = expanded.iter().find_map(|(token, expansion)| {
+                let inside = token.span.lo() <= diagnostic.span().lo()
+                    && diagnostic.span().lo() < token.span.hi();
+                expansion.as_ref().filter(|_| inside)
+            });
+            match found {
+                Some(expansion) => expansion.backtrace(map, diagnostic),
+                None => diagnostic,
+            }
+        };
+
+        let tokens = self.replace_defined(span, expanded.iter().map(|&(token, _)| token))?;
+        let reported = self.diagnostics.len();
+        let value = self
+            .map
+            .evaluate(span, &tokens, &self.evaluation, &mut self.diagnostics)
+            .map_err(|diagnostic| backtrace(&self.map, diagnostic));
+        self.diagnostics
+            .update_since(reported, |diagnostic| backtrace(&self.map, diagnostic));
+        self.statistics.expansion += expansion.elapsed();
+        value
+    }
+
+    /// Check if the identifier `name` is the name of a macro that is defined. The
+    /// `__has_include` operators are defined too, so their availability can be checked (section
+    /// 6.10.1 of C23).
+    fn is_defined(&self, name: Token) -> bool {
+        self.macros.is_defined(self.map.symbol(name)) || self.has_include_operator(name).is_some()
+    }
+
+    /// Check if `token` is a `__has_include` operator, if the standard has it, or a
+    /// `__has_include_next` operator, if `#include_next` is available. Return whether it is the
+    /// second one.
+    fn has_include_operator(&self, token: Token) -> Option<bool> {
+        if token.kind != TokenKind::Ident || !self.standard.has_has_include() {
+            return None;
+        }
+        match &*self.map.get_bytes(token.span) {
+            b"__has_include" => Some(false),
+            b"__has_include_next" if self.extensions.contains(&Extension::IncludeNext) => {
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    /// Evaluate the `defined` operators inside `tokens` (section 6.10.1p1 of C17) and the
+    /// `__has_include` operators, replacing each one of them and its operand with `1` or `0`.
+    ///
+    /// Return an error if an operand is not valid, reported at its `defined` operator or at
+    /// `span` if there are no tokens after it.
+    fn replace_defined(
+        &mut self,
+        span: Span,
+        tokens: impl IntoIterator<Item = Token>,
+    ) -> Result<Vec<Token>, Diagnostic> {
+        let mut output = Vec::new();
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            if let Some(next) = self.has_include_operator(token) {
+                let found = self.replace_has_include(span, token, &mut tokens, next)?;
+                let span = self
+                    .map
+                    .store_at(if found { b"1" } else { b"0" }, token.span)?;
+                output.push(Token::new(TokenKind::Number, span));
+                continue;
+            }
+            if token.kind != TokenKind::Ident || &*self.map.get_bytes(token.span) != b"defined" {
+                output.push(token);
+                continue;
+            }
+
+            let mut next = skip_space(&mut tokens);
+            let open = next.filter(|&token| self.map.is_punct(token, b"("));
+            if open.is_some() {
+                next = skip_space(&mut tokens);
+            }
+            let code = DiagnosticCode::InvalidDefined;
+            let name = match next {
+                Some(token) if token.kind == TokenKind::Ident => token,
+                next => {
+                    let span = next.map_or(span, |next| next.span);
+                    let message = "Operator \"defined\" requires an identifier";
+                    return Err(self.map.diagnostic(Severity::Error, code, span, message));
+                }
+            };
+            if let Some(open) = open {
+                if !skip_space(&mut tokens).is_some_and(|token| self.map.is_punct(token, b")")) {
+                    let message = "Missing \")\" after \"defined\"";
+                    let label = self.map.label(open.span, "To match this \"(\"");
+                    let diagnostic = self
+                        .map
+                        .diagnostic(Severity::Error, code, name.span, message);
+                    return Err(diagnostic.with_label(label));
+                }
+            }
+
+            let value: &[u8] = if self.is_defined(name) { b"1" } else { b"0" };
+            let span = self.map.store_at(value, token.span)?;
+            output.push(Token::new(TokenKind::Number, span));
+        }
+        Ok(output)
+    }
+
+    /// Evaluate the `__has_include` operator `operator`, or the `__has_include_next` operator if
+    /// `next` is `true`, whose operand is taken from `tokens` with its parentheses.
+    ///
+    /// Return an error if the operand is not valid, reported at the operator or at `span` if
+    /// there are no tokens after it.
+    fn replace_has_include(
+        &mut self,
+        span: Span,
+        operator: Token,
+        tokens: &mut impl Iterator<Item = Token>,
+        next: bool,
+    ) -> Result<bool, Diagnostic> {
+        let code = DiagnosticCode::InvalidHasInclude;
+        let name = self.map.spelling(operator);
+        let open = match skip_space(tokens) {
+            Some(open) if self.map.is_punct(open, b"(") => open,
+            next => {
+                let span = next.map_or(span, |next| next.span);
+                let message = format!("Missing \"(\" after \"{}\"", name);
+                return Err(self.map.diagnostic(Severity::Error, code, span, message));
+            }
+        };
+        let mut operand = Vec::new();
+        loop {
+            match tokens.next() {
+                Some(token) if self.map.is_punct(token, b")") => break,
+                Some(token) => operand.push(token),
+                None => {
+                    let message = format!("Missing \")\" after \"{}\"", name);
+                    let label = self.map.label(open.span, "To match this \"(\"");
+                    let diagnostic =
+                        self.map
+                            .diagnostic(Severity::Error, code, operator.span, message);
+                    return Err(diagnostic.with_label(label));
+                }
+            }
+        }
+        self.has_include(operator, &operand, next)
+    }
+
+    /// Replace the macros in `text` and move the result to `output`.
+    ///
+    /// Return an error if replacing the macros fails.
+    fn flush(&mut self, text: &mut Vec<Token>, output: &mut TokenBuffer) -> Result<(), Diagnostic> {
+        let first = match text.first() {
+            Some(&first) => first,
+            None => return Ok(()),
+        };
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("expand", tokens = text.len()).entered();
+        let expansion = Instant::now();
+        let expanded = self.macros.expand_traced(&self.map, text);
+        self.report_expansion_errors();
+        let expanded = self.replace_pragma_operators(expanded?)?;
+        self.record_expansions(self.streamed + output.tokens().len(), &expanded);
+        for invocation in self.macros.take_invocations() {
+            let index = self
+                .invocations
+                .partition_point(|span| span.lo() <= invocation.lo());
+            self.invocations.insert(index, invocation);
+        }
+        output.extend(expanded.into_iter().map(|(token, _)| token));
+        text.clear();
+        self.statistics.expansion += expansion.elapsed();
+        self.trace_event("Expand", expansion, |context| {
+            let line = context.map.find_line(first.span).unwrap_or_default();
+            format!("{}:{}", context.trace_file(first.span), line)
+        });
+        Ok(())
+    }
+}
+
+/// Get the region of a line, including its new-line character if it has one.
+fn line_span(line: &[Token]) -> Span {
+    Span::new(
+        line.first().map_or(0, |token| token.span.lo()),
+        line.last().map_or(0, |token| token.span.hi()),
+    )
+}
+
+impl SourceMap {
+    /// Check if `line` is a directive and return its name and the tokens after it.
+    ///
+    /// The name is `None` for the null directive.
+    pub(crate) fn directive<'a>(&self, line: &'a [Token]) -> Option<(Option<Token>, &'a [Token])> {
+        let mut cursor = Cursor::new(line);
+        // A directive starts with a `#` that is the first token of the line.
+        let hash = cursor.next_non_space()?;
+        if !self.is_punct(hash, b"#") {
+            return None;
+        }
+
+        match cursor.next_non_space() {
+            Some(token) if token.kind == TokenKind::Ident => Some((Some(token), cursor.rest())),
+            Some(token) if token.kind != TokenKind::Newline => {
+                Some((Some(token), &line[line.len()..]))
+            }
+            _ => Some((None, &line[line.len()..])),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! The diagnostics about the extensions to ISO C accepted by the preprocessor, which are only
+//! reported if the [`Pedantic`](crate::Warning::Pedantic) warning is enabled. The extensions that
+//! are not accepted unless enabled using [`Extension`](crate::Extension), like `$` in
+//! identifiers, are not reported.
+use super::Context;
+use crate::{
+    diagnostic::{DiagnosticCode, Severity},
+    lexer::{Token, TokenKind},
+    macros::{Macro, MacroKind},
+    span::Span,
+    Standard,
+};
+
+/// The names of the directives of C17 (section 6.10 of C17).
+const DIRECTIVES: &[&str] = &[
+    "if", "ifdef", "ifndef", "elif", "else", "endif", "include", "define", "undef", "line",
+    "error", "pragma",
+];
+
+/// The names of the directives added by C23.
+const C23_DIRECTIVES: &[&str] = &["elifdef", "elifndef", "embed", "warning"];
+
+impl Context {
+    /// Report the use of an extension in the region `span`.
+    fn extension(&mut self, span: Span, message: impl Into<String>) {
+        let code = DiagnosticCode::Extension;
+        if self.diagnostics.is_reported(code) {
+            let diagnostic = self.map.diagnostic(Severity::Warning, code, span, message);
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Report the directive whose name is the token `name` if it is not part of the standard
+    /// being followed.
+    pub(super) fn check_directive_extension(&mut self, name: Token) {
+        let directive = self.map.spelling(name);
+        let message = if DIRECTIVES.contains(&directive.as_str()) {
+            return;
+        } else if directive.starts_with(|c: char| c.is_ascii_digit()) {
+            "Line markers are a GNU extension".to_owned()
+        } else if C23_DIRECTIVES.contains(&directive.as_str()) {
+            if self.standard >= Standard::C23 {
+                return;
+            }
+            format!("#{} is a C23 extension", directive)
+        } else {
+            format!("#{} is an extension", directive)
+        };
+        self.extension(name.span, message);
+    }
+
+    /// Report the definition of an object-like macro without white-space between its name and
+    /// its replacement list, which is required by section 6.10.3p3 of C17, and the `__VA_OPT__`
+    /// operators of variadic macros before C23. `body` are the tokens of the `#define` directive
+    /// after its name and `definition` is the macro they define.
+    pub(super) fn check_define_extension(&mut self, body: &[Token], definition: &Macro) {
+        if self.standard < Standard::C23
+            && matches!(definition.kind, MacroKind::Function { variadic: true, .. })
+        {
+            let operators: Vec<Span> = definition
+                .replacement
+                .tokens()
+                .iter()
+                .filter(|token| token.kind == TokenKind::Ident)
+                .filter(|token| *self.map.get_bytes(token.span) == *b"__VA_OPT__")
+                .map(|token| token.span)
+                .collect();
+            for span in operators {
+                self.extension(span, "__VA_OPT__ is a C23 extension");
+            }
+        }
+
+        let mut tokens = body
+            .iter()
+            .skip_while(|token| token.kind == TokenKind::Space);
+        let next = match (tokens.next(), tokens.next()) {
+            (Some(name), Some(&next)) if name.kind == TokenKind::Ident => next,
+            _ => return,
+        };
+        // A `(` right after the name starts the parameters of a function-like macro.
+        if !matches!(next.kind, TokenKind::Space | TokenKind::Newline)
+            && !self.map.is_punct(next, b"(")
+        {
+            let message = "ISO C requires white-space after the name of an object-like macro";
+            self.extension(next.span, message);
+        }
+    }
+
+    /// Report the invocations of variadic macros without their variable arguments replaced since
+    /// the last call, which are only allowed since C23.
+    pub(super) fn check_expansion_extensions(&mut self) {
+        let omitted = self.macros.take_omitted_variadic();
+        if self.standard >= Standard::C23 {
+            return;
+        }
+        for span in omitted {
+            let name = String::from_utf8_lossy(&self.map.get_bytes(span)).into_owned();
+            let message = format!(
+                "Invoking macro \"{}\" without its variable arguments is a C23 extension",
+                name
+            );
+            self.extension(span, message);
+        }
+    }
+}
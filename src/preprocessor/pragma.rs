@@ -0,0 +1,98 @@
+//! The `_Pragma` operator (section 6.10.9 of C17).
+use super::{line::unescape, line_span, Context};
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    lexer::{Token, TokenKind},
+    macros::ExpandedToken,
+};
+
+impl Context {
+    /// Replace the `_Pragma` operators inside `tokens`, the result of replacing macros, with the
+    /// `#pragma` directives their string literals stand for, each on its own line, as GCC does.
+    /// The directives are kept like the other `#pragma` directives. An operator whose operand is
+    /// not valid is reported and kept as it is.
+    ///
+    /// Return an error if the directives cannot be stored.
+    pub(super) fn replace_pragma_operators(
+        &mut self,
+        tokens: Vec<ExpandedToken>,
+    ) -> Result<Vec<ExpandedToken>, Diagnostic> {
+        if !self.standard.has_pragma_operator() {
+            return Ok(tokens);
+        }
+        let mut output: Vec<ExpandedToken> = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+        while i < tokens.len() {
+            let operator = tokens[i].0;
+            if operator.kind != TokenKind::Ident
+                || &*self.map.get_bytes(operator.span) != b"_Pragma"
+            {
+                output.push(tokens[i].clone());
+                i += 1;
+                continue;
+            }
+
+            // The operator is followed by `(`, a string literal and `)`, with any white-space
+            // between them.
+            let operand: Vec<usize> = (i + 1..tokens.len())
+                .filter(|&j| tokens[j].0.kind != TokenKind::Space)
+                .take(3)
+                .collect();
+            let (literal, close) = match operand[..] {
+                [open, literal, close]
+                    if self.map.is_punct(tokens[open].0, b"(")
+                        && tokens[literal].0.kind == TokenKind::Str
+                        && self.map.is_punct(tokens[close].0, b")") =>
+                {
+                    (tokens[literal].0, close)
+                }
+                _ => {
+                    let diagnostic = self.map.diagnostic(
+                        Severity::Error,
+                        DiagnosticCode::InvalidPragmaOperator,
+                        operator.span,
+                        "_Pragma takes a string literal between parentheses",
+                    );
+                    self.diagnostics.push(diagnostic);
+                    output.push(tokens[i].clone());
+                    i += 1;
+                    continue;
+                }
+            };
+
+            // The string literal is destringized: its prefix and its quotes are removed and the
+            // `\\` and `\"` inside it are replaced.
+            let spelling = self.map.spelling(literal);
+            let start = spelling.find('"').unwrap() + 1;
+            let pragma = unescape(&spelling[start..spelling.len() - 1]);
+            let source = format!("#pragma {}\n", pragma);
+            let line = self.map.tokenize_bytes(source.as_bytes(), operator.span)?;
+            let line = line.tokens();
+            self.pragma_operators.push(line_span(line));
+            if let Some((_, body)) = self.map.directive(&line[..line.len() - 1]) {
+                self.pragma_diagnostic(body);
+            }
+
+            // The directive starts a new line unless there is only white-space before it, and the
+            // white-space at the end of the previous line is removed.
+            let line_start = output
+                .iter()
+                .rev()
+                .take_while(|(token, _)| token.kind != TokenKind::Newline)
+                .all(|(token, _)| token.kind == TokenKind::Space);
+            if !line_start {
+                while output
+                    .last()
+                    .is_some_and(|(token, _)| token.kind == TokenKind::Space)
+                {
+                    output.pop();
+                }
+                let newline = self.map.store_at(b"\n", operator.span)?;
+                output.push((Token::new(TokenKind::Newline, newline), None));
+            }
+            output.extend(line.iter().map(|&token| (token, None)));
+            i = close + 1;
+        }
+        Ok(output)
+    }
+}
@@ -0,0 +1,345 @@
+//! Self-contained source files with the contents of the included files inlined.
+use std::{iter::Peekable, path::Path, slice::Iter};
+
+use super::{coverage::BranchRecord, graph::IncludeRecord, Context, Preprocessed};
+use crate::{
+    lexer::{Lines, Token, TokenKind},
+    span::{SourceMap, Span},
+};
+
+/// The comment marking the directives disabled by the rewrite, which is the same one used by
+/// Clang.
+const MARKER: &str = "expanded by -frewrite-includes";
+
+impl Preprocessed {
+    /// Rewrite the input so it does not need any of the files included by `#include` directives.
+    /// This is equivalent to the `-frewrite-includes` option of Clang.
+    ///
+    /// Each `#include` directive whose file was included is disabled by surrounding it with
+    /// `#if 0` and `#endif` and followed by the contents of the file, rewritten in the same way.
+    /// Nothing else is changed, so preprocessing the result with the same configuration produces
+    /// the same output. Line markers keep the original locations of every line. The `#pragma
+    /// once` directives are disabled too, as every file is inlined only where it was processed.
+    ///
+    /// The files included before processing the input are not part of the result.
+    ///
+    /// ```
+    /// use beheader::{MemoryFileSystem, Preprocessor};
+    ///
+    /// let mut files = MemoryFileSystem::new();
+    /// files.insert("main.c", "#include \"a.h\"\nint b = A;\n");
+    /// files.insert("a.h", "#define A 1\n");
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.file_system(files);
+    /// let rewritten = preprocessor.preprocess_file(&"main.c").unwrap().rewrite_includes();
+    ///
+    /// let rewritten = String::from_utf8(rewritten).unwrap();
+    /// assert_eq!(
+    ///     rewritten.lines().collect::<Vec<_>>(),
+    ///     [
+    ///         "# 1 \"main.c\"",
+    ///         "#if 0 /* expanded by -frewrite-includes */",
+    ///         "#include \"a.h\"",
+    ///         "#endif /* expanded by -frewrite-includes */",
+    ///         "# 1 \"a.h\" 1",
+    ///         "#define A 1",
+    ///         "# 2 \"main.c\" 2",
+    ///         "int b = A;",
+    ///     ]
+    /// );
+    /// ```
+    pub fn rewrite_includes(&self) -> Vec<u8> {
+        self.inline_includes(false)
+    }
+
+    /// Replace the `#include` directives of the input with the contents of the files they
+    /// included, recursively, leaving everything else as it is.
+    ///
+    /// Unlike [`rewrite_includes`](Self::rewrite_includes), the directives and the line markers
+    /// are not kept. A directive is removed without replacing it if its file was not processed
+    /// because of a `#pragma once` directive or if the whole file is a conditional inclusion
+    /// directive whose groups were all skipped, like a file with an include guard that was
+    /// included already. The `#pragma once` directives are removed too.
+    ///
+    /// The files included before processing the input are not part of the result.
+    ///
+    /// ```
+    /// use beheader::{MemoryFileSystem, Preprocessor};
+    ///
+    /// let mut files = MemoryFileSystem::new();
+    /// files.insert("main.c", "#include \"a.h\"\n#include \"a.h\"\nint b = A;\n");
+    /// files.insert("a.h", "#ifndef A_H\n#define A_H\n#define A 1\n#endif\n");
+    ///
+    /// let mut preprocessor = Preprocessor::new();
+    /// preprocessor.file_system(files);
+    /// let flattened = preprocessor.preprocess_file(&"main.c").unwrap().flatten_includes();
+    /// assert_eq!(
+    ///     String::from_utf8(flattened).unwrap(),
+    ///     "#ifndef A_H\n#define A_H\n#define A 1\n#endif\nint b = A;\n"
+    /// );
+    /// ```
+    pub fn flatten_includes(&self) -> Vec<u8> {
+        self.inline_includes(true)
+    }
+
+    /// Inline the files included by the input, disabling the `#include` directives and adding
+    /// line markers unless `flatten` is `true`.
+    fn inline_includes(&self, flatten: bool) -> Vec<u8> {
+        let context = &self.context;
+        let source = match context.source {
+            Some(source) => source,
+            None => return Vec::new(),
+        };
+        let input = context.input.as_deref().unwrap_or(Path::new("<stdin>"));
+
+        // The directives of the files included before the input are recorded before the ones of
+        // the input.
+        let start = context
+            .include_edges
+            .iter()
+            .position(|record| record.depth == 0 && source.contains(record.line))
+            .unwrap_or(context.include_edges.len());
+
+        let mut rewriter = Rewriter {
+            preprocessed: self,
+            output: Vec::new(),
+            records: context.include_edges[start..].iter().peekable(),
+            flatten,
+        };
+        rewriter.line_marker(1, input, "");
+        rewriter.rewrite(source, input, 0);
+        rewriter.output
+    }
+}
+
+/// The state of [`Preprocessed::rewrite_includes`].
+struct Rewriter<'a> {
+    preprocessed: &'a Preprocessed,
+    output: Vec<u8>,
+    /// The `#include` directives that have not been rewritten yet, in the order they were found.
+    records: Peekable<Iter<'a, IncludeRecord>>,
+    /// Whether the directives are removed instead of disabled and no line markers are added.
+    flatten: bool,
+}
+
+impl Rewriter<'_> {
+    /// Rewrite the region `span` of the file at `path`, whose directives were found with `depth`
+    /// other `#include` directives being processed.
+    fn rewrite(&mut self, span: Span, path: &Path, depth: usize) {
+        let context = &self.preprocessed.context;
+        let mut pos = span.lo();
+
+        while let Some(record) = self.records.next_if(|record| record.depth == depth) {
+            self.copy(pos, record.line.lo(), path);
+            pos = record.line.hi();
+            if self.flatten {
+                self.flatten(record, depth);
+                continue;
+            }
+            self.disable(record.line);
+
+            match record.contents {
+                Some(contents) => {
+                    let system = context.system_headers.contains(&record.included);
+                    self.line_marker(1, &record.included, if system { " 1 3" } else { " 1" });
+                    self.rewrite(contents, &record.included, depth + 1);
+                    self.end_line();
+                    self.resume(record.line, path, " 2");
+                }
+                None => self.resume(record.line, path, ""),
+            }
+        }
+
+        self.copy(pos, span.hi(), path);
+    }
+
+    /// Replace the directive of `record`, found with `depth` other `#include` directives being
+    /// processed, with the contents of its file.
+    fn flatten(&mut self, record: &IncludeRecord, depth: usize) {
+        let context = &self.preprocessed.context;
+        match record.contents {
+            Some(contents) if !self.is_skipped(record, contents) => {
+                self.end_line();
+                self.rewrite(contents, &record.included, depth + 1);
+                self.end_line();
+            }
+            // The directives found in the file are removed with it.
+            Some(_) => while self.records.next_if(|next| next.depth > depth).is_some() {},
+            // The file was not processed because of a `#pragma once` directive.
+            None if context
+                .map
+                .file_span(&record.included)
+                .is_some_and(|span| context.once.contains(&span)) => {}
+            // The file was not processed because of the maximum depth, so the directive is kept.
+            None => {
+                let bytes = context.map.get_bytes(record.line);
+                self.output.extend_from_slice(&bytes);
+            }
+        }
+    }
+
+    /// Check if the whole file in the region `contents` included by the directive of `record` is
+    /// a conditional inclusion directive whose groups were all skipped.
+    fn is_skipped(&self, record: &IncludeRecord, contents: Span) -> bool {
+        let context = &self.preprocessed.context;
+        context
+            .skipped_conditional(contents, record.sections)
+            .is_some()
+    }
+
+    /// Copy the region between `lo` and `hi` of the file at `path` to the output, disabling the
+    /// `#pragma once` directives inside it, or removing them if flattening.
+    fn copy(&mut self, mut lo: usize, hi: usize, path: &Path) {
+        let context = &self.preprocessed.context;
+        let mut directives: Vec<_> = context
+            .once_directives
+            .iter()
+            .filter(|line| line.lo() >= lo && line.hi() <= hi)
+            .collect();
+        directives.sort_by_key(|line| line.lo());
+        directives.dedup();
+
+        for &line in directives {
+            let span = Span::new(lo, line.lo());
+            self.output.extend_from_slice(&context.map.get_bytes(span));
+            if !self.flatten {
+                self.disable(line);
+                self.resume(line, path, "");
+            }
+            lo = line.hi();
+        }
+        let span = Span::new(lo, hi);
+        self.output.extend_from_slice(&context.map.get_bytes(span));
+    }
+
+    /// Add the directive in the region `line` to the output surrounded by `#if 0` and `#endif`.
+    fn disable(&mut self, line: Span) {
+        self.end_line();
+        self.output
+            .extend_from_slice(format!("#if 0 /* {} */\n", MARKER).as_bytes());
+        let bytes = self.preprocessed.context.map.get_bytes(line);
+        self.output.extend_from_slice(&bytes);
+        self.end_line();
+        self.output
+            .extend_from_slice(format!("#endif /* {} */\n", MARKER).as_bytes());
+    }
+
+    /// Add a line marker saying the next line is the line after the directive in the region
+    /// `line` of the file at `path`, followed by `flags`. This is needed after the lines added by
+    /// the rewrite.
+    fn resume(&mut self, line: Span, path: &Path, flags: &str) {
+        let context = &self.preprocessed.context;
+        // The directive can span several lines.
+        let number =
+            context.map.find_line(line).unwrap_or(1) + count_lines(&context.map.get_bytes(line));
+        let system = if context.system_headers.contains(path) {
+            " 3"
+        } else {
+            ""
+        };
+        self.line_marker(number, path, &format!("{}{}", flags, system));
+    }
+
+    /// Add a line marker saying the next line is the line `line` of the file at `path`, followed
+    /// by `flags`.
+    fn line_marker(&mut self, line: usize, path: &Path, flags: &str) {
+        if self.flatten {
+            return;
+        }
+        let path = self.preprocessed.context.map.display_path(path);
+        self.output
+            .extend_from_slice(line_marker(line, &path, flags).as_bytes());
+    }
+
+    /// Add a new-line character to the output unless it is empty or already ends with one.
+    fn end_line(&mut self) {
+        if !self.output.is_empty() && !self.output.ends_with(b"\n") {
+            self.output.push(b'\n');
+        }
+    }
+}
+
+/// Produce a line marker saying the next line is the line `line` of the file at `path`, followed
+/// by `flags`.
+pub(super) fn line_marker(line: usize, path: &Path, flags: &str) -> String {
+    let path = path.to_string_lossy();
+    let path = path.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("# {} \"{}\"{}\n", line, path, flags)
+}
+
+impl Context {
+    /// Get the branches of the conditional inclusion directive that encloses the whole file in
+    /// the region `contents` if all of its groups were skipped, given the index in
+    /// [`sections`](Self::sections) of the first directive that can be found in the file.
+    pub(super) fn skipped_conditional(
+        &self,
+        contents: Span,
+        section: usize,
+    ) -> Option<&[BranchRecord]> {
+        let directive = enclosing_conditional(&self.map, contents)?;
+        // The first conditional inclusion directive found in the file is the enclosing one.
+        let branches = self.sections.get(section)?;
+        let skipped = branches[0].directive().span == directive.span
+            && branches.iter().all(|branch| !branch.is_taken());
+        skipped.then_some(branches.as_slice())
+    }
+}
+
+/// Get the name of the conditional inclusion directive that encloses the whole file in the
+/// region `span`, except for white-space, like the ones of include guards. Return `None` if there
+/// is no such directive or the file contains invalid tokens.
+fn enclosing_conditional(map: &SourceMap, span: Span) -> Option<Token> {
+    let tokens = Lines::tokenize(map, span)?;
+    let mut first = None;
+    let mut depth = 0_usize;
+    let mut closed = false;
+
+    for line in tokens.split_inclusive(|token| token.kind == TokenKind::Newline) {
+        if line
+            .iter()
+            .all(|token| matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+        {
+            continue;
+        }
+        if closed {
+            return None;
+        }
+        let line: Vec<_> = line
+            .iter()
+            .map(|&token| {
+                Token::new(
+                    token.kind,
+                    Span::new(span.lo() + token.span.lo(), span.lo() + token.span.hi()),
+                )
+            })
+            .collect();
+
+        let name = match map.directive(&line) {
+            Some((name, _)) => name,
+            None if depth > 0 => continue,
+            None => return None,
+        };
+        match name.map(|name| map.spelling(name)).as_deref() {
+            Some("if" | "ifdef" | "ifndef") => {
+                if depth == 0 {
+                    first = name;
+                }
+                depth += 1;
+            }
+            Some("endif") => {
+                depth = depth.checked_sub(1)?;
+                closed = depth == 0;
+            }
+            _ if depth == 0 => return None,
+            _ => {}
+        }
+    }
+
+    first.filter(|_| closed)
+}
+
+/// Count the new-line characters in `bytes`.
+pub(super) fn count_lines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&byte| byte == b'\n').count()
+}
@@ -0,0 +1,109 @@
+//! Preprocessing of many translation units one after the other in a single session.
+use std::path::{Path, PathBuf};
+
+use super::{HeaderCache, Preprocessed, Preprocessor};
+use crate::{error::Error, span::SourceMap};
+
+/// A session that preprocesses many translation units one after the other using the same
+/// configuration, for tools that work on a whole project.
+///
+/// Every translation unit starts with only the predefined macros and the macros from the
+/// configuration defined, as if it were preprocessed on its own. However, the source code read,
+/// the interned spellings of the identifiers and the tokens of the files are shared by all of
+/// them, so the files included by many translation units are only read and tokenized once. The
+/// tokens are shared using the [`HeaderCache`] of the preprocessor or a new one if it does not
+/// have any.
+///
+/// Only the files read are kept by the session, so the other source code of a translation unit,
+/// like the results of the `#` and `##` operators, is dropped with its result.
+///
+/// Files are only read the first time they are needed, so changes made to them afterwards are
+/// not seen by the session until [`invalidate_stale`](Self::invalidate_stale) is called.
+///
+/// ```
+/// use std::path::PathBuf;
+///
+/// use beheader::{Emitter, MemoryFileSystem, Preprocessor, Session};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("a.c", "#include \"common.h\"\n#define A 1\nint a = A + COMMON;\n");
+/// files.insert("b.c", "#include \"common.h\"\nint b = A + COMMON;\n");
+/// files.insert("common.h", "#define COMMON 2\n");
+///
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.file_system(files);
+/// let mut session = Session::new(preprocessor);
+///
+/// let mut emitter = Emitter::new();
+/// emitter.line_markers(false);
+/// let a = session.preprocess_file(&"a.c").unwrap();
+/// assert_eq!(emitter.render(&a), b"int a = 1 + 2;\n");
+/// // The macros defined by `a.c` are not defined in `b.c`.
+/// let b = session.preprocess_file(&"b.c").unwrap();
+/// assert_eq!(emitter.render(&b), b"int b = A + 2;\n");
+/// assert_eq!(b.dependencies(), ["b.c", "common.h"].map(PathBuf::from));
+/// ```
+pub struct Session {
+    preprocessor: Preprocessor,
+    /// The files read so far by the translation units of the session.
+    map: SourceMap,
+}
+
+impl Session {
+    /// Create a new session that uses `preprocessor` for every translation unit.
+    pub fn new(mut preprocessor: Preprocessor) -> Self {
+        preprocessor
+            .header_cache
+            .get_or_insert_with(HeaderCache::default);
+        let map = preprocessor.source_map();
+        Self { preprocessor, map }
+    }
+
+    /// Preprocess a file as a new translation unit, like [`Preprocessor::preprocess_file`].
+    ///
+    /// Return an error if any of the files cannot be read or if the source code is not valid.
+    pub fn preprocess_file<P: AsRef<Path>>(&mut self, path: &P) -> Result<Preprocessed, Error> {
+        let result = self
+            .preprocessor
+            .preprocess_file_in(self.map.fork(), path.as_ref());
+        self.keep(result)
+    }
+
+    /// Preprocess `source` as a new translation unit, like the contents of a file named `name`
+    /// that is not a dependency of the result, as done by [`Preprocessor::preprocess_source`].
+    ///
+    /// Return an error if any of the files cannot be read or if the source code is not valid.
+    pub fn preprocess_source<P: AsRef<Path>>(
+        &mut self,
+        name: &P,
+        source: &[u8],
+    ) -> Result<Preprocessed, Error> {
+        let result = self
+            .preprocessor
+            .preprocess_reader_in(self.map.fork(), source, name.as_ref());
+        self.keep(result)
+    }
+
+    /// Forget the files read by the translation units of the session that changed since they
+    /// were read, and remove their contents from the [`HeaderCache`], so the next translation
+    /// units read them again. Return the paths of those files.
+    ///
+    /// The files are checked like in [`Preprocessed::stale_files`].
+    pub fn invalidate_stale(&mut self) -> Vec<PathBuf> {
+        let stale = self.map.stale_files();
+        for path in &stale {
+            self.map.invalidate(path);
+        }
+        stale
+    }
+
+    /// Keep the files read by the translation unit that produced `result`, so the next ones do
+    /// not read them again.
+    ///
+    /// Return an error if the files cannot be stored.
+    fn keep(&mut self, result: Result<Preprocessed, Error>) -> Result<Preprocessed, Error> {
+        let preprocessed = result?;
+        self.map.keep_files(&preprocessed.context.map)?;
+        Ok(preprocessed)
+    }
+}
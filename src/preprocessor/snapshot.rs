@@ -0,0 +1,265 @@
+//! Snapshots of the state of the preprocessor.
+use std::{error, fmt, str::FromStr};
+
+use super::{Conditional, Context, Preprocessed};
+use crate::{
+    buffer::TokenBuffer,
+    error::Error,
+    lexer::{Lines, TokenKind},
+};
+
+/// The state of the preprocessor at some point of a preprocessing run: the macros defined, the
+/// next value of `__COUNTER__` and the conditional inclusion directives whose `#endif` was not
+/// found yet. A snapshot can be used as the initial state of another run using
+/// [`Preprocessor::restore`](super::Preprocessor::restore), which is useful to avoid processing
+/// the same prefix of includes many times.
+///
+/// Snapshots are taken at the end of the input using [`Preprocessed::snapshot`]. To take one at
+/// another point, the input before it is preprocessed using
+/// [`Preprocessor::preprocess_prefix`](super::Preprocessor::preprocess_prefix) and the rest of
+/// the input is preprocessed after restoring the snapshot.
+///
+/// Snapshots can be converted to and from a textual representation using their [`Display`] and
+/// [`FromStr`] implementations. This representation has one line per macro, where each line
+/// starts with either `builtin` or `defined` followed by a space and the `#define` directive of
+/// the macro, followed by a line with `counter` and the next value of `__COUNTER__` if it is
+/// replaced and one line per conditional inclusion directive, from the outermost to the
+/// innermost. These lines start with `conditional` followed by the state of the current group of
+/// the directive, which is `active` if it is processed, `taken` if it is skipped because a
+/// previous group was processed and `skipped` otherwise, and the last directive of the
+/// conditional, like `#ifdef A` or `#else`.
+///
+/// ```
+/// use beheader::{Emitter, Preprocessor};
+///
+/// let prefix = b"#define A 1\n#ifdef A\n";
+/// let rest = b"a = A;\n#else\nb = A;\n#endif\n";
+/// let preprocessor = Preprocessor::new();
+/// let snapshot = preprocessor.preprocess_prefix(prefix).unwrap().snapshot();
+/// assert_eq!(snapshot.to_string().lines().last(), Some("conditional active #ifdef A"));
+///
+/// let mut resumed = preprocessor.clone();
+/// resumed.restore(snapshot);
+/// let preprocessed = resumed.preprocess(rest).unwrap();
+/// let text = Emitter::new().line_markers(false).render(&preprocessed);
+/// assert_eq!(text, b"a = 1;\n\n");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The `#define` directives of the predefined macros.
+    builtin: Vec<String>,
+    /// The `#define` directives of the other macros.
+    defined: Vec<String>,
+    /// The next value of `__COUNTER__`, if it is replaced.
+    counter: Option<usize>,
+    /// The conditional inclusion directives whose `#endif` was not found yet, from the outermost
+    /// to the innermost.
+    conditionals: Vec<OpenConditional>,
+}
+
+/// A conditional inclusion directive of a [`Snapshot`] whose `#endif` was not found yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct OpenConditional {
+    /// The state of the current group of the directive.
+    state: GroupState,
+    /// The last directive of the conditional, like `#ifdef A` or `#else`.
+    directive: String,
+}
+
+/// The state of the current group of an [`OpenConditional`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupState {
+    /// The group is processed.
+    Active,
+    /// The group is skipped because a previous group of the directive was processed.
+    Taken,
+    /// The group is skipped and no previous group was processed.
+    Skipped,
+}
+
+impl GroupState {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Taken => "taken",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// The names of the directives that can be the last directive of an [`OpenConditional`].
+const CONDITIONAL_DIRECTIVES: &[&str] = &[
+    "if", "ifdef", "ifndef", "elif", "elifdef", "elifndef", "else",
+];
+
+impl Snapshot {
+    /// Restore the state in the snapshot inside `context`. Return an error if any of the
+    /// directives is not valid or if they cannot be stored.
+    pub(super) fn restore_into(&self, context: &mut Context) -> Result<(), Error> {
+        for (name, directives) in [("<built-in>", &self.builtin), ("<snapshot>", &self.defined)] {
+            let mut source = String::new();
+            for directive in directives {
+                source.push_str(directive);
+                source.push('\n');
+            }
+            let span = context.map.store_virtual_file(name, source.as_bytes())?;
+            context.process_into(span, &mut TokenBuffer::default())?;
+        }
+        if let Some(counter) = self.counter {
+            context.macros.set_counter(counter);
+        }
+
+        // The directives of the conditionals are stored so they can be referred to when the
+        // conditionals are terminated in the input.
+        let mut source = String::new();
+        for conditional in &self.conditionals {
+            source.push_str(&conditional.directive);
+            source.push('\n');
+        }
+        let span = context
+            .map
+            .store_virtual_file("<snapshot>", source.as_bytes())?;
+        let mut lines = Lines::new(span);
+        let mut line = Vec::new();
+        for conditional in &self.conditionals {
+            lines.next_line(&context.map, &mut line)?;
+            let (name, body) = match context.map.directive(&line) {
+                Some((Some(name), body)) => (name, body),
+                _ => continue,
+            };
+            let body = match body.split_last() {
+                Some((last, body)) if last.kind == TokenKind::Newline => body,
+                _ => body,
+            };
+            let enclosing = context.is_active();
+            let active = enclosing && conditional.state == GroupState::Active;
+            let section = context.record_branch(None, name, body, None, active);
+            context.conditionals.push(Conditional {
+                enclosing,
+                active,
+                taken: conditional.state != GroupState::Skipped,
+                has_else: context.map.spelling(name) == "else",
+                section,
+            });
+        }
+        context.resumed = self.conditionals.len();
+        Ok(())
+    }
+}
+
+impl Preprocessed {
+    /// Take a snapshot of the state at the end of the input.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut snapshot = Snapshot::default();
+        for definition in self.macros() {
+            if definition.is_builtin() {
+                snapshot.builtin.push(definition.to_string());
+            } else {
+                snapshot.defined.push(definition.to_string());
+            }
+        }
+        snapshot.counter = self.context.macros.counter();
+        for conditional in &self.context.conditionals {
+            let state = if conditional.active {
+                GroupState::Active
+            } else if conditional.taken {
+                GroupState::Taken
+            } else {
+                GroupState::Skipped
+            };
+            let record = self.context.sections[conditional.section].last().unwrap();
+            let mut directive = format!("#{}", self.context.map.spelling(record.directive()));
+            if !record.condition().is_empty() {
+                directive.push(' ');
+                directive.push_str(record.condition());
+            }
+            snapshot
+                .conditionals
+                .push(OpenConditional { state, directive });
+        }
+        snapshot
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for directive in &self.builtin {
+            writeln!(f, "builtin {}", directive)?;
+        }
+        for directive in &self.defined {
+            writeln!(f, "defined {}", directive)?;
+        }
+        if let Some(counter) = self.counter {
+            writeln!(f, "counter {}", counter)?;
+        }
+        for conditional in &self.conditionals {
+            let state = conditional.state.as_str();
+            writeln!(f, "conditional {} {}", state, conditional.directive)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = ParseSnapshotError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut snapshot = Snapshot::default();
+        for (index, line) in s.lines().enumerate() {
+            let error = ParseSnapshotError { line: index + 1 };
+            let (kind, rest) = line.split_once(' ').ok_or(error.clone())?;
+            match kind {
+                "builtin" | "defined" if rest.starts_with("#define ") => {
+                    let directives = match kind {
+                        "builtin" => &mut snapshot.builtin,
+                        _ => &mut snapshot.defined,
+                    };
+                    directives.push(rest.to_owned());
+                }
+                "counter" => snapshot.counter = Some(rest.parse().map_err(|_| error.clone())?),
+                "conditional" => {
+                    let (state, directive) = rest.split_once(' ').ok_or(error.clone())?;
+                    let state = match state {
+                        "active" => GroupState::Active,
+                        "taken" => GroupState::Taken,
+                        "skipped" => GroupState::Skipped,
+                        _ => return Err(error),
+                    };
+                    let name = directive
+                        .strip_prefix('#')
+                        .and_then(|directive| directive.split(' ').next());
+                    if !name.is_some_and(|name| CONDITIONAL_DIRECTIVES.contains(&name)) {
+                        return Err(error);
+                    }
+                    snapshot.conditionals.push(OpenConditional {
+                        state,
+                        directive: directive.to_owned(),
+                    });
+                }
+                _ => return Err(error),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+/// The error returned when parsing an invalid [`Snapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseSnapshotError {
+    line: usize,
+}
+
+impl ParseSnapshotError {
+    /// The line of the invalid snapshot where the error was found, starting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for ParseSnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid snapshot entry at line {}", self.line)
+    }
+}
+
+impl error::Error for ParseSnapshotError {}
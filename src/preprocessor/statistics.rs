@@ -0,0 +1,126 @@
+//! The statistics collected while preprocessing.
+use std::{fmt, time::Duration};
+
+/// What was done during a preprocessing run and how long it took, which can be used to find
+/// where the preprocessing time goes.
+///
+/// The time spent in each phase does not include the time spent in the others, but
+/// [`total`](Self::total) includes everything, like processing directives or waiting for a
+/// stream to be written.
+///
+/// ```
+/// let preprocessed = beheader::preprocess(b"#define ONE 1\n#if 0\n#endif\nONE + ONE\n").unwrap();
+/// let statistics = preprocessed.statistics();
+/// assert_eq!(statistics.macros_expanded(), 2);
+/// assert_eq!(statistics.branches_skipped(), 1);
+/// println!("{}", statistics);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Statistics {
+    pub(super) files_read: usize,
+    pub(super) bytes_lexed: usize,
+    pub(super) tokens: usize,
+    pub(super) macros_defined: usize,
+    pub(super) macros_expanded: usize,
+    pub(super) branches_skipped: usize,
+    pub(super) cache_hits: usize,
+    pub(super) lexing: Duration,
+    pub(super) expansion: Duration,
+    pub(super) includes: Duration,
+    pub(super) total: Duration,
+}
+
+impl Statistics {
+    /// The number of files read from the file system, including the ones that were only read
+    /// to find out they must not be included again.
+    pub fn files_read(&self) -> usize {
+        self.files_read
+    }
+
+    /// The number of bytes of source code tokenized. The groups skipped by conditional
+    /// inclusion are not tokenized and neither are the files whose tokens were taken from a
+    /// cache.
+    pub fn bytes_lexed(&self) -> usize {
+        self.bytes_lexed
+    }
+
+    /// The number of tokens of the output, including white-space.
+    pub fn tokens(&self) -> usize {
+        self.tokens
+    }
+
+    /// The number of `#define` directives processed, including the ones of the predefined
+    /// macros.
+    pub fn macros_defined(&self) -> usize {
+        self.macros_defined
+    }
+
+    /// The number of macro invocations replaced, including the ones inside other replacements
+    /// and inside the conditions of conditional inclusion directives.
+    pub fn macros_expanded(&self) -> usize {
+        self.macros_expanded
+    }
+
+    /// The number of groups of conditional inclusion directives that were skipped, including
+    /// the ones nested inside skipped groups.
+    pub fn branches_skipped(&self) -> usize {
+        self.branches_skipped
+    }
+
+    /// The number of files whose tokens were taken from a
+    /// [`HeaderCache`](super::HeaderCache), a token cache or an earlier inclusion of the same
+    /// file instead of tokenizing them.
+    pub fn cache_hits(&self) -> usize {
+        self.cache_hits
+    }
+
+    /// The time spent tokenizing the source code, including reading the token caches and
+    /// skipping groups of conditional inclusion directives.
+    pub fn lexing(&self) -> Duration {
+        self.lexing
+    }
+
+    /// The time spent replacing macros and evaluating the conditions of conditional inclusion
+    /// directives.
+    pub fn expansion(&self) -> Duration {
+        self.expansion
+    }
+
+    /// The time spent finding and reading included files.
+    pub fn includes(&self) -> Duration {
+        self.includes
+    }
+
+    /// The time spent in the whole run, from the processing of the predefined macros to the
+    /// end of the input.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let counts = [
+            ("files read", self.files_read),
+            ("bytes lexed", self.bytes_lexed),
+            ("tokens", self.tokens),
+            ("macros defined", self.macros_defined),
+            ("macros expanded", self.macros_expanded),
+            ("branches skipped", self.branches_skipped),
+            ("cache hits", self.cache_hits),
+        ];
+        for (name, count) in counts {
+            writeln!(f, "{:<18}{:>12}", name, count)?;
+        }
+        let times = [
+            ("lexing", self.lexing),
+            ("expansion", self.expansion),
+            ("includes", self.includes),
+            ("total", self.total),
+        ];
+        for (name, time) in times {
+            writeln!(f, "{:<18}{:>12}", name, format!("{:.3?}", time))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+//! The concatenation of adjacent string literals (translation phase 6, section 5.1.1.2p1 of C17).
+use super::Context;
+use crate::{
+    buffer::TokenBuffer,
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    lexer::{Token, TokenKind},
+};
+
+/// An escape sequence at the end of the contents of a string literal that would take more
+/// characters if they were added after it.
+#[derive(Clone, Copy)]
+enum OpenEscape {
+    /// A hexadecimal escape sequence, which takes every hexadecimal digit after `\x`.
+    Hex,
+    /// An octal escape sequence with less than three digits.
+    Octal,
+}
+
+impl Context {
+    /// Concatenate the adjacent string literals of `output`, removing the white-space between
+    /// them, and update the tokens of the files being written accordingly.
+    ///
+    /// Return an error if a concatenated string literal cannot be stored, the literals after it
+    /// are kept as they are.
+    pub(super) fn concatenate_strings(
+        &mut self,
+        output: &mut TokenBuffer,
+    ) -> Result<(), Diagnostic> {
+        let tokens = output.take();
+        let mut error = None;
+        // The index of each token after the concatenation, which is the index of the next token
+        // that is kept for the removed ones.
+        let mut indices = Vec::with_capacity(tokens.len() + 1);
+        let mut i = 0;
+        while i < tokens.len() {
+            // The string literal starting at `i` and the ones after it that are only separated
+            // by white-space.
+            let mut group = vec![i];
+            let mut next = i + 1;
+            while tokens[*group.last().unwrap()].kind == TokenKind::Str {
+                while tokens.get(next).is_some_and(|token| {
+                    matches!(token.kind, TokenKind::Space | TokenKind::Newline)
+                }) {
+                    next += 1;
+                }
+                match tokens.get(next) {
+                    Some(token) if token.kind == TokenKind::Str => group.push(next),
+                    _ => break,
+                }
+                next += 1;
+            }
+
+            let last = *group.last().unwrap();
+            let literals: Vec<Token> = group.iter().map(|&index| tokens[index]).collect();
+            let concatenated = match literals.len() {
+                1 => None,
+                _ if error.is_some() => None,
+                _ => self.concatenate(&literals).unwrap_or_else(|diagnostic| {
+                    error = Some(diagnostic);
+                    None
+                }),
+            };
+            match concatenated {
+                Some(token) => {
+                    indices.push(output.tokens().len());
+                    output.push(token);
+                    indices.extend(std::iter::repeat_n(output.tokens().len(), last - i));
+                }
+                None => {
+                    for &token in &tokens[i..=last] {
+                        indices.push(output.tokens().len());
+                        output.push(token);
+                    }
+                }
+            }
+            i = last + 1;
+        }
+        indices.push(output.tokens().len());
+        self.move_output(&indices);
+        self.move_expanded(&indices);
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Concatenate `literals` into a single string literal. Return `None` if they have different
+    /// encoding prefixes, which is diagnosed, and an error if the result cannot be stored.
+    fn concatenate(&mut self, literals: &[Token]) -> Result<Option<Token>, Diagnostic> {
+        let mut prefix = Vec::new();
+        // The first literal with a prefix, which gives the prefix of the result.
+        let mut prefixed = None;
+        let mut contents = Vec::new();
+        let mut open = None;
+        for &literal in literals {
+            let spelling = self.map.get_bytes(literal.span).to_vec();
+            let quote = spelling.iter().position(|&byte| byte == b'"').unwrap();
+            let (literal_prefix, rest) = spelling.split_at(quote);
+            // A string literal without prefix takes the prefix of the others (section 6.4.5p5
+            // of C17). Whether literals with different prefixes can be concatenated is
+            // implementation-defined and they are not supported.
+            if !literal_prefix.is_empty() && !prefix.is_empty() && literal_prefix != prefix {
+                let diagnostic = self.map.diagnostic(
+                    Severity::Error,
+                    DiagnosticCode::StringPrefixMismatch,
+                    literal.span,
+                    format!(
+                        "Cannot concatenate string literals with the encoding prefixes \"{}\" \
+                         and \"{}\"",
+                        String::from_utf8_lossy(&prefix),
+                        String::from_utf8_lossy(literal_prefix)
+                    ),
+                );
+                let label = format!(
+                    "The prefix \"{}\" is used here",
+                    String::from_utf8_lossy(&prefix)
+                );
+                let label = self.map.label(prefixed.unwrap_or(literal.span), label);
+                self.diagnostics.push(diagnostic.with_label(label));
+                return Ok(None);
+            }
+            if prefix.is_empty() && !literal_prefix.is_empty() {
+                prefix = literal_prefix.to_vec();
+                prefixed = Some(literal.span);
+            }
+
+            let text = &rest[1..rest.len() - 1];
+            // Escape sequences are replaced before concatenating, so a character that would
+            // continue an escape sequence at the end of the previous literal is escaped too.
+            match (open, text.first()) {
+                (Some(OpenEscape::Hex), Some(&first)) if first.is_ascii_hexdigit() => {
+                    contents.extend_from_slice(format!("\\{:03o}", first).as_bytes());
+                    contents.extend_from_slice(&text[1..]);
+                }
+                (Some(OpenEscape::Octal), Some(&first)) if (b'0'..=b'7').contains(&first) => {
+                    contents.extend_from_slice(format!("\\{:03o}", first).as_bytes());
+                    contents.extend_from_slice(&text[1..]);
+                }
+                _ => contents.extend_from_slice(text),
+            }
+            if !text.is_empty() {
+                open = open_escape(&contents);
+            }
+        }
+
+        let mut spelling = prefix;
+        spelling.push(b'"');
+        spelling.extend_from_slice(&contents);
+        spelling.push(b'"');
+        let span = self.map.store_at(&spelling, literals[0].span)?;
+        Ok(Some(Token::new(TokenKind::Str, span)))
+    }
+}
+
+/// Find the escape sequence at the end of `contents`, the characters of a string literal, that
+/// would take more characters if they were added after it.
+fn open_escape(contents: &[u8]) -> Option<OpenEscape> {
+    let mut i = 0;
+    while i < contents.len() {
+        if contents[i] != b'\\' {
+            i += 1;
+            continue;
+        }
+        match contents.get(i + 1) {
+            Some(b'x') => {
+                i += 2;
+                while contents.get(i).is_some_and(u8::is_ascii_hexdigit) {
+                    i += 1;
+                }
+                if i == contents.len() {
+                    return Some(OpenEscape::Hex);
+                }
+            }
+            Some(b'0'..=b'7') => {
+                let digits = contents[i + 1..]
+                    .iter()
+                    .take(3)
+                    .take_while(|digit| (b'0'..=b'7').contains(*digit))
+                    .count();
+                i += 1 + digits;
+                if i == contents.len() && digits < 3 {
+                    return Some(OpenEscape::Octal);
+                }
+            }
+            _ => i += 2,
+        }
+    }
+    None
+}
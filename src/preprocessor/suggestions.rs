@@ -0,0 +1,45 @@
+//! The [`Suggestion`](crate::Suggestion)s added to diagnostics whose fix is obvious.
+use super::Context;
+use crate::{
+    diagnostic::{DiagnosticCode, Suggestion},
+    lexer::{Token, TokenKind},
+    span::Span,
+};
+
+impl Context {
+    /// Create a suggestion to terminate the `count` conditional inclusion directives left open
+    /// at the end of the region `span`, which must not be empty.
+    pub(super) fn endif_suggestion(&self, span: Span, count: usize) -> Suggestion {
+        // The directives are added after the last byte so they start in a new line.
+        let last = Span::new(span.hi() - 1, span.hi());
+        let mut replacement = String::from_utf8_lossy(&self.map.get_bytes(last)).into_owned();
+        if replacement != "\n" {
+            replacement.push('\n');
+        }
+        replacement.push_str(&"#endif\n".repeat(count));
+        self.map
+            .suggestion(last, "Add \"#endif\" at the end of the file", replacement)
+    }
+
+    /// Suggest replacing the `#if` directive named by the token `name`, whose tokens after the
+    /// name are `body`, with an `#ifdef` directive when its condition is a single identifier that
+    /// is not a macro, which is usually a mistake. Only the diagnostics reported after the first
+    /// `reported` ones are changed.
+    pub(super) fn suggest_ifdef(&mut self, name: Token, body: &[Token], reported: usize) {
+        let mut tokens = body.iter().filter(|token| token.kind != TokenKind::Space);
+        if !matches!((tokens.next(), tokens.next()), (Some(token), None) if token.kind == TokenKind::Ident)
+        {
+            return;
+        }
+        let suggestion = self
+            .map
+            .suggestion(name.span, "Did you mean \"#ifdef\"?", "ifdef");
+        self.diagnostics.update_since(reported, |diagnostic| {
+            if diagnostic.code() == DiagnosticCode::UndefinedIdentifier {
+                diagnostic.with_suggestion(suggestion.clone())
+            } else {
+                diagnostic
+            }
+        });
+    }
+}
@@ -0,0 +1,4333 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use super::token_cache::FileInfo;
+use super::{
+    Batch, Comments, Depfile, Emitter, HeaderCache, IncludeKind, IncludeResolution, MacroConstant,
+    MacroDump, OutputProfile, Preprocessed, Preprocessor, Session, Snapshot, TokenTable,
+};
+use crate::lexer::{Lines, TokenKind};
+use crate::span::SourceMap;
+use crate::symbol::Symbol;
+use crate::{
+    CTokenKind, ColumnUnit, ContentHash, Delimiter, Diagnostic, DiagnosticCode, DiagnosticGroup,
+    DiagnosticRenderer, Error, Event, Extension, FeatureMacro, FloatingSize, InputCharset,
+    IntegerSize, IntmaxWidth, Keyword, Level, MemoryFileSystem, RealFileSystem, Severity,
+    SourceMapSnapshot, Spacing, Span, Standard, Target, TokenTree, Warning, WarningGroup,
+};
+
+/// Write `contents` into a file named `name` inside a temporary directory and return its path.
+fn temp_file(name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("beheader-tests");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Preprocess `source` and check that the spelling of the output is `expected`. Each white-space
+/// token is spelled as a single space.
+#[track_caller]
+fn preprocess_eq(preprocessor: &Preprocessor, source: &str, expected: &str) {
+    let context = preprocessor.preprocess(source.as_bytes()).unwrap().context;
+
+    let mut output = String::new();
+    for token in context.output.tokens() {
+        match token.kind {
+            TokenKind::Space => output.push(' '),
+            _ => output.push_str(&context.map.spelling(*token)),
+        }
+    }
+
+    assert_eq!(expected, output);
+}
+
+/// Preprocess `source` and return the diagnostic of the error that stopped the preprocessing.
+#[track_caller]
+fn preprocess_err(preprocessor: &Preprocessor, source: &str) -> Diagnostic {
+    match preprocessor.preprocess(source.as_bytes()) {
+        Err(Error::Source { diagnostic, .. }) => diagnostic,
+        Err(error) => panic!("Unexpected error: {}", error),
+        Ok(_) => panic!("Preprocessing {:?} did not fail", source),
+    }
+}
+
+/// Preprocess `source` and return the first error reported, checking that it did not stop the
+/// preprocessing.
+#[track_caller]
+fn preprocess_error(preprocessor: &Preprocessor, source: &str) -> Diagnostic {
+    match preprocessor.preprocess(source.as_bytes()) {
+        Ok(preprocessed) => preprocessed
+            .diagnostics()
+            .iter()
+            .find(|diagnostic| diagnostic.severity() == Severity::Error)
+            .unwrap_or_else(|| panic!("Preprocessing {:?} reported no errors", source))
+            .clone(),
+        Err(error) => panic!("Unexpected error: {}", error),
+    }
+}
+
+#[test]
+fn object_like() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define FOO 1 + 2\nFOO;\n",
+        "\n1 + 2;\n",
+    );
+}
+
+#[test]
+fn function_like() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define max(a, b) ((a) > (b) ? (a) : (b))\nmax(x, f(y, z))",
+        "\n((x) > (f(y, z)) ? (x) : (f(y, z)))",
+    );
+}
+
+#[test]
+fn function_like_without_parens() {
+    preprocess_eq(&Preprocessor::new(), "#define f(a) a\nf + f (1)", "\nf + 1");
+}
+
+#[test]
+fn recursive() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define foo foo bar\n#define bar foo\nfoo",
+        "\n\nfoo foo",
+    );
+}
+
+#[test]
+fn stringize() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define str(s) # s\nstr( a  \"\\n\"   b )",
+        "\n\"a \\\"\\\\n\\\" b\"",
+    );
+}
+
+#[test]
+fn paste() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define cat(a, b) a ## b\ncat(x, 1) cat(, y) cat(,)",
+        "\nx1 y ",
+    );
+}
+
+#[test]
+fn variadic() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define call(f, ...) f(__VA_ARGS__)\ncall(g, 1, 2) call(h)",
+        "\ng(1, 2) h()",
+    );
+}
+
+/// The example of section 6.10.5.1 of C23 and the operators applied to `__VA_OPT__`.
+#[test]
+fn va_opt() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define F(...) f(0 __VA_OPT__(,) __VA_ARGS__)\n\
+         #define G(X, ...) f(0, X __VA_OPT__(,) __VA_ARGS__)\n\
+         #define SDEF(sname, ...) S sname __VA_OPT__(= { __VA_ARGS__ })\n\
+         #define EMP\n\
+         F(a, b, c) F() F(EMP) G(a, b, c) G(a, ) G(a) SDEF(foo); SDEF(bar, 1, 2);",
+        "\n\n\n\nf(0 , a, b, c) f(0  ) f(0  ) f(0, a , b, c) f(0, a  ) f(0, a  ) S foo ; \
+         S bar = { 1, 2 };",
+    );
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define S(...) #__VA_OPT__(a  __VA_ARGS__)\n\
+         #define P(x, ...) x ## __VA_OPT__(y x) ## z\n\
+         S() S(1) P(1) P(1, 2)",
+        "\n\n\"\" \"a 1\" 1z 1y 1z",
+    );
+    // The variable arguments are only replaced once.
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.extension(Extension::Counter, true);
+    preprocess_eq(
+        &preprocessor,
+        "#define F(...) __VA_OPT__(x) __VA_ARGS__\nF(__COUNTER__) F(__COUNTER__)",
+        "\nx 0 x 1",
+    );
+    let error = preprocess_error(&Preprocessor::new(), "#define F(...) __VA_OPT__(a\n");
+    assert_eq!(error.code(), DiagnosticCode::InvalidVaOpt);
+    let error = preprocess_error(&Preprocessor::new(), "#define F(...) __VA_OPT__(## a)\n");
+    assert_eq!(error.code(), DiagnosticCode::PasteAtEdge);
+}
+
+#[test]
+fn has_include() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("include/found.h", "");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .include_dir("include")
+        .standard(Standard::C23);
+    preprocess_eq(
+        &preprocessor,
+        "#define HEADER \"found.h\"\n\
+         #if __has_include(<found.h>) && __has_include(HEADER) && !__has_include(\"missing.h\")\n\
+         found\n\
+         #endif\n\
+         #ifdef __has_include\nhas_include\n#endif\n",
+        "\n\nfound\n\n\nhas_include\n\n",
+    );
+    let error = preprocess_error(&preprocessor, "#if __has_include(found.h)\n#endif\n");
+    assert_eq!(error.code(), DiagnosticCode::InvalidHasInclude);
+    let error = preprocess_error(&preprocessor, "#if __has_include\n#endif\n");
+    assert_eq!(error.code(), DiagnosticCode::InvalidHasInclude);
+
+    // It is available in every GNU dialect, but not in ISO C before C23.
+    preprocessor.standard(Standard::Gnu89);
+    preprocess_eq(
+        &preprocessor,
+        "__has_include\n#ifdef __has_include\na\n#endif",
+        "__has_include\n\na\n",
+    );
+    preprocessor.standard(Standard::C17);
+    preprocess_eq(&preprocessor, "#ifdef __has_include\na\n#endif", "\n\n");
+}
+
+#[test]
+fn pragma_operator() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define DO_PRAGMA(x) _Pragma(#x) x\n\
+         a _Pragma(\"message(\\\"a\\\")\") b\n\
+         _Pragma ( L\"pack()\" )\n\
+         DO_PRAGMA(weak c)\n",
+        "\na\n#pragma message(\"a\")\n b\n#pragma pack()\n\n#pragma weak c\n weak c\n",
+    );
+    let error = preprocess_error(&Preprocessor::new(), "_Pragma(1)\n");
+    assert_eq!(error.code(), DiagnosticCode::InvalidPragmaOperator);
+
+    // GCC goes back to the line of the operator after the directive.
+    let preprocessed = Preprocessor::new()
+        .preprocess(b"a _Pragma(\"foo\") b\nc\n")
+        .unwrap();
+    let text = Emitter::new()
+        .profile(OutputProfile::Gcc)
+        .render(&preprocessed);
+    let expected = "a\n# 1 \"<stdin>\"\n#pragma foo\n# 1 \"<stdin>\"\n b\nc\n";
+    assert!(String::from_utf8(text).unwrap().ends_with(expected));
+
+    // The warnings are changed when the operator is found.
+    let source = "_Pragma(\"GCC diagnostic ignored \\\"-Wunused-macros\\\"\")\n\
+        #define UNUSED\n";
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.warning(Warning::UnusedMacros, true);
+    let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+    assert!(preprocessed.diagnostics().is_empty());
+
+    // The operator was added by C99.
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.standard(Standard::C89);
+    preprocess_eq(&preprocessor, "_Pragma(\"once\")", "_Pragma(\"once\")");
+}
+
+/// Example 3 of section 6.10.3.5 of C17, without the parts that require `#undef` after use.
+#[test]
+fn standard_example() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define x 3\n#define f(a) f(x * (a))\n#undef x\n#define x 2\n#define g f\n#define z z[0]\n\
+         #define h g(~\n#define m(a) a(w)\n#define w 0,1\n\
+         f(y+1) + f(f(z)) % t(t(g)(0) + t)(1);\ng(x+(3,4)-w) | h 5) & m\n(f)^m(m);",
+        "\n\n\n\n\n\n\n\n\nf(2 * (y+1)) + f(2 * (f(2 * (z[0])))) % t(t(f)(0) + t)(1);\n\
+         f(2 * (2+(3,4)-0,1)) | f(2 * (~ 5)) & f(2 * (0,1))^m(0,1);",
+    );
+}
+
+#[test]
+fn command_line() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .define("FOO")
+        .define("BAR=FOO + 1")
+        .define("BAZ(x)=x")
+        .define("QUX")
+        .undefine("QUX");
+
+    preprocess_eq(&preprocessor, "FOO BAR BAZ(2) QUX", "1 1 + 1 2 QUX");
+}
+
+#[test]
+fn command_line_location() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("FOO(a, b)=a");
+
+    let diagnostic = preprocess_error(&preprocessor, "FOO(1)");
+    assert_eq!(DiagnosticCode::ArgumentCount, diagnostic.code());
+    assert_eq!(
+        "Macro \"FOO\" requires 2 arguments, but 1 were given",
+        diagnostic.message()
+    );
+    let label = &diagnostic.labels()[0];
+    assert_eq!("Macro \"FOO\" is defined here", label.message());
+    assert_eq!(Some(&PathBuf::from("<command-line>")), label.file());
+    assert_eq!(Some(1), label.line());
+}
+
+#[test]
+fn pre_include() {
+    let config = temp_file("pre_include.h", "#define CONFIG 1\nint config;\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.include(&config);
+
+    preprocess_eq(&preprocessor, "CONFIG", "\nint config;\n1");
+}
+
+#[test]
+fn pre_include_source() {
+    let config = temp_file("pre_include_source.h", "#undef CONFIG\n#define CONFIG 2\n");
+    let mut preprocessor = Preprocessor::new();
+    // Files and sources are included in the order they were given.
+    preprocessor
+        .include_source(
+            "<config>",
+            "#define CONFIG 1\nint config;\n#define FOO(a, b) a\n",
+        )
+        .include(&config);
+
+    preprocess_eq(&preprocessor, "CONFIG", "\nint config;\n\n\n\n2");
+    let preprocessed = preprocessor.preprocess(b"").unwrap();
+    assert_eq!(preprocessed.dependencies(), [config]);
+    let diagnostic = preprocess_error(&preprocessor, "FOO(1)");
+    assert_eq!(DiagnosticCode::ArgumentCount, diagnostic.code());
+    let label = &diagnostic.labels()[0];
+    assert_eq!(Some(&PathBuf::from("<config>")), label.file());
+    assert_eq!(Some(3), label.line());
+}
+
+#[test]
+fn pre_include_macros() {
+    let config = temp_file("pre_include_macros.h", "#define CONFIG 1\nint config;\n");
+    let other = temp_file("pre_include_macros_other.h", "#undef CONFIG\n");
+    let mut preprocessor = Preprocessor::new();
+    // Macro files are processed first even if they were given last.
+    preprocessor.include(&other).include_macros(&config);
+
+    preprocess_eq(&preprocessor, "CONFIG", "\nCONFIG");
+}
+
+#[test]
+fn target() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.target(Target::from_triple("x86_64-pc-windows-msvc").unwrap());
+
+    preprocess_eq(
+        &preprocessor,
+        "_WIN32 __linux__ __SIZEOF_LONG__",
+        "1 __linux__ 4",
+    );
+}
+
+#[test]
+fn target_custom() {
+    let mut target = Target::from_triple("x86_64-unknown-linux-gnu").unwrap();
+    target.define("__SIZEOF_LONG__", "16").undefine("__linux__");
+    let mut preprocessor = Preprocessor::new();
+    // Command-line definitions take precedence over the target ones.
+    preprocessor.target(target).undefine("__unix__");
+
+    preprocess_eq(
+        &preprocessor,
+        "__linux__ __unix__ __SIZEOF_LONG__ __BYTE_ORDER__",
+        "__linux__ __unix__ 16 1234",
+    );
+}
+
+#[test]
+fn feature_macros() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .feature_macro(FeatureMacro::NoAtomics, true)
+        .feature_macro(FeatureMacro::Utf16, false);
+
+    preprocess_eq(
+        &preprocessor,
+        "__STDC__ __STDC_VERSION__ __STDC_NO_ATOMICS__ __STDC_UTF_16__ __STDC_UTF_32__",
+        "1 201710L 1 __STDC_UTF_16__ 1",
+    );
+}
+
+#[test]
+fn standard_macros_only() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .target(Target::x86_64_linux_gnu())
+        .standard_macros_only(true)
+        .define("__linux__=2");
+
+    preprocess_eq(
+        &preprocessor,
+        "__STDC_HOSTED__ __x86_64__ __linux__",
+        "1 __x86_64__ 2",
+    );
+}
+
+#[test]
+fn macro_query() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("FOO=1");
+    let source = temp_file(
+        "macro_query.c",
+        "\n#define BAR(a, ...) a  +  __VA_ARGS__\n#undef FOO\n",
+    );
+    let preprocessed = preprocessor.preprocess_file(&source).unwrap();
+
+    assert!(preprocessed.get_macro("FOO").is_none());
+
+    let bar = preprocessed.get_macro("BAR").unwrap();
+    assert_eq!("BAR", bar.name());
+    assert!(bar.is_function_like());
+    assert!(bar.is_variadic());
+    assert!(!bar.is_builtin());
+    assert_eq!(Some(&["a".to_owned()][..]), bar.params());
+    assert_eq!(
+        vec!["a", " ", "+", " ", "__VA_ARGS__"],
+        bar.replacement_tokens()
+    );
+    assert_eq!(Some(2), bar.line());
+
+    let stdc = preprocessed.get_macro("__STDC__").unwrap();
+    assert!(stdc.is_builtin());
+    assert!(!stdc.is_function_like());
+    assert_eq!(None, stdc.params());
+    assert_eq!("1", stdc.replacement());
+
+    let names: Vec<_> = preprocessed
+        .macros()
+        .map(|definition| definition.name())
+        .collect();
+    assert_eq!(
+        vec![
+            "BAR",
+            "__STDC_HOSTED__",
+            "__STDC_UTF_16__",
+            "__STDC_UTF_32__",
+            "__STDC_VERSION__",
+            "__STDC__"
+        ],
+        names
+    );
+}
+
+#[test]
+fn dump_final() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .standard_macros_only(true)
+        .feature_macro(FeatureMacro::Utf16, false)
+        .feature_macro(FeatureMacro::Utf32, false)
+        .dump_macros(MacroDump::Final);
+
+    preprocess_eq(
+        &preprocessor,
+        "#define FOO(a, ...)  a\n#define BAR\nFOO(1)",
+        "#define BAR\n#define FOO(a, ...) a\n#define __STDC_HOSTED__ 1\n\
+         #define __STDC_VERSION__ 201710L\n#define __STDC__ 1\n",
+    );
+}
+
+#[test]
+fn dump_directives() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.dump_macros(MacroDump::Directives);
+
+    preprocess_eq(
+        &preprocessor,
+        "#define FOO(a) a\nFOO(1)\n# undef FOO\n",
+        "#define FOO(a) a\n1\n# undef FOO\n",
+    );
+}
+
+#[test]
+fn dump_names() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.dump_macros(MacroDump::Names);
+
+    preprocess_eq(
+        &preprocessor,
+        "#define FOO(a) a\nFOO(1)\n#undef FOO\n",
+        "#define FOO\n1\n#undef FOO\n",
+    );
+}
+
+#[test]
+fn snapshot_restore() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("FOO(x)=x + 1");
+    let prefix = preprocessor
+        .preprocess(b"#define BAR FOO(2)\n#undef __STDC_UTF_16__\n")
+        .unwrap();
+
+    // Go through the textual representation.
+    let snapshot: Snapshot = prefix.snapshot().to_string().parse().unwrap();
+    assert_eq!(prefix.snapshot(), snapshot);
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.restore(snapshot).define("IGNORED");
+    preprocess_eq(
+        &preprocessor,
+        "BAR IGNORED __STDC_UTF_16__ __STDC__",
+        "2 + 1 IGNORED __STDC_UTF_16__ 1",
+    );
+
+    let preprocessed = preprocessor.preprocess(b"").unwrap();
+    assert!(preprocessed.get_macro("__STDC__").unwrap().is_builtin());
+    assert!(!preprocessed.get_macro("BAR").unwrap().is_builtin());
+}
+
+#[test]
+fn snapshot_resume() {
+    let source = concat!(
+        "#define A 1\n",
+        "#if A\n",
+        "#ifdef B\n",
+        "b\n",
+        "#else\n",
+        "__COUNTER__ A\n",
+        "#define C __COUNTER__\n",
+        "C\n",
+        "#endif\n",
+        "#elif 1\n",
+        "never\n",
+        "#endif\n",
+        "C A\n",
+    );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.extension(Extension::Counter, true);
+    let full = preprocessor.preprocess(source.as_bytes()).unwrap();
+    // Blank lines are not compared, as the ones at the start of the output are not written.
+    let lines = |preprocessed: &Preprocessed| {
+        let text = Emitter::new().line_markers(false).render(preprocessed);
+        let text = String::from_utf8(text).unwrap();
+        text.lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+
+    // Resume at every line, going through the textual representation of the snapshot.
+    for (point, _) in source.match_indices('\n') {
+        let (before, rest) = source.split_at(point + 1);
+        let prefix = preprocessor.preprocess_prefix(before.as_bytes()).unwrap();
+        assert!(prefix.diagnostics().is_empty());
+        let snapshot: Snapshot = prefix.snapshot().to_string().parse().unwrap();
+        assert_eq!(prefix.snapshot(), snapshot);
+
+        let mut resumed = preprocessor.clone();
+        resumed.restore(snapshot);
+        let rest = resumed.preprocess(rest.as_bytes()).unwrap();
+        assert!(rest.diagnostics().is_empty());
+        let mut text = lines(&prefix);
+        text.extend(lines(&rest));
+        assert_eq!(text, lines(&full), "resumed after {:?}", before);
+        assert_eq!(rest.snapshot(), full.snapshot());
+    }
+
+    // The conditionals of a snapshot must be terminated.
+    let prefix = preprocessor.preprocess_prefix(b"#if 1\n").unwrap();
+    let mut resumed = preprocessor.clone();
+    resumed.restore(prefix.snapshot());
+    let error = preprocess_error(&resumed, "a\n");
+    assert_eq!(error.code(), DiagnosticCode::UnterminatedConditional);
+}
+
+#[test]
+fn snapshot_invalid() {
+    let error = "builtin #define FOO 1\nfoo #define BAR 2"
+        .parse::<Snapshot>()
+        .unwrap_err();
+    assert_eq!(2, error.line());
+    for invalid in [
+        "counter -1",
+        "conditional open #if 1",
+        "conditional active #define A",
+    ] {
+        assert!(invalid.parse::<Snapshot>().is_err());
+    }
+}
+
+#[test]
+fn conditional_groups() {
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define A\n#ifdef A\na\n#else\nb\n#endif\n#ifndef A\nc\n#elif 1\nd\n#elif 1\ne\n#endif\n",
+        "\n\na\n\n\n\n\n\n\nd\n\n\n\n",
+    );
+}
+
+#[test]
+fn conditional_nested() {
+    // The condition of the nested directive is not evaluated.
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#if 0\n#if 1 / 0\n#else\na\n#endif\n#else\nb\n#endif\n",
+        "\n\n\n\n\n\nb\n\n",
+    );
+}
+
+/// Check that the `#if` controlling expression `expr` evaluates to `expected`.
+#[track_caller]
+fn condition_eq(expr: &str, expected: bool) {
+    let source = format!("#define ONE 1\n#if {}\ntrue\n#else\nfalse\n#endif\n", expr);
+    let context = Preprocessor::new()
+        .preprocess(source.as_bytes())
+        .unwrap()
+        .context;
+    let found = context
+        .output
+        .tokens()
+        .iter()
+        .any(|token| context.map.spelling(*token) == "true");
+    assert_eq!(expected, found, "{}", expr);
+}
+
+#[test]
+fn condition_arithmetic() {
+    condition_eq("1 + 2 * 3 == 7", true);
+    condition_eq("(1 + 2) * 3 == 9", true);
+    condition_eq("-7 / 2 == -3 && -7 % 2 == -1", true);
+    condition_eq("~0 == -1 && !0 && !!5", true);
+    condition_eq("1 << 62 > 0 && -8 >> 1 == -4", true);
+    condition_eq("(6 & 3) == 2 && (6 | 3) == 7 && (6 ^ 3) == 5", true);
+    condition_eq("0x10 == 16 && 010 == 8 && 0b11 == 3", true);
+    condition_eq("9223372036854775807 + 0 > 0", true);
+}
+
+#[test]
+fn condition_unsigned() {
+    condition_eq("-1 < 0", true);
+    condition_eq("-1 < 0u", false);
+    condition_eq("-1 > 0 ? 1 : 0u", false);
+    // The result of the conditional operator is unsigned even if the signed operand is chosen.
+    condition_eq("(1 ? -1 : 0u) > 0", true);
+    condition_eq("18446744073709551615 == -1", true);
+    condition_eq("0xffffffffffffffff > 0", true);
+}
+
+#[test]
+fn condition_logical() {
+    // The unevaluated operands cannot cause errors.
+    condition_eq("0 && 1 / 0", false);
+    condition_eq("1 || 1 / 0", true);
+    condition_eq("1 ? 2 : 1 / 0", true);
+    condition_eq("(0, 1)", true);
+    condition_eq("1 ? 0 : 1 ? 1 : 1", false);
+}
+
+#[test]
+fn condition_identifiers() {
+    condition_eq("ONE", true);
+    condition_eq("UNDEFINED", false);
+    condition_eq("defined ONE && defined(ONE) && !defined UNDEFINED", true);
+    condition_eq("ONE + UNDEFINED == 1", true);
+}
+
+/// Evaluate the `#if` controlling expression `expr` and return the messages and lines of the
+/// diagnostics reported.
+fn condition_diagnostics(expr: &str) -> Vec<(Severity, String, usize)> {
+    // Each expression gets its own file as tests run in parallel.
+    let mut hasher = DefaultHasher::new();
+    expr.hash(&mut hasher);
+    let path = temp_file(
+        &format!("condition-{:016x}.h", hasher.finish()),
+        &format!("\n#if {}\n#endif\n", expr),
+    );
+    Preprocessor::new()
+        .preprocess_file(&path)
+        .unwrap()
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.severity(),
+                diagnostic.message().to_owned(),
+                diagnostic.line().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn condition_division_by_zero() {
+    condition_eq("1 / 0 == 0", true);
+    let diagnostics = condition_diagnostics("1 / 0");
+    assert_eq!(
+        vec![(
+            Severity::Error,
+            "Division by zero in preprocessor expression".to_owned(),
+            2
+        )],
+        diagnostics
+    );
+    // Unevaluated operands are not diagnosed.
+    assert!(condition_diagnostics("0 && 1 % 0").is_empty());
+}
+
+#[test]
+fn condition_overflow() {
+    for expr in [
+        "9223372036854775807 + 1",
+        "-9223372036854775807 - 2",
+        "-(-9223372036854775807 - 1)",
+        "4611686018427387904 * 2",
+        "1 << 63",
+    ] {
+        let diagnostics = condition_diagnostics(expr);
+        assert_eq!(1, diagnostics.len(), "{}", expr);
+        assert_eq!(Severity::Warning, diagnostics[0].0);
+        assert!(diagnostics[0].1.contains("overflow"), "{}", expr);
+    }
+    // Unsigned arithmetic wraps around.
+    assert!(condition_diagnostics("18446744073709551615u + 1").is_empty());
+    condition_eq("18446744073709551615u + 1 == 0", true);
+}
+
+#[test]
+fn condition_shift_out_of_range() {
+    condition_eq(
+        "(1 << 64) == 0 && (-8 >> 100) == -1 && (1 >> -1) == 0",
+        true,
+    );
+    let diagnostics = condition_diagnostics("1 << 64");
+    assert_eq!(1, diagnostics.len());
+    assert!(diagnostics[0].1.contains("Shift count out of range"));
+}
+
+#[test]
+fn condition_floating() {
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#if 1.0\n#endif\n");
+    assert!(diagnostic.message().starts_with("Invalid integer constant"));
+}
+
+#[test]
+fn conditional_unterminated() {
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#if 1\n#if 1\n#endif\n");
+    assert_eq!("Unterminated conditional directive", diagnostic.message());
+}
+
+#[test]
+fn conditional_unbalanced() {
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#endif\n");
+    assert_eq!("#endif without #if", diagnostic.message());
+}
+
+#[test]
+fn condition_char() {
+    condition_eq("'A' == 65 && '0' + 9 == '9'", true);
+    condition_eq(
+        "'\\n' == 10 && '\\0' == 0 && '\\'' == 39 && '\\\\' == 92",
+        true,
+    );
+    condition_eq("'\\101' == 65 && '\\x41' == 65 && '\\?' == 63", true);
+    condition_eq("'\\xff' == -1 && '\\377' < 0", true);
+    condition_eq("'ab' == 0x6162", true);
+    condition_eq(
+        "L'\\u00e9' == 0xe9 && U'\\U0001F600' == 0x1F600 && u'\\xffff' == 65535",
+        true,
+    );
+    // Unicode characters without prefix are sequences of UTF-8 bytes.
+    condition_eq("'é' == 0xc3a9", true);
+    condition_eq("u'\\0' - 1 > 0", true);
+}
+
+#[test]
+fn condition_unsigned_char() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.signed_char(false);
+    preprocess_eq(
+        &preprocessor,
+        "#if '\\xff' == 255\nyes\n#endif\n",
+        "\nyes\n\n",
+    );
+}
+
+#[test]
+fn condition_multi_char_disabled() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.multi_char_constants(false);
+    let diagnostic = preprocess_error(&preprocessor, "#if 'ab'\n#endif\n");
+    assert_eq!(
+        "Multi-character character constant 'ab'",
+        diagnostic.message()
+    );
+}
+
+#[test]
+fn condition_bool_keywords() {
+    let source = "#if true && !false\nyes\n#else\nno\n#endif\n__STDC_VERSION__";
+    preprocess_eq(&Preprocessor::new(), source, "\n\n\nno\n\n201710L");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.standard(Standard::C23);
+    preprocess_eq(&preprocessor, source, "\nyes\n\n\n\n202311L");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.warn_bool_keywords(true);
+    let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+    // The right operand of `&&` is not evaluated.
+    assert_eq!(1, preprocessed.diagnostics().len());
+
+    preprocessor.standard(Standard::C23);
+    let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+    assert!(preprocessed.diagnostics().is_empty());
+}
+
+#[test]
+fn standard_dialects() {
+    let preprocessor = |standard| {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.standard(standard);
+        preprocessor
+    };
+    preprocess_eq(
+        &preprocessor(Standard::C89),
+        "__STDC_VERSION__",
+        "__STDC_VERSION__",
+    );
+    preprocess_eq(
+        &preprocessor(Standard::Gnu11),
+        "__STDC_VERSION__",
+        "201112L",
+    );
+    preprocess_eq(
+        &preprocessor(Standard::Gnu23),
+        "__STDC_VERSION__",
+        "202311L",
+    );
+    assert_eq!(Standard::from_name("gnu2x"), Some(Standard::Gnu23));
+    assert_eq!(Standard::from_name("iso9899:2018"), Some(Standard::C17));
+    assert!(Standard::Gnu17 < Standard::C23 && Standard::Gnu23.iso() == Standard::C23);
+
+    // `#elifdef` and `#elifndef` are ignored inside skipped groups before C23.
+    let source = "#define B\n#ifdef A\na\n#elifdef B\nb\n#elifndef A\nc\n#endif\n";
+    preprocess_eq(&preprocessor(Standard::C17), source, "\n\n\n\n\n\n\n\n");
+    preprocess_eq(&preprocessor(Standard::Gnu17), source, "\n\n\n\nb\n\n\n\n");
+    preprocess_eq(&preprocessor(Standard::C23), source, "\n\n\n\nb\n\n\n\n");
+
+    // `#elifdef` is not a directive before C23, and `#embed` is not supported.
+    let source = "#if 1\na\n#elifdef B\nb\n#endif\n#embed \"data.bin\"\n";
+    preprocess_eq(&preprocessor(Standard::C17), source, "\na\n\nb\n\n\n");
+    let error = preprocess_error(&preprocessor(Standard::C17), source);
+    assert_eq!(error.code(), DiagnosticCode::UnsupportedDirective);
+    assert_eq!(error.message(), "#elifdef is not a directive before C23");
+    let error = preprocess_error(&preprocessor(Standard::C23), source);
+    assert_eq!(error.message(), "#embed is not supported");
+
+    // Trigraphs are only replaced in ISO C before C23.
+    let source = "??=define A(x) x??(0??) ??/\n+ 1\nA(a)\n";
+    preprocess_eq(&preprocessor(Standard::C99), source, "\na[0] + 1\n");
+    for standard in [Standard::Gnu99, Standard::C23] {
+        preprocess_eq(&preprocessor(standard), source, source);
+    }
+
+    // Digit separators are only part of numbers since C23.
+    let source = "#if 1'0'0 == 100\nyes\n#endif\n";
+    preprocess_eq(&preprocessor(Standard::C23), source, "\nyes\n\n");
+    preprocess_error(&preprocessor(Standard::Gnu17), source);
+
+    // Universal character names are only part of identifiers since C99.
+    let source = "#define caf\\u00e9 1\ncaf\\u00e9\n";
+    preprocess_eq(&preprocessor(Standard::C99), source, "\n1\n");
+    preprocess_eq(&preprocessor(Standard::C89), source, "\n\\u00e9 1\\u00e9\n");
+}
+
+#[test]
+fn extensions() {
+    let preprocessor = |extension| {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.extension(extension, true);
+        preprocessor
+    };
+
+    // Named variable arguments, and the comma before empty variable arguments is removed.
+    let source = "#define F(fmt, args...) f(fmt, ## args)\nF(a) F(a, b, c)\n";
+    preprocess_eq(
+        &preprocessor(Extension::GnuVariadic),
+        source,
+        "\nf(a) f(a,b, c)\n",
+    );
+    preprocess_error(&Preprocessor::new(), source);
+    let source = "#define F(fmt, ...) f(fmt, ## __VA_ARGS__)\nF(a)\n";
+    preprocess_eq(&preprocessor(Extension::GnuVariadic), source, "\nf(a)\n");
+
+    let source = "#if defined(__COUNTER__)\n__COUNTER__ __COUNTER__\n#endif\n";
+    preprocess_eq(&preprocessor(Extension::Counter), source, "\n0 1\n\n");
+    preprocess_eq(&Preprocessor::new(), source, "\n\n\n");
+
+    let source = "#define $a 1\n$a\n";
+    preprocess_eq(&preprocessor(Extension::DollarIdents), source, "\n1\n");
+    preprocess_error(&Preprocessor::new(), source);
+
+    let source = "#define PASTE(a, b) a ## b\nPASTE(., +)\n";
+    preprocess_eq(&preprocessor(Extension::MsvcPaste), source, "\n.+\n");
+    preprocess_error(&Preprocessor::new(), source);
+
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "main.c",
+        "#include <a.h>\n#import \"b.h\"\n#import \"b.h\"\n",
+    );
+    files.insert("first/a.h", "first\n#include_next <a.h>\n");
+    files.insert("second/a.h", "second\n");
+    files.insert("b.h", "b\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .include_dir("first")
+        .include_dir("second")
+        .extension(Extension::IncludeNext, true)
+        .extension(Extension::Import, true);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(String::from_utf8(text).unwrap(), "first\nsecond\nb\n\n");
+}
+
+#[test]
+fn condition_width() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.intmax_width(IntmaxWidth::Bits32);
+    let check = |expr: &str, expected: bool| {
+        let source = format!("#if {}\ntrue\n#endif\n", expr);
+        let output = if expected { "\ntrue\n\n" } else { "\n\n\n" };
+        preprocess_eq(&preprocessor, &source, output);
+    };
+    check("2147483647 > 0", true);
+    // Constants that do not fit in 32-bit `intmax_t` are unsigned.
+    check("2147483648 > -1", false);
+    check("0u - 1 == 4294967295", true);
+    check("(1 << 31) < 0", true);
+    check("(1u << 32) == 0", true);
+    check("~0u == 0xffffffff", true);
+
+    let preprocessed = preprocessor
+        .preprocess(b"#if 2147483647 + 1\n#endif\n")
+        .unwrap();
+    assert!(preprocessed.diagnostics()[0].message().contains("overflow"));
+    let preprocessed = preprocessor
+        .preprocess(b"#if 4294967296\n#endif\n")
+        .unwrap();
+    assert!(preprocessed.diagnostics()[0]
+        .message()
+        .contains("too large"));
+}
+
+#[test]
+fn skip_group() {
+    // Unterminated character constants are allowed in skipped groups and the directives inside
+    // comments and string literals are ignored.
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#if 0\ndon't\n\"#endif\"\n/* #endif\n#else */ x\n// \\\n#endif\n#define A\n#endif\nA\n",
+        "\n\n\n\n\n\n\nA\n",
+    );
+    // Directives can be preceded by comments and contain white-space and digraphs.
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#if 0\n/* a */ # /* b */ if 1\n#endif\n %: else\nyes\n# endif\n",
+        "\n\n\n\nyes\n\n",
+    );
+}
+
+#[test]
+fn skip_group_unterminated() {
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#if 0\n#ifdef A\n#endif\n");
+    assert_eq!("Unterminated conditional directive", diagnostic.message());
+}
+
+#[test]
+fn condition_expansion_to_defined() {
+    let source = "#define A 1\n#define IS_A defined(A)\n#define WRAP IS_A\n#if WRAP\nyes\n#endif\n";
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    let diagnostics = preprocessed.diagnostics();
+    assert_eq!(1, diagnostics.len());
+    assert_eq!(Severity::Warning, diagnostics[0].severity());
+    assert_eq!(DiagnosticCode::ExpansionToDefined, diagnostics[0].code());
+    assert_eq!(
+        diagnostics[0].notes(),
+        [
+            "In expansion of macro \"IS_A\"",
+            "In expansion of macro \"WRAP\""
+        ]
+    );
+    // The operand of `defined` is not replaced even if it is a macro.
+    preprocess_eq(&Preprocessor::new(), source, "\n\n\n\nyes\n\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.expansion_to_defined(Level::Allow);
+    let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+    assert!(preprocessed.diagnostics().is_empty());
+
+    preprocessor.expansion_to_defined(Level::Error);
+    let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+    assert_eq!(Severity::Error, preprocessed.diagnostics()[0].severity());
+}
+
+#[test]
+fn expansion_backtrace() {
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "paste.c",
+        "#define PASTE(a, b) a ## b\n#define WRAP(x) PASTE(x, +)\nint a = WRAP(.);\n",
+    );
+    files.insert(
+        "args.c",
+        "#define ONE(x) x\n#define TWO ONE(1, 2)\n#define THREE TWO\nTHREE\n",
+    );
+    files.insert(
+        "condition.c",
+        "#define DIV(x) (1 / (x))\n#define ZERO DIV(0)\n#if 1\n#elif 2 + ZERO\n#endif\n#if ZERO\n#endif\n",
+    );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+
+    let preprocessed = preprocessor.preprocess_file(&"paste.c").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.code(), DiagnosticCode::InvalidPaste);
+    assert_eq!(
+        diagnostic.notes(),
+        [
+            "In expansion of macro \"PASTE\" at paste.c:2",
+            "In expansion of macro \"WRAP\" at paste.c:3"
+        ]
+    );
+
+    // The invocation with the wrong number of arguments is not part of the backtrace.
+    let preprocessed = preprocessor.preprocess_file(&"args.c").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.code(), DiagnosticCode::ArgumentCount);
+    assert_eq!(
+        diagnostic.notes(),
+        [
+            "In expansion of macro \"TWO\" at args.c:3",
+            "In expansion of macro \"THREE\" at args.c:4"
+        ]
+    );
+
+    let preprocessed = preprocessor.preprocess_file(&"condition.c").unwrap();
+    let diagnostics = preprocessed.diagnostics();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code(), DiagnosticCode::DivisionByZero);
+    assert_eq!(diagnostics[0].line(), Some(1));
+    assert_eq!(
+        diagnostics[0].notes(),
+        [
+            "In expansion of macro \"DIV\" at condition.c:2",
+            "In expansion of macro \"ZERO\" at condition.c:6"
+        ]
+    );
+}
+
+#[test]
+fn conditional_coverage() {
+    let path = temp_file(
+        "coverage.h",
+        "#define A 2\n#ifdef B\n#elif A  >  1\n#if 1\n#endif\n#else\n#endif\n#if 0\n#ifndef A\n#endif\n#endif\n",
+    );
+    let preprocessed = Preprocessor::new().preprocess_file(&path).unwrap();
+    let summary: Vec<Vec<_>> = preprocessed
+        .conditionals()
+        .iter()
+        .map(|section| {
+            section
+                .branches()
+                .iter()
+                .map(|branch| {
+                    (
+                        branch.directive().to_owned(),
+                        branch.condition().to_owned(),
+                        branch.line().unwrap(),
+                        branch.value(),
+                        branch.is_taken(),
+                    )
+                })
+                .collect()
+        })
+        .collect();
+
+    let branch = |directive: &str, condition: &str, line, value, taken| {
+        (
+            directive.to_owned(),
+            condition.to_owned(),
+            line,
+            value,
+            taken,
+        )
+    };
+    assert_eq!(
+        vec![
+            vec![
+                branch("ifdef", "B", 2, Some(false), false),
+                branch("elif", "A > 1", 3, Some(true), true),
+                branch("else", "", 6, None, false),
+            ],
+            vec![branch("if", "1", 4, Some(true), true)],
+            vec![branch("if", "0", 8, Some(false), false)],
+            vec![branch("ifndef", "A", 9, None, false)],
+        ],
+        summary
+    );
+    let sections = preprocessed.conditionals();
+    assert_eq!("elif", sections[0].taken().unwrap().directive());
+    assert!(sections[3].taken().is_none());
+}
+
+#[test]
+fn include_search_path() {
+    let root = std::env::temp_dir()
+        .join("beheader-tests")
+        .join("include_search_path");
+    for dir in ["src", "quote", "angled", "after"] {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    let write = |path: &str, contents: &str| std::fs::write(root.join(path), contents).unwrap();
+    write(
+        "src/main.c",
+        "#include \"a.h\"\n#include <a.h>\n#include \"b.h\"\n#include <c.h>\n",
+    );
+    write("src/a.h", "src_a\n");
+    write("quote/a.h", "quote_a\n");
+    write("quote/b.h", "quote_b\n");
+    write("angled/a.h", "angled_a\n");
+    write("angled/b.h", "angled_b\n");
+    write("angled/c.h", "angled_c\n");
+    write("after/c.h", "after_c\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .include_dir_after(root.join("after"))
+        .include_dir(root.join("angled"))
+        .quote_include_dir(root.join("quote"));
+    let context = preprocessor
+        .preprocess_file(&root.join("src/main.c"))
+        .unwrap()
+        .context;
+    let output: Vec<_> = context
+        .output
+        .tokens()
+        .iter()
+        .filter(|token| token.kind == TokenKind::Ident)
+        .map(|&token| context.map.spelling(token))
+        .collect();
+
+    assert_eq!(output, ["src_a", "angled_a", "quote_b", "angled_c"]);
+}
+
+#[test]
+fn include_computed() {
+    let header = temp_file("include_computed.h", "int included;\n");
+    let dir = header.parent().unwrap();
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .include_dir(dir)
+        .define("HEADER=<include_computed.h>");
+    preprocess_eq(&preprocessor, "#include HEADER\nx", "int included;\n\nx");
+
+    preprocessor.keep_include_directives(true);
+    preprocess_eq(
+        &preprocessor,
+        "#include <include_computed.h>\n",
+        "#include <include_computed.h>\nint included;\n\n",
+    );
+}
+
+#[test]
+fn include_missing() {
+    let diagnostic = preprocess_err(&Preprocessor::new(), "#include <missing.h>\n");
+    assert_eq!(
+        "Cannot find included file \"missing.h\"",
+        diagnostic.message()
+    );
+}
+
+#[test]
+fn include_sysroot() {
+    let root = std::env::temp_dir()
+        .join("beheader-tests")
+        .join("include_sysroot");
+    std::fs::create_dir_all(root.join("usr/include")).unwrap();
+    std::fs::create_dir_all(root.join("opt/include")).unwrap();
+    std::fs::write(root.join("usr/include/sys.h"), "sys\n").unwrap();
+    std::fs::write(root.join("opt/include/opt.h"), "opt\n").unwrap();
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .sysroot(&root)
+        .include_dir("=/usr/include")
+        .include_dir_after("$SYSROOT/opt/include");
+    preprocess_eq(
+        &preprocessor,
+        "#include <sys.h>\n#include <opt.h>\n",
+        "sys\n\nopt\n\n",
+    );
+}
+
+#[test]
+fn include_framework() {
+    let root = std::env::temp_dir()
+        .join("beheader-tests")
+        .join("include_framework");
+    let foundation = root.join("Frameworks/Foundation.framework");
+    std::fs::create_dir_all(foundation.join("Headers")).unwrap();
+    std::fs::create_dir_all(foundation.join("PrivateHeaders")).unwrap();
+    std::fs::create_dir_all(foundation.join("Frameworks/Sub.framework/Headers")).unwrap();
+    std::fs::write(
+        foundation.join("Headers/Foundation.h"),
+        "foundation\n#include <Foundation/Private.h>\n#include <Sub/Sub.h>\n",
+    )
+    .unwrap();
+    std::fs::write(foundation.join("PrivateHeaders/Private.h"), "private\n").unwrap();
+    std::fs::write(
+        foundation.join("Frameworks/Sub.framework/Headers/Sub.h"),
+        "sub\n",
+    )
+    .unwrap();
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.framework_dir(root.join("Frameworks"));
+    preprocess_eq(
+        &preprocessor,
+        "#include <Foundation/Foundation.h>\n",
+        "foundation\nprivate\n\nsub\n\n\n",
+    );
+}
+
+#[test]
+fn file_system_memory() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("src/main.c", "#include \"local.h\"\n#include <lib.h>\n")
+        .insert("src/local.h", "local\n")
+        .insert("include/lib.h", "lib\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).include_dir("include");
+    let context = preprocessor.preprocess_file(&"src/main.c").unwrap().context;
+    let output: Vec<_> = context
+        .output
+        .tokens()
+        .iter()
+        .filter(|token| token.kind == TokenKind::Ident)
+        .map(|&token| context.map.spelling(token))
+        .collect();
+    assert_eq!(output, ["local", "lib"]);
+
+    assert!(preprocessor.preprocess_file(&"src/missing.c").is_err());
+}
+
+#[test]
+fn file_system_overlay() {
+    let header = temp_file("file_system_overlay.h", "on_disk\n");
+    let modified = temp_file("file_system_overlay_modified.h", "on_disk\n");
+    let dir = header.parent().unwrap();
+
+    let mut files = MemoryFileSystem::overlay(RealFileSystem);
+    files.insert(&modified, "in_memory\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).include_dir(dir);
+    preprocess_eq(
+        &preprocessor,
+        "#include <file_system_overlay.h>\n#include <file_system_overlay_modified.h>\n",
+        "on_disk\n\nin_memory\n\n",
+    );
+}
+
+#[test]
+fn include_hook() {
+    let header = temp_file("include_hook.h", "on_disk\n");
+    let dir = header.parent().unwrap().to_owned();
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.include_hook(move |request| match (request.name(), request.kind()) {
+        ("generated.h", IncludeKind::Quoted) => IncludeResolution::Contents {
+            path: "generated.h".into(),
+            contents: b"generated\n".to_vec(),
+        },
+        ("skipped.h", _) => IncludeResolution::Skip,
+        ("renamed.h", IncludeKind::Angled) => IncludeResolution::Path(dir.join("include_hook.h")),
+        _ => IncludeResolution::Default,
+    });
+    preprocess_eq(
+        &preprocessor,
+        "#include \"generated.h\"\n#include <skipped.h>\n#include <renamed.h>\n",
+        "generated\n\n\non_disk\n\n",
+    );
+}
+
+#[test]
+fn include_depth() {
+    let header = temp_file("include_depth.h", "x\n#include \"include_depth.h\"\n");
+    let main = temp_file("include_depth.c", "#include \"include_depth.h\"\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.max_include_depth(3);
+    // Exceeding the depth is fatal, even if its level is changed.
+    preprocessor.diagnostic_level(DiagnosticCode::IncludeDepth, Level::Warn);
+    let error = preprocessor.preprocess_file(&main).err().unwrap();
+    let diagnostic = error.diagnostic().unwrap();
+
+    assert!(diagnostic.code().is_fatal());
+    assert_eq!(diagnostic.severity(), Severity::Error);
+    assert_eq!(diagnostic.file(), Some(&header));
+    assert_eq!(diagnostic.line(), Some(2));
+    assert_eq!(
+        diagnostic.message(),
+        "#include nested more than 3 levels deep"
+    );
+    assert!(diagnostic
+        .to_string()
+        .contains("fatal error[include-depth]"));
+    let (main, h) = (main.display(), header.display());
+    assert_eq!(
+        diagnostic.notes(),
+        [
+            format!("{}:1 includes {}", main, h),
+            format!("{}:2 includes {}", h, h),
+            format!("{}:2 includes {}", h, h),
+            format!("{}:2 includes {}", h, h),
+            format!("Included from {}:1", main),
+        ]
+    );
+}
+
+#[test]
+fn include_stack_notes() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("a.c", "int a;\n#include \"b.h\"\n#if 3 / 0\n#endif\n");
+    files.insert("b.h", "\n\n#include \"c.h\"\n#if 1 / 0\n#endif\n");
+    files.insert("c.h", "#if 2 / 0\n#endif\n");
+    files.insert("d.c", "#include \"e.h\"\n");
+    files.insert("e.h", "#endif\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+
+    let preprocessed = preprocessor.preprocess_file(&"a.c").unwrap();
+    let diagnostics: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| (diagnostic.file().unwrap().clone(), diagnostic.notes()))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                PathBuf::from("c.h"),
+                &[
+                    "Included from b.h:3".to_owned(),
+                    "Included from a.c:2".to_owned()
+                ][..]
+            ),
+            (
+                PathBuf::from("b.h"),
+                &["Included from a.c:2".to_owned()][..]
+            ),
+            (PathBuf::from("a.c"), &[][..]),
+        ]
+    );
+
+    let preprocessed = preprocessor.preprocess_file(&"d.c").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.code(), DiagnosticCode::UnmatchedConditional);
+    assert_eq!(diagnostic.notes(), ["Included from d.c:1"]);
+}
+
+#[test]
+fn max_errors() {
+    let source = "#if 1 / 0\n#endif\n#if 2 / 0\n#else X\n#endif\n#if 3 / 0\n#endif\n";
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    assert_eq!(preprocessed.diagnostics().len(), 4);
+
+    // Only errors count, including warnings reported as errors.
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.max_errors(4);
+    assert!(preprocessor.preprocess(source.as_bytes()).is_ok());
+    preprocessor.max_errors(3).warnings_as_errors(true);
+    let error = preprocessor.preprocess(source.as_bytes()).err().unwrap();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.code(), DiagnosticCode::TooManyErrors);
+    assert_eq!(diagnostic.line(), None);
+    assert_eq!(
+        diagnostic.to_string(),
+        "fatal error[too-many-errors]: Stopping after 3 errors"
+    );
+    // The errors that were counted come before the error that stopped the preprocessing.
+    let codes: Vec<_> = error.diagnostics().iter().map(Diagnostic::code).collect();
+    assert_eq!(
+        codes,
+        [
+            DiagnosticCode::DivisionByZero,
+            DiagnosticCode::DivisionByZero,
+            DiagnosticCode::ExtraTokens,
+        ]
+    );
+
+    // The limit also applies to the errors found in included files.
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.c", "#include \"a.h\"\n#if 1 / 0\n#endif\nint a;\n");
+    files.insert("a.h", "#if 1 / 0\n#endif\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).max_errors(2);
+    let error = preprocessor.preprocess_file(&"main.c").err().unwrap();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.code(), DiagnosticCode::TooManyErrors);
+    assert_eq!(diagnostic.line(), Some(2));
+    let reported = error.diagnostics();
+    assert_eq!(reported.len(), 2);
+    assert_eq!(reported[0].file(), Some(&PathBuf::from("a.h")));
+    assert_eq!(reported[0].notes(), ["Included from main.c:1"]);
+}
+
+#[test]
+fn budget() {
+    let source = "#define A a a a a\n#define B A A A A\nB B B B\n#undef A\nB B B B\n";
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.max_tokens(1000).max_source_bytes(1000);
+    assert!(preprocessor.preprocess(source.as_bytes()).is_ok());
+
+    // The budget is checked before each line, once the text before it has been replaced.
+    preprocessor.max_tokens(100);
+    let diagnostic = preprocess_err(&preprocessor, source);
+    assert_eq!(diagnostic.code(), DiagnosticCode::BudgetExceeded);
+    assert_eq!(
+        diagnostic.message(),
+        "Stopping after exceeding the budget of 100 tokens"
+    );
+    assert_eq!(
+        diagnostic.notes()[1..],
+        ["61 bytes of source code loaded", "131 tokens produced"]
+    );
+
+    // The bytes of included files count too.
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.c", "#include \"a.h\"\nint a;\n");
+    files.insert("a.h", "int b;\n".repeat(20));
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).max_source_bytes(100);
+    let error = preprocessor.preprocess_file(&"main.c").err().unwrap();
+    let diagnostic = error.diagnostic().unwrap();
+    assert_eq!(diagnostic.code(), DiagnosticCode::BudgetExceeded);
+    assert_eq!(diagnostic.notes()[1], "162 bytes of source code loaded");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.max_duration(std::time::Duration::from_nanos(1));
+    let diagnostic = preprocess_err(&preprocessor, source);
+    assert!(diagnostic.message().ends_with("budget of 1ns"));
+}
+
+#[test]
+fn cancellation() {
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "main.c",
+        "#define UNUSED\nint a;\n#include \"slow.h\"\nint c;\n",
+    );
+    files.insert("slow.h", "int b;\n");
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .warning(Warning::UnusedMacros, true)
+        .cancellation(cancelled.clone())
+        .include_hook(move |_| {
+            flag.store(true, Ordering::Relaxed);
+            IncludeResolution::Default
+        });
+
+    // The run stops before the first line of the included file.
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert!(preprocessed.is_cancelled());
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(String::from_utf8(text).unwrap(), "int a;\n");
+    let [diagnostic] = preprocessed.diagnostics() else {
+        panic!("Unexpected diagnostics: {:?}", preprocessed.diagnostics());
+    };
+    assert_eq!(diagnostic.code(), DiagnosticCode::Cancelled);
+    assert_eq!(diagnostic.severity(), Severity::Note);
+    assert_eq!(diagnostic.file(), Some(&PathBuf::from("slow.h")));
+
+    cancelled.store(false, Ordering::Relaxed);
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.cancellation(cancelled);
+    let preprocessed = preprocessor.preprocess(b"int a;\n").unwrap();
+    assert!(!preprocessed.is_cancelled());
+}
+
+#[test]
+fn include_case_insensitive() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("sdk/include/windows.h", "windows\n")
+        .insert("sdk/include/sys/types.h", "types\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).include_dir("sdk/include");
+    let source = b"#include <Windows.h>\n#include <SYS/Types.h>\n";
+    assert!(preprocessor.preprocess(source).is_err());
+
+    preprocessor.case_insensitive_includes(true);
+    let preprocessed = preprocessor.preprocess(source).unwrap();
+    let messages: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| (diagnostic.severity(), diagnostic.message()))
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            (
+                Severity::Warning,
+                "Included file \"Windows.h\" was found with a different case at \
+                 sdk/include/windows.h"
+            ),
+            (
+                Severity::Warning,
+                "Included file \"SYS/Types.h\" was found with a different case at \
+                 sdk/include/sys/types.h"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn include_canonical_paths() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("once.h", "#pragma once\nonce\n")
+        .insert(
+            "guarded.h",
+            "#ifndef GUARD\n#define GUARD\nguarded\n#endif\n",
+        )
+        .insert("dir/other.h", "other\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor
+        .preprocess(
+            b"#include \"once.h\"\n#include \"./dir/../once.h\"\n\
+              #include \"guarded.h\"\n#include \"dir/./../guarded.h\"\n\
+              #include \"dir/../dir/other.h\"\n",
+        )
+        .unwrap();
+    let context = &preprocessed.context;
+    let output: Vec<_> = context
+        .output
+        .tokens()
+        .iter()
+        .filter(|token| token.kind == TokenKind::Ident)
+        .map(|&token| context.map.spelling(token))
+        .collect();
+    assert_eq!(output, ["once", "guarded", "other"]);
+
+    let other = context
+        .output
+        .tokens()
+        .iter()
+        .find(|&&token| context.map.spelling(token) == "other")
+        .unwrap();
+    let file = context.map.find_file(other.span);
+    assert_eq!(file, Some(PathBuf::from("dir/other.h")));
+}
+
+#[cfg(unix)]
+#[test]
+fn include_symlinks() {
+    let header = temp_file("include_symlinks.h", "#pragma once\nonce\n");
+    let link = header.with_file_name("include_symlinks_link.h");
+    let _ = std::fs::remove_file(&link);
+    std::os::unix::fs::symlink(&header, &link).unwrap();
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.include_dir(header.parent().unwrap());
+    let source = "#include <include_symlinks.h>\n#include <include_symlinks_link.h>\n";
+    preprocess_eq(&preprocessor, source, "\nonce\n\n\nonce\n\n");
+
+    preprocessor.resolve_symlinks(true);
+    preprocess_eq(&preprocessor, source, "\nonce\n\n\n");
+}
+
+#[test]
+fn include_graph() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"a.h\"\n#include <b.h>\n")
+        .insert("a.h", "#include <b.h>\n")
+        .insert("include/b.h", "#pragma once\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).include_dir("include");
+    let graph = preprocessor
+        .preprocess_file(&"main.c")
+        .unwrap()
+        .include_graph();
+
+    let edges: Vec<_> = graph
+        .edges()
+        .iter()
+        .map(|edge| {
+            (
+                edge.includer().unwrap().to_str().unwrap(),
+                edge.line().unwrap(),
+                edge.included().to_str().unwrap(),
+                edge.kind(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        edges,
+        [
+            ("main.c", 1, "a.h", IncludeKind::Quoted),
+            ("a.h", 1, "include/b.h", IncludeKind::Angled),
+            ("main.c", 2, "include/b.h", IncludeKind::Angled),
+        ]
+    );
+    assert_eq!(
+        graph.files(),
+        ["main.c", "a.h", "include/b.h"].map(std::path::Path::new)
+    );
+    assert_eq!(graph.includes("a.h".as_ref()).count(), 1);
+    assert_eq!(
+        graph.to_dot(),
+        "digraph includes {\n    \"main.c\" -> \"a.h\";\n    \"a.h\" -> \"include/b.h\" \
+         [style=dashed];\n    \"main.c\" -> \"include/b.h\" [style=dashed];\n}\n"
+    );
+}
+
+#[test]
+fn depfile() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("src/main.c", "#include \"a$b#c.h\"\n#include <long.h>\n")
+        .insert("src/a$b#c.h", "#include <long.h>\n")
+        .insert("include/long.h", "")
+        .insert("config.h", "");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .include_dir("include")
+        .include("config.h");
+    let preprocessed = preprocessor.preprocess_file(&"src/main.c").unwrap();
+    assert_eq!(
+        preprocessed.dependencies(),
+        ["src/main.c", "config.h", "src/a$b#c.h", "include/long.h"].map(PathBuf::from)
+    );
+
+    let mut depfile = Depfile::new();
+    assert_eq!(
+        depfile.render(&preprocessed),
+        "main.o: src/main.c config.h src/a$$b\\#c.h include/long.h\n"
+    );
+
+    depfile
+        .target("$(OBJ)")
+        .quoted_target("out dir/objects/main.o")
+        .phony_targets(true);
+    assert_eq!(
+        depfile.render(&preprocessed),
+        "$(OBJ) out\\ dir/objects/main.o: src/main.c config.h src/a$$b\\#c.h \\\n include/long.h\n\
+         config.h:\nsrc/a$$b\\#c.h:\ninclude/long.h:\n"
+    );
+
+    let preprocessed = Preprocessor::new().preprocess(b"").unwrap();
+    assert_eq!(Depfile::new().render(&preprocessed), "-:\n");
+}
+
+#[test]
+fn depfile_system_headers() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include <stdio.h>\n#include <local.h>\n#include <late.h>\n",
+        )
+        .insert("sys/stdio.h", "#include \"bits.h\"\n")
+        .insert("sys/bits.h", "")
+        .insert("include/local.h", "")
+        .insert("after/late.h", "");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .include_dir("include")
+        .system_include_dir("sys")
+        .include_dir_after("after");
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+
+    let mut depfile = Depfile::new();
+    assert_eq!(
+        depfile.render(&preprocessed),
+        "main.o: main.c sys/stdio.h sys/bits.h include/local.h after/late.h\n"
+    );
+    depfile.skip_system_headers(true);
+    assert_eq!(
+        depfile.render(&preprocessed),
+        "main.o: main.c include/local.h\n"
+    );
+}
+
+#[test]
+fn missing_files() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("src/main.c", "#include \"config.h\"\n#include <config.h>\n")
+        .insert("include/config.h", "");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .quote_include_dir("quote")
+        .include_dir("generated")
+        .include_dir("include")
+        .include_dir_after("after");
+    let preprocessed = preprocessor.preprocess_file(&"src/main.c").unwrap();
+    assert_eq!(
+        preprocessed.missing_files(),
+        ["src/config.h", "quote/config.h", "generated/config.h"].map(PathBuf::from)
+    );
+}
+
+#[test]
+fn stale_files() {
+    let dir = std::env::temp_dir()
+        .join("beheader-tests")
+        .join("stale_files");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("include")).unwrap();
+    std::fs::create_dir_all(dir.join("generated")).unwrap();
+    let main = dir.join("main.c");
+    let config = dir.join("include").join("config.h");
+    std::fs::write(&main, "#include <config.h>\nVERSION\n").unwrap();
+    std::fs::write(&config, "#define VERSION 1\n").unwrap();
+
+    let cache = HeaderCache::new();
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .include_dir(dir.join("generated"))
+        .include_dir(dir.join("include"))
+        .header_cache(cache.clone());
+    let preprocess = || preprocessor.preprocess_file(&main).unwrap();
+
+    let preprocessed = preprocess();
+    assert!(preprocessed.stale_files().is_empty());
+    assert_eq!(cache.len(), 2);
+
+    std::fs::write(&config, "#define VERSION 22\n").unwrap();
+    assert_eq!(
+        preprocessed.invalidate_stale(&cache),
+        std::slice::from_ref(&config)
+    );
+    assert_eq!(cache.len(), 1);
+
+    let preprocessed = preprocess();
+    assert!(preprocessed.stale_files().is_empty());
+    let generated = dir.join("generated").join("config.h");
+    std::fs::write(&generated, "#define VERSION 3\n").unwrap();
+    std::fs::remove_file(&config).unwrap();
+    assert_eq!(preprocessed.stale_files(), [config, generated]);
+}
+
+#[test]
+fn file_prefix_map() {
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "/build/src/main.c",
+        "#include \"config.h\"\n#if 1 / 0\n#endif\n",
+    );
+    files.insert("/build/src/config.h", "int a;\n");
+    files.insert("/build/srcs/other.c", "#include \"../src/config.h\"\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .file_prefix_map("/build", "/elsewhere")
+        .file_prefix_map("/build/src", ".");
+
+    let preprocessed = preprocessor.preprocess_file(&"/build/src/main.c").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.file(), Some(&PathBuf::from("./main.c")));
+    let text = Emitter::new().render(&preprocessed);
+    let text = String::from_utf8(text).unwrap();
+    assert!(text.contains("# 1 \"./config.h\" 1\n"), "{}", text);
+    assert!(!text.contains("/build"), "{}", text);
+    let depfile = Depfile::new().render(&preprocessed);
+    assert!(depfile.contains("./main.c"), "{}", depfile);
+    assert!(depfile.contains("./config.h"), "{}", depfile);
+    assert_eq!(
+        preprocessed.dependencies()[0],
+        PathBuf::from("/build/src/main.c")
+    );
+
+    let preprocessed = preprocessor
+        .preprocess_file(&"/build/srcs/other.c")
+        .unwrap();
+    let text = String::from_utf8(Emitter::new().render(&preprocessed)).unwrap();
+    assert!(text.contains("\"/elsewhere/srcs/other.c\""), "{}", text);
+}
+
+#[test]
+fn file_and_line_macros() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "/build/main.c",
+            "#define F __FILE__\n#define L __LINE__\n#define CALL(x) x L\n__FILE__ __LINE__\nF L\n\
+             CALL(\n__LINE__)\n#include \"a.h\"\n#line 100 \"other\\\\dir.c\"\n__FILE__ __LINE__\n\
+             #if __LINE__ == 101 && defined(__FILE__) && defined __LINE__\nok\n#endif\n",
+        )
+        .insert("/build/a.h", "__FILE__ __LINE__\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let render = |preprocessor: &Preprocessor| {
+        let preprocessed = preprocessor.preprocess_file(&"/build/main.c").unwrap();
+        let text = Emitter::new().line_markers(false).render(&preprocessed);
+        String::from_utf8(text).unwrap()
+    };
+
+    // The arguments of a macro have the lines where they are, while the replacement list has the
+    // line of the invocation.
+    assert_eq!(
+        render(&preprocessor),
+        "\"/build/main.c\" 4\n\"/build/main.c\" 5\n7 6\n\"/build/a.h\" 1\n\n\
+         \"other\\\\dir.c\" 100\n\nok\n\n"
+    );
+
+    // The prefixes are replaced by both maps, but only the file prefix map changes the locations
+    // of the diagnostics and line markers.
+    preprocessor.macro_prefix_map("/build", "src");
+    let text = render(&preprocessor);
+    assert!(text.starts_with("\"src/main.c\" 4\n"), "{}", text);
+    assert!(text.contains("\"src/a.h\" 1\n"), "{}", text);
+    let preprocessed = preprocessor.preprocess_file(&"/build/main.c").unwrap();
+    let text = String::from_utf8(Emitter::new().render(&preprocessed)).unwrap();
+    assert!(text.starts_with("# 1 \"/build/main.c\"\n"), "{}", text);
+    preprocessor.file_prefix_map("/build", ".");
+    assert!(render(&preprocessor).starts_with("\"./main.c\" 4\n"));
+
+    // The source code that is not in a file is the standard input.
+    let preprocessed = Preprocessor::new()
+        .preprocess(b"\n__LINE__ __FILE__\n")
+        .unwrap();
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(text, b"2 \"<stdin>\"\n");
+}
+
+#[test]
+fn batch() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("common.h", "#define COMMON 1\n");
+    let mut paths = Vec::new();
+    for i in 0..16 {
+        let path = format!("unit{}.c", i);
+        files.insert(
+            &path,
+            format!("#include \"common.h\"\n#if COMMON\nunit{}\n#endif\n", i),
+        );
+        paths.push(path);
+    }
+    paths.push("missing.c".to_owned());
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let results = Batch::new(preprocessor)
+        .threads(NonZeroUsize::new(4).unwrap())
+        .preprocess_files(&paths);
+
+    let results = results.into_results();
+    assert_eq!(results.len(), 17);
+    for (i, result) in results[..16].iter().enumerate() {
+        let context = &result.as_ref().unwrap().context;
+        let output: Vec<_> = context
+            .output
+            .tokens()
+            .iter()
+            .filter(|token| token.kind == TokenKind::Ident)
+            .map(|&token| context.map.spelling(token))
+            .collect();
+        assert_eq!(output, [format!("unit{}", i)]);
+    }
+    assert!(results[16].is_err());
+}
+
+#[test]
+fn batch_sources() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("common.h", "#define COMMON 1\n");
+    let sources: Vec<_> = (0..8)
+        .map(|i| {
+            let source = format!("#include \"common.h\"\n#if COMMON\nunit{}\n#endif\n", i);
+            (format!("unit{}.c", i), source)
+        })
+        .collect();
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let results = Batch::new(preprocessor)
+        .threads(NonZeroUsize::new(3).unwrap())
+        .preprocess_sources(&sources);
+
+    let results = results.into_results();
+    assert_eq!(results.len(), 8);
+    for (i, result) in results.iter().enumerate() {
+        let preprocessed = result.as_ref().unwrap();
+        let text = Emitter::new().render(preprocessed);
+        assert!(text.starts_with(format!("# 1 \"unit{}.c\"", i).as_bytes()));
+        assert!(text.ends_with(format!("unit{}\n\n", i).as_bytes()));
+        assert_eq!(preprocessed.dependencies(), [PathBuf::from("common.h")]);
+    }
+}
+
+#[test]
+fn session() {
+    let header = temp_file("session.h", "#define HEADER 1\n");
+    let source = format!("#include \"{}\"\nHEADER LOCAL\n", header.display());
+
+    let mut session = Session::new(Preprocessor::new());
+    let first = session
+        .preprocess_source(
+            &"first.c",
+            format!("#define LOCAL 2\n{}", source).as_bytes(),
+        )
+        .unwrap();
+    assert_eq!(first.dependencies(), std::slice::from_ref(&header));
+    assert_eq!(first.statistics().cache_hits(), 0);
+
+    // The header is not read again, so changes made to it are not seen.
+    std::fs::write(&header, "#define HEADER 33\n").unwrap();
+    let second = session
+        .preprocess_source(&"second.c", source.as_bytes())
+        .unwrap();
+    let text = Emitter::new().line_markers(false).render(&second);
+    assert_eq!(String::from_utf8(text).unwrap(), "1 LOCAL\n");
+    assert_eq!(second.dependencies(), std::slice::from_ref(&header));
+    assert_eq!(second.statistics().files_read(), 1);
+    assert_eq!(second.statistics().cache_hits(), 1);
+
+    // Once it is invalidated, the header is read again.
+    assert_eq!(session.invalidate_stale(), std::slice::from_ref(&header));
+    assert!(session.invalidate_stale().is_empty());
+    let edited = session
+        .preprocess_source(&"edited.c", source.as_bytes())
+        .unwrap();
+    let text = Emitter::new().line_markers(false).render(&edited);
+    assert_eq!(String::from_utf8(text).unwrap(), "33 LOCAL\n");
+    assert_eq!(edited.dependencies(), [header]);
+    assert_eq!(edited.statistics().cache_hits(), 0);
+
+    // Only the files of a translation unit are kept, not the results of `#` and `##`.
+    let source = b"#define STR(x) #x\n#define CAT(a, b) a ## b\nSTR(a) CAT(b, c)\n";
+    let regions = |session: &mut Session| {
+        let preprocessed = session.preprocess_source(&"regions.c", source).unwrap();
+        preprocessed.source_map().regions().len()
+    };
+    assert_eq!(regions(&mut session), regions(&mut session));
+
+    assert!(session.preprocess_file(&"session-missing.c").is_err());
+    let third = session.preprocess_source(&"third.c", b"LOCAL\n").unwrap();
+    assert!(third.dependencies().is_empty());
+}
+
+#[test]
+fn cache_key() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"config.h\"\nVERSION\n")
+        .insert("config.h", "#define VERSION 1\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files.clone());
+
+    let key = |preprocessor: &Preprocessor| {
+        let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+        let key = preprocessor.cache_key(&preprocessed);
+        let dependencies = preprocessed.dependencies();
+        assert_eq!(preprocessor.file_cache_key(&dependencies).unwrap(), key);
+        key
+    };
+
+    let original = key(&preprocessor);
+    assert_eq!(key(&preprocessor), original);
+    assert_eq!(original.to_string().len(), 64);
+
+    preprocessor.concatenate_strings(true);
+    assert_ne!(key(&preprocessor), original);
+    preprocessor.concatenate_strings(false);
+
+    preprocessor.define("DEBUG");
+    let defined = key(&preprocessor);
+    assert_ne!(defined, original);
+
+    files.insert("config.h", "#define VERSION 2\n");
+    preprocessor.file_system(files);
+    assert_ne!(key(&preprocessor), defined);
+
+    let source = b"#define A 1\n";
+    let preprocessor = Preprocessor::new();
+    let first = preprocessor.cache_key(&preprocessor.preprocess(source).unwrap());
+    let second = preprocessor.cache_key(&preprocessor.preprocess(b"#define A 2\n").unwrap());
+    assert_ne!(first, second);
+}
+
+#[test]
+fn content_hashes() {
+    let vectors = [
+        (
+            &b""[..],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        (
+            &[b'a'; 1_000_000],
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+        ),
+    ];
+    for (contents, hash) in vectors {
+        assert_eq!(ContentHash::of(contents).to_string(), hash);
+    }
+
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"config.h\"\nVERSION\n")
+        .insert("config.h", "#define VERSION 1\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files.clone());
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert_eq!(
+        preprocessed.file_hashes(),
+        [
+            (
+                PathBuf::from("main.c"),
+                ContentHash::of(b"#include \"config.h\"\nVERSION\n")
+            ),
+            (
+                PathBuf::from("config.h"),
+                ContentHash::of(b"#define VERSION 1\n")
+            ),
+        ]
+    );
+    let original = preprocessed.content_hash();
+    // The configuration is not part of the hash.
+    preprocessor.define("DEBUG");
+    let defined = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert_eq!(defined.content_hash(), original);
+
+    files.insert("config.h", "#define VERSION 2\n");
+    preprocessor.file_system(files);
+    let changed = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert_ne!(changed.content_hash(), original);
+
+    let first = Preprocessor::new().preprocess(b"int a;\n").unwrap();
+    let second = Preprocessor::new().preprocess(b"int b;\n").unwrap();
+    assert!(first.file_hashes().is_empty());
+    assert_ne!(first.content_hash(), second.content_hash());
+}
+
+#[test]
+fn rewrite_includes() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#include \"a.h\"\n#if 0\n#include \"missing.h\"\n#endif\n#include <sys.h>\nA B",
+        )
+        .insert("a.h", "#pragma once\n#include \"b.h\"\n")
+        .insert("b.h", "#define A a\n")
+        .insert("system/sys.h", "#define B b\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).system_include_dir("system");
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let rewritten = preprocessed.rewrite_includes();
+
+    assert_eq!(
+        String::from_utf8(rewritten.clone()).unwrap(),
+        "# 1 \"main.c\"\n\
+         #if 0 /* expanded by -frewrite-includes */\n\
+         #include \"a.h\"\n\
+         #endif /* expanded by -frewrite-includes */\n\
+         # 1 \"a.h\" 1\n\
+         #if 0 /* expanded by -frewrite-includes */\n\
+         #pragma once\n\
+         #endif /* expanded by -frewrite-includes */\n\
+         # 2 \"a.h\"\n\
+         #if 0 /* expanded by -frewrite-includes */\n\
+         #include \"b.h\"\n\
+         #endif /* expanded by -frewrite-includes */\n\
+         # 1 \"b.h\" 1\n\
+         #define A a\n\
+         # 3 \"a.h\" 2\n\
+         # 2 \"main.c\" 2\n\
+         #if 0 /* expanded by -frewrite-includes */\n\
+         #include \"a.h\"\n\
+         #endif /* expanded by -frewrite-includes */\n\
+         # 3 \"main.c\"\n\
+         #if 0\n\
+         #include \"missing.h\"\n\
+         #endif\n\
+         #if 0 /* expanded by -frewrite-includes */\n\
+         #include <sys.h>\n\
+         #endif /* expanded by -frewrite-includes */\n\
+         # 1 \"system/sys.h\" 1 3\n\
+         #define B b\n\
+         # 7 \"main.c\" 2\n\
+         A B"
+    );
+
+    // The rewritten file does not need any other file.
+    let context = Preprocessor::new().preprocess(&rewritten).unwrap().context;
+    let output: Vec<_> = context
+        .output
+        .tokens()
+        .iter()
+        .filter(|token| token.kind == TokenKind::Ident)
+        .map(|&token| context.map.spelling(token))
+        .collect();
+    assert_eq!(output, ["a", "b"]);
+
+    // Preprocessing the rewritten file produces the same text.
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.c", rewritten);
+    let mut rewritten_preprocessor = Preprocessor::new();
+    rewritten_preprocessor.file_system(files);
+    let rewritten = rewritten_preprocessor.preprocess_file(&"main.c").unwrap();
+    let mut emitter = Emitter::new();
+    emitter.line_markers(false);
+    assert_eq!(
+        String::from_utf8(emitter.render(&rewritten)).unwrap(),
+        String::from_utf8(emitter.render(&preprocessed)).unwrap()
+    );
+}
+
+#[test]
+fn token_cache() {
+    let dir = std::env::temp_dir()
+        .join("beheader-tests")
+        .join("token_cache");
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#include \"b.h\"\n#include \"a.h\"\n#include \"c.h\"\nA B C\n",
+        )
+        .insert("a.h", "#ifndef A\n#define A a\n#endif\n")
+        // Skipped groups can contain invalid tokens, so this file cannot be cached.
+        .insert("b.h", "#if 0\ndon't\n#endif\n#define B b\n")
+        .insert(
+            "c.h",
+            "#if !defined(C)\n#define C c\n#else\n#error\n#endif\n",
+        );
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).token_cache(&dir);
+    let output = || {
+        let context = preprocessor.preprocess_file(&"main.c").unwrap().context;
+        context
+            .output
+            .tokens()
+            .iter()
+            .map(|&token| (token.kind, context.map.spelling(token)))
+            .collect::<Vec<_>>()
+    };
+
+    let uncached = output();
+    let cached = std::fs::read_dir(&dir).unwrap().count();
+    assert!(cached > 0);
+    assert_eq!(output(), uncached);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), cached);
+
+    // The second `#include "a.h"` is skipped because of its include guard.
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let conditionals = preprocessed.conditionals();
+    let directives: Vec<_> = conditionals
+        .iter()
+        .map(|conditional| conditional.branches()[0].condition())
+        .collect();
+    assert_eq!(directives, ["A", "0", "!defined(C)"]);
+
+    let map = SourceMap::default();
+    let info = |source: &str| {
+        let span = map.store_bytes(source.as_bytes()).unwrap();
+        let tokens = Lines::tokenize(&map, span).unwrap();
+        FileInfo::new(&map, span, &tokens)
+    };
+    let guard = |guard: &str| FileInfo {
+        guard: Some(guard.to_owned()),
+        once: false,
+    };
+    assert_eq!(
+        info("\n#ifndef A\n#if 1\n#else\n#endif\n#endif\n\n"),
+        guard("A")
+    );
+    assert_eq!(info("#if ! defined ( A )\n#endif"), guard("A"));
+    assert!(info("#pragma once\n#ifndef A\n#endif\n").once);
+    for source in [
+        "#ifndef A\n#endif\nint a;\n",
+        "int a;\n#ifndef A\n#endif\n",
+        "#ifndef A\n#else\n#endif\n",
+        "#ifndef A\n#endif\n#ifndef B\n#endif\n",
+        "#if !defined A || 1\n#endif\n",
+        "#ifdef A\n#endif\n",
+        "#ifndef A\n",
+    ] {
+        assert_eq!(info(source), FileInfo::default(), "{}", source);
+    }
+
+    // Invalid cache files are ignored.
+    for entry in std::fs::read_dir(&dir).unwrap() {
+        std::fs::write(entry.unwrap().path(), "invalid").unwrap();
+    }
+    assert_eq!(output(), uncached);
+}
+
+#[test]
+fn header_cache() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("a.c", "#include \"common.h\"\n#include \"common.h\"\nA B C\n")
+        .insert("b.c", "#define SECOND\n#include \"common.h\"\n#include \"bad.h\"\nA B C\n")
+        .insert(
+            "common.h",
+            "#ifndef SECOND\n#define A a /*\n*/\n#define SECOND\n#else\n#define B b\n#endif\n#define C c\n",
+        )
+        // Skipped groups can contain invalid tokens, so this file is not tokenized in advance.
+        .insert("bad.h", "#if 0\ndon't\n#endif\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let output = |preprocessor: &Preprocessor, path: &str| {
+        let context = preprocessor.preprocess_file(&path).unwrap().context;
+        context
+            .output
+            .tokens()
+            .iter()
+            .map(|&token| (token.kind, context.map.spelling(token)))
+            .collect::<Vec<_>>()
+    };
+    let uncached = [output(&preprocessor, "a.c"), output(&preprocessor, "b.c")];
+
+    let cache = HeaderCache::new();
+    preprocessor.header_cache(cache.clone());
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = ["a.c", "b.c"]
+            .into_iter()
+            .map(|path| scope.spawn(|| output(&preprocessor, path)))
+            .collect();
+        let cached: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(cached, uncached);
+    });
+    assert_eq!(cache.len(), 4);
+
+    let first = preprocessor.preprocess_file(&"a.c").unwrap();
+    let second = preprocessor.preprocess_file(&"a.c").unwrap();
+    let span = first.context.map.read_file(&"common.h").unwrap();
+    assert!(Arc::ptr_eq(
+        &first.context.map.get_contents(span).unwrap(),
+        &second.context.map.get_contents(span).unwrap()
+    ));
+
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn emitter() {
+    let source = "#define P +\n#define E\n#define DOT .\nP+ -P x E y 1 E.5 a/**/b DOT.DOT <P:\n";
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(
+        String::from_utf8(text.clone()).unwrap(),
+        "+ + -+ x y 1 .5 a b .. . <+:\n"
+    );
+
+    // The text has the same tokens as the result of preprocessing.
+    let spellings = |source: &[u8]| {
+        let context = Preprocessor::new().preprocess(source).unwrap().context;
+        context
+            .output
+            .tokens()
+            .iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+            .map(|&token| context.map.spelling(token))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(spellings(&text), spellings(source.as_bytes()));
+}
+
+#[test]
+fn line_markers() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#define F(x) x\nint b = F(\n1\n\n\n\n\n\n\n\n\n\n);\nint c;\n#include <s.h>\n",
+        )
+        .insert("a.h", "\n#include \"empty.h\"\nint a;\n")
+        .insert("empty.h", "")
+        .insert("sys/s.h", "int s;\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).system_include_dir("sys");
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+
+    let text = Emitter::new().render(&preprocessed);
+    assert_eq!(
+        String::from_utf8(text).unwrap().lines().collect::<Vec<_>>(),
+        [
+            "# 1 \"main.c\"",
+            "# 1 \"a.h\" 1",
+            "",
+            "# 1 \"empty.h\" 1",
+            "# 3 \"a.h\" 2",
+            "int a;",
+            "# 2 \"main.c\" 2",
+            "",
+            "int b = 1;",
+            "# 15 \"main.c\"",
+            "int c;",
+            "# 1 \"sys/s.h\" 1 3",
+            "int s;",
+            "# 17 \"main.c\" 2",
+        ]
+    );
+
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "int a;\n\nint b = 1;\nint c;\nint s;\n"
+    );
+}
+
+#[test]
+fn stream() {
+    // The output is long enough to be written in several chunks, which start and end in the
+    // middle of included files.
+    let mut main = String::new();
+    for i in 0..600 {
+        main.push_str(&format!("int a{} = A; // {}\n", i, i));
+        if i % 50 == 0 {
+            main.push_str("#include \"a.h\"\n#if 0\n\n\n\n\n\n\n\n\n\n#endif\n");
+        }
+    }
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", main)
+        .insert("a.h", "#include <s.h>\nint b = A;\n".repeat(40))
+        .insert("first.h", "int first;\n")
+        .insert("sys/s.h", "#define A s\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .system_include_dir("sys")
+        .include("first.h")
+        .keep_comments(Comments::Text);
+
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let mut without_markers = Emitter::new();
+    without_markers.line_markers(false);
+    for emitter in [Emitter::new(), without_markers] {
+        let path = temp_file("stream.i", "");
+        let file = std::fs::File::create(&path).unwrap();
+        let streamed = emitter.stream_file(&preprocessor, &"main.c", file).unwrap();
+        assert_eq!(
+            String::from_utf8(std::fs::read(&path).unwrap()).unwrap(),
+            String::from_utf8(emitter.render(&preprocessed)).unwrap()
+        );
+        assert!(emitter.render(&streamed).is_empty());
+        assert_eq!(streamed.get_macro("A").unwrap().to_string(), "#define A s");
+    }
+}
+
+#[test]
+fn events() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "int a;\n#include \"a.h\"\nint c = B;\n#undef A\n#endif\n",
+        )
+        .insert(
+            "a.h",
+            "#define B 2\n#ifdef B\n#elif A\n#else\n#endif B\nint b;\n",
+        );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+
+    // The tokens between the other events are joined, and the directives keep their new-line
+    // characters before their events.
+    let mut events = preprocessor.events_file(&"main.c");
+    let mut described = Vec::new();
+    let mut text = String::new();
+    while let Some(event) = events.next() {
+        let description = match event {
+            Event::Token(token) => {
+                text.push_str(std::str::from_utf8(events.spelling(token).unwrap()).unwrap());
+                continue;
+            }
+            Event::EnterFile(path) => format!("enter {}", path.unwrap().display()),
+            Event::ExitFile(path) => format!("exit {}", path.unwrap().display()),
+            Event::MacroDefined(name, definition) => format!("{}: {}", name, definition),
+            Event::ConditionalSkipped(branch) => format!("skip #{}", branch.directive()),
+            Event::Diagnostic(diagnostic) => diagnostic.message().to_owned(),
+        };
+        described.push(std::mem::take(&mut text));
+        described.push(description);
+    }
+    described.push(text);
+    let preprocessed = events.finish().unwrap();
+    assert_eq!(
+        preprocessed.diagnostics().last().unwrap().code(),
+        DiagnosticCode::UnmatchedConditional
+    );
+    assert_eq!(
+        described,
+        [
+            "",
+            "enter main.c",
+            "int a;\n",
+            "enter a.h",
+            "\n",
+            "B: #define B 2",
+            "\n\n",
+            "skip #elif",
+            "\n",
+            "skip #else",
+            "\n",
+            "Extra tokens at end of #endif directive",
+            "int b;\n\n",
+            "exit a.h",
+            "int c = 2;\n\n\n",
+            "#endif without #if",
+            "",
+            "exit main.c",
+            "",
+        ]
+    );
+
+    let mut events = Preprocessor::new().events(b"#define A 1\nA\n");
+    assert!(matches!(events.next(), Some(Event::EnterFile(None))));
+    let preprocessed = events.finish().unwrap();
+    assert!(preprocessed.tokens().is_empty());
+    assert_eq!(
+        preprocessed.get_macro("A").unwrap().to_string(),
+        "#define A 1"
+    );
+}
+
+#[test]
+fn position_map() {
+    let sources = [
+        (
+            "main.c",
+            "#include \"a.h\"\n#define F(x) [x]\nint b = F(A)\n  + A;\n#define S(x) #x\nS(a)\n",
+        ),
+        ("a.h", "\n#define A\\\n  1\nint a;\n"),
+    ];
+    let mut files = MemoryFileSystem::new();
+    for (path, contents) in sources {
+        files.insert(path, contents);
+    }
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let (text, map) = Emitter::new().render_with_map(&preprocessed);
+    assert_eq!(text, Emitter::new().render(&preprocessed));
+
+    let text = String::from_utf8(text).unwrap();
+    let lines: Vec<_> = text.lines().collect();
+    let mut located = Vec::new();
+    for token in map.tokens() {
+        let line = lines[token.output_line() - 1];
+        let start = token.output_column() - 1;
+        let spelling = &line[start..start + token.len()];
+        assert_eq!(map.find(token.output_line(), start + 1), Some(token));
+
+        match token.file() {
+            Some(file) => {
+                let (_, contents) = sources.iter().find(|(path, _)| file == *path).unwrap();
+                let line = contents.split('\n').nth(token.line().unwrap() - 1).unwrap();
+                let start = token.column().unwrap() - 1;
+                assert_eq!(&line[start..start + token.len()], spelling);
+                located.push((spelling, file.to_str().unwrap(), token.line().unwrap()));
+            }
+            None => located.push((spelling, "", 0)),
+        }
+    }
+    assert_eq!(
+        located,
+        [
+            ("int", "a.h", 4),
+            ("a", "a.h", 4),
+            (";", "a.h", 4),
+            ("int", "main.c", 3),
+            ("b", "main.c", 3),
+            ("=", "main.c", 3),
+            ("[", "main.c", 2),
+            ("1", "a.h", 3),
+            ("]", "main.c", 2),
+            ("+", "main.c", 4),
+            ("1", "a.h", 3),
+            (";", "main.c", 4),
+            ("\"a\"", "", 0),
+        ]
+    );
+
+    let json = map.to_json();
+    assert!(json.starts_with(
+        "{\"tokens\":[{\"output_line\":6,\"output_column\":1,\"length\":3,\"file\":\"a.h\",\"line\":4,\"column\":1},"
+    ));
+    assert!(json.ends_with("\"length\":3,\"file\":null,\"line\":null,\"column\":null}]}"));
+}
+
+#[test]
+fn source_map_snapshot() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#include \"empty.h\"\n#define S(x) #x\nS(A)\n",
+        )
+        .insert("a.h", "#define A a\n")
+        .insert("empty.h", "");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let snapshot = preprocessed.source_map();
+
+    let paths: Vec<_> = snapshot
+        .regions()
+        .iter()
+        .filter_map(|region| region.path())
+        .collect();
+    for path in ["<built-in>", "<command-line>", "main.c", "a.h", "empty.h"] {
+        assert!(paths.iter().any(|other| *other == path));
+    }
+    let spellings: Vec<_> = preprocessed
+        .tokens()
+        .iter()
+        .filter(|token| !matches!(token.kind(), TokenKind::Space | TokenKind::Newline))
+        .map(|token| snapshot.spelling(token.span()).unwrap())
+        .collect();
+    assert_eq!(spellings, [b"\"A\""]);
+    let region = snapshot.regions().last().unwrap();
+    assert_eq!(region.path(), None);
+    assert_eq!(region.contents(), b"\"A\"");
+}
+
+#[test]
+fn reader_input() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("a.h", "int a;\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let source = &b"#include \"a.h\"\n#if 1 / 0\n#endif\n"[..];
+    let preprocessed = preprocessor.preprocess_reader(source, &"<stdin>").unwrap();
+    assert_eq!(preprocessed.dependencies(), [PathBuf::from("a.h")]);
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.file(), Some(&PathBuf::from("<stdin>")));
+    assert_eq!(diagnostic.line(), Some(2));
+
+    struct Failing;
+    impl std::io::Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+    assert!(matches!(
+        preprocessor.preprocess_reader(Failing, &"<stdin>"),
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn source_map_files() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"empty.h\"\n#include \"a.h\"\n#if 1 / 0\n#endif\n",
+        )
+        .insert("empty.h", "")
+        .insert("a.h", "#include \"empty.h\"\n#if 2 / 0\n#endif\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+
+    let locations: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            let file = diagnostic.file().unwrap().to_str().unwrap().to_owned();
+            (file, diagnostic.line(), diagnostic.column())
+        })
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            ("a.h".to_owned(), Some(2), Some(9)),
+            ("main.c".to_owned(), Some(3), Some(9)),
+        ]
+    );
+    let snapshot = preprocessed.source_map();
+    let empty = snapshot
+        .regions()
+        .iter()
+        .find(|region| region.path().is_some_and(|path| path == "empty.h"))
+        .unwrap()
+        .span();
+    let location = preprocessed.resolve_span(empty).unwrap();
+    assert_eq!(snapshot.resolve(empty), Some(location.clone()));
+    assert_eq!(location.file().unwrap().to_str(), Some("empty.h"));
+    assert_eq!((location.start_line(), location.start_column()), (1, 1));
+}
+
+#[test]
+fn stored_source_map() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"a.h\"\n#include \"empty.h\"\nint b;\n")
+        .insert("a.h", "#define S(x) #x\nconst char *a = S(a);\n")
+        .insert("empty.h", "");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let snapshot = preprocessed.source_map();
+    let mut bytes = Vec::new();
+    snapshot.write_to(&mut bytes).unwrap();
+
+    let loaded = SourceMapSnapshot::read_from(&bytes[..]).unwrap();
+    assert_eq!(loaded, snapshot);
+    for token in preprocessed.tokens() {
+        let span = token.span();
+        assert_eq!(loaded.spelling(span), snapshot.spelling(span));
+        assert_eq!(loaded.resolve(span), snapshot.resolve(span));
+    }
+
+    assert!(SourceMapSnapshot::read_from(&bytes[..bytes.len() - 1]).is_err());
+    assert!(SourceMapSnapshot::read_from(&b"BHSMAP02"[..]).is_err());
+    // The last region has a single line, which must start at its start.
+    let mut wrong_line = bytes.clone();
+    let last = wrong_line.len() - 4;
+    wrong_line[last] = 1;
+    assert!(SourceMapSnapshot::read_from(&wrong_line[..]).is_err());
+}
+
+#[test]
+fn span_size() {
+    assert_eq!(std::mem::size_of::<Span>(), 8);
+    assert_eq!(std::mem::size_of::<crate::Token>(), 16);
+
+    let preprocessed = Preprocessor::new().preprocess(b"int a;\n").unwrap();
+    let tokens = preprocessed.tokens();
+    let span = tokens[2].span();
+    assert_eq!(span.hi() - span.lo(), 1);
+    assert_eq!(preprocessed.source_map().spelling(span), Some(&b"a"[..]));
+}
+
+#[test]
+fn symbols() {
+    let mut files = MemoryFileSystem::new();
+    files.insert("a.h", "#define A(x) x + defined\nA(B) B\n");
+    let cache = HeaderCache::new();
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files).header_cache(cache);
+
+    // The second run takes the tokens of the header from the cache, which are interned again.
+    for _ in 0..2 {
+        let preprocessed = preprocessor
+            .preprocess(b"#include \"a.h\"\n#ifdef A\nint defined;\n#endif\n")
+            .unwrap();
+        let map = &preprocessed.context.map;
+        let idents: Vec<_> = preprocessed
+            .tokens()
+            .iter()
+            .filter(|token| token.kind == TokenKind::Ident)
+            .collect();
+        assert_eq!(idents.len(), 5);
+        assert!(idents.iter().all(|token| token.symbol.is_some()));
+        assert_eq!(idents[0].symbol, idents[2].symbol);
+        assert_ne!(idents[0].symbol, idents[1].symbol);
+        assert_eq!(idents[1].symbol, Some(Symbol::DEFINED));
+        assert_eq!(idents[4].symbol, Some(Symbol::DEFINED));
+        assert_eq!(map.find_symbol(b"B"), idents[0].symbol);
+        assert_eq!(map.find_symbol(b"C"), None);
+        assert!(preprocessed.get_macro("A").is_some());
+    }
+}
+
+#[test]
+fn token_table() {
+    let mut table = TokenTable::new();
+    assert_eq!(table.insert(5, 'a'), None);
+    assert_eq!(table.insert(1, 'b'), None);
+    assert_eq!(table.insert(3, 'c'), None);
+    assert_eq!(table.insert(5, 'd'), Some('a'));
+    *table.get_mut(1).unwrap() = 'e';
+    assert_eq!(
+        table.iter().collect::<Vec<_>>(),
+        [(1, &'e'), (3, &'c'), (5, &'d')]
+    );
+    assert_eq!(table.remove(3), Some('c'));
+    assert_eq!(table.remove(3), None);
+    assert_eq!(table.len(), 2);
+
+    let preprocessed = Preprocessor::new().preprocess(b"int a;\n").unwrap();
+    let info = preprocessed.token_info(0).unwrap();
+    assert_eq!(info.token(), preprocessed.tokens()[0]);
+    assert_eq!(info.file(), None);
+    assert!(preprocessed
+        .token_info(preprocessed.tokens().len())
+        .is_none());
+}
+
+#[test]
+fn shared_file_contents() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"a.h\"\nint b;\n")
+        .insert("a.h", "int a;\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+
+    let (first, second) = (preprocessed.source_map(), preprocessed.source_map());
+    assert_eq!(first, second);
+    let header = |snapshot: &SourceMapSnapshot| {
+        let regions = snapshot.regions().iter();
+        let mut headers = regions.filter(|region| region.path() == Some(&PathBuf::from("a.h")));
+        headers.next().unwrap().contents().as_ptr()
+    };
+    assert_eq!(header(&first), header(&second));
+}
+
+#[test]
+fn span_resolution() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#define S(x) #x\nint\n  b = S(A);\n",
+        )
+        .insert("a.h", "#define A a\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let snapshot = preprocessed.source_map();
+
+    let locations: Vec<_> = preprocessed
+        .tokens()
+        .iter()
+        .filter(|token| !matches!(token.kind(), TokenKind::Space | TokenKind::Newline))
+        .map(|token| {
+            let location = preprocessed.resolve_span(token.span()).unwrap();
+            assert_eq!(snapshot.resolve(token.span()), Some(location.clone()));
+            let file = location
+                .file()
+                .map(|file| file.to_str().unwrap().to_owned());
+            let start = (location.start_line(), location.start_column());
+            let end = (location.end_line(), location.end_column());
+            (file, start, end)
+        })
+        .collect();
+    let main = || Some("main.c".to_owned());
+    assert_eq!(
+        locations,
+        [
+            (main(), (3, 1), (3, 4)),
+            (main(), (4, 3), (4, 4)),
+            (main(), (4, 5), (4, 6)),
+            (None, (1, 1), (1, 4)),
+            (main(), (4, 11), (4, 12)),
+        ]
+    );
+
+    let main = preprocessed.tokens()[0].span();
+    let stringized = preprocessed.tokens().last().unwrap().span();
+    let across = main.join(stringized);
+    assert_eq!(preprocessed.resolve_span(across), None);
+    assert_eq!(snapshot.resolve(across), None);
+}
+
+#[test]
+fn column_units() {
+    let source = "\t/* \u{1f600} */ int a;\n#if 1 / 0\n#endif\n";
+    let columns = |unit| {
+        let mut files = MemoryFileSystem::new();
+        files.insert("main.c", source);
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.file_system(files).column_unit(unit);
+        let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+        let token = preprocessed
+            .tokens()
+            .iter()
+            .find(|token| token.kind() == TokenKind::Ident)
+            .unwrap();
+        let location = preprocessed.resolve_span(token.span()).unwrap();
+        assert_eq!(
+            preprocessed.source_map().resolve_with(token.span(), unit),
+            Some(location.clone())
+        );
+        let diagnostic = &preprocessed.diagnostics()[0];
+        (
+            location.start_column(),
+            location.end_column(),
+            diagnostic.column().unwrap(),
+        )
+    };
+    assert_eq!(columns(ColumnUnit::Bytes), (13, 16, 9));
+    assert_eq!(columns(ColumnUnit::Chars), (10, 13, 9));
+    assert_eq!(columns(ColumnUnit::Utf16), (11, 14, 9));
+    assert_eq!(columns(ColumnUnit::Display { tab_width: 4 }), (13, 16, 9));
+    assert_eq!(columns(ColumnUnit::Display { tab_width: 0 }), (10, 13, 9));
+}
+
+#[test]
+fn input_charsets() {
+    let source = "/* caf\u{e9} */ int a;\n#if 1 / 0\n#endif\n";
+    let utf16 = |le: bool, bom: bool| -> Vec<u8> {
+        let bom = if bom { "\u{feff}" } else { "" };
+        let units = bom.encode_utf16().chain(source.encode_utf16());
+        let bytes = units.flat_map(|unit| {
+            if le {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            }
+        });
+        bytes.collect()
+    };
+    let latin1: Vec<u8> = source.chars().map(|ch| ch as u8).collect();
+    let preprocess = |charset: &str, contents: Vec<u8>| {
+        let mut files = MemoryFileSystem::new();
+        files.insert("main.c", contents);
+        let mut preprocessor = Preprocessor::new();
+        preprocessor
+            .file_system(files)
+            .input_charset(InputCharset::from_name(charset).unwrap());
+        let preprocessed = preprocessor.preprocess_file(&"main.c")?;
+        let key = preprocessor.cache_key(&preprocessed);
+        assert_eq!(preprocessor.file_cache_key(&["main.c"]).unwrap(), key);
+        let snapshot = preprocessed.source_map();
+        let main = Some(&PathBuf::from("main.c"));
+        let mut regions = snapshot.regions().iter();
+        let region = regions.find(|region| region.path() == main).unwrap();
+        assert_eq!(region.contents(), source.as_bytes());
+        Ok::<_, Error>(preprocessed.diagnostics()[0].line())
+    };
+
+    assert_eq!(preprocess("UTF-8", source.into()).unwrap(), Some(2));
+    assert_eq!(preprocess("utf-16", utf16(true, true)).unwrap(), Some(2));
+    assert_eq!(preprocess("UTF-16", utf16(false, true)).unwrap(), Some(2));
+    assert_eq!(preprocess("UTF-16", utf16(false, false)).unwrap(), Some(2));
+    assert_eq!(preprocess("UTF-16LE", utf16(true, false)).unwrap(), Some(2));
+    assert_eq!(preprocess("UTF16BE", utf16(false, true)).unwrap(), Some(2));
+    assert_eq!(preprocess("ISO-8859-1", latin1.clone()).unwrap(), Some(2));
+    assert_eq!(preprocess("latin1", latin1).unwrap(), Some(2));
+    let mut odd = utf16(true, true);
+    odd.pop();
+    assert!(matches!(preprocess("UTF-16LE", odd), Err(Error::Io(_))));
+    assert!(matches!(
+        preprocess("UTF-16LE", vec![0x00, 0xD8, b'a', 0]),
+        Err(Error::Io(_))
+    ));
+    assert_eq!(InputCharset::from_name("EBCDIC"), None);
+}
+
+#[test]
+fn source_snippets() {
+    let preprocessed = Preprocessor::new()
+        .preprocess(b"int a;\r\nint b;\r\nint c;\r\nint d;")
+        .unwrap();
+    let snapshot = preprocessed.source_map();
+    let tokens = preprocessed.tokens();
+    let lines = |span, context_lines| {
+        let snippet = snapshot.snippet(span, context_lines).unwrap();
+        assert_eq!(snapshot.spelling(snippet.span()), Some(snippet.text()));
+        let lines: Vec<_> = snippet
+            .lines()
+            .map(|(number, line)| (number, String::from_utf8(line.to_vec()).unwrap()))
+            .collect();
+        lines
+    };
+    let line = |number: usize| {
+        (
+            number,
+            format!("int {};", (b'a' + number as u8 - 1) as char),
+        )
+    };
+
+    let (a, b) = (tokens[0].span(), tokens[6].span());
+    assert_eq!(lines(a, 0), [line(1)]);
+    assert_eq!(lines(a, 1), [line(1), line(2)]);
+    assert_eq!(lines(b, 5), [line(1), line(2), line(3), line(4)]);
+    assert_eq!(lines(a.join(b), 0), [line(1), line(2)]);
+    // The new-line token of the first line ends at the start of the second line.
+    let newline = tokens[4].span();
+    assert_eq!(snapshot.spelling(newline), Some(&b"\r\n"[..]));
+    assert_eq!(lines(newline, 0), [line(1)]);
+    assert_eq!(lines(tokens.last().unwrap().span(), 1), [line(3), line(4)]);
+
+    let across = Span::new(0, snapshot.regions().last().unwrap().span().hi() + 1);
+    assert_eq!(snapshot.snippet(across, 0), None);
+}
+
+#[test]
+fn macro_expansions() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("first.h", "#define FIRST first\nint FIRST;\n")
+        .insert(
+            "main.c",
+            "#define S(x) #x \"!\"\n#define ID(x) x\n#define F(x) ID(S(x)) x\nF(a) \"b\" FIRST\n",
+        );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .include("first.h")
+        .concatenate_strings(true);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let snapshot = preprocessed.source_map();
+    let spelling = |span| String::from_utf8(snapshot.spelling(span).unwrap().to_vec()).unwrap();
+    let location = |span| {
+        let location = preprocessed.resolve_span(span).unwrap();
+        (location.start_line(), location.start_column())
+    };
+
+    let expansions: Vec<_> = preprocessed
+        .tokens()
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| !matches!(token.kind(), TokenKind::Space | TokenKind::Newline))
+        .map(|(index, &token)| {
+            let chain: Vec<_> = std::iter::successors(preprocessed.expansion(index), |expansion| {
+                expansion.parent()
+            })
+            .map(|expansion| {
+                assert_eq!(spelling(expansion.call_site()), expansion.name());
+                assert_eq!(spelling(expansion.definition()), expansion.name());
+                expansion.name().to_owned()
+            })
+            .collect();
+            let site = location(preprocessed.expansion_site(index));
+            (spelling(token.span()), chain, site)
+        })
+        .collect();
+    let names = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        expansions,
+        [
+            ("int".to_owned(), names(&[]), (2, 1)),
+            ("first".to_owned(), names(&["FIRST"]), (2, 5)),
+            (";".to_owned(), names(&[]), (2, 10)),
+            // The string literals produced by `S` and `ID` are concatenated into the first one.
+            ("\"a!\"".to_owned(), names(&["S", "F"]), (4, 1)),
+            ("a".to_owned(), names(&[]), (4, 3)),
+            ("\"b\"".to_owned(), names(&[]), (4, 6)),
+            ("first".to_owned(), names(&["FIRST"]), (4, 10)),
+        ]
+    );
+}
+
+#[test]
+fn gcc_profile() {
+    // The expected text was produced by `gcc -E -undef -isystem sys -include first.h main.c`.
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#define PLUS +\n#define STR(x) #x\nint f() {\n\treturn PLUS+1;\n}\n\
+             const char *s = STR(a  +  b);\n#include \"a.h\"\n#include <s.h>\n\n\n\n\n\n\n\n\n\
+             int last;\n",
+        )
+        .insert("a.h", "#pragma once\n\n  int a;\n")
+        .insert("first.h", "int first;\n")
+        .insert("sys/s.h", "\n  int s;\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .system_include_dir("sys")
+        .include("first.h");
+
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let text = Emitter::new()
+        .profile(OutputProfile::Gcc)
+        .render(&preprocessed);
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "# 0 \"main.c\"\n# 0 \"<built-in>\"\n# 0 \"<command-line>\"\n# 1 \"./first.h\" 1\n\
+         int first;\n# 0 \"<command-line>\" 2\n# 1 \"main.c\"\n# 1 \"a.h\" 1\n       \n\n  int a;\n\
+         # 2 \"main.c\" 2\n\n\nint f() {\n return + +1;\n}\nconst char *s = \"a + b\";\n\n\
+         # 1 \"sys/s.h\" 1 3 4\n\n  \n# 2 \"sys/s.h\" 3 4\n int s;\n# 10 \"main.c\" 2\n\
+         # 18 \"main.c\"\n\n# 18 \"main.c\"\nint last;\n"
+    );
+}
+
+#[test]
+fn gcc_profile_lines() {
+    // The expected text was produced by `gcc -E -undef -nostdinc main.c`.
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"g.h\"\n#define F(a, b) a b\nint x = F(1,\n  2) + 3;\n#include \"g.h\"\n\
+             #include \"g.h\"\nint y;\n",
+        )
+        .insert("g.h", "#ifndef G\n#define G\nint g;\n#endif\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let text = Emitter::new()
+        .profile(OutputProfile::Gcc)
+        .render(&preprocessed);
+    // The file guarded by `G` is not entered again and the tokens after the invocation of `F` are
+    // written in their own line.
+    assert_eq!(
+        String::from_utf8(text).unwrap(),
+        "# 0 \"main.c\"\n# 0 \"<built-in>\"\n# 0 \"<command-line>\"\n# 1 \"main.c\"\n\
+         # 1 \"g.h\" 1\n\n\nint g;\n# 2 \"main.c\" 2\n\nint x = 1 2\n     + 3;\n\n\nint y;\n"
+    );
+}
+
+#[test]
+fn dump_tokens() {
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "main.c",
+        "#define S(x) #x\nS(a)\n  aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\n",
+    );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+
+    let dump = preprocessed.dump_tokens();
+    let lines: Vec<Vec<_>> = dump
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(lines.len(), preprocessed.tokens().len());
+    // The spans are skipped as they depend on the size of the predefined macros.
+    let expected = [
+        (0, ["0", "Newline"], ["main.c:1:16", "\"\\n\""]),
+        (1, ["1", "Str"], ["?", "\"\\\"a\\\"\""]),
+        (
+            4,
+            ["4", "Ident"],
+            [
+                "main.c:3:3",
+                "\"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\"...",
+            ],
+        ),
+    ];
+    for (index, start, end) in expected {
+        assert_eq!(lines[index][..2], start);
+        assert_eq!(lines[index][3..], end);
+    }
+}
+
+#[test]
+fn statistics() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#if A\n#elif 0\n#if 1\n#endif\n#else\nint b = A;\n#endif\n\
+             #include \"a.h\"\n",
+        )
+        .insert("a.h", "#define A F(1)\n#define F(x) x\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .standard_macros_only(true)
+        .header_cache(HeaderCache::new());
+
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let statistics = preprocessed.statistics();
+    assert_eq!(statistics.files_read(), 2);
+    assert_eq!(statistics.tokens(), preprocessed.tokens().len());
+    // The predefined macros are processed in every run.
+    let empty = Preprocessor::new()
+        .standard_macros_only(true)
+        .preprocess(b"")
+        .unwrap();
+    let predefined = empty.statistics();
+    // Both inclusions of `a.h` define its macros.
+    assert_eq!(statistics.macros_defined(), predefined.macros_defined() + 4);
+    // `A` and `F` are replaced in the condition of `#if`.
+    assert_eq!(statistics.macros_expanded(), 2);
+    // The `#elif` and `#else` groups and the `#if` nested inside the `#elif`.
+    assert_eq!(statistics.branches_skipped(), 3);
+    // The second inclusion of `a.h` is not tokenized again.
+    assert_eq!(statistics.cache_hits(), 1);
+    let contents: usize = ["main.c", "a.h"]
+        .iter()
+        .map(|path| preprocessed.context.map.file_span(path.as_ref()).unwrap())
+        .map(|span| span.hi() - span.lo())
+        .sum();
+    assert_eq!(
+        statistics.bytes_lexed(),
+        predefined.bytes_lexed() + contents
+    );
+    assert!(statistics.total() >= statistics.lexing() + statistics.expansion());
+
+    let report = statistics.to_string();
+    assert!(report.starts_with("files read                   2\n"));
+    assert_eq!(report.lines().count(), 11);
+}
+
+#[test]
+fn time_trace() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"a.h\"\nint a = A;\n")
+        .insert("a.h", "#define A 1\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .time_trace(std::time::Duration::ZERO);
+
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let trace = preprocessed.time_trace().unwrap();
+    let find = |name, detail| {
+        trace
+            .events()
+            .iter()
+            .find(|event| event.name() == name && event.detail() == detail)
+            .unwrap()
+    };
+    find("ReadFile", "main.c");
+    find("ReadFile", "a.h");
+    find("Expand", "main.c:2");
+    let main = find("Source", "main.c");
+    let header = find("Source", "a.h");
+    assert!(main.start() <= header.start());
+    assert!(header.start() + header.duration() <= main.start() + main.duration());
+
+    let json: serde_json::Value = serde_json::from_str(&trace.to_json()).unwrap();
+    let events = json["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), trace.events().len());
+    let event = events
+        .iter()
+        .find(|event| event["name"] == "Source")
+        .unwrap();
+    assert_eq!(event["ph"], "X");
+    assert_eq!(event["args"]["detail"], "a.h");
+    assert!(event["ts"].is_u64() && event["dur"].is_u64());
+
+    preprocessor.time_trace(std::time::Duration::from_secs(3600));
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert!(preprocessed.time_trace().unwrap().events().is_empty());
+    assert!(Preprocessor::new()
+        .preprocess(b"")
+        .unwrap()
+        .time_trace()
+        .is_none());
+}
+
+#[test]
+fn concatenate_strings() {
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.concatenate_strings(true);
+    let tokens = |source: &str| {
+        let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+        preprocessed
+            .tokens()
+            .iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+            .map(|&token| preprocessed.context.map.spelling(token))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    assert_eq!(tokens("\"a\" \"b\"\n\"c\" x \"d\""), "\"abc\" x \"d\"");
+    assert_eq!(tokens("\"a\" L\"b\" \"\""), "L\"ab\"");
+    // The escape sequences at the end of a string literal are not continued by the next one.
+    assert_eq!(
+        tokens(r#""\x1" "2" "\12" "3" "\123" "4""#),
+        r#""\x1\062\12\063\1234""#
+    );
+    assert_eq!(tokens(r#""\x1" "" "f" "\\" "1""#), r#""\x1\146\\1""#);
+    assert_eq!(tokens("u\"a\" \"b\" U\"c\""), "u\"a\" \"b\" U\"c\"");
+    let preprocessed = preprocessor.preprocess(b"u\"a\" U\"c\"").unwrap();
+    assert_eq!(preprocessed.diagnostics().len(), 1);
+
+    // The line markers are still right after concatenating string literals from different files.
+    let mut main = String::from("#include \"a.h\"\n\"b\"\nint x;\n");
+    for i in 0..2000 {
+        main.push_str(&format!("\"{}\"\n\"{}\";\n", i, i));
+    }
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.c", main).insert("a.h", "\"a\"\n");
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let text = String::from_utf8(Emitter::new().render(&preprocessed)).unwrap();
+    assert!(text.contains("# 1 \"a.h\" 1\n\"ab\"\n# 3 \"main.c\" 2\nint x;\n\"00\";\n\n"));
+
+    let path = temp_file("concatenate_strings.i", "");
+    let file = std::fs::File::create(&path).unwrap();
+    Emitter::new()
+        .stream_file(&preprocessor, &"main.c", file)
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+}
+
+#[test]
+fn c_tokens() {
+    let convert = |source: &str, standard: Standard| {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.standard(standard);
+        let c_tokens = preprocessor
+            .preprocess(source.as_bytes())
+            .unwrap()
+            .c_tokens();
+        let kinds: Vec<_> = c_tokens.tokens().iter().map(|token| token.kind()).collect();
+        (kinds, c_tokens.diagnostics().len())
+    };
+
+    let (kinds, errors) = convert("bool _Bool x; \"s\" 'c' +=", Standard::C17);
+    assert_eq!(
+        kinds,
+        [
+            CTokenKind::Ident,
+            CTokenKind::Keyword(Keyword::Bool),
+            CTokenKind::Ident,
+            CTokenKind::Punct,
+            CTokenKind::Str,
+            CTokenKind::Char,
+            CTokenKind::Punct,
+        ]
+    );
+    assert_eq!(errors, 0);
+    let (kinds, _) = convert("bool true typeof", Standard::C23);
+    assert_eq!(
+        kinds,
+        [
+            CTokenKind::Keyword(Keyword::Bool),
+            CTokenKind::Keyword(Keyword::True),
+            CTokenKind::Keyword(Keyword::Typeof),
+        ]
+    );
+
+    let integer = |spelling: &str| match convert(spelling, Standard::C17).0[..] {
+        [CTokenKind::Integer(constant)] => Some((
+            constant.value(),
+            constant.radix(),
+            constant.is_unsigned(),
+            constant.size(),
+        )),
+        _ => None,
+    };
+    assert_eq!(integer("0"), Some((0, 10, false, IntegerSize::Int)));
+    assert_eq!(integer("017uL"), Some((15, 8, true, IntegerSize::Long)));
+    assert_eq!(
+        integer("0xffLLU"),
+        Some((255, 16, true, IntegerSize::LongLong))
+    );
+    assert_eq!(integer("0b101"), Some((5, 2, false, IntegerSize::Int)));
+    assert_eq!(
+        integer("18446744073709551615"),
+        Some((u64::MAX, 10, false, IntegerSize::Int))
+    );
+    for invalid in ["08", "0x", "1lul", "1uu", "12abc", "18446744073709551616"] {
+        assert_eq!(integer(invalid), None, "{}", invalid);
+        assert_eq!(convert(invalid, Standard::C17).1, 1, "{}", invalid);
+    }
+    let separated = |spelling: &str| match convert(spelling, Standard::C23).0[..] {
+        [CTokenKind::Integer(constant)] => Some(constant.value()),
+        [CTokenKind::Floating(constant)] => Some(constant.value() as u64),
+        _ => None,
+    };
+    assert_eq!(separated("0'17"), Some(15));
+    assert_eq!(separated("0xF'F"), Some(255));
+    assert_eq!(separated("1'000.5e1"), Some(10005));
+    assert_eq!(separated("0x'1"), None);
+
+    let floating = |spelling: &str| match convert(spelling, Standard::C17).0[..] {
+        [CTokenKind::Floating(constant)] => {
+            Some((constant.value(), constant.is_hexadecimal(), constant.size()))
+        }
+        _ => None,
+    };
+    assert_eq!(floating("1.5"), Some((1.5, false, FloatingSize::Double)));
+    assert_eq!(floating(".5f"), Some((0.5, false, FloatingSize::Float)));
+    assert_eq!(floating("1."), Some((1.0, false, FloatingSize::Double)));
+    assert_eq!(
+        floating("25e-2L"),
+        Some((0.25, false, FloatingSize::LongDouble))
+    );
+    assert_eq!(floating("0x1.8p1"), Some((3.0, true, FloatingSize::Double)));
+    assert_eq!(floating("0X.4P+2f"), Some((1.0, true, FloatingSize::Float)));
+    for invalid in [
+        "1.2.3", "1e", "1e+", "0x1.8", "1.5u", "1.5ff", "0x.p1", "1e5e5",
+    ] {
+        assert_eq!(floating(invalid), None, "{}", invalid);
+        assert_eq!(convert(invalid, Standard::C17).1, 1, "{}", invalid);
+    }
+
+    // Stray characters and the preprocessing operators are not tokens.
+    let (kinds, errors) = convert("#define S(x) #x\n@ a S(b) %:", Standard::C17);
+    assert_eq!(kinds, [CTokenKind::Ident, CTokenKind::Str]);
+    assert_eq!(errors, 2);
+}
+
+#[test]
+fn token_stream() {
+    let stream = |source: &str| {
+        let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+        preprocessed.token_stream()
+    };
+
+    let trees = stream("p->x <<= a[1] + -1.5f;").unwrap();
+    assert_eq!(trees.to_string(), "p -> x <<= a [1] + - 1.5f ;");
+    let puncts: Vec<_> = trees
+        .iter()
+        .filter_map(|tree| match tree {
+            TokenTree::Punct(punct) => Some((punct.as_char(), punct.spacing())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        puncts,
+        [
+            ('-', Spacing::Joint),
+            ('>', Spacing::Alone),
+            ('<', Spacing::Joint),
+            ('<', Spacing::Joint),
+            ('=', Spacing::Alone),
+            ('+', Spacing::Alone),
+            ('-', Spacing::Alone),
+            (';', Spacing::Alone),
+        ]
+    );
+    match &trees.trees()[11] {
+        TokenTree::Literal(literal) => {
+            assert_eq!(literal.as_str(), "1.5f");
+            assert!(matches!(literal.kind(), CTokenKind::Floating(_)));
+        }
+        tree => panic!("Unexpected {:?}", tree),
+    }
+
+    // Digraphs delimit groups like the punctuators they represent.
+    let trees = stream("int a<:2:> = <% {1}, (2) %>;").unwrap();
+    assert_eq!(trees.to_string(), "int a [2] = {{1} , (2)} ;");
+    match &trees.trees()[4] {
+        TokenTree::Group(group) => {
+            assert_eq!(group.delimiter(), Delimiter::Brace);
+            let delimiters: Vec<_> = group
+                .stream()
+                .iter()
+                .filter_map(|tree| match tree {
+                    TokenTree::Group(group) => Some(group.delimiter()),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(delimiters, [Delimiter::Brace, Delimiter::Parenthesis]);
+        }
+        tree => panic!("Unexpected {:?}", tree),
+    }
+
+    assert_eq!(stream("(]").unwrap_err().message(), "Unmatched \"]\"");
+    assert_eq!(stream("f({)").unwrap_err().message(), "Unmatched \")\"");
+    assert_eq!(stream("{ (x)").unwrap_err().message(), "Unclosed \"{\"");
+}
+
+#[test]
+fn macro_constants() {
+    let source = "\
+        #define NEG -(1 << 3)\n\
+        #define BIG 0xffffffffffffffff\n\
+        #define WRAP (0u - 1)\n\
+        #define HALF 1 / 2.0f\n\
+        #define CHAR 'A' + 1\n\
+        #define COND NEG < 0 ? 2 : 3.5\n\
+        #define STR u8\"a\\x41\" \"\\0\"\n\
+        #define OVERFLOW 9223372036854775807 + 1\n\
+        #define ZERO 1 % 0\n\
+        #define IDENT x + 1\n\
+        #define CAST ((int)1)\n\
+        #define WIDE L\"a\"\n\
+        #define EMPTY\n\
+        #define F(x) x\n\
+        #define TRAILING 1 2\n";
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    let constant = |name| preprocessed.macro_constant(name);
+    assert_eq!(constant("NEG"), Some(MacroConstant::Signed(-8)));
+    assert_eq!(constant("BIG"), Some(MacroConstant::Unsigned(u64::MAX)));
+    assert_eq!(constant("WRAP"), Some(MacroConstant::Unsigned(u64::MAX)));
+    assert_eq!(constant("HALF"), Some(MacroConstant::Float(0.5)));
+    assert_eq!(constant("CHAR"), Some(MacroConstant::Signed(66)));
+    assert_eq!(constant("COND"), Some(MacroConstant::Float(2.0)));
+    assert_eq!(constant("STR"), Some(MacroConstant::Str(b"aA\0".to_vec())));
+    for name in [
+        "OVERFLOW", "ZERO", "IDENT", "CAST", "WIDE", "EMPTY", "F", "TRAILING", "MISSING",
+    ] {
+        assert_eq!(constant(name), None, "{}", name);
+    }
+
+    let names: Vec<_> = preprocessed
+        .macro_constants()
+        .filter(|(definition, _)| !definition.is_builtin())
+        .map(|(definition, _)| definition.name())
+        .collect();
+    assert_eq!(names, ["BIG", "CHAR", "COND", "HALF", "NEG", "STR", "WRAP"]);
+}
+
+#[test]
+fn errors() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"bad.h\"\n")
+        .insert("bad.h", "int a;\n#else\n")
+        .insert("token.c", "int a;\nint b; /* unterminated\n")
+        .insert("define.c", "#define 1 2\n")
+        .insert("defined.c", "#if defined(A\n#endif\n");
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let error = |path: &str| match preprocessor.preprocess_file(&path) {
+        Ok(preprocessed) => preprocessed.diagnostics()[0].clone(),
+        Err(error) => panic!("Preprocessing {} failed: {}", path, error),
+    };
+
+    let diagnostic = error("main.c");
+    assert_eq!(Severity::Error, diagnostic.severity());
+    assert_eq!("#else without #if", diagnostic.message());
+    assert_eq!(Some(&PathBuf::from("bad.h")), diagnostic.file());
+    assert_eq!(Some(2), diagnostic.line());
+
+    let diagnostic = error("token.c");
+    assert!(diagnostic.message().starts_with("Invalid token"));
+    assert_eq!(Some(2), diagnostic.line());
+
+    let diagnostic = error("define.c");
+    assert_eq!("Macro names must be identifiers", diagnostic.message());
+    assert_eq!(Some(1), diagnostic.line());
+
+    let diagnostic = error("defined.c");
+    assert_eq!("Missing \")\" after \"defined\"", diagnostic.message());
+
+    assert!(matches!(
+        preprocessor.preprocess_file(&"missing.c"),
+        Err(Error::Io(_))
+    ));
+}
+
+#[test]
+fn error_recovery() {
+    // The errors that are not fatal are reported and the preprocessing continues, keeping the
+    // name of the invocations that are not valid as GCC does.
+    let source = "#define F(x) [x]\n#if 1 / 0\nA\n#endif\nx F(1, 2) y\n#endif\nF(3)\n";
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    let codes: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(Diagnostic::code)
+        .collect();
+    assert_eq!(
+        codes,
+        [
+            DiagnosticCode::DivisionByZero,
+            DiagnosticCode::ArgumentCount,
+            DiagnosticCode::UnmatchedConditional,
+        ]
+    );
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(text, b"x F y\n\n[3]\n");
+
+    // The fatal errors stop the preprocessing, after the errors reported before them.
+    let source = "F(1)\n#define F(a, b)\nF(1)\n#include \"missing.h\"\nF(1, 2)\n";
+    let error = Preprocessor::new()
+        .preprocess(source.as_bytes())
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.diagnostic().unwrap().code(),
+        DiagnosticCode::IncludeNotFound
+    );
+    let codes: Vec<_> = error.diagnostics().iter().map(Diagnostic::code).collect();
+    assert_eq!(codes, [DiagnosticCode::ArgumentCount]);
+}
+
+#[test]
+fn line_directives() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "a\n#line 10\nb\n#line 20 \"foo.c\"\n#if 1 / 0\n#endif\n#include \"a.h\"\nc\n",
+        )
+        .insert(
+            "a.h",
+            "#define LINE 30\n#define FILE \"b\\\\c.h\"\n#line LINE FILE\n#if 2 / 0\n#endif\n",
+        )
+        .insert(
+            "marker.c",
+            "x\n# 5 \"x.h\" 1 3\n#if 3 / 0\n#endif\n# 3 \"marker.c\" 2\ny\n",
+        );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+
+    // The diagnostics and the line markers use the lines and file names given by the directives,
+    // including the notes of the files included after them.
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let locations: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            let file = diagnostic.file().unwrap().display().to_string();
+            (
+                file,
+                diagnostic.line().unwrap(),
+                diagnostic.notes().to_vec(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        locations,
+        [
+            ("foo.c".to_owned(), 20, vec![]),
+            (
+                "b\\c.h".to_owned(),
+                30,
+                vec!["Included from foo.c:22".to_owned()]
+            ),
+        ]
+    );
+    let text = String::from_utf8(Emitter::new().render(&preprocessed)).unwrap();
+    assert_eq!(
+        text,
+        concat!(
+            "# 1 \"main.c\"\na\n\n\n\n\n\n\n\n\nb\n# 20 \"foo.c\"\n\n\n",
+            "# 1 \"a.h\" 1\n\n\n# 30 \"b\\\\c.h\"\n\n\n# 23 \"foo.c\" 2\nc\n",
+        )
+    );
+
+    // Line markers can enter and leave files and mark system headers.
+    let preprocessed = preprocessor.preprocess_file(&"marker.c").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.file(), Some(&PathBuf::from("x.h")));
+    assert_eq!(diagnostic.line(), Some(5));
+    let text = String::from_utf8(Emitter::new().render(&preprocessed)).unwrap();
+    assert_eq!(
+        text,
+        "# 1 \"marker.c\"\nx\n# 5 \"x.h\" 1 3\n\n\n# 3 \"marker.c\" 2\ny\n"
+    );
+
+    for (source, message) in [
+        ("#line\n", "#line expects a line number"),
+        ("#line x\n", "\"x\" after #line is not a positive integer"),
+        (
+            "#line 0x10\n",
+            "\"0x10\" after #line is not a positive integer",
+        ),
+        ("#line 2147483648\n", "Line number out of range"),
+        ("#line 1 x\n", "\"x\" is not a valid file name"),
+        (
+            "#line 1 L\"a.c\"\n",
+            "\"L\"a.c\"\" is not a valid file name",
+        ),
+        ("# 1 \"a.c\" 3 1\n", "Invalid flag \"1\" in line marker"),
+    ] {
+        let diagnostic = preprocess_error(&Preprocessor::new(), source);
+        assert_eq!(diagnostic.code(), DiagnosticCode::InvalidLineDirective);
+        assert_eq!(diagnostic.message(), message);
+    }
+}
+
+#[test]
+fn diagnostic_directives() {
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "main.c",
+        "#if 0\n#error skipped\n#endif\n#error  \"stop\"   here\n#warning careful\nint a;\n",
+    );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let diagnostics: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            (
+                diagnostic.severity(),
+                diagnostic.code(),
+                diagnostic.message(),
+                diagnostic.line(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                Severity::Error,
+                DiagnosticCode::ErrorDirective,
+                "#error \"stop\" here",
+                Some(4)
+            ),
+            (
+                Severity::Warning,
+                DiagnosticCode::WarningDirective,
+                "#warning careful",
+                Some(5)
+            ),
+        ]
+    );
+    // The directives are not part of the output, which continues after them.
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(text, b"int a;\n");
+
+    preprocessor.warning_option("-Wno-cpp");
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert_eq!(preprocessed.diagnostics().len(), 1);
+}
+
+#[test]
+fn unknown_directives() {
+    // The operands of pragmas are not replaced, like in GCC.
+    preprocess_eq(
+        &Preprocessor::new(),
+        "#define for FOR\n#define X 1\n#pragma omp parallel for\n#pragma GCC poison X\nX\n",
+        "\n\n#pragma omp parallel for\n#pragma GCC poison X\n1\n",
+    );
+
+    let preprocessor = Preprocessor::new();
+    let preprocessed = preprocessor
+        .preprocess(b"#defien x\n# \"str\"\n#if 0\n#skipped\n#endif\na\n")
+        .unwrap();
+    let diagnostics: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| (diagnostic.code(), diagnostic.message()))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                DiagnosticCode::InvalidDirective,
+                "Invalid preprocessing directive #defien"
+            ),
+            (
+                DiagnosticCode::InvalidDirective,
+                "Invalid preprocessing directive #\"str\""
+            ),
+        ]
+    );
+    // The lines of the directives are left out of the output.
+    let text = Emitter::new().line_markers(false).render(&preprocessed);
+    assert_eq!(text, b"a\n");
+}
+
+#[test]
+fn diagnostic_labels() {
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#if A\n#else\n#elif B\n#endif\n");
+    assert_eq!(DiagnosticCode::ElseAfterElse, diagnostic.code());
+    assert_eq!(
+        "error[else-after-else]: #elif after #else\nnote: Previous #else is here",
+        diagnostic.to_string()
+    );
+
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#if (1 ? 2 : 3\n#endif\n");
+    assert_eq!(DiagnosticCode::InvalidExpression, diagnostic.code());
+    assert_eq!("To match this \"(\"", diagnostic.labels()[0].message());
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.concatenate_strings(true);
+    let preprocessed = preprocessor.preprocess(b"u8\"a\" \"b\" L\"c\"").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(DiagnosticCode::StringPrefixMismatch, diagnostic.code());
+    let label = &diagnostic.labels()[0];
+    assert_eq!("The prefix \"u8\" is used here", label.message());
+    // The label points to the first literal and the error to the last one.
+    assert_eq!(5, label.span().hi() - label.span().lo());
+    assert!(label.span().hi() < diagnostic.span().lo());
+
+    let preprocessed = Preprocessor::new().preprocess(b"f(a[1)]").unwrap();
+    let diagnostic = preprocessed.token_stream().unwrap_err();
+    assert_eq!(DiagnosticCode::UnmatchedDelimiter, diagnostic.code());
+    assert_eq!("Unclosed \"[\"", diagnostic.labels()[0].message());
+}
+
+#[test]
+fn diagnostic_rendering() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"pair.h\"\nint x =\tPAIR(1);\n")
+        .insert("pair.h", "#define PAIR(a, b) a, b\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let diagnostic = match preprocessor.preprocess_file(&"main.c") {
+        Ok(preprocessed) => preprocessed.diagnostics()[0].clone(),
+        Err(error) => error.diagnostic().unwrap().clone(),
+    };
+    assert_eq!(Some(2), diagnostic.line());
+    assert_eq!(Some(9), diagnostic.column());
+    assert_eq!(
+        DiagnosticRenderer::new()
+            .render(&diagnostic)
+            .lines()
+            .collect::<Vec<_>>(),
+        [
+            "error[argument-count]: Macro \"PAIR\" requires 2 arguments, but 1 were given",
+            " --> main.c:2:9",
+            "  |",
+            "2 | int x =    PAIR(1);",
+            "  |            ^^^^",
+            " ::: pair.h:1:9",
+            "1 | #define PAIR(a, b) a, b",
+            "  |         ---- Macro \"PAIR\" is defined here",
+        ]
+    );
+
+    // Regions that are not in a file have no location and no line numbers.
+    let diagnostic = preprocess_error(&Preprocessor::new(), "#if 1 +\n#endif\n");
+    let text = DiagnosticRenderer::new().render(&diagnostic);
+    assert_eq!(None, diagnostic.column());
+    assert!(!text.contains("-->"));
+    assert!(text.contains("\n  | #if 1 +\n"));
+
+    let text = DiagnosticRenderer::new().colors(true).render(&diagnostic);
+    assert!(text.starts_with("\x1b[1;31merror[invalid-expression]\x1b[0m\x1b[1m: "));
+    assert!(text.contains("\x1b[1;34m  |\x1b[0m #if 1 +\n"));
+}
+
+#[test]
+fn diagnostic_severities() {
+    let source = b"#if 1 / 0 || 0x7fffffffffffffff + 1\n#endif\n@\n";
+    let severities = |preprocessor: &Preprocessor| {
+        let preprocessed = preprocessor.preprocess(source).unwrap();
+        let diagnostics = preprocessed.diagnostics().iter();
+        let c_tokens = preprocessed.c_tokens();
+        diagnostics
+            .chain(c_tokens.diagnostics())
+            .map(|diagnostic| (diagnostic.code(), diagnostic.severity()))
+            .collect::<Vec<_>>()
+    };
+
+    let mut preprocessor = Preprocessor::new();
+    assert_eq!(
+        severities(&preprocessor),
+        [
+            (DiagnosticCode::DivisionByZero, Severity::Error),
+            (DiagnosticCode::IntegerOverflow, Severity::Warning),
+            (DiagnosticCode::StrayToken, Severity::Error),
+        ]
+    );
+
+    preprocessor.warnings_as_errors(true);
+    assert_eq!(
+        severities(&preprocessor),
+        [
+            (DiagnosticCode::DivisionByZero, Severity::Error),
+            (DiagnosticCode::IntegerOverflow, Severity::Error),
+            (DiagnosticCode::StrayToken, Severity::Error),
+        ]
+    );
+
+    // The level of a code takes precedence over the one of its group, and notes are not
+    // promoted to errors.
+    preprocessor
+        .diagnostic_group_level(DiagnosticGroup::Expressions, Level::Allow)
+        .diagnostic_level(DiagnosticCode::IntegerOverflow, Level::Note)
+        .diagnostic_level(DiagnosticCode::StrayToken, Level::Warn);
+    assert_eq!(
+        severities(&preprocessor),
+        [
+            (DiagnosticCode::IntegerOverflow, Severity::Note),
+            (DiagnosticCode::StrayToken, Severity::Error),
+        ]
+    );
+
+    preprocessor
+        .warnings_as_errors(false)
+        .diagnostic_group_level(DiagnosticGroup::Tokens, Level::Allow);
+    assert_eq!(
+        severities(&preprocessor),
+        [
+            (DiagnosticCode::IntegerOverflow, Severity::Note),
+            (DiagnosticCode::StrayToken, Severity::Warning),
+        ]
+    );
+    preprocessor.diagnostic_level(DiagnosticCode::StrayToken, Level::Allow);
+    assert_eq!(
+        severities(&preprocessor),
+        [(DiagnosticCode::IntegerOverflow, Severity::Note)]
+    );
+}
+
+#[test]
+fn warnings() {
+    let diagnostics = |preprocessor: &Preprocessor, source: &str| {
+        let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+        preprocessed
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| (diagnostic.code(), diagnostic.message().to_owned()))
+            .collect::<Vec<_>>()
+    };
+    let source = concat!(
+        "/* a /* b */ // c \\\n d\n",
+        "const char *s = \"??=\";\n",
+        "#define UNUSED 1\n",
+        "#define USED 2\n",
+        "#undef __STDC_HOSTED__\n",
+        "#if USED && NOT_DEFINED\n",
+        "#else USED\n",
+        "#endif /* comment */ USED\n",
+    );
+
+    assert_eq!(
+        diagnostics(&Preprocessor::new(), source),
+        [
+            (
+                DiagnosticCode::BuiltinMacroRedefined,
+                "Undefining predefined macro \"__STDC_HOSTED__\"".to_owned()
+            ),
+            (
+                DiagnosticCode::ExtraTokens,
+                "Extra tokens at end of #else directive".to_owned()
+            ),
+            (
+                DiagnosticCode::ExtraTokens,
+                "Extra tokens at end of #endif directive".to_owned()
+            ),
+        ]
+    );
+
+    // Trigraphs are only reported where they are not replaced.
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .standard(Standard::Gnu17)
+        .warning_group(WarningGroup::All, true)
+        .warning(Warning::UnusedMacros, true)
+        .warning(Warning::Undef, true)
+        .warning(Warning::EndifLabels, false)
+        .warning_option("-Wno-builtin-macro-redefined");
+    assert_eq!(
+        diagnostics(&preprocessor, source),
+        [
+            (
+                DiagnosticCode::NestedComment,
+                "\"/*\" within block comment".to_owned()
+            ),
+            (
+                DiagnosticCode::MultiLineComment,
+                "Multi-line \"//\" comment".to_owned()
+            ),
+            (
+                DiagnosticCode::Trigraph,
+                "Trigraph \"??=\" is ignored".to_owned()
+            ),
+            (
+                DiagnosticCode::UndefinedIdentifier,
+                "\"NOT_DEFINED\" is not defined, evaluates to 0".to_owned()
+            ),
+            (
+                DiagnosticCode::UnusedMacro,
+                "Macro \"UNUSED\" is not used".to_owned()
+            ),
+        ]
+    );
+
+    // Macros are used when they are checked, and unused macros are reported when they are
+    // redefined.
+    let source = "#define A 1\n#define B 2\n#define A 1\n#ifdef B\n#endif\n";
+    assert_eq!(
+        diagnostics(&preprocessor, source),
+        [
+            (
+                DiagnosticCode::UnusedMacro,
+                "Macro \"A\" is not used".to_owned()
+            ),
+            (
+                DiagnosticCode::UnusedMacro,
+                "Macro \"A\" is not used".to_owned()
+            ),
+        ]
+    );
+
+    // The pragmas change the warnings until the end of the input or the next `pop`.
+    let source = concat!(
+        "#pragma GCC diagnostic push\n",
+        "#pragma GCC diagnostic error \"-Wundef\"\n",
+        "#if A\n#endif\n",
+        "#pragma clang diagnostic ignored \"-Wundef\"\n",
+        "#if B\n#endif\n",
+        "#pragma GCC diagnostic pop\n",
+        "#if C\n#endif\n",
+    );
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.warning_option("-Wundef");
+    let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+    let severities: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| (diagnostic.line(), diagnostic.severity()))
+        .collect();
+    assert_eq!(severities.len(), 2);
+    assert_eq!(severities[0].1, Severity::Error);
+    assert_eq!(severities[1].1, Severity::Warning);
+    // The pragmas are kept in the output.
+    assert_eq!(
+        Emitter::new()
+            .line_markers(false)
+            .render(&preprocessed)
+            .split(|&byte| byte == b'\n')
+            .filter(|line| line.starts_with(b"#pragma"))
+            .count(),
+        4
+    );
+
+    // `-Werror` does not apply to the warnings reported as warnings with `-Wno-error=`.
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .warning_option("-Werror")
+        .warning_option("-Wundef")
+        .warning_option("-Wno-error=undef")
+        .warning_option("-Wunknown-warning");
+    let preprocessed = preprocessor
+        .preprocess(b"#if A\n#else B\n#endif\n")
+        .unwrap();
+    let severities: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| (diagnostic.code(), diagnostic.severity()))
+        .collect();
+    assert_eq!(
+        severities,
+        [
+            (DiagnosticCode::UndefinedIdentifier, Severity::Warning),
+            (DiagnosticCode::ExtraTokens, Severity::Error),
+        ]
+    );
+}
+
+#[test]
+fn macro_redefinition() {
+    let source = concat!(
+        "#define A(x, y) x  + /* sum */ y\n",
+        "#define A(x, y) x + y\n",
+        "#define A(x, z) x + z\n",
+        "#define B 1 + 2\n",
+        "#define B 1+2\n",
+        "#define C()\n",
+        "#define C\n",
+    );
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.c", source);
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let diagnostics: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| {
+            assert_eq!(diagnostic.code(), DiagnosticCode::MacroRedefined);
+            (diagnostic.line(), diagnostic.message().to_owned())
+        })
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (Some(3), "Macro \"A\" redefined".to_owned()),
+            (Some(5), "Macro \"B\" redefined".to_owned()),
+            (Some(7), "Macro \"C\" redefined".to_owned()),
+        ]
+    );
+
+    preprocessor.warning(Warning::MacroRedefined, false);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    assert!(preprocessed.diagnostics().is_empty());
+}
+
+#[test]
+fn suggestions() {
+    let suggestions = |diagnostic: &Diagnostic| {
+        diagnostic
+            .suggestions()
+            .iter()
+            .map(|suggestion| {
+                let position = (suggestion.line().unwrap(), suggestion.column().unwrap());
+                (position, suggestion.replacement().to_owned())
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut files = MemoryFileSystem::new();
+    files.insert(
+        "main.c",
+        "#if FEATURE\n#endif FEATURE\n#if FEATURE + 1\n#else A /* B */ C\n#endif\nchar *s = \"??/\";\n",
+    );
+    files.insert("open.c", "#if 1\n#ifdef A\nint a;");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor
+        .file_system(files)
+        .standard(Standard::Gnu17)
+        .warning(Warning::Undef, true)
+        .warning(Warning::Trigraphs, true);
+
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let diagnostics: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(|diagnostic| (diagnostic.code(), suggestions(diagnostic)))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                DiagnosticCode::UndefinedIdentifier,
+                vec![((1, 2), "ifdef".to_owned())]
+            ),
+            (
+                DiagnosticCode::ExtraTokens,
+                vec![((2, 8), "/* FEATURE */".to_owned())]
+            ),
+            // Only a single identifier is obviously meant to be checked with `#ifdef`.
+            (DiagnosticCode::UndefinedIdentifier, vec![]),
+            // The tokens cannot be put in a comment because they contain one.
+            (DiagnosticCode::ExtraTokens, vec![]),
+            (DiagnosticCode::Trigraph, vec![((6, 12), "\\".to_owned())]),
+        ]
+    );
+    let text = DiagnosticRenderer::new().render(&preprocessed.diagnostics()[0]);
+    assert_eq!(
+        text.lines().last(),
+        Some("  = help: Did you mean \"#ifdef\"?")
+    );
+
+    let preprocessed = preprocessor.preprocess_file(&"open.c").unwrap();
+    let diagnostic = &preprocessed.diagnostics()[0];
+    assert_eq!(diagnostic.code(), DiagnosticCode::UnterminatedConditional);
+    assert_eq!(diagnostic.line(), Some(2));
+    assert_eq!(
+        suggestions(diagnostic),
+        [((3, 6), ";\n#endif\n#endif\n".to_owned())]
+    );
+}
+
+#[test]
+fn pedantic() {
+    let source = concat!(
+        "#define EMPTY\n",
+        "#define PLUS+1\n",
+        "#define CALL(f, ...) f(__VA_ARGS__)\n",
+        "#define OPT(...) __VA_OPT__(,)\n",
+        "#if 0b1 + PLUS\n",
+        "#ident \"version\"\n",
+        "#warning \"message\"\n",
+        "#endif\n",
+        "#if 0\n#ident \"skipped\"\n#endif\n",
+        "CALL(g) CALL(g,) CALL(g, 1)\n",
+        "#pragma once\n",
+    );
+    // Only the `#warning` directive is reported.
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    let codes: Vec<_> = preprocessed
+        .diagnostics()
+        .iter()
+        .map(Diagnostic::code)
+        .collect();
+    assert_eq!(codes, [DiagnosticCode::WarningDirective]);
+
+    let extensions = |preprocessor: &Preprocessor| {
+        let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+        preprocessed
+            .diagnostics()
+            .iter()
+            .filter(|diagnostic| diagnostic.code() != DiagnosticCode::WarningDirective)
+            .map(|diagnostic| {
+                assert_eq!(diagnostic.code(), DiagnosticCode::Extension);
+                diagnostic.message().to_owned()
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.pedantic(Level::Warn);
+    assert_eq!(
+        extensions(&preprocessor),
+        [
+            "ISO C requires white-space after the name of an object-like macro",
+            "__VA_OPT__ is a C23 extension",
+            "Binary integer constants are a C23 extension",
+            "#ident is an extension",
+            "#warning is a C23 extension",
+            "Invoking macro \"CALL\" without its variable arguments is a C23 extension",
+        ]
+    );
+
+    // The features of C23 are not extensions when following C23.
+    preprocessor.standard(Standard::C23);
+    assert_eq!(
+        extensions(&preprocessor),
+        [
+            "ISO C requires white-space after the name of an object-like macro",
+            "#ident is an extension",
+        ]
+    );
+
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.warning_option("-Wpedantic");
+    assert_eq!(extensions(&preprocessor).len(), 6);
+}
+
+#[cfg(feature = "cexpr")]
+#[test]
+fn cexpr() {
+    use cexpr::{expr::EvalResult, token::Kind};
+
+    let source = "#define F(x) x\n#define S \"a\"\n#define E (1.5)\nint F(a) = S[0];\n";
+    let preprocessed = Preprocessor::new().preprocess(source.as_bytes()).unwrap();
+    let kinds: Vec<_> = preprocessed
+        .cexpr_tokens()
+        .iter()
+        .map(|token| token.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            Kind::Keyword,
+            Kind::Identifier,
+            Kind::Punctuation,
+            Kind::Literal,
+            Kind::Punctuation,
+            Kind::Literal,
+            Kind::Punctuation,
+            Kind::Punctuation,
+        ]
+    );
+
+    assert!(preprocessed.cexpr_macro("F").is_none());
+    assert!(preprocessed.cexpr_macro("G").is_none());
+    let tokens = preprocessed.cexpr_macro("E").unwrap();
+    let (_, value) = cexpr::expr::macro_definition(&tokens).unwrap().1;
+    assert_eq!(value, EvalResult::Float(1.5));
+    let tokens = preprocessed.cexpr_macro("S").unwrap();
+    let (_, value) = cexpr::expr::macro_definition(&tokens).unwrap().1;
+    assert_eq!(value, EvalResult::Str(b"a".to_vec()));
+}
+
+#[cfg(feature = "lang-c")]
+#[test]
+fn lang_c() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert("main.c", "#include \"a.h\"\nint b = A;\n")
+        .insert("a.h", "#define A 1\ntypedef int a;\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let config = lang_c::driver::Config::default();
+    let parse = preprocessed.parse_lang_c(&config).unwrap();
+    assert_eq!(parse.unit.0.len(), 2);
+
+    // The locations of syntax errors refer to the source files.
+    let preprocessed = Preprocessor::new().preprocess(b"int a = ;\n").unwrap();
+    let error = preprocessed.parse_lang_c(&config).unwrap_err();
+    let (location, _) = error.get_location();
+    assert_eq!((location.file, location.line), ("<stdin>", 1));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    let preprocessed = Preprocessor::new().preprocess(b"#define A 1\nA\n").unwrap();
+    let snapshot = preprocessed.source_map();
+
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(
+        serde_json::from_str::<crate::SourceMapSnapshot>(&json).unwrap(),
+        snapshot
+    );
+    // Snapshots are checked like the ones read using `SourceMapSnapshot::read_from`.
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    value["regions"][0]["contents"]
+        .as_array_mut()
+        .unwrap()
+        .pop();
+    assert!(serde_json::from_value::<crate::SourceMapSnapshot>(value).is_err());
+    let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    value["regions"].as_array_mut().unwrap().reverse();
+    assert!(serde_json::from_value::<crate::SourceMapSnapshot>(value).is_err());
+
+    let json = serde_json::to_string(preprocessed.tokens()).unwrap();
+    assert!(json.contains("{\"kind\":\"Number\",\"span\":{\"lo\":"));
+    let tokens: Vec<crate::Token> = serde_json::from_str(&json).unwrap();
+    assert_eq!(tokens, preprocessed.tokens());
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing() {
+    use std::sync::Mutex;
+    use tracing::{span, Event, Metadata, Subscriber};
+
+    /// A subscriber that records the names of the spans and the messages of the events.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Subscriber for &'static Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name().to_owned());
+            span::Id::from_u64(names.len() as u64)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut message = String::new();
+            event.record(
+                &mut |field: &tracing::field::Field, value: &dyn std::fmt::Debug| {
+                    message.push_str(&format!(" {}={:?}", field, value));
+                },
+            );
+            self.0.lock().unwrap().push(format!("event{}", message));
+        }
+
+        fn enter(&self, _: &span::Id) {}
+
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let recorder = Box::leak(Box::default());
+    let mut files = MemoryFileSystem::new();
+    files.insert("main.c", "#include \"a.h\"\nA\n");
+    files.insert("a.h", "#define A 1\n");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    tracing::subscriber::with_default(&*recorder, || {
+        preprocessor.preprocess_file(&"main.c").unwrap();
+    });
+
+    // The predefined macros are processed before reading the input.
+    let recorded = recorder.0.lock().unwrap();
+    let input = recorded
+        .iter()
+        .position(|name| name == "read_file")
+        .unwrap();
+    assert_eq!(
+        recorded[input..],
+        [
+            "read_file",
+            "process_file",
+            "include",
+            "read_file",
+            "event message=resolved include name=a.h path=a.h system=false",
+            "process_file",
+            "event message=tokenized lines bytes=12",
+            "expand",
+            "event message=tokenized lines bytes=17",
+        ]
+    );
+}
+
+#[test]
+fn comments() {
+    let source = "#define F(x) x /* in F */ + 1 // end of F\nint a; // end\nF(1 /* arg */ + 2) /* two\nlines */ int b;\nF(3 // line */\n+ 4);\n";
+    let render = |comments| {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.keep_comments(comments);
+        let preprocessed = preprocessor.preprocess(source.as_bytes()).unwrap();
+        String::from_utf8(Emitter::new().render(&preprocessed)).unwrap()
+    };
+
+    assert_eq!(
+        render(Comments::Text),
+        "# 1 \"<stdin>\"\n\nint a; // end\n1 /* arg */ + 2 + 1 /* two\nlines */ int b;\n3 /* line * / */ + 4 + 1;\n"
+    );
+    assert_eq!(
+        render(Comments::All),
+        "# 1 \"<stdin>\"\n\nint a; // end\n1 /* arg */ + 2 /* in F */ + 1 /* two\nlines */ int b;\n3 /* line * / */ + 4 /* in F */ + 1;\n"
+    );
+}
+
+#[test]
+fn flatten_includes() {
+    let mut files = MemoryFileSystem::new();
+    files
+        .insert(
+            "main.c",
+            "#include \"a.h\"\n#include \"a.h\"\n#include \"g.h\"\n#include \"g.h\"\n#include \"m.h\"\n#include \"m.h\"\n#if 0\n#include \"missing.h\"\n#endif\nA G M",
+        )
+        .insert("a.h", "#pragma once\n#include \"b.h\"\n")
+        .insert("b.h", "#define A a\n")
+        .insert("g.h", "// guard\n#ifndef G_H\n#define G_H\n#define G g\n#endif\n")
+        .insert("m.h", "#define M m");
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.file_system(files);
+    let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+    let flattened = preprocessed.flatten_includes();
+
+    assert_eq!(
+        String::from_utf8(flattened.clone()).unwrap(),
+        "#define A a\n\
+         // guard\n\
+         #ifndef G_H\n\
+         #define G_H\n\
+         #define G g\n\
+         #endif\n\
+         #define M m\n\
+         #define M m\n\
+         #if 0\n\
+         #include \"missing.h\"\n\
+         #endif\n\
+         A G M"
+    );
+
+    // The result does not need the included files anymore.
+    let flattened = Preprocessor::new().preprocess(&flattened).unwrap();
+    let output = |preprocessed: &Preprocessed| {
+        let context = &preprocessed.context;
+        context
+            .output
+            .tokens()
+            .iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+            .map(|&token| context.map.spelling(token))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(output(&flattened), output(&preprocessed));
+}
+
+#[test]
+fn reduce_conditionals() {
+    let source = "#if A\na\n#elif defined(B) && VERSION >= 2\nb\n#elif C\nc\n#else\nd\n#endif\n\
+                  #ifndef B\n#if 0\nzero\n#endif\n#else\n#  ifdef C /* c */\nbc\n#  elif B\nbb\n#  endif\n#endif\n\
+                  #if VERSION < 2\nold\n#else\nnew\n#endif\n#define X 1\n";
+    let mut preprocessor = Preprocessor::new();
+    preprocessor.define("B").define("VERSION=3");
+    let reduced = preprocessor.reduce_conditionals(source.as_bytes()).unwrap();
+    assert_eq!(
+        String::from_utf8(reduced).unwrap(),
+        "#if A\na\n#else\nb\n#endif\n\
+         #  ifdef C /* c */\nbc\n#  else\nbb\n#  endif\n\
+         new\n#define X 1\n"
+    );
+}
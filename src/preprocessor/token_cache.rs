@@ -0,0 +1,252 @@
+//! A cache of the tokens of the files read, stored on disk so it can be reused across runs.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use super::cache_key::KeyHasher;
+use crate::{
+    buffer::Cursor,
+    lexer::{Lines, Token, TokenKind},
+    span::{SourceMap, Span},
+};
+
+/// The version of the format of the files of the cache, which is part of their keys so files
+/// written in other formats are never read.
+const FORMAT: u8 = 2;
+
+/// The kinds of tokens, in the order used to store them.
+const KINDS: [TokenKind; 9] = [
+    TokenKind::Header,
+    TokenKind::Ident,
+    TokenKind::Number,
+    TokenKind::Char,
+    TokenKind::Str,
+    TokenKind::Punct,
+    TokenKind::Any,
+    TokenKind::Space,
+    TokenKind::Newline,
+];
+
+/// Used to give a different name to the temporary files written by each thread.
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// What is known about a file from its tokens that lets it be skipped when it is included again,
+/// stored in the cache with them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct FileInfo {
+    /// The name of the macro of the include guard of the file, if the whole file except for
+    /// white-space is a `#ifndef` directive for the macro, or a `#if` directive whose condition
+    /// is `!defined` followed by it, without other branches.
+    pub(crate) guard: Option<String>,
+    /// Whether the file contains a `#pragma once` directive outside of conditional inclusion
+    /// directives.
+    pub(crate) once: bool,
+}
+
+impl FileInfo {
+    /// Find the include guard and the `#pragma once` directive of the file in the region `span`,
+    /// given its `tokens` relative to its start.
+    pub(crate) fn new(map: &SourceMap, span: Span, tokens: &[Token]) -> Self {
+        let mut once = false;
+        let mut guard = None;
+        // Whether everything found so far is inside the first conditional inclusion directive,
+        // without other branches.
+        let mut enclosed = true;
+        let mut depth = 0_usize;
+        let mut first = true;
+        let mut closed = false;
+
+        for line in tokens.split_inclusive(|token| token.kind == TokenKind::Newline) {
+            if line
+                .iter()
+                .all(|token| matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+            {
+                continue;
+            }
+            let line: Vec<_> = line
+                .iter()
+                .filter(|token| token.kind != TokenKind::Newline)
+                .map(|&token| {
+                    Token::new(
+                        token.kind,
+                        Span::new(span.lo() + token.span.lo(), span.lo() + token.span.hi()),
+                    )
+                })
+                .collect();
+            enclosed &= !closed;
+
+            let (name, body) = map.directive(&line).unwrap_or((None, &[]));
+            match name.map(|name| map.spelling(name)).as_deref() {
+                Some(name @ ("if" | "ifdef" | "ifndef")) => {
+                    if first {
+                        guard = guard_macro(map, name, body);
+                    } else if depth == 0 {
+                        enclosed = false;
+                    }
+                    depth += 1;
+                }
+                Some("else" | "elif" | "elifdef" | "elifndef") if depth == 1 => enclosed = false,
+                Some("endif") => {
+                    depth = depth.saturating_sub(1);
+                    closed = depth == 0;
+                }
+                Some("pragma") if depth == 0 => {
+                    let mut cursor = Cursor::new(body);
+                    once |= matches!(
+                        (cursor.next_non_space(), cursor.next_non_space()),
+                        (Some(token), None) if &*map.get_bytes(token.span) == b"once"
+                    );
+                    enclosed = false;
+                }
+                _ if depth == 0 => enclosed = false,
+                _ => {}
+            }
+            first = false;
+        }
+
+        Self {
+            guard: guard.filter(|_| enclosed && closed),
+            once,
+        }
+    }
+}
+
+/// Get the name of the macro tested by a `#ifndef` directive, or a `#if` directive whose
+/// condition is `!defined` followed by a name, given its `name` and its `body`.
+fn guard_macro(map: &SourceMap, name: &str, body: &[Token]) -> Option<String> {
+    let mut cursor = Cursor::new(body);
+    if name == "if" {
+        let not = cursor.next_non_space()?;
+        let defined = cursor.next_non_space()?;
+        if !map.is_punct(not, b"!") || map.get_bytes(defined.span).as_ref() != b"defined" {
+            return None;
+        }
+    } else if name != "ifndef" {
+        return None;
+    }
+    let mut operand = cursor.next_non_space()?;
+    let parenthesized = map.is_punct(operand, b"(");
+    if parenthesized {
+        operand = cursor.next_non_space()?;
+    }
+    if operand.kind != TokenKind::Ident
+        || parenthesized
+            && !cursor
+                .next_non_space()
+                .is_some_and(|token| map.is_punct(token, b")"))
+        || cursor.next_non_space().is_some()
+    {
+        return None;
+    }
+    Some(map.spelling(operand))
+}
+
+/// A directory where the tokens of each file are stored, keyed by the hash of its contents.
+#[derive(Clone, Debug)]
+pub(crate) struct TokenCache {
+    dir: PathBuf,
+}
+
+impl TokenCache {
+    /// Create a cache that stores its files in `dir`, which is created if it does not exist.
+    pub(crate) fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_owned(),
+        }
+    }
+
+    /// Get the tokens of the region `span` relative to its start, what is known about the file
+    /// from them and whether they were stored in the cache, tokenizing it and storing the result
+    /// in the cache if they were not. Return `None` if the region contains invalid tokens.
+    ///
+    /// Any error while accessing the cache is ignored, the region is tokenized again instead.
+    pub(crate) fn tokens(
+        &self,
+        map: &SourceMap,
+        span: Span,
+    ) -> Option<(Arc<[Token]>, FileInfo, bool)> {
+        let mut hasher = KeyHasher::new();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write(&[FORMAT, map.lexer_features().index() as u8]);
+        hasher.write(&map.get_bytes(span));
+        let path = self.dir.join(format!("{}.tokens", hasher.finish()));
+
+        if let Some((tokens, info)) = fs::read(&path)
+            .ok()
+            .and_then(|bytes| decode(&bytes, span.hi() - span.lo()))
+        {
+            return Some((tokens.into(), info, true));
+        }
+
+        let tokens = Lines::tokenize(map, span)?;
+        let info = FileInfo::new(map, span, &tokens);
+        // The file is written under a different name first so other processes never read it
+        // half-written.
+        let temporary = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let stored = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&temporary, encode(&tokens, &info)))
+            .and_then(|()| fs::rename(&temporary, &path));
+        if stored.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+
+        Some((tokens.into(), info, false))
+    }
+}
+
+/// Encode the tokens of a region and what is known about its file. The information about the
+/// file comes first: whether it contains `#pragma once`, the length of the name of the macro of
+/// its include guard, which is `0` if there is none, and the name. Tokens are contiguous so only
+/// the kind and the length of each token are stored after it.
+fn encode(tokens: &[Token], info: &FileInfo) -> Vec<u8> {
+    let guard = info.guard.as_deref().unwrap_or_default();
+    let mut bytes = Vec::with_capacity(5 + guard.len() + tokens.len() * 5);
+    bytes.push(u8::from(info.once));
+    bytes.extend_from_slice(&(guard.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(guard.as_bytes());
+    for token in tokens {
+        let kind = KINDS.iter().position(|&kind| kind == token.kind).unwrap();
+        let len = token.span.hi() - token.span.lo();
+        bytes.push(kind as u8);
+        bytes.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+    bytes
+}
+
+/// Decode the tokens and the information encoded by [`encode`]. Return `None` if `bytes` is not
+/// a valid encoding of the tokens of a region of length `len`.
+fn decode(bytes: &[u8], len: usize) -> Option<(Vec<Token>, FileInfo)> {
+    let (&once, bytes) = bytes.split_first()?;
+    let once = match once {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    let (guard_len, bytes) = bytes.split_first_chunk::<4>()?;
+    let guard_len = u32::from_le_bytes(*guard_len) as usize;
+    let guard = match bytes.get(..guard_len)? {
+        [] => None,
+        guard => Some(String::from_utf8(guard.to_vec()).ok()?),
+    };
+    let bytes = &bytes[guard_len..];
+
+    let mut tokens = Vec::with_capacity(bytes.len() / 5);
+    let mut lo = 0;
+    for chunk in bytes.chunks(5) {
+        let (&kind, length) = chunk.split_first()?;
+        let kind = *KINDS.get(usize::from(kind))?;
+        let hi = lo + u32::from_le_bytes(length.try_into().ok()?) as usize;
+        tokens.push(Token::new(kind, Span::new(lo, hi)));
+        lo = hi;
+    }
+    (lo == len).then_some((tokens, FileInfo { guard, once }))
+}
@@ -0,0 +1,169 @@
+//! The time trace of a preprocessing run, written in the trace event format of Chrome.
+use std::{fmt::Write as _, fs, io, path::Path, time::Duration};
+
+use super::{mapping::json_string, Context};
+use crate::{span::Span, time::Instant};
+
+/// The events recorded while preprocessing using
+/// [`time_trace`](super::Preprocessor::time_trace), which can be written as a JSON file in the
+/// trace event format understood by `chrome://tracing`, Perfetto and Speedscope, like the one
+/// produced by the `-ftime-trace` option of Clang.
+///
+/// The events are:
+///
+/// - `Source`: the processing of a file, including the files it includes.
+/// - `ReadFile`: finding and reading an included file.
+/// - `Tokenize`: tokenizing a whole file to store its tokens in a cache. Otherwise, each line
+///   is tokenized right before processing it.
+/// - `Expand`: replacing the macros of the text between two directives.
+///
+/// With the `tracing` feature, the same operations are also reported to the current `tracing`
+/// subscriber whether there is a time trace or not: `process_file`, `read_file`, `include`,
+/// `tokenize` and `expand` spans, and events for each resolved `#include` and for the bytes
+/// tokenized line by line in each file.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let mut preprocessor = beheader::Preprocessor::new();
+/// preprocessor.time_trace(Duration::ZERO);
+/// let preprocessed = preprocessor.preprocess(b"#define A 1\nA\n").unwrap();
+/// let trace = preprocessed.time_trace().unwrap();
+/// assert!(trace.events().iter().any(|event| event.name() == "Expand"));
+/// assert!(trace.to_json().starts_with("{\"traceEvents\":["));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TimeTrace {
+    events: Vec<TraceEvent>,
+}
+
+impl TimeTrace {
+    /// The events of the trace, in the order they ended.
+    pub fn events(&self) -> &[TraceEvent] {
+        &self.events
+    }
+
+    /// Produce the JSON representation of the trace, an object whose `traceEvents` member has
+    /// an array with one complete event per event of the trace. Times are in microseconds.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"traceEvents\":[");
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"name\":\"{}\",\"cat\":\"beheader\",\"ph\":\"X\",\"pid\":1,\"tid\":0,\
+                 \"ts\":{},\"dur\":{},\"args\":{{\"detail\":",
+                event.name,
+                event.start.as_micros(),
+                event.duration.as_micros()
+            )
+            .unwrap();
+            json_string(&mut json, &event.detail);
+            json.push_str("}}");
+        }
+        json.push_str("]}");
+        json
+    }
+
+    /// Write the JSON representation of the trace to the file at `path`.
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+/// Something done while preprocessing and when it was done.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEvent {
+    name: &'static str,
+    detail: String,
+    start: Duration,
+    duration: Duration,
+}
+
+impl TraceEvent {
+    /// The kind of event, as listed in the documentation of [`TimeTrace`].
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The path of the file the event is about or, for `Expand` events, the file and line where
+    /// the text starts.
+    pub fn detail(&self) -> &str {
+        &self.detail
+    }
+
+    /// When the event started, since the start of the run.
+    pub fn start(&self) -> Duration {
+        self.start
+    }
+
+    /// How long the event took.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// The state of the time trace of a run.
+pub(super) struct Tracer {
+    /// When the run started.
+    started: Instant,
+    /// The minimum duration of the events recorded.
+    granularity: Duration,
+    trace: TimeTrace,
+}
+
+impl Tracer {
+    pub(super) fn new(granularity: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            granularity,
+            trace: TimeTrace::default(),
+        }
+    }
+
+    pub(super) fn trace(&self) -> &TimeTrace {
+        &self.trace
+    }
+}
+
+impl Context {
+    /// Record an event named `name` that started at `start` and ends now, if there is a time
+    /// trace and the event is long enough. `detail` is only called if the event is recorded.
+    pub(super) fn trace_event(
+        &mut self,
+        name: &'static str,
+        start: Instant,
+        detail: impl FnOnce(&Self) -> String,
+    ) {
+        let duration = match &self.tracer {
+            Some(tracer) if start.elapsed() >= tracer.granularity => start.elapsed(),
+            _ => return,
+        };
+        let detail = detail(self);
+        let tracer = self.tracer.as_mut().unwrap();
+        tracer.trace.events.push(TraceEvent {
+            name,
+            detail,
+            start: start.saturating_duration_since(tracer.started),
+            duration,
+        });
+    }
+
+    /// Describe the file in the region `span` for the time trace.
+    pub(super) fn trace_file(&self, span: Span) -> String {
+        match self.map.find_file(span) {
+            Some(path) => path.display().to_string(),
+            None => String::from("<stdin>"),
+        }
+    }
+
+    /// Read the file at `path`, recording a `ReadFile` event.
+    pub(super) fn read_file(&mut self, path: &Path) -> io::Result<Span> {
+        let start = Instant::now();
+        let span = self.map.read_file(&path)?;
+        self.trace_event("ReadFile", start, |_| path.display().to_string());
+        Ok(span)
+    }
+}
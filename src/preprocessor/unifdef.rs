@@ -0,0 +1,265 @@
+//! Partial evaluation of conditional inclusion directives, like the `unifdef` tool does.
+use std::collections::HashSet;
+
+use super::{line_span, CommandLineMacro, Context, Preprocessor};
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    error::Error,
+    lexer::{Lines, Token, TokenKind},
+    span::{SourceMap, Span},
+};
+
+impl Preprocessor {
+    /// Remove the conditional inclusion directives of `source` whose conditions only depend on
+    /// the macros defined or undefined using [`define`](Self::define) and
+    /// [`undefine`](Self::undefine), together with the groups they skip. This is what the
+    /// `unifdef` tool does.
+    ///
+    /// Everything else is kept byte for byte, including other directives and the conditional
+    /// inclusion directives that depend on any other macro or only on constants, like `#if 0`.
+    /// If a directive that is kept is followed by a branch that is always taken, the branch
+    /// becomes an `#else` directive. If the first branches of a directive are removed, the next
+    /// one becomes an `#if` directive. The macros defined or undefined by `source` are ignored.
+    ///
+    /// Return an error if any of the files included before processing cannot be read or if the
+    /// source code is not valid.
+    ///
+    /// ```
+    /// use beheader::Preprocessor;
+    ///
+    /// let source = b"#ifdef DEBUG\nlog();\n#elif LEVEL > 1\nwarn();\n#endif\n#if OTHER\nx\n#endif\n";
+    /// let reduced = Preprocessor::new()
+    ///     .undefine("DEBUG")
+    ///     .reduce_conditionals(source)
+    ///     .unwrap();
+    /// assert_eq!(reduced, b"#if LEVEL > 1\nwarn();\n#endif\n#if OTHER\nx\n#endif\n");
+    /// ```
+    pub fn reduce_conditionals(&self, source: &[u8]) -> Result<Vec<u8>, Error> {
+        let known: HashSet<&str> = self
+            .command_line
+            .iter()
+            .map(|item| match item {
+                CommandLineMacro::Define(definition) => {
+                    definition.split(['=', '(']).next().unwrap_or_default()
+                }
+                CommandLineMacro::Undefine(name) => name,
+            })
+            .collect();
+
+        let mut context = self.context()?;
+        let span = context.map.store_bytes(source)?;
+        let mut output = Vec::new();
+        let mut sections: Vec<Section> = Vec::new();
+        let mut lines = Lines::new(span);
+        let mut line = Vec::new();
+
+        while lines.next_line(&context.map, &mut line)? {
+            let live = sections.iter().all(|section| section.live);
+            let (name, rest) = match context.map.directive(&line) {
+                Some((Some(name), rest)) => (name, rest),
+                _ => {
+                    if live {
+                        output.extend_from_slice(&context.map.get_bytes(line_span(&line)));
+                    }
+                    continue;
+                }
+            };
+            let body = match rest.split_last() {
+                Some((last, body)) if last.kind == TokenKind::Newline => body,
+                _ => rest,
+            };
+
+            let directive = context.map.spelling(name);
+            let action = match directive.as_str() {
+                "if" | "ifdef" | "ifndef" => {
+                    let mut section = Section::new(line_span(&line), !live);
+                    let value = if live {
+                        context.known_condition(&directive, line_span(&line), body, &known)?
+                    } else {
+                        None
+                    };
+                    let action = section.branch(value);
+                    sections.push(section);
+                    action
+                }
+                "elif" => {
+                    let section = innermost(&context.map, &mut sections, &line, "#elif")?;
+                    let value = if section.removed || section.taken {
+                        Some(false)
+                    } else {
+                        context.known_condition("if", line_span(&line), body, &known)?
+                    };
+                    innermost(&context.map, &mut sections, &line, "#elif")?.branch(value)
+                }
+                "else" => match innermost(&context.map, &mut sections, &line, "#else")?
+                    .branch(Some(true))
+                {
+                    Action::Else => Action::Keep,
+                    action => action,
+                },
+                "endif" => {
+                    let section = innermost(&context.map, &mut sections, &line, "#endif")?;
+                    let kept = section.kept;
+                    sections.pop();
+                    if kept {
+                        Action::Keep
+                    } else {
+                        Action::Remove
+                    }
+                }
+                _ if live => Action::Keep,
+                _ => Action::Remove,
+            };
+
+            let line_span = line_span(&line);
+            let bytes = context.map.get_bytes(line_span);
+            let (before, after) = (
+                &bytes[..name.span.lo() - line_span.lo()],
+                &bytes[name.span.hi() - line_span.lo()..],
+            );
+            match action {
+                Action::Keep => output.extend_from_slice(&bytes),
+                Action::Remove => {}
+                // Only an `#elif` directive is changed into an `#if` directive.
+                Action::If if directive == "elif" => {
+                    output.extend_from_slice(before);
+                    output.extend_from_slice(b"if");
+                    output.extend_from_slice(after);
+                }
+                Action::If => output.extend_from_slice(&bytes),
+                Action::Else => {
+                    output.extend_from_slice(before);
+                    output.extend_from_slice(b"else");
+                    if line
+                        .last()
+                        .is_some_and(|token| token.kind == TokenKind::Newline)
+                    {
+                        output.extend_from_slice(&context.map.get_bytes(line.last().unwrap().span));
+                    }
+                }
+            }
+        }
+
+        if let Some(section) = sections.last() {
+            let code = DiagnosticCode::UnterminatedConditional;
+            let message = "Unterminated conditional directive";
+            let diagnostic = context
+                .map
+                .diagnostic(Severity::Error, code, section.span, message);
+            return Err(diagnostic.into());
+        }
+
+        Ok(output)
+    }
+}
+
+impl Context {
+    /// Evaluate the condition of an `#if`, `#ifdef` or `#ifndef` directive in the region `span`
+    /// whose tokens after the name are `body` if it only depends on macros whose names are in
+    /// `known`. Return `None` otherwise or an error if the condition is not valid.
+    fn known_condition(
+        &mut self,
+        directive: &str,
+        span: Span,
+        body: &[Token],
+        known: &HashSet<&str>,
+    ) -> Result<Option<bool>, Diagnostic> {
+        let mut names = body
+            .iter()
+            .filter(|token| token.kind == TokenKind::Ident)
+            .map(|&token| self.map.spelling(token))
+            .filter(|name| name != "defined")
+            .peekable();
+        // Conditions without macros, like `#if 0`, are kept.
+        if names.peek().is_none() || !names.all(|name| known.contains(name.as_str())) {
+            return Ok(None);
+        }
+        self.condition(directive, span, body).map(Some)
+    }
+}
+
+/// A conditional inclusion directive whose `#endif` has not been found yet.
+struct Section {
+    /// The region of the directive that started the section.
+    span: Span,
+    /// Whether the whole directive is removed, as it is inside a group that is removed.
+    removed: bool,
+    /// Whether any directive of the section is kept.
+    kept: bool,
+    /// Whether a branch that is always taken has been found already.
+    taken: bool,
+    /// Whether the lines of the current group are kept.
+    live: bool,
+}
+
+impl Section {
+    fn new(span: Span, removed: bool) -> Self {
+        Self {
+            span,
+            removed,
+            kept: false,
+            taken: false,
+            live: false,
+        }
+    }
+
+    /// Start a new branch whose condition has the value `value`, which is `None` if it is not
+    /// known, and decide what to do with its directive.
+    fn branch(&mut self, value: Option<bool>) -> Action {
+        if self.removed || self.taken {
+            self.live = false;
+            return Action::Remove;
+        }
+        match value {
+            Some(false) => {
+                self.live = false;
+                Action::Remove
+            }
+            Some(true) => {
+                self.live = true;
+                self.taken = true;
+                if self.kept {
+                    Action::Else
+                } else {
+                    Action::Remove
+                }
+            }
+            None => {
+                self.live = true;
+                if std::mem::replace(&mut self.kept, true) {
+                    Action::Keep
+                } else {
+                    Action::If
+                }
+            }
+        }
+    }
+}
+
+/// What to do with a directive.
+enum Action {
+    Keep,
+    Remove,
+    /// Keep it as the first branch of its directive.
+    If,
+    /// Replace it with an `#else` directive.
+    Else,
+}
+
+/// Get the innermost section or an error about the directive in `line`, whose spelling is in
+/// `map`, if there is none. `directive` is used in the message.
+fn innermost<'a>(
+    map: &SourceMap,
+    sections: &'a mut [Section],
+    line: &[Token],
+    directive: &str,
+) -> Result<&'a mut Section, Diagnostic> {
+    match sections.last_mut() {
+        Some(section) => Ok(section),
+        None => {
+            let message = format!("{} without #if", directive);
+            let code = DiagnosticCode::UnmatchedConditional;
+            Err(map.diagnostic(Severity::Error, code, line_span(line), message))
+        }
+    }
+}
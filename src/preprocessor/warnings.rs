@@ -0,0 +1,264 @@
+//! The warnings about valid source code that is likely a mistake, which are reported depending on
+//! the [`Warning`](crate::Warning)s enabled, and the `#pragma GCC diagnostic` directives that
+//! enable and disable them.
+use std::path::Path;
+
+use super::Context;
+use crate::{
+    charset::TRIGRAPHS,
+    diagnostic::{DiagnosticCode, Severity},
+    lexer::{Token, TokenKind},
+    macros::Macro,
+    span::Span,
+    symbol::Symbol,
+};
+
+impl Context {
+    /// Report the comments and trigraph sequences of `line` that are likely mistakes.
+    pub(super) fn check_line(&mut self, line: &[Token]) {
+        let comments = self.diagnostics.is_reported(DiagnosticCode::NestedComment)
+            || self
+                .diagnostics
+                .is_reported(DiagnosticCode::MultiLineComment);
+        let trigraphs = self.diagnostics.is_reported(DiagnosticCode::Trigraph);
+
+        if comments {
+            for token in line.iter().filter(|token| token.kind == TokenKind::Space) {
+                let problems = comment_problems(&self.map.get_bytes(token.span));
+                for (pos, code) in problems {
+                    let message = match code {
+                        DiagnosticCode::NestedComment => "\"/*\" within block comment",
+                        _ => "Multi-line \"//\" comment",
+                    };
+                    self.warn(code, subspan(token.span, pos, 2), message);
+                }
+            }
+        }
+
+        if trigraphs {
+            // Trigraph sequences cannot contain white-space, so they are inside a run of tokens
+            // without white-space between them.
+            let runs =
+                line.split(|token| matches!(token.kind, TokenKind::Space | TokenKind::Newline));
+            for run in runs.filter(|run| !run.is_empty()) {
+                let span = Span::new(run[0].span.lo(), run[run.len() - 1].span.hi());
+                let trigraphs: Vec<(usize, u8, char)> = self
+                    .map
+                    .get_bytes(span)
+                    .windows(3)
+                    .enumerate()
+                    .filter(|(_, bytes)| bytes[..2] == *b"??")
+                    .filter_map(|(pos, bytes)| {
+                        let &(byte, replacement) =
+                            TRIGRAPHS.iter().find(|(byte, _)| *byte == bytes[2])?;
+                        Some((pos, byte, replacement))
+                    })
+                    .collect();
+                for (pos, byte, replacement) in trigraphs {
+                    let trigraph = subspan(span, pos, 3);
+                    let message = format!("Trigraph \"??{}\" is ignored", byte as char);
+                    let suggestion = self.map.suggestion(
+                        trigraph,
+                        format!("Replace the trigraph with \"{}\"", replacement),
+                        replacement,
+                    );
+                    let diagnostic = self
+                        .map
+                        .diagnostic(
+                            Severity::Warning,
+                            DiagnosticCode::Trigraph,
+                            trigraph,
+                            message,
+                        )
+                        .with_suggestion(suggestion);
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+        }
+    }
+
+    /// Report a warning with the code `code` about the region `span`.
+    fn warn(&mut self, code: DiagnosticCode, span: Span, message: impl Into<String>) {
+        let diagnostic = self.map.diagnostic(Severity::Warning, code, span, message);
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report the tokens after the name of an `#else` or `#endif` directive, whose tokens after
+    /// the name are `body`.
+    pub(super) fn check_extra_tokens(&mut self, directive: &str, body: &[Token]) {
+        let mut tokens = body.iter().filter(|token| token.kind != TokenKind::Space);
+        let first = match tokens.next() {
+            Some(first) => first,
+            None => return,
+        };
+        if !self.diagnostics.is_reported(DiagnosticCode::ExtraTokens) {
+            return;
+        }
+        let last = tokens.next_back().unwrap_or(first);
+        let span = Span::new(first.span.lo(), last.span.hi());
+        let message = format!("Extra tokens at end of #{} directive", directive);
+        let mut diagnostic = self.map.diagnostic(
+            Severity::Warning,
+            DiagnosticCode::ExtraTokens,
+            span,
+            message,
+        );
+        // The tokens are usually a label naming the condition, which can be kept in a comment.
+        let text = self.map.get_bytes(span);
+        let text = String::from_utf8_lossy(&text).into_owned();
+        if !text.contains("*/") {
+            let replacement = format!("/* {} */", text);
+            let suggestion = self
+                .map
+                .suggestion(span, "Turn them into a comment", replacement);
+            diagnostic = diagnostic.with_suggestion(suggestion);
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report the problems with `previous`, the definition of the macro named `name` replaced or
+    /// removed by the `directive` directive in the region `span`: predefined macros must not be
+    /// redefined or undefined and macros defined in the main file should be used.
+    pub(super) fn check_previous_definition(
+        &mut self,
+        directive: &str,
+        span: Span,
+        name: &str,
+        previous: &Macro,
+    ) {
+        let code = DiagnosticCode::BuiltinMacroRedefined;
+        let built_in = self.map.file_span(Path::new("<built-in>"));
+        if built_in.is_some_and(|built_in| built_in.contains(previous.span))
+            && self.diagnostics.is_reported(code)
+        {
+            let action = match directive {
+                "define" => "Redefining",
+                _ => "Undefining",
+            };
+            let message = format!("{} predefined macro \"{}\"", action, name);
+            self.warn(code, span, message);
+        }
+        if !previous.used.get() {
+            self.report_unused_macro(previous.span, name);
+        }
+    }
+
+    /// Report the macros defined in the main file that are still defined and were never used.
+    pub(super) fn check_unused_macros(&mut self) {
+        if !self.diagnostics.is_reported(DiagnosticCode::UnusedMacro) {
+            return;
+        }
+        let mut unused: Vec<(Span, String)> = self
+            .macros
+            .iter()
+            .filter(|(_, definition)| !definition.used.get())
+            .map(|(name, definition)| (definition.span, name.clone()))
+            .collect();
+        unused.sort_by_key(|(span, _)| span.lo());
+        for (span, name) in unused {
+            self.report_unused_macro(span, &name);
+        }
+    }
+
+    /// Report the definition of the macro named `name`, whose symbol is `symbol`, if it is not
+    /// identical to `previous`, the definition it replaced (section 6.10.3p2 of C17). Predefined
+    /// macros are reported by [`check_previous_definition`](Self::check_previous_definition)
+    /// instead.
+    pub(super) fn check_redefinition(&mut self, symbol: Symbol, name: &str, previous: &Macro) {
+        let code = DiagnosticCode::MacroRedefined;
+        let built_in = self.map.file_span(Path::new("<built-in>"));
+        if built_in.is_some_and(|built_in| built_in.contains(previous.span))
+            || !self.diagnostics.is_reported(code)
+        {
+            return;
+        }
+        let span = match self.macros.get(symbol) {
+            Some(definition) if !definition.is_identical(previous, &self.map) => definition.span,
+            _ => return,
+        };
+        let message = format!("Macro \"{}\" redefined", name);
+        let label = self
+            .map
+            .label(previous.span, "The previous definition is here");
+        let diagnostic = self
+            .map
+            .diagnostic(Severity::Warning, code, span, message)
+            .with_label(label);
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Report the macro named `name`, whose name is in the region `span`, as unused if it was
+    /// defined in the main file.
+    fn report_unused_macro(&mut self, span: Span, name: &str) {
+        let code = DiagnosticCode::UnusedMacro;
+        if self.source.is_some_and(|source| source.contains(span))
+            && self.diagnostics.is_reported(code)
+        {
+            self.warn(code, span, format!("Macro \"{}\" is not used", name));
+        }
+    }
+
+    /// Apply the `#pragma GCC diagnostic` or `#pragma clang diagnostic` directive whose tokens
+    /// after the name are `body`, if it is one. Unsupported warnings are ignored.
+    pub(super) fn pragma_diagnostic(&mut self, body: &[Token]) {
+        let tokens: Vec<String> = body
+            .iter()
+            .filter(|token| token.kind != TokenKind::Space)
+            .map(|&token| self.map.spelling(token))
+            .collect();
+        let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        match tokens[..] {
+            ["GCC" | "clang", "diagnostic", "push"] => self.diagnostics.save(),
+            ["GCC" | "clang", "diagnostic", "pop"] => self.diagnostics.restore(),
+            ["GCC" | "clang", "diagnostic", kind, option] => {
+                if let Some(option) = option
+                    .strip_prefix('"')
+                    .and_then(|option| option.strip_suffix('"'))
+                {
+                    self.diagnostics.severities_mut().apply_pragma(kind, option);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The region of the `len` bytes starting at the offset `pos` of the region `span`.
+fn subspan(span: Span, pos: usize, len: usize) -> Span {
+    Span::new(span.lo() + pos, span.lo() + pos + len)
+}
+
+/// Find the `/*` inside block comments and the line comments continued by line splices in
+/// `bytes`, a sequence of white-space characters and comments. Return the offset of each problem
+/// and its code.
+fn comment_problems(bytes: &[u8]) -> Vec<(usize, DiagnosticCode)> {
+    let mut problems = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match &bytes[i..] {
+            [b'/', b'*', ..] => {
+                let end = bytes[i + 2..]
+                    .windows(2)
+                    .position(|window| window == b"*/")
+                    .map_or(bytes.len(), |pos| i + 2 + pos);
+                let nested = bytes[i + 2..end]
+                    .windows(2)
+                    .enumerate()
+                    .filter(|(_, window)| *window == b"/*")
+                    .map(|(pos, _)| (i + 2 + pos, DiagnosticCode::NestedComment));
+                problems.extend(nested);
+                i = end + 2;
+            }
+            [b'/', b'/', ..] => {
+                // Line comments end at the first new-line character that is not part of a line
+                // splice, which is not part of the white-space, so they go until its end.
+                if bytes[i..].contains(&b'\n') {
+                    problems.push((i, DiagnosticCode::MultiLineComment));
+                }
+                break;
+            }
+            _ => i += 1,
+        }
+    }
+    problems
+}
@@ -0,0 +1,451 @@
+//! Symbolic analysis of conditional inclusion directives. Instead of evaluating the controlling
+//! expressions for a single configuration, the conditions are kept as boolean formulas so the
+//! condition under which each region of a file is compiled can be reported. These conditions
+//! are usually called presence conditions.
+#[cfg(test)]
+mod tests;
+
+use std::{collections::HashMap, fmt, ops};
+
+use crate::{
+    lexer::{Lines, Token, TokenKind},
+    macros::skip_space,
+    span::SourceMap,
+};
+
+/// A boolean formula over the macros of a configuration.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Condition {
+    /// Always true.
+    True,
+    /// Always false.
+    False,
+    /// The macro is defined.
+    Defined(String),
+    /// An expression that is not boolean, such as `VERSION > 2`, which is true when it evaluates
+    /// to a non-zero value. The tokens of the expression are separated by single spaces.
+    Expr(String),
+    /// The negation of a condition.
+    Not(Box<Condition>),
+    /// The conjunction of two or more conditions.
+    And(Vec<Condition>),
+    /// The disjunction of two or more conditions.
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    /// The conjunction of `self` and `other`, simplified.
+    pub fn and(self, other: Self) -> Self {
+        match (self, other) {
+            (Condition::False, _) | (_, Condition::False) => Condition::False,
+            (Condition::True, condition) | (condition, Condition::True) => condition,
+            (Condition::And(mut lhs), Condition::And(rhs)) => {
+                lhs.extend(rhs);
+                Condition::And(lhs)
+            }
+            (Condition::And(mut lhs), rhs) => {
+                lhs.push(rhs);
+                Condition::And(lhs)
+            }
+            (lhs, Condition::And(mut rhs)) => {
+                rhs.insert(0, lhs);
+                Condition::And(rhs)
+            }
+            (lhs, rhs) => Condition::And(vec![lhs, rhs]),
+        }
+    }
+
+    /// The disjunction of `self` and `other`, simplified.
+    pub fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Condition::True, _) | (_, Condition::True) => Condition::True,
+            (Condition::False, condition) | (condition, Condition::False) => condition,
+            (Condition::Or(mut lhs), Condition::Or(rhs)) => {
+                lhs.extend(rhs);
+                Condition::Or(lhs)
+            }
+            (Condition::Or(mut lhs), rhs) => {
+                lhs.push(rhs);
+                Condition::Or(lhs)
+            }
+            (lhs, Condition::Or(mut rhs)) => {
+                rhs.insert(0, lhs);
+                Condition::Or(rhs)
+            }
+            (lhs, rhs) => Condition::Or(vec![lhs, rhs]),
+        }
+    }
+
+    /// Write `self`, enclosed in parentheses if it is a conjunction or a disjunction.
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::And(_) | Condition::Or(_) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+/// The negation of a condition, simplified.
+impl ops::Not for Condition {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        match self {
+            Condition::True => Condition::False,
+            Condition::False => Condition::True,
+            Condition::Not(condition) => *condition,
+            condition => Condition::Not(Box::new(condition)),
+        }
+    }
+}
+
+/// Conditions are written using the syntax of controlling expressions.
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::True => write!(f, "1"),
+            Condition::False => write!(f, "0"),
+            Condition::Defined(name) => write!(f, "defined({})", name),
+            Condition::Expr(expr) => write!(f, "({})", expr),
+            Condition::Not(condition) => {
+                write!(f, "!")?;
+                condition.fmt_operand(f)
+            }
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                let separator = if let Condition::And(_) = self {
+                    " && "
+                } else {
+                    " || "
+                };
+                for (i, condition) in conditions.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", separator)?;
+                    }
+                    // Conjunctions inside disjunctions do not need parentheses but they make the
+                    // result easier to read.
+                    condition.fmt_operand(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A sequence of lines that are not directives and share the same presence condition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    start_line: usize,
+    end_line: usize,
+    condition: Condition,
+}
+
+impl Region {
+    /// The first line of the region, starting from 1.
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+
+    /// The last line of the region, starting from 1.
+    pub fn end_line(&self) -> usize {
+        self.end_line
+    }
+
+    /// The condition under which the region is compiled.
+    pub fn condition(&self) -> &Condition {
+        &self.condition
+    }
+}
+
+/// Compute the presence condition of every region of `source` that is not a directive.
+///
+/// Macros are treated as unknowns, except for the ones defined or undefined unconditionally
+/// inside `source`, which make later uses of `defined` known. Macro replacement is not
+/// performed, so a macro used inside a controlling expression is kept as part of an
+/// [`Condition::Expr`]. The lines after the first one with invalid tokens are not analyzed, and
+/// nothing is analyzed if `source` is larger than the 4 GiB that can be stored.
+///
+/// ```
+/// let source = b"#ifdef A\na\n#elif B > 2\nb\n#else\nc\n#endif\n";
+/// let regions = beheader::presence_conditions(source);
+/// assert_eq!("defined(A)", regions[0].condition().to_string());
+/// assert_eq!("!defined(A) && (B > 2)", regions[1].condition().to_string());
+/// assert_eq!("!defined(A) && !(B > 2)", regions[2].condition().to_string());
+/// ```
+pub fn presence_conditions(source: &[u8]) -> Vec<Region> {
+    let map = SourceMap::default();
+    let span = match map.store_bytes(source) {
+        Ok(span) => span,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut analysis = Analysis {
+        map: &map,
+        known: HashMap::new(),
+        stack: Vec::new(),
+    };
+    let mut regions: Vec<Region> = Vec::new();
+
+    let mut lines = Lines::new(span);
+    let mut line = Vec::new();
+    let mut line_number = 1;
+
+    // The analysis stops at the first line with invalid tokens.
+    while lines.next_line(&map, &mut line).unwrap_or(false) {
+        let start_line = line_number;
+        line_number += line
+            .iter()
+            .map(|token| {
+                map.get_bytes(token.span)
+                    .iter()
+                    .filter(|&&byte| byte == b'\n')
+                    .count()
+            })
+            .sum::<usize>();
+
+        let (name, rest) = match map.directive(&line) {
+            Some(directive) => directive,
+            None => {
+                let condition = analysis.current();
+                let end_line = start_line.max(line_number - 1);
+                match regions.last_mut() {
+                    Some(region)
+                        if region.end_line + 1 >= start_line && region.condition == condition =>
+                    {
+                        region.end_line = end_line;
+                    }
+                    _ => regions.push(Region {
+                        start_line,
+                        end_line,
+                        condition,
+                    }),
+                }
+                continue;
+            }
+        };
+
+        let name = name.map(|name| map.spelling(name));
+        analysis.directive(name.as_deref(), rest);
+    }
+
+    regions
+}
+
+/// A conditional inclusion directive whose `#endif` has not been found yet.
+struct Section {
+    /// The condition of the current group.
+    group: Condition,
+    /// The condition under which none of the previous groups is taken.
+    remaining: Condition,
+}
+
+/// The state of the analysis of a file.
+struct Analysis<'a> {
+    map: &'a SourceMap,
+    /// The macros that are known to be defined or undefined.
+    known: HashMap<String, bool>,
+    /// The directives whose `#endif` has not been found yet.
+    stack: Vec<Section>,
+}
+
+impl Analysis<'_> {
+    /// The presence condition of the current line.
+    fn current(&self) -> Condition {
+        self.stack
+            .iter()
+            .fold(Condition::True, |condition, section| {
+                condition.and(section.group.clone())
+            })
+    }
+
+    /// Update the state after finding a directive named `name` followed by `rest`.
+    fn directive(&mut self, name: Option<&str>, rest: &[Token]) {
+        let body = match rest.split_last() {
+            Some((last, body)) if last.kind == TokenKind::Newline => body,
+            _ => rest,
+        };
+        let macro_name = || {
+            skip_space(&mut body.iter().copied())
+                .filter(|token| token.kind == TokenKind::Ident)
+                .map(|token| self.map.spelling(token))
+        };
+
+        match name {
+            Some(directive @ ("if" | "ifdef" | "ifndef")) => {
+                let condition = match (directive, macro_name()) {
+                    ("if", _) => self.condition(body),
+                    (_, Some(name)) => {
+                        let defined = self.defined(name);
+                        if directive == "ifdef" {
+                            defined
+                        } else {
+                            !defined
+                        }
+                    }
+                    _ => Condition::False,
+                };
+                self.stack.push(Section {
+                    group: condition.clone(),
+                    remaining: !condition,
+                });
+            }
+            Some("elif") => {
+                let condition = self.condition(body);
+                if let Some(section) = self.stack.last_mut() {
+                    section.group = section.remaining.clone().and(condition.clone());
+                    section.remaining = section.remaining.clone().and(!condition);
+                }
+            }
+            Some("else") => {
+                if let Some(section) = self.stack.last_mut() {
+                    section.group = std::mem::replace(&mut section.remaining, Condition::False);
+                }
+            }
+            Some("endif") => {
+                self.stack.pop();
+            }
+            Some(directive @ ("define" | "undef")) => {
+                if let Some(name) = macro_name() {
+                    // Only unconditional definitions make the state of a macro known.
+                    if self.current() == Condition::True {
+                        self.known.insert(name, directive == "define");
+                    } else {
+                        self.known.remove(&name);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The condition that a macro named `name` is defined.
+    fn defined(&self, name: String) -> Condition {
+        match self.known.get(&name) {
+            Some(true) => Condition::True,
+            Some(false) => Condition::False,
+            None => Condition::Defined(name),
+        }
+    }
+
+    /// Convert the controlling expression `tokens` into a condition.
+    fn condition(&self, tokens: &[Token]) -> Condition {
+        let tokens: Vec<Token> = tokens
+            .iter()
+            .copied()
+            .filter(|token| token.kind != TokenKind::Space)
+            .collect();
+        self.expr(&tokens)
+    }
+
+    /// Convert an expression without white-space into a condition. Only the `||`, `&&`, `!`
+    /// and `defined` operators and the constants are interpreted.
+    fn expr(&self, tokens: &[Token]) -> Condition {
+        // The conditional and comma operators have lower precedence than `||`.
+        if self.split(tokens, b"?").len() > 1 || self.split(tokens, b",").len() > 1 {
+            return self.opaque(tokens);
+        }
+
+        let or = self.split(tokens, b"||");
+        if or.len() > 1 {
+            return or.into_iter().fold(Condition::False, |condition, tokens| {
+                condition.or(self.expr(tokens))
+            });
+        }
+
+        let and = self.split(tokens, b"&&");
+        if and.len() > 1 {
+            return and.into_iter().fold(Condition::True, |condition, tokens| {
+                condition.and(self.expr(tokens))
+            });
+        }
+
+        match tokens {
+            [not, rest @ ..] if self.map.is_punct(*not, b"!") && self.is_unary(rest) => {
+                !self.expr(rest)
+            }
+            [_, inner @ .., _] if self.is_parenthesized(tokens) => self.expr(inner),
+            [defined, name] if self.is_defined(*defined) && name.kind == TokenKind::Ident => {
+                self.defined(self.map.spelling(*name))
+            }
+            [defined, open, name, close]
+                if self.is_defined(*defined)
+                    && self.map.is_punct(*open, b"(")
+                    && name.kind == TokenKind::Ident
+                    && self.map.is_punct(*close, b")") =>
+            {
+                self.defined(self.map.spelling(*name))
+            }
+            [number] if number.kind == TokenKind::Number => match &*self.map.spelling(*number) {
+                "0" => Condition::False,
+                "1" => Condition::True,
+                _ => self.opaque(tokens),
+            },
+            _ => self.opaque(tokens),
+        }
+    }
+
+    /// Check if `token` is the `defined` operator.
+    fn is_defined(&self, token: Token) -> bool {
+        token.kind == TokenKind::Ident && &*self.map.get_bytes(token.span) == b"defined"
+    }
+
+    /// Check if `tokens` is a unary expression that can be interpreted as a condition.
+    fn is_unary(&self, tokens: &[Token]) -> bool {
+        match tokens {
+            [_] => true,
+            [not, rest @ ..] if self.map.is_punct(*not, b"!") => self.is_unary(rest),
+            [defined, name] => self.is_defined(*defined) && name.kind == TokenKind::Ident,
+            _ => self.is_parenthesized(tokens),
+        }
+    }
+
+    /// Check if `tokens` starts with a `(` that is closed by its last token.
+    fn is_parenthesized(&self, tokens: &[Token]) -> bool {
+        if !tokens
+            .first()
+            .is_some_and(|&token| self.map.is_punct(token, b"("))
+        {
+            return false;
+        }
+
+        let mut depth = 0usize;
+        for (i, &token) in tokens.iter().enumerate() {
+            if self.map.is_punct(token, b"(") {
+                depth += 1;
+            } else if self.map.is_punct(token, b")") {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1 == tokens.len();
+                }
+            }
+        }
+        false
+    }
+
+    /// Split `tokens` at the binary operator `punct` when it is not enclosed in parentheses.
+    fn split<'t>(&self, tokens: &'t [Token], punct: &[u8]) -> Vec<&'t [Token]> {
+        let mut parts = Vec::new();
+        let mut depth = 0usize;
+        let mut start = 0;
+        for (i, &token) in tokens.iter().enumerate() {
+            if self.map.is_punct(token, b"(") {
+                depth += 1;
+            } else if self.map.is_punct(token, b")") {
+                depth = depth.saturating_sub(1);
+            } else if depth == 0 && self.map.is_punct(token, punct) {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            }
+        }
+        parts.push(&tokens[start..]);
+        parts
+    }
+
+    /// Keep an expression that cannot be interpreted as a condition.
+    fn opaque(&self, tokens: &[Token]) -> Condition {
+        let spelling: Vec<String> = tokens
+            .iter()
+            .map(|&token| self.map.spelling(token))
+            .collect();
+        Condition::Expr(spelling.join(" "))
+    }
+}
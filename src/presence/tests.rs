@@ -0,0 +1,72 @@
+use super::{presence_conditions, Condition};
+
+/// Compute the presence conditions of `source` and check that they are `expected`, given as the
+/// first and last line of each region and its condition.
+#[track_caller]
+fn regions_eq(source: &str, expected: &[(usize, usize, &str)]) {
+    let regions: Vec<_> = presence_conditions(source.as_bytes())
+        .into_iter()
+        .map(|region| {
+            (
+                region.start_line(),
+                region.end_line(),
+                region.condition().to_string(),
+            )
+        })
+        .collect();
+    let expected: Vec<_> = expected
+        .iter()
+        .map(|&(start, end, condition)| (start, end, condition.to_owned()))
+        .collect();
+    assert_eq!(expected, regions);
+}
+
+#[test]
+fn nested() {
+    regions_eq(
+        "a\nb\n#ifdef A\nc\n#ifndef B\nd\n#endif\n#else\ne\n#endif\nf\n",
+        &[
+            (1, 2, "1"),
+            (4, 4, "defined(A)"),
+            (6, 6, "defined(A) && !defined(B)"),
+            (9, 9, "!defined(A)"),
+            (11, 11, "1"),
+        ],
+    );
+}
+
+#[test]
+fn expressions() {
+    regions_eq(
+        "#if defined(A) || !defined B && (C || 0)\na\n#elif X > 1 ? 1 : 0\nb\n#endif\n",
+        &[
+            (2, 2, "defined(A) || (!defined(B) && (C))"),
+            (
+                4,
+                4,
+                "!(defined(A) || (!defined(B) && (C))) && (X > 1 ? 1 : 0)",
+            ),
+        ],
+    );
+}
+
+#[test]
+fn known_macros() {
+    regions_eq(
+        "#define A\n#undef B\n#ifdef A\na\n#endif\n#if defined(B) || C\nb\n#endif\n#if 0\nc\n#endif\n",
+        &[(4, 4, "1"), (7, 7, "(C)"), (10, 10, "0")],
+    );
+}
+
+#[test]
+fn simplify() {
+    let a = Condition::Defined("A".to_owned());
+    assert_eq!(a.clone(), !!a.clone());
+    assert_eq!(a.clone(), Condition::True.and(a.clone()));
+    assert_eq!(Condition::False, Condition::False.and(a.clone()));
+    assert_eq!(Condition::True, a.clone().or(Condition::True));
+    assert_eq!(
+        Condition::And(vec![a.clone(), a.clone(), a.clone()]),
+        a.clone().and(a.clone()).and(a)
+    );
+}
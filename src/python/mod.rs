@@ -0,0 +1,336 @@
+//! Bindings to use the preprocessor from Python, built as an extension module named `beheader`
+//! using `maturin` and the `pyproject.toml` of the crate.
+//!
+//! ```python
+//! import beheader
+//!
+//! preprocessor = beheader.Preprocessor().include_dir("include").define("NDEBUG")
+//! preprocessed = preprocessor.preprocess_file("main.c")
+//! for diagnostic in preprocessed.diagnostics:
+//!     print(diagnostic)
+//! print(preprocessed.get_macro("VERSION").replacement)
+//! identifiers = [token.spelling for token in preprocessed.tokens() if token.kind == "ident"]
+//! ```
+#[cfg(test)]
+mod tests;
+
+use pyo3::{create_exception, exceptions::PyException, exceptions::PyValueError, prelude::*};
+
+use crate::{Emitter, Error, MacroDefinition, Preprocessor, Standard};
+
+create_exception!(
+    beheader,
+    PreprocessorError,
+    PyException,
+    "The error that stopped the preprocessing. Its arguments are the message of the error, the \
+     `Diagnostic` describing it, or `None` if a file could not be read, and the list of \
+     diagnostics reported before it."
+);
+
+impl PreprocessorError {
+    fn from_error(error: Error) -> PyErr {
+        let diagnostic = error.diagnostic().map(PyDiagnostic::new);
+        let reported: Vec<_> = error.diagnostics().iter().map(PyDiagnostic::new).collect();
+        Self::new_err((error.to_string(), diagnostic, reported))
+    }
+}
+
+/// The source code to preprocess, either as text or as bytes.
+#[derive(FromPyObject)]
+enum Source {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Source {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+/// A preprocessor and its configuration. Each configuration method returns the preprocessor, so
+/// they can be chained.
+#[pyclass(name = "Preprocessor", module = "beheader")]
+#[derive(Default)]
+struct PyPreprocessor {
+    preprocessor: Preprocessor,
+}
+
+#[pymethods]
+impl PyPreprocessor {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define a macro as the `-D` option of a compiler does, using `NAME` or
+    /// `NAME=DEFINITION`.
+    fn define<'py>(mut slf: PyRefMut<'py, Self>, definition: &str) -> PyRefMut<'py, Self> {
+        slf.preprocessor.define(definition);
+        slf
+    }
+
+    /// Undefine a macro as the `-U` option of a compiler does.
+    fn undefine<'py>(mut slf: PyRefMut<'py, Self>, name: &str) -> PyRefMut<'py, Self> {
+        slf.preprocessor.undefine(name);
+        slf
+    }
+
+    /// Add a directory where included files are searched, as the `-I` option of a compiler does.
+    fn include_dir<'py>(mut slf: PyRefMut<'py, Self>, dir: &str) -> PyRefMut<'py, Self> {
+        slf.preprocessor.include_dir(dir);
+        slf
+    }
+
+    /// Add a directory where included files are searched and considered system headers, as the
+    /// `-isystem` option of a compiler does.
+    fn system_include_dir<'py>(mut slf: PyRefMut<'py, Self>, dir: &str) -> PyRefMut<'py, Self> {
+        slf.preprocessor.system_include_dir(dir);
+        slf
+    }
+
+    /// Include a file before the input, as the `-include` option of a compiler does.
+    fn include<'py>(mut slf: PyRefMut<'py, Self>, path: &str) -> PyRefMut<'py, Self> {
+        slf.preprocessor.include(path);
+        slf
+    }
+
+    /// Set the version of the C standard using its name for the `-std` option of GCC, like
+    /// `c99` or `gnu11`. Raise `ValueError` if the standard is not known.
+    fn standard<'py>(mut slf: PyRefMut<'py, Self>, name: &str) -> PyResult<PyRefMut<'py, Self>> {
+        let standard = Standard::from_name(name)
+            .ok_or_else(|| PyValueError::new_err(format!("unknown standard {:?}", name)))?;
+        slf.preprocessor.standard(standard);
+        Ok(slf)
+    }
+
+    /// Enable or disable warnings using an option like `-Wall` or `-Werror=trigraphs`.
+    fn warning_option<'py>(mut slf: PyRefMut<'py, Self>, option: &str) -> PyRefMut<'py, Self> {
+        slf.preprocessor.warning_option(option);
+        slf
+    }
+
+    /// Set the maximum number of errors before the preprocessing stops, where 0 means there is
+    /// no limit.
+    fn max_errors<'py>(mut slf: PyRefMut<'py, Self>, limit: usize) -> PyRefMut<'py, Self> {
+        slf.preprocessor.max_errors(limit);
+        slf
+    }
+
+    /// Preprocess `source`, given as `str` or `bytes`. Raise `PreprocessorError` if the
+    /// preprocessing cannot finish.
+    fn preprocess(&self, py: Python<'_>, source: Source) -> PyResult<PyPreprocessed> {
+        py.detach(|| self.preprocessor.preprocess(source.as_bytes()))
+            .map(PyPreprocessed::new)
+            .map_err(PreprocessorError::from_error)
+    }
+
+    /// Preprocess the file at `path`. Raise `PreprocessorError` if the preprocessing cannot
+    /// finish.
+    fn preprocess_file(&self, py: Python<'_>, path: &str) -> PyResult<PyPreprocessed> {
+        py.detach(|| self.preprocessor.preprocess_file(&path))
+            .map(PyPreprocessed::new)
+            .map_err(PreprocessorError::from_error)
+    }
+}
+
+/// The result of preprocessing some source code.
+#[pyclass(name = "Preprocessed", module = "beheader", unsendable)]
+struct PyPreprocessed {
+    preprocessed: crate::Preprocessed,
+}
+
+impl PyPreprocessed {
+    fn new(preprocessed: crate::Preprocessed) -> Self {
+        Self { preprocessed }
+    }
+}
+
+#[pymethods]
+impl PyPreprocessed {
+    /// The output as text, with line markers unless `line_markers` is `False`.
+    #[pyo3(signature = (line_markers = true))]
+    fn text(&self, line_markers: bool) -> String {
+        let text = Emitter::new()
+            .line_markers(line_markers)
+            .render(&self.preprocessed);
+        String::from_utf8_lossy(&text).into_owned()
+    }
+
+    /// The tokens of the output, including white-space and new-lines.
+    fn tokens(&self) -> Vec<PyToken> {
+        let snapshot = self.preprocessed.source_map();
+        self.preprocessed
+            .tokens()
+            .iter()
+            .map(|token| {
+                let spelling = snapshot.spelling(token.span()).unwrap_or_default();
+                let location = self
+                    .preprocessed
+                    .resolve_span(token.span())
+                    .filter(|location| location.file().is_some());
+                PyToken {
+                    kind: format!("{:?}", token.kind()).to_lowercase(),
+                    spelling: String::from_utf8_lossy(spelling).into_owned(),
+                    file: location
+                        .as_ref()
+                        .and_then(|location| location.file())
+                        .map(|file| file.display().to_string()),
+                    line: location.as_ref().map(|location| location.start_line()),
+                    column: location.as_ref().map(|location| location.start_column()),
+                }
+            })
+            .collect()
+    }
+
+    /// The macros defined at the end of the input, sorted by name.
+    fn macros(&self) -> Vec<PyMacro> {
+        self.preprocessed.macros().map(PyMacro::new).collect()
+    }
+
+    /// Get the macro named `name` if it is defined at the end of the input.
+    fn get_macro(&self, name: &str) -> Option<PyMacro> {
+        self.preprocessed.get_macro(name).map(PyMacro::new)
+    }
+
+    /// The diagnostics reported while preprocessing.
+    #[getter]
+    fn diagnostics(&self) -> Vec<PyDiagnostic> {
+        let diagnostics = self.preprocessed.diagnostics();
+        diagnostics.iter().map(PyDiagnostic::new).collect()
+    }
+
+    /// The paths of the files read while preprocessing, starting with the input file if it is a
+    /// file.
+    fn dependencies(&self) -> Vec<String> {
+        let dependencies = self.preprocessed.dependencies();
+        let paths = dependencies.iter().map(|path| path.display().to_string());
+        paths.collect()
+    }
+}
+
+/// A token of the output. Its kind is one of `header`, `ident`, `number`, `char`, `str`,
+/// `punct`, `any`, `space` and `newline`, and its position is `None` if it is not in a file.
+#[pyclass(name = "Token", module = "beheader", frozen, get_all)]
+struct PyToken {
+    kind: String,
+    spelling: String,
+    file: Option<String>,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+#[pymethods]
+impl PyToken {
+    fn __repr__(&self) -> String {
+        format!("Token({}, {:?})", self.kind, self.spelling)
+    }
+}
+
+/// A macro definition. The parameters are `None` for object-like macros.
+#[pyclass(name = "Macro", module = "beheader", frozen)]
+struct PyMacro {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    params: Option<Vec<String>>,
+    #[pyo3(get)]
+    variadic: bool,
+    #[pyo3(get)]
+    replacement: String,
+    #[pyo3(get)]
+    file: Option<String>,
+    #[pyo3(get)]
+    line: Option<usize>,
+    /// The definition as a `#define` directive.
+    definition: String,
+}
+
+impl PyMacro {
+    fn new(definition: MacroDefinition<'_>) -> Self {
+        Self {
+            name: definition.name().to_owned(),
+            params: definition.params().map(<[String]>::to_vec),
+            variadic: definition.is_variadic(),
+            replacement: definition.replacement(),
+            file: definition.file().map(|file| file.display().to_string()),
+            line: definition.line(),
+            definition: definition.to_string(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyMacro {
+    fn __str__(&self) -> &str {
+        &self.definition
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Macro({:?})", self.definition)
+    }
+}
+
+/// A diagnostic reported while preprocessing. Its severity is one of `note`, `warning` and
+/// `error`, and its position is `None` if it is not about a file.
+#[pyclass(name = "Diagnostic", module = "beheader", frozen)]
+struct PyDiagnostic {
+    #[pyo3(get)]
+    severity: String,
+    #[pyo3(get)]
+    code: String,
+    #[pyo3(get)]
+    message: String,
+    #[pyo3(get)]
+    file: Option<String>,
+    #[pyo3(get)]
+    line: Option<usize>,
+    #[pyo3(get)]
+    column: Option<usize>,
+    #[pyo3(get)]
+    notes: Vec<String>,
+    /// The diagnostic as it is shown by a compiler.
+    text: String,
+}
+
+impl PyDiagnostic {
+    fn new(diagnostic: &crate::Diagnostic) -> Self {
+        Self {
+            severity: format!("{:?}", diagnostic.severity()).to_lowercase(),
+            code: diagnostic.code().as_str().to_owned(),
+            message: diagnostic.message().to_owned(),
+            file: diagnostic.file().map(|file| file.display().to_string()),
+            line: diagnostic.line(),
+            column: diagnostic.column(),
+            notes: diagnostic.notes().to_vec(),
+            text: diagnostic.to_string(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyDiagnostic {
+    fn __str__(&self) -> &str {
+        &self.text
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Diagnostic({}, {:?})", self.code, self.message)
+    }
+}
+
+/// The `beheader` Python module.
+#[pymodule]
+fn beheader(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyPreprocessor>()?;
+    module.add_class::<PyPreprocessed>()?;
+    module.add_class::<PyToken>()?;
+    module.add_class::<PyMacro>()?;
+    module.add_class::<PyDiagnostic>()?;
+    let error = module.py().get_type::<PreprocessorError>();
+    module.add("PreprocessorError", error)?;
+    Ok(())
+}
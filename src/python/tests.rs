@@ -0,0 +1,74 @@
+use std::ffi::CStr;
+
+use pyo3::{types::PyDict, wrap_pymodule};
+
+use super::*;
+
+/// Run the Python code `code` with the `beheader` module imported.
+fn run(code: &CStr) {
+    Python::initialize();
+    Python::attach(|py| {
+        let globals = PyDict::new(py);
+        globals
+            .set_item("beheader", wrap_pymodule!(beheader)(py))
+            .unwrap();
+        if let Err(error) = py.run(code, Some(&globals), None) {
+            error.print(py);
+            panic!("the Python code failed");
+        }
+    });
+}
+
+#[test]
+fn preprocess() {
+    run(cr##"
+preprocessor = beheader.Preprocessor().define("DEBUG=1").standard("gnu11")
+preprocessed = preprocessor.preprocess("#if DEBUG\nint debug;\n#endif\n#define A(x, ...) x\n")
+assert preprocessed.text() == '# 1 "<stdin>"\n\nint debug;\n\n\n'
+assert preprocessed.text(line_markers=False) == "int debug;\n\n"
+assert preprocessed.diagnostics == []
+
+tokens = [(token.kind, token.spelling) for token in preprocessed.tokens()]
+assert tokens[1:4] == [("ident", "int"), ("space", " "), ("ident", "debug")], tokens
+token = preprocessed.tokens()[1]
+assert (token.file, token.line, token.column) == (None, None, None)
+
+macro = preprocessed.get_macro("A")
+assert macro.params == ["x"] and macro.variadic and macro.replacement == "x"
+assert str(macro) == "#define A(x, ...) x"
+assert preprocessed.get_macro("B") is None
+assert "A" in [macro.name for macro in preprocessed.macros()]
+assert preprocessed.get_macro("DEBUG").file == "<command-line>"
+
+preprocessed = beheader.Preprocessor().preprocess(b"#define A 1\n#define A 2\n")
+[diagnostic] = preprocessed.diagnostics
+assert diagnostic.severity == "warning" and diagnostic.code == "macro-redefined"
+assert diagnostic.message == 'Macro "A" redefined'
+
+try:
+    beheader.Preprocessor().standard("c42")
+    assert False
+except ValueError:
+    pass
+"##);
+}
+
+#[test]
+fn errors() {
+    run(cr##"
+try:
+    beheader.Preprocessor().preprocess("#if 1/0\n#endif\n#include <nonexistent.h>\n")
+    assert False
+except beheader.PreprocessorError as error:
+    message, diagnostic, reported = error.args
+    assert diagnostic.code == "include-not-found" and diagnostic.severity == "error"
+    assert message == str(diagnostic)
+    assert [diagnostic.code for diagnostic in reported] == ["division-by-zero"]
+
+try:
+    beheader.Preprocessor().preprocess_file("/nonexistent/beheader.c")
+    assert False
+except beheader.PreprocessorError as error:
+    assert error.args[1] is None
+"##);
+}
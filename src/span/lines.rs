@@ -0,0 +1,130 @@
+//! Tables with the start of each line of a stored region, used to find the line and column of an
+//! offset without scanning the region again, and the units columns are counted in.
+
+/// How columns are counted. Columns always start from 1.
+///
+/// ```
+/// use beheader::{ColumnUnit, MemoryFileSystem, Preprocessor};
+///
+/// let column = |unit| {
+///     let mut files = MemoryFileSystem::new();
+///     files.insert("main.c", "\t/* \u{e9}\u{1f600} */ #if 1 / 0\n#endif\n");
+///     let mut preprocessor = Preprocessor::new();
+///     preprocessor.file_system(files).column_unit(unit);
+///     let preprocessed = preprocessor.preprocess_file(&"main.c").unwrap();
+///     preprocessed.diagnostics()[0].column().unwrap()
+/// };
+/// assert_eq!(column(ColumnUnit::Bytes), 23);
+/// assert_eq!(column(ColumnUnit::Chars), 19);
+/// assert_eq!(column(ColumnUnit::Utf16), 20);
+/// assert_eq!(column(ColumnUnit::Display { tab_width: 8 }), 26);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ColumnUnit {
+    /// Bytes, as GCC and Clang report them by default.
+    #[default]
+    Bytes,
+    /// Unicode scalar values.
+    Chars,
+    /// UTF-16 code units, as required by the Language Server Protocol.
+    Utf16,
+    /// Unicode scalar values, where a tab advances to the next multiple of `tab_width` columns,
+    /// like the `-fdiagnostics-column-unit=display` option of GCC. A `tab_width` of `0` counts
+    /// tabs as a single column.
+    Display {
+        /// The number of columns between tab stops.
+        tab_width: usize,
+    },
+}
+
+impl ColumnUnit {
+    /// The column of the position after `before`, the start of a line. Invalid UTF-8 sequences
+    /// count as a single replacement character.
+    pub(crate) fn column(self, before: &[u8]) -> usize {
+        let text = || String::from_utf8_lossy(before);
+        let width = match self {
+            Self::Bytes => before.len(),
+            Self::Chars => text().chars().count(),
+            Self::Utf16 => text().encode_utf16().count(),
+            Self::Display { tab_width } => text().chars().fold(0, |width, ch| {
+                if ch == '\t' && tab_width > 0 {
+                    (width / tab_width + 1) * tab_width
+                } else {
+                    width + 1
+                }
+            }),
+        };
+        width + 1
+    }
+}
+
+/// The offsets where each line of a sequence of bytes starts. A line ends after a new-line
+/// character, so there is always at least one line, even for empty sequences.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct LineTable {
+    starts: Vec<usize>,
+}
+
+impl LineTable {
+    /// Find the start of each line of `bytes`.
+    pub(crate) fn new(bytes: &[u8]) -> Self {
+        let newlines = bytes
+            .iter()
+            .enumerate()
+            .filter(|(_, &byte)| byte == b'\n')
+            .map(|(pos, _)| pos + 1);
+        Self {
+            starts: std::iter::once(0).chain(newlines).collect(),
+        }
+    }
+
+    /// Use `starts` as the start of each line of `bytes`. Return `None` if they are not the
+    /// start of the first line followed by the offsets right after new-line characters of
+    /// `bytes`, in order. The new-line characters without a line after them are not found, so
+    /// the lines are wrong but valid.
+    pub(crate) fn from_starts(bytes: &[u8], starts: Vec<usize>) -> Option<Self> {
+        let valid = starts.first() == Some(&0)
+            && starts.windows(2).all(|pair| pair[0] < pair[1])
+            && starts[1..]
+                .iter()
+                .all(|&start| bytes.get(start - 1) == Some(&b'\n'));
+        valid.then_some(Self { starts })
+    }
+
+    /// The start of each line.
+    pub(crate) fn starts(&self) -> &[usize] {
+        &self.starts
+    }
+
+    /// The number of lines.
+    pub(crate) fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// The index of the line containing the byte at `offset`, starting from 0. An offset right
+    /// after a new-line character is at the start of the next line.
+    pub(crate) fn index(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset) - 1
+    }
+
+    /// The line and column of the byte at `offset` of `bytes`, the bytes whose lines are in the
+    /// table, starting from 1 and with the column counted in `unit`.
+    pub(crate) fn position(&self, bytes: &[u8], offset: usize, unit: ColumnUnit) -> (usize, usize) {
+        let index = self.index(offset);
+        (index + 1, unit.column(&bytes[self.starts[index]..offset]))
+    }
+
+    /// The start of the line at `index` and the end of its contents, which is where its new-line
+    /// character is or `len`, the length of the bytes, for the last line.
+    pub(crate) fn range(&self, index: usize, len: usize) -> (usize, usize) {
+        let end = self.starts.get(index + 1).map_or(len, |next| next - 1);
+        (self.starts[index], end)
+    }
+
+    /// The start of the line containing the byte at `offset` and the end of its contents, like
+    /// [`range`](Self::range).
+    pub(crate) fn line(&self, offset: usize, len: usize) -> (usize, usize) {
+        self.range(self.index(offset), len)
+    }
+}
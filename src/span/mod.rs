@@ -1,11 +1,168 @@
+mod lines;
+mod snapshot;
 mod source_map;
-pub(crate) use source_map::SourceMap;
+use std::path::PathBuf;
+
+pub use lines::ColumnUnit;
+use lines::LineTable;
+pub use snapshot::{SourceMapSnapshot, SourceSnippet, StoredRegion};
+pub(crate) use source_map::{LineMarkerFlags, PresumedLine, SourceMap};
 
 /// A region of code. The position of a span is *not* guaranteed to be relative to the start of the
-/// file that includes the region. The methods inside [`SourceMap`] can be used to extract the
-/// string representation of this region.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) struct Span {
-    pub(crate) lo: usize,
-    pub(crate) hi: usize,
+/// file that includes the region. The [`SourceMapSnapshot`] of the result of preprocessing can be
+/// used to extract the string representation of this region.
+///
+/// Offsets are stored in 32 bits to keep tokens small, so the source code stored while
+/// preprocessing can have at most [`u32::MAX`] bytes. Storing more than that is reported as an
+/// error, either when reading a file or as a
+/// [`SourceTooLarge`](crate::DiagnosticCode::SourceTooLarge) error when producing tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    lo: u32,
+    hi: u32,
+}
+
+/// The largest offset a [`Span`] can have.
+pub(crate) const MAX_OFFSET: usize = u32::MAX as usize;
+
+impl Span {
+    /// Create the region from `lo` to `hi`, which cannot be larger than [`MAX_OFFSET`] as the
+    /// source map checks before storing source code.
+    pub(crate) fn new(lo: usize, hi: usize) -> Self {
+        debug_assert!(lo <= hi && hi <= MAX_OFFSET);
+        Self {
+            lo: lo as u32,
+            hi: hi as u32,
+        }
+    }
+
+    /// The start of the region.
+    pub fn lo(&self) -> usize {
+        self.lo as usize
+    }
+
+    /// The end of the region, which is not part of it.
+    pub fn hi(&self) -> usize {
+        self.hi as usize
+    }
+
+    /// The smallest region containing both regions. The result is only meaningful if both regions
+    /// are inside the same region of the source map, like a file.
+    ///
+    /// ```
+    /// let preprocessed = beheader::preprocess(b"int a;\n").unwrap();
+    /// let tokens = preprocessed.tokens();
+    /// let (int, semicolon) = (tokens[0].span(), tokens[3].span());
+    /// let declaration = int.join(semicolon);
+    /// assert_eq!(preprocessed.source_map().spelling(declaration), Some(&b"int a;"[..]));
+    /// assert_eq!(semicolon.join(int), declaration);
+    /// assert!(declaration.contains(semicolon));
+    /// assert!(!int.overlaps(semicolon));
+    /// assert!(int.overlaps(declaration));
+    /// ```
+    pub fn join(&self, other: Span) -> Span {
+        Span {
+            lo: self.lo.min(other.lo),
+            hi: self.hi.max(other.hi),
+        }
+    }
+
+    /// Check if `other` is inside the region. Empty regions are inside the regions they are at the
+    /// start or end of.
+    pub fn contains(&self, other: Span) -> bool {
+        self.lo <= other.lo && other.hi <= self.hi
+    }
+
+    /// Check if both regions have at least one byte in common, so empty regions do not overlap
+    /// with any region.
+    pub fn overlaps(&self, other: Span) -> bool {
+        self.lo < other.hi && other.lo < self.hi
+    }
+}
+
+/// Where a [`Span`] is inside the region stored in the source map that contains it, which is
+/// usually a file. It can be obtained using [`Preprocessed::resolve_span`] or
+/// [`SourceMapSnapshot::resolve`].
+///
+/// Lines and columns start from 1 and columns are counted in bytes unless another [`ColumnUnit`]
+/// is chosen. The end is the position right after the last byte of the span, so it is the same as
+/// the start for empty spans.
+///
+/// [`Preprocessed::resolve_span`]: crate::Preprocessed::resolve_span
+///
+/// ```
+/// use beheader::{MemoryFileSystem, Preprocessor, TokenKind};
+///
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("main.c", "#define TEXT \"text\"\nconst char *a =\n  TEXT;\n");
+/// let preprocessed = Preprocessor::new()
+///     .file_system(files)
+///     .preprocess_file(&"main.c")
+///     .unwrap();
+/// let string = preprocessed
+///     .tokens()
+///     .iter()
+///     .find(|token| token.kind() == TokenKind::Str)
+///     .unwrap();
+/// let location = preprocessed.resolve_span(string.span()).unwrap();
+/// assert_eq!(location.file().unwrap().to_str(), Some("main.c"));
+/// assert_eq!((location.start_line(), location.start_column()), (1, 14));
+/// assert_eq!((location.end_line(), location.end_column()), (1, 20));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpanLocation {
+    file: Option<PathBuf>,
+    start_line: usize,
+    start_column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+impl SpanLocation {
+    /// Find where the bytes from `lo` to `hi` of `contents`, whose lines are `lines`, are,
+    /// counting columns in `unit`. The contents are the ones of the file at `file` if it is a file.
+    fn new(
+        file: Option<PathBuf>,
+        contents: &[u8],
+        lines: &LineTable,
+        (lo, hi): (usize, usize),
+        unit: ColumnUnit,
+    ) -> Self {
+        let (start_line, start_column) = lines.position(contents, lo, unit);
+        let (end_line, end_column) = lines.position(contents, hi, unit);
+        Self {
+            file,
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+        }
+    }
+
+    /// The file containing the span, if it is in a file. Spans produced by the preprocessor, like
+    /// the ones of the tokens produced by the `#` and `##` operators, are not.
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    /// The line where the span starts.
+    pub fn start_line(&self) -> usize {
+        self.start_line
+    }
+
+    /// The column where the span starts.
+    pub fn start_column(&self) -> usize {
+        self.start_column
+    }
+
+    /// The line where the span ends.
+    pub fn end_line(&self) -> usize {
+        self.end_line
+    }
+
+    /// The column right after the last byte of the span.
+    pub fn end_column(&self) -> usize {
+        self.end_column
+    }
 }
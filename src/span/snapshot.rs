@@ -0,0 +1,385 @@
+//! A copy of the contents of a [`SourceMap`](super::SourceMap) that does not depend on the
+//! preprocessor.
+use std::{
+    cell::OnceCell,
+    io::{self, Read, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use super::{ColumnUnit, LineTable, Span, SpanLocation};
+
+/// A copy of all the source code seen while preprocessing, which can be used to get the spelling
+/// of the [`Span`]s of the result without the preprocessor, possibly in another program.
+///
+/// ```
+/// let preprocessed = beheader::preprocess(b"#define A 1 + 2\nint a = A;\n").unwrap();
+/// let snapshot = preprocessed.source_map();
+/// let spellings: Vec<_> = preprocessed
+///     .tokens()
+///     .iter()
+///     .map(|token| snapshot.spelling(token.span()).unwrap())
+///     .collect();
+/// assert_eq!(
+///     spellings,
+///     [&b"\n"[..], b"int", b" ", b"a", b" ", b"=", b" ", b"1", b" ", b"+", b" ", b"2", b";", b"\n"]
+/// );
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawSnapshot")
+)]
+pub struct SourceMapSnapshot {
+    pub(super) regions: Vec<StoredRegion>,
+}
+
+/// The fields of a [`SourceMapSnapshot`] as they are deserialized, before they are checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawSnapshot {
+    regions: Vec<StoredRegion>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawSnapshot> for SourceMapSnapshot {
+    type Error = io::Error;
+
+    fn try_from(raw: RawSnapshot) -> io::Result<Self> {
+        Self::new(raw.regions)
+    }
+}
+
+impl SourceMapSnapshot {
+    /// Create a snapshot with `regions`. Return an error if they are not sorted by their
+    /// positions or if they overlap.
+    fn new(regions: Vec<StoredRegion>) -> io::Result<Self> {
+        let sorted = regions
+            .windows(2)
+            .all(|pair| pair[0].span.hi() <= pair[1].span.lo());
+        if !sorted {
+            return Err(invalid_snapshot());
+        }
+        Ok(Self { regions })
+    }
+
+    /// The regions stored, sorted by their positions.
+    pub fn regions(&self) -> &[StoredRegion] {
+        &self.regions
+    }
+
+    /// Get the stored region containing `span`, if any.
+    fn region(&self, span: Span) -> Option<&StoredRegion> {
+        // The region of an empty file is at the start of the region stored after it.
+        let key = |region: &StoredRegion| (region.span.lo(), region.span.hi());
+        if let Ok(index) = self
+            .regions
+            .binary_search_by_key(&(span.lo(), span.hi()), key)
+        {
+            return Some(&self.regions[index]);
+        }
+        let index = self
+            .regions
+            .partition_point(|region| region.span.lo() <= span.lo());
+        let region = self.regions[..index].last()?;
+        (span.lo() <= span.hi() && region.span.contains(span)).then_some(region)
+    }
+
+    /// Get the contents of the region `span`, which must be inside a single stored region.
+    /// Return `None` otherwise.
+    pub fn spelling(&self, span: Span) -> Option<&[u8]> {
+        let region = self.region(span)?;
+        Some(&region.contents[span.lo() - region.span.lo()..span.hi() - region.span.lo()])
+    }
+
+    /// Get the lines containing `span`, which must be inside a single stored region, with up to
+    /// `context_lines` lines before and after them. Return `None` otherwise.
+    ///
+    /// ```
+    /// let preprocessed = beheader::preprocess(b"int a;\nint b;\nint c;\nint d;\n").unwrap();
+    /// let snapshot = preprocessed.source_map();
+    /// let b = preprocessed.tokens()[7].span();
+    /// let snippet = snapshot.snippet(b, 1).unwrap();
+    /// assert_eq!(snippet.first_line(), 1);
+    /// assert_eq!(snippet.text(), b"int a;\nint b;\nint c;");
+    /// let lines: Vec<_> = snippet.lines().map(|(number, _)| number).collect();
+    /// assert_eq!(lines, [1, 2, 3]);
+    /// ```
+    pub fn snippet(&self, span: Span, context_lines: usize) -> Option<SourceSnippet<'_>> {
+        let region = self.region(span)?;
+        let contents = &region.contents;
+        let lines = region.lines();
+        let (lo, hi) = (span.lo() - region.span.lo(), span.hi() - region.span.lo());
+        let first = lines.index(lo);
+        // A region ending with a new-line character does not include the line after it.
+        let last = lines.index(hi.saturating_sub(1).max(lo));
+        // Neither does the contents, so the empty line after its last new-line character is
+        // only shown if the region is there.
+        let count = lines.len() - usize::from(contents.ends_with(b"\n"));
+        let first = first.saturating_sub(context_lines);
+        let last = last.saturating_add(context_lines).min(count - 1).max(last);
+        let (start, _) = lines.range(first, contents.len());
+        let (_, end) = lines.range(last, contents.len());
+        Some(SourceSnippet {
+            span: Span::new(region.span.lo() + start, region.span.lo() + end),
+            first_line: first + 1,
+            text: &contents[start..end],
+        })
+    }
+
+    /// Find the file, lines and columns of `span`, which must be inside a single stored region,
+    /// counting columns in bytes. Return `None` otherwise.
+    pub fn resolve(&self, span: Span) -> Option<SpanLocation> {
+        self.resolve_with(span, ColumnUnit::Bytes)
+    }
+
+    /// Find the file, lines and columns of `span` like [`resolve`](Self::resolve), counting
+    /// columns in `unit`.
+    pub fn resolve_with(&self, span: Span, unit: ColumnUnit) -> Option<SpanLocation> {
+        let region = self.region(span)?;
+        Some(SpanLocation::new(
+            region.path.clone(),
+            &region.contents,
+            region.lines(),
+            (span.lo() - region.span.lo(), span.hi() - region.span.lo()),
+            unit,
+        ))
+    }
+
+    /// Write the snapshot to `writer` in a binary format that can be read using
+    /// [`read_from`](Self::read_from), so the locations of the [`Span`]s of a result can be found
+    /// in other processes. The contents, paths and lines of every region are written. Paths that
+    /// are not valid Unicode are written with their invalid sequences replaced.
+    ///
+    /// ```
+    /// use beheader::SourceMapSnapshot;
+    ///
+    /// let preprocessed = beheader::preprocess(b"int a;\nint b;\n").unwrap();
+    /// let snapshot = preprocessed.source_map();
+    /// let mut bytes = Vec::new();
+    /// snapshot.write_to(&mut bytes).unwrap();
+    ///
+    /// let loaded = SourceMapSnapshot::read_from(&bytes[..]).unwrap();
+    /// assert_eq!(loaded, snapshot);
+    /// let b = preprocessed.tokens()[7].span();
+    /// assert_eq!(loaded.spelling(b), Some(&b"b"[..]));
+    /// assert_eq!(loaded.resolve(b).unwrap().start_line(), 2);
+    /// ```
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_u32(&mut writer, self.regions.len())?;
+        for region in &self.regions {
+            write_u32(&mut writer, region.span.lo())?;
+            write_u32(&mut writer, region.span.hi())?;
+            match &region.path {
+                Some(path) => {
+                    let path = path.to_string_lossy();
+                    writer.write_all(&[1])?;
+                    write_u32(&mut writer, path.len())?;
+                    writer.write_all(path.as_bytes())?;
+                }
+                None => writer.write_all(&[0])?,
+            }
+            writer.write_all(&region.contents)?;
+            let starts = region.lines().starts();
+            write_u32(&mut writer, starts.len())?;
+            for &start in starts {
+                write_u32(&mut writer, start)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a snapshot written using [`write_to`](Self::write_to) from `reader`. The lines of
+    /// the regions are read instead of being found again. Return an error if `reader` cannot be
+    /// read or if its contents are not a snapshot.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != *MAGIC {
+            return Err(invalid_snapshot());
+        }
+        let count = read_u32(&mut reader)?;
+        let mut regions: Vec<StoredRegion> = Vec::new();
+        for _ in 0..count {
+            let (lo, hi) = (read_u32(&mut reader)?, read_u32(&mut reader)?);
+            if hi < lo {
+                return Err(invalid_snapshot());
+            }
+            let mut flag = [0];
+            reader.read_exact(&mut flag)?;
+            let path = match flag {
+                [0] => None,
+                [1] => {
+                    let len = read_u32(&mut reader)?;
+                    let path = read_bytes(&mut reader, len)?;
+                    let path = String::from_utf8(path).map_err(|_| invalid_snapshot())?;
+                    Some(PathBuf::from(path))
+                }
+                _ => return Err(invalid_snapshot()),
+            };
+            let contents = read_bytes(&mut reader, hi - lo)?;
+            let len = read_u32(&mut reader)?;
+            let starts = (0..len)
+                .map(|_| read_u32(&mut reader))
+                .collect::<io::Result<_>>()?;
+            let lines = LineTable::from_starts(&contents, starts).ok_or_else(invalid_snapshot)?;
+            let region = StoredRegion::new(Span::new(lo, hi), path, contents.into())?;
+            // The region was just created, so its lines are not set yet.
+            let _ = region.lines.set(lines);
+            regions.push(region);
+        }
+        Self::new(regions)
+    }
+}
+
+/// The bytes at the start of the binary format of snapshots, including its version.
+const MAGIC: &[u8; 8] = b"BHSMAP01";
+
+/// Write `value`, which must fit in 32 bits as offsets do, in little-endian order.
+fn write_u32<W: Write>(writer: &mut W, value: usize) -> io::Result<()> {
+    let value = u32::try_from(value).map_err(|_| invalid_snapshot())?;
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Read a value written using [`write_u32`].
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes) as usize)
+}
+
+/// Read `len` bytes, without trusting `len` to allocate them in advance.
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+/// The error for contents that are not a snapshot.
+fn invalid_snapshot() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid source map snapshot")
+}
+
+/// A sequence of bytes stored in a [`SourceMapSnapshot`], like the contents of a file. Each region
+/// has its own allocation, which is shared with the preprocessor and the other snapshots instead
+/// of being copied.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawRegion")
+)]
+pub struct StoredRegion {
+    pub(super) span: Span,
+    pub(super) path: Option<PathBuf>,
+    pub(super) contents: Arc<[u8]>,
+    /// The lines of the contents, which are found the first time they are needed.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(super) lines: OnceCell<LineTable>,
+}
+
+impl PartialEq for StoredRegion {
+    fn eq(&self, other: &Self) -> bool {
+        // The lines only depend on the contents.
+        (self.span, &self.path, &self.contents) == (other.span, &other.path, &other.contents)
+    }
+}
+
+impl Eq for StoredRegion {}
+
+/// The fields of a [`StoredRegion`] as they are deserialized, before they are checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawRegion {
+    span: Span,
+    path: Option<PathBuf>,
+    contents: Arc<[u8]>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawRegion> for StoredRegion {
+    type Error = io::Error;
+
+    fn try_from(raw: RawRegion) -> io::Result<Self> {
+        Self::new(raw.span, raw.path, raw.contents)
+    }
+}
+
+impl StoredRegion {
+    /// Create a region at `span` with `contents`. Return an error if the length of `span` is not
+    /// the length of `contents`.
+    fn new(span: Span, path: Option<PathBuf>, contents: Arc<[u8]>) -> io::Result<Self> {
+        if span.lo() > span.hi() || span.hi() - span.lo() != contents.len() {
+            return Err(invalid_snapshot());
+        }
+        Ok(Self {
+            span,
+            path,
+            contents,
+            lines: OnceCell::new(),
+        })
+    }
+
+    /// The lines of the contents.
+    fn lines(&self) -> &LineTable {
+        self.lines.get_or_init(|| LineTable::new(&self.contents))
+    }
+
+    /// The position of the region.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The path of the file whose contents were stored, if the region is a file. Regions that
+    /// are not files are produced by the preprocessor, for example by the `#` operator.
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// The bytes stored.
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+}
+
+/// Some whole lines of a [`StoredRegion`], obtained using [`SourceMapSnapshot::snippet`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceSnippet<'a> {
+    span: Span,
+    first_line: usize,
+    text: &'a [u8],
+}
+
+impl<'a> SourceSnippet<'a> {
+    /// The position of the lines, without the line terminator of the last one.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The number of the first line inside the stored region, starting from 1.
+    pub fn first_line(&self) -> usize {
+        self.first_line
+    }
+
+    /// The contents of the lines, without the line terminator of the last one.
+    pub fn text(&self) -> &'a [u8] {
+        self.text
+    }
+
+    /// The number and contents of each line, without their line terminators.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &'a [u8])> {
+        let first_line = self.first_line;
+        self.text
+            .split(|&byte| byte == b'\n')
+            .enumerate()
+            .map(move |(index, line)| {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                (first_line + index, line)
+            })
+    }
+}
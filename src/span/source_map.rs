@@ -1,76 +1,742 @@
 use std::{
-    cell::{Ref, RefCell, RefMut},
-    collections::{hash_map::Entry, HashMap},
-    fs::File,
+    borrow::Cow,
+    cell::{OnceCell, Ref, RefCell},
+    collections::{HashMap, HashSet},
     io::{self, Read},
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use crate::span::Span;
+use super::{
+    ColumnUnit, LineTable, SourceMapSnapshot, Span, SpanLocation, StoredRegion, MAX_OFFSET,
+};
+use crate::{
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    fs::{default_file_system, normalize, FileMetadata, FileSystem, PrefixMap},
+    standard::LexerFeatures,
+    symbol::{Symbol, SymbolTable},
+    HeaderCache, InputCharset,
+};
 
 /// Keeps track of all the source code being preprocessed. This not only includes files and text
 /// provided by the user but also any source files included when processing `#include` directives.
-#[derive(Default)]
 pub(crate) struct SourceMap {
     inner: RefCell<SourceMapInner>,
+    /// Where files are read from.
+    file_system: Arc<dyn FileSystem>,
+    /// Whether symbolic links are resolved when comparing the paths of files.
+    resolve_symlinks: bool,
+    /// The cache where the contents of the files read are shared with other [`SourceMap`]s.
+    header_cache: Option<HeaderCache>,
+    /// How the columns of locations are counted.
+    column_unit: ColumnUnit,
+    /// The encoding of the files read, which are converted to UTF-8.
+    input_charset: InputCharset,
+    /// The replacements of the prefixes of the paths shown to users.
+    prefix_map: PrefixMap,
+    /// The features of the lexer used to tokenize the contents.
+    lexer_features: LexerFeatures,
+    /// The symbols of the spellings of the identifiers.
+    symbols: RefCell<SymbolTable>,
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        Self::new(
+            default_file_system(),
+            false,
+            None,
+            ColumnUnit::Bytes,
+            InputCharset::Utf8,
+            PrefixMap::default(),
+            LexerFeatures::default(),
+        )
+    }
 }
 
 #[derive(Default)]
 struct SourceMapInner {
-    buffer: Vec<u8>,
+    /// The contents of the regions stored so far, in the order they were stored. Each region
+    /// starts where the previous one ends and has its own allocation, so storing a region never
+    /// moves the contents of the others.
+    chunks: Vec<Chunk>,
+    /// The end of the last region.
+    len: usize,
+    /// The region of each path, which is the last one stored for that path.
     map: HashMap<PathBuf, Span>,
+    /// The regions stored as files and their paths, sorted by their starts as they are stored
+    /// after the previous ones.
+    files: Vec<(Span, PathBuf)>,
+    /// The paths of the files read so far, in the order they were read.
+    read: Vec<PathBuf>,
+    /// The paths of the files read by the [`SourceMap`] this one was forked from, which are added
+    /// to [`read`](Self::read) the first time they are needed.
+    inherited: HashSet<PathBuf>,
+    /// The metadata of the files read, for the files whose metadata could be obtained before
+    /// reading them.
+    metadata: HashMap<PathBuf, FileMetadata>,
+    /// The lines given a new number by `#line` directives and line markers, sorted by their
+    /// starts.
+    presumed: Vec<PresumedLine>,
+}
+
+/// A line given a new number by a `#line` directive or a line marker (section 6.10.4 of C17),
+/// which also numbers the lines after it in the same file.
+#[derive(Clone, Debug)]
+pub(crate) struct PresumedLine {
+    /// The region of the file the line is in.
+    file: Span,
+    /// The start of the line, right after the new-line character of the directive.
+    at: usize,
+    /// The number given to the line.
+    pub(crate) line: usize,
+    /// The name given to the file from the line on, which is the previous one if the directive
+    /// did not give any.
+    pub(crate) path: PathBuf,
+    /// The flags of a line marker, or `None` for a `#line` directive.
+    pub(crate) flags: Option<LineMarkerFlags>,
+}
+
+/// The flags after the file name of a line marker, in the format written by GCC.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct LineMarkerFlags {
+    /// Flag 1: a file is entered, as if it was included.
+    pub(crate) enter: bool,
+    /// Flag 2: the file that included the current one is returned to.
+    pub(crate) leave: bool,
+    /// Flag 3: the lines come from a system header.
+    pub(crate) system: bool,
+}
+
+/// A region stored in a [`SourceMap`], whose contents can be shared with other [`SourceMap`]s.
+#[derive(Clone)]
+struct Chunk {
+    lo: usize,
+    bytes: Arc<[u8]>,
+    /// The lines of the region, which are found the first time a location inside it is needed.
+    lines: OnceCell<LineTable>,
+}
+
+impl Chunk {
+    /// The lines of the region.
+    fn lines(&self) -> &LineTable {
+        self.lines.get_or_init(|| LineTable::new(&self.bytes))
+    }
+}
+
+/// The message of the error for source code that cannot be stored.
+const TOO_LARGE: &str = "the source code is larger than the 4 GiB that can be stored";
+
+/// The error for source code that cannot be stored because its end would be larger than
+/// [`MAX_OFFSET`].
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::OutOfMemory, TOO_LARGE)
+}
+
+impl SourceMapInner {
+    /// Store `bytes` after the last region and return the [`Span`] for it. Return `None` if the
+    /// end of the region would be larger than [`MAX_OFFSET`], which is not stored.
+    fn push(&mut self, bytes: Arc<[u8]>) -> Option<Span> {
+        let lo = self.len;
+        let hi = lo.checked_add(bytes.len()).filter(|&hi| hi <= MAX_OFFSET)?;
+        self.len = hi;
+        if !bytes.is_empty() {
+            self.chunks.push(Chunk {
+                lo,
+                bytes,
+                lines: OnceCell::new(),
+            });
+        }
+        Some(Span::new(lo, hi))
+    }
+
+    /// Get the chunk containing the start of `span`, if any.
+    fn chunk(&self, span: Span) -> Option<&Chunk> {
+        let index = self.chunks.partition_point(|chunk| chunk.lo <= span.lo());
+        self.chunks[..index].last()
+    }
+
+    /// Store that the region `span` has the contents of the file at `path`.
+    fn insert_file(&mut self, path: PathBuf, span: Span) {
+        self.map.insert(path.clone(), span);
+        self.files.push((span, path));
+    }
+
+    /// Get the index in [`files`](Self::files) of the file containing `span`, if any.
+    fn file_index(&self, span: Span) -> Option<usize> {
+        let end = self
+            .files
+            .partition_point(|(file, _)| file.lo() <= span.lo());
+        // Regions of files do not overlap, but the region of an empty file is also at the start
+        // of the region stored after it, so a file whose region is exactly `span` is preferred.
+        let mut found = None;
+        for (index, (file, _)) in self.files[..end].iter().enumerate().rev() {
+            if *file == span {
+                return Some(index);
+            }
+            if found.is_none() && file.contains(span) {
+                found = Some(index);
+            }
+            if file.lo() < span.lo() {
+                break;
+            }
+        }
+        found
+    }
+
+    /// Get the region of the file containing `span`, if any.
+    fn file_region(&self, span: Span) -> Option<Span> {
+        self.file_index(span).map(|index| self.files[index].0)
+    }
+
+    /// Get the line and column, counted in `unit`, of the byte at `offset`, which must be inside
+    /// `file`, a region returned by one of the methods that store bytes.
+    fn position(&self, file: Span, offset: usize, unit: ColumnUnit) -> (usize, usize) {
+        match self.chunk(file) {
+            // Empty files are not stored in any chunk.
+            Some(chunk) if file.lo() < file.hi() => {
+                chunk
+                    .lines()
+                    .position(&chunk.bytes, offset - chunk.lo, unit)
+            }
+            _ => (1, 1),
+        }
+    }
+
+    /// Get the contents of a region. The region must be inside a single stored region.
+    fn bytes(&self, span: Span) -> &[u8] {
+        match self.chunk(span) {
+            Some(chunk) => &chunk.bytes[span.lo() - chunk.lo..span.hi() - chunk.lo],
+            None => &[],
+        }
+    }
 }
 
 impl SourceMap {
-    /// Get the string representation of a region.
+    /// Create an empty [`SourceMap`] that reads files from `file_system`. If `resolve_symlinks`
+    /// is `true`, files are identified by their canonical paths. If there is a `header_cache`,
+    /// the contents of the files read are shared through it. Columns are counted in `column_unit`.
+    /// The files read are converted to UTF-8 from `input_charset`. The paths shown to users are
+    /// changed using `prefix_map`. Contents are tokenized using `lexer_features`.
+    pub(crate) fn new(
+        file_system: Arc<dyn FileSystem>,
+        resolve_symlinks: bool,
+        header_cache: Option<HeaderCache>,
+        column_unit: ColumnUnit,
+        input_charset: InputCharset,
+        prefix_map: PrefixMap,
+        lexer_features: LexerFeatures,
+    ) -> Self {
+        Self {
+            inner: RefCell::default(),
+            file_system,
+            resolve_symlinks,
+            header_cache,
+            column_unit,
+            input_charset,
+            prefix_map,
+            lexer_features,
+            symbols: RefCell::default(),
+        }
+    }
+
+    /// Get the [`Symbol`] of `spelling`, interning it if needed.
+    pub(crate) fn intern(&self, spelling: &[u8]) -> Symbol {
+        self.symbols.borrow_mut().intern(spelling)
+    }
+
+    /// Get the [`Symbol`] of `spelling` if it was interned already.
+    pub(crate) fn find_symbol(&self, spelling: &[u8]) -> Option<Symbol> {
+        self.symbols.borrow().get(spelling)
+    }
+
+    /// Get the path shown to users for the file at `path`, whose prefix may be replaced.
+    pub(crate) fn display_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        self.prefix_map.apply(path)
+    }
+
+    /// Get the features of the lexer used to tokenize the contents.
+    pub(crate) fn lexer_features(&self) -> LexerFeatures {
+        self.lexer_features
+    }
+
+    /// Get how the columns of locations are counted.
+    pub(crate) fn column_unit(&self) -> ColumnUnit {
+        self.column_unit
+    }
+
+    /// Get the [`HeaderCache`] the contents of the files read are shared through, if any.
+    pub(crate) fn header_cache(&self) -> Option<&HeaderCache> {
+        self.header_cache.as_ref()
+    }
+
+    /// Get the [`FileSystem`] files are read from.
+    pub(crate) fn file_system(&self) -> &dyn FileSystem {
+        &*self.file_system
+    }
+
+    /// Get the string representation of a region. The region must be inside a region returned
+    /// by one of the methods that store bytes.
     ///
     /// As the value returned by this method is of type [`Ref`], it must be dropped before doing
     /// any write operation on the [`SourceMap`].
     pub(crate) fn get_bytes(&self, span: Span) -> Ref<'_, [u8]> {
-        Ref::map(self.inner.borrow(), |inner| &inner.buffer[span.lo..span.hi])
+        Ref::map(self.inner.borrow(), |inner| inner.bytes(span))
+    }
+
+    /// Get the shared contents of the region `span` if it is exactly a region returned by one of
+    /// the methods that store bytes.
+    pub(crate) fn get_contents(&self, span: Span) -> Option<Arc<[u8]>> {
+        let inner = self.inner.borrow();
+        let chunk = inner.chunk(span)?;
+        (chunk.lo == span.lo() && chunk.bytes.len() == span.hi() - span.lo())
+            .then(|| chunk.bytes.clone())
+    }
+
+    /// Get the region returned by one of the methods that store bytes that contains `span`
+    /// together with its shared contents, if there is one.
+    pub(crate) fn get_region(&self, span: Span) -> Option<(Span, Arc<[u8]>)> {
+        let inner = self.inner.borrow();
+        let chunk = inner.chunk(span)?;
+        let region = Span::new(chunk.lo, chunk.lo + chunk.bytes.len());
+        region.contains(span).then(|| (region, chunk.bytes.clone()))
     }
 
     /// Read a file, store its contents in the [`SourceMap`] and return the [`Span`] for the
     /// contents of the file.
     ///
-    /// If the path of the file has already been seen by this method, the file is not read again.
+    /// The path is normalized first, resolving symbolic links if required. If the same path has
+    /// already been seen by this method, the file is not read again.
     pub(crate) fn read_file<P: AsRef<Path>>(&self, path: &P) -> io::Result<Span> {
-        let (mut map, mut buffer) = RefMut::map_split(self.inner.borrow_mut(), |inner| {
-            (&mut inner.map, &mut inner.buffer)
-        });
-        match map.entry(path.as_ref().to_owned()) {
-            Entry::Occupied(entry) => Ok(*entry.get()),
-            Entry::Vacant(entry) => {
-                let lo = buffer.len();
-                let hi = lo + File::open(path)?.read_to_end(&mut buffer)?;
-                let span = Span { lo, hi };
-                entry.insert(span);
-                Ok(span)
+        let path = self.file_path(path.as_ref())?;
+
+        let mut inner = self.inner.borrow_mut();
+        if let Some(&span) = inner.map.get(&path) {
+            if inner.inherited.remove(&path) {
+                inner.read.push(path);
+            }
+            return Ok(span);
+        }
+
+        // The metadata is obtained first so changes made while reading are not missed.
+        let metadata = self.file_system.metadata(&path);
+        let contents = self.read_contents(&path)?;
+        let contents = match &self.header_cache {
+            Some(cache) => cache.intern(contents),
+            None => contents.into(),
+        };
+        let span = inner.push(contents).ok_or_else(too_large)?;
+        inner.insert_file(path.clone(), span);
+        if let Ok(metadata) = metadata {
+            inner.metadata.insert(path.clone(), metadata);
+        }
+        inner.read.push(path);
+        Ok(span)
+    }
+
+    /// Read the file at `path` and convert it to UTF-8.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "read_file",
+            level = "debug",
+            skip_all,
+            fields(path = %path.display())
+        )
+    )]
+    fn read_contents(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let contents = self.file_system.read(path)?;
+        let contents = self
+            .input_charset
+            .decode_source(contents.into(), self.lexer_features)?;
+        Ok(contents.into_owned())
+    }
+
+    /// Check if the file at `path`, which must have been read using
+    /// [`read_file`](Self::read_file), changed since it was read. The file is only read again if
+    /// its metadata is not the same. Files that cannot be read anymore changed.
+    pub(crate) fn is_stale(&self, path: &Path) -> bool {
+        let inner = self.inner.borrow();
+        let span = match inner.map.get(path) {
+            Some(&span) => span,
+            None => return true,
+        };
+        if let (Some(old), Ok(new)) = (inner.metadata.get(path), self.file_system.metadata(path)) {
+            if *old == new {
+                return false;
             }
         }
+        match self.read_contents(path) {
+            Ok(contents) => contents != inner.bytes(span),
+            Err(_) => true,
+        }
+    }
+
+    /// Get the paths of the files read using [`read_file`](Self::read_file), including the ones
+    /// read by the [`SourceMap`] this one was forked from, that changed since they were read.
+    pub(crate) fn stale_files(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = {
+            let inner = self.inner.borrow();
+            let mut paths: Vec<_> = inner.inherited.iter().chain(&inner.read).cloned().collect();
+            paths.sort_by_key(|path| inner.map.get(path).map(|span| span.lo()));
+            paths
+        };
+        paths.retain(|path| self.is_stale(path));
+        paths
+    }
+
+    /// Forget the contents of the file at `path`, which must have been read using
+    /// [`read_file`](Self::read_file), so the next time it is needed it is read again. Its
+    /// contents are also removed from the [`HeaderCache`], if any, and it is not one of the
+    /// [`files_read`](Self::files_read) until it is read again. The regions already stored for it
+    /// keep their contents. Return whether the file was stored.
+    pub(crate) fn invalidate(&self, path: &Path) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let span = match inner.map.remove(path) {
+            Some(span) => span,
+            None => return false,
+        };
+        inner.metadata.remove(path);
+        inner.inherited.remove(path);
+        inner.read.retain(|read| read != path);
+        if let Some(cache) = &self.header_cache {
+            cache.remove(inner.bytes(span));
+        }
+        true
+    }
+
+    /// Get the path that identifies the file at `path` in the [`SourceMap`]. This path is
+    /// normalized and its symbolic links are resolved if required.
+    pub(crate) fn file_path(&self, path: &Path) -> io::Result<PathBuf> {
+        if self.resolve_symlinks {
+            self.file_system.canonicalize(path)
+        } else {
+            Ok(normalize(path))
+        }
+    }
+
+    /// Get the region of the file stored with the path `path`, if any. The path must be the one
+    /// returned by [`file_path`](Self::file_path).
+    pub(crate) fn file_span(&self, path: &Path) -> Option<Span> {
+        self.inner.borrow().map.get(path).copied()
+    }
+
+    /// Get the number of bytes stored so far, including the ones of the [`SourceMap`] this one
+    /// was forked from.
+    pub(crate) fn stored_len(&self) -> usize {
+        self.inner.borrow().len
+    }
+
+    /// Get the paths of the files read using [`read_file`](Self::read_file), in the order they
+    /// were read.
+    pub(crate) fn files_read(&self) -> Vec<PathBuf> {
+        self.inner.borrow().read.clone()
     }
 
     /// Store a sequence of bytes in the [`SourceMap`] and return the [`Span`] for it.
     ///
-    /// The returned [`Span`] is not associated to any file path.
-    pub(crate) fn store_bytes(&self, bytes: &[u8]) -> Span {
-        let buffer = &mut self.inner.borrow_mut().buffer;
+    /// The returned [`Span`] is not associated to any file path. Return an error if the source
+    /// code stored would be larger than [`MAX_OFFSET`] bytes.
+    pub(crate) fn store_bytes(&self, bytes: &[u8]) -> io::Result<Span> {
+        self.inner
+            .borrow_mut()
+            .push(bytes.into())
+            .ok_or_else(too_large)
+    }
 
-        let lo = buffer.len();
-        buffer.extend_from_slice(bytes);
-        let hi = buffer.len();
+    /// Store a sequence of bytes produced while processing the region `at`, like the result of
+    /// an operator, and return the [`Span`] for it. Return a fatal error about `at` if the source
+    /// code stored would be larger than [`MAX_OFFSET`] bytes.
+    pub(crate) fn store_at(&self, bytes: &[u8], at: Span) -> Result<Span, Diagnostic> {
+        self.store_bytes(bytes).map_err(|_| {
+            let code = DiagnosticCode::SourceTooLarge;
+            let message = "The source code is larger than the 4 GiB that can be stored";
+            self.diagnostic(Severity::Error, code, at, message)
+        })
+    }
 
-        Span { lo, hi }
+    /// Store a sequence of bytes in the [`SourceMap`] as if it were the contents of a file named
+    /// `name` and return the [`Span`] for it.
+    ///
+    /// This is used for regions that do not come from an actual file but must be attributable in
+    /// diagnostics, such as `<command-line>`.
+    pub(crate) fn store_virtual_file<P: AsRef<Path>>(
+        &self,
+        name: P,
+        bytes: &[u8],
+    ) -> io::Result<Span> {
+        let span = self.store_bytes(bytes)?;
+        self.inner
+            .borrow_mut()
+            .insert_file(name.as_ref().to_owned(), span);
+        Ok(span)
+    }
+
+    /// Read the contents of `reader` until its end and store them in the [`SourceMap`] as if they
+    /// were the contents of a file named `name`, returning the [`Span`] for them.
+    ///
+    /// The contents are read in chunks into a single buffer, which becomes the stored region
+    /// without copying it unless it is converted to UTF-8. Reading stops with an error as soon as
+    /// the contents do not fit.
+    pub(crate) fn read_from<R: Read, P: AsRef<Path>>(
+        &self,
+        reader: R,
+        name: P,
+    ) -> io::Result<Span> {
+        let available = MAX_OFFSET - self.inner.borrow().len;
+        let mut contents = Vec::new();
+        reader
+            .take(available as u64 + 1)
+            .read_to_end(&mut contents)?;
+        if contents.len() > available {
+            return Err(too_large());
+        }
+        let contents = self
+            .input_charset
+            .decode_source(contents.into(), self.lexer_features)?
+            .into_owned();
+        let mut inner = self.inner.borrow_mut();
+        let span = inner.push(contents.into()).ok_or_else(too_large)?;
+        inner.insert_file(name.as_ref().to_owned(), span);
+        Ok(span)
     }
 
     /// Find the file path to which a [`Span`] belongs. Return `None` if the [`Span`] does not
     /// belong to any file.
     pub(crate) fn find_file(&self, target: Span) -> Option<PathBuf> {
-        for (path, span) in &self.inner.borrow().map {
-            if span.lo <= target.lo && span.hi >= target.hi {
-                return Some(path.clone());
+        let inner = self.inner.borrow();
+        let index = inner.file_index(target)?;
+        Some(inner.files[index].1.clone())
+    }
+
+    /// Find the line number, starting from 1, of the start of a [`Span`] inside the file it
+    /// belongs to. Return `None` if the [`Span`] does not belong to any file.
+    pub(crate) fn find_line(&self, target: Span) -> Option<usize> {
+        let inner = self.inner.borrow();
+        let file = inner.file_region(target)?;
+        Some(inner.position(file, target.lo(), ColumnUnit::Bytes).0)
+    }
+
+    /// Find the name of the file and the number of the line of the start of a [`Span`] given by
+    /// the `#line` directives and line markers before it in its file, which are the ones found by
+    /// [`find_file`](Self::find_file) and [`find_line`](Self::find_line) if there are none. Return
+    /// `None` if the [`Span`] does not belong to any file.
+    pub(crate) fn find_presumed(&self, target: Span) -> Option<(PathBuf, usize)> {
+        let inner = self.inner.borrow();
+        let (file, path) = &inner.files[inner.file_index(target)?];
+        let line = inner.position(*file, target.lo(), ColumnUnit::Bytes).0;
+        let end = inner
+            .presumed
+            .partition_point(|presumed| presumed.at <= target.lo());
+        let presumed = inner.presumed[..end]
+            .iter()
+            .rev()
+            .take_while(|presumed| presumed.at >= file.lo())
+            .find(|presumed| presumed.file == *file);
+        Some(match presumed {
+            Some(presumed) => {
+                let start = inner.position(*file, presumed.at, ColumnUnit::Bytes).0;
+                (presumed.path.clone(), presumed.line + line - start)
+            }
+            None => (path.clone(), line),
+        })
+    }
+
+    /// Give the number `line` to the line after the directive in the region `directive`, which
+    /// must end with a new-line character, and the name `path` to its file from that line on,
+    /// keeping the previous name if it is `None`. `flags` are the flags of a line marker, or
+    /// `None` for a `#line` directive. Nothing changes if the directive is not in a file.
+    pub(crate) fn set_presumed_line(
+        &self,
+        directive: Span,
+        line: usize,
+        path: Option<PathBuf>,
+        flags: Option<LineMarkerFlags>,
+    ) {
+        let (previous, _) = match self.find_presumed(directive) {
+            Some(presumed) => presumed,
+            None => return,
+        };
+        let mut inner = self.inner.borrow_mut();
+        let file = inner.file_region(directive).unwrap();
+        let presumed = PresumedLine {
+            file,
+            at: directive.hi(),
+            line,
+            path: path.unwrap_or(previous),
+            flags,
+        };
+        // The directives of a file included several times are found again.
+        let index = inner
+            .presumed
+            .partition_point(|other| other.at < presumed.at);
+        match inner.presumed.get_mut(index) {
+            Some(other) if other.at == presumed.at => *other = presumed,
+            _ => inner.presumed.insert(index, presumed),
+        }
+    }
+
+    /// Get the line starting at `at` given a new number by a `#line` directive or a line marker,
+    /// if any.
+    pub(crate) fn presumed_line(&self, at: usize) -> Option<PresumedLine> {
+        let inner = self.inner.borrow();
+        let index = inner.presumed.partition_point(|presumed| presumed.at < at);
+        inner
+            .presumed
+            .get(index)
+            .filter(|presumed| presumed.at == at)
+            .cloned()
+    }
+
+    /// Find the column number, starting from 1, of the start of a [`Span`] inside its line,
+    /// counted in the [`ColumnUnit`] of the [`SourceMap`]. Return `None` if the [`Span`] does not
+    /// belong to any file.
+    pub(crate) fn find_column(&self, target: Span) -> Option<usize> {
+        let inner = self.inner.borrow();
+        let file = inner.file_region(target)?;
+        Some(inner.position(file, target.lo(), self.column_unit).1)
+    }
+
+    /// Find the region of the line containing the start of a [`Span`], without its line
+    /// terminator. Unlike [`find_line`](Self::find_line), this works for regions that do not
+    /// belong to any file. Return `None` if the [`Span`] was not stored.
+    pub(crate) fn find_source_line(&self, target: Span) -> Option<Span> {
+        let inner = self.inner.borrow();
+        let chunk = inner.chunk(target)?;
+        let offset = target.lo() - chunk.lo;
+        if offset > chunk.bytes.len() {
+            return None;
+        }
+        let (start, end) = chunk.lines().line(offset, chunk.bytes.len());
+        Some(Span::new(chunk.lo + start, chunk.lo + end))
+    }
+
+    /// Find the file, lines and columns of `target`, which must be inside a single stored region,
+    /// counting columns in the [`ColumnUnit`] of the [`SourceMap`]. Return `None` otherwise.
+    pub(crate) fn resolve(&self, target: Span) -> Option<SpanLocation> {
+        let inner = self.inner.borrow();
+        // Empty files are not stored in any chunk.
+        if let Some(index) = inner.file_index(target) {
+            let (file, path) = &inner.files[index];
+            if file.lo() == file.hi() {
+                let lines = LineTable::new(&[]);
+                let location =
+                    SpanLocation::new(Some(path.clone()), &[], &lines, (0, 0), self.column_unit);
+                return Some(location);
+            }
+        }
+        let chunk = inner.chunk(target)?;
+        let region = Span::new(chunk.lo, chunk.lo + chunk.bytes.len());
+        if target.lo() > target.hi() || target.hi() > region.hi() {
+            return None;
+        }
+        let file = inner
+            .file_index(region)
+            .filter(|&index| inner.files[index].0 == region)
+            .map(|index| inner.files[index].1.clone());
+        Some(SpanLocation::new(
+            file,
+            &chunk.bytes,
+            chunk.lines(),
+            (target.lo() - region.lo(), target.hi() - region.lo()),
+            self.column_unit,
+        ))
+    }
+
+    /// Create a [`SourceMap`] with the same contents, files and symbols as this one, which can be
+    /// changed independently of it. The contents are shared and the files are not read again,
+    /// but they are only part of the [`files_read`](Self::files_read) of the new [`SourceMap`]
+    /// once they are read using it.
+    pub(crate) fn fork(&self) -> Self {
+        let inner = self.inner.borrow();
+        let mut inherited = inner.inherited.clone();
+        inherited.extend(inner.read.iter().cloned());
+        let inner = SourceMapInner {
+            chunks: inner.chunks.clone(),
+            len: inner.len,
+            map: inner.map.clone(),
+            files: inner.files.clone(),
+            read: Vec::new(),
+            inherited,
+            metadata: inner.metadata.clone(),
+            presumed: inner.presumed.clone(),
+        };
+        Self {
+            inner: RefCell::new(inner),
+            file_system: self.file_system.clone(),
+            resolve_symlinks: self.resolve_symlinks,
+            header_cache: self.header_cache.clone(),
+            column_unit: self.column_unit,
+            input_charset: self.input_charset,
+            prefix_map: self.prefix_map.clone(),
+            lexer_features: self.lexer_features,
+            symbols: self.symbols.clone(),
+        }
+    }
+
+    /// Store the files read by `other`, a [`SourceMap`] forked from this one, that this one does
+    /// not have yet, and take the symbols interned by it. The other regions of `other` are not
+    /// stored, so they do not take space here once `other` is dropped. The contents of the files
+    /// are shared and they are stored as files read by this [`SourceMap`].
+    ///
+    /// Return an error if the files cannot be stored.
+    pub(crate) fn keep_files(&mut self, other: &SourceMap) -> io::Result<()> {
+        let other_inner = other.inner.borrow();
+        let inner = self.inner.get_mut();
+        for path in &other_inner.read {
+            let span = match other_inner.map.get(path) {
+                Some(&span) if !inner.map.contains_key(path) => span,
+                _ => continue,
+            };
+            let contents = match other_inner.chunk(span) {
+                Some(chunk) if chunk.lo == span.lo() && span.lo() < span.hi() => {
+                    chunk.bytes.clone()
+                }
+                // Empty files are not stored in any chunk.
+                _ => Arc::from([]),
+            };
+            let span = inner.push(contents).ok_or_else(too_large)?;
+            inner.insert_file(path.clone(), span);
+            if let Some(&metadata) = other_inner.metadata.get(path) {
+                inner.metadata.insert(path.clone(), metadata);
+            }
+            inner.read.push(path.clone());
+        }
+        self.symbols = other.symbols.clone();
+        Ok(())
+    }
+
+    /// Copy the contents of the [`SourceMap`] into a [`SourceMapSnapshot`].
+    pub(crate) fn snapshot(&self) -> SourceMapSnapshot {
+        let inner = self.inner.borrow();
+        let mut regions: Vec<_> = inner
+            .chunks
+            .iter()
+            .map(|chunk| StoredRegion {
+                span: Span::new(chunk.lo, chunk.lo + chunk.bytes.len()),
+                path: None,
+                contents: chunk.bytes.clone(),
+                // The lines already found are shared with the snapshot.
+                lines: chunk.lines.clone(),
+            })
+            .collect();
+        let mut empty = Vec::new();
+        for &(span, ref path) in &inner.files {
+            match regions.binary_search_by_key(&span.lo(), |region| region.span.lo()) {
+                Ok(index) if regions[index].span == span => {
+                    regions[index].path = Some(path.clone());
+                }
+                // Empty files are not stored in any chunk.
+                _ => empty.push(StoredRegion {
+                    span,
+                    path: Some(path.clone()),
+                    contents: Arc::from([]),
+                    lines: OnceCell::new(),
+                }),
             }
         }
-        None
+        regions.extend(empty);
+        regions.sort_by_key(|region| (region.span.lo(), region.span.hi()));
+        SourceMapSnapshot { regions }
     }
 }
@@ -0,0 +1,163 @@
+//! The versions of the C standard.
+
+/// A version of the ISO C standard, or its GNU dialect, which changes the predefined macros, the
+/// directives and operators that are available and how some tokens are lexed.
+///
+/// The trigraph sequences of the source code, like `??=`, are replaced by the characters they
+/// stand for in ISO C until C23, and never in the GNU dialects. They are replaced when the source
+/// code is read, so the [`Span`](crate::Span)s and columns of the result refer to the contents
+/// after replacing them.
+///
+/// Standards are ordered by version, and each GNU dialect comes right after the version it
+/// extends, so `standard >= Standard::C23` holds for both C23 and its GNU dialect.
+///
+/// ```
+/// use beheader::{Emitter, Preprocessor, Standard};
+///
+/// let source = b"#ifdef A\n#elifdef __STDC__\nstdc\n#endif\n__STDC_VERSION__\n";
+/// let mut preprocessor = Preprocessor::new();
+/// preprocessor.standard(Standard::from_name("gnu99").unwrap());
+/// let preprocessed = preprocessor.preprocess(source).unwrap();
+/// let text = Emitter::new().line_markers(false).render(&preprocessed);
+/// assert_eq!(text, b"stdc\n\n199901L\n");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Standard {
+    /// ISO/IEC 9899:1990, also known as C89 or C90.
+    C89,
+    /// C89 with GNU extensions.
+    Gnu89,
+    /// ISO/IEC 9899:1999, also known as C99.
+    C99,
+    /// C99 with GNU extensions.
+    Gnu99,
+    /// ISO/IEC 9899:2011, also known as C11.
+    C11,
+    /// C11 with GNU extensions.
+    Gnu11,
+    /// ISO/IEC 9899:2018, also known as C17.
+    #[default]
+    C17,
+    /// C17 with GNU extensions.
+    Gnu17,
+    /// ISO/IEC 9899:2024, also known as C23.
+    C23,
+    /// C23 with GNU extensions.
+    Gnu23,
+}
+
+impl Standard {
+    /// Get the standard named `name`, as used by the `-std` option of GCC, like `c99`, `gnu11`
+    /// or `iso9899:2018`. The names used before a standard was published, like `c2x`, are
+    /// accepted too.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "c89" | "c90" | "iso9899:1990" => Some(Self::C89),
+            "gnu89" | "gnu90" => Some(Self::Gnu89),
+            "c99" | "c9x" | "iso9899:1999" | "iso9899:199x" => Some(Self::C99),
+            "gnu99" | "gnu9x" => Some(Self::Gnu99),
+            "c11" | "c1x" | "iso9899:2011" => Some(Self::C11),
+            "gnu11" | "gnu1x" => Some(Self::Gnu11),
+            "c17" | "c18" | "iso9899:2017" | "iso9899:2018" => Some(Self::C17),
+            "gnu17" | "gnu18" => Some(Self::Gnu17),
+            "c23" | "c2x" | "iso9899:2024" => Some(Self::C23),
+            "gnu23" | "gnu2x" => Some(Self::Gnu23),
+            _ => None,
+        }
+    }
+
+    /// The value of the `__STDC_VERSION__` macro, which is not defined by C89.
+    pub fn version(self) -> Option<&'static str> {
+        match self.iso() {
+            Self::C89 => None,
+            Self::C99 => Some("199901L"),
+            Self::C11 => Some("201112L"),
+            Self::C17 => Some("201710L"),
+            _ => Some("202311L"),
+        }
+    }
+
+    /// Check if this is the GNU dialect of a version of the standard.
+    pub fn is_gnu(self) -> bool {
+        matches!(
+            self,
+            Self::Gnu89 | Self::Gnu99 | Self::Gnu11 | Self::Gnu17 | Self::Gnu23
+        )
+    }
+
+    /// The version of the standard without GNU extensions.
+    pub fn iso(self) -> Self {
+        match self {
+            Self::Gnu89 => Self::C89,
+            Self::Gnu99 => Self::C99,
+            Self::Gnu11 => Self::C11,
+            Self::Gnu17 => Self::C17,
+            Self::Gnu23 => Self::C23,
+            iso => iso,
+        }
+    }
+
+    /// Check if the `#elifdef` and `#elifndef` directives are available, which were added by
+    /// C23 and are accepted by every GNU dialect.
+    pub(crate) fn has_elifdef(self) -> bool {
+        self >= Self::C23 || self.is_gnu()
+    }
+
+    /// Check if the `__has_include` operator is available, which was added by C23 and is
+    /// accepted by every GNU dialect.
+    pub(crate) fn has_has_include(self) -> bool {
+        self >= Self::C23 || self.is_gnu()
+    }
+
+    /// Check if the `_Pragma` operator is available, which was added by C99 and is accepted by
+    /// every GNU dialect.
+    pub(crate) fn has_pragma_operator(self) -> bool {
+        self >= Self::C99 || self.is_gnu()
+    }
+
+    /// The features of the lexer that depend on the standard.
+    pub(crate) fn lexer_features(self) -> LexerFeatures {
+        LexerFeatures {
+            digit_separators: self >= Self::C23,
+            ucns: self >= Self::C99,
+            dollar_idents: false,
+            trigraphs: !self.is_gnu() && self < Self::C23,
+        }
+    }
+}
+
+/// The parts of the lexical grammar that depend on the [`Standard`] and the enabled
+/// [`Extension`](crate::Extension)s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct LexerFeatures {
+    /// Whether digits of a `pp-number` can be separated by `'` (section 6.4.8 of C23).
+    pub(crate) digit_separators: bool,
+    /// Whether universal character names can be part of identifiers and `pp-number`s, which was
+    /// added by C99 (section 6.4.3 of C17).
+    pub(crate) ucns: bool,
+    /// Whether `$` can be part of identifiers like a letter.
+    pub(crate) dollar_idents: bool,
+    /// Whether trigraph sequences are replaced before tokenizing, which ISO C requires until C23
+    /// (section 5.2.1.1 of C17) and GNU C does not.
+    pub(crate) trigraphs: bool,
+}
+
+impl LexerFeatures {
+    /// The number of different combinations of features.
+    pub(crate) const COUNT: usize = 16;
+
+    /// A different index below [`COUNT`](Self::COUNT) for every combination of features.
+    pub(crate) fn index(self) -> usize {
+        usize::from(self.trigraphs) * 8
+            + usize::from(self.digit_separators) * 4
+            + usize::from(self.ucns) * 2
+            + usize::from(self.dollar_idents)
+    }
+}
+
+impl Default for LexerFeatures {
+    fn default() -> Self {
+        Standard::default().lexer_features()
+    }
+}
@@ -0,0 +1,89 @@
+//! The interned spellings of identifiers, so they can be compared without comparing their bytes.
+use std::{collections::HashMap, num::NonZeroU32};
+
+use crate::{
+    lexer::{Token, TokenKind},
+    span::SourceMap,
+};
+
+/// The spelling of an identifier interned in a [`SymbolTable`]. Two identifiers have the same
+/// symbol if and only if they are spelled the same, as long as the symbols come from the same
+/// table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct Symbol(NonZeroU32);
+
+impl Symbol {
+    /// The symbol of `defined`, which is interned before any other identifier.
+    pub(crate) const DEFINED: Self = Self(NonZeroU32::MIN);
+    /// The symbol of `__COUNTER__`, which is interned right after `defined`.
+    pub(crate) const COUNTER: Self = match NonZeroU32::new(2) {
+        Some(index) => Self(index),
+        None => unreachable!(),
+    };
+    /// The symbol of `__FILE__`, which is interned right after `__COUNTER__`.
+    pub(crate) const FILE: Self = match NonZeroU32::new(3) {
+        Some(index) => Self(index),
+        None => unreachable!(),
+    };
+    /// The symbol of `__LINE__`, which is interned right after `__FILE__`.
+    pub(crate) const LINE: Self = match NonZeroU32::new(4) {
+        Some(index) => Self(index),
+        None => unreachable!(),
+    };
+}
+
+/// A table that gives a [`Symbol`] to every spelling interned in it.
+#[derive(Clone)]
+pub(crate) struct SymbolTable {
+    symbols: HashMap<Box<[u8]>, Symbol>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        let mut table = Self {
+            symbols: HashMap::new(),
+        };
+        table.intern(b"defined");
+        table.intern(b"__COUNTER__");
+        table.intern(b"__FILE__");
+        table.intern(b"__LINE__");
+        table
+    }
+}
+
+impl SymbolTable {
+    /// Get the symbol of `spelling`, adding it to the table if it is not there yet.
+    pub(crate) fn intern(&mut self, spelling: &[u8]) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(spelling) {
+            return symbol;
+        }
+        let index = u32::try_from(self.symbols.len() + 1).expect("too many identifiers");
+        let symbol = Symbol(NonZeroU32::new(index).unwrap());
+        self.symbols.insert(spelling.into(), symbol);
+        symbol
+    }
+
+    /// Get the symbol of `spelling` if it was interned already.
+    pub(crate) fn get(&self, spelling: &[u8]) -> Option<Symbol> {
+        self.symbols.get(spelling).copied()
+    }
+}
+
+impl SourceMap {
+    /// Get the symbol of the spelling of `token`, which must be an identifier. The symbol given
+    /// to the identifiers when they were tokenized is used if they have one.
+    pub(crate) fn symbol(&self, token: Token) -> Symbol {
+        debug_assert_eq!(token.kind, TokenKind::Ident);
+        match token.symbol {
+            Some(symbol) => symbol,
+            None => self.intern(&self.get_bytes(token.span)),
+        }
+    }
+
+    /// Give the symbol of its spelling to `token` if it is an identifier.
+    pub(crate) fn intern_token(&self, token: &mut Token) {
+        if token.kind == TokenKind::Ident && token.symbol.is_none() {
+            token.symbol = Some(self.intern(&self.get_bytes(token.span)));
+        }
+    }
+}
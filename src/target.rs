@@ -0,0 +1,168 @@
+//! Predefined macros describing compilation targets.
+
+/// A compilation target, described by the macros that are predefined when preprocessing for it.
+///
+/// The predefined profiles follow the macros defined by Clang for the corresponding target
+/// triple, including the sizes of the basic types and the byte order.
+///
+/// ```
+/// let mut target = beheader::Target::x86_64_linux_gnu();
+/// target.define("__GLIBC__", "2").undefine("__ELF__");
+///
+/// let mut preprocessor = beheader::Preprocessor::new();
+/// preprocessor.target(target);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Target {
+    /// The predefined macros and their replacement lists, in definition order.
+    macros: Vec<(String, String)>,
+}
+
+impl Target {
+    /// Create a target without any predefined macros.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the predefined profile for a target triple, if there is one.
+    ///
+    /// The supported triples are `x86_64-linux-gnu`, `aarch64-apple-darwin` and
+    /// `x86_64-windows-msvc`. The vendor component of the triple is optional.
+    pub fn from_triple(triple: &str) -> Option<Self> {
+        // The vendor is ignored.
+        let mut components = triple
+            .split('-')
+            .filter(|component| !matches!(*component, "pc" | "apple" | "unknown"));
+        let arch = components.next()?;
+        let os = components.next()?;
+        let env = components.next().unwrap_or_default();
+
+        match (arch, os, env) {
+            ("x86_64", "linux", "gnu") => Some(Self::x86_64_linux_gnu()),
+            ("aarch64" | "arm64", "darwin" | "macos", "") => Some(Self::aarch64_apple_darwin()),
+            ("x86_64", "windows", "msvc") => Some(Self::x86_64_windows_msvc()),
+            _ => None,
+        }
+    }
+
+    /// The `x86_64-linux-gnu` target.
+    pub fn x86_64_linux_gnu() -> Self {
+        let mut target = Self::new();
+        target
+            .define_all(&[
+                ("__x86_64__", "1"),
+                ("__x86_64", "1"),
+                ("__amd64__", "1"),
+                ("__amd64", "1"),
+                ("__linux__", "1"),
+                ("__linux", "1"),
+                ("__gnu_linux__", "1"),
+                ("__unix__", "1"),
+                ("__unix", "1"),
+                ("__ELF__", "1"),
+                ("__LP64__", "1"),
+                ("_LP64", "1"),
+            ])
+            .define_sizes(8, 16, 4)
+            .define_byte_order("__ORDER_LITTLE_ENDIAN__");
+        target
+    }
+
+    /// The `aarch64-apple-darwin` target.
+    pub fn aarch64_apple_darwin() -> Self {
+        let mut target = Self::new();
+        target
+            .define_all(&[
+                ("__aarch64__", "1"),
+                ("__arm64__", "1"),
+                ("__arm64", "1"),
+                ("__APPLE__", "1"),
+                ("__MACH__", "1"),
+                ("__LP64__", "1"),
+                ("_LP64", "1"),
+            ])
+            .define_sizes(8, 8, 4)
+            .define_byte_order("__ORDER_LITTLE_ENDIAN__");
+        target
+    }
+
+    /// The `x86_64-windows-msvc` target.
+    pub fn x86_64_windows_msvc() -> Self {
+        let mut target = Self::new();
+        target
+            .define_all(&[
+                ("__x86_64__", "1"),
+                ("__x86_64", "1"),
+                ("__amd64__", "1"),
+                ("__amd64", "1"),
+                ("_WIN32", "1"),
+                ("_WIN64", "1"),
+                ("_M_X64", "100"),
+                ("_M_AMD64", "100"),
+                ("_MSC_VER", "1930"),
+            ])
+            .define_sizes(4, 8, 2)
+            .define_byte_order("__ORDER_LITTLE_ENDIAN__");
+        target
+    }
+
+    /// Define a macro for this target, replacing any previous definition with the same name.
+    pub fn define(&mut self, name: &str, value: &str) -> &mut Self {
+        match self.macros.iter_mut().find(|(other, _)| other == name) {
+            Some((_, old)) => *old = value.to_owned(),
+            None => self.macros.push((name.to_owned(), value.to_owned())),
+        }
+        self
+    }
+
+    /// Remove the definition of a macro for this target.
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.macros.retain(|(other, _)| other != name);
+        self
+    }
+
+    /// Return an iterator over the names and replacement lists of the macros predefined for this
+    /// target.
+    pub fn macros(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.macros
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    fn define_all(&mut self, macros: &[(&str, &str)]) -> &mut Self {
+        for (name, value) in macros {
+            self.define(name, value);
+        }
+        self
+    }
+
+    /// Define the `__SIZEOF_*__` macros. Only the size of `long`, `long double` and `wchar_t`
+    /// change between the supported targets.
+    fn define_sizes(&mut self, long: usize, long_double: usize, wchar: usize) -> &mut Self {
+        self.define_all(&[
+            ("__CHAR_BIT__", "8"),
+            ("__SIZEOF_SHORT__", "2"),
+            ("__SIZEOF_INT__", "4"),
+            ("__SIZEOF_LONG__", &long.to_string()),
+            ("__SIZEOF_LONG_LONG__", "8"),
+            ("__SIZEOF_POINTER__", "8"),
+            ("__SIZEOF_SIZE_T__", "8"),
+            ("__SIZEOF_PTRDIFF_T__", "8"),
+            ("__SIZEOF_FLOAT__", "4"),
+            ("__SIZEOF_DOUBLE__", "8"),
+            ("__SIZEOF_LONG_DOUBLE__", &long_double.to_string()),
+            ("__SIZEOF_WCHAR_T__", &wchar.to_string()),
+        ])
+    }
+
+    /// Define the byte order macros, `order` must be the name of one of the `__ORDER_*__`
+    /// macros.
+    fn define_byte_order(&mut self, order: &str) -> &mut Self {
+        self.define_all(&[
+            ("__ORDER_LITTLE_ENDIAN__", "1234"),
+            ("__ORDER_BIG_ENDIAN__", "4321"),
+            ("__ORDER_PDP_ENDIAN__", "3412"),
+            ("__BYTE_ORDER__", order),
+        ])
+    }
+}
@@ -0,0 +1,9 @@
+//! The clock used to measure how long preprocessing takes.
+//!
+//! The clock of the standard library is not available on `wasm32-unknown-unknown`, where the
+//! clock of the JavaScript host is used instead.
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use web_time::Instant;
@@ -0,0 +1,374 @@
+//! A structured view of the tokens of the output, similar to the one of the `proc_macro` crate.
+use std::fmt;
+
+use crate::{
+    c_token::{CToken, CTokenKind},
+    diagnostic::{Diagnostic, DiagnosticCode, Severity},
+    span::{SourceMap, Span},
+};
+
+/// A sequence of token trees, where the tokens between matching parentheses, brackets and braces
+/// are grouped together.
+///
+/// The `Display` implementation writes the tokens back as C code, with a space between tokens
+/// unless they are parts of the same punctuator.
+///
+/// ```
+/// use beheader::TokenTree;
+///
+/// let preprocessed = beheader::preprocess(b"#define CALL(f) f(1, (2))\nCALL(g);\n").unwrap();
+/// let stream = preprocessed.token_stream().unwrap();
+/// assert_eq!(stream.to_string(), "g (1 , (2)) ;");
+/// match &stream.trees()[1] {
+///     TokenTree::Group(group) => assert_eq!(group.stream().len(), 3),
+///     tree => panic!("Unexpected {:?}", tree),
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TokenStream {
+    trees: Vec<TokenTree>,
+}
+
+impl TokenStream {
+    /// The token trees of the stream.
+    pub fn trees(&self) -> &[TokenTree] {
+        &self.trees
+    }
+
+    /// Return an iterator over the token trees of the stream.
+    pub fn iter(&self) -> std::slice::Iter<'_, TokenTree> {
+        self.trees.iter()
+    }
+
+    /// The number of token trees of the stream, without counting the ones inside groups.
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+
+    /// Check if the stream has no token trees.
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
+    }
+
+    /// Group `tokens`, whose spellings are in `map`. Return a diagnostic about the first
+    /// delimiter without a matching one.
+    pub(crate) fn build(tokens: &[CToken], map: &SourceMap) -> Result<Self, Diagnostic> {
+        // The groups that have not been closed yet, from the outermost to the innermost, and
+        // the trees found so far in each of them.
+        let mut open: Vec<(Delimiter, Span, Vec<TokenTree>)> = Vec::new();
+        let mut trees = Vec::new();
+
+        for token in tokens {
+            let span = token.span();
+            let spelling = String::from_utf8_lossy(&map.get_bytes(span)).into_owned();
+            match token.kind() {
+                CTokenKind::Keyword(_) | CTokenKind::Ident => {
+                    trees.push(TokenTree::Ident(Ident {
+                        name: spelling,
+                        span,
+                    }));
+                }
+                CTokenKind::Punct => {
+                    // Digraphs behave like the punctuators they represent (section 6.4.6p3 of
+                    // C17).
+                    let spelling = match spelling.as_str() {
+                        "<:" => "[",
+                        ":>" => "]",
+                        "<%" => "{",
+                        "%>" => "}",
+                        spelling => spelling,
+                    };
+                    if let Some(delimiter) = Delimiter::opened_by(spelling) {
+                        open.push((delimiter, span, std::mem::take(&mut trees)));
+                    } else if let Some(delimiter) = Delimiter::closed_by(spelling) {
+                        let (opening, open_span, outer) = match open.pop() {
+                            Some(group) if group.0 == delimiter => group,
+                            group => {
+                                let code = DiagnosticCode::UnmatchedDelimiter;
+                                let message = format!("Unmatched \"{}\"", spelling);
+                                let diagnostic =
+                                    map.diagnostic(Severity::Error, code, span, message);
+                                // The innermost group is the one that should have been closed.
+                                return Err(match group {
+                                    Some((opening, open_span, _)) => {
+                                        let message = format!("Unclosed \"{}\"", opening.open());
+                                        diagnostic.with_label(map.label(open_span, message))
+                                    }
+                                    None => diagnostic,
+                                });
+                            }
+                        };
+                        let stream = TokenStream {
+                            trees: std::mem::replace(&mut trees, outer),
+                        };
+                        trees.push(TokenTree::Group(Group {
+                            delimiter: opening,
+                            stream,
+                            span_open: open_span,
+                            span_close: span,
+                        }));
+                    } else {
+                        let chars: Vec<char> = spelling.chars().collect();
+                        for (i, &ch) in chars.iter().enumerate() {
+                            let spacing = if i + 1 < chars.len() {
+                                Spacing::Joint
+                            } else {
+                                Spacing::Alone
+                            };
+                            trees.push(TokenTree::Punct(Punct { ch, spacing, span }));
+                        }
+                    }
+                }
+                kind => trees.push(TokenTree::Literal(Literal {
+                    kind,
+                    spelling,
+                    span,
+                })),
+            }
+        }
+
+        if let Some((delimiter, span, _)) = open.pop() {
+            let code = DiagnosticCode::UnclosedDelimiter;
+            let message = format!("Unclosed \"{}\"", delimiter.open());
+            return Err(map.diagnostic(Severity::Error, code, span, message));
+        }
+        Ok(Self { trees })
+    }
+}
+
+impl<'a> IntoIterator for &'a TokenStream {
+    type Item = &'a TokenTree;
+    type IntoIter = std::slice::Iter<'a, TokenTree>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for TokenStream {
+    type Item = TokenTree;
+    type IntoIter = std::vec::IntoIter<TokenTree>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.trees.into_iter()
+    }
+}
+
+impl fmt::Display for TokenStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut joint = true;
+        for tree in &self.trees {
+            if !joint {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", tree)?;
+            joint = matches!(tree, TokenTree::Punct(punct) if punct.spacing == Spacing::Joint);
+        }
+        Ok(())
+    }
+}
+
+/// A single token or a group of token trees.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenTree {
+    /// Token trees between matching delimiters.
+    Group(Group),
+    /// An identifier or a keyword.
+    Ident(Ident),
+    /// A single character of a punctuator.
+    Punct(Punct),
+    /// A constant or a string literal.
+    Literal(Literal),
+}
+
+impl TokenTree {
+    /// The region of the preprocessing token the tree was produced from or, for groups, the
+    /// region of its opening delimiter.
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Group(group) => group.span_open,
+            Self::Ident(ident) => ident.span,
+            Self::Punct(punct) => punct.span,
+            Self::Literal(literal) => literal.span,
+        }
+    }
+}
+
+impl fmt::Display for TokenTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Group(group) => write!(
+                f,
+                "{}{}{}",
+                group.delimiter.open(),
+                group.stream,
+                group.delimiter.close()
+            ),
+            Self::Ident(ident) => write!(f, "{}", ident.name),
+            Self::Punct(punct) => write!(f, "{}", punct.ch),
+            Self::Literal(literal) => write!(f, "{}", literal.spelling),
+        }
+    }
+}
+
+/// Token trees between matching delimiters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Group {
+    delimiter: Delimiter,
+    stream: TokenStream,
+    span_open: Span,
+    span_close: Span,
+}
+
+impl Group {
+    /// The delimiters of the group.
+    pub fn delimiter(&self) -> Delimiter {
+        self.delimiter
+    }
+
+    /// The token trees between the delimiters.
+    pub fn stream(&self) -> &TokenStream {
+        &self.stream
+    }
+
+    /// The region of the opening delimiter.
+    pub fn span_open(&self) -> Span {
+        self.span_open
+    }
+
+    /// The region of the closing delimiter.
+    pub fn span_close(&self) -> Span {
+        self.span_close
+    }
+}
+
+/// The delimiters of a [`Group`]. The digraphs `<:`, `:>`, `<%` and `%>` are delimiters too.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Delimiter {
+    /// `( ... )`
+    Parenthesis,
+    /// `[ ... ]`
+    Bracket,
+    /// `{ ... }`
+    Brace,
+}
+
+impl Delimiter {
+    /// The opening delimiter.
+    pub fn open(self) -> char {
+        match self {
+            Self::Parenthesis => '(',
+            Self::Bracket => '[',
+            Self::Brace => '{',
+        }
+    }
+
+    /// The closing delimiter.
+    pub fn close(self) -> char {
+        match self {
+            Self::Parenthesis => ')',
+            Self::Bracket => ']',
+            Self::Brace => '}',
+        }
+    }
+
+    /// The delimiter whose opening punctuator is spelled `spelling`.
+    fn opened_by(spelling: &str) -> Option<Self> {
+        match spelling {
+            "(" => Some(Self::Parenthesis),
+            "[" => Some(Self::Bracket),
+            "{" => Some(Self::Brace),
+            _ => None,
+        }
+    }
+
+    /// The delimiter whose closing punctuator is spelled `spelling`.
+    fn closed_by(spelling: &str) -> Option<Self> {
+        match spelling {
+            ")" => Some(Self::Parenthesis),
+            "]" => Some(Self::Bracket),
+            "}" => Some(Self::Brace),
+            _ => None,
+        }
+    }
+}
+
+/// An identifier or a keyword.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ident {
+    name: String,
+    span: Span,
+}
+
+impl Ident {
+    /// The spelling of the identifier.
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The region of the identifier.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+/// A single character of a punctuator. Punctuators with more than one character, like `->`, are
+/// split into one `Punct` per character.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Punct {
+    ch: char,
+    spacing: Spacing,
+    span: Span,
+}
+
+impl Punct {
+    /// The character.
+    pub fn as_char(&self) -> char {
+        self.ch
+    }
+
+    /// Whether the next character is part of the same punctuator.
+    pub fn spacing(&self) -> Spacing {
+        self.spacing
+    }
+
+    /// The region of the whole punctuator.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+/// Whether a [`Punct`] is followed by another character of the same punctuator.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Spacing {
+    /// The next `Punct` is part of the same punctuator, like `-` in `->`.
+    Joint,
+    /// This is the last character of the punctuator.
+    Alone,
+}
+
+/// A constant or a string literal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Literal {
+    kind: CTokenKind,
+    spelling: String,
+    span: Span,
+}
+
+impl Literal {
+    /// The kind of the literal, which is never [`CTokenKind::Keyword`],
+    /// [`CTokenKind::Ident`] or [`CTokenKind::Punct`].
+    pub fn kind(&self) -> CTokenKind {
+        self.kind
+    }
+
+    /// The spelling of the literal.
+    pub fn as_str(&self) -> &str {
+        &self.spelling
+    }
+
+    /// The region of the literal.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
@@ -0,0 +1,200 @@
+//! Bindings to use the preprocessor from JavaScript when compiled to WebAssembly.
+//!
+//! Both functions take the source code to preprocess and a JSON object with the options, which
+//! can be empty, and return a JSON object with the result. The options are:
+//!
+//! - `path`: the name of the input file, used to find the files it includes with quotes.
+//! - `files`: an object whose members are the paths and contents of the files that can be
+//!   included. No other file can be included.
+//! - `includeDirs` and `systemIncludeDirs`: arrays of directories where included files are
+//!   searched, as the `-I` and `-isystem` options of a compiler.
+//! - `defines` and `undefines`: arrays of macros to define, as `NAME` or `NAME=DEFINITION`, and
+//!   to undefine, as the `-D` and `-U` options of a compiler.
+//! - `standard`: the name of the version of the C standard, like `c99` or `gnu11`.
+//! - `lineMarkers`: whether the output of [`preprocess`] has line markers, which it has by
+//!   default.
+//!
+//! The result has a `diagnostics` member with an array of objects with the `severity`, `code`,
+//! `message`, `file`, `line` and `column` of each diagnostic, and an `error` member with the
+//! message of the error that stopped the preprocessing, or `null`.
+//!
+//! ```js
+//! import { preprocess } from "beheader";
+//!
+//! const options = { files: { "config.h": "#define VERSION 2\n" }, lineMarkers: false };
+//! const source = '#include "config.h"\nVERSION\n';
+//! const result = JSON.parse(preprocess(source, JSON.stringify(options)));
+//! console.log(result.output); // "2\n"
+//! ```
+#[cfg(test)]
+mod tests;
+
+use serde_json::{json, Map, Value};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{Diagnostic, Emitter, Error, MemoryFileSystem, Preprocessed, Preprocessor, Standard};
+
+/// Preprocess `source` with the JSON `options`, returning a JSON object whose `output` member
+/// is the output as text, or `null` if the preprocessing did not finish.
+///
+/// Throw an error if the options are not valid.
+#[wasm_bindgen]
+pub fn preprocess(source: &str, options: &str) -> Result<String, String> {
+    let options = Options::parse(options)?;
+    let result = options.run(source);
+    let output = result.as_ref().ok().map(|preprocessed| {
+        let text = Emitter::new()
+            .line_markers(options.line_markers)
+            .render(preprocessed);
+        String::from_utf8_lossy(&text).into_owned()
+    });
+    Ok(to_json(result, "output", json!(output)))
+}
+
+/// Preprocess `source` with the JSON `options`, returning a JSON object whose `tokens` member is
+/// an array with the `kind`, `spelling`, `file`, `line` and `column` of each token of the
+/// output, or `null` if the preprocessing did not finish. The kind is one of `header`, `ident`,
+/// `number`, `char`, `str`, `punct`, `any`, `space` and `newline`.
+///
+/// Throw an error if the options are not valid.
+#[wasm_bindgen]
+pub fn tokenize(source: &str, options: &str) -> Result<String, String> {
+    let options = Options::parse(options)?;
+    let result = options.run(source);
+    let tokens = result.as_ref().ok().map(|preprocessed| {
+        let snapshot = preprocessed.source_map();
+        let tokens = preprocessed.tokens().iter().map(|token| {
+            let spelling = snapshot.spelling(token.span()).unwrap_or_default();
+            let (file, line, column) = match preprocessed.resolve_span(token.span()) {
+                Some(location) if location.file().is_some() => (
+                    location.file().map(|file| file.display().to_string()),
+                    Some(location.start_line()),
+                    Some(location.start_column()),
+                ),
+                _ => (None, None, None),
+            };
+            json!({
+                "kind": format!("{:?}", token.kind()).to_lowercase(),
+                "spelling": String::from_utf8_lossy(spelling),
+                "file": file,
+                "line": line,
+                "column": column,
+            })
+        });
+        tokens.collect::<Vec<_>>()
+    });
+    Ok(to_json(result, "tokens", json!(tokens)))
+}
+
+/// The options of a run, parsed from JSON.
+struct Options {
+    preprocessor: Preprocessor,
+    path: Option<String>,
+    line_markers: bool,
+}
+
+impl Options {
+    fn parse(options: &str) -> Result<Self, String> {
+        let options: Value = serde_json::from_str(options).map_err(|error| error.to_string())?;
+        let options = options.as_object().ok_or("the options must be an object")?;
+
+        let mut preprocessor = Preprocessor::new();
+        let mut files = MemoryFileSystem::new();
+        if let Some(value) = options.get("files") {
+            let value = value.as_object().ok_or("\"files\" must be an object")?;
+            for (path, contents) in value {
+                let contents = contents
+                    .as_str()
+                    .ok_or("the contents of the files must be strings")?;
+                files.insert(path, contents);
+            }
+        }
+        preprocessor.file_system(files);
+        for dir in strings(options, "includeDirs")? {
+            preprocessor.include_dir(dir);
+        }
+        for dir in strings(options, "systemIncludeDirs")? {
+            preprocessor.system_include_dir(dir);
+        }
+        for definition in strings(options, "defines")? {
+            preprocessor.define(definition);
+        }
+        for name in strings(options, "undefines")? {
+            preprocessor.undefine(name);
+        }
+        if let Some(value) = options.get("standard") {
+            let standard = value
+                .as_str()
+                .and_then(Standard::from_name)
+                .ok_or("\"standard\" must be the name of a standard")?;
+            preprocessor.standard(standard);
+        }
+
+        let path = options
+            .get("path")
+            .map(|value| value.as_str().map(str::to_owned));
+        let path = path
+            .map(|path| path.ok_or("\"path\" must be a string"))
+            .transpose()?;
+        let line_markers = match options.get("lineMarkers") {
+            Some(value) => value.as_bool().ok_or("\"lineMarkers\" must be a boolean")?,
+            None => true,
+        };
+        Ok(Self {
+            preprocessor,
+            path,
+            line_markers,
+        })
+    }
+
+    fn run(&self, source: &str) -> Result<Preprocessed, Error> {
+        match &self.path {
+            Some(path) => self.preprocessor.preprocess_source(path, source.as_bytes()),
+            None => self.preprocessor.preprocess(source.as_bytes()),
+        }
+    }
+}
+
+/// Get the strings of the array that is the member `name` of `options`, if any.
+fn strings<'a>(options: &'a Map<String, Value>, name: &str) -> Result<Vec<&'a str>, String> {
+    let error = || format!("\"{}\" must be an array of strings", name);
+    match options.get(name) {
+        Some(value) => value
+            .as_array()
+            .ok_or_else(error)?
+            .iter()
+            .map(|value| value.as_str().ok_or_else(error))
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Produce the JSON object of the result of a run whose member `name` is `value`.
+fn to_json(result: Result<Preprocessed, Error>, name: &str, value: Value) -> String {
+    let (diagnostics, error) = match &result {
+        Ok(preprocessed) => (preprocessed.diagnostics(), None),
+        Err(error) => (error.diagnostics(), Some(error)),
+    };
+    // The diagnostic of the error is the last one.
+    let diagnostics: Vec<_> = diagnostics
+        .iter()
+        .chain(error.and_then(|error| error.diagnostic()))
+        .map(diagnostic_to_json)
+        .collect();
+    let mut object = Map::new();
+    object.insert(name.to_owned(), value);
+    object.insert("diagnostics".to_owned(), json!(diagnostics));
+    object.insert("error".to_owned(), json!(error.map(Error::to_string)));
+    Value::Object(object).to_string()
+}
+
+fn diagnostic_to_json(diagnostic: &Diagnostic) -> Value {
+    json!({
+        "severity": format!("{:?}", diagnostic.severity()).to_lowercase(),
+        "code": diagnostic.code().as_str(),
+        "message": diagnostic.message(),
+        "file": diagnostic.file().map(|file| file.display().to_string()),
+        "line": diagnostic.line(),
+        "column": diagnostic.column(),
+    })
+}
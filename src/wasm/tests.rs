@@ -0,0 +1,72 @@
+use serde_json::{json, Value};
+
+use super::{preprocess, tokenize};
+
+#[test]
+fn preprocess_json() {
+    let options = json!({
+        "path": "src/main.c",
+        "files": {
+            "src/local.h": "#define LOCAL 1\n",
+            "include/config.h": "#define VERSION 2\n#define VERSION 3\n",
+        },
+        "includeDirs": ["include"],
+        "defines": ["DEBUG"],
+        "standard": "c99",
+        "lineMarkers": false,
+    });
+    let source =
+        "#include \"local.h\"\n#include <config.h>\nLOCAL VERSION DEBUG __STDC_VERSION__\n";
+    let result = preprocess(source, &options.to_string()).unwrap();
+    let result: Value = serde_json::from_str(&result).unwrap();
+    assert_eq!(result["output"], "1 3 1 199901L\n");
+    assert_eq!(result["error"], Value::Null);
+    assert_eq!(
+        result["diagnostics"],
+        json!([{
+            "severity": "warning",
+            "code": "macro-redefined",
+            "message": "Macro \"VERSION\" redefined",
+            "file": "include/config.h",
+            "line": 2,
+            "column": 9,
+        }])
+    );
+
+    // The files that are not given cannot be included.
+    let result = preprocess("#include <stdio.h>\n", "{}").unwrap();
+    let result: Value = serde_json::from_str(&result).unwrap();
+    assert_eq!(result["output"], Value::Null);
+    assert_eq!(result["diagnostics"][0]["code"], "include-not-found");
+    assert!(result["error"].as_str().unwrap().contains("stdio.h"));
+
+    assert_eq!(
+        preprocess("", "{\"defines\": [1]}").err().unwrap(),
+        "\"defines\" must be an array of strings"
+    );
+    assert!(preprocess("", "[]").is_err());
+}
+
+#[test]
+fn tokenize_json() {
+    let options = json!({ "path": "a.c", "defines": ["A=x"] });
+    let result = tokenize("int A;\n", &options.to_string()).unwrap();
+    let result: Value = serde_json::from_str(&result).unwrap();
+    let tokens = result["tokens"].as_array().unwrap();
+    assert_eq!(
+        tokens[..3],
+        [
+            json!({"kind": "ident", "spelling": "int", "file": "a.c", "line": 1, "column": 1}),
+            json!({"kind": "space", "spelling": " ", "file": "a.c", "line": 1, "column": 4}),
+            // Macros defined by the options are in a file of their own.
+            json!({
+                "kind": "ident",
+                "spelling": "x",
+                "file": "<command-line>",
+                "line": 1,
+                "column": 11,
+            }),
+        ]
+    );
+    assert_eq!(result["diagnostics"], json!([]));
+}